@@ -16,16 +16,31 @@ import static org.junit.Assert.*;
  */
 @RunWith(AndroidJUnit4.class)
 public class SurfmanInstrumentedTest {
+    private static native void testContextApiSelection();
     private static native void testContextCreation();
+    private static native void testContextDescriptorDiagnostics();
+    private static native void testContextDescriptorEnumeration();
+    private static native void testContextVersionNegotiation();
     private static native void testCrossDeviceSurfaceTextureBlitFramebuffer();
     private static native void testCrossThreadSurfaceTextureBlitFramebuffer();
     private static native void testDeviceAccessors();
+    private static native void testDeviceCapabilities();
     private static native void testDeviceCreation();
+    private static native void testErrorReporting();
     private static native void testGenericSurfaceCreation();
     private static native void testGL();
+    private static native void testGLInfo();
+    private static native void testLayeredSurface();
+    private static native void testLeakPolicy();
+    private static native void testManagedObjects();
+    private static native void testManagedObjectsOutlivingTheirContext();
     private static native void testNewlyCreatedContextsAreNotCurrent();
+    private static native void testPlanarImageImport();
+    private static native void testSurfaceFormats();
+    private static native void testSurfaceHandleRoundTrip();
     private static native void testSurfaceTextureBlitFramebuffer();
     private static native void testSurfaceTextureRightSideUp();
+    private static native void testUploadSurfaceData();
 
     static {
         System.loadLibrary("surfman_android_threads");
@@ -39,11 +54,31 @@ public class SurfmanInstrumentedTest {
         assertEquals("org.mozilla.surfmanthreadsexample", appContext.getPackageName());
     }
 
+    @Test
+    public void contextApiSelection() {
+        testContextApiSelection();
+    }
+
     @Test
     public void contextCreation() {
         testContextCreation();
     }
 
+    @Test
+    public void contextDescriptorDiagnostics() {
+        testContextDescriptorDiagnostics();
+    }
+
+    @Test
+    public void contextDescriptorEnumeration() {
+        testContextDescriptorEnumeration();
+    }
+
+    @Test
+    public void contextVersionNegotiation() {
+        testContextVersionNegotiation();
+    }
+
     @Test
     public void crossDeviceSurfaceTextureBlitFramebuffer() {
         testCrossDeviceSurfaceTextureBlitFramebuffer();
@@ -59,11 +94,21 @@ public class SurfmanInstrumentedTest {
         testDeviceAccessors();
     }
 
+    @Test
+    public void deviceCapabilities() {
+        testDeviceCapabilities();
+    }
+
     @Test
     public void deviceCreation() {
         testDeviceCreation();
     }
 
+    @Test
+    public void errorReporting() {
+        testErrorReporting();
+    }
+
     @Test
     public void genericSurfaceCreation() {
         testGenericSurfaceCreation();
@@ -74,11 +119,51 @@ public class SurfmanInstrumentedTest {
         testGL();
     }
 
+    @Test
+    public void glInfo() {
+        testGLInfo();
+    }
+
+    @Test
+    public void layeredSurface() {
+        testLayeredSurface();
+    }
+
+    @Test
+    public void leakPolicy() {
+        testLeakPolicy();
+    }
+
+    @Test
+    public void managedObjects() {
+        testManagedObjects();
+    }
+
+    @Test
+    public void managedObjectsOutlivingTheirContext() {
+        testManagedObjectsOutlivingTheirContext();
+    }
+
     @Test
     public void newlyCreatedContextsAreNotCurrent() {
         testNewlyCreatedContextsAreNotCurrent();
     }
 
+    @Test
+    public void planarImageImport() {
+        testPlanarImageImport();
+    }
+
+    @Test
+    public void surfaceFormats() {
+        testSurfaceFormats();
+    }
+
+    @Test
+    public void surfaceHandleRoundTrip() {
+        testSurfaceHandleRoundTrip();
+    }
+
     @Test
     public void surfaceTextureBlitFramebuffer() {
         testSurfaceTextureBlitFramebuffer();
@@ -88,4 +173,9 @@ public class SurfmanInstrumentedTest {
     public void surfaceTextureRightSideUp() {
         testSurfaceTextureRightSideUp();
     }
+
+    @Test
+    public void uploadSurfaceData() {
+        testUploadSurfaceData();
+    }
 }
@@ -64,6 +64,14 @@ pub unsafe extern "system" fn
 
 // NB: New tests should be added here.
 
+#[no_mangle]
+pub unsafe extern "system" fn
+        Java_org_mozilla_surfmanthreadsexample_SurfmanInstrumentedTest_testContextApiSelection(
+            _env: JNIEnv,
+            _class: JClass) {
+    tests::test_context_api_selection();
+}
+
 #[no_mangle]
 pub unsafe extern "system" fn
         Java_org_mozilla_surfmanthreadsexample_SurfmanInstrumentedTest_testContextCreation(
@@ -72,6 +80,30 @@ pub unsafe extern "system" fn
     tests::test_context_creation();
 }
 
+#[no_mangle]
+pub unsafe extern "system" fn
+        Java_org_mozilla_surfmanthreadsexample_SurfmanInstrumentedTest_testContextDescriptorDiagnostics(
+            _env: JNIEnv,
+            _class: JClass) {
+    tests::test_context_descriptor_diagnostics();
+}
+
+#[no_mangle]
+pub unsafe extern "system" fn
+        Java_org_mozilla_surfmanthreadsexample_SurfmanInstrumentedTest_testContextDescriptorEnumeration(
+            _env: JNIEnv,
+            _class: JClass) {
+    tests::test_context_descriptor_enumeration();
+}
+
+#[no_mangle]
+pub unsafe extern "system" fn
+        Java_org_mozilla_surfmanthreadsexample_SurfmanInstrumentedTest_testContextVersionNegotiation(
+            _env: JNIEnv,
+            _class: JClass) {
+    tests::test_context_version_negotiation();
+}
+
 #[no_mangle]
 pub unsafe extern "system" fn
         Java_org_mozilla_surfmanthreadsexample_SurfmanInstrumentedTest_testCrossDeviceSurfaceTextureBlitFramebuffer(
@@ -96,6 +128,14 @@ pub unsafe extern "system" fn
     tests::test_device_accessors();
 }
 
+#[no_mangle]
+pub unsafe extern "system" fn
+        Java_org_mozilla_surfmanthreadsexample_SurfmanInstrumentedTest_testDeviceCapabilities(
+            _env: JNIEnv,
+            _class: JClass) {
+    tests::test_device_capabilities();
+}
+
 #[no_mangle]
 pub unsafe extern "system" fn
         Java_org_mozilla_surfmanthreadsexample_SurfmanInstrumentedTest_testDeviceCreation(
@@ -104,6 +144,14 @@ pub unsafe extern "system" fn
     tests::test_device_creation();
 }
 
+#[no_mangle]
+pub unsafe extern "system" fn
+        Java_org_mozilla_surfmanthreadsexample_SurfmanInstrumentedTest_testErrorReporting(
+            _env: JNIEnv,
+            _class: JClass) {
+    tests::test_error_reporting();
+}
+
 #[no_mangle]
 pub unsafe extern "system" fn
         Java_org_mozilla_surfmanthreadsexample_SurfmanInstrumentedTest_testGenericSurfaceCreation(
@@ -120,6 +168,46 @@ pub unsafe extern "system" fn
     tests::test_gl();
 }
 
+#[no_mangle]
+pub unsafe extern "system" fn
+        Java_org_mozilla_surfmanthreadsexample_SurfmanInstrumentedTest_testGLInfo(
+            _env: JNIEnv,
+            _class: JClass) {
+    tests::test_gl_info();
+}
+
+#[no_mangle]
+pub unsafe extern "system" fn
+        Java_org_mozilla_surfmanthreadsexample_SurfmanInstrumentedTest_testLayeredSurface(
+            _env: JNIEnv,
+            _class: JClass) {
+    tests::test_layered_surface();
+}
+
+#[no_mangle]
+pub unsafe extern "system" fn
+        Java_org_mozilla_surfmanthreadsexample_SurfmanInstrumentedTest_testLeakPolicy(
+            _env: JNIEnv,
+            _class: JClass) {
+    tests::test_leak_policy();
+}
+
+#[no_mangle]
+pub unsafe extern "system" fn
+        Java_org_mozilla_surfmanthreadsexample_SurfmanInstrumentedTest_testManagedObjects(
+            _env: JNIEnv,
+            _class: JClass) {
+    tests::test_managed_objects();
+}
+
+#[no_mangle]
+pub unsafe extern "system" fn
+        Java_org_mozilla_surfmanthreadsexample_SurfmanInstrumentedTest_testManagedObjectsOutlivingTheirContext(
+            _env: JNIEnv,
+            _class: JClass) {
+    tests::test_managed_objects_outliving_their_context();
+}
+
 #[no_mangle]
 pub unsafe extern "system" fn
         Java_org_mozilla_surfmanthreadsexample_SurfmanInstrumentedTest_testNewlyCreatedContextsAreNotCurrent(
@@ -128,6 +216,30 @@ pub unsafe extern "system" fn
     tests::test_newly_created_contexts_are_not_current();
 }
 
+#[no_mangle]
+pub unsafe extern "system" fn
+        Java_org_mozilla_surfmanthreadsexample_SurfmanInstrumentedTest_testPlanarImageImport(
+            _env: JNIEnv,
+            _class: JClass) {
+    tests::test_planar_image_import();
+}
+
+#[no_mangle]
+pub unsafe extern "system" fn
+        Java_org_mozilla_surfmanthreadsexample_SurfmanInstrumentedTest_testSurfaceFormats(
+            _env: JNIEnv,
+            _class: JClass) {
+    tests::test_surface_formats();
+}
+
+#[no_mangle]
+pub unsafe extern "system" fn
+        Java_org_mozilla_surfmanthreadsexample_SurfmanInstrumentedTest_testSurfaceHandleRoundTrip(
+            _env: JNIEnv,
+            _class: JClass) {
+    tests::test_surface_handle_round_trip();
+}
+
 #[no_mangle]
 pub unsafe extern "system" fn
         Java_org_mozilla_surfmanthreadsexample_SurfmanInstrumentedTest_testSurfaceTextureBlitFramebuffer(
@@ -144,6 +256,14 @@ pub unsafe extern "system" fn
     tests::test_surface_texture_right_side_up();
 }
 
+#[no_mangle]
+pub unsafe extern "system" fn
+        Java_org_mozilla_surfmanthreadsexample_SurfmanInstrumentedTest_testUploadSurfaceData(
+            _env: JNIEnv,
+            _class: JClass) {
+    tests::test_upload_surface_data();
+}
+
 struct JavaResourceLoader {
     loader: GlobalRef,
     vm: JavaVM,
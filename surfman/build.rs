@@ -21,14 +21,19 @@ fn main() {
         registry.write_bindings(StructGenerator, &mut file).unwrap();
     }
 
-    // Generate GL bindings.
+    // Generate GL bindings. `GL_OVR_multiview` is used to render to every layer of a layered
+    // surface at once.
     if target_os == "android" {
         let mut file = File::create(&dest.join("gl_bindings.rs")).unwrap();
-        let registry = Registry::new(Api::Gles2, (3, 0), Profile::Core, Fallbacks::All, []);
+        let registry = Registry::new(Api::Gles2, (3, 0), Profile::Core, Fallbacks::All, [
+            "GL_OVR_multiview",
+        ]);
         registry.write_bindings(StructGenerator, &mut file).unwrap();
     } else {
         let mut file = File::create(&dest.join("gl_bindings.rs")).unwrap();
-        let registry = Registry::new(Api::Gl, (3, 3), Profile::Core, Fallbacks::All, []);
+        let registry = Registry::new(Api::Gl, (3, 3), Profile::Core, Fallbacks::All, [
+            "GL_OVR_multiview",
+        ]);
         registry.write_bindings(StructGenerator, &mut file).unwrap();
     }
 }
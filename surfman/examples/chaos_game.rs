@@ -50,7 +50,7 @@ fn main() {
 
     let native_widget = connection.create_native_widget_from_winit_window(&window).unwrap();
 
-    let surface_type = SurfaceType::Widget { native_widget };
+    let surface_type = SurfaceType::Widget { native_widget, color_space: None };
     let mut surface = device.create_surface(SurfaceAccess::GPUCPU, surface_type).unwrap();
 
     let mut rng = rand::thread_rng();
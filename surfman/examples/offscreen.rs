@@ -15,7 +15,7 @@ use std::mem;
 use std::path::Path;
 use std::slice;
 use surfman::{Connection, ContextAttributeFlags, ContextAttributes, GLApi, GLVersion};
-use surfman::{SurfaceAccess, SurfaceType};
+use surfman::{SurfaceAccess, SurfaceFormat, SurfaceType};
 
 mod common;
 
@@ -69,14 +69,14 @@ fn main() {
 
     let mut device = connection.create_device(&adapter).unwrap();
 
-    let context_attributes = ContextAttributes {
-        version: GLVersion::new(3, 3),
-        flags: ContextAttributeFlags::empty(),
-    };
+    let context_attributes = ContextAttributes::new(GLVersion::new(3, 3),
+                                                    ContextAttributeFlags::empty());
     let context_descriptor = device.create_context_descriptor(&context_attributes).unwrap();
     let mut context = device.create_context(&context_descriptor).unwrap();
     let surface = device.create_surface(&context, SurfaceAccess::GPUOnly, SurfaceType::Generic {
         size: Size2D::new(FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT),
+        format: SurfaceFormat::RGBA8,
+        depth_stencil: None,
     }).unwrap();
     device.bind_surface_to_context(&mut context, surface).unwrap();
 
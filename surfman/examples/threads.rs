@@ -9,7 +9,7 @@ use gl::types::{GLchar, GLenum, GLint, GLuint, GLvoid};
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::thread;
 use surfman::{Adapter, Connection, Context, ContextDescriptor, Device, GLApi, Surface};
-use surfman::{SurfaceAccess, SurfaceTexture, SurfaceType, declare_surfman};
+use surfman::{SurfaceAccess, SurfaceFormat, SurfaceTexture, SurfaceType, declare_surfman};
 
 #[cfg(not(target_os = "android"))]
 use self::common::FilesystemResourceLoader;
@@ -100,13 +100,11 @@ fn main() {
     let adapter = connection.create_low_power_adapter().unwrap();
     let mut device = connection.create_device(&adapter).unwrap();
 
-    let context_attributes = ContextAttributes {
-        version: GLVersion::new(3, 0),
-        flags: ContextAttributeFlags::ALPHA,
-    };
+    let context_attributes = ContextAttributes::new(GLVersion::new(3, 0),
+                                                    ContextAttributeFlags::ALPHA);
     let context_descriptor = device.create_context_descriptor(&context_attributes).unwrap();
 
-    let surface_type = SurfaceType::Widget { native_widget };
+    let surface_type = SurfaceType::Widget { native_widget, color_space: None };
     let mut context = device.create_context(&context_descriptor).unwrap();
     let surface = device.create_surface(&context, SurfaceAccess::GPUOnly, surface_type).unwrap();
     device.bind_surface_to_context(&mut context, surface).unwrap();
@@ -329,7 +327,11 @@ fn worker_thread(connection: Connection,
                  worker_from_main_receiver: Receiver<Surface>) {
     // Open the device, create a context, and make it current.
     let size = Size2D::new(SUBSCREEN_WIDTH, SUBSCREEN_HEIGHT);
-    let surface_type = SurfaceType::Generic { size };
+    let surface_type = SurfaceType::Generic {
+        size,
+        format: SurfaceFormat::RGBA8,
+        depth_stencil: None,
+    };
     let mut device = connection.create_device(&adapter).unwrap();
     let mut context = device.create_context(&context_descriptor).unwrap();
     let surface = device.create_surface(&context, SurfaceAccess::GPUOnly, surface_type).unwrap();
@@ -357,7 +359,11 @@ fn worker_thread(connection: Connection,
     let mut theta_z = INITIAL_ROTATION_Z;
 
     // Send an initial surface back to the main thread.
-    let surface_type = SurfaceType::Generic { size };
+    let surface_type = SurfaceType::Generic {
+        size,
+        format: SurfaceFormat::RGBA8,
+        depth_stencil: None,
+    };
     let surface = Some(device.create_surface(&context, SurfaceAccess::GPUOnly, surface_type)
                              .unwrap());
     worker_to_main_sender.send(Frame {
@@ -2,13 +2,10 @@
 //
 //! Declarations common to all platform contexts.
 
-use crate::Gl;
-use crate::gl::types::GLuint;
-use crate::gl;
-use crate::info::GLVersion;
+use crate::Error;
+use crate::info::{GLApi, GLVersion};
 
-use std::ffi::CStr;
-use std::os::raw::c_char;
+use std::fmt;
 use std::sync::Mutex;
 
 /// A unique ID among all currently-allocated contexts.
@@ -34,6 +31,11 @@ bitflags! {
         /// Surfaces created for this context will have an alpha channel (RGBA or BGRA; i.e. 4
         /// channels, 32 bits per pixel, 8 bits per channel). If this is not present, surfaces will
         /// be RGBX or BGRX (i.e. 3 channels, 32 bits per pixel, 8 bits per channel).
+        /// 
+        /// On X11 and Wayland, this also selects a config whose widget surfaces can be
+        /// transparent. On X11, the window must use the visual that
+        /// `Device::context_descriptor_visual_info()` returns. Only the X11 device and the default
+        /// Linux device, which switches between Wayland and X11, have that method.
         const ALPHA                 = 0x01;
         /// Surfaces created for this context will have a 24-bit depth buffer.
         const DEPTH                 = 0x02;
@@ -43,6 +45,12 @@ bitflags! {
         /// The OpenGL compatibility profile will be used. If this is not present, the core profile
         /// is used.
         const COMPATIBILITY_PROFILE = 0x08;
+        /// Together with `COMPATIBILITY_PROFILE`, the compatibility profile is preferred, but the
+        /// core profile will be used at versions where the compatibility profile is unavailable.
+        ///
+        /// Only context descriptors report this flag. Attributes of a descriptor fetched from a
+        /// context report the profile that the context actually has.
+        const ALLOW_CORE_PROFILE    = 0x10;
     }
 }
 
@@ -51,53 +59,291 @@ bitflags! {
 /// Similar to: https://www.khronos.org/registry/webgl/specs/latest/1.0/#WEBGLCONTEXTATTRIBUTES
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct ContextAttributes {
+    /// Whether the context should be an OpenGL or an OpenGL ES context.
+    ///
+    /// If this is `None`, the context uses the device's default, `Device::gl_api()`. Devices that
+    /// can't create contexts of the requested flavor return an `UnsupportedGLType` error.
+    pub api: Option<GLApi>,
     /// The OpenGL or OpenGL ES version that this context supports.
     /// 
     /// Keep in mind that OpenGL and OpenGL ES have different version numbering schemes. Before
-    /// filling in this field, check the result of `Device::gl_api()`.
+    /// filling in this field, check `api`, or the result of `Device::gl_api()` if that is
+    /// `None`.
+    ///
+    /// If `minimum_version` is present, this is the preferred version: the newest one tried.
     pub version: GLVersion,
+    /// The oldest acceptable version, if a context older than `version` will do.
+    ///
+    /// Context creation tries `version` and then each older version of the API in turn, down to
+    /// this one. Afterward, `Device::context_descriptor()` reports the version that was
+    /// obtained. If this is `None`, only `version` is tried.
+    pub minimum_version: Option<GLVersion>,
     /// Various flags.
     pub flags: ContextAttributeFlags,
 }
 
+bitflags! {
+    /// The kinds of native surface that a pixel format can render to.
+    pub struct PixelFormatSurfaceTypes: u8 {
+        /// The pixel format can render to native windows.
+        const WINDOW  = 0x01;
+        /// The pixel format can render to offscreen pbuffers.
+        const PBUFFER = 0x02;
+        /// The pixel format can render to native pixmaps.
+        const PIXMAP  = 0x04;
+    }
+}
+
+/// The properties of a pixel format that a context descriptor can be created from. EGL calls a
+/// pixel format a "config".
+///
+/// Sizes are in bits. A size of zero means that the buffer or channel is absent.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PixelFormatAttributes {
+    /// The size of the red channel of the color buffer.
+    pub red_size: u32,
+    /// The size of the green channel of the color buffer.
+    pub green_size: u32,
+    /// The size of the blue channel of the color buffer.
+    pub blue_size: u32,
+    /// The size of the alpha channel of the color buffer.
+    pub alpha_size: u32,
+    /// The size of the depth buffer.
+    pub depth_size: u32,
+    /// The size of the stencil buffer.
+    pub stencil_size: u32,
+    /// The number of samples per pixel, or zero if the pixel format isn't multisampled.
+    pub samples: u32,
+    /// The kinds of native surface that the pixel format can render to.
+    ///
+    /// This is empty on macOS, where every surface is a framebuffer object.
+    pub surface_types: PixelFormatSurfaceTypes,
+    /// The ID of the native visual or format that corresponds to this pixel format, if any.
+    ///
+    /// On X11, this is an X visual ID. On Windows with WGL, it's the pixel format index to pass
+    /// to `SetPixelFormat()`.
+    pub native_visual_id: Option<u32>,
+}
+
+/// An explanation of which pixel formats a device passes over when creating a context descriptor
+/// with some attributes, and why.
+///
+/// The `Display` implementation summarizes this in one line, naming the attributes that the
+/// nearest pixel format lacks.
+#[derive(Debug)]
+pub struct ContextDescriptorDiagnostics {
+    /// The attributes that were requested.
+    pub requested: ContextAttributes,
+    /// An error that rules out every pixel format, such as `UnsupportedGLProfile` when the
+    /// requested version and profile can't be combined.
+    pub error: Option<Error>,
+    /// The pixel formats that satisfy the requested attributes.
+    pub accepted: Vec<PixelFormatAttributes>,
+    /// The pixel formats that don't satisfy the requested attributes.
+    pub rejected: Vec<RejectedPixelFormat>,
+}
+
+/// A pixel format that doesn't satisfy the requested context attributes.
+#[derive(Clone, PartialEq, Debug)]
+pub struct RejectedPixelFormat {
+    /// The attributes of the pixel format.
+    pub pixel_format: PixelFormatAttributes,
+    /// Each attribute that rules the pixel format out.
+    pub mismatches: Vec<PixelFormatMismatch>,
+}
+
+/// One attribute of a pixel format that differs from what was requested.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PixelFormatMismatch {
+    /// The name of the attribute: `red`, `green`, `blue`, `alpha`, `depth`, or `stencil`.
+    pub attribute: &'static str,
+    /// The size in bits that was requested. Alpha, depth, and stencil sizes are minimums; color
+    /// sizes are exact.
+    pub requested: u32,
+    /// The size in bits that the pixel format has.
+    pub actual: u32,
+}
+
 impl ContextAttributes {
+    /// Returns attributes for the given version and flags, with the device's default API and no
+    /// older fallback version.
+    ///
+    /// Set the other fields afterward, or with struct update syntax, so that the code keeps
+    /// compiling when fields are added.
+    #[inline]
+    pub fn new(version: GLVersion, flags: ContextAttributeFlags) -> ContextAttributes {
+        ContextAttributes { api: None, version, minimum_version: None, flags }
+    }
+
     #[allow(dead_code)]
     pub(crate) fn zeroed() -> ContextAttributes {
-        ContextAttributes { version: GLVersion::new(0, 0), flags: ContextAttributeFlags::empty() }
+        ContextAttributes {
+            api: None,
+            version: GLVersion::new(0, 0),
+            minimum_version: None,
+            flags: ContextAttributeFlags::empty(),
+        }
     }
 }
 
-#[cfg(target_os = "android")]
-pub(crate) fn current_context_uses_compatibility_profile(gl: &Gl) -> bool {
-    false
+// Hands the pixel formats of `candidates` to `choose` and returns the descriptor it picks.
+pub(crate) fn choose_context_descriptor<D, F>(mut candidates: Vec<(D, PixelFormatAttributes)>,
+                                              choose: F)
+                                              -> Result<D, Error>
+                                              where F: FnOnce(&[PixelFormatAttributes]) ->
+                                                    Option<usize> {
+    let pixel_formats: Vec<_> = candidates.iter().map(|&(_, ref attributes)| *attributes).collect();
+    match choose(&pixel_formats) {
+        Some(index) if index < candidates.len() => Ok(candidates.swap_remove(index).0),
+        Some(_) | None => Err(Error::NoPixelFormatFound),
+    }
 }
 
-#[cfg(not(target_os = "android"))]
-pub(crate) fn current_context_uses_compatibility_profile(gl: &Gl) -> bool {
-    unsafe {
-        // First, try `GL_CONTEXT_PROFILE_MASK`.
-        let mut context_profile_mask = 0;
-        gl.GetIntegerv(gl::CONTEXT_PROFILE_MASK, &mut context_profile_mask);
-        if gl.GetError() == gl::NO_ERROR &&
-                (context_profile_mask & gl::CONTEXT_COMPATIBILITY_PROFILE_BIT as i32) != 0 {
-            return true;
-        }
+impl ContextDescriptorDiagnostics {
+    // Sorts the pixel formats of `candidates` into those that satisfy `requested` and those that
+    // don't. If `rgb_size` is present, the backend only accepts pixel formats with exactly that
+    // many bits in each color channel.
+    pub(crate) fn new<D>(requested: &ContextAttributes,
+                         candidates: Result<Vec<(D, PixelFormatAttributes)>, Error>,
+                         rgb_size: Option<u32>)
+                         -> ContextDescriptorDiagnostics {
+        let mut diagnostics = ContextDescriptorDiagnostics {
+            requested: *requested,
+            error: None,
+            accepted: vec![],
+            rejected: vec![],
+        };
+        let candidates = match candidates {
+            Ok(candidates) => candidates,
+            Err(err) => {
+                diagnostics.error = Some(err);
+                return diagnostics;
+            }
+        };
 
-        // Second, look for the `GL_ARB_compatibility` extension.
-        let mut compatibility_profile = false;
-        let mut num_extensions = 0;
-        gl.GetIntegerv(gl::NUM_EXTENSIONS, &mut num_extensions);
-        if gl.GetError() == gl::NO_ERROR {
-            for extension_index in 0..(num_extensions as GLuint) {
-                let extension = gl.GetStringi(gl::EXTENSIONS, extension_index) as *const c_char;
-                let extension = CStr::from_ptr(extension);
-                if extension.to_str() == Ok("GL_ARB_compatibility") {
-                    return true;
+        let minimum_sizes = requested_sizes(requested.flags);
+        for (_, pixel_format) in candidates {
+            let mut mismatches = vec![];
+            if let Some(rgb_size) = rgb_size {
+                for &(attribute, actual) in &[("red", pixel_format.red_size),
+                                              ("green", pixel_format.green_size),
+                                              ("blue", pixel_format.blue_size)] {
+                    if actual != rgb_size {
+                        mismatches.push(PixelFormatMismatch {
+                            attribute,
+                            requested: rgb_size,
+                            actual,
+                        });
+                    }
+                }
+            }
+            for &(attribute, requested, actual) in
+                    &[("alpha", minimum_sizes.0, pixel_format.alpha_size),
+                      ("depth", minimum_sizes.1, pixel_format.depth_size),
+                      ("stencil", minimum_sizes.2, pixel_format.stencil_size)] {
+                if actual < requested {
+                    mismatches.push(PixelFormatMismatch { attribute, requested, actual });
                 }
             }
+
+            if mismatches.is_empty() {
+                diagnostics.accepted.push(pixel_format);
+            } else {
+                diagnostics.rejected.push(RejectedPixelFormat { pixel_format, mismatches });
+            }
+        }
+        diagnostics
+    }
+
+    /// Returns the rejected pixel format that comes closest to satisfying the requested
+    /// attributes: the one with the fewest mismatches, and then the smallest differences in size.
+    pub fn nearest(&self) -> Option<&RejectedPixelFormat> {
+        self.rejected.iter().min_by_key(|rejected| {
+            let distance: i64 = rejected.mismatches.iter().map(|mismatch| {
+                (mismatch.requested as i64 - mismatch.actual as i64).abs()
+            }).sum();
+            (rejected.mismatches.len(), distance)
+        })
+    }
+}
+
+impl fmt::Display for ContextDescriptorDiagnostics {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let flags = self.requested.flags;
+        let (alpha_size, depth_size, stencil_size) = requested_sizes(flags);
+        let profile = if flags.contains(ContextAttributeFlags::COMPATIBILITY_PROFILE) {
+            if flags.contains(ContextAttributeFlags::ALLOW_CORE_PROFILE) {
+                "compatibility or core"
+            } else {
+                "compatibility"
+            }
+        } else {
+            "core"
+        };
+        let api = match self.requested.api {
+            Some(GLApi::GLES) => "GLES",
+            Some(GLApi::GL) | None => "GL",
+        };
+        write!(formatter,
+               "requested {} {}.{}",
+               api,
+               self.requested.version.major,
+               self.requested.version.minor)?;
+        if let Some(minimum_version) = self.requested.minimum_version {
+            write!(formatter, " down to {}.{}", minimum_version.major, minimum_version.minor)?;
+        }
+        write!(formatter,
+               " {} profile, alpha={}, depth={}, stencil={}",
+               profile,
+               alpha_size,
+               depth_size,
+               stencil_size)?;
+
+        if let Some(ref err) = self.error {
+            return write!(formatter, "; failed with {:?}", err);
+        }
+        if !self.accepted.is_empty() {
+            return write!(formatter, "; {} pixel formats match", self.accepted.len());
         }
+        let nearest = match self.nearest() {
+            None => return write!(formatter, "; no pixel formats are available"),
+            Some(nearest) => nearest,
+        };
 
-        false
+        write!(formatter, "; none of {} pixel formats match; nearest has", self.rejected.len())?;
+        for (index, mismatch) in nearest.mismatches.iter().enumerate() {
+            write!(formatter,
+                   "{} {}={}",
+                   if index == 0 { "" } else { "," },
+                   mismatch.attribute,
+                   mismatch.actual)?;
+        }
+        Ok(())
+    }
+}
+
+// Returns the versions and profiles to try creating a context with, best first, as pairs of a
+// version and whether to use the compatibility profile.
+pub(crate) fn version_candidates(gl_api: GLApi,
+                                 version: GLVersion,
+                                 minimum_version: GLVersion,
+                                 compatibility_profile: bool,
+                                 allow_core_profile: bool)
+                                 -> Vec<(GLVersion, bool)> {
+    let mut candidates = vec![];
+    for version in version.descending_to(minimum_version, gl_api) {
+        candidates.push((version, compatibility_profile));
+        if compatibility_profile && allow_core_profile {
+            candidates.push((version, false));
+        }
     }
+    candidates
 }
 
+// The minimum alpha, depth, and stencil sizes that the given flags ask for.
+fn requested_sizes(flags: ContextAttributeFlags) -> (u32, u32, u32) {
+    let alpha_size   = if flags.contains(ContextAttributeFlags::ALPHA)   { 8  } else { 0 };
+    let depth_size   = if flags.contains(ContextAttributeFlags::DEPTH)   { 24 } else { 0 };
+    let stencil_size = if flags.contains(ContextAttributeFlags::STENCIL) { 8  } else { 0 };
+    (alpha_size, depth_size, stencil_size)
+}
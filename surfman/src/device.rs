@@ -2,11 +2,19 @@
 //
 //! The abstract interface that all devices conform to.
 
-use crate::{ContextAttributes, ContextID, Error, GLApi, SurfaceAccess, SurfaceInfo, SurfaceType};
+use crate::{ContextAttributes, ContextDescriptorDiagnostics, ContextID, DeviceCapabilities};
+use crate::{Error, GLApi, GLInfo, LeakPolicy};
+use crate::{SurfaceAccess, SurfaceFormat, SurfaceInfo};
+use crate::{PixelFormatAttributes, SurfaceTextureTarget, SurfaceType};
+use crate::ipc::SurfaceHandle;
+#[cfg(unix)]
+use crate::video::PlanarImage;
 use crate::gl::types::{GLenum, GLuint};
 use super::connection::Connection as ConnectionInterface;
 
+use euclid::default::Rect;
 use std::os::raw::c_void;
+use std::rc::Rc;
 
 /// A thread-local handle to a device.
 ///
@@ -20,6 +28,8 @@ pub trait Device: Sized where Self::Connection: ConnectionInterface {
     type ContextDescriptor;
     /// The native context type associated with this device.
     type NativeContext;
+    /// The native image type that surfaces can be adopted from on this device.
+    type NativeImage;
     /// The surface type associated with this device.
     type Surface;
     /// The surface texture type associated with this device.
@@ -36,9 +46,23 @@ pub trait Device: Sized where Self::Connection: ConnectionInterface {
     /// Returns the adapter that this device was created with.
     fn adapter(&self) -> <Self::Connection as ConnectionInterface>::Adapter;
 
-    /// Returns the OpenGL API flavor that this device supports (OpenGL or OpenGL ES).
+    /// Returns the OpenGL API flavor that contexts on this device use by default (OpenGL or
+    /// OpenGL ES).
+    ///
+    /// Some devices can also create contexts of the other flavor if `ContextAttributes::api`
+    /// requests it. Use `context_gl_api()` to find out which flavor a context has.
     fn gl_api(&self) -> GLApi;
 
+    /// Returns the optional features that this device supports.
+    fn capabilities(&self) -> DeviceCapabilities;
+
+    /// Sets what happens to contexts and surfaces created with this device that are dropped
+    /// without being destroyed, overriding the global leak policy.
+    fn set_leak_policy(&self, policy: LeakPolicy);
+
+    /// Returns the leak policy that this device follows.
+    fn leak_policy(&self) -> LeakPolicy;
+
     // context.rs
 
     /// Creates a context descriptor with the given attributes.
@@ -47,6 +71,30 @@ pub trait Device: Sized where Self::Connection: ConnectionInterface {
     fn create_context_descriptor(&self, attributes: &ContextAttributes)
                                  -> Result<Self::ContextDescriptor, Error>;
 
+    /// Returns a context descriptor for every pixel format that this device can create contexts
+    /// with, along with the attributes of each pixel format.
+    ///
+    /// The version and profile of the descriptors come from `attributes`. The `ALPHA`, `DEPTH`,
+    /// and `STENCIL` flags are ignored.
+    fn enumerate_context_descriptors(&self, attributes: &ContextAttributes)
+                                     -> Result<Vec<(Self::ContextDescriptor,
+                                                    PixelFormatAttributes)>, Error>;
+
+    /// Creates a context descriptor from the pixel format that `choose` picks out of those that
+    /// `enumerate_context_descriptors()` returns.
+    ///
+    /// `choose` returns an index into the slice it's given, or `None` to fail with
+    /// `NoPixelFormatFound`.
+    fn create_context_descriptor_with<F>(&self, attributes: &ContextAttributes, choose: F)
+                                         -> Result<Self::ContextDescriptor, Error>
+                                         where F: FnOnce(&[PixelFormatAttributes]) ->
+                                               Option<usize>;
+
+    /// Explains which pixel formats `create_context_descriptor()` passes over for the given
+    /// attributes, and why.
+    fn diagnose_context_descriptor(&self, attributes: &ContextAttributes)
+                                   -> ContextDescriptorDiagnostics;
+
     /// Creates a new OpenGL context.
     /// 
     /// The context initially has no surface attached. Until a surface is bound to it, rendering
@@ -81,6 +129,16 @@ pub trait Device: Sized where Self::Connection: ConnectionInterface {
     fn context_descriptor_attributes(&self, context_descriptor: &Self::ContextDescriptor)
                                      -> ContextAttributes;
 
+    /// Returns the OpenGL API flavor (OpenGL or OpenGL ES) that the context was created for.
+    fn context_gl_api(&self, context: &Self::Context) -> GLApi;
+
+    /// Returns what the OpenGL implementation reports about the context: its version and
+    /// profile, the vendor and renderer, and the extensions it supports.
+    ///
+    /// The context is queried the first time this is called, and the same information is
+    /// returned after that.
+    fn gl_info(&self, context: &Self::Context) -> Result<Rc<GLInfo>, Error>;
+
     /// Fetches the address of an OpenGL function associated with this context.
     /// 
     /// OpenGL functions are local to a context. You should not use OpenGL functions on one context
@@ -137,6 +195,19 @@ pub trait Device: Sized where Self::Connection: ConnectionInterface {
         surface_type: SurfaceType<<Self::Connection as ConnectionInterface>::NativeWidget>)
         -> Result<Self::Surface, Error>;
 
+    /// Adopts an image that another library created as a generic surface, without taking
+    /// ownership of it.
+    /// 
+    /// The resulting surface can be bound to the given context with `bind_surface_to_context()`
+    /// and wrapped in a `SurfaceTexture` like any other generic surface. Destroying the surface
+    /// leaves the native image alone, so the image must outlive it.
+    /// 
+    /// The CPU can't access the data of adopted surfaces.
+    unsafe fn create_surface_from_native_image(&self,
+                                               context: &Self::Context,
+                                               native_image: Self::NativeImage)
+                                               -> Result<Self::Surface, Error>;
+
     /// Creates a surface texture from an existing generic surface for use with the given context.
     /// 
     /// The surface texture is local to the supplied context and takes ownership of the surface.
@@ -155,8 +226,8 @@ pub trait Device: Sized where Self::Connection: ConnectionInterface {
     /// The supplied context must be the context the surface is associated with, or this returns
     /// an `IncompatibleSurface` error.
     /// 
-    /// You must explicitly call this method to dispose of a surface. Otherwise, a panic occurs in
-    /// the `drop` method.
+    /// You should explicitly call this method to dispose of a surface. Otherwise, the surface is
+    /// handled according to the device's `LeakPolicy` when it is dropped.
     fn destroy_surface(&self, context: &mut Self::Context, surface: &mut Self::Surface)
                        -> Result<(), Error>;
 
@@ -165,18 +236,29 @@ pub trait Device: Sized where Self::Connection: ConnectionInterface {
     /// The supplied context must be the same context the surface texture was created with, or an
     /// `IncompatibleSurfaceTexture` error is returned.
     /// 
-    /// All surface textures must be explicitly destroyed with this function, or a panic will
-    /// occur.
+    /// All surface textures should be explicitly destroyed with this function. Otherwise, the
+    /// texture object is leaked and the surface is handled according to the device's `LeakPolicy`
+    /// when the surface texture is dropped.
     fn destroy_surface_texture(&self,
                                context: &mut Self::Context,
                                surface_texture: Self::SurfaceTexture)
                                -> Result<Self::Surface, (Error, Self::SurfaceTexture)>;
 
-    /// Returns the OpenGL texture target needed to read from this surface texture.
+    /// Returns the OpenGL texture target needed to read from textures wrapping surfaces created
+    /// on this device.
     /// 
-    /// This will be `GL_TEXTURE_2D` or `GL_TEXTURE_RECTANGLE`, depending on platform.
+    /// This will be `GL_TEXTURE_2D` or `GL_TEXTURE_RECTANGLE`, depending on platform. Textures
+    /// created from handles may use a different target; `surface_texture_target()` returns the
+    /// target of any particular surface texture.
     fn surface_gl_texture_target(&self) -> GLenum;
 
+    /// Returns the formats that generic surfaces created on this device with the given context may
+    /// have.
+    /// 
+    /// Some formats depend on the version and extensions of the context. `SurfaceFormat::RGBA8` is
+    /// supported everywhere.
+    fn supported_surface_formats(&self, context: &Self::Context) -> Vec<SurfaceFormat>;
+
     /// Displays the contents of a widget surface on screen.
     /// 
     /// Widget surfaces are internally double-buffered, so changes to them don't show up in their
@@ -187,6 +269,25 @@ pub trait Device: Sized where Self::Connection: ConnectionInterface {
     fn present_surface(&self, context: &Self::Context, surface: &mut Self::Surface)
                        -> Result<(), Error>;
 
+    /// Copies pixels from the CPU into a rectangle of a generic surface.
+    /// 
+    /// `data` holds the rows of `rect` in order, `stride` bytes apart, in the given format, which
+    /// must be the surface's format. Row 0 of `rect` is row 0 of the surface, as seen through a
+    /// surface texture.
+    /// 
+    /// The surface must allow CPU access, or a `SurfaceDataInaccessible` error is returned. On
+    /// surfaces created with `SurfaceAccess::GPUCPUWriteCombined`, the upload may complete
+    /// asynchronously. The supplied context must match the context the surface was created with,
+    /// or an `IncompatibleSurface` error is returned.
+    fn upload_surface_data(&self,
+                           context: &Self::Context,
+                           surface: &mut Self::Surface,
+                           rect: &Rect<i32>,
+                           data: &[u8],
+                           stride: usize,
+                           format: SurfaceFormat)
+                           -> Result<(), Error>;
+
     /// Returns various information about the surface, including the framebuffer object needed to
     /// render to this surface.
     /// 
@@ -199,4 +300,77 @@ pub trait Device: Sized where Self::Connection: ConnectionInterface {
     /// 
     /// It is only legal to read from, not write to, this texture object.
     fn surface_texture_object(&self, surface_texture: &Self::SurfaceTexture) -> GLuint;
+
+    /// Returns the OpenGL texture object containing the depth buffer of the surface, or `None` if
+    /// the surface wasn't created with a sampleable depth buffer.
+    /// 
+    /// It is only legal to read from, not write to, this texture object.
+    fn surface_texture_depth_object(&self, surface_texture: &Self::SurfaceTexture)
+                                    -> Option<GLuint>;
+
+    /// Returns the OpenGL texture object containing the given layer of the surface, or `None` if
+    /// the surface has no such layer.
+    /// 
+    /// This is `surface_texture_object()` unless the surface's layers are stored in an array
+    /// texture, in which case each layer has a texture of its own. It is only legal to read from,
+    /// not write to, this texture object.
+    fn surface_texture_layer_object(&self, surface_texture: &Self::SurfaceTexture, layer: u32)
+                                    -> Option<GLuint>;
+
+    /// Returns the region of `surface_texture_layer_object()` that holds the given layer of the
+    /// surface, in texels, or `None` if the surface has no such layer.
+    /// 
+    /// Surfaces that aren't layered have a single layer that covers the whole texture.
+    fn surface_texture_layer_rect(&self, surface_texture: &Self::SurfaceTexture, layer: u32)
+                                  -> Option<Rect<i32>>;
+
+    /// Returns the OpenGL texture target that the surface texture's texture object must be bound
+    /// to.
+    /// 
+    /// This is `GL_TEXTURE_2D`, `GL_TEXTURE_RECTANGLE`, or `GL_TEXTURE_EXTERNAL_OES`. The last
+    /// is only possible for textures created from handles with `SurfaceTextureTarget::Any`.
+    fn surface_texture_target(&self, surface_texture: &Self::SurfaceTexture) -> GLenum;
+
+    /// Exports a generic surface so that it can be sampled from in another process.
+    /// 
+    /// The supplied context must be the context the surface is associated with. The returned
+    /// handle shares memory with the surface when the platform supports it; otherwise, it
+    /// contains a snapshot of the surface contents in shared memory. The handle doesn't record
+    /// how many layers the surface has.
+    /// 
+    /// Calling this method on a widget surface returns a `WidgetAttached` error.
+    fn export_surface(&self, context: &Self::Context, surface: &Self::Surface)
+                      -> Result<SurfaceHandle, Error>;
+
+    /// Wraps a surface exported from another process in a surface texture local to the given
+    /// context.
+    /// 
+    /// The surface that the returned texture wraps can only be read from. Destroy it with
+    /// `destroy_surface_texture()` followed by `destroy_surface()` as usual.
+    /// 
+    /// Some images, such as YUV dma-bufs, can only be sampled as external textures. Pass
+    /// `SurfaceTextureTarget::Compatible2D` if your shaders can't use `samplerExternalOES`; an
+    /// `UnsupportedTextureTarget` error is returned for such images.
+    fn create_surface_texture_from_handle(&self,
+                                          context: &mut Self::Context,
+                                          handle: SurfaceHandle,
+                                          target: SurfaceTextureTarget)
+                                          -> Result<Self::SurfaceTexture, Error>;
+
+    /// Wraps a decoded video frame in a surface texture local to the given context.
+    /// 
+    /// The frame is imported without a copy if the EGL implementation supports
+    /// `EGL_EXT_image_dma_buf_import`, with its color space and range passed along as hints.
+    /// Such frames usually have to be sampled as `GL_TEXTURE_EXTERNAL_OES` textures. If
+    /// `SurfaceTextureTarget::Compatible2D` is requested for one of those, or if the frame can't
+    /// be imported directly, its planes are mapped and converted to RGBA on the CPU instead.
+    /// 
+    /// The first row of the frame is row 0 of the texture. As with imported handles, the surface
+    /// that the texture wraps can only be read from.
+    #[cfg(unix)]
+    fn create_surface_texture_from_planes(&self,
+                                          context: &mut Self::Context,
+                                          image: &PlanarImage,
+                                          target: SurfaceTextureTarget)
+                                          -> Result<Self::SurfaceTexture, Error>;
 }
@@ -2,6 +2,9 @@
 //
 //! Various errors that methods can produce.
 
+use std::error::Error as StdError;
+use std::fmt::{self, Display, Formatter};
+
 /// Various errors that methods can produce.
 #[derive(Debug)]
 pub enum Error {
@@ -21,16 +24,18 @@ pub enum Error {
     UnsupportedGLProfile,
     /// The system doesn't support the requested OpenGL API version.
     UnsupportedGLVersion,
+    /// The OpenGL implementation reported a version string that couldn't be parsed.
+    UnparseableGLVersion,
     /// Choosing an OpenGL pixel format failed.
-    PixelFormatSelectionFailed(WindowingApiError),
+    PixelFormatSelectionFailed(Box<WindowingApiFailure>),
     /// The system couldn't choose an OpenGL pixel format.
     NoPixelFormatFound,
     /// The system couldn't create an OpenGL context.
-    ContextCreationFailed(WindowingApiError),
+    ContextCreationFailed(Box<WindowingApiFailure>),
     /// The system couldn't destroy the OpenGL context.
-    ContextDestructionFailed(WindowingApiError),
+    ContextDestructionFailed(Box<WindowingApiFailure>),
     /// The system couldn't make the OpenGL context current or not current.
-    MakeCurrentFailed(WindowingApiError),
+    MakeCurrentFailed(Box<WindowingApiFailure>),
     /// The system OpenGL library couldn't be located.
     NoGLLibraryFound,
     /// An extension necessary for this library to function isn't supported.
@@ -46,13 +51,26 @@ pub enum Error {
     /// The device couldn't be opened.
     DeviceOpenFailed,
     /// The system couldn't create a surface.
-    SurfaceCreationFailed(WindowingApiError),
+    SurfaceCreationFailed(Box<WindowingApiFailure>),
+    /// The device doesn't support the requested surface format.
+    UnsupportedSurfaceFormat,
+    /// The display doesn't support the requested color space.
+    UnsupportedColorSpace,
+    /// The requested depth and stencil buffers can't be allocated on this backend.
+    UnsupportedDepthStencilFormat,
+    /// The requested number of layers is zero, or too many to fit in one surface.
+    UnsupportedLayerCount,
+    /// The image can only be sampled as a `GL_TEXTURE_EXTERNAL_OES` texture, but a target
+    /// compatible with `sampler2D` was requested.
+    UnsupportedTextureTarget,
     /// The system couldn't import a surface from another thread.
-    SurfaceImportFailed(WindowingApiError),
+    SurfaceImportFailed(Box<WindowingApiFailure>),
+    /// The system couldn't export a surface for use in another process.
+    SurfaceExportFailed(Box<WindowingApiFailure>),
     /// The system couldn't create a surface texture from a surface.
-    SurfaceTextureCreationFailed(WindowingApiError),
+    SurfaceTextureCreationFailed(Box<WindowingApiFailure>),
     /// The system couldn't present a widget surface.
-    PresentFailed(WindowingApiError),
+    PresentFailed(Box<WindowingApiFailure>),
     /// A context couldn't be created because there is no current context.
     NoCurrentContext,
     /// The current connection couldn't be fetched because there is no current connection.
@@ -78,6 +96,9 @@ pub enum Error {
     SurfaceDataInaccessible,
     /// The surface could not be locked for CPU reading due to an OS error.
     SurfaceLockFailed,
+    /// The rectangle, stride, or length of pixel data supplied for upload doesn't fit the
+    /// surface.
+    InvalidSurfaceData,
     /// A connection to the display server could not be opened.
     ConnectionFailed,
     /// A connection to the window server is required to open a hardware device.
@@ -92,12 +113,14 @@ pub enum Error {
     IncompatibleNativeContext,
     /// The native device does not match the supplied connection.
     IncompatibleNativeDevice,
+    /// The native image does not match the supplied device.
+    IncompatibleNativeImage,
 }
 
 /// Abstraction of the errors that EGL, CGL, GLX, CGL, etc. return.
 ///
 /// They all tend to follow similar patterns.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum WindowingApiError {
     /// Miscellaneous error.
     Failed,
@@ -186,4 +209,190 @@ pub enum WindowingApiError {
     BadOperation,
     /// EGL: The EGL configuration is unsupported.
     BadConfig,
+    /// An error code that doesn't correspond to any of the other variants, as the windowing API
+    /// reported it.
+    Other(i32),
+}
+
+/// A failure that a windowing API call reported, with as much detail as the API gives.
+///
+/// `Error` holds failures in a `Box` so that results stay small.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WindowingApiFailure {
+    /// The kind of error.
+    pub error: WindowingApiError,
+    /// The error code that the windowing API returned, such as the result of `eglGetError()`, if
+    /// there was one.
+    pub native_code: Option<i32>,
+    /// The name of the native function that failed, such as `eglCreateImageKHR`, if the failure
+    /// came from a native call rather than from checks that surfman makes itself.
+    pub call: Option<&'static str>,
+}
+
+impl WindowingApiFailure {
+    /// Records that the given native function failed with the given error code.
+    #[inline]
+    pub fn new(call: &'static str, error: WindowingApiError, native_code: Option<i32>)
+               -> WindowingApiFailure {
+        WindowingApiFailure { error, native_code, call: Some(call) }
+    }
+}
+
+impl From<WindowingApiError> for WindowingApiFailure {
+    #[inline]
+    fn from(error: WindowingApiError) -> WindowingApiFailure {
+        WindowingApiFailure { error, native_code: error.native_code(), call: None }
+    }
+}
+
+impl From<WindowingApiError> for Box<WindowingApiFailure> {
+    #[inline]
+    fn from(error: WindowingApiError) -> Box<WindowingApiFailure> {
+        Box::new(error.into())
+    }
+}
+
+impl Error {
+    /// Returns the windowing API failure behind this error, if it has one.
+    pub fn windowing_api_failure(&self) -> Option<&WindowingApiFailure> {
+        match *self {
+            Error::PixelFormatSelectionFailed(ref failure) |
+            Error::ContextCreationFailed(ref failure) |
+            Error::ContextDestructionFailed(ref failure) |
+            Error::MakeCurrentFailed(ref failure) |
+            Error::SurfaceCreationFailed(ref failure) |
+            Error::SurfaceImportFailed(ref failure) |
+            Error::SurfaceExportFailed(ref failure) |
+            Error::SurfaceTextureCreationFailed(ref failure) |
+            Error::PresentFailed(ref failure) => Some(&**failure),
+            _ => None,
+        }
+    }
+
+    /// Returns the raw error code that the windowing API reported, if there was one.
+    #[inline]
+    pub fn native_code(&self) -> Option<i32> {
+        self.windowing_api_failure().and_then(|failure| failure.native_code)
+    }
+
+    /// Returns the name of the native function whose failure caused this error, if known.
+    #[inline]
+    pub fn failed_call(&self) -> Option<&'static str> {
+        self.windowing_api_failure().and_then(|failure| failure.call)
+    }
+}
+
+impl WindowingApiError {
+    /// Returns the raw error code of an `Other` error.
+    ///
+    /// The other variants are shared between windowing APIs with different numbering, so they
+    /// have no single code; `WindowingApiFailure::native_code` holds the code that was reported.
+    #[inline]
+    pub fn native_code(self) -> Option<i32> {
+        match self {
+            WindowingApiError::Other(code) => Some(code),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        let message = match *self {
+            Error::Failed => "the operation failed",
+            Error::UnsupportedOnThisPlatform => "not supported on this platform",
+            Error::Unimplemented => "not implemented on this platform yet",
+            Error::UnsupportedGLType => "the requested OpenGL API type is unsupported",
+            Error::UnsupportedGLProfile => "the requested OpenGL profile is unsupported",
+            Error::UnsupportedGLVersion => "the requested OpenGL version is unsupported",
+            Error::UnparseableGLVersion => "the OpenGL version string couldn't be parsed",
+            Error::PixelFormatSelectionFailed(_) => "failed to choose a pixel format",
+            Error::NoPixelFormatFound => "no suitable pixel format was found",
+            Error::ContextCreationFailed(_) => "failed to create the context",
+            Error::ContextDestructionFailed(_) => "failed to destroy the context",
+            Error::MakeCurrentFailed(_) => "failed to make the context current or not current",
+            Error::NoGLLibraryFound => "the system OpenGL library couldn't be found",
+            Error::RequiredExtensionUnavailable => "a required extension is unavailable",
+            Error::GLFunctionNotFound => "an OpenGL function couldn't be found",
+            Error::ExternalRenderTarget => "the context renders to an external render target",
+            Error::SurfaceAlreadyBound => "a surface is already bound to the context",
+            Error::NoAdapterFound => "no suitable adapter was found",
+            Error::DeviceOpenFailed => "failed to open the device",
+            Error::SurfaceCreationFailed(_) => "failed to create the surface",
+            Error::UnsupportedSurfaceFormat => "the surface format is unsupported",
+            Error::UnsupportedColorSpace => "the color space is unsupported",
+            Error::UnsupportedDepthStencilFormat => "the depth and stencil format is unsupported",
+            Error::UnsupportedLayerCount => "the layer count is unsupported",
+            Error::UnsupportedTextureTarget => "the texture target is unsupported for this image",
+            Error::SurfaceImportFailed(_) => "failed to import the surface",
+            Error::SurfaceExportFailed(_) => "failed to export the surface",
+            Error::SurfaceTextureCreationFailed(_) => "failed to create the surface texture",
+            Error::PresentFailed(_) => "failed to present the surface",
+            Error::NoCurrentContext => "there is no current context",
+            Error::NoCurrentConnection => "there is no current connection",
+            Error::IncompatibleSurface => "the surface wasn't created from this context",
+            Error::IncompatibleContextDescriptor => {
+                "the context descriptor is incompatible with this device"
+            }
+            Error::IncompatibleContext => "the context is incompatible with this device",
+            Error::IncompatibleSurfaceTexture => {
+                "the surface texture is incompatible with this device"
+            }
+            Error::NoWidgetAttached => "the surface has no widget attached",
+            Error::WidgetAttached => "the surface has a widget attached",
+            Error::InvalidNativeWidget => "the native widget is invalid",
+            Error::SurfaceDataInaccessible => "the surface doesn't allow CPU access",
+            Error::SurfaceLockFailed => "failed to lock the surface",
+            Error::InvalidSurfaceData => "the pixel data doesn't fit the surface",
+            Error::ConnectionFailed => "failed to connect to the display server",
+            Error::ConnectionRequired => "a display server connection is required",
+            Error::IncompatibleAdapter => "the adapter is incompatible with this connection",
+            Error::IncompatibleNativeWidget => "the native widget is incompatible with this device",
+            Error::IncompatibleWinitWindow => "the winit window is incompatible with this backend",
+            Error::IncompatibleNativeContext => {
+                "the native context is incompatible with this device"
+            }
+            Error::IncompatibleNativeDevice => {
+                "the native device is incompatible with this connection"
+            }
+            Error::IncompatibleNativeImage => "the native image is incompatible with this device",
+        };
+        formatter.write_str(message)?;
+        if let Some(failure) = self.windowing_api_failure() {
+            write!(formatter, ": {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.windowing_api_failure().map(|failure| failure as &(dyn StdError + 'static))
+    }
 }
+
+impl Display for WindowingApiFailure {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        if let Some(call) = self.call {
+            write!(formatter, "{} failed: ", call)?;
+        }
+        write!(formatter, "{}", self.error)?;
+        match (self.error, self.native_code) {
+            (WindowingApiError::Other(_), _) | (_, None) => Ok(()),
+            (_, Some(native_code)) => write!(formatter, " ({:#x})", native_code),
+        }
+    }
+}
+
+impl StdError for WindowingApiFailure {}
+
+impl Display for WindowingApiError {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match *self {
+            WindowingApiError::Other(code) => write!(formatter, "error {:#x}", code),
+            _ => write!(formatter, "{:?}", self),
+        }
+    }
+}
+
+impl StdError for WindowingApiError {}
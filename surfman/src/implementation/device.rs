@@ -6,19 +6,28 @@
 use crate::connection::Connection as ConnectionInterface;
 use crate::device::Device as DeviceInterface;
 use crate::gl::types::{GLenum, GLuint};
-use crate::{ContextAttributes, ContextID, Error, GLApi, SurfaceAccess, SurfaceInfo, SurfaceType};
+use crate::ipc::SurfaceHandle;
+#[cfg(unix)]
+use crate::video::PlanarImage;
+use crate::{ContextAttributes, ContextDescriptorDiagnostics, ContextID, DeviceCapabilities};
+use crate::{Error, GLApi, GLInfo, LeakPolicy};
+use crate::{SurfaceAccess, SurfaceFormat, SurfaceInfo};
+use crate::{PixelFormatAttributes, SurfaceTextureTarget, SurfaceType};
 use super::super::connection::Connection;
 use super::super::context::{Context, ContextDescriptor, NativeContext};
 use super::super::device::{Adapter, Device};
-use super::super::surface::{NativeWidget, Surface, SurfaceTexture};
+use super::super::surface::{NativeImage, NativeWidget, Surface, SurfaceTexture};
 
+use euclid::default::Rect;
 use std::os::raw::c_void;
+use std::rc::Rc;
 
 impl DeviceInterface for Device {
     type Connection = Connection;
     type Context = Context;
     type ContextDescriptor = ContextDescriptor;
     type NativeContext = NativeContext;
+    type NativeImage = NativeImage;
     type Surface = Surface;
     type SurfaceTexture = SurfaceTexture;
 
@@ -45,6 +54,21 @@ impl DeviceInterface for Device {
         Device::gl_api(self)
     }
 
+    #[inline]
+    fn capabilities(&self) -> DeviceCapabilities {
+        Device::capabilities(self)
+    }
+
+    #[inline]
+    fn set_leak_policy(&self, policy: LeakPolicy) {
+        Device::set_leak_policy(self, policy)
+    }
+
+    #[inline]
+    fn leak_policy(&self) -> LeakPolicy {
+        Device::leak_policy(self)
+    }
+
     // context.rs
 
     #[inline]
@@ -53,6 +77,27 @@ impl DeviceInterface for Device {
         Device::create_context_descriptor(self, attributes)
     }
 
+    #[inline]
+    fn enumerate_context_descriptors(&self, attributes: &ContextAttributes)
+                                     -> Result<Vec<(Self::ContextDescriptor,
+                                                    PixelFormatAttributes)>, Error> {
+        Device::enumerate_context_descriptors(self, attributes)
+    }
+
+    #[inline]
+    fn create_context_descriptor_with<F>(&self, attributes: &ContextAttributes, choose: F)
+                                         -> Result<Self::ContextDescriptor, Error>
+                                         where F: FnOnce(&[PixelFormatAttributes]) ->
+                                               Option<usize> {
+        Device::create_context_descriptor_with(self, attributes, choose)
+    }
+
+    #[inline]
+    fn diagnose_context_descriptor(&self, attributes: &ContextAttributes)
+                                   -> ContextDescriptorDiagnostics {
+        Device::diagnose_context_descriptor(self, attributes)
+    }
+
     #[inline]
     fn create_context(&mut self, descriptor: &Self::ContextDescriptor)
                       -> Result<Self::Context, Error> {
@@ -91,6 +136,16 @@ impl DeviceInterface for Device {
         Device::context_descriptor_attributes(self, context_descriptor)
     }
 
+    #[inline]
+    fn context_gl_api(&self, context: &Context) -> GLApi {
+        Device::context_gl_api(self, context)
+    }
+
+    #[inline]
+    fn gl_info(&self, context: &Context) -> Result<Rc<GLInfo>, Error> {
+        Device::gl_info(self, context)
+    }
+
     #[inline]
     fn get_proc_address(&self, context: &Self::Context, symbol_name: &str) -> *const c_void {
         Device::get_proc_address(self, context, symbol_name)
@@ -134,6 +189,14 @@ impl DeviceInterface for Device {
         Device::create_surface(self, context, surface_access, surface_type)
     }
 
+    #[inline]
+    unsafe fn create_surface_from_native_image(&self,
+                                               context: &Self::Context,
+                                               native_image: Self::NativeImage)
+                                               -> Result<Self::Surface, Error> {
+        Device::create_surface_from_native_image(self, context, native_image)
+    }
+
     #[inline]
     fn create_surface_texture(&self, context: &mut Self::Context, surface: Self::Surface)
                               -> Result<Self::SurfaceTexture, (Error, Self::Surface)> {
@@ -159,12 +222,29 @@ impl DeviceInterface for Device {
         Device::surface_gl_texture_target(self)
     }
 
+    #[inline]
+    fn supported_surface_formats(&self, context: &Context) -> Vec<SurfaceFormat> {
+        Device::supported_surface_formats(self, context)
+    }
+
     #[inline]
     fn present_surface(&self, context: &Self::Context, surface: &mut Self::Surface)
                        -> Result<(), Error> {
         Device::present_surface(self, context, surface)
     }
 
+    #[inline]
+    fn upload_surface_data(&self,
+                           context: &Self::Context,
+                           surface: &mut Self::Surface,
+                           rect: &Rect<i32>,
+                           data: &[u8],
+                           stride: usize,
+                           format: SurfaceFormat)
+                           -> Result<(), Error> {
+        Device::upload_surface_data(self, context, surface, rect, data, stride, format)
+    }
+
     #[inline]
     fn surface_info(&self, surface: &Self::Surface) -> SurfaceInfo {
         Device::surface_info(self, surface)
@@ -174,4 +254,52 @@ impl DeviceInterface for Device {
     fn surface_texture_object(&self, surface_texture: &Self::SurfaceTexture) -> GLuint {
         Device::surface_texture_object(self, surface_texture)
     }
+
+    #[inline]
+    fn surface_texture_depth_object(&self, surface_texture: &Self::SurfaceTexture)
+                                    -> Option<GLuint> {
+        Device::surface_texture_depth_object(self, surface_texture)
+    }
+
+    #[inline]
+    fn surface_texture_layer_object(&self, surface_texture: &Self::SurfaceTexture, layer: u32)
+                                    -> Option<GLuint> {
+        Device::surface_texture_layer_object(self, surface_texture, layer)
+    }
+
+    #[inline]
+    fn surface_texture_layer_rect(&self, surface_texture: &Self::SurfaceTexture, layer: u32)
+                                  -> Option<Rect<i32>> {
+        Device::surface_texture_layer_rect(self, surface_texture, layer)
+    }
+
+    #[inline]
+    fn surface_texture_target(&self, surface_texture: &Self::SurfaceTexture) -> GLenum {
+        Device::surface_texture_target(self, surface_texture)
+    }
+
+    #[inline]
+    fn export_surface(&self, context: &Self::Context, surface: &Self::Surface)
+                      -> Result<SurfaceHandle, Error> {
+        Device::export_surface(self, context, surface)
+    }
+
+    #[inline]
+    fn create_surface_texture_from_handle(&self,
+                                          context: &mut Self::Context,
+                                          handle: SurfaceHandle,
+                                          target: SurfaceTextureTarget)
+                                          -> Result<Self::SurfaceTexture, Error> {
+        Device::create_surface_texture_from_handle(self, context, handle, target)
+    }
+
+    #[cfg(unix)]
+    #[inline]
+    fn create_surface_texture_from_planes(&self,
+                                          context: &mut Self::Context,
+                                          image: &PlanarImage,
+                                          target: SurfaceTextureTarget)
+                                          -> Result<Self::SurfaceTexture, Error> {
+        Device::create_surface_texture_from_planes(self, context, image, target)
+    }
 }
@@ -2,11 +2,15 @@
 //
 //! OpenGL information.
 
-use crate::Gl;
+use crate::gl::types::{GLenum, GLuint};
 use crate::gl;
+use crate::{Error, Gl};
 
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::ffi::CStr;
 use std::os::raw::c_char;
+use std::rc::Rc;
 
 /// The API (OpenGL or OpenGL ES).
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -21,7 +25,9 @@ pub enum GLApi {
 /// 
 /// Since OpenGL and OpenGL ES have different version numbering schemes, the valid values here
 /// depend on the value of `Device::gl_api()`.
-#[derive(Clone, Copy, Debug, PartialEq)]
+/// 
+/// Versions are ordered by major version and then by minor version.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct GLVersion {
     /// The major OpenGL version (e.g. 4 in 4.2).
     pub major: u8,
@@ -29,6 +35,31 @@ pub struct GLVersion {
     pub minor: u8,
 }
 
+// Every OpenGL version that has a context creation API, newest first.
+static GL_VERSIONS: [GLVersion; 13] = [
+    GLVersion { major: 4, minor: 6 },
+    GLVersion { major: 4, minor: 5 },
+    GLVersion { major: 4, minor: 4 },
+    GLVersion { major: 4, minor: 3 },
+    GLVersion { major: 4, minor: 2 },
+    GLVersion { major: 4, minor: 1 },
+    GLVersion { major: 4, minor: 0 },
+    GLVersion { major: 3, minor: 3 },
+    GLVersion { major: 3, minor: 2 },
+    GLVersion { major: 3, minor: 1 },
+    GLVersion { major: 3, minor: 0 },
+    GLVersion { major: 2, minor: 1 },
+    GLVersion { major: 2, minor: 0 },
+];
+
+// Every OpenGL ES version that has a context creation API, newest first.
+static GL_ES_VERSIONS: [GLVersion; 4] = [
+    GLVersion { major: 3, minor: 2 },
+    GLVersion { major: 3, minor: 1 },
+    GLVersion { major: 3, minor: 0 },
+    GLVersion { major: 2, minor: 0 },
+];
+
 impl GLVersion {
     /// Creates a GL version structure with the given major and minor version numbers.
     #[inline]
@@ -36,25 +67,210 @@ impl GLVersion {
         GLVersion { major, minor }
     }
 
-    pub(crate) fn current(gl: &Gl) -> GLVersion {
+    // Returns this version followed by each older version of `gl_api` down to and including
+    // `minimum`.
+    pub(crate) fn descending_to(self, minimum: GLVersion, gl_api: GLApi) -> Vec<GLVersion> {
+        let known_versions = match gl_api {
+            GLApi::GL => &GL_VERSIONS[..],
+            GLApi::GLES => &GL_ES_VERSIONS[..],
+        };
+        let mut versions = vec![self];
+        versions.extend(known_versions.iter().filter(|&&version| {
+            version < self && version >= minimum
+        }));
+        versions
+    }
+
+    // Parses the first version number in a `GL_VERSION` string, such as "4.6.0 NVIDIA 440.82",
+    // "OpenGL ES 3.2 Mesa 20.0.8", or "OpenGL ES-CM 1.1". Anything after the minor version is
+    // ignored.
+    pub(crate) fn parse(version_string: &str) -> Option<GLVersion> {
+        let number = version_string.split_whitespace().find(|word| {
+            word.starts_with(|c: char| c.is_ascii_digit())
+        })?;
+        let mut components = number.split('.').map(|component| {
+            let digits_end = component.find(|c: char| !c.is_ascii_digit())
+                                      .unwrap_or(component.len());
+            component[..digits_end].parse().ok()
+        });
+        let major = components.next()??;
+        let minor = components.next()??;
+        Some(GLVersion { major, minor })
+    }
+}
+
+/// Information that the OpenGL implementation reports about a context.
+///
+/// Fetch this with `Device::gl_info()`.
+#[derive(Clone, Debug)]
+pub struct GLInfo {
+    /// Whether the context is an OpenGL or an OpenGL ES context.
+    pub api: GLApi,
+    /// The version of the context, which may be newer than the version that was requested.
+    pub version: GLVersion,
+    /// Whether the context uses the OpenGL compatibility profile. This is always false for
+    /// OpenGL ES contexts.
+    pub compatibility_profile: bool,
+    /// The company responsible for the OpenGL implementation, from `GL_VENDOR`.
+    pub vendor: String,
+    /// The name of the renderer, typically naming the GPU, from `GL_RENDERER`.
+    pub renderer: String,
+    /// The shading language version string, from `GL_SHADING_LANGUAGE_VERSION`.
+    ///
+    /// This is `None` for contexts without a shading language, such as OpenGL ES 1.x contexts.
+    pub shading_language_version: Option<String>,
+    /// The names of the extensions that the context supports, such as `GL_ARB_compatibility`.
+    pub extensions: HashSet<String>,
+}
+
+impl GLInfo {
+    /// Returns true if the context supports the named extension.
+    #[inline]
+    pub fn supports_extension(&self, name: &str) -> bool {
+        self.extensions.contains(name)
+    }
+
+    // Queries the current context.
+    pub(crate) fn current(gl: &Gl) -> Result<GLInfo, Error> {
         unsafe {
-            let version_string = gl.GetString(gl::VERSION) as *const c_char;
-            let version_string = CStr::from_ptr(version_string).to_string_lossy()
-                                                               .trim_start_matches("OpenGL ES")
-                                                               .trim_start()
-                                                               .to_owned();
-            let mut version_string_iter = version_string.split(|c| c == '.' || c == ' ');
-            let major_version: u8 =
-                version_string_iter.next()
-                                   .expect("Where's the major GL version?")
-                                   .parse()
-                                   .expect("Couldn't parse the major GL version!");
-            let minor_version: u8 =
-                version_string_iter.next()
-                                   .expect("Where's the minor GL version?")
-                                   .parse()
-                                   .expect("Couldn't parse the minor GL version!");
-            GLVersion { major: major_version, minor: minor_version }
+            let version_string = get_string(gl, gl::VERSION).ok_or(Error::Failed)?;
+            let version = match GLVersion::parse(&version_string) {
+                None => return Err(Error::UnparseableGLVersion),
+                Some(version) => version,
+            };
+            let api = if version_string.starts_with("OpenGL ES") {
+                GLApi::GLES
+            } else {
+                GLApi::GL
+            };
+
+            let extensions = current_extensions(gl, version);
+            let compatibility_profile = api == GLApi::GL &&
+                current_context_uses_compatibility_profile(gl, &extensions);
+
+            Ok(GLInfo {
+                api,
+                version,
+                compatibility_profile,
+                vendor: get_string(gl, gl::VENDOR).ok_or(Error::Failed)?,
+                renderer: get_string(gl, gl::RENDERER).ok_or(Error::Failed)?,
+                shading_language_version: get_string(gl, gl::SHADING_LANGUAGE_VERSION),
+                extensions,
+            })
+        }
+    }
+}
+
+/// The optional features that a device supports.
+///
+/// Using a missing feature fails with an error such as `Unimplemented`,
+/// `UnsupportedOnThisPlatform`, or `RequiredExtensionUnavailable`. Checking these first lets
+/// applications choose a code path before creating anything.
+///
+/// Fetch this with `Device::capabilities()`.
+#[derive(Clone, Debug)]
+pub struct DeviceCapabilities {
+    /// Whether `create_surface()` can create widget surfaces that display in a native window.
+    pub widget_surfaces: bool,
+    /// Whether `lock_surface_data()` can map surfaces that allow CPU access for reading.
+    pub cpu_surface_reads: bool,
+    /// Whether `upload_surface_data()` can copy pixels into surfaces that allow CPU access.
+    pub cpu_surface_uploads: bool,
+    /// Whether surfaces can be wrapped in surface textures for contexts on other devices opened
+    /// from the same connection.
+    pub cross_device_surface_textures: bool,
+    /// Whether the window system can present widget surfaces with damage regions, so that only
+    /// the parts of the window that changed are redrawn.
+    ///
+    /// `present_surface()` always presents the whole surface; this reports whether the native
+    /// swap path (`EGL_KHR_swap_buffers_with_damage` or `EGL_EXT_swap_buffers_with_damage`) is
+    /// there.
+    pub damage_present: bool,
+    /// The OpenGL texture target that `surface_gl_texture_target()` returns.
+    pub surface_texture_target: GLenum,
+    /// The extensions that the window system binding advertises: EGL display extensions, or WGL
+    /// extensions on Windows. This is empty with CGL, which has no extensions.
+    ///
+    /// OpenGL extensions vary from context to context; `Device::gl_info()` lists those.
+    pub platform_extensions: HashSet<String>,
+}
+
+impl DeviceCapabilities {
+    /// Returns true if the window system binding advertises the named extension.
+    #[inline]
+    pub fn supports_platform_extension(&self, name: &str) -> bool {
+        self.platform_extensions.contains(name)
+    }
+}
+
+// Holds the information about a context once it has been queried, so that each context is only
+// queried once.
+#[derive(Default)]
+pub(crate) struct GLInfoCache(RefCell<Option<Rc<GLInfo>>>);
+
+impl GLInfoCache {
+    pub(crate) fn get_or_query<F>(&self, query: F) -> Result<Rc<GLInfo>, Error>
+                                  where F: FnOnce() -> Result<GLInfo, Error> {
+        if let Some(ref gl_info) = *self.0.borrow() {
+            return Ok(gl_info.clone());
         }
+        let gl_info = Rc::new(query()?);
+        *self.0.borrow_mut() = Some(gl_info.clone());
+        Ok(gl_info)
     }
 }
+
+unsafe fn get_string(gl: &Gl, name: GLenum) -> Option<String> {
+    let string = gl.GetString(name) as *const c_char;
+    if string.is_null() {
+        // Clear the error, if any, so that callers don't see it.
+        gl.GetError();
+        return None;
+    }
+    Some(CStr::from_ptr(string).to_string_lossy().into_owned())
+}
+
+// OpenGL 3.0 and OpenGL ES 3.0 list extensions one at a time, and core profile contexts don't
+// support the older space-separated `GL_EXTENSIONS` string at all.
+unsafe fn current_extensions(gl: &Gl, version: GLVersion) -> HashSet<String> {
+    if version.major < 3 {
+        return match get_string(gl, gl::EXTENSIONS) {
+            None => HashSet::new(),
+            Some(extensions) => extensions.split_whitespace().map(|name| name.to_owned()).collect(),
+        };
+    }
+
+    let mut extension_count = 0;
+    gl.GetIntegerv(gl::NUM_EXTENSIONS, &mut extension_count);
+    if gl.GetError() != gl::NO_ERROR {
+        return HashSet::new();
+    }
+    (0..(extension_count as GLuint)).filter_map(|extension_index| {
+        let extension = gl.GetStringi(gl::EXTENSIONS, extension_index) as *const c_char;
+        if extension.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(extension).to_string_lossy().into_owned())
+        }
+    }).collect()
+}
+
+#[cfg(target_os = "android")]
+unsafe fn current_context_uses_compatibility_profile(_: &Gl, _: &HashSet<String>) -> bool {
+    false
+}
+
+#[cfg(not(target_os = "android"))]
+unsafe fn current_context_uses_compatibility_profile(gl: &Gl, extensions: &HashSet<String>)
+                                                     -> bool {
+    // First, try `GL_CONTEXT_PROFILE_MASK`.
+    let mut context_profile_mask = 0;
+    gl.GetIntegerv(gl::CONTEXT_PROFILE_MASK, &mut context_profile_mask);
+    if gl.GetError() == gl::NO_ERROR &&
+            (context_profile_mask & gl::CONTEXT_COMPATIBILITY_PROFILE_BIT as i32) != 0 {
+        return true;
+    }
+
+    // Second, look for the `GL_ARB_compatibility` extension.
+    extensions.contains("GL_ARB_compatibility")
+}
@@ -0,0 +1,601 @@
+// surfman/surfman/src/ipc.rs
+//
+//! Serializable surface handles for sharing surfaces between processes.
+//!
+//! A process that renders to a generic surface can export it with `Device::export_surface()`
+//! and send the resulting `SurfaceHandle` to another process, which wraps it in a
+//! `SurfaceTexture` with `Device::create_surface_texture_from_handle()`. Handles are zero-copy
+//! where the platform allows it: dma-bufs on Linux, `IOSurface`s on macOS, and Direct3D share
+//! handles on Windows with ANGLE. When zero-copy export isn't available, the surface contents are
+//! copied into an anonymous shared memory segment instead.
+//!
+//! On Unix, handles can be sent over a `UnixStream` with `send_surface_handle()` and
+//! `recv_surface_handle()`. File descriptors are passed as `SCM_RIGHTS` ancillary data.
+
+use euclid::default::Size2D;
+
+#[cfg(unix)]
+use crate::{Error, SurfaceFormat, WindowingApiError, WindowingApiFailure};
+#[cfg(unix)]
+use std::io::{self, Read};
+#[cfg(unix)]
+use std::mem;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::ptr;
+#[cfg(unix)]
+use std::slice;
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The maximum number of planes that a dma-buf image can have.
+pub const MAX_DMA_BUF_PLANES: usize = 4;
+
+#[cfg(unix)]
+const MESSAGE_SIZE: usize = 128;
+
+#[cfg(unix)]
+const TAG_DMA_BUF: u32 = 1;
+#[cfg(unix)]
+const TAG_IO_SURFACE: u32 = 2;
+#[cfg(unix)]
+const TAG_D3D_SHARE_HANDLE: u32 = 3;
+#[cfg(unix)]
+const TAG_SHARED_MEMORY: u32 = 4;
+
+/// A platform-neutral handle to a surface that can be sent to another process.
+///
+/// Handles that contain file descriptors own them and close them when dropped.
+#[derive(Debug)]
+pub enum SurfaceHandle {
+    /// A Linux dma-buf image, exported via `EGL_MESA_image_dma_buf_export`.
+    #[cfg(unix)]
+    DmaBuf(DmaBufImage),
+    /// A global `IOSurface` on macOS, identified by its ID.
+    IOSurface {
+        /// The ID of the surface, as returned by `IOSurfaceGetID()`.
+        id: u32,
+        /// The size of the surface in pixels.
+        size: Size2D<i32>,
+    },
+    /// A Direct3D 11 texture share handle, as used by ANGLE.
+    D3DShareHandle {
+        /// The legacy (non-NT) share handle of the texture.
+        handle: usize,
+        /// The size of the surface in pixels.
+        size: Size2D<i32>,
+    },
+    /// A CPU copy of the surface contents in shared memory.
+    ///
+    /// This is a snapshot taken at export time, not a live view of the surface.
+    #[cfg(unix)]
+    SharedMemory(SharedMemoryImage),
+}
+
+/// A dma-buf image, which consists of one or more planes.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct DmaBufImage {
+    /// The size of the image in pixels.
+    pub size: Size2D<i32>,
+    /// The DRM fourcc code describing the pixel format.
+    pub fourcc: u32,
+    /// The DRM format modifier describing the memory layout.
+    pub modifier: u64,
+    /// The planes of the image. There are at most `MAX_DMA_BUF_PLANES` of these.
+    pub planes: Vec<DmaBufPlane>,
+}
+
+/// A single plane of a dma-buf image.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct DmaBufPlane {
+    /// The dma-buf file descriptor. This is owned by the enclosing `SurfaceHandle`, if any.
+    pub fd: RawFd,
+    /// The offset of the plane data within the buffer, in bytes.
+    pub offset: u32,
+    /// The number of bytes per row of the plane.
+    pub stride: u32,
+}
+
+/// A copy of surface pixels in an anonymous shared memory segment.
+///
+/// Pixels are stored as rows in the layout of `format`, bottom row first, as `glReadPixels()`
+/// produces them.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct SharedMemoryImage {
+    /// The shared memory file descriptor. This is owned by the enclosing `SurfaceHandle`.
+    pub fd: RawFd,
+    /// The size of the image in pixels.
+    pub size: Size2D<i32>,
+    /// The number of bytes per row.
+    pub stride: u32,
+    /// The format of the pixels.
+    pub format: SurfaceFormat,
+}
+
+impl SurfaceHandle {
+    /// Returns the size of the surface that this handle refers to.
+    pub fn size(&self) -> Size2D<i32> {
+        match *self {
+            #[cfg(unix)]
+            SurfaceHandle::DmaBuf(ref image) => image.size,
+            #[cfg(unix)]
+            SurfaceHandle::SharedMemory(ref image) => image.size,
+            SurfaceHandle::IOSurface { size, .. } |
+            SurfaceHandle::D3DShareHandle { size, .. } => size,
+        }
+    }
+
+    /// Returns true if importing this handle shares memory with the exporting surface, or false
+    /// if it is a copy.
+    pub fn is_zero_copy(&self) -> bool {
+        match *self {
+            #[cfg(unix)]
+            SurfaceHandle::SharedMemory(_) => false,
+            _ => true,
+        }
+    }
+
+    #[cfg(unix)]
+    fn fds(&self) -> Vec<RawFd> {
+        match *self {
+            SurfaceHandle::DmaBuf(ref image) => image.planes.iter().map(|plane| plane.fd).collect(),
+            SurfaceHandle::SharedMemory(ref image) => vec![image.fd],
+            SurfaceHandle::IOSurface { .. } | SurfaceHandle::D3DShareHandle { .. } => vec![],
+        }
+    }
+
+    #[cfg(unix)]
+    fn serialize(&self) -> [u8; MESSAGE_SIZE] {
+        let mut writer = MessageWriter { buffer: [0; MESSAGE_SIZE], offset: 0 };
+        match *self {
+            SurfaceHandle::DmaBuf(ref image) => {
+                writer.write_u32(TAG_DMA_BUF);
+                writer.write_size(&image.size);
+                writer.write_u32(image.fourcc);
+                writer.write_u64(image.modifier);
+                writer.write_u32(image.planes.len() as u32);
+                for plane in &image.planes {
+                    writer.write_u32(plane.offset);
+                    writer.write_u32(plane.stride);
+                }
+            }
+            SurfaceHandle::IOSurface { id, ref size } => {
+                writer.write_u32(TAG_IO_SURFACE);
+                writer.write_size(size);
+                writer.write_u32(id);
+            }
+            SurfaceHandle::D3DShareHandle { handle, ref size } => {
+                writer.write_u32(TAG_D3D_SHARE_HANDLE);
+                writer.write_size(size);
+                writer.write_u64(handle as u64);
+            }
+            SurfaceHandle::SharedMemory(ref image) => {
+                writer.write_u32(TAG_SHARED_MEMORY);
+                writer.write_size(&image.size);
+                writer.write_u32(image.stride);
+                writer.write_u32(surface_format_to_u32(image.format));
+            }
+        }
+        writer.buffer
+    }
+
+    // Takes ownership of the file descriptors, closing them on failure.
+    #[cfg(unix)]
+    fn deserialize(message: &[u8; MESSAGE_SIZE], fds: Vec<RawFd>) -> io::Result<SurfaceHandle> {
+        let mut reader = MessageReader { buffer: message, offset: 0 };
+        let mut fds = FdList(fds);
+        let handle = match reader.read_u32() {
+            TAG_DMA_BUF => {
+                let size = reader.read_size();
+                let fourcc = reader.read_u32();
+                let modifier = reader.read_u64();
+                let plane_count = reader.read_u32() as usize;
+                if plane_count == 0 || plane_count > MAX_DMA_BUF_PLANES ||
+                        plane_count != fds.0.len() {
+                    return Err(invalid_message());
+                }
+                let planes = fds.take().into_iter().map(|fd| {
+                    DmaBufPlane { fd, offset: reader.read_u32(), stride: reader.read_u32() }
+                }).collect();
+                SurfaceHandle::DmaBuf(DmaBufImage { size, fourcc, modifier, planes })
+            }
+            TAG_IO_SURFACE if fds.0.is_empty() => {
+                let size = reader.read_size();
+                SurfaceHandle::IOSurface { size, id: reader.read_u32() }
+            }
+            TAG_D3D_SHARE_HANDLE if fds.0.is_empty() => {
+                let size = reader.read_size();
+                SurfaceHandle::D3DShareHandle { size, handle: reader.read_u64() as usize }
+            }
+            TAG_SHARED_MEMORY if fds.0.len() == 1 => {
+                let size = reader.read_size();
+                let stride = reader.read_u32();
+                let format = match surface_format_from_u32(reader.read_u32()) {
+                    Some(format) => format,
+                    None => return Err(invalid_message()),
+                };
+                let fd = fds.take()[0];
+                SurfaceHandle::SharedMemory(SharedMemoryImage { fd, size, stride, format })
+            }
+            _ => return Err(invalid_message()),
+        };
+        Ok(handle)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for SurfaceHandle {
+    fn drop(&mut self) {
+        // Planes of the same dma-buf may share a file descriptor.
+        let mut fds = self.fds();
+        fds.sort();
+        fds.dedup();
+        for fd in fds {
+            unsafe {
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+/// Sends a surface handle over a Unix domain socket.
+///
+/// Any file descriptors in the handle are duplicated into the receiving process; the handle
+/// retains ownership of its own copies.
+#[cfg(unix)]
+pub fn send_surface_handle(socket: &UnixStream, handle: &SurfaceHandle) -> io::Result<()> {
+    let mut message = handle.serialize();
+    let fds = handle.fds();
+
+    unsafe {
+        let mut iovec = libc::iovec {
+            iov_base: message.as_mut_ptr() as *mut libc::c_void,
+            iov_len: MESSAGE_SIZE,
+        };
+
+        let mut control = vec![0u8; control_buffer_size(fds.len())];
+        let mut msghdr: libc::msghdr = mem::zeroed();
+        msghdr.msg_iov = &mut iovec;
+        msghdr.msg_iovlen = 1;
+        if !fds.is_empty() {
+            msghdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msghdr.msg_controllen = control.len() as _;
+
+            let cmsg = libc::CMSG_FIRSTHDR(&msghdr);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN((fds.len() * mem::size_of::<RawFd>()) as u32) as _;
+            ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg) as *mut RawFd, fds.len());
+        }
+
+        let sent = libc::sendmsg(socket.as_raw_fd(), &msghdr, 0);
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Ancillary data only accompanies the first chunk, so any remainder can go out as plain
+        // bytes.
+        let mut socket = socket;
+        io::Write::write_all(&mut socket, &message[(sent as usize)..])
+    }
+}
+
+/// Receives a surface handle sent with `send_surface_handle()` from a Unix domain socket.
+#[cfg(unix)]
+pub fn recv_surface_handle(socket: &UnixStream) -> io::Result<SurfaceHandle> {
+    let mut message = [0; MESSAGE_SIZE];
+    let mut fds = FdList(vec![]);
+
+    unsafe {
+        let mut iovec = libc::iovec {
+            iov_base: message.as_mut_ptr() as *mut libc::c_void,
+            iov_len: MESSAGE_SIZE,
+        };
+
+        let mut control = vec![0u8; control_buffer_size(MAX_DMA_BUF_PLANES)];
+        let mut msghdr: libc::msghdr = mem::zeroed();
+        msghdr.msg_iov = &mut iovec;
+        msghdr.msg_iovlen = 1;
+        msghdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msghdr.msg_controllen = control.len() as _;
+
+        let received = libc::recvmsg(socket.as_raw_fd(), &mut msghdr, RECVMSG_FLAGS);
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if received == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msghdr);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                let data_len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                let count = data_len / mem::size_of::<RawFd>();
+                fds.0.extend_from_slice(slice::from_raw_parts(data, count));
+            }
+            cmsg = libc::CMSG_NXTHDR(&msghdr, cmsg);
+        }
+        set_close_on_exec(&fds.0);
+
+        if msghdr.msg_flags & libc::MSG_CTRUNC != 0 {
+            return Err(invalid_message());
+        }
+
+        let mut socket = socket;
+        socket.read_exact(&mut message[(received as usize)..])?;
+    }
+
+    SurfaceHandle::deserialize(&message, fds.take())
+}
+
+// Linux and Android can mark received file descriptors close-on-exec atomically.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const RECVMSG_FLAGS: libc::c_int = libc::MSG_CMSG_CLOEXEC;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_close_on_exec(_: &[RawFd]) {}
+
+// Other Unix systems, such as macOS, lack `MSG_CMSG_CLOEXEC`, so the flag is set afterward.
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+const RECVMSG_FLAGS: libc::c_int = 0;
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+fn set_close_on_exec(fds: &[RawFd]) {
+    for &fd in fds {
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFD);
+            if flags >= 0 {
+                libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC);
+            }
+        }
+    }
+}
+
+/// A mapping of an anonymous shared memory segment or a dma-buf.
+#[cfg(unix)]
+pub(crate) struct SharedMemoryMapping {
+    pub(crate) ptr: *mut u8,
+    pub(crate) len: usize,
+}
+
+#[cfg(unix)]
+impl SharedMemoryMapping {
+    /// Allocates a new shared memory segment of the given length and maps it.
+    ///
+    /// Returns the owned file descriptor along with the mapping.
+    pub(crate) fn create(len: usize) -> Result<(RawFd, SharedMemoryMapping), Error> {
+        unsafe {
+            let fd = create_anonymous_shared_memory();
+            if fd < 0 {
+                let failure = os_failure(CREATE_SHARED_MEMORY_CALL);
+                return Err(Error::SurfaceExportFailed(Box::new(failure)));
+            }
+            if libc::ftruncate(fd, len as libc::off_t) < 0 {
+                let failure = os_failure("ftruncate");
+                libc::close(fd);
+                return Err(Error::SurfaceExportFailed(Box::new(failure)));
+            }
+            match SharedMemoryMapping::map(fd, len) {
+                Some(mapping) => Ok((fd, mapping)),
+                None => {
+                    let failure = os_failure("mmap");
+                    libc::close(fd);
+                    Err(Error::SurfaceExportFailed(Box::new(failure)))
+                }
+            }
+        }
+    }
+
+    /// Maps an existing shared memory segment. The file descriptor is not consumed.
+    pub(crate) fn open(fd: RawFd, len: usize) -> Result<SharedMemoryMapping, Error> {
+        unsafe {
+            let mut stat: libc::stat = mem::zeroed();
+            if libc::fstat(fd, &mut stat) < 0 || (stat.st_size as usize) < len {
+                return Err(Error::SurfaceImportFailed(WindowingApiError::BadParameter.into()));
+            }
+            SharedMemoryMapping::map(fd, len)
+                .ok_or_else(|| Error::SurfaceImportFailed(Box::new(os_failure("mmap"))))
+        }
+    }
+
+    /// Maps the first `len` bytes of a file descriptor for reading only. The file descriptor is
+    /// not consumed.
+    ///
+    /// Unlike `open()`, this works for dma-bufs, which may not be writable and don't report their
+    /// size through `fstat()`.
+    pub(crate) fn open_read_only(fd: RawFd, len: usize) -> Result<SharedMemoryMapping, Error> {
+        unsafe {
+            let size = libc::lseek(fd, 0, libc::SEEK_END);
+            if size < 0 || (size as usize) < len || len == 0 {
+                return Err(Error::SurfaceImportFailed(WindowingApiError::BadParameter.into()));
+            }
+            SharedMemoryMapping::map_with_protection(fd, len, libc::PROT_READ)
+                .ok_or_else(|| Error::SurfaceImportFailed(Box::new(os_failure("mmap"))))
+        }
+    }
+
+    unsafe fn map(fd: RawFd, len: usize) -> Option<SharedMemoryMapping> {
+        SharedMemoryMapping::map_with_protection(fd, len, libc::PROT_READ | libc::PROT_WRITE)
+    }
+
+    unsafe fn map_with_protection(fd: RawFd, len: usize, protection: libc::c_int)
+                                  -> Option<SharedMemoryMapping> {
+        let ptr = libc::mmap(ptr::null_mut(), len, protection, libc::MAP_SHARED, fd, 0);
+        if ptr == libc::MAP_FAILED {
+            None
+        } else {
+            Some(SharedMemoryMapping { ptr: ptr as *mut u8, len })
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for SharedMemoryMapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const CREATE_SHARED_MEMORY_CALL: &str = "memfd_create";
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn create_anonymous_shared_memory() -> RawFd {
+    libc::memfd_create(b"surfman-surface\0".as_ptr() as *const libc::c_char, libc::MFD_CLOEXEC)
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+const CREATE_SHARED_MEMORY_CALL: &str = "shm_open";
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+static NEXT_SHARED_MEMORY_ID: AtomicUsize = AtomicUsize::new(0);
+
+// The segment is unlinked right away, so its name only has to be unique while it's being opened.
+// Names are unique within this process, but a stale segment left behind by a crashed process
+// with the same ID can still be in the way, so try the next name then. Names are kept short
+// because macOS rejects those longer than 31 bytes.
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+unsafe fn create_anonymous_shared_memory() -> RawFd {
+    loop {
+        let id = NEXT_SHARED_MEMORY_ID.fetch_add(1, Ordering::Relaxed);
+        let name = format!("/surfman-{}-{}\0", libc::getpid(), id);
+        let fd = libc::shm_open(name.as_ptr() as *const libc::c_char,
+                                libc::O_RDWR | libc::O_CREAT | libc::O_EXCL,
+                                0o600);
+        if fd >= 0 {
+            libc::shm_unlink(name.as_ptr() as *const libc::c_char);
+            return fd;
+        }
+        if io::Error::last_os_error().raw_os_error() != Some(libc::EEXIST) {
+            return fd;
+        }
+    }
+}
+
+// Records that the named system call just failed, along with its `errno`.
+#[cfg(unix)]
+fn os_failure(call: &'static str) -> WindowingApiFailure {
+    let errno = io::Error::last_os_error().raw_os_error();
+    WindowingApiFailure::new(call, WindowingApiError::Failed, errno)
+}
+
+// Closes any file descriptors that haven't been claimed by a `SurfaceHandle`.
+#[cfg(unix)]
+struct FdList(Vec<RawFd>);
+
+#[cfg(unix)]
+impl FdList {
+    fn take(&mut self) -> Vec<RawFd> {
+        mem::replace(&mut self.0, vec![])
+    }
+}
+
+#[cfg(unix)]
+impl Drop for FdList {
+    fn drop(&mut self) {
+        for &fd in &self.0 {
+            unsafe {
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+struct MessageWriter {
+    buffer: [u8; MESSAGE_SIZE],
+    offset: usize,
+}
+
+#[cfg(unix)]
+impl MessageWriter {
+    fn write_u32(&mut self, value: u32) {
+        self.buffer[self.offset..(self.offset + 4)].copy_from_slice(&value.to_le_bytes());
+        self.offset += 4;
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.buffer[self.offset..(self.offset + 8)].copy_from_slice(&value.to_le_bytes());
+        self.offset += 8;
+    }
+
+    fn write_size(&mut self, size: &Size2D<i32>) {
+        self.write_u32(size.width as u32);
+        self.write_u32(size.height as u32);
+    }
+}
+
+#[cfg(unix)]
+struct MessageReader<'a> {
+    buffer: &'a [u8; MESSAGE_SIZE],
+    offset: usize,
+}
+
+#[cfg(unix)]
+impl<'a> MessageReader<'a> {
+    fn read_u32(&mut self) -> u32 {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&self.buffer[self.offset..(self.offset + 4)]);
+        self.offset += 4;
+        u32::from_le_bytes(bytes)
+    }
+
+    fn read_u64(&mut self) -> u64 {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&self.buffer[self.offset..(self.offset + 8)]);
+        self.offset += 8;
+        u64::from_le_bytes(bytes)
+    }
+
+    fn read_size(&mut self) -> Size2D<i32> {
+        let width = self.read_u32() as i32;
+        Size2D::new(width, self.read_u32() as i32)
+    }
+}
+
+#[cfg(unix)]
+fn control_buffer_size(fd_count: usize) -> usize {
+    unsafe { libc::CMSG_SPACE((fd_count * mem::size_of::<RawFd>()) as u32) as usize }
+}
+
+#[cfg(unix)]
+fn surface_format_to_u32(format: SurfaceFormat) -> u32 {
+    match format {
+        SurfaceFormat::RGBA8 => 0,
+        SurfaceFormat::BGRA8 => 1,
+        SurfaceFormat::SRGB8A8 => 2,
+        SurfaceFormat::RGB10A2 => 3,
+        SurfaceFormat::RGBA16F => 4,
+        SurfaceFormat::R8 => 5,
+        SurfaceFormat::RG8 => 6,
+    }
+}
+
+#[cfg(unix)]
+fn surface_format_from_u32(value: u32) -> Option<SurfaceFormat> {
+    match value {
+        0 => Some(SurfaceFormat::RGBA8),
+        1 => Some(SurfaceFormat::BGRA8),
+        2 => Some(SurfaceFormat::SRGB8A8),
+        3 => Some(SurfaceFormat::RGB10A2),
+        4 => Some(SurfaceFormat::RGBA16F),
+        5 => Some(SurfaceFormat::R8),
+        6 => Some(SurfaceFormat::RG8),
+        _ => None,
+    }
+}
+
+#[cfg(unix)]
+fn invalid_message() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed surface handle message")
+}
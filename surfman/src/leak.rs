@@ -0,0 +1,300 @@
+// surfman/surfman/src/leak.rs
+//
+//! What happens to contexts and surfaces that are dropped without being destroyed.
+
+use crate::ContextID;
+
+use std::any::Any;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
+
+#[cfg(debug_assertions)]
+use std::backtrace::{Backtrace, BacktraceStatus};
+
+/// What happens when a context, surface, surface texture, or presenter is dropped without having
+/// been destroyed.
+///
+/// These objects own native resources that only their device can release, with
+/// `destroy_context()`, `destroy_surface()`, `destroy_surface_texture()`, or
+/// `destroy_presenter()`. Dropping a surface texture drops its surface, which is handled like any
+/// other surface; the texture object itself is leaked.
+///
+/// Each device follows the policy set with `Device::set_leak_policy()`, or the global one from
+/// `set_leak_policy()` if it has none. In debug builds, the report includes a backtrace of the
+/// place where the object was created if backtraces are enabled with `RUST_BACKTRACE` or
+/// `RUST_LIB_BACKTRACE`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LeakPolicy {
+    /// Panic. This is the default.
+    ///
+    /// If the thread is already panicking, the object is logged and leaked instead, so that the
+    /// original panic isn't turned into an abort.
+    Panic,
+    /// Log an error and leak the native resources.
+    Leak,
+    /// Log a warning and give the object back to its device to destroy later. Surfaces are
+    /// destroyed the next time their context is made current or destroyed. Contexts are destroyed
+    /// on the thread that dropped them, the next time the device makes a context current or
+    /// destroys one there, or when the device is dropped.
+    ///
+    /// The EGL backends on Linux (X11, Wayland, and surfaceless) support this. Elsewhere, and
+    /// once the device itself has been dropped, objects are leaked as with `Leak`. Presenters are
+    /// always leaked.
+    Defer,
+}
+
+// Devices that have no policy of their own store this.
+const NO_LEAK_POLICY: usize = usize::MAX;
+
+static GLOBAL_LEAK_POLICY: AtomicUsize = AtomicUsize::new(LeakPolicy::Panic as usize);
+
+/// Sets the leak policy for devices that haven't been given their own with
+/// `Device::set_leak_policy()`.
+pub fn set_leak_policy(policy: LeakPolicy) {
+    GLOBAL_LEAK_POLICY.store(policy as usize, Ordering::SeqCst)
+}
+
+/// Returns the leak policy for devices that haven't been given their own.
+pub fn leak_policy() -> LeakPolicy {
+    LeakPolicy::from_bits(GLOBAL_LEAK_POLICY.load(Ordering::SeqCst))
+}
+
+impl LeakPolicy {
+    fn from_bits(bits: usize) -> LeakPolicy {
+        match bits {
+            0 => LeakPolicy::Panic,
+            1 => LeakPolicy::Leak,
+            _ => LeakPolicy::Defer,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum LeakedObject {
+    Context,
+    Surface,
+    SurfaceTexture,
+    Presenter,
+}
+
+impl LeakedObject {
+    fn name(self) -> &'static str {
+        match self {
+            LeakedObject::Context => "context",
+            LeakedObject::Surface => "surface",
+            LeakedObject::SurfaceTexture => "surface texture",
+            LeakedObject::Presenter => "presenter",
+        }
+    }
+
+    fn destructor(self) -> &'static str {
+        match self {
+            LeakedObject::Context => "destroy_context()",
+            LeakedObject::Surface => "destroy_surface()",
+            LeakedObject::SurfaceTexture => "destroy_surface_texture()",
+            LeakedObject::Presenter => "destroy_presenter()",
+        }
+    }
+}
+
+// Shared between a device and the objects it creates. It holds the device's leak policy and the
+// objects that were dropped under `LeakPolicy::Defer`, which the device destroys later.
+pub(crate) struct LeakTracker {
+    policy: AtomicUsize,
+    deferred: Mutex<DeferredObjects>,
+}
+
+struct DeferredObjects {
+    objects: Vec<DeferredObject>,
+    // Set once the device is gone, after which there's nobody to destroy deferred objects.
+    closed: bool,
+}
+
+struct DeferredObject {
+    object: Box<dyn Any>,
+    // The context that the object belongs to. For a context, this is its own ID.
+    context_id: ContextID,
+    // The thread that deferred a context. Surfaces can be destroyed on any thread.
+    thread: Option<ThreadId>,
+}
+
+// Surfaces are `Send`, and contexts are only taken back out on the thread that deferred them.
+unsafe impl Send for DeferredObject {}
+
+impl LeakTracker {
+    pub(crate) fn new() -> Arc<LeakTracker> {
+        Arc::new(LeakTracker {
+            policy: AtomicUsize::new(NO_LEAK_POLICY),
+            deferred: Mutex::new(DeferredObjects { objects: vec![], closed: false }),
+        })
+    }
+
+    pub(crate) fn policy(&self) -> LeakPolicy {
+        match self.policy.load(Ordering::SeqCst) {
+            NO_LEAK_POLICY => leak_policy(),
+            bits => LeakPolicy::from_bits(bits),
+        }
+    }
+
+    pub(crate) fn set_policy(&self, policy: LeakPolicy) {
+        self.policy.store(policy as usize, Ordering::SeqCst)
+    }
+
+    // Removes the deferred contexts that the current thread dropped.
+    pub(crate) fn take_deferred_contexts<C>(&self) -> Vec<C> where C: 'static {
+        let thread = Some(thread::current().id());
+        self.take_deferred(|object| object.thread == thread)
+    }
+
+    // Removes the deferred surfaces that belong to the given context.
+    pub(crate) fn take_deferred_surfaces<S>(&self, context_id: ContextID) -> Vec<S>
+                                            where S: 'static + Send {
+        self.take_deferred(|object| object.thread.is_none() && object.context_id == context_id)
+    }
+
+    // Removes the deferred objects of the given type that don't need a particular thread or
+    // context to be destroyed.
+    pub(crate) fn take_deferred_objects<T>(&self) -> Vec<T> where T: 'static {
+        self.take_deferred(|object| object.thread.is_none())
+    }
+
+    pub(crate) fn has_deferred_surfaces(&self, context_id: ContextID) -> bool {
+        let deferred = self.deferred.lock().unwrap();
+        deferred.objects.iter().any(|object| {
+            object.thread.is_none() && object.context_id == context_id
+        })
+    }
+
+    fn take_deferred<T, F>(&self, mut matches: F) -> Vec<T>
+                           where T: 'static, F: FnMut(&DeferredObject) -> bool {
+        let mut deferred = self.deferred.lock().unwrap();
+        let mut taken = vec![];
+        let mut index = 0;
+        while index < deferred.objects.len() {
+            let object = &deferred.objects[index];
+            if object.object.is::<T>() && matches(object) {
+                let object = deferred.objects.swap_remove(index).object;
+                taken.push(*object.downcast::<T>().unwrap());
+            } else {
+                index += 1;
+            }
+        }
+        taken
+    }
+
+    // Called when the device is dropped. Anything still waiting to be destroyed is leaked.
+    pub(crate) fn close(&self) {
+        let mut deferred = self.deferred.lock().unwrap();
+        deferred.closed = true;
+        if deferred.objects.is_empty() {
+            return;
+        }
+        error!("Leaking {} object(s) that were dropped but not yet destroyed when their device \
+                was dropped.",
+               deferred.objects.len());
+        for object in deferred.objects.drain(..) {
+            mem::forget(object.object);
+        }
+    }
+}
+
+// The device that created an object and, in debug builds, where it was created. Contexts and
+// surfaces hold one of these so that they can follow their device's leak policy when they're
+// dropped without being destroyed.
+pub(crate) struct Origin {
+    tracker: Arc<LeakTracker>,
+    #[cfg(debug_assertions)]
+    backtrace: Option<Backtrace>,
+}
+
+impl Origin {
+    pub(crate) fn new(tracker: &Arc<LeakTracker>) -> Origin {
+        Origin {
+            tracker: tracker.clone(),
+            #[cfg(debug_assertions)]
+            backtrace: Some(Backtrace::capture()),
+        }
+    }
+
+    // Moves the creation site to a new origin, for when an object's contents are moved into a
+    // new object that's handed back to the device.
+    pub(crate) fn take(&mut self) -> Origin {
+        Origin {
+            tracker: self.tracker.clone(),
+            #[cfg(debug_assertions)]
+            backtrace: self.backtrace.take(),
+        }
+    }
+
+    // The leak policy of the device that created the object.
+    pub(crate) fn policy(&self) -> LeakPolicy {
+        self.tracker.policy()
+    }
+
+    // Applies the leak policy to an object that was dropped without being destroyed. Returns
+    // true if the caller should pass the object's contents to `defer()`. Otherwise, the caller
+    // must leak them.
+    pub(crate) fn report_leak(&self, object: LeakedObject, can_defer: bool) -> bool {
+        let creation_site = self.creation_site();
+        match self.tracker.policy() {
+            LeakPolicy::Panic if !thread::panicking() => {
+                panic!("A {} must be destroyed explicitly with `{}`!{}",
+                       object.name(),
+                       object.destructor(),
+                       creation_site)
+            }
+            LeakPolicy::Defer if can_defer => {
+                warn!("A {} was dropped without being destroyed with `{}`; its device will \
+                       destroy it later.{}",
+                      object.name(),
+                      object.destructor(),
+                      creation_site);
+                true
+            }
+            LeakPolicy::Panic | LeakPolicy::Leak | LeakPolicy::Defer => {
+                error!("Leaking a {} that was dropped without being destroyed with `{}`.{}",
+                       object.name(),
+                       object.destructor(),
+                       creation_site);
+                false
+            }
+        }
+    }
+
+    // Hands an object to the device to be destroyed later. If the device is gone, the object is
+    // returned, and the caller must leak it.
+    pub(crate) fn defer<T>(&self, object: LeakedObject, context_id: ContextID, contents: T)
+                           -> Result<(), T>
+                           where T: 'static {
+        let mut deferred = self.tracker.deferred.lock().unwrap();
+        if deferred.closed {
+            error!("Leaking a {} because its device has already been dropped.", object.name());
+            return Err(contents);
+        }
+        let thread = match object {
+            LeakedObject::Context => Some(thread::current().id()),
+            LeakedObject::Surface |
+            LeakedObject::SurfaceTexture |
+            LeakedObject::Presenter => None,
+        };
+        deferred.objects.push(DeferredObject { object: Box::new(contents), context_id, thread });
+        Ok(())
+    }
+
+    #[cfg(debug_assertions)]
+    fn creation_site(&self) -> String {
+        match self.backtrace {
+            Some(ref backtrace) if backtrace.status() == BacktraceStatus::Captured => {
+                format!("\n\nIt was created at:\n{}", backtrace)
+            }
+            _ => String::new(),
+        }
+    }
+
+    #[cfg(not(debug_assertions))]
+    fn creation_site(&self) -> String {
+        String::new()
+    }
+}
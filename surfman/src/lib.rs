@@ -26,34 +26,47 @@ pub mod platform;
 pub use platform::default::connection::Connection;
 pub use platform::default::context::{Context, ContextDescriptor};
 pub use platform::default::device::{Adapter, Device};
-pub use platform::default::surface::{NativeWidget, Surface, SurfaceTexture};
+pub use platform::default::surface::{NativeImage, NativeWidget, Surface, SurfaceTexture};
 
 // TODO(pcwalton): Fill this in with other OS's.
 #[cfg(target_os = "android")]
 pub use platform::default::context::NativeContext;
 #[cfg(target_os = "android")]
 pub use platform::default::device::NativeDevice;
-#[cfg(target_os = "macos")]
+#[cfg(all(unix, not(target_os = "android")))]
 pub use platform::system::connection::Connection as SystemConnection;
-#[cfg(target_os = "macos")]
+#[cfg(all(unix, not(target_os = "android")))]
 pub use platform::system::device::{Adapter as SystemAdapter, Device as SystemDevice};
-#[cfg(target_os = "macos")]
+#[cfg(all(unix, not(target_os = "android")))]
 pub use platform::system::surface::Surface as SystemSurface;
 
 pub mod connection;
 pub mod device;
 
 pub mod error;
-pub use crate::error::{Error, WindowingApiError};
+pub use crate::error::{Error, WindowingApiError, WindowingApiFailure};
+
+pub mod ipc;
+pub mod managed;
+#[cfg(unix)]
+pub mod video;
 
 mod context;
 pub use crate::context::{ContextAttributes, ContextAttributeFlags, ContextID};
+pub use crate::context::{ContextDescriptorDiagnostics, PixelFormatAttributes, PixelFormatMismatch};
+pub use crate::context::{PixelFormatSurfaceTypes, RejectedPixelFormat};
 
 mod info;
-pub use crate::info::{GLApi, GLVersion};
+pub use crate::info::{DeviceCapabilities, GLApi, GLInfo, GLVersion};
+
+mod leak;
+pub use crate::leak::{LeakPolicy, leak_policy, set_leak_policy};
 
 mod surface;
-pub use crate::surface::{SurfaceAccess, SurfaceID, SurfaceInfo, SurfaceType, SystemSurfaceInfo};
+pub use crate::surface::{SurfaceAccess, SurfaceFormat, SurfaceID, SurfaceInfo, SurfaceType};
+pub use crate::surface::{ColorSpace, DepthStencilAttributes, DepthStencilFormat, LayerStorage};
+pub use crate::surface::{SurfaceTextureTarget, SystemSurfaceInfo};
+pub use crate::surface::GL_TEXTURE_EXTERNAL_OES;
 
 pub mod macros;
 
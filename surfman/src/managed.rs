@@ -0,0 +1,441 @@
+// surfman/surfman/src/managed.rs
+//
+//! Wrappers that destroy contexts, surfaces, and surface textures when they're dropped.
+//!
+//! The rest of `surfman` requires every object to be destroyed explicitly, with the context it
+//! belongs to. This module trades a little overhead for not having to: the device is shared
+//! through an `Rc`, and every object remembers the device and context that own it.
+//!
+//! * Dropping a context destroys it, along with the surface bound to it.
+//!
+//! * Dropping a surface, on any thread, queues it for destruction on the device's thread. Queued
+//!   surfaces are destroyed the next time their context is passed mutably to a method of the
+//!   managed device, or when their context is dropped.
+//!
+//! * Dropping a surface texture queues it in the same way. Its surface is then destroyed too.
+//!
+//! * Surfaces and surface textures that are dropped after their context can no longer be
+//!   destroyed, so they're logged and leaked.
+//!
+//! Anything that isn't wrapped here can be done with the underlying device, which `borrow()`
+//! returns, and the underlying objects, which the wrappers dereference to.
+
+use crate::connection::Connection as ConnectionInterface;
+use crate::device::Device as DeviceInterface;
+use crate::{ContextID, Error, SurfaceAccess, SurfaceType};
+
+use std::cell::{Ref, RefCell, RefMut};
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+/// A device that can be shared by the contexts and surfaces it creates.
+///
+/// Cloning a managed device produces another handle to the same device. Like the device it
+/// wraps, it is local to a single thread.
+pub struct Device<D = crate::Device> where D: DeviceInterface,
+                                           D::Connection: ConnectionInterface {
+    shared: Rc<SharedDevice<D>>,
+}
+
+/// A context that destroys itself, along with its bound surface, when it's dropped.
+///
+/// This dereferences to the underlying context, for use with the methods of the underlying
+/// device.
+pub struct Context<D = crate::Device> where D: DeviceInterface,
+                                            D::Connection: ConnectionInterface {
+    context: Option<D::Context>,
+    id: ContextID,
+    device: Rc<SharedDevice<D>>,
+}
+
+/// A surface that is destroyed on its device's thread after it's dropped.
+///
+/// Surfaces can be sent between threads and dropped on any of them. This dereferences to the
+/// underlying surface, for use with the methods of the underlying device.
+pub struct Surface<D = crate::Device> where D: DeviceInterface,
+                                            D::Connection: ConnectionInterface {
+    surface: Option<D::Surface>,
+    context_id: ContextID,
+    dropped_surfaces: Arc<Mutex<DroppedSurfaces<D>>>,
+}
+
+/// A surface texture that is destroyed, along with its surface, after it's dropped.
+///
+/// This dereferences to the underlying surface texture, for use with the methods of the
+/// underlying device.
+pub struct SurfaceTexture<D = crate::Device> where D: DeviceInterface,
+                                                   D::Connection: ConnectionInterface {
+    surface_texture: Option<D::SurfaceTexture>,
+    context_id: ContextID,
+    device: Rc<SharedDevice<D>>,
+}
+
+struct SharedDevice<D> where D: DeviceInterface, D::Connection: ConnectionInterface {
+    // Declared before the device, so that anything still queued when the device goes away is
+    // dropped while the device is still alive and handled by its leak policy.
+    dropped_surfaces: Arc<Mutex<DroppedSurfaces<D>>>,
+    dropped_surface_textures: RefCell<Vec<(ContextID, D::SurfaceTexture)>>,
+    device: RefCell<D>,
+}
+
+// Surfaces waiting to be destroyed, along with the IDs of the contexts they belong to. This is
+// shared with the surfaces themselves, which may be dropped on other threads.
+struct DroppedSurfaces<D> where D: DeviceInterface {
+    surfaces: Vec<(ContextID, D::Surface)>,
+    // The contexts that haven't been dropped yet. Only their surfaces can still be destroyed.
+    live_contexts: Vec<ContextID>,
+}
+
+impl<D> Clone for Device<D> where D: DeviceInterface, D::Connection: ConnectionInterface {
+    #[inline]
+    fn clone(&self) -> Device<D> {
+        Device { shared: self.shared.clone() }
+    }
+}
+
+impl<D> Device<D> where D: DeviceInterface, D::Connection: ConnectionInterface {
+    /// Takes ownership of a device so that the objects created with it can destroy themselves.
+    pub fn new(device: D) -> Device<D> {
+        Device {
+            shared: Rc::new(SharedDevice {
+                dropped_surfaces: Arc::new(Mutex::new(DroppedSurfaces {
+                    surfaces: vec![],
+                    live_contexts: vec![],
+                })),
+                dropped_surface_textures: RefCell::new(vec![]),
+                device: RefCell::new(device),
+            }),
+        }
+    }
+
+    /// Returns the underlying device, for anything that this module doesn't wrap.
+    ///
+    /// The methods of this type, and dropping contexts and surface textures, borrow the device
+    /// too, so don't hold on to the result across them.
+    #[inline]
+    pub fn borrow(&self) -> Ref<'_, D> {
+        self.shared.device.borrow()
+    }
+
+    /// Returns the underlying device mutably.
+    ///
+    /// The same caveats as `borrow()` apply.
+    #[inline]
+    pub fn borrow_mut(&self) -> RefMut<'_, D> {
+        self.shared.device.borrow_mut()
+    }
+
+    /// Creates a new OpenGL context, which is destroyed when it's dropped.
+    pub fn create_context(&self, descriptor: &D::ContextDescriptor)
+                          -> Result<Context<D>, Error> {
+        let mut device = self.shared.device.borrow_mut();
+        let context = device.create_context(descriptor)?;
+        let id = device.context_id(&context);
+        self.shared.dropped_surfaces.lock().unwrap().live_contexts.push(id);
+        Ok(Context { context: Some(context), id, device: self.shared.clone() })
+    }
+
+    /// Creates either a generic or a widget surface, which is destroyed after it's dropped.
+    pub fn create_surface(&self,
+                          context: &Context<D>,
+                          surface_access: SurfaceAccess,
+                          surface_type: SurfaceType<
+                              <D::Connection as ConnectionInterface>::NativeWidget>)
+                          -> Result<Surface<D>, Error> {
+        let mut device = self.shared.device.borrow_mut();
+        let surface = device.create_surface(context, surface_access, surface_type)?;
+        Ok(self.shared.wrap_surface(context.id, surface))
+    }
+
+    /// Attaches a surface to a context for rendering.
+    ///
+    /// The context takes ownership of the surface and destroys it when the context is dropped,
+    /// unless it's unbound first. If an error is returned, the surface is returned alongside it.
+    pub fn bind_surface_to_context(&self, context: &mut Context<D>, surface: Surface<D>)
+                                   -> Result<(), (Error, Surface<D>)> {
+        self.shared.destroy_dropped_objects(context);
+
+        let (surface, context_id) = surface.into_parts();
+        let device = self.shared.device.borrow();
+        device.bind_surface_to_context(context.inner_mut(), surface).map_err(|(err, surface)| {
+            (err, self.shared.wrap_surface(context_id, surface))
+        })
+    }
+
+    /// Makes the context the current OpenGL context for this thread, after destroying any of its
+    /// dropped surfaces and surface textures.
+    pub fn make_context_current(&self, context: &mut Context<D>) -> Result<(), Error> {
+        self.shared.destroy_dropped_objects(context);
+        self.shared.device.borrow().make_context_current(context)
+    }
+
+    /// Removes and returns any attached surface from this context.
+    pub fn unbind_surface_from_context(&self, context: &mut Context<D>)
+                                       -> Result<Option<Surface<D>>, Error> {
+        self.shared.destroy_dropped_objects(context);
+
+        let device = self.shared.device.borrow();
+        let surface = device.unbind_surface_from_context(context.inner_mut())?;
+        Ok(surface.map(|surface| self.shared.wrap_surface(context.id, surface)))
+    }
+
+    /// Wraps a surface in a surface texture local to the given context, which is destroyed after
+    /// it's dropped, along with the surface.
+    ///
+    /// If an error is returned, the surface is returned alongside it.
+    pub fn create_surface_texture(&self, context: &mut Context<D>, surface: Surface<D>)
+                                  -> Result<SurfaceTexture<D>, (Error, Surface<D>)> {
+        self.shared.destroy_dropped_objects(context);
+
+        let (surface, surface_context_id) = surface.into_parts();
+        let device = self.shared.device.borrow();
+        match device.create_surface_texture(context.inner_mut(), surface) {
+            Ok(surface_texture) => {
+                Ok(SurfaceTexture {
+                    surface_texture: Some(surface_texture),
+                    context_id: context.id,
+                    device: self.shared.clone(),
+                })
+            }
+            Err((err, surface)) => Err((err, self.shared.wrap_surface(surface_context_id, surface))),
+        }
+    }
+
+    /// Destroys a surface texture right away and returns its surface.
+    ///
+    /// The supplied context must be the one the surface texture was created with. If an error is
+    /// returned, the surface texture is returned alongside it.
+    pub fn destroy_surface_texture(&self,
+                                   context: &mut Context<D>,
+                                   surface_texture: SurfaceTexture<D>)
+                                   -> Result<Surface<D>, (Error, SurfaceTexture<D>)> {
+        let (surface_texture, context_id) = surface_texture.into_parts();
+        let device = self.shared.device.borrow();
+        match device.destroy_surface_texture(context.inner_mut(), surface_texture) {
+            Ok(surface) => {
+                let surface_context_id = device.surface_info(&surface).context_id;
+                Ok(self.shared.wrap_surface(surface_context_id, surface))
+            }
+            Err((err, surface_texture)) => {
+                Err((err, SurfaceTexture {
+                    surface_texture: Some(surface_texture),
+                    context_id,
+                    device: self.shared.clone(),
+                }))
+            }
+        }
+    }
+
+    /// Destroys the surfaces and surface textures belonging to this context that have been
+    /// dropped.
+    ///
+    /// This happens automatically whenever the context is passed mutably to another method of
+    /// this type, so it's only needed for contexts that rarely are.
+    #[inline]
+    pub fn destroy_dropped_objects(&self, context: &mut Context<D>) {
+        self.shared.destroy_dropped_objects(context)
+    }
+}
+
+impl<D> SharedDevice<D> where D: DeviceInterface, D::Connection: ConnectionInterface {
+    fn wrap_surface(&self, context_id: ContextID, surface: D::Surface) -> Surface<D> {
+        Surface {
+            surface: Some(surface),
+            context_id,
+            dropped_surfaces: self.dropped_surfaces.clone(),
+        }
+    }
+
+    fn destroy_dropped_objects(&self, context: &mut Context<D>) {
+        let context_id = context.id;
+        if let Some(ref mut context) = context.context {
+            self.destroy_dropped_objects_for(context, context_id);
+        }
+    }
+
+    fn destroy_dropped_objects_for(&self, context: &mut D::Context, context_id: ContextID) {
+        let surface_textures = take_matching(&mut self.dropped_surface_textures.borrow_mut(),
+                                             context_id);
+        let device = self.device.borrow();
+        for surface_texture in surface_textures {
+            match device.destroy_surface_texture(context, surface_texture) {
+                Ok(surface) => {
+                    let surface_context_id = device.surface_info(&surface).context_id;
+                    self.dropped_surfaces.lock().unwrap().push(surface_context_id, surface);
+                }
+                Err((err, _)) => error!("Failed to destroy a dropped surface texture: {:?}", err),
+            }
+        }
+
+        let surfaces = take_matching(&mut self.dropped_surfaces.lock().unwrap().surfaces,
+                                     context_id);
+        self.destroy_surfaces(context, surfaces);
+    }
+
+    fn destroy_surfaces(&self, context: &mut D::Context, surfaces: Vec<D::Surface>) {
+        let device = self.device.borrow();
+        for mut surface in surfaces {
+            if let Err(err) = device.destroy_surface(context, &mut surface) {
+                error!("Failed to destroy a dropped surface: {:?}", err);
+            }
+        }
+    }
+
+    fn context_is_live(&self, context_id: ContextID) -> bool {
+        self.dropped_surfaces.lock().unwrap().live_contexts.contains(&context_id)
+    }
+}
+
+impl<D> DroppedSurfaces<D> where D: DeviceInterface {
+    // Queues a dropped surface, or leaks it if its context is gone.
+    fn push(&mut self, context_id: ContextID, surface: D::Surface) {
+        if self.live_contexts.contains(&context_id) {
+            self.surfaces.push((context_id, surface));
+            return;
+        }
+        error!("Leaking a surface that was dropped after its context.");
+        mem::forget(surface);
+    }
+}
+
+// Removes the objects belonging to the given context from a queue.
+fn take_matching<T>(queue: &mut Vec<(ContextID, T)>, context_id: ContextID) -> Vec<T> {
+    let (matching, rest) = mem::take(queue).into_iter().partition(|&(id, _)| {
+        id == context_id
+    });
+    *queue = rest;
+    matching.into_iter().map(|(_, object)| object).collect::<Vec<_>>()
+}
+
+impl<D> Context<D> where D: DeviceInterface, D::Connection: ConnectionInterface {
+    /// Returns the ID of this context.
+    #[inline]
+    pub fn id(&self) -> ContextID {
+        self.id
+    }
+
+    #[inline]
+    fn inner_mut(&mut self) -> &mut D::Context {
+        self.context.as_mut().unwrap()
+    }
+}
+
+impl<D> Deref for Context<D> where D: DeviceInterface, D::Connection: ConnectionInterface {
+    type Target = D::Context;
+
+    #[inline]
+    fn deref(&self) -> &D::Context {
+        self.context.as_ref().unwrap()
+    }
+}
+
+impl<D> Drop for Context<D> where D: DeviceInterface, D::Connection: ConnectionInterface {
+    fn drop(&mut self) {
+        let mut context = match self.context.take() {
+            None => return,
+            Some(context) => context,
+        };
+        self.device.destroy_dropped_objects_for(&mut context, self.id);
+
+        // From here on, this context's surfaces are leaked when they're dropped. Destroy any that
+        // were queued on other threads in the meantime.
+        let surfaces = {
+            let mut dropped_surfaces = self.device.dropped_surfaces.lock().unwrap();
+            let id = self.id;
+            dropped_surfaces.live_contexts.retain(|&live_id| live_id != id);
+            take_matching(&mut dropped_surfaces.surfaces, id)
+        };
+        self.device.destroy_surfaces(&mut context, surfaces);
+
+        if let Err(err) = self.device.device.borrow().destroy_context(&mut context) {
+            error!("Failed to destroy a dropped context: {:?}", err);
+        }
+    }
+}
+
+impl<D> Surface<D> where D: DeviceInterface, D::Connection: ConnectionInterface {
+    /// Returns the ID of the context that this surface belongs to.
+    #[inline]
+    pub fn context_id(&self) -> ContextID {
+        self.context_id
+    }
+
+    /// Returns the underlying surface, which must then be destroyed explicitly.
+    #[inline]
+    pub fn into_inner(self) -> D::Surface {
+        self.into_parts().0
+    }
+
+    fn into_parts(mut self) -> (D::Surface, ContextID) {
+        (self.surface.take().unwrap(), self.context_id)
+    }
+}
+
+impl<D> Deref for Surface<D> where D: DeviceInterface, D::Connection: ConnectionInterface {
+    type Target = D::Surface;
+
+    #[inline]
+    fn deref(&self) -> &D::Surface {
+        self.surface.as_ref().unwrap()
+    }
+}
+
+impl<D> DerefMut for Surface<D> where D: DeviceInterface, D::Connection: ConnectionInterface {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut D::Surface {
+        self.surface.as_mut().unwrap()
+    }
+}
+
+impl<D> Drop for Surface<D> where D: DeviceInterface, D::Connection: ConnectionInterface {
+    fn drop(&mut self) {
+        if let Some(surface) = self.surface.take() {
+            self.dropped_surfaces.lock().unwrap().push(self.context_id, surface);
+        }
+    }
+}
+
+impl<D> SurfaceTexture<D> where D: DeviceInterface, D::Connection: ConnectionInterface {
+    /// Returns the ID of the context that this surface texture is local to.
+    #[inline]
+    pub fn context_id(&self) -> ContextID {
+        self.context_id
+    }
+
+    /// Returns the underlying surface texture, which must then be destroyed explicitly.
+    #[inline]
+    pub fn into_inner(self) -> D::SurfaceTexture {
+        self.into_parts().0
+    }
+
+    fn into_parts(mut self) -> (D::SurfaceTexture, ContextID) {
+        (self.surface_texture.take().unwrap(), self.context_id)
+    }
+}
+
+impl<D> Deref for SurfaceTexture<D> where D: DeviceInterface,
+                                          D::Connection: ConnectionInterface {
+    type Target = D::SurfaceTexture;
+
+    #[inline]
+    fn deref(&self) -> &D::SurfaceTexture {
+        self.surface_texture.as_ref().unwrap()
+    }
+}
+
+impl<D> Drop for SurfaceTexture<D> where D: DeviceInterface, D::Connection: ConnectionInterface {
+    fn drop(&mut self) {
+        let surface_texture = match self.surface_texture.take() {
+            None => return,
+            Some(surface_texture) => surface_texture,
+        };
+        if !self.device.context_is_live(self.context_id) {
+            error!("Leaking a surface texture that was dropped after its context.");
+            mem::forget(surface_texture);
+            return;
+        }
+        self.device.dropped_surface_textures.borrow_mut().push((self.context_id, surface_texture));
+    }
+}
@@ -5,6 +5,7 @@
 //! FIXME(pcwalton): Should this instead wrap `EGLDisplay`? Is that thread-safe on Android?
 
 use crate::Error;
+use crate::leak::LeakTracker;
 use super::device::{Adapter, Device, NativeDevice};
 use super::surface::NativeWidget;
 
@@ -80,7 +81,11 @@ impl Connection {
     #[inline]
     pub unsafe fn create_device_from_native_device(&self, native_device: NativeDevice)
                                                    -> Result<Device, Error> {
-        Ok(Device { egl_display: native_device.0, display_is_owned: false })
+        Ok(Device {
+            egl_display: native_device.0,
+            display_is_owned: false,
+            leak_tracker: LeakTracker::new(),
+        })
     }
 
     /// Opens the display connection corresponding to the given `winit` window.
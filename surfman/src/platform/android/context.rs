@@ -2,21 +2,25 @@
 //
 //! OpenGL rendering contexts.
 
-use crate::context::{CREATE_CONTEXT_MUTEX, ContextID};
+use crate::context::{CREATE_CONTEXT_MUTEX, ContextDescriptorDiagnostics, ContextID};
+use crate::context::choose_context_descriptor;
 use crate::egl::types::{EGLConfig, EGLContext, EGLSurface, EGLint};
 use crate::egl;
+use crate::info::GLInfoCache;
+use crate::leak::{LeakedObject, Origin};
 use crate::platform::generic::egl::context::{self, CurrentContextGuard};
 use crate::platform::generic::egl::device::EGL_FUNCTIONS;
 use crate::platform::generic::egl::error::ToWindowingApiError;
 use crate::platform::generic::egl::surface::ExternalEGLSurfaces;
 use crate::surface::Framebuffer;
-use crate::{ContextAttributes, Error, Gl, SurfaceInfo};
+use crate::{ContextAttributes, Error, GLApi, GLInfo, Gl, PixelFormatAttributes, SurfaceInfo};
+use crate::WindowingApiFailure;
 use super::device::Device;
 use super::surface::{Surface, SurfaceObjects};
 
 use std::mem;
 use std::os::raw::c_void;
-use std::thread;
+use std::rc::Rc;
 
 pub use crate::platform::generic::egl::context::{ContextDescriptor, NativeContext};
 
@@ -25,6 +29,12 @@ thread_local! {
     pub static GL_FUNCTIONS: Gl = Gl::load_with(context::get_proc_address);
 }
 
+// The config attributes that every context on this device needs.
+const CONFIG_ATTRIBUTES: [EGLint; 4] = [
+    egl::COLOR_BUFFER_TYPE as EGLint,   egl::RGB_BUFFER as EGLint,
+    egl::SURFACE_TYPE as EGLint,        egl::PBUFFER_BIT as EGLint,
+];
+
 /// Represents an OpenGL rendering context.
 /// 
 /// A context allows you to issue rendering commands to a surface. When initially created, a
@@ -40,20 +50,22 @@ thread_local! {
 /// OpenGL objects may not be shared across contexts directly, but surface textures effectively
 /// allow for sharing of texture data. Contexts are local to a single thread and device.
 /// 
-/// A context must be explicitly destroyed with `destroy_context()`, or a panic will occur.
+/// A context should be explicitly destroyed with `destroy_context()`. A context that is dropped
+/// without being destroyed is handled according to the device's `LeakPolicy`.
 pub struct Context {
     pub(crate) egl_context: EGLContext,
     pub(crate) id: ContextID,
     pub(crate) pbuffer: EGLSurface,
+    gl_info: GLInfoCache,
     framebuffer: Framebuffer<Surface, ExternalEGLSurfaces>,
     context_is_owned: bool,
+    origin: Origin,
 }
 
 impl Drop for Context {
-    #[inline]
     fn drop(&mut self) {
-        if self.egl_context != egl::NO_CONTEXT && !thread::panicking() {
-            panic!("Contexts must be destroyed explicitly with `destroy_context`!")
+        if self.egl_context != egl::NO_CONTEXT {
+            self.origin.report_leak(LeakedObject::Context, false);
         }
     }
 }
@@ -66,11 +78,53 @@ impl Device {
     pub fn create_context_descriptor(&self, attributes: &ContextAttributes)
                                      -> Result<ContextDescriptor, Error> {
         unsafe {
-            ContextDescriptor::new(self.egl_display, attributes, &[
-                egl::COLOR_BUFFER_TYPE as EGLint,   egl::RGB_BUFFER as EGLint,
-                egl::SURFACE_TYPE as EGLint,        egl::PBUFFER_BIT as EGLint,
-                egl::RENDERABLE_TYPE as EGLint,     egl::OPENGL_ES2_BIT as EGLint,
-            ])
+            ContextDescriptor::new(self.egl_display, attributes, self.gl_api(), &CONFIG_ATTRIBUTES)
+        }
+    }
+
+    /// Returns a context descriptor for every pixel format that this device can create contexts
+    /// with, along with the attributes of each pixel format.
+    ///
+    /// The version and profile of the descriptors come from `attributes`. The `ALPHA`, `DEPTH`,
+    /// and `STENCIL` flags are ignored; use the pixel format attributes to tell the descriptors
+    /// apart.
+    pub fn enumerate_context_descriptors(&self, attributes: &ContextAttributes)
+                                         -> Result<Vec<(ContextDescriptor, PixelFormatAttributes)>,
+                                                   Error> {
+        unsafe {
+            ContextDescriptor::enumerate(self.egl_display,
+                                         attributes,
+                                         self.gl_api(),
+                                         &CONFIG_ATTRIBUTES)
+        }
+    }
+
+    /// Creates a context descriptor from one of the pixel formats that
+    /// `enumerate_context_descriptors()` returns.
+    ///
+    /// `choose` receives the attributes of every candidate pixel format and returns the index of
+    /// the one to use. If it returns `None` or an index out of range, a `NoPixelFormatFound` error
+    /// is returned.
+    pub fn create_context_descriptor_with<F>(&self, attributes: &ContextAttributes, choose: F)
+                                             -> Result<ContextDescriptor, Error>
+                                             where F: FnOnce(&[PixelFormatAttributes]) ->
+                                                   Option<usize> {
+        let candidates = self.enumerate_context_descriptors(attributes)?;
+        choose_context_descriptor(candidates, choose)
+    }
+
+    /// Explains which pixel formats `create_context_descriptor()` passes over for the given
+    /// attributes, and why.
+    ///
+    /// This is meant for finding out why `create_context_descriptor()` returned
+    /// `NoPixelFormatFound` or `UnsupportedGLProfile`.
+    pub fn diagnose_context_descriptor(&self, attributes: &ContextAttributes)
+                                       -> ContextDescriptorDiagnostics {
+        unsafe {
+            ContextDescriptor::diagnose(self.egl_display,
+                                        attributes,
+                                        self.gl_api(),
+                                        &CONFIG_ATTRIBUTES)
         }
     }
 
@@ -81,7 +135,6 @@ impl Device {
     pub fn create_context(&mut self, descriptor: &ContextDescriptor) -> Result<Context, Error> {
         let mut next_context_id = CREATE_CONTEXT_MUTEX.lock().unwrap();
 
-        let egl_config = self.context_descriptor_to_egl_config(descriptor);
         let egl_display = self.egl_display;
 
         unsafe {
@@ -89,15 +142,23 @@ impl Device {
             let egl_context = context::create_context(egl_display, descriptor)?;
 
             // Create a dummy pbuffer.
-            let pbuffer = context::create_dummy_pbuffer(egl_display, egl_context);
+            let pbuffer = match context::create_dummy_pbuffer(egl_display, egl_context) {
+                Ok(pbuffer) => pbuffer,
+                Err(err) => {
+                    EGL_FUNCTIONS.with(|egl| egl.DestroyContext(egl_display, egl_context));
+                    return Err(err);
+                }
+            };
 
             // Wrap up the EGL context.
             let context = Context {
                 egl_context,
                 id: *next_context_id,
                 pbuffer,
+                gl_info: GLInfoCache::default(),
                 framebuffer: Framebuffer::None,
                 context_is_owned: true,
+                origin: Origin::new(&self.leak_tracker),
             };
             next_context_id.0 += 1;
             Ok(context)
@@ -114,18 +175,20 @@ impl Device {
         let mut next_context_id = CREATE_CONTEXT_MUTEX.lock().unwrap();
 
         // Create a dummy pbuffer.
-        let pbuffer = context::create_dummy_pbuffer(self.egl_display, native_context.egl_context);
+        let pbuffer = context::create_dummy_pbuffer(self.egl_display, native_context.egl_context)?;
 
         // Create the context.
         let context = Context {
             egl_context: native_context.egl_context,
             id: *next_context_id,
             pbuffer,
+            gl_info: GLInfoCache::default(),
             framebuffer: Framebuffer::External(ExternalEGLSurfaces {
                 draw: native_context.egl_draw_surface,
                 read: native_context.egl_read_surface,
             }),
             context_is_owned: false,
+            origin: Origin::new(&self.leak_tracker),
         };
         next_context_id.0 += 1;
 
@@ -194,6 +257,9 @@ impl Device {
                 Framebuffer::Surface(Surface {
                     objects: SurfaceObjects::HardwareBuffer { .. },
                     ..
+                }) | Framebuffer::Surface(Surface {
+                    objects: SurfaceObjects::Imported { .. },
+                    ..
                 }) | Framebuffer::None => (context.pbuffer, context.pbuffer),
             };
 
@@ -203,8 +269,8 @@ impl Device {
                                              egl_read_surface,
                                              egl_context);
                 if result == egl::FALSE {
-                    let err = egl.GetError().to_windowing_api_error();
-                    return Err(Error::MakeCurrentFailed(err));
+                    let err = egl.GetError().to_windowing_api_failure("eglMakeCurrent");
+                    return Err(Error::MakeCurrentFailed(Box::new(err)));
                 }
                 Ok(())
             })
@@ -236,6 +302,9 @@ impl Device {
         if context.id != new_surface.context_id {
             return Err((Error::IncompatibleSurface, new_surface));
         }
+        if let SurfaceObjects::Imported { .. } = new_surface.objects {
+            return Err((Error::IncompatibleSurface, new_surface));
+        }
 
         match context.framebuffer {
             Framebuffer::External { .. } => return Err((Error::ExternalRenderTarget, new_surface)),
@@ -283,6 +352,26 @@ impl Device {
         }
     }
 
+    /// Returns the OpenGL API flavor (OpenGL or OpenGL ES) that the context was created for.
+    #[inline]
+    pub fn context_gl_api(&self, context: &Context) -> GLApi {
+        unsafe {
+            context::context_gl_api(self.egl_display, context.egl_context)
+        }
+    }
+
+    /// Returns what the OpenGL implementation reports about the context: its version and
+    /// profile, the vendor and renderer, and the extensions it supports.
+    ///
+    /// The context is queried the first time this is called, and the same information is
+    /// returned after that.
+    pub fn gl_info(&self, context: &Context) -> Result<Rc<GLInfo>, Error> {
+        context.gl_info.get_or_query(|| {
+            let _guard = self.temporarily_make_context_current(context)?;
+            GL_FUNCTIONS.with(GLInfo::current)
+        })
+    }
+
     /// Fetches the address of an OpenGL function associated with this context.
     /// 
     /// OpenGL functions are local to a context. You should not use OpenGL functions on one context
@@ -296,7 +385,7 @@ impl Device {
     }
 
     pub(crate) fn context_descriptor_to_egl_config(&self, context_descriptor: &ContextDescriptor)
-                                                   -> EGLConfig {
+                                                   -> Result<EGLConfig, WindowingApiFailure> {
         unsafe {
             context::egl_config_from_id(self.egl_display,
                                         context_descriptor.egl_config_id)
@@ -341,6 +430,9 @@ impl Device {
             Framebuffer::Surface(Surface {
                 objects: SurfaceObjects::HardwareBuffer { .. },
                 ..
+            }) | Framebuffer::Surface(Surface {
+                objects: SurfaceObjects::Imported { .. },
+                ..
             }) | Framebuffer::None => (context.pbuffer, context.pbuffer),
         };
 
@@ -4,10 +4,14 @@
 
 use crate::egl::types::EGLDisplay;
 use crate::egl;
-use crate::platform::generic::egl::device::EGL_FUNCTIONS;
-use crate::{Error, GLApi};
+use crate::platform::generic::egl::device::{EGL_FUNCTIONS, display_extensions};
+use crate::platform::generic::egl::device::extensions_support_damage_present;
+use crate::leak::LeakTracker;
+use crate::{DeviceCapabilities, Error, GLApi, LeakPolicy};
 use super::connection::Connection;
 
+use std::sync::Arc;
+
 /// Represents a hardware display adapter that can be used for rendering (including the CPU).
 ///
 /// Adapters can be sent between threads. To render with an adapter, open a thread-local `Device`.
@@ -20,6 +24,7 @@ pub struct Adapter;
 pub struct Device {
     pub(crate) egl_display: EGLDisplay,
     pub(crate) display_is_owned: bool,
+    pub(crate) leak_tracker: Arc<LeakTracker>,
 }
 
 /// Wrapper for an `EGLDisplay`.
@@ -60,14 +65,21 @@ impl Device {
         EGL_FUNCTIONS.with(|egl| {
             unsafe {
                 let egl_display = egl.GetDisplay(egl::DEFAULT_DISPLAY);
-                assert_ne!(egl_display, egl::NO_DISPLAY);
+                if egl_display == egl::NO_DISPLAY {
+                    return Err(Error::DeviceOpenFailed);
+                }
 
-                // I don't think this should ever fail.
                 let (mut major_version, mut minor_version) = (0, 0);
                 let result = egl.Initialize(egl_display, &mut major_version, &mut minor_version);
-                assert_ne!(result, egl::FALSE);
+                if result == egl::FALSE {
+                    return Err(Error::DeviceOpenFailed);
+                }
 
-                Ok(Device { egl_display, display_is_owned: true })
+                Ok(Device {
+                    egl_display,
+                    display_is_owned: true,
+                    leak_tracker: LeakTracker::new(),
+                })
             }
         })
     }
@@ -90,9 +102,37 @@ impl Device {
         Adapter
     }
 
-    /// Returns the OpenGL API flavor that this device supports (OpenGL or OpenGL ES).
+    /// Returns the OpenGL API flavor that contexts on this device use by default (OpenGL or
+    /// OpenGL ES).
     #[inline]
     pub fn gl_api(&self) -> GLApi {
         GLApi::GLES
     }
+
+    /// Returns the optional features that this device supports.
+    pub fn capabilities(&self) -> DeviceCapabilities {
+        let platform_extensions = unsafe { display_extensions(self.egl_display) };
+        DeviceCapabilities {
+            widget_surfaces: true,
+            cpu_surface_reads: false,
+            cpu_surface_uploads: true,
+            cross_device_surface_textures: true,
+            damage_present: extensions_support_damage_present(&platform_extensions),
+            surface_texture_target: self.surface_gl_texture_target(),
+            platform_extensions,
+        }
+    }
+
+    /// Sets what happens to contexts and surfaces created with this device that are dropped
+    /// without being destroyed, overriding the global leak policy.
+    #[inline]
+    pub fn set_leak_policy(&self, policy: LeakPolicy) {
+        self.leak_tracker.set_policy(policy)
+    }
+
+    /// Returns the leak policy that this device follows.
+    #[inline]
+    pub fn leak_policy(&self) -> LeakPolicy {
+        self.leak_tracker.policy()
+    }
 }
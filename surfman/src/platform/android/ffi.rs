@@ -3,6 +3,8 @@
 use std::os::raw::c_int;
 
 pub(crate) const AHARDWAREBUFFER_FORMAT_R8G8B8A8_UNORM:     u32 = 1;
+pub(crate) const AHARDWAREBUFFER_FORMAT_R16G16B16A16_FLOAT: u32 = 0x16;
+pub(crate) const AHARDWAREBUFFER_FORMAT_R10G10B10A2_UNORM:  u32 = 0x2b;
 
 pub(crate) const AHARDWAREBUFFER_USAGE_CPU_READ_NEVER:      u64 = 0;
 pub(crate) const AHARDWAREBUFFER_USAGE_CPU_WRITE_NEVER:     u64 = 0 << 4;
@@ -33,9 +35,12 @@ pub struct ANativeWindow {
 
 #[link(name = "android")]
 extern {
+    pub(crate) fn AHardwareBuffer_acquire(buffer: *mut AHardwareBuffer);
     pub(crate) fn AHardwareBuffer_allocate(desc: *const AHardwareBuffer_Desc,
                                            outBuffer: *mut *mut AHardwareBuffer)
                                            -> c_int;
+    pub(crate) fn AHardwareBuffer_describe(buffer: *const AHardwareBuffer,
+                                           outDesc: *mut AHardwareBuffer_Desc);
     pub(crate) fn AHardwareBuffer_release(buffer: *mut AHardwareBuffer);
     
 
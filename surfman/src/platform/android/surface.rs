@@ -8,29 +8,41 @@ use crate::egl;
 use crate::gl::types::{GLenum, GLuint};
 use crate::gl;
 use crate::gl_utils;
+use crate::ipc::SurfaceHandle;
+use crate::leak::{LeakedObject, Origin};
 use crate::platform::generic::egl::device::EGL_FUNCTIONS;
+use crate::platform::generic::egl::error::ToWindowingApiError;
 use crate::platform::generic::egl::ffi::EGLImageKHR;
 use crate::platform::generic::egl::ffi::EGL_EXTENSION_FUNCTIONS;
+use crate::platform::generic::egl::ffi::EGL_GL_COLORSPACE_KHR;
 use crate::platform::generic::egl::ffi::EGL_IMAGE_PRESERVED_KHR;
 use crate::platform::generic::egl::ffi::EGL_NATIVE_BUFFER_ANDROID;
 use crate::platform::generic::egl::ffi::EGL_NO_IMAGE_KHR;
 use crate::platform::generic;
 use crate::renderbuffers::Renderbuffers;
-use crate::{Error, SurfaceAccess, SurfaceID, SurfaceInfo, SurfaceType, WindowingApiError};
+use crate::{ColorSpace, DepthStencilAttributes, Error, SurfaceAccess, SurfaceFormat, SurfaceID};
+use crate::{LayerStorage, SurfaceInfo};
+use crate::{SurfaceTextureTarget, SurfaceType};
+use crate::surface::{check_upload_region, layer_rect, layered_surface_size};
+use crate::video::PlanarImage;
+use crate::{WindowingApiError, WindowingApiFailure};
 use super::context::{Context, GL_FUNCTIONS};
 use super::device::Device;
+use super::ffi::AHARDWAREBUFFER_FORMAT_R10G10B10A2_UNORM;
+use super::ffi::AHARDWAREBUFFER_FORMAT_R16G16B16A16_FLOAT;
 use super::ffi::{AHARDWAREBUFFER_FORMAT_R8G8B8A8_UNORM, AHARDWAREBUFFER_USAGE_CPU_READ_NEVER};
 use super::ffi::{AHARDWAREBUFFER_USAGE_CPU_WRITE_NEVER, AHARDWAREBUFFER_USAGE_GPU_FRAMEBUFFER};
 use super::ffi::{AHARDWAREBUFFER_USAGE_GPU_SAMPLED_IMAGE, AHardwareBuffer, AHardwareBuffer_Desc};
-use super::ffi::{AHardwareBuffer_allocate, AHardwareBuffer_release, ANativeWindow};
+use super::ffi::{AHardwareBuffer_acquire, AHardwareBuffer_allocate, AHardwareBuffer_describe};
+use super::ffi::{AHardwareBuffer_release, ANativeWindow};
 use super::ffi::{ANativeWindow_getHeight, ANativeWindow_getWidth};
 
-use euclid::default::Size2D;
+use euclid::default::{Rect, Size2D};
 use std::fmt::{self, Debug, Formatter};
 use std::marker::PhantomData;
+use std::mem;
 use std::os::raw::c_void;
 use std::ptr;
-use std::thread;
 
 pub use crate::platform::generic::egl::context::ContextDescriptor;
 
@@ -51,12 +63,16 @@ const SURFACE_GL_TEXTURE_TARGET: GLenum = gl::TEXTURE_2D;
 /// 
 /// Depending on the platform, each surface may be internally double-buffered.
 /// 
-/// Surfaces must be destroyed with the `destroy_surface()` method, or a panic will occur.
+/// Surfaces should be destroyed with the `destroy_surface()` method. A surface that is dropped
+/// without being destroyed is handled according to the device's `LeakPolicy`.
 pub struct Surface {
     pub(crate) context_id: ContextID,
     pub(crate) size: Size2D<i32>,
+    // The number of layers laid out side by side in the surface.
+    pub(crate) layers: u32,
     pub(crate) objects: SurfaceObjects,
     pub(crate) destroyed: bool,
+    origin: Origin,
 }
 
 /// Represents an OpenGL texture that wraps a surface.
@@ -66,12 +82,17 @@ pub struct Surface {
 /// framebuffer).
 /// 
 /// Surface textures are local to a context, but that context does not have to be the same context
-/// as that associated with the underlying surface. The texture must be destroyed with the
-/// `destroy_surface_texture()` method, or a panic will occur.
+/// as that associated with the underlying surface. The texture should be destroyed with the
+/// `destroy_surface_texture()` method. Dropping it instead leaks the texture object and drops its
+/// surface, which is handled according to the device's `LeakPolicy`.
 pub struct SurfaceTexture {
     pub(crate) surface: Surface,
     pub(crate) local_egl_image: EGLImageKHR,
     pub(crate) texture_object: GLuint,
+    // The target that `texture_object` is bound to.
+    pub(crate) target: GLenum,
+    // Zero if the surface has no sampleable depth buffer.
+    pub(crate) depth_texture_object: GLuint,
     pub(crate) phantom: PhantomData<*const ()>,
 }
 
@@ -82,10 +103,22 @@ pub(crate) enum SurfaceObjects {
         framebuffer_object: GLuint,
         texture_object: GLuint,
         renderbuffers: Renderbuffers,
+        // An image wrapping the depth texture, if the depth buffer is sampleable.
+        depth_egl_image: EGLImageKHR,
+        format: SurfaceFormat,
+        access: SurfaceAccess,
+        // Created on the first streamed upload; zero until then.
+        pixel_unpack_buffer: GLuint,
     },
     Window {
         egl_surface: EGLSurface,
     },
+    // A surface imported from another process, which can only be sampled from.
+    Imported {
+        egl_image: EGLImageKHR,
+        texture_object: GLuint,
+        texture_target: GLenum,
+    },
 }
 
 unsafe impl Send for Surface {}
@@ -98,8 +131,8 @@ impl Debug for Surface {
 
 impl Drop for Surface {
     fn drop(&mut self) {
-        if !self.destroyed && !thread::panicking() {
-            panic!("Should have destroyed the surface first with `destroy_surface()`!")
+        if !self.destroyed {
+            self.origin.report_leak(LeakedObject::Surface, false);
         }
     }
 }
@@ -115,6 +148,13 @@ pub struct NativeWidget {
     pub(crate) native_window: *mut ANativeWindow,
 }
 
+/// An Android hardware buffer that another library allocated, to be adopted as a surface with
+/// `Device::create_surface_from_native_image()`.
+#[derive(Clone, Copy)]
+pub struct NativeImage {
+    pub(crate) hardware_buffer: *mut AHardwareBuffer,
+}
+
 impl Device {
     /// Creates either a generic or a widget surface, depending on the supplied surface type.
     /// 
@@ -122,105 +162,251 @@ impl Device {
     /// up in a `SurfaceTexture` for reading by other contexts.
     pub fn create_surface(&mut self,
                           context: &Context,
-                          _: SurfaceAccess,
+                          access: SurfaceAccess,
                           surface_type: SurfaceType<NativeWidget>)
                           -> Result<Surface, Error> {
         match surface_type {
-            SurfaceType::Generic { size } => self.create_generic_surface(context, &size),
-            SurfaceType::Widget { native_widget } => {
+            SurfaceType::Generic { size, format, depth_stencil } => {
+                self.create_generic_surface(context, &size, access, format, depth_stencil, 1)
+            }
+            SurfaceType::Layered { size, layers } => {
+                let size = layered_surface_size(&size, layers)?;
+                let format = SurfaceFormat::RGBA8;
+                self.create_generic_surface(context, &size, access, format, None, layers)
+            }
+            SurfaceType::Widget { native_widget, color_space } => {
                 unsafe {
-                    self.create_window_surface(context, native_widget.native_window)
+                    self.create_window_surface(context, native_widget.native_window, color_space)
                 }
             }
         }
     }
 
-    fn create_generic_surface(&mut self, context: &Context, size: &Size2D<i32>)
+    fn create_generic_surface(&mut self,
+                              context: &Context,
+                              size: &Size2D<i32>,
+                              access: SurfaceAccess,
+                              format: SurfaceFormat,
+                              depth_stencil: Option<DepthStencilAttributes>,
+                              layers: u32)
                               -> Result<Surface, Error> {
-        let _guard = self.temporarily_make_context_current(context)?;
+        let hardware_buffer_format = match format {
+            SurfaceFormat::RGBA8 => AHARDWAREBUFFER_FORMAT_R8G8B8A8_UNORM,
+            SurfaceFormat::RGB10A2 => AHARDWAREBUFFER_FORMAT_R10G10B10A2_UNORM,
+            SurfaceFormat::RGBA16F => AHARDWAREBUFFER_FORMAT_R16G16B16A16_FLOAT,
+            _ => return Err(Error::UnsupportedSurfaceFormat),
+        };
 
-        GL_FUNCTIONS.with(|gl| {
-            unsafe {
-                // Create a native hardware buffer.
-                let hardware_buffer_desc = AHardwareBuffer_Desc {
-                    format: AHARDWAREBUFFER_FORMAT_R8G8B8A8_UNORM,
-                    height: size.height as u32,
-                    width: size.width as u32,
-                    layers: 1,
-                    rfu0: 0,
-                    rfu1: 0,
-                    stride: 10,
-                    usage: AHARDWAREBUFFER_USAGE_CPU_READ_NEVER |
-                        AHARDWAREBUFFER_USAGE_CPU_WRITE_NEVER |
-                        AHARDWAREBUFFER_USAGE_GPU_FRAMEBUFFER |
-                        AHARDWAREBUFFER_USAGE_GPU_SAMPLED_IMAGE,
-                };
-                let mut hardware_buffer = ptr::null_mut();
-                let result = AHardwareBuffer_allocate(&hardware_buffer_desc, &mut hardware_buffer);
-                if result != 0 {
-                    return Err(Error::SurfaceCreationFailed(WindowingApiError::Failed));
-                }
-
-                // Create an EGL image, and bind it to a texture.
-                let egl_image = self.create_egl_image(context, hardware_buffer);
+        let _guard = self.temporarily_make_context_current(context)?;
 
-                // Initialize and bind the image to the texture.
-                let texture_object =
-                    generic::egl::surface::bind_egl_image_to_gl_texture(gl, egl_image);
+        unsafe {
+            // Create a native hardware buffer.
+            let hardware_buffer_desc = AHardwareBuffer_Desc {
+                format: hardware_buffer_format,
+                height: size.height as u32,
+                width: size.width as u32,
+                layers: 1,
+                rfu0: 0,
+                rfu1: 0,
+                stride: 10,
+                usage: AHARDWAREBUFFER_USAGE_CPU_READ_NEVER |
+                    AHARDWAREBUFFER_USAGE_CPU_WRITE_NEVER |
+                    AHARDWAREBUFFER_USAGE_GPU_FRAMEBUFFER |
+                    AHARDWAREBUFFER_USAGE_GPU_SAMPLED_IMAGE,
+            };
+            let mut hardware_buffer = ptr::null_mut();
+            let result = AHardwareBuffer_allocate(&hardware_buffer_desc, &mut hardware_buffer);
+            if result != 0 {
+                let failure = WindowingApiFailure::new("AHardwareBuffer_allocate",
+                                                       WindowingApiError::Failed,
+                                                       Some(result));
+                return Err(Error::SurfaceCreationFailed(Box::new(failure)));
+            }
 
-                // Create the framebuffer, and bind the texture to it.
-                let framebuffer_object =
-                    gl_utils::create_and_bind_framebuffer(gl,
-                                                          SURFACE_GL_TEXTURE_TARGET,
-                                                          texture_object);
+            self.create_hardware_buffer_surface(context,
+                                                hardware_buffer,
+                                                size,
+                                                access,
+                                                format,
+                                                depth_stencil,
+                                                layers)
+        }
+    }
 
-                // Bind renderbuffers as appropriate.
-                let context_descriptor = self.context_descriptor(context);
-                let context_attributes = self.context_descriptor_attributes(&context_descriptor);
-                let renderbuffers = Renderbuffers::new(gl, size, &context_attributes);
-                renderbuffers.bind_to_current_framebuffer(gl);
+    /// Adopts a hardware buffer that another library allocated as a generic surface.
+    /// 
+    /// The surface holds a reference to the hardware buffer, which it gives up when it's
+    /// destroyed. It can be bound to the context and wrapped in a surface texture like any other
+    /// generic surface. The buffer must have been allocated with
+    /// `AHARDWAREBUFFER_USAGE_GPU_FRAMEBUFFER` and `AHARDWAREBUFFER_USAGE_GPU_SAMPLED_IMAGE`, in
+    /// one of the formats that `supported_surface_formats()` lists.
+    pub unsafe fn create_surface_from_native_image(&self,
+                                                   context: &Context,
+                                                   native_image: NativeImage)
+                                                   -> Result<Surface, Error> {
+        let hardware_buffer = native_image.hardware_buffer;
+        let mut hardware_buffer_desc: AHardwareBuffer_Desc = mem::zeroed();
+        AHardwareBuffer_describe(hardware_buffer, &mut hardware_buffer_desc);
+        let format = match hardware_buffer_desc.format {
+            AHARDWAREBUFFER_FORMAT_R8G8B8A8_UNORM => SurfaceFormat::RGBA8,
+            AHARDWAREBUFFER_FORMAT_R10G10B10A2_UNORM => SurfaceFormat::RGB10A2,
+            AHARDWAREBUFFER_FORMAT_R16G16B16A16_FLOAT => SurfaceFormat::RGBA16F,
+            _ => return Err(Error::UnsupportedSurfaceFormat),
+        };
+        let size = Size2D::new(hardware_buffer_desc.width as i32,
+                               hardware_buffer_desc.height as i32);
 
-                debug_assert_eq!(gl.CheckFramebufferStatus(gl::FRAMEBUFFER),
-                                 gl::FRAMEBUFFER_COMPLETE);
+        let _guard = self.temporarily_make_context_current(context)?;
+        AHardwareBuffer_acquire(hardware_buffer);
+        self.create_hardware_buffer_surface(context,
+                                            hardware_buffer,
+                                            &size,
+                                            SurfaceAccess::GPUOnly,
+                                            format,
+                                            None,
+                                            1)
+    }
 
-                Ok(Surface {
-                    size: *size,
-                    context_id: context.id,
-                    objects: SurfaceObjects::HardwareBuffer {
-                        hardware_buffer,
-                        egl_image,
-                        framebuffer_object,
-                        texture_object,
-                        renderbuffers,
-                    },
-                    destroyed: false,
-                })
+    // Takes ownership of a reference to the hardware buffer, releasing it on failure. The context
+    // must be current.
+    unsafe fn create_hardware_buffer_surface(&self,
+                                             context: &Context,
+                                             hardware_buffer: *mut AHardwareBuffer,
+                                             size: &Size2D<i32>,
+                                             access: SurfaceAccess,
+                                             format: SurfaceFormat,
+                                             depth_stencil: Option<DepthStencilAttributes>,
+                                             layers: u32)
+                                             -> Result<Surface, Error> {
+        GL_FUNCTIONS.with(|gl| {
+            let context_descriptor = self.context_descriptor(context);
+            let context_attributes = self.context_descriptor_attributes(&context_descriptor);
+            let mut renderbuffers =
+                Renderbuffers::new(gl, size, &context_attributes, depth_stencil);
+            let depth_egl_image = match renderbuffers.depth_texture() {
+                None => EGL_NO_IMAGE_KHR,
+                Some(depth_texture) => {
+                    let depth_egl_image =
+                        generic::egl::surface::create_egl_image_from_gl_texture(
+                            self.egl_display,
+                            context.egl_context,
+                            depth_texture);
+                    if depth_egl_image == EGL_NO_IMAGE_KHR {
+                        renderbuffers.destroy(gl);
+                        AHardwareBuffer_release(hardware_buffer);
+                        return Err(Error::UnsupportedDepthStencilFormat);
+                    }
+                    depth_egl_image
+                }
+            };
+
+            // Create an EGL image, and bind it to a texture.
+            let egl_image = match self.create_egl_image(context,
+                                                        hardware_buffer,
+                                                        Error::SurfaceCreationFailed) {
+                Ok(egl_image) => egl_image,
+                Err(err) => {
+                    if depth_egl_image != EGL_NO_IMAGE_KHR {
+                        (EGL_EXTENSION_FUNCTIONS.DestroyImageKHR)(self.egl_display,
+                                                                  depth_egl_image);
+                    }
+                    renderbuffers.destroy(gl);
+                    AHardwareBuffer_release(hardware_buffer);
+                    return Err(err);
+                }
+            };
+
+            // Initialize and bind the image to the texture.
+            let texture_object =
+                generic::egl::surface::bind_egl_image_to_gl_texture(gl,
+                                                                    egl_image,
+                                                                    SURFACE_GL_TEXTURE_TARGET);
+
+            // Create the framebuffer, and bind the texture to it.
+            let framebuffer_object =
+                gl_utils::create_and_bind_framebuffer(gl,
+                                                      SURFACE_GL_TEXTURE_TARGET,
+                                                      texture_object);
+
+            // Bind renderbuffers as appropriate.
+            renderbuffers.bind_to_current_framebuffer(gl);
+
+            let framebuffer_status = gl.CheckFramebufferStatus(gl::FRAMEBUFFER);
+            if framebuffer_status != gl::FRAMEBUFFER_COMPLETE {
+                gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+                gl.DeleteFramebuffers(1, &framebuffer_object);
+                gl.DeleteTextures(1, &texture_object);
+                if depth_egl_image != EGL_NO_IMAGE_KHR {
+                    (EGL_EXTENSION_FUNCTIONS.DestroyImageKHR)(self.egl_display, depth_egl_image);
+                }
+                renderbuffers.destroy(gl);
+                (EGL_EXTENSION_FUNCTIONS.DestroyImageKHR)(self.egl_display, egl_image);
+                AHardwareBuffer_release(hardware_buffer);
+
+                let failure = WindowingApiFailure::new("glCheckFramebufferStatus",
+                                                       WindowingApiError::BadAlloc,
+                                                       Some(framebuffer_status as i32));
+                return Err(Error::SurfaceCreationFailed(Box::new(failure)));
             }
+
+            Ok(Surface {
+                size: *size,
+                layers,
+                context_id: context.id,
+                objects: SurfaceObjects::HardwareBuffer {
+                    hardware_buffer,
+                    egl_image,
+                    framebuffer_object,
+                    texture_object,
+                    renderbuffers,
+                    depth_egl_image,
+                    format,
+                    access,
+                    pixel_unpack_buffer: 0,
+                },
+                destroyed: false,
+                origin: Origin::new(&self.leak_tracker),
+            })
         })
     }
 
     unsafe fn create_window_surface(&mut self,
                                     context: &Context,
-                                    native_window: *mut ANativeWindow)
+                                    native_window: *mut ANativeWindow,
+                                    color_space: Option<ColorSpace>)
                                     -> Result<Surface, Error> {
         let width = ANativeWindow_getWidth(native_window);
         let height = ANativeWindow_getHeight(native_window);
 
         let context_descriptor = self.context_descriptor(context);
-        let egl_config = self.context_descriptor_to_egl_config(&context_descriptor);
+        let egl_config = self.context_descriptor_to_egl_config(&context_descriptor)
+                             .map_err(|failure| Error::SurfaceCreationFailed(Box::new(failure)))?;
+
+        let mut attributes = vec![];
+        if let Some(color_space) = generic::egl::surface::egl_color_space(self.egl_display,
+                                                                          color_space)? {
+            attributes.push(EGL_GL_COLORSPACE_KHR as EGLint);
+            attributes.push(color_space as EGLint);
+        }
+        attributes.push(egl::NONE as EGLint);
 
         EGL_FUNCTIONS.with(|egl| {
             let egl_surface = egl.CreateWindowSurface(self.egl_display,
                                                       egl_config,
                                                       native_window as *const c_void,
-                                                      ptr::null());
-            assert_ne!(egl_surface, egl::NO_SURFACE);
+                                                      attributes.as_ptr());
+            if egl_surface == egl::NO_SURFACE {
+                let failure = egl.GetError().to_windowing_api_failure("eglCreateWindowSurface");
+                return Err(Error::SurfaceCreationFailed(Box::new(failure)));
+            }
 
             Ok(Surface {
                 context_id: context.id,
                 size: Size2D::new(width, height),
+                layers: 1,
                 objects: SurfaceObjects::Window { egl_surface },
                 destroyed: false,
+                origin: Origin::new(&self.leak_tracker),
             })
         })
     }
@@ -240,21 +426,59 @@ impl Device {
         unsafe {
             match surface.objects {
                 SurfaceObjects::Window { .. } => return Err((Error::WidgetAttached, surface)),
-                SurfaceObjects::HardwareBuffer { hardware_buffer, .. } => {
+                SurfaceObjects::Imported { egl_image, texture_target, .. } => {
                     GL_FUNCTIONS.with(|gl| {
                         let _guard = match self.temporarily_make_context_current(context) {
                             Ok(guard) => guard,
                             Err(err) => return Err((err, surface)),
                         };
 
-                        let local_egl_image = self.create_egl_image(context, hardware_buffer);
+                        // The image belongs to the surface, so there's no local image to destroy.
                         let texture_object = generic::egl::surface::bind_egl_image_to_gl_texture(
                             gl,
-                            local_egl_image);
+                            egl_image,
+                            texture_target);
+                        Ok(SurfaceTexture {
+                            surface,
+                            local_egl_image: EGL_NO_IMAGE_KHR,
+                            texture_object,
+                            target: texture_target,
+                            depth_texture_object: 0,
+                            phantom: PhantomData,
+                        })
+                    })
+                }
+                SurfaceObjects::HardwareBuffer { hardware_buffer, depth_egl_image, .. } => {
+                    GL_FUNCTIONS.with(|gl| {
+                        let _guard = match self.temporarily_make_context_current(context) {
+                            Ok(guard) => guard,
+                            Err(err) => return Err((err, surface)),
+                        };
+
+                        let local_egl_image =
+                            match self.create_egl_image(context,
+                                                        hardware_buffer,
+                                                        Error::SurfaceTextureCreationFailed) {
+                                Ok(local_egl_image) => local_egl_image,
+                                Err(err) => return Err((err, surface)),
+                            };
+                        let texture_object = generic::egl::surface::bind_egl_image_to_gl_texture(
+                            gl,
+                            local_egl_image,
+                            SURFACE_GL_TEXTURE_TARGET);
+                        let depth_texture_object = if depth_egl_image != EGL_NO_IMAGE_KHR {
+                            generic::egl::surface::bind_depth_egl_image_to_gl_texture(
+                                gl,
+                                depth_egl_image)
+                        } else {
+                            0
+                        };
                         Ok(SurfaceTexture {
                             surface,
                             local_egl_image,
                             texture_object,
+                            target: SURFACE_GL_TEXTURE_TARGET,
+                            depth_texture_object,
                             phantom: PhantomData,
                         })
                     })
@@ -282,23 +506,36 @@ impl Device {
                         egl.SwapBuffers(self.egl_display, egl_surface);
                         Ok(())
                     }
-                    SurfaceObjects::HardwareBuffer { .. } => Err(Error::NoWidgetAttached),
+                    SurfaceObjects::HardwareBuffer { .. } | SurfaceObjects::Imported { .. } => {
+                        Err(Error::NoWidgetAttached)
+                    }
                 }
             }
         })
     }
 
+    // `error` wraps native failures, so that callers can report them as surface or surface
+    // texture creation failures.
     #[allow(non_snake_case)]
-    unsafe fn create_egl_image(&self, _: &Context, hardware_buffer: *mut AHardwareBuffer)
-                               -> EGLImageKHR {
+    unsafe fn create_egl_image(&self,
+                               _: &Context,
+                               hardware_buffer: *mut AHardwareBuffer,
+                               error: fn(Box<WindowingApiFailure>) -> Error)
+                               -> Result<EGLImageKHR, Error> {
         // Get the native client buffer.
         let eglGetNativeClientBufferANDROID =
-            EGL_EXTENSION_FUNCTIONS.GetNativeClientBufferANDROID
-                                   .expect("Where's the `EGL_ANDROID_get_native_client_buffer` \
-                                            extension?");
+            match EGL_EXTENSION_FUNCTIONS.GetNativeClientBufferANDROID {
+                None => return Err(Error::RequiredExtensionUnavailable),
+                Some(eglGetNativeClientBufferANDROID) => eglGetNativeClientBufferANDROID,
+            };
         let client_buffer =
             eglGetNativeClientBufferANDROID(hardware_buffer as *const AHardwareBuffer as *const _);
-        assert!(!client_buffer.is_null());
+        if client_buffer.is_null() {
+            let failure = EGL_FUNCTIONS.with(|egl| {
+                egl.GetError().to_windowing_api_failure("eglGetNativeClientBufferANDROID")
+            });
+            return Err(error(Box::new(failure)));
+        }
 
         // Create the EGL image.
         let egl_image_attributes = [
@@ -310,8 +547,13 @@ impl Device {
                                                                  EGL_NATIVE_BUFFER_ANDROID,
                                                                  client_buffer,
                                                                  egl_image_attributes.as_ptr());
-        assert_ne!(egl_image, EGL_NO_IMAGE_KHR);
-        egl_image
+        if egl_image == EGL_NO_IMAGE_KHR {
+            let failure = EGL_FUNCTIONS.with(|egl| {
+                egl.GetError().to_windowing_api_failure("eglCreateImageKHR")
+            });
+            return Err(error(Box::new(failure)));
+        }
+        Ok(egl_image)
     }
 
     /// Destroys a surface.
@@ -319,8 +561,8 @@ impl Device {
     /// The supplied context must be the context the surface is associated with, or this returns
     /// an `IncompatibleSurface` error.
     /// 
-    /// You must explicitly call this method to dispose of a surface. Otherwise, a panic occurs in
-    /// the `drop` method.
+    /// You should explicitly call this method to dispose of a surface. Otherwise, the surface is
+    /// handled according to the device's `LeakPolicy` when it is dropped.
     pub fn destroy_surface(&self, context: &mut Context, surface: &mut Surface)
                            -> Result<(), Error> {
         if context.id != surface.context_id {
@@ -335,12 +577,27 @@ impl Device {
                     ref mut framebuffer_object,
                     ref mut texture_object,
                     ref mut renderbuffers,
+                    ref mut depth_egl_image,
+                    ref mut pixel_unpack_buffer,
+                    ..
                 } => {
                     GL_FUNCTIONS.with(|gl| {
                         gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
                         gl.DeleteFramebuffers(1, framebuffer_object);
                         *framebuffer_object = 0;
 
+                        if *pixel_unpack_buffer != 0 {
+                            gl.DeleteBuffers(1, pixel_unpack_buffer);
+                            *pixel_unpack_buffer = 0;
+                        }
+
+                        if *depth_egl_image != EGL_NO_IMAGE_KHR {
+                            let result = (EGL_EXTENSION_FUNCTIONS.DestroyImageKHR)(
+                                self.egl_display,
+                                *depth_egl_image);
+                            assert_ne!(result, egl::FALSE);
+                            *depth_egl_image = EGL_NO_IMAGE_KHR;
+                        }
                         renderbuffers.destroy(gl);
 
                         gl.DeleteTextures(1, texture_object);
@@ -362,6 +619,19 @@ impl Device {
                         *egl_surface = egl::NO_SURFACE;
                     })
                 }
+                SurfaceObjects::Imported { ref mut egl_image, ref mut texture_object, .. } => {
+                    GL_FUNCTIONS.with(|gl| {
+                        if *texture_object != 0 {
+                            gl.DeleteTextures(1, texture_object);
+                            *texture_object = 0;
+                        }
+
+                        let result = (EGL_EXTENSION_FUNCTIONS.DestroyImageKHR)(self.egl_display,
+                                                                               *egl_image);
+                        assert_ne!(result, egl::FALSE);
+                        *egl_image = EGL_NO_IMAGE_KHR;
+                    });
+                }
             }
         }
 
@@ -374,8 +644,9 @@ impl Device {
     /// The supplied context must be the same context the surface texture was created with, or an
     /// `IncompatibleSurfaceTexture` error is returned.
     /// 
-    /// All surface textures must be explicitly destroyed with this function, or a panic will
-    /// occur.
+    /// All surface textures should be explicitly destroyed with this function. Otherwise, the
+    /// texture object is leaked and the surface is handled according to the device's `LeakPolicy`
+    /// when the surface texture is dropped.
     pub fn destroy_surface_texture(&self,
                                    context: &mut Context,
                                    mut surface_texture: SurfaceTexture)
@@ -385,13 +656,19 @@ impl Device {
             unsafe {
                 gl.DeleteTextures(1, &surface_texture.texture_object);
                 surface_texture.texture_object = 0;
+                if surface_texture.depth_texture_object != 0 {
+                    gl.DeleteTextures(1, &surface_texture.depth_texture_object);
+                    surface_texture.depth_texture_object = 0;
+                }
 
-                let egl_display = self.egl_display;
-                let result =
-                    (EGL_EXTENSION_FUNCTIONS.DestroyImageKHR)(egl_display,
-                                                              surface_texture.local_egl_image);
-                assert_ne!(result, egl::FALSE);
-                surface_texture.local_egl_image = EGL_NO_IMAGE_KHR;
+                if surface_texture.local_egl_image != EGL_NO_IMAGE_KHR {
+                    let egl_display = self.egl_display;
+                    let result =
+                        (EGL_EXTENSION_FUNCTIONS.DestroyImageKHR)(egl_display,
+                                                                  surface_texture.local_egl_image);
+                    assert_ne!(result, egl::FALSE);
+                    surface_texture.local_egl_image = EGL_NO_IMAGE_KHR;
+                }
             }
 
             Ok(surface_texture.surface)
@@ -405,6 +682,62 @@ impl Device {
         Err(Error::Unimplemented)
     }
 
+    /// Copies pixels from the CPU into a rectangle of a generic surface.
+    /// 
+    /// Uploads to surfaces created with `SurfaceAccess::GPUCPUWriteCombined` are streamed through
+    /// a pixel buffer object; other surfaces are updated synchronously.
+    pub fn upload_surface_data(&self,
+                               context: &Context,
+                               surface: &mut Surface,
+                               rect: &Rect<i32>,
+                               data: &[u8],
+                               stride: usize,
+                               format: SurfaceFormat)
+                               -> Result<(), Error> {
+        if context.id != surface.context_id {
+            return Err(Error::IncompatibleSurface);
+        }
+
+        match surface.objects {
+            SurfaceObjects::HardwareBuffer {
+                texture_object,
+                format: surface_format,
+                access,
+                ref mut pixel_unpack_buffer,
+                ..
+            } => {
+                if !access.cpu_access_allowed() {
+                    return Err(Error::SurfaceDataInaccessible);
+                }
+                if format != surface_format {
+                    return Err(Error::UnsupportedSurfaceFormat);
+                }
+                check_upload_region(&surface.size, rect, data.len(), stride, format)?;
+
+                let gl_info = self.gl_info(context)?;
+                let _guard = self.temporarily_make_context_current(context)?;
+                let pixel_unpack_buffer = match access {
+                    SurfaceAccess::GPUCPUWriteCombined => Some(pixel_unpack_buffer),
+                    SurfaceAccess::GPUOnly | SurfaceAccess::GPUCPU => None,
+                };
+                GL_FUNCTIONS.with(|gl| {
+                    unsafe {
+                        generic::egl::surface::upload_texture_data(gl,
+                                                                   &gl_info,
+                                                                   texture_object,
+                                                                   pixel_unpack_buffer,
+                                                                   rect,
+                                                                   data,
+                                                                   stride,
+                                                                   format)
+                    }
+                })
+            }
+            SurfaceObjects::Window { .. } => Err(Error::WidgetAttached),
+            SurfaceObjects::Imported { .. } => Err(Error::IncompatibleSurface),
+        }
+    }
+
     /// Returns the OpenGL texture target needed to read from this surface texture.
     /// 
     /// This will be `GL_TEXTURE_2D` or `GL_TEXTURE_RECTANGLE`, depending on platform.
@@ -413,6 +746,14 @@ impl Device {
         SURFACE_GL_TEXTURE_TARGET
     }
 
+    /// Returns the formats that generic surfaces created on this device may have.
+    /// 
+    /// These are the formats that every `AHardwareBuffer` implementation can render to.
+    #[inline]
+    pub fn supported_surface_formats(&self, _: &Context) -> Vec<SurfaceFormat> {
+        vec![SurfaceFormat::RGBA8, SurfaceFormat::RGB10A2, SurfaceFormat::RGBA16F]
+    }
+
     /// Returns various information about the surface, including the framebuffer object needed to
     /// render to this surface.
     /// 
@@ -420,14 +761,17 @@ impl Device {
     /// on the framebuffer object returned by this function. This framebuffer object may or not be
     /// 0, the default framebuffer, depending on platform.
     pub fn surface_info(&self, surface: &Surface) -> SurfaceInfo {
+        let framebuffer_object = match surface.objects {
+            SurfaceObjects::HardwareBuffer { framebuffer_object, .. } => framebuffer_object,
+            SurfaceObjects::Window { .. } | SurfaceObjects::Imported { .. } => 0,
+        };
         SurfaceInfo {
             size: surface.size,
             id: surface.id(),
             context_id: surface.context_id,
-            framebuffer_object: match surface.objects {
-                SurfaceObjects::HardwareBuffer { framebuffer_object, .. } => framebuffer_object,
-                SurfaceObjects::Window { .. } => 0,
-            },
+            framebuffer_object,
+            layers: surface.layers,
+            layer_storage: LayerStorage::Atlas,
         }
     }
 
@@ -438,6 +782,170 @@ impl Device {
     pub fn surface_texture_object(&self, surface_texture: &SurfaceTexture) -> GLuint {
         surface_texture.texture_object
     }
+
+    /// Returns the OpenGL texture object containing the depth buffer of this surface texture's
+    /// surface, or `None` if the surface wasn't created with a sampleable depth buffer.
+    /// 
+    /// As with `surface_texture_object()`, it is only legal to read from this texture object.
+    #[inline]
+    pub fn surface_texture_depth_object(&self, surface_texture: &SurfaceTexture)
+                                        -> Option<GLuint> {
+        match surface_texture.depth_texture_object {
+            0 => None,
+            depth_texture_object => Some(depth_texture_object),
+        }
+    }
+
+    /// Returns the OpenGL texture object containing the given layer of the surface, or `None` if
+    /// the surface has no such layer.
+    /// 
+    /// Layers are laid out side by side in one texture on this platform, so this is
+    /// `surface_texture_object()` for every layer.
+    pub fn surface_texture_layer_object(&self, surface_texture: &SurfaceTexture, layer: u32)
+                                        -> Option<GLuint> {
+        self.surface_texture_layer_rect(surface_texture, layer)
+            .map(|_| self.surface_texture_object(surface_texture))
+    }
+
+    /// Returns the region of `surface_texture_layer_object()` that holds the given layer of the
+    /// surface, in texels, or `None` if the surface has no such layer.
+    /// 
+    /// Surfaces that aren't layered have a single layer that covers the whole texture.
+    pub fn surface_texture_layer_rect(&self, surface_texture: &SurfaceTexture, layer: u32)
+                                      -> Option<Rect<i32>> {
+        let surface = &surface_texture.surface;
+        layer_rect(&surface.size, surface.layers, layer)
+    }
+
+    /// Returns the OpenGL texture target that the surface texture's texture object must be bound
+    /// to.
+    /// 
+    /// Hardware buffers that we allocate are always sampled as `GL_TEXTURE_2D`; only imported
+    /// images that the driver can't sample any other way use `GL_TEXTURE_EXTERNAL_OES`.
+    #[inline]
+    pub fn surface_texture_target(&self, surface_texture: &SurfaceTexture) -> GLenum {
+        surface_texture.target
+    }
+
+    /// Exports a generic surface so that it can be sampled from in another process.
+    /// 
+    /// Hardware buffers can't be exported as dma-bufs here, so the surface contents are copied
+    /// into shared memory, and the handle won't reflect subsequent rendering.
+    /// 
+    /// The supplied context must be the context the surface is associated with, or an
+    /// `IncompatibleSurface` error is returned.
+    pub fn export_surface(&self, context: &Context, surface: &Surface)
+                          -> Result<SurfaceHandle, Error> {
+        if context.id != surface.context_id {
+            return Err(Error::IncompatibleSurface);
+        }
+
+        let (egl_image, framebuffer_object, format) = match surface.objects {
+            SurfaceObjects::HardwareBuffer { egl_image, framebuffer_object, format, .. } => {
+                (egl_image, framebuffer_object, format)
+            }
+            SurfaceObjects::Window { .. } => return Err(Error::WidgetAttached),
+            SurfaceObjects::Imported { .. } => return Err(Error::IncompatibleSurface),
+        };
+
+        let _guard = self.temporarily_make_context_current(context)?;
+        GL_FUNCTIONS.with(|gl| {
+            unsafe {
+                generic::egl::surface::export_surface_image(gl,
+                                                            self.egl_display,
+                                                            egl_image,
+                                                            framebuffer_object,
+                                                            &surface.size,
+                                                            format)
+            }
+        })
+    }
+
+    /// Wraps a surface exported from another process in a surface texture local to the given
+    /// context.
+    /// 
+    /// Both dma-buf and shared memory handles are accepted. The surface that the returned texture
+    /// wraps can only be read from.
+    pub fn create_surface_texture_from_handle(&self,
+                                              context: &mut Context,
+                                              handle: SurfaceHandle,
+                                              target: SurfaceTextureTarget)
+                                              -> Result<SurfaceTexture, Error> {
+        let _guard = self.temporarily_make_context_current(context)?;
+        GL_FUNCTIONS.with(|gl| {
+            unsafe {
+                let texture_target =
+                    generic::egl::surface::imported_texture_target(self.egl_display,
+                                                                   &handle,
+                                                                   target)?;
+                let (egl_image, texture_object) =
+                    generic::egl::surface::import_surface_image(gl,
+                                                                self.egl_display,
+                                                                context.egl_context,
+                                                                &handle)?;
+                let surface = Surface {
+                    context_id: context.id,
+                    size: handle.size(),
+                    layers: 1,
+                    objects: SurfaceObjects::Imported { egl_image, texture_object, texture_target },
+                    destroyed: false,
+                    origin: Origin::new(&self.leak_tracker),
+                };
+                Ok(SurfaceTexture {
+                    surface,
+                    local_egl_image: EGL_NO_IMAGE_KHR,
+                    texture_object: generic::egl::surface::bind_egl_image_to_gl_texture(
+                        gl,
+                        egl_image,
+                        texture_target),
+                    target: texture_target,
+                    depth_texture_object: 0,
+                    phantom: PhantomData,
+                })
+            }
+        })
+    }
+
+    /// Wraps a decoded video frame in a surface texture local to the given context.
+    /// 
+    /// Few Android drivers can import dma-bufs, so the planes are usually converted to RGBA on the
+    /// CPU. The first row of the frame is row 0 of the texture.
+    pub fn create_surface_texture_from_planes(&self,
+                                              context: &mut Context,
+                                              image: &PlanarImage,
+                                              target: SurfaceTextureTarget)
+                                              -> Result<SurfaceTexture, Error> {
+        let _guard = self.temporarily_make_context_current(context)?;
+        GL_FUNCTIONS.with(|gl| {
+            unsafe {
+                let (egl_image, texture_object, texture_target) =
+                    generic::egl::surface::import_planar_image(gl,
+                                                               self.egl_display,
+                                                               context.egl_context,
+                                                               image,
+                                                               target)?;
+                let surface = Surface {
+                    context_id: context.id,
+                    size: image.size,
+                    layers: 1,
+                    objects: SurfaceObjects::Imported { egl_image, texture_object, texture_target },
+                    destroyed: false,
+                    origin: Origin::new(&self.leak_tracker),
+                };
+                Ok(SurfaceTexture {
+                    surface,
+                    local_egl_image: EGL_NO_IMAGE_KHR,
+                    texture_object: generic::egl::surface::bind_egl_image_to_gl_texture(
+                        gl,
+                        egl_image,
+                        texture_target),
+                    target: texture_target,
+                    depth_texture_object: 0,
+                    phantom: PhantomData,
+                })
+            }
+        })
+    }
 }
 
 impl NativeWidget {
@@ -448,10 +956,19 @@ impl NativeWidget {
     }
 }
 
+impl NativeImage {
+    /// Creates a native image type from an Android `AHardwareBuffer`.
+    #[inline]
+    pub unsafe fn from_hardware_buffer(hardware_buffer: *mut AHardwareBuffer) -> NativeImage {
+        NativeImage { hardware_buffer }
+    }
+}
+
 impl Surface {
     fn id(&self) -> SurfaceID {
         match self.objects {
-            SurfaceObjects::HardwareBuffer { egl_image, .. } => SurfaceID(egl_image as usize),
+            SurfaceObjects::HardwareBuffer { egl_image, .. } |
+            SurfaceObjects::Imported { egl_image, .. } => SurfaceID(egl_image as usize),
             SurfaceObjects::Window { egl_surface } => SurfaceID(egl_surface as usize),
         }
     }
@@ -2,25 +2,27 @@
 //
 //! Functionality common to backends using EGL contexts.
 
-use crate::context::{self, CREATE_CONTEXT_MUTEX};
-use crate::egl::types::{EGLConfig, EGLContext, EGLDisplay, EGLSurface, EGLint};
+use crate::context::{self, CREATE_CONTEXT_MUTEX, PixelFormatAttributes};
+use crate::context::{ContextDescriptorDiagnostics, PixelFormatSurfaceTypes};
+use crate::egl::types::{EGLConfig, EGLContext, EGLDisplay, EGLSurface, EGLenum, EGLint};
 use crate::egl;
 use crate::gl::types::GLuint;
 use crate::gl;
+use crate::info::GLInfoCache;
+use crate::leak::{LeakTracker, LeakedObject, Origin};
 use crate::surface::Framebuffer;
 use crate::{ContextAttributeFlags, ContextAttributes, ContextID, Error, GLApi, GLVersion};
-use crate::{Gl, SurfaceInfo};
-use super::device::EGL_FUNCTIONS;
+use crate::{GLInfo, Gl, LayerStorage, SurfaceInfo, WindowingApiError, WindowingApiFailure};
+use super::device::{EGL_FUNCTIONS, display_supports_client_api};
 use super::error::ToWindowingApiError;
 use super::ffi::{EGL_CONTEXT_MINOR_VERSION_KHR, EGL_CONTEXT_OPENGL_COMPATIBILITY_PROFILE_BIT};
 use super::ffi::{EGL_CONTEXT_OPENGL_CORE_PROFILE_BIT, EGL_CONTEXT_OPENGL_PROFILE_MASK};
-use super::surface::{EGLBackedSurface, ExternalEGLSurfaces};
+use super::surface::{self, EGLBackedSurface, EGLSurfaceObjects, ExternalEGLSurfaces};
 
 use std::ffi::{CStr, CString};
 use std::mem;
 use std::os::raw::{c_char, c_void};
 use std::ptr;
-use std::thread;
 
 #[allow(dead_code)]
 const DUMMY_PBUFFER_SIZE: EGLint = 16;
@@ -29,8 +31,10 @@ const RGB_CHANNEL_BIT_DEPTH: EGLint = 8;
 pub(crate) struct EGLBackedContext {
     pub(crate) egl_context: EGLContext,
     pub(crate) id: ContextID,
+    pub(crate) gl_info: GLInfoCache,
     framebuffer: Framebuffer<EGLBackedSurface, ExternalEGLSurfaces>,
     context_is_owned: bool,
+    origin: Origin,
 }
 
 /// Wrapper for a native `EGLContext`.
@@ -50,8 +54,11 @@ pub struct NativeContext {
 #[derive(Clone)]
 pub struct ContextDescriptor {
     pub(crate) egl_config_id: EGLint,
+    pub(crate) gl_api: GLApi,
     pub(crate) gl_version: GLVersion,
+    pub(crate) minimum_gl_version: GLVersion,
     pub(crate) compatibility_profile: bool,
+    pub(crate) allow_core_profile: bool,
 }
 
 #[must_use]
@@ -63,10 +70,28 @@ pub(crate) struct CurrentContextGuard {
 }
 
 impl Drop for EGLBackedContext {
-    #[inline]
     fn drop(&mut self) {
-        if self.egl_context != egl::NO_CONTEXT && !thread::panicking() {
-            panic!("Contexts must be destroyed explicitly with `destroy_context`!")
+        if self.egl_context == egl::NO_CONTEXT {
+            return;
+        }
+
+        // The bound surface, if any, goes wherever the context goes.
+        let framebuffer = mem::replace(&mut self.framebuffer, Framebuffer::None);
+        if !self.origin.report_leak(LeakedObject::Context, true) {
+            mem::forget(framebuffer);
+            return;
+        }
+
+        let context = EGLBackedContext {
+            egl_context: mem::replace(&mut self.egl_context, egl::NO_CONTEXT),
+            id: self.id,
+            gl_info: GLInfoCache::default(),
+            framebuffer,
+            context_is_owned: self.context_is_owned,
+            origin: self.origin.take(),
+        };
+        if let Err(context) = self.origin.defer(LeakedObject::Context, self.id, context) {
+            mem::forget(context);
         }
     }
 }
@@ -89,34 +114,39 @@ impl Drop for CurrentContextGuard {
 impl EGLBackedContext {
     pub(crate) unsafe fn new(egl_display: EGLDisplay,
                              descriptor: &ContextDescriptor,
-                             gl_api: GLApi)
+                             origin: Origin)
                              -> Result<EGLBackedContext, Error> {
         let mut next_context_id = CREATE_CONTEXT_MUTEX.lock().unwrap();
 
         // Create the context.
-        let egl_context = create_context(egl_display, descriptor, gl_api)?;
+        let egl_context = create_context(egl_display, descriptor)?;
 
         // Wrap and return it.
         let context = EGLBackedContext {
             egl_context,
             id: *next_context_id,
+            gl_info: GLInfoCache::default(),
             framebuffer: Framebuffer::None,
             context_is_owned: true,
+            origin,
         };
         next_context_id.0 += 1;
         Ok(context)
     }
 
-    pub(crate) unsafe fn from_native_context(native_context: NativeContext) -> EGLBackedContext {
+    pub(crate) unsafe fn from_native_context(native_context: NativeContext, origin: Origin)
+                                             -> EGLBackedContext {
         let mut next_context_id = CREATE_CONTEXT_MUTEX.lock().unwrap();
         let context = EGLBackedContext {
             egl_context: native_context.egl_context,
             id: *next_context_id,
+            gl_info: GLInfoCache::default(),
             framebuffer: Framebuffer::External(ExternalEGLSurfaces {
                 draw: native_context.egl_draw_surface,
                 read: native_context.egl_read_surface,
             }),
             context_is_owned: false,
+            origin,
         };
         next_context_id.0 += 1;
         context
@@ -162,8 +192,8 @@ impl EGLBackedContext {
                                          egl_surfaces.read,
                                          self.egl_context);
             if result == egl::FALSE {
-                let err = egl.GetError().to_windowing_api_error();
-                return Err(Error::MakeCurrentFailed(err));
+                let err = egl.GetError().to_windowing_api_failure("eglMakeCurrent");
+                return Err(Error::MakeCurrentFailed(Box::new(err)));
             }
             Ok(())
         })
@@ -183,6 +213,9 @@ impl EGLBackedContext {
         if self.id != surface.context_id {
             return Err((Error::IncompatibleSurface, surface));
         }
+        if let EGLSurfaceObjects::Imported { .. } = surface.objects {
+            return Err((Error::IncompatibleSurface, surface));
+        }
 
         match self.framebuffer {
             Framebuffer::None => self.framebuffer = Framebuffer::Surface(surface),
@@ -202,7 +235,15 @@ impl EGLBackedContext {
                                         -> Result<Option<EGLBackedSurface>, Error> {
         match self.framebuffer {
             Framebuffer::None => return Ok(None),
-            Framebuffer::Surface(_) => {}
+            Framebuffer::Surface(ref surface) => {
+                // Other contexts read array-backed layered surfaces through copies of their
+                // layers, which have to be brought up to date before the surface is handed back.
+                if surface.info().layer_storage != LayerStorage::Atlas {
+                    let _guard = CurrentContextGuard::new();
+                    self.make_current(egl_display)?;
+                    surface.copy_layers(gl);
+                }
+            }
             Framebuffer::External(_) => return Err(Error::ExternalRenderTarget),
         }
 
@@ -217,6 +258,20 @@ impl EGLBackedContext {
         Ok(Some(surface))
     }
 
+    // Destroys the surfaces of this context that were dropped under `LeakPolicy::Defer`. If
+    // there are any, this leaves the context current. Returns the native windows of window
+    // surfaces, as `surface::destroy_deferred_surfaces()` does.
+    pub(crate) unsafe fn destroy_deferred_surfaces(&self,
+                                                   gl: &Gl,
+                                                   egl_display: EGLDisplay,
+                                                   leak_tracker: &LeakTracker)
+                                                   -> Vec<*const c_void> {
+        if !leak_tracker.has_deferred_surfaces(self.id) || self.make_current(egl_display).is_err() {
+            return vec![];
+        }
+        surface::destroy_deferred_surfaces(gl, egl_display, leak_tracker, self.id)
+    }
+
     pub(crate) fn surface_info(&self) -> Result<Option<SurfaceInfo>, Error> {
         match self.framebuffer {
             Framebuffer::None => Ok(None),
@@ -249,25 +304,41 @@ impl NativeContext {
 }
 
 impl ContextDescriptor {
+    // `default_gl_api` is the API to use if `attributes` doesn't request one.
     pub(crate) unsafe fn new(egl_display: EGLDisplay,
                              attributes: &ContextAttributes,
+                             default_gl_api: GLApi,
                              extra_config_attributes: &[EGLint])
                              -> Result<ContextDescriptor, Error> {
+        ContextDescriptor::with_config_preference(egl_display,
+                                                  attributes,
+                                                  default_gl_api,
+                                                  extra_config_attributes,
+                                                  |_| true)
+    }
+
+    // Like `new()`, but picks the first matching config that `prefer` accepts, if there is one.
+    pub(crate) unsafe fn with_config_preference<F>(egl_display: EGLDisplay,
+                                                   attributes: &ContextAttributes,
+                                                   default_gl_api: GLApi,
+                                                   extra_config_attributes: &[EGLint],
+                                                   prefer: F)
+                                                   -> Result<ContextDescriptor, Error>
+                                                   where F: Fn(EGLConfig) -> bool {
         let flags = attributes.flags;
 
         let alpha_size   = if flags.contains(ContextAttributeFlags::ALPHA)   { 8  } else { 0 };
         let depth_size   = if flags.contains(ContextAttributeFlags::DEPTH)   { 24 } else { 0 };
         let stencil_size = if flags.contains(ContextAttributeFlags::STENCIL) { 8  } else { 0 };
 
-        let compatibility_profile = flags.contains(ContextAttributeFlags::COMPATIBILITY_PROFILE);
-
-        // Mesa doesn't support the OpenGL compatibility profile post version 3.0. Take that into
-        // account.
-        if compatibility_profile &&
-                (attributes.version.major > 3 ||
-                 attributes.version.major == 3 && attributes.version.minor > 0) {
+        if !profile_is_supported(attributes) {
+            debug!("surfman: GL {}.{} was requested with the compatibility profile, which Mesa \
+                    only supports up to GL 3.0",
+                   attributes.version.major,
+                   attributes.version.minor);
             return Err(Error::UnsupportedGLProfile);
         }
+        let gl_api = requested_gl_api(egl_display, attributes, default_gl_api)?;
 
         // Create required config attributes.
         //
@@ -285,105 +356,176 @@ impl ContextDescriptor {
             egl::ALPHA_SIZE as EGLint,      alpha_size,
             egl::DEPTH_SIZE as EGLint,      depth_size,
             egl::STENCIL_SIZE as EGLint,    stencil_size,
+            egl::RENDERABLE_TYPE as EGLint, renderable_type_bit(gl_api),
         ]);
         requested_config_attributes.extend_from_slice(extra_config_attributes);
         requested_config_attributes.extend_from_slice(&[egl::NONE as EGLint, 0, 0, 0]);
 
-        EGL_FUNCTIONS.with(|egl| {
-            // See how many applicable configs there are.
-            let mut config_count = 0;
-            let result = egl.ChooseConfig(egl_display,
-                                          requested_config_attributes.as_ptr(),
-                                          ptr::null_mut(),
-                                          0,
-                                          &mut config_count);
-            if result == egl::FALSE {
-                let err = egl.GetError().to_windowing_api_error();
-                return Err(Error::PixelFormatSelectionFailed(err));
+        let configs = choose_configs(egl_display, &requested_config_attributes)?;
+
+        // Sanitize configs.
+        let mut egl_configs = Vec::with_capacity(configs.len());
+        for egl_config in configs {
+            let mut sane = true;
+            for pair in required_config_attributes.chunks(2) {
+                let value = get_config_attr(egl_display, egl_config, pair[0])
+                    .map_err(|failure| Error::PixelFormatSelectionFailed(Box::new(failure)))?;
+                sane &= value == pair[1];
+            }
+            if sane {
+                egl_configs.push(egl_config);
             }
-            if config_count == 0 {
+        }
+        let egl_config = egl_configs.iter().find(|&&egl_config| prefer(egl_config));
+        let egl_config = match egl_config.or_else(|| egl_configs.first()) {
+            None => {
+                debug!("surfman: No EGL config found; {}",
+                       ContextDescriptor::diagnose(egl_display,
+                                                   attributes,
+                                                   default_gl_api,
+                                                   extra_config_attributes));
                 return Err(Error::NoPixelFormatFound);
             }
+            Some(&egl_config) => egl_config,
+        };
 
-            // Enumerate all those configs.
-            let mut configs = vec![ptr::null(); config_count as usize];
-            let mut real_config_count = config_count;
-            let result = egl.ChooseConfig(egl_display,
-                                          requested_config_attributes.as_ptr(),
-                                          configs.as_mut_ptr(),
-                                          config_count,
-                                          &mut real_config_count);
-            if result == egl::FALSE {
-                let err = egl.GetError().to_windowing_api_error();
-                return Err(Error::PixelFormatSelectionFailed(err));
-            }
+        // Get the config ID.
+        let egl_config_id = get_config_attr(egl_display, egl_config, egl::CONFIG_ID as EGLint)
+            .map_err(|failure| Error::PixelFormatSelectionFailed(Box::new(failure)))?;
+        Ok(ContextDescriptor::from_config_id(egl_config_id, attributes, gl_api))
+    }
+
+    // Returns a descriptor for every RGB config matching `extra_config_attributes`, regardless of
+    // channel depths and of the alpha, depth, and stencil flags in `attributes`.
+    pub(crate) unsafe fn enumerate(egl_display: EGLDisplay,
+                                   attributes: &ContextAttributes,
+                                   default_gl_api: GLApi,
+                                   extra_config_attributes: &[EGLint])
+                                   -> Result<Vec<(ContextDescriptor, PixelFormatAttributes)>,
+                                             Error> {
+        if !profile_is_supported(attributes) {
+            return Err(Error::UnsupportedGLProfile);
+        }
+        let gl_api = requested_gl_api(egl_display, attributes, default_gl_api)?;
 
-            // Sanitize configs.
-            let egl_config = configs.into_iter().filter(|&egl_config| {
-                required_config_attributes.chunks(2).all(|pair| {
-                    get_config_attr(egl_display, egl_config, pair[0]) == pair[1]
-                })
-            }).next();
-            let egl_config = match egl_config {
-                None => return Err(Error::NoPixelFormatFound),
-                Some(egl_config) => egl_config,
+        let mut config_attributes = extra_config_attributes.to_vec();
+        config_attributes.extend_from_slice(&[
+            egl::RENDERABLE_TYPE as EGLint, renderable_type_bit(gl_api),
+        ]);
+        config_attributes.extend_from_slice(&[egl::NONE as EGLint, 0, 0, 0]);
+
+        let egl_configs = choose_configs(egl_display, &config_attributes)?;
+        let mut descriptors = vec![];
+        for egl_config in egl_configs {
+            let attr = |attribute: EGLenum| {
+                get_config_attr(egl_display, egl_config, attribute as EGLint)
+                    .map_err(|failure| Error::PixelFormatSelectionFailed(Box::new(failure)))
             };
+            if attr(egl::COLOR_BUFFER_TYPE)? != egl::RGB_BUFFER as EGLint {
+                continue;
+            }
+            let egl_config_id = attr(egl::CONFIG_ID)?;
+            let pixel_format_attributes = pixel_format_attributes(egl_display, egl_config)
+                .map_err(|failure| Error::PixelFormatSelectionFailed(Box::new(failure)))?;
+            descriptors.push((ContextDescriptor::from_config_id(egl_config_id, attributes, gl_api),
+                              pixel_format_attributes));
+        }
+        Ok(descriptors)
+    }
 
-            // Get the config ID and version.
-            let egl_config_id = get_config_attr(egl_display, egl_config, egl::CONFIG_ID as EGLint);
-            let gl_version = attributes.version;
+    fn from_config_id(egl_config_id: EGLint, attributes: &ContextAttributes, gl_api: GLApi)
+                      -> ContextDescriptor {
+        let flags = attributes.flags;
+        ContextDescriptor {
+            egl_config_id,
+            gl_api,
+            gl_version: attributes.version,
+            minimum_gl_version: attributes.minimum_version.unwrap_or(attributes.version),
+            compatibility_profile: flags.contains(ContextAttributeFlags::COMPATIBILITY_PROFILE),
+            allow_core_profile: flags.contains(ContextAttributeFlags::ALLOW_CORE_PROFILE),
+        }
+    }
 
-            Ok(ContextDescriptor {
-                egl_config_id,
-                gl_version,
-                compatibility_profile,
-            })
-        })
+    // Explains which configs `new()` rejects for `attributes`, and why.
+    pub(crate) unsafe fn diagnose(egl_display: EGLDisplay,
+                                  attributes: &ContextAttributes,
+                                  default_gl_api: GLApi,
+                                  extra_config_attributes: &[EGLint])
+                                  -> ContextDescriptorDiagnostics {
+        let candidates = ContextDescriptor::enumerate(egl_display,
+                                                      attributes,
+                                                      default_gl_api,
+                                                      extra_config_attributes);
+        ContextDescriptorDiagnostics::new(attributes,
+                                          candidates,
+                                          Some(RGB_CHANNEL_BIT_DEPTH as u32))
     }
 
     pub(crate) unsafe fn from_egl_context(gl: &Gl,
                                           egl_display: EGLDisplay,
                                           egl_context: EGLContext)
                                           -> ContextDescriptor {
-        let egl_config_id = get_context_attr(egl_display, egl_context, egl::CONFIG_ID as EGLint);
+        // A config ID of zero names no config, so creating anything from the descriptor will
+        // fail with an error if the context can't be queried.
+        let egl_config_id = match get_context_attr(egl_display,
+                                                   egl_context,
+                                                   egl::CONFIG_ID as EGLint) {
+            Ok(egl_config_id) => egl_config_id,
+            Err(failure) => {
+                warn!("surfman: Couldn't query the config of a context: {:?}", failure);
+                0
+            }
+        };
+        let gl_api = context_gl_api(egl_display, egl_context);
 
         EGL_FUNCTIONS.with(|egl| {
             let _guard = CurrentContextGuard::new();
             egl.MakeCurrent(egl_display, egl::NO_SURFACE, egl::NO_SURFACE, egl_context);
-            let gl_version = GLVersion::current(gl);
-            let compatibility_profile = context::current_context_uses_compatibility_profile(gl);
+            let (gl_version, compatibility_profile) = match GLInfo::current(gl) {
+                Ok(gl_info) => (gl_info.version, gl_info.compatibility_profile),
+                Err(err) => {
+                    // Fall back to the major version that EGL reports.
+                    warn!("surfman: Couldn't query the GL version of a context: {:?}", err);
+                    let client_version = get_context_attr(egl_display,
+                                                          egl_context,
+                                                          egl::CONTEXT_CLIENT_VERSION as EGLint)
+                        .unwrap_or(0);
+                    (GLVersion::new(client_version as u8, 0), false)
+                }
+            };
 
-            ContextDescriptor { egl_config_id, gl_version, compatibility_profile }
+            ContextDescriptor {
+                egl_config_id,
+                gl_api,
+                gl_version,
+                minimum_gl_version: gl_version,
+                compatibility_profile,
+                allow_core_profile: false,
+            }
         })
     }
 
     #[allow(dead_code)]
-    pub(crate) unsafe fn to_egl_config(&self, egl_display: EGLDisplay) -> EGLConfig {
-        let config_attributes = [
-            egl::CONFIG_ID as EGLint,   self.egl_config_id,
-            egl::NONE as EGLint,        0,
-            0,                          0,
-        ];
-
-        EGL_FUNCTIONS.with(|egl| {
-            let (mut config, mut config_count) = (ptr::null(), 0);
-            let result = egl.ChooseConfig(egl_display,
-                                          config_attributes.as_ptr(),
-                                          &mut config,
-                                          1,
-                                          &mut config_count);
-            assert_ne!(result, egl::FALSE);
-            assert!(config_count > 0);
-            config
-        })
+    pub(crate) unsafe fn to_egl_config(&self, egl_display: EGLDisplay)
+                                       -> Result<EGLConfig, WindowingApiFailure> {
+        egl_config_from_id(egl_display, self.egl_config_id)
     }
 
     pub(crate) unsafe fn attributes(&self, egl_display: EGLDisplay) -> ContextAttributes {
-        let egl_config = egl_config_from_id(egl_display, self.egl_config_id);
-
-        let alpha_size = get_config_attr(egl_display, egl_config, egl::ALPHA_SIZE as EGLint);
-        let depth_size = get_config_attr(egl_display, egl_config, egl::DEPTH_SIZE as EGLint);
-        let stencil_size = get_config_attr(egl_display, egl_config, egl::STENCIL_SIZE as EGLint);
+        // If the config can't be queried, report that it has none of these buffers.
+        let buffer_sizes = egl_config_from_id(egl_display, self.egl_config_id).and_then(|config| {
+            let attr = |attribute: EGLenum| {
+                get_config_attr(egl_display, config, attribute as EGLint)
+            };
+            Ok((attr(egl::ALPHA_SIZE)?, attr(egl::DEPTH_SIZE)?, attr(egl::STENCIL_SIZE)?))
+        });
+        let (alpha_size, depth_size, stencil_size) = match buffer_sizes {
+            Ok(buffer_sizes) => buffer_sizes,
+            Err(failure) => {
+                warn!("surfman: Couldn't query the config of a context descriptor: {:?}", failure);
+                (0, 0, 0)
+            }
+        };
 
         // Convert to `surfman` context attribute flags.
         let mut attribute_flags = ContextAttributeFlags::empty();
@@ -393,9 +535,20 @@ impl ContextDescriptor {
 
         attribute_flags.set(ContextAttributeFlags::COMPATIBILITY_PROFILE,
                             self.compatibility_profile);
+        attribute_flags.set(ContextAttributeFlags::ALLOW_CORE_PROFILE, self.allow_core_profile);
 
         // Create appropriate context attributes.
-        ContextAttributes { flags: attribute_flags, version: self.gl_version }
+        let minimum_version = if self.minimum_gl_version < self.gl_version {
+            Some(self.minimum_gl_version)
+        } else {
+            None
+        };
+        ContextAttributes {
+            api: Some(self.gl_api),
+            version: self.gl_version,
+            minimum_version,
+            flags: attribute_flags,
+        }
     }
 }
 
@@ -414,49 +567,199 @@ impl CurrentContextGuard {
     }
 }
 
-pub(crate) unsafe fn create_context(egl_display: EGLDisplay,
-                                    descriptor: &ContextDescriptor,
-                                    gl_api: GLApi)
+// Returns every config matching the given `EGL_NONE`-terminated attribute list, in the order
+// `eglChooseConfig` sorts them.
+unsafe fn choose_configs(egl_display: EGLDisplay, config_attributes: &[EGLint])
+                         -> Result<Vec<EGLConfig>, Error> {
+    EGL_FUNCTIONS.with(|egl| {
+        // See how many applicable configs there are.
+        let mut config_count = 0;
+        let result = egl.ChooseConfig(egl_display,
+                                      config_attributes.as_ptr(),
+                                      ptr::null_mut(),
+                                      0,
+                                      &mut config_count);
+        if result == egl::FALSE {
+            let err = egl.GetError().to_windowing_api_failure("eglChooseConfig");
+            return Err(Error::PixelFormatSelectionFailed(Box::new(err)));
+        }
+        if config_count == 0 {
+            return Ok(vec![]);
+        }
+
+        // Enumerate all those configs.
+        let mut configs = vec![ptr::null(); config_count as usize];
+        let mut real_config_count = config_count;
+        let result = egl.ChooseConfig(egl_display,
+                                      config_attributes.as_ptr(),
+                                      configs.as_mut_ptr(),
+                                      config_count,
+                                      &mut real_config_count);
+        if result == egl::FALSE {
+            let err = egl.GetError().to_windowing_api_failure("eglChooseConfig");
+            return Err(Error::PixelFormatSelectionFailed(Box::new(err)));
+        }
+        configs.truncate(real_config_count as usize);
+        Ok(configs)
+    })
+}
+
+pub(crate) unsafe fn pixel_format_attributes(egl_display: EGLDisplay, egl_config: EGLConfig)
+                                             -> Result<PixelFormatAttributes, WindowingApiFailure> {
+    let attr = |attribute: EGLenum| get_config_attr(egl_display, egl_config, attribute as EGLint);
+
+    let egl_surface_type = attr(egl::SURFACE_TYPE)?;
+    let mut surface_types = PixelFormatSurfaceTypes::empty();
+    surface_types.set(PixelFormatSurfaceTypes::WINDOW,
+                      egl_surface_type & egl::WINDOW_BIT as EGLint != 0);
+    surface_types.set(PixelFormatSurfaceTypes::PBUFFER,
+                      egl_surface_type & egl::PBUFFER_BIT as EGLint != 0);
+    surface_types.set(PixelFormatSurfaceTypes::PIXMAP,
+                      egl_surface_type & egl::PIXMAP_BIT as EGLint != 0);
+
+    let native_visual_id = attr(egl::NATIVE_VISUAL_ID)?;
+    Ok(PixelFormatAttributes {
+        red_size: attr(egl::RED_SIZE)? as u32,
+        green_size: attr(egl::GREEN_SIZE)? as u32,
+        blue_size: attr(egl::BLUE_SIZE)? as u32,
+        alpha_size: attr(egl::ALPHA_SIZE)? as u32,
+        depth_size: attr(egl::DEPTH_SIZE)? as u32,
+        stencil_size: attr(egl::STENCIL_SIZE)? as u32,
+        samples: attr(egl::SAMPLES)? as u32,
+        surface_types,
+        native_visual_id: if native_visual_id == 0 { None } else { Some(native_visual_id as u32) },
+    })
+}
+
+pub(crate) unsafe fn create_context(egl_display: EGLDisplay, descriptor: &ContextDescriptor)
                                     -> Result<EGLContext, Error> {
+    let gl_api = descriptor.gl_api;
     EGL_FUNCTIONS.with(|egl| {
         let ok = match gl_api {
             GLApi::GL => egl.BindAPI(egl::OPENGL_API),
             GLApi::GLES => egl.BindAPI(egl::OPENGL_ES_API),
         };
-        assert_ne!(ok, egl::FALSE);
-    });
+        if ok == egl::FALSE {
+            let failure = egl.GetError().to_windowing_api_failure("eglBindAPI");
+            return Err(Error::ContextCreationFailed(Box::new(failure)));
+        }
+        Ok(())
+    })?;
+
+    let egl_config = egl_config_from_id(egl_display, descriptor.egl_config_id)
+        .map_err(|failure| Error::ContextCreationFailed(Box::new(failure)))?;
+
+    // Work down from the preferred version and profile until the driver accepts one.
+    let candidates = context::version_candidates(gl_api,
+                                                 descriptor.gl_version,
+                                                 descriptor.minimum_gl_version,
+                                                 descriptor.compatibility_profile,
+                                                 descriptor.allow_core_profile);
+    let mut last_error = WindowingApiFailure::from(WindowingApiError::Failed);
+    for (gl_version, compatibility_profile) in candidates {
+        let mut profile_mask = EGL_CONTEXT_OPENGL_CORE_PROFILE_BIT;
+        if compatibility_profile {
+            profile_mask |= EGL_CONTEXT_OPENGL_COMPATIBILITY_PROFILE_BIT;
+        }
+
+        let mut egl_context_attributes = vec![
+            egl::CONTEXT_CLIENT_VERSION as EGLint,      gl_version.major as EGLint,
+            EGL_CONTEXT_MINOR_VERSION_KHR as EGLint,    gl_version.minor as EGLint,
+        ];
+        // Profiles only exist in desktop OpenGL, and Mesa rejects the attribute otherwise.
+        if gl_api == GLApi::GL {
+            egl_context_attributes.extend_from_slice(&[
+                EGL_CONTEXT_OPENGL_PROFILE_MASK as EGLint,  profile_mask,
+            ]);
+        }
+        // Include some extra zeroes to work around broken implementations.
+        //
+        // FIXME(pcwalton): Which implementations are those? (This is copied from Gecko.)
+        egl_context_attributes.extend_from_slice(&[egl::NONE as EGLint, 0, 0, 0]);
+
+        let egl_context = EGL_FUNCTIONS.with(|egl| {
+            egl.CreateContext(egl_display,
+                              egl_config,
+                              egl::NO_CONTEXT,
+                              egl_context_attributes.as_ptr())
+        });
+        if egl_context != egl::NO_CONTEXT {
+            return Ok(egl_context);
+        }
+        last_error = EGL_FUNCTIONS.with(|egl| {
+            egl.GetError().to_windowing_api_failure("eglCreateContext")
+        });
+    }
 
-    let egl_config = egl_config_from_id(egl_display, descriptor.egl_config_id);
+    Err(Error::ContextCreationFailed(Box::new(last_error)))
+}
 
-    let mut profile_mask = EGL_CONTEXT_OPENGL_CORE_PROFILE_BIT;
-    if descriptor.compatibility_profile {
-        profile_mask |= EGL_CONTEXT_OPENGL_COMPATIBILITY_PROFILE_BIT;
+// Returns the API that `attributes` asks for, or an `UnsupportedGLType` error if the display
+// can't create contexts for it. The device's default API is assumed to be supported.
+unsafe fn requested_gl_api(egl_display: EGLDisplay,
+                           attributes: &ContextAttributes,
+                           default_gl_api: GLApi)
+                           -> Result<GLApi, Error> {
+    let gl_api = attributes.api.unwrap_or(default_gl_api);
+    if gl_api != default_gl_api && !display_supports_client_api(egl_display, gl_api) {
+        return Err(Error::UnsupportedGLType);
     }
+    Ok(gl_api)
+}
 
-    // Include some extra zeroes to work around broken implementations.
-    //
-    // FIXME(pcwalton): Which implementations are those? (This is copied from Gecko.)
-    let egl_context_attributes = [
-        egl::CONTEXT_CLIENT_VERSION as EGLint,      descriptor.gl_version.major as EGLint,
-        EGL_CONTEXT_MINOR_VERSION_KHR as EGLint,    descriptor.gl_version.minor as EGLint,
-        EGL_CONTEXT_OPENGL_PROFILE_MASK as EGLint,  profile_mask,
-        egl::NONE as EGLint, 0,
-        0, 0,
-    ];
+fn renderable_type_bit(gl_api: GLApi) -> EGLint {
+    match gl_api {
+        GLApi::GL => egl::OPENGL_BIT as EGLint,
+        GLApi::GLES => egl::OPENGL_ES2_BIT as EGLint,
+    }
+}
 
-    EGL_FUNCTIONS.with(|egl| {
-        let egl_context = egl.CreateContext(egl_display,
-                                            egl_config,
-                                            egl::NO_CONTEXT,
-                                            egl_context_attributes.as_ptr());
-        if egl_context == egl::NO_CONTEXT {
-            let err = egl.GetError();
-            let err = err.to_windowing_api_error();
-            return Err(Error::ContextCreationFailed(err));
+// Returns the API that the given context was created for, or OpenGL if the context can't be
+// queried.
+pub(crate) unsafe fn context_gl_api(egl_display: EGLDisplay, egl_context: EGLContext) -> GLApi {
+    match get_context_attr(egl_display, egl_context, egl::CONTEXT_CLIENT_TYPE as EGLint) {
+        Ok(client_type) if client_type == egl::OPENGL_ES_API as EGLint => GLApi::GLES,
+        Ok(_) => GLApi::GL,
+        Err(failure) => {
+            warn!("surfman: Couldn't query the API of a context: {:?}", failure);
+            GLApi::GL
         }
+    }
+}
 
-        Ok(egl_context)
-    })
+// Mesa doesn't support the OpenGL compatibility profile post version 3.0. Returns false if that
+// rules out every version and profile that `attributes` accepts.
+fn profile_is_supported(attributes: &ContextAttributes) -> bool {
+    let flags = attributes.flags;
+    let minimum_version = attributes.minimum_version.map_or(attributes.version, |version| {
+        version.min(attributes.version)
+    });
+    !flags.contains(ContextAttributeFlags::COMPATIBILITY_PROFILE) ||
+        flags.contains(ContextAttributeFlags::ALLOW_CORE_PROFILE) ||
+        minimum_version <= GLVersion::new(3, 0)
+}
+
+// Destroys the contexts that the current thread dropped under `LeakPolicy::Defer`, along with
+// their surfaces. If there are any, this leaves no context current. Returns the native windows of
+// window surfaces, as `surface::destroy_deferred_surfaces()` does.
+pub(crate) unsafe fn destroy_deferred_contexts(gl: &Gl,
+                                               egl_display: EGLDisplay,
+                                               leak_tracker: &LeakTracker)
+                                               -> Vec<*const c_void> {
+    let mut native_windows = vec![];
+    let contexts: Vec<EGLBackedContext> = leak_tracker.take_deferred_contexts();
+    for mut context in contexts {
+        native_windows.extend(context.destroy_deferred_surfaces(gl, egl_display, leak_tracker));
+        if context.make_current(egl_display).is_ok() {
+            if let Ok(Some(mut surface)) = context.unbind_surface(gl, egl_display) {
+                if let Ok(Some(native_window)) = surface.destroy(gl, egl_display, context.id) {
+                    native_windows.push(native_window);
+                }
+            }
+        }
+        context.destroy(egl_display);
+    }
+    native_windows
 }
 
 pub(crate) unsafe fn make_no_context_current(egl_display: EGLDisplay) -> Result<(), Error> {
@@ -466,37 +769,43 @@ pub(crate) unsafe fn make_no_context_current(egl_display: EGLDisplay) -> Result<
                                      egl::NO_SURFACE,
                                      egl::NO_CONTEXT);
         if result == egl::FALSE {
-            let err = egl.GetError().to_windowing_api_error();
-            return Err(Error::MakeCurrentFailed(err));
+            let err = egl.GetError().to_windowing_api_failure("eglMakeCurrent");
+            return Err(Error::MakeCurrentFailed(Box::new(err)));
         }
         Ok(())
     })
 }
 
 pub(crate) unsafe fn get_config_attr(egl_display: EGLDisplay, egl_config: EGLConfig, attr: EGLint)
-                                     -> EGLint {
+                                     -> Result<EGLint, WindowingApiFailure> {
     EGL_FUNCTIONS.with(|egl| {
         let mut value = 0;
         let result = egl.GetConfigAttrib(egl_display, egl_config, attr, &mut value);
-        assert_ne!(result, egl::FALSE);
-        value
+        if result == egl::FALSE {
+            return Err(egl.GetError().to_windowing_api_failure("eglGetConfigAttrib"));
+        }
+        Ok(value)
     })
 }
 
 pub(crate) unsafe fn get_context_attr(egl_display: EGLDisplay,
                                       egl_context: EGLContext,
                                       attr: EGLint)
-                                      -> EGLint {
+                                      -> Result<EGLint, WindowingApiFailure> {
     EGL_FUNCTIONS.with(|egl| {
         let mut value = 0;
         let result = egl.QueryContext(egl_display, egl_context, attr, &mut value);
-        assert_ne!(result, egl::FALSE);
-        value
+        if result == egl::FALSE {
+            return Err(egl.GetError().to_windowing_api_failure("eglQueryContext"));
+        }
+        Ok(value)
     })
 }
 
+// Returns the config with the given ID, or a `BadConfig` failure if the display has no such
+// config.
 pub(crate) unsafe fn egl_config_from_id(egl_display: EGLDisplay, egl_config_id: EGLint)
-                                        -> EGLConfig {
+                                        -> Result<EGLConfig, WindowingApiFailure> {
     let config_attributes = [
         egl::CONFIG_ID as EGLint,   egl_config_id,
         egl::NONE as EGLint,        0,
@@ -510,9 +819,15 @@ pub(crate) unsafe fn egl_config_from_id(egl_display: EGLDisplay, egl_config_id:
                                       &mut config,
                                       1,
                                       &mut config_count);
-        assert_ne!(result, egl::FALSE);
-        assert!(config_count > 0);
-        config
+        if result == egl::FALSE {
+            return Err(egl.GetError().to_windowing_api_failure("eglChooseConfig"));
+        }
+        if config_count == 0 {
+            return Err(WindowingApiFailure::new("eglChooseConfig",
+                                                WindowingApiError::BadConfig,
+                                                None));
+        }
+        Ok(config)
     })
 }
 
@@ -529,9 +844,11 @@ pub(crate) fn get_proc_address(symbol_name: &str) -> *const c_void {
 // framebuffer on some backends.
 #[allow(dead_code)]
 pub(crate) unsafe fn create_dummy_pbuffer(egl_display: EGLDisplay, egl_context: EGLContext)
-                                          -> EGLSurface {
-    let egl_config_id = get_context_attr(egl_display, egl_context, egl::CONFIG_ID as EGLint);
-    let egl_config = egl_config_from_id(egl_display, egl_config_id);
+                                          -> Result<EGLSurface, Error> {
+    let egl_config_id = get_context_attr(egl_display, egl_context, egl::CONFIG_ID as EGLint)
+        .map_err(|failure| Error::ContextCreationFailed(Box::new(failure)))?;
+    let egl_config = egl_config_from_id(egl_display, egl_config_id)
+        .map_err(|failure| Error::ContextCreationFailed(Box::new(failure)))?;
 
     let pbuffer_attributes = [
         egl::WIDTH as EGLint,   DUMMY_PBUFFER_SIZE,
@@ -544,8 +861,11 @@ pub(crate) unsafe fn create_dummy_pbuffer(egl_display: EGLDisplay, egl_context:
         let pbuffer = egl.CreatePbufferSurface(egl_display,
                                                egl_config,
                                                pbuffer_attributes.as_ptr());
-        assert_ne!(pbuffer, egl::NO_SURFACE);
-        pbuffer
+        if pbuffer == egl::NO_SURFACE {
+            let failure = egl.GetError().to_windowing_api_failure("eglCreatePbufferSurface");
+            return Err(Error::ContextCreationFailed(Box::new(failure)));
+        }
+        Ok(pbuffer)
     })
 }
 
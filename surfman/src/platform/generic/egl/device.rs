@@ -2,9 +2,13 @@
 //
 //! Functionality common to backends using EGL displays.
 
+use crate::egl::types::{EGLDisplay, EGLint};
 use crate::egl::Egl;
+use crate::egl;
+use crate::info::GLApi;
 
-use std::ffi::CString;
+use std::collections::HashSet;
+use std::ffi::{CStr, CString};
 use std::mem;
 use std::os::raw::{c_char, c_void};
 
@@ -71,3 +75,52 @@ pub(crate) unsafe fn lookup_egl_extension(name: &'static [u8]) -> *mut c_void {
         mem::transmute(egl.GetProcAddress(&name[0] as *const u8 as *const c_char))
     })
 }
+
+/// Returns true if the given EGL display advertises the named extension.
+///
+/// Some implementations hand out entry points for extensions they don't support on a particular
+/// display, so check this before calling any optional function.
+pub(crate) unsafe fn display_supports_extension(egl_display: EGLDisplay, name: &str) -> bool {
+    EGL_FUNCTIONS.with(|egl| {
+        let extensions = egl.QueryString(egl_display, egl::EXTENSIONS as EGLint);
+        if extensions.is_null() {
+            return false;
+        }
+        CStr::from_ptr(extensions).to_string_lossy().split_whitespace().any(|ext| ext == name)
+    })
+}
+
+/// Returns the names of every extension that the given EGL display advertises.
+pub(crate) unsafe fn display_extensions(egl_display: EGLDisplay) -> HashSet<String> {
+    EGL_FUNCTIONS.with(|egl| {
+        let extensions = egl.QueryString(egl_display, egl::EXTENSIONS as EGLint);
+        if extensions.is_null() {
+            return HashSet::new();
+        }
+        CStr::from_ptr(extensions).to_string_lossy()
+                                  .split_whitespace()
+                                  .map(|ext| ext.to_owned())
+                                  .collect()
+    })
+}
+
+/// Returns true if these display extensions let window surfaces be swapped with damage regions.
+pub(crate) fn extensions_support_damage_present(extensions: &HashSet<String>) -> bool {
+    extensions.contains("EGL_KHR_swap_buffers_with_damage") ||
+        extensions.contains("EGL_EXT_swap_buffers_with_damage")
+}
+
+/// Returns true if the given EGL display can create contexts for the given API.
+pub(crate) unsafe fn display_supports_client_api(egl_display: EGLDisplay, gl_api: GLApi) -> bool {
+    let name = match gl_api {
+        GLApi::GL => "OpenGL",
+        GLApi::GLES => "OpenGL_ES",
+    };
+    EGL_FUNCTIONS.with(|egl| {
+        let client_apis = egl.QueryString(egl_display, egl::CLIENT_APIS as EGLint);
+        if client_apis.is_null() {
+            return false;
+        }
+        CStr::from_ptr(client_apis).to_string_lossy().split_whitespace().any(|api| api == name)
+    })
+}
@@ -2,12 +2,15 @@
 
 //! Translation of errors from the EGL API to `surfman` errors.
 
-use crate::WindowingApiError;
+use crate::{WindowingApiError, WindowingApiFailure};
 use crate::egl::types::{EGLenum, EGLint};
 use crate::egl;
 
 pub(crate) trait ToWindowingApiError {
     fn to_windowing_api_error(self) -> WindowingApiError;
+
+    // Records that the named EGL function failed with this error code.
+    fn to_windowing_api_failure(self, call: &'static str) -> WindowingApiFailure;
 }
 
 impl ToWindowingApiError for EGLint {
@@ -27,7 +30,12 @@ impl ToWindowingApiError for EGLint {
             egl::BAD_NATIVE_PIXMAP => WindowingApiError::BadNativePixmap,
             egl::BAD_NATIVE_WINDOW => WindowingApiError::BadNativeWindow,
             egl::CONTEXT_LOST => WindowingApiError::ContextLost,
-            _ => WindowingApiError::Failed,
+            egl::SUCCESS => WindowingApiError::Failed,
+            _ => WindowingApiError::Other(self),
         }
     }
+
+    fn to_windowing_api_failure(self, call: &'static str) -> WindowingApiFailure {
+        WindowingApiFailure::new(call, self.to_windowing_api_error(), Some(self))
+    }
 }
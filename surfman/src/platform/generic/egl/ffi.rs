@@ -7,7 +7,7 @@
 use crate::egl::types::{EGLAttrib, EGLBoolean, EGLContext, EGLDeviceEXT, EGLDisplay, EGLSurface};
 use crate::egl::types::{EGLenum, EGLint};
 
-use std::os::raw::c_void;
+use std::os::raw::{c_int, c_void};
 
 pub enum EGLClientBufferOpaque {}
 pub type EGLClientBuffer = *mut EGLClientBufferOpaque;
@@ -15,6 +15,10 @@ pub type EGLClientBuffer = *mut EGLClientBufferOpaque;
 pub enum EGLImageKHROpaque {}
 pub type EGLImageKHR = *mut EGLImageKHROpaque;
 
+pub const EGL_GL_COLORSPACE_SRGB_KHR:            EGLenum = 0x3089;
+pub const EGL_GL_COLORSPACE_LINEAR_KHR:          EGLenum = 0x308a;
+pub const EGL_GL_COLORSPACE_KHR:                 EGLenum = 0x309d;
+pub const EGL_NATIVE_PIXMAP_KHR:                 EGLenum = 0x30b0;
 pub const EGL_GL_TEXTURE_2D_KHR:                 EGLenum = 0x30b1;
 pub const EGL_IMAGE_PRESERVED_KHR:               EGLenum = 0x30d2;
 pub const EGL_CONTEXT_MINOR_VERSION_KHR:         EGLenum = 0x30fb;
@@ -22,16 +26,89 @@ pub const EGL_CONTEXT_OPENGL_PROFILE_MASK:       EGLenum = 0x30fd;
 pub const EGL_PLATFORM_DEVICE_EXT:               EGLenum = 0x313f;
 pub const EGL_NATIVE_BUFFER_ANDROID:             EGLenum = 0x3140;
 pub const EGL_PLATFORM_X11_KHR:                  EGLenum = 0x31d5;
+pub const EGL_WAYLAND_BUFFER_WL:                 EGLenum = 0x31d5;
+pub const EGL_WAYLAND_PLANE_WL:                  EGLenum = 0x31d6;
+pub const EGL_TEXTURE_Y_U_V_WL:                  EGLenum = 0x31d7;
+pub const EGL_TEXTURE_Y_UV_WL:                   EGLenum = 0x31d8;
 pub const EGL_PLATFORM_WAYLAND_KHR:              EGLenum = 0x31d8;
+pub const EGL_TEXTURE_Y_XUXV_WL:                 EGLenum = 0x31d9;
+pub const EGL_TEXTURE_EXTERNAL_WL:               EGLenum = 0x31da;
+pub const EGL_WAYLAND_Y_INVERTED_WL:             EGLenum = 0x31db;
 pub const EGL_PLATFORM_SURFACELESS_MESA:         EGLenum = 0x31dd;
 pub const EGL_D3D_TEXTURE_2D_SHARE_HANDLE_ANGLE: EGLenum = 0x3200;
+pub const EGL_LINUX_DMA_BUF_EXT:                 EGLenum = 0x3270;
+pub const EGL_LINUX_DRM_FOURCC_EXT:              EGLenum = 0x3271;
+pub const EGL_DMA_BUF_PLANE0_FD_EXT:             EGLenum = 0x3272;
+pub const EGL_DMA_BUF_PLANE0_OFFSET_EXT:         EGLenum = 0x3273;
+pub const EGL_DMA_BUF_PLANE0_PITCH_EXT:          EGLenum = 0x3274;
+pub const EGL_DMA_BUF_PLANE1_FD_EXT:             EGLenum = 0x3275;
+pub const EGL_DMA_BUF_PLANE1_OFFSET_EXT:         EGLenum = 0x3276;
+pub const EGL_DMA_BUF_PLANE1_PITCH_EXT:          EGLenum = 0x3277;
+pub const EGL_DMA_BUF_PLANE2_FD_EXT:             EGLenum = 0x3278;
+pub const EGL_DMA_BUF_PLANE2_OFFSET_EXT:         EGLenum = 0x3279;
+pub const EGL_DMA_BUF_PLANE2_PITCH_EXT:          EGLenum = 0x327a;
+pub const EGL_YUV_COLOR_SPACE_HINT_EXT:          EGLenum = 0x327b;
+pub const EGL_SAMPLE_RANGE_HINT_EXT:             EGLenum = 0x327c;
+pub const EGL_ITU_REC601_EXT:                    EGLenum = 0x327f;
+pub const EGL_ITU_REC709_EXT:                    EGLenum = 0x3280;
+pub const EGL_ITU_REC2020_EXT:                   EGLenum = 0x3281;
+pub const EGL_YUV_FULL_RANGE_EXT:                EGLenum = 0x3282;
+pub const EGL_YUV_NARROW_RANGE_EXT:              EGLenum = 0x3283;
+pub const EGL_GL_COLORSPACE_BT2020_PQ_EXT:        EGLenum = 0x3340;
+pub const EGL_GL_COLORSPACE_SCRGB_LINEAR_EXT:    EGLenum = 0x3350;
+pub const EGL_GL_COLORSPACE_DISPLAY_P3_EXT:      EGLenum = 0x3363;
 pub const EGL_D3D11_DEVICE_ANGLE:                EGLenum = 0x33a1;
 pub const EGL_DXGI_KEYED_MUTEX_ANGLE:            EGLenum = 0x33a2;
 pub const EGL_D3D_TEXTURE_ANGLE:                 EGLenum = 0x33a3;
+pub const EGL_DMA_BUF_PLANE3_FD_EXT:             EGLenum = 0x3440;
+pub const EGL_DMA_BUF_PLANE3_OFFSET_EXT:         EGLenum = 0x3441;
+pub const EGL_DMA_BUF_PLANE3_PITCH_EXT:          EGLenum = 0x3442;
+pub const EGL_DMA_BUF_PLANE0_MODIFIER_LO_EXT:    EGLenum = 0x3443;
+pub const EGL_DMA_BUF_PLANE0_MODIFIER_HI_EXT:    EGLenum = 0x3444;
+pub const EGL_DMA_BUF_PLANE1_MODIFIER_LO_EXT:    EGLenum = 0x3445;
+pub const EGL_DMA_BUF_PLANE1_MODIFIER_HI_EXT:    EGLenum = 0x3446;
+pub const EGL_DMA_BUF_PLANE2_MODIFIER_LO_EXT:    EGLenum = 0x3447;
+pub const EGL_DMA_BUF_PLANE2_MODIFIER_HI_EXT:    EGLenum = 0x3448;
+pub const EGL_DMA_BUF_PLANE3_MODIFIER_LO_EXT:    EGLenum = 0x3449;
+pub const EGL_DMA_BUF_PLANE3_MODIFIER_HI_EXT:    EGLenum = 0x344a;
 
 pub const EGL_NO_DEVICE_EXT: EGLDeviceEXT = 0 as EGLDeviceEXT;
 pub const EGL_NO_IMAGE_KHR: EGLImageKHR = 0 as EGLImageKHR;
 
+pub const DRM_FORMAT_MOD_INVALID: u64 = 0x00ff_ffff_ffff_ffff;
+
+// The attributes used to import each plane of a dma-buf image, indexed by plane number.
+pub const EGL_DMA_BUF_PLANE_ATTRIBUTES: [[EGLenum; 5]; 4] = [
+    [
+        EGL_DMA_BUF_PLANE0_FD_EXT,
+        EGL_DMA_BUF_PLANE0_OFFSET_EXT,
+        EGL_DMA_BUF_PLANE0_PITCH_EXT,
+        EGL_DMA_BUF_PLANE0_MODIFIER_LO_EXT,
+        EGL_DMA_BUF_PLANE0_MODIFIER_HI_EXT,
+    ],
+    [
+        EGL_DMA_BUF_PLANE1_FD_EXT,
+        EGL_DMA_BUF_PLANE1_OFFSET_EXT,
+        EGL_DMA_BUF_PLANE1_PITCH_EXT,
+        EGL_DMA_BUF_PLANE1_MODIFIER_LO_EXT,
+        EGL_DMA_BUF_PLANE1_MODIFIER_HI_EXT,
+    ],
+    [
+        EGL_DMA_BUF_PLANE2_FD_EXT,
+        EGL_DMA_BUF_PLANE2_OFFSET_EXT,
+        EGL_DMA_BUF_PLANE2_PITCH_EXT,
+        EGL_DMA_BUF_PLANE2_MODIFIER_LO_EXT,
+        EGL_DMA_BUF_PLANE2_MODIFIER_HI_EXT,
+    ],
+    [
+        EGL_DMA_BUF_PLANE3_FD_EXT,
+        EGL_DMA_BUF_PLANE3_OFFSET_EXT,
+        EGL_DMA_BUF_PLANE3_PITCH_EXT,
+        EGL_DMA_BUF_PLANE3_MODIFIER_LO_EXT,
+        EGL_DMA_BUF_PLANE3_MODIFIER_HI_EXT,
+    ],
+];
+
 pub const EGL_CONTEXT_OPENGL_CORE_PROFILE_BIT: EGLint = 1;
 pub const EGL_CONTEXT_OPENGL_COMPATIBILITY_PROFILE_BIT: EGLint = 2;
 
@@ -48,6 +125,18 @@ pub(crate) struct EGLExtensionFunctions {
     pub(crate) ImageTargetTexture2DOES: extern "C" fn(target: EGLenum, image: EGLImageKHR),
 
     // Optional extensions
+    pub(crate) ExportDMABUFImageMESA: Option<extern "C" fn(dpy: EGLDisplay,
+                                                           image: EGLImageKHR,
+                                                           fds: *mut c_int,
+                                                           strides: *mut EGLint,
+                                                           offsets: *mut EGLint)
+                                                           -> EGLBoolean>,
+    pub(crate) ExportDMABUFImageQueryMESA: Option<extern "C" fn(dpy: EGLDisplay,
+                                                                image: EGLImageKHR,
+                                                                fourcc: *mut c_int,
+                                                                num_planes: *mut c_int,
+                                                                modifiers: *mut u64)
+                                                                -> EGLBoolean>,
     pub(crate) CreateDeviceANGLE: Option<extern "C" fn(device_type: EGLint,
                                                        native_device: *mut c_void,
                                                        attrib_list: *const EGLAttrib)
@@ -62,6 +151,22 @@ pub(crate) struct EGLExtensionFunctions {
                                                            attribute: EGLint,
                                                            value: *mut EGLAttrib)
                                                            -> EGLBoolean>,
+    pub(crate) BindWaylandDisplayWL: Option<extern "C" fn(dpy: EGLDisplay, display: *mut c_void)
+                                                          -> EGLBoolean>,
+    pub(crate) UnbindWaylandDisplayWL: Option<extern "C" fn(dpy: EGLDisplay, display: *mut c_void)
+                                                            -> EGLBoolean>,
+    pub(crate) QueryWaylandBufferWL: Option<extern "C" fn(dpy: EGLDisplay,
+                                                          buffer: *mut c_void,
+                                                          attribute: EGLint,
+                                                          value: *mut EGLint)
+                                                          -> EGLBoolean>,
+    pub(crate) QueryDmaBufModifiersEXT: Option<extern "C" fn(dpy: EGLDisplay,
+                                                             format: EGLint,
+                                                             max_modifiers: EGLint,
+                                                             modifiers: *mut u64,
+                                                             external_only: *mut EGLBoolean,
+                                                             num_modifiers: *mut EGLint)
+                                                             -> EGLBoolean>,
     pub(crate) QuerySurfacePointerANGLE: Option<extern "C" fn(dpy: EGLDisplay,
                                                               surface: EGLSurface,
                                                               attribute: EGLint,
@@ -79,10 +184,16 @@ lazy_static! {
                 DestroyImageKHR: cast(get(b"eglDestroyImageKHR\0")),
                 ImageTargetTexture2DOES: cast(get(b"glEGLImageTargetTexture2DOES\0")),
 
+                ExportDMABUFImageMESA: cast(get(b"eglExportDMABUFImageMESA\0")),
+                ExportDMABUFImageQueryMESA: cast(get(b"eglExportDMABUFImageQueryMESA\0")),
                 CreateDeviceANGLE: cast(get(b"eglCreateDeviceANGLE\0")),
                 GetNativeClientBufferANDROID: cast(get(b"eglGetNativeClientBufferANDROID\0")),
                 QueryDeviceAttribEXT: cast(get(b"eglQueryDeviceAttribEXT\0")),
                 QueryDisplayAttribEXT: cast(get(b"eglQueryDisplayAttribEXT\0")),
+                BindWaylandDisplayWL: cast(get(b"eglBindWaylandDisplayWL\0")),
+                UnbindWaylandDisplayWL: cast(get(b"eglUnbindWaylandDisplayWL\0")),
+                QueryWaylandBufferWL: cast(get(b"eglQueryWaylandBufferWL\0")),
+                QueryDmaBufModifiersEXT: cast(get(b"eglQueryDmaBufModifiersEXT\0")),
                 QuerySurfacePointerANGLE: cast(get(b"eglQuerySurfacePointerANGLE\0")),
             }
         }
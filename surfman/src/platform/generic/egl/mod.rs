@@ -7,3 +7,6 @@ pub(crate) mod device;
 pub(crate) mod error;
 pub(crate) mod ffi;
 pub(crate) mod surface;
+
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+pub(crate) mod wayland;
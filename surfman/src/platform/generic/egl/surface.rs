@@ -2,30 +2,90 @@
 //
 //! Functionality common to backends using EGL surfaces.
 
-use crate::egl::types::{EGLAttrib, EGLConfig, EGLContext, EGLDisplay, EGLSurface, EGLint};
+use crate::egl::types::{EGLAttrib, EGLConfig, EGLContext, EGLDisplay, EGLSurface, EGLenum};
+use crate::egl::types::EGLint;
 use crate::egl;
 use crate::Gl;
-use crate::gl::types::{GLint, GLuint};
+use crate::gl::types::{GLenum, GLint, GLsizei, GLsizeiptr, GLuint};
 use crate::gl;
 use crate::gl_utils;
+use crate::leak::{LeakTracker, LeakedObject, Origin};
 use crate::platform::generic::egl::error::ToWindowingApiError;
 use crate::platform::generic::egl::ffi::EGLClientBuffer;
 use crate::platform::generic::egl::ffi::EGLImageKHR;
 use crate::platform::generic::egl::ffi::EGL_EXTENSION_FUNCTIONS;
 use crate::platform::generic::egl::ffi::EGL_GL_TEXTURE_2D_KHR;
+use crate::platform::generic::egl::ffi::{EGL_GL_COLORSPACE_BT2020_PQ_EXT, EGL_GL_COLORSPACE_KHR};
+use crate::platform::generic::egl::ffi::EGL_GL_COLORSPACE_DISPLAY_P3_EXT;
+use crate::platform::generic::egl::ffi::{EGL_GL_COLORSPACE_LINEAR_KHR, EGL_GL_COLORSPACE_SRGB_KHR};
+use crate::platform::generic::egl::ffi::EGL_GL_COLORSPACE_SCRGB_LINEAR_EXT;
 use crate::platform::generic::egl::ffi::EGL_IMAGE_PRESERVED_KHR;
 use crate::platform::generic::egl::ffi::EGL_NO_IMAGE_KHR;
 use crate::renderbuffers::Renderbuffers;
-use crate::{ContextAttributes, ContextID, Error, SurfaceID, SurfaceInfo};
+use crate::surface;
+use crate::{ColorSpace, ContextAttributes, ContextID, Error, GLApi, GLInfo, GLVersion};
+use crate::{LayerStorage, SurfaceFormat, SurfaceID};
+use crate::{ContextAttributeFlags, DepthStencilAttributes, SurfaceAccess, SurfaceInfo};
+use crate::SurfaceTextureTarget;
+use crate::{WindowingApiError, WindowingApiFailure};
 use super::context::CurrentContextGuard;
-use super::device::EGL_FUNCTIONS;
+use super::device::{EGL_FUNCTIONS, display_supports_extension};
 
-use euclid::default::Size2D;
+use euclid::default::{Point2D, Rect, Size2D};
 use std::fmt::{self, Debug, Formatter};
 use std::marker::PhantomData;
 use std::mem;
 use std::os::raw::c_void;
 use std::ptr;
+use std::sync::Arc;
+
+#[cfg(unix)]
+use crate::ipc::{DmaBufImage, DmaBufPlane, MAX_DMA_BUF_PLANES, SharedMemoryImage};
+#[cfg(unix)]
+use crate::ipc::{SharedMemoryMapping, SurfaceHandle};
+#[cfg(unix)]
+use crate::platform::generic::egl::ffi::{DRM_FORMAT_MOD_INVALID, EGL_DMA_BUF_PLANE_ATTRIBUTES};
+#[cfg(unix)]
+use crate::platform::generic::egl::ffi::{EGL_LINUX_DMA_BUF_EXT, EGL_LINUX_DRM_FOURCC_EXT};
+#[cfg(unix)]
+use crate::platform::generic::egl::ffi::{EGL_ITU_REC2020_EXT, EGL_ITU_REC601_EXT};
+#[cfg(unix)]
+use crate::platform::generic::egl::ffi::{EGL_ITU_REC709_EXT, EGL_SAMPLE_RANGE_HINT_EXT};
+#[cfg(unix)]
+use crate::platform::generic::egl::ffi::{EGL_YUV_COLOR_SPACE_HINT_EXT, EGL_YUV_FULL_RANGE_EXT};
+#[cfg(unix)]
+use crate::platform::generic::egl::ffi::EGL_YUV_NARROW_RANGE_EXT;
+#[cfg(unix)]
+use crate::video::{PlanarFormat, PlanarImage, YUVColorSpace, YUVRange};
+#[cfg(unix)]
+use crate::GL_TEXTURE_EXTERNAL_OES;
+
+// `GL_BGRA` isn't part of the OpenGL ES bindings, but it has the same value as `GL_BGRA_EXT`.
+const GL_BGRA: GLenum = 0x80e1;
+
+/// A texture or EGL image created outside `surfman`, to be adopted as a surface with
+/// `Device::create_surface_from_native_image()`.
+/// 
+/// The surface doesn't take ownership of the object, which must outlive it.
+#[derive(Clone, Copy, Debug)]
+pub enum NativeImage {
+    /// A `GL_TEXTURE_2D` texture object belonging to the context that adopts it.
+    /// 
+    /// Level 0 of the texture must already have storage allocated in a color-renderable format.
+    Texture {
+        /// The OpenGL texture object.
+        texture_object: GLuint,
+        /// The size of level 0 of the texture.
+        size: Size2D<i32>,
+    },
+    /// An EGL image created on the device's EGL display.
+    EGLImage {
+        /// The EGL image.
+        egl_image: EGLImageKHR,
+        /// The size of the image.
+        size: Size2D<i32>,
+    },
+}
 
 #[allow(dead_code)]
 #[derive(Clone)]
@@ -37,8 +97,11 @@ pub(crate) struct ExternalEGLSurfaces {
 pub struct EGLBackedSurface {
     pub(crate) context_id: ContextID,
     pub(crate) size: Size2D<i32>,
+    // The number of layers, laid out side by side unless the objects are a `TextureArray`.
+    pub(crate) layers: u32,
     pub(crate) objects: EGLSurfaceObjects,
     pub(crate) destroyed: bool,
+    origin: Origin,
 }
 
 impl Debug for EGLBackedSurface {
@@ -49,6 +112,36 @@ impl Debug for EGLBackedSurface {
 
 unsafe impl Send for EGLBackedSurface {}
 
+impl Drop for EGLBackedSurface {
+    fn drop(&mut self) {
+        if self.destroyed {
+            return;
+        }
+
+        // Leave behind a window with no surface, which owns nothing.
+        let objects = mem::replace(&mut self.objects, EGLSurfaceObjects::Window {
+            native_window: ptr::null(),
+            egl_surface: egl::NO_SURFACE,
+        });
+        if !self.origin.report_leak(LeakedObject::Surface, true) {
+            // The objects are bare handles, so dropping them leaks what they name.
+            return;
+        }
+
+        let surface = EGLBackedSurface {
+            context_id: self.context_id,
+            size: self.size,
+            layers: self.layers,
+            objects,
+            destroyed: false,
+            origin: self.origin.take(),
+        };
+        if let Err(surface) = self.origin.defer(LeakedObject::Surface, self.context_id, surface) {
+            mem::forget(surface);
+        }
+    }
+}
+
 #[allow(dead_code)]
 pub(crate) enum EGLSurfaceObjects {
     TextureImage {
@@ -56,16 +149,57 @@ pub(crate) enum EGLSurfaceObjects {
         framebuffer_object: GLuint,
         texture_object: GLuint,
         renderbuffers: Renderbuffers,
+        // An image wrapping the depth texture, if the depth buffer is sampleable.
+        depth_egl_image: EGLImageKHR,
+        format: SurfaceFormat,
+        access: SurfaceAccess,
+        // Created on the first streamed upload or write mapping; zero until then.
+        pixel_unpack_buffer: GLuint,
+        // The texture or image that another library created, if this surface was adopted from
+        // one. Destroying the surface leaves that object alone.
+        adopted: Option<NativeImage>,
+    },
+    // A layered surface that renders to the layers of a `GL_TEXTURE_2D_ARRAY`. EGL can't make
+    // images of array layers, so each layer is copied into a 2D texture of its own when the
+    // surface is unbound, and other contexts sample those copies through images of them.
+    TextureArray {
+        texture_object: GLuint,
+        // The matching depth and stencil layers, or zero if the context has neither.
+        depth_stencil_texture_object: GLuint,
+        // One per layer, with the layer attached via `glFramebufferTextureLayer()`.
+        framebuffer_objects: Arc<[GLuint]>,
+        // Every layer attached as a `GL_OVR_multiview` view, or zero without the extension.
+        multiview_framebuffer_object: GLuint,
+        layer_texture_objects: Vec<GLuint>,
+        layer_egl_images: Vec<EGLImageKHR>,
+        // Framebuffers wrapping `layer_texture_objects`, which the layers are blitted into.
+        layer_framebuffer_objects: Vec<GLuint>,
     },
     Window {
         native_window: *const c_void,
         egl_surface: EGLSurface,
     },
+    // A surface imported from another process. It can be sampled from but not rendered to.
+    //
+    // `texture_object` is nonzero if the pixels were uploaded from shared memory.
+    // `texture_target` is the target that surface textures wrapping the image are bound to.
+    Imported {
+        egl_image: EGLImageKHR,
+        texture_object: GLuint,
+        texture_target: GLenum,
+    },
 }
 
 pub(crate) struct EGLSurfaceTexture {
     pub(crate) surface: EGLBackedSurface,
     pub(crate) texture_object: GLuint,
+    // The target that `texture_object` is bound to.
+    pub(crate) target: GLenum,
+    // Zero if the surface has no sampleable depth buffer.
+    pub(crate) depth_texture_object: GLuint,
+    // One texture per layer if the surface's layers are an array texture, the first of which is
+    // `texture_object`. Empty otherwise.
+    pub(crate) layer_texture_objects: Vec<GLuint>,
     pub(crate) phantom: PhantomData<*const ()>,
 }
 
@@ -81,36 +215,61 @@ impl EGLBackedSurface {
                               egl_context: EGLContext,
                               context_id: ContextID,
                               context_attributes: &ContextAttributes,
-                              size: &Size2D<i32>)
-                              -> EGLBackedSurface {
-        let egl_image_attribs = [
-            EGL_IMAGE_PRESERVED_KHR as EGLint,  egl::FALSE as EGLint,
-            egl::NONE as EGLint,                0,
-        ];
-
+                              size: &Size2D<i32>,
+                              access: SurfaceAccess,
+                              format: SurfaceFormat,
+                              depth_stencil: Option<DepthStencilAttributes>,
+                              layers: u32,
+                              origin: Origin)
+                              -> Result<EGLBackedSurface, Error> {
         unsafe {
+            // Allocate depth and stencil buffers first, so that we have nothing else to clean up
+            // if the depth texture can't be shared.
+            let mut renderbuffers = Renderbuffers::new(gl, size, context_attributes, depth_stencil);
+            let depth_egl_image = match renderbuffers.depth_texture() {
+                None => EGL_NO_IMAGE_KHR,
+                Some(depth_texture) => {
+                    let depth_egl_image = create_egl_image_from_gl_texture(egl_display,
+                                                                           egl_context,
+                                                                           depth_texture);
+                    if depth_egl_image == EGL_NO_IMAGE_KHR {
+                        renderbuffers.destroy(gl);
+                        return Err(Error::UnsupportedDepthStencilFormat);
+                    }
+                    depth_egl_image
+                }
+            };
+
             // Create our texture.
+            let (internal_format, gl_format, gl_type) = gl_texture_formats(format);
             let mut texture_object = 0;
             gl.GenTextures(1, &mut texture_object);
             gl.BindTexture(gl::TEXTURE_2D, texture_object);
             gl.TexImage2D(gl::TEXTURE_2D,
                           0,
-                          gl::RGBA as GLint,
+                          internal_format as GLint,
                           size.width,
                           size.height,
                           0,
-                          gl::RGBA,
-                          gl::UNSIGNED_BYTE,
+                          gl_format,
+                          gl_type,
                           ptr::null());
 
             // Create our image.
-            let egl_client_buffer = texture_object as usize as EGLClientBuffer;
-            let egl_image =
-                (EGL_EXTENSION_FUNCTIONS.CreateImageKHR)(egl_display,
-                                                         egl_context,
-                                                         EGL_GL_TEXTURE_2D_KHR,
-                                                         egl_client_buffer,
-                                                         egl_image_attribs.as_ptr());
+            let egl_image = create_egl_image_from_gl_texture(egl_display,
+                                                             egl_context,
+                                                             texture_object);
+            if egl_image == EGL_NO_IMAGE_KHR {
+                let failure = EGL_FUNCTIONS.with(|egl| {
+                    egl.GetError().to_windowing_api_failure("eglCreateImageKHR")
+                });
+                gl.DeleteTextures(1, &texture_object);
+                if depth_egl_image != EGL_NO_IMAGE_KHR {
+                    (EGL_EXTENSION_FUNCTIONS.DestroyImageKHR)(egl_display, depth_egl_image);
+                }
+                renderbuffers.destroy(gl);
+                return Err(Error::SurfaceCreationFailed(Box::new(failure)));
+            }
 
             // Create the framebuffer, and bind the texture to it.
             let framebuffer_object = gl_utils::create_and_bind_framebuffer(gl,
@@ -118,46 +277,274 @@ impl EGLBackedSurface {
                                                                            texture_object);
 
             // Bind renderbuffers as appropriate.
-            let renderbuffers = Renderbuffers::new(gl, size, context_attributes);
             renderbuffers.bind_to_current_framebuffer(gl);
+            let framebuffer_status = gl.CheckFramebufferStatus(gl::FRAMEBUFFER);
 
-            debug_assert_eq!(gl.CheckFramebufferStatus(gl::FRAMEBUFFER), gl::FRAMEBUFFER_COMPLETE);
-
-            EGLBackedSurface {
+            let mut surface = EGLBackedSurface {
                 context_id,
                 size: *size,
+                layers,
                 objects: EGLSurfaceObjects::TextureImage {
                     egl_image,
                     framebuffer_object,
                     texture_object,
                     renderbuffers,
+                    depth_egl_image,
+                    format,
+                    access,
+                    pixel_unpack_buffer: 0,
+                    adopted: None,
                 },
                 destroyed: false,
+                origin,
+            };
+
+            // The format was checked up front, so an incomplete framebuffer means the driver
+            // couldn't allocate the storage.
+            if framebuffer_status != gl::FRAMEBUFFER_COMPLETE {
+                surface.destroy(gl, egl_display, context_id)?;
+                let failure = WindowingApiFailure::new("glCheckFramebufferStatus",
+                                                       WindowingApiError::BadAlloc,
+                                                       Some(framebuffer_status as i32));
+                return Err(Error::SurfaceCreationFailed(Box::new(failure)));
             }
+            Ok(surface)
         }
     }
 
+    // Creates a layered surface backed by a `GL_TEXTURE_2D_ARRAY`. The context that the surface
+    // is being created for must be current and support array textures.
+    pub(crate) fn new_layered(gl: &Gl,
+                              gl_info: &GLInfo,
+                              egl_display: EGLDisplay,
+                              egl_context: EGLContext,
+                              context_id: ContextID,
+                              context_attributes: &ContextAttributes,
+                              size: &Size2D<i32>,
+                              layers: u32,
+                              origin: Origin)
+                              -> Result<EGLBackedSurface, Error> {
+        unsafe {
+            let mut max_layers = 0;
+            gl.GetIntegerv(gl::MAX_ARRAY_TEXTURE_LAYERS, &mut max_layers);
+            if layers == 0 || layers > max_layers as u32 {
+                return Err(Error::UnsupportedLayerCount);
+            }
+
+            let (internal_format, gl_format, gl_type) = gl_texture_formats(SurfaceFormat::RGBA8);
+            let texture_object =
+                create_array_texture(gl, size, layers, internal_format, gl_format, gl_type);
+            let depth_stencil_flags = ContextAttributeFlags::DEPTH | ContextAttributeFlags::STENCIL;
+            let depth_stencil_texture_object =
+                if context_attributes.flags.intersects(depth_stencil_flags) {
+                    create_array_texture(gl,
+                                         size,
+                                         layers,
+                                         gl::DEPTH24_STENCIL8,
+                                         gl::DEPTH_STENCIL,
+                                         gl::UNSIGNED_INT_24_8)
+                } else {
+                    0
+                };
+
+            // Keep the first incomplete framebuffer's status, if any, to report once everything
+            // is in the surface and can be destroyed together.
+            let mut framebuffer_status = gl::FRAMEBUFFER_COMPLETE;
+            let mut framebuffer_objects = Vec::with_capacity(layers as usize);
+            for layer in 0..layers {
+                let mut framebuffer_object = 0;
+                gl.GenFramebuffers(1, &mut framebuffer_object);
+                gl.BindFramebuffer(gl::FRAMEBUFFER, framebuffer_object);
+                gl.FramebufferTextureLayer(gl::FRAMEBUFFER,
+                                           gl::COLOR_ATTACHMENT0,
+                                           texture_object,
+                                           0,
+                                           layer as GLint);
+                if depth_stencil_texture_object != 0 {
+                    gl.FramebufferTextureLayer(gl::FRAMEBUFFER,
+                                               gl::DEPTH_STENCIL_ATTACHMENT,
+                                               depth_stencil_texture_object,
+                                               0,
+                                               layer as GLint);
+                }
+                if framebuffer_status == gl::FRAMEBUFFER_COMPLETE {
+                    framebuffer_status = gl.CheckFramebufferStatus(gl::FRAMEBUFFER);
+                }
+                framebuffer_objects.push(framebuffer_object);
+            }
+
+            let multiview_framebuffer_object =
+                create_multiview_framebuffer(gl,
+                                             gl_info,
+                                             texture_object,
+                                             depth_stencil_texture_object,
+                                             layers);
+
+            let mut layer_failure = None;
+            let (mut layer_texture_objects, mut layer_egl_images) = (vec![], vec![]);
+            let mut layer_framebuffer_objects = vec![];
+            for _ in 0..layers {
+                let mut layer_texture_object = 0;
+                gl.GenTextures(1, &mut layer_texture_object);
+                gl.BindTexture(gl::TEXTURE_2D, layer_texture_object);
+                gl.TexImage2D(gl::TEXTURE_2D,
+                              0,
+                              internal_format as GLint,
+                              size.width,
+                              size.height,
+                              0,
+                              gl_format,
+                              gl_type,
+                              ptr::null());
+                let layer_egl_image = create_egl_image_from_gl_texture(egl_display,
+                                                                       egl_context,
+                                                                       layer_texture_object);
+                if layer_egl_image == EGL_NO_IMAGE_KHR && layer_failure.is_none() {
+                    layer_failure = Some(EGL_FUNCTIONS.with(|egl| {
+                        egl.GetError().to_windowing_api_failure("eglCreateImageKHR")
+                    }));
+                }
+                let layer_framebuffer_object =
+                    gl_utils::create_and_bind_framebuffer(gl, gl::TEXTURE_2D, layer_texture_object);
+                layer_framebuffer_objects.push(layer_framebuffer_object);
+                layer_texture_objects.push(layer_texture_object);
+                layer_egl_images.push(layer_egl_image);
+            }
+            gl.BindTexture(gl::TEXTURE_2D, 0);
+            gl.BindFramebuffer(gl::FRAMEBUFFER, framebuffer_objects[0]);
+
+            let mut surface = EGLBackedSurface {
+                context_id,
+                size: *size,
+                layers,
+                objects: EGLSurfaceObjects::TextureArray {
+                    texture_object,
+                    depth_stencil_texture_object,
+                    framebuffer_objects: Arc::from(framebuffer_objects),
+                    multiview_framebuffer_object,
+                    layer_texture_objects,
+                    layer_egl_images,
+                    layer_framebuffer_objects,
+                },
+                destroyed: false,
+                origin,
+            };
+
+            if let Some(failure) = layer_failure {
+                surface.destroy(gl, egl_display, context_id)?;
+                return Err(Error::SurfaceCreationFailed(Box::new(failure)));
+            }
+            if framebuffer_status != gl::FRAMEBUFFER_COMPLETE {
+                surface.destroy(gl, egl_display, context_id)?;
+                let failure = WindowingApiFailure::new("glCheckFramebufferStatus",
+                                                       WindowingApiError::BadAlloc,
+                                                       Some(framebuffer_status as i32));
+                return Err(Error::SurfaceCreationFailed(Box::new(failure)));
+            }
+            Ok(surface)
+        }
+    }
+
+    // The context that the surface is being created for must be current.
+    pub(crate) unsafe fn from_native_image(gl: &Gl,
+                                           egl_display: EGLDisplay,
+                                           egl_context: EGLContext,
+                                           context_id: ContextID,
+                                           context_attributes: &ContextAttributes,
+                                           native_image: NativeImage,
+                                           origin: Origin)
+                                           -> Result<EGLBackedSurface, Error> {
+        // Pair the adopted object with the one we create ourselves: an image so that other
+        // contexts can sample the texture, or a texture so that this context can render to the
+        // image.
+        let (egl_image, texture_object, size) = match native_image {
+            NativeImage::Texture { texture_object, size } => {
+                let egl_image = create_egl_image_from_gl_texture(egl_display,
+                                                                 egl_context,
+                                                                 texture_object);
+                if egl_image == EGL_NO_IMAGE_KHR {
+                    let failure = EGL_FUNCTIONS.with(|egl| {
+                        egl.GetError().to_windowing_api_failure("eglCreateImageKHR")
+                    });
+                    return Err(Error::SurfaceCreationFailed(Box::new(failure)));
+                }
+                (egl_image, texture_object, size)
+            }
+            NativeImage::EGLImage { egl_image, size } => {
+                let texture_object = bind_egl_image_to_gl_texture(gl, egl_image, gl::TEXTURE_2D);
+                (egl_image, texture_object, size)
+            }
+        };
+
+        let framebuffer_object = gl_utils::create_and_bind_framebuffer(gl,
+                                                                       gl::TEXTURE_2D,
+                                                                       texture_object);
+        let renderbuffers = Renderbuffers::new(gl, &size, context_attributes, None);
+        renderbuffers.bind_to_current_framebuffer(gl);
+        let framebuffer_status = gl.CheckFramebufferStatus(gl::FRAMEBUFFER);
+
+        // We don't know the format of the image, so the CPU can't upload to it.
+        let mut surface = EGLBackedSurface {
+            context_id,
+            size,
+            layers: 1,
+            objects: EGLSurfaceObjects::TextureImage {
+                egl_image,
+                framebuffer_object,
+                texture_object,
+                renderbuffers,
+                depth_egl_image: EGL_NO_IMAGE_KHR,
+                format: SurfaceFormat::RGBA8,
+                access: SurfaceAccess::GPUOnly,
+                pixel_unpack_buffer: 0,
+                adopted: Some(native_image),
+            },
+            destroyed: false,
+            origin,
+        };
+
+        if framebuffer_status != gl::FRAMEBUFFER_COMPLETE {
+            surface.destroy(gl, egl_display, context_id)?;
+            return Err(Error::UnsupportedSurfaceFormat);
+        }
+        Ok(surface)
+    }
+
     pub(crate) fn new_window(egl_display: EGLDisplay,
                              egl_config: EGLConfig,
                              native_window: *mut c_void,
                              context_id: ContextID,
-                             size: &Size2D<i32>)
-                             -> EGLBackedSurface {
+                             size: &Size2D<i32>,
+                             color_space: Option<ColorSpace>,
+                             origin: Origin)
+                             -> Result<EGLBackedSurface, Error> {
         EGL_FUNCTIONS.with(|egl| {
             unsafe {
-                let window_surface_attribs = [egl::NONE as EGLAttrib];
+                let mut window_surface_attribs = vec![];
+                if let Some(color_space) = egl_color_space(egl_display, color_space)? {
+                    window_surface_attribs.push(EGL_GL_COLORSPACE_KHR as EGLAttrib);
+                    window_surface_attribs.push(color_space as EGLAttrib);
+                }
+                window_surface_attribs.push(egl::NONE as EGLAttrib);
+
                 let egl_surface = egl.CreatePlatformWindowSurface(egl_display,
                                                                   egl_config,
                                                                   native_window,
                                                                   window_surface_attribs.as_ptr());
-                assert_ne!(egl_surface, egl::NO_SURFACE);
+                if egl_surface == egl::NO_SURFACE {
+                    let failure =
+                        egl.GetError().to_windowing_api_failure("eglCreatePlatformWindowSurface");
+                    return Err(Error::SurfaceCreationFailed(Box::new(failure)));
+                }
 
-                EGLBackedSurface {
+                Ok(EGLBackedSurface {
                     context_id,
                     size: *size,
+                    layers: 1,
                     objects: EGLSurfaceObjects::Window { native_window, egl_surface },
                     destroyed: false,
-                }
+                    origin,
+                })
             }
         })
     }
@@ -165,12 +552,42 @@ impl EGLBackedSurface {
     pub(crate) fn to_surface_texture(self, gl: &Gl)
                                      -> Result<EGLSurfaceTexture, (Error, EGLBackedSurface)> {
         unsafe {
-            let egl_image = match self.objects {
-                EGLSurfaceObjects::TextureImage { egl_image, .. } => egl_image,
+            let (egl_image, depth_egl_image, target) = match self.objects {
+                EGLSurfaceObjects::TextureImage { egl_image, depth_egl_image, .. } => {
+                    (egl_image, depth_egl_image, gl::TEXTURE_2D)
+                }
+                EGLSurfaceObjects::TextureArray { ref layer_egl_images, .. } => {
+                    let layer_texture_objects: Vec<_> = layer_egl_images.iter().map(|&egl_image| {
+                        bind_egl_image_to_gl_texture(gl, egl_image, gl::TEXTURE_2D)
+                    }).collect();
+                    return Ok(EGLSurfaceTexture {
+                        surface: self,
+                        texture_object: layer_texture_objects[0],
+                        target: gl::TEXTURE_2D,
+                        depth_texture_object: 0,
+                        layer_texture_objects,
+                        phantom: PhantomData,
+                    });
+                }
+                EGLSurfaceObjects::Imported { egl_image, texture_target, .. } => {
+                    (egl_image, EGL_NO_IMAGE_KHR, texture_target)
+                }
                 EGLSurfaceObjects::Window { .. } => return Err((Error::WidgetAttached, self)),
             };
-            let texture_object = bind_egl_image_to_gl_texture(gl, egl_image);
-            Ok(EGLSurfaceTexture { surface: self, texture_object, phantom: PhantomData })
+            let texture_object = bind_egl_image_to_gl_texture(gl, egl_image, target);
+            let depth_texture_object = if depth_egl_image != EGL_NO_IMAGE_KHR {
+                bind_depth_egl_image_to_gl_texture(gl, depth_egl_image)
+            } else {
+                0
+            };
+            Ok(EGLSurfaceTexture {
+                surface: self,
+                texture_object,
+                target,
+                depth_texture_object,
+                layer_texture_objects: vec![],
+                phantom: PhantomData,
+            })
         }
     }
 
@@ -187,17 +604,80 @@ impl EGLBackedSurface {
                     ref mut framebuffer_object,
                     ref mut texture_object,
                     ref mut renderbuffers,
+                    ref mut depth_egl_image,
+                    ref mut pixel_unpack_buffer,
+                    adopted,
+                    ..
                 } => {
                     gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
                     gl.DeleteFramebuffers(1, framebuffer_object);
                     *framebuffer_object = 0;
+
+                    if *pixel_unpack_buffer != 0 {
+                        gl.DeleteBuffers(1, pixel_unpack_buffer);
+                        *pixel_unpack_buffer = 0;
+                    }
+
+                    if *depth_egl_image != EGL_NO_IMAGE_KHR {
+                        let result = (EGL_EXTENSION_FUNCTIONS.DestroyImageKHR)(egl_display,
+                                                                               *depth_egl_image);
+                        assert_ne!(result, egl::FALSE);
+                        *depth_egl_image = EGL_NO_IMAGE_KHR;
+                    }
                     renderbuffers.destroy(gl);
 
-                    let result = (EGL_EXTENSION_FUNCTIONS.DestroyImageKHR)(egl_display,
-                                                                           *egl_image);
-                    assert_ne!(result, egl::FALSE);
+                    match adopted {
+                        Some(NativeImage::EGLImage { .. }) => {}
+                        Some(NativeImage::Texture { .. }) | None => {
+                            let result = (EGL_EXTENSION_FUNCTIONS.DestroyImageKHR)(egl_display,
+                                                                                   *egl_image);
+                            assert_ne!(result, egl::FALSE);
+                        }
+                    }
                     *egl_image = EGL_NO_IMAGE_KHR;
 
+                    match adopted {
+                        Some(NativeImage::Texture { .. }) => {}
+                        Some(NativeImage::EGLImage { .. }) | None => {
+                            gl.DeleteTextures(1, texture_object);
+                        }
+                    }
+                    *texture_object = 0;
+
+                    self.destroyed = true;
+                    Ok(None)
+                }
+                EGLSurfaceObjects::TextureArray {
+                    ref mut texture_object,
+                    ref mut depth_stencil_texture_object,
+                    ref framebuffer_objects,
+                    ref mut multiview_framebuffer_object,
+                    ref mut layer_texture_objects,
+                    ref mut layer_egl_images,
+                    ref mut layer_framebuffer_objects,
+                } => {
+                    gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+                    gl.DeleteFramebuffers(framebuffer_objects.len() as GLsizei,
+                                          framebuffer_objects.as_ptr());
+                    gl.DeleteFramebuffers(1, multiview_framebuffer_object);
+                    *multiview_framebuffer_object = 0;
+                    gl.DeleteFramebuffers(layer_framebuffer_objects.len() as GLsizei,
+                                          layer_framebuffer_objects.as_ptr());
+                    layer_framebuffer_objects.clear();
+
+                    for layer_egl_image in layer_egl_images.drain(..) {
+                        if layer_egl_image != EGL_NO_IMAGE_KHR {
+                            let result = (EGL_EXTENSION_FUNCTIONS.DestroyImageKHR)(egl_display,
+                                                                                   layer_egl_image);
+                            assert_ne!(result, egl::FALSE);
+                        }
+                    }
+                    gl.DeleteTextures(layer_texture_objects.len() as GLsizei,
+                                      layer_texture_objects.as_ptr());
+                    layer_texture_objects.clear();
+
+                    gl.DeleteTextures(1, depth_stencil_texture_object);
+                    *depth_stencil_texture_object = 0;
                     gl.DeleteTextures(1, texture_object);
                     *texture_object = 0;
 
@@ -213,6 +693,20 @@ impl EGLBackedSurface {
                     self.destroyed = true;
                     Ok(Some(mem::replace(native_window, ptr::null())))
                 }
+                EGLSurfaceObjects::Imported { ref mut egl_image, ref mut texture_object, .. } => {
+                    let result = (EGL_EXTENSION_FUNCTIONS.DestroyImageKHR)(egl_display,
+                                                                           *egl_image);
+                    assert_ne!(result, egl::FALSE);
+                    *egl_image = EGL_NO_IMAGE_KHR;
+
+                    if *texture_object != 0 {
+                        gl.DeleteTextures(1, texture_object);
+                        *texture_object = 0;
+                    }
+
+                    self.destroyed = true;
+                    Ok(None)
+                }
             }
         }
     }
@@ -234,30 +728,206 @@ impl EGLBackedSurface {
                         if ok != egl::FALSE {
                             Ok(())
                         } else {
-                            Err(Error::PresentFailed(egl.GetError().to_windowing_api_error()))
+                            let failure = egl.GetError().to_windowing_api_failure("eglSwapBuffers");
+                            Err(Error::PresentFailed(Box::new(failure)))
                         }
                     })
                 }
-                EGLSurfaceObjects::TextureImage { .. } => Err(Error::NoWidgetAttached),
+                EGLSurfaceObjects::TextureImage { .. } |
+                EGLSurfaceObjects::TextureArray { .. } |
+                EGLSurfaceObjects::Imported { .. } => Err(Error::NoWidgetAttached),
+            }
+        }
+    }
+
+    pub(crate) fn upload_data(&mut self,
+                              gl: &Gl,
+                              gl_info: &GLInfo,
+                              context_id: ContextID,
+                              rect: &Rect<i32>,
+                              data: &[u8],
+                              stride: usize,
+                              format: SurfaceFormat)
+                              -> Result<(), Error> {
+        if context_id != self.context_id {
+            return Err(Error::IncompatibleSurface);
+        }
+
+        match self.objects {
+            EGLSurfaceObjects::TextureImage {
+                texture_object,
+                format: surface_format,
+                access,
+                ref mut pixel_unpack_buffer,
+                ..
+            } => {
+                if !access.cpu_access_allowed() {
+                    return Err(Error::SurfaceDataInaccessible);
+                }
+                if format != surface_format {
+                    return Err(Error::UnsupportedSurfaceFormat);
+                }
+                surface::check_upload_region(&self.size, rect, data.len(), stride, format)?;
+
+                let pixel_unpack_buffer = match access {
+                    SurfaceAccess::GPUCPUWriteCombined => Some(pixel_unpack_buffer),
+                    SurfaceAccess::GPUOnly | SurfaceAccess::GPUCPU => None,
+                };
+                unsafe {
+                    upload_texture_data(gl,
+                                        gl_info,
+                                        texture_object,
+                                        pixel_unpack_buffer,
+                                        rect,
+                                        data,
+                                        stride,
+                                        format)
+                }
+            }
+            // Array-backed surfaces are only created for `SurfaceAccess::GPUOnly`.
+            EGLSurfaceObjects::TextureArray { .. } => Err(Error::SurfaceDataInaccessible),
+            EGLSurfaceObjects::Window { .. } => Err(Error::WidgetAttached),
+            EGLSurfaceObjects::Imported { .. } => Err(Error::IncompatibleSurface),
+        }
+    }
+
+    // Maps storage for the whole surface for writing. Returns the pointer, stride, and length of
+    // the mapping. The written pixels reach the surface when `unmap_data()` is called.
+    #[cfg(unix)]
+    pub(crate) fn map_data(&mut self, gl: &Gl, gl_info: &GLInfo, context_id: ContextID)
+                           -> Result<(*mut u8, usize, usize), Error> {
+        if context_id != self.context_id {
+            return Err(Error::IncompatibleSurface);
+        }
+        if !supports_pixel_unpack_buffers(gl_info) {
+            return Err(Error::UnsupportedOnThisPlatform);
+        }
+
+        match self.objects {
+            EGLSurfaceObjects::TextureImage {
+                format,
+                access,
+                ref mut pixel_unpack_buffer,
+                ..
+            } => {
+                if !access.cpu_access_allowed() {
+                    return Err(Error::SurfaceDataInaccessible);
+                }
+                let stride = self.size.width as usize * format.bytes_per_pixel();
+                let len = stride * self.size.height as usize;
+                unsafe {
+                    let ptr = map_pixel_unpack_buffer(gl, pixel_unpack_buffer, len)?;
+                    Ok((ptr, stride, len))
+                }
+            }
+            // Array-backed surfaces are only created for `SurfaceAccess::GPUOnly`.
+            EGLSurfaceObjects::TextureArray { .. } => Err(Error::SurfaceDataInaccessible),
+            EGLSurfaceObjects::Window { .. } => Err(Error::WidgetAttached),
+            EGLSurfaceObjects::Imported { .. } => Err(Error::IncompatibleSurface),
+        }
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn unmap_data(&mut self, gl: &Gl) {
+        if let EGLSurfaceObjects::TextureImage {
+            texture_object,
+            format,
+            pixel_unpack_buffer,
+            ..
+        } = self.objects {
+            unsafe {
+                gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, pixel_unpack_buffer);
+                gl.UnmapBuffer(gl::PIXEL_UNPACK_BUFFER);
+                let rect = Rect::new(Point2D::zero(), self.size);
+                tex_sub_image_from_pixel_unpack_buffer(gl, texture_object, &rect, format);
+                gl.Flush();
             }
         }
     }
 
     pub(crate) fn info(&self) -> SurfaceInfo {
+        let (framebuffer_object, layer_storage) = match self.objects {
+            EGLSurfaceObjects::TextureImage { framebuffer_object, .. } => {
+                (framebuffer_object, LayerStorage::Atlas)
+            }
+            EGLSurfaceObjects::TextureArray {
+                ref framebuffer_objects,
+                multiview_framebuffer_object,
+                ..
+            } => {
+                let layer_storage = LayerStorage::Array {
+                    framebuffer_objects: framebuffer_objects.clone(),
+                    multiview_framebuffer_object,
+                };
+                (framebuffer_objects.first().cloned().unwrap_or(0), layer_storage)
+            }
+            EGLSurfaceObjects::Window { .. } | EGLSurfaceObjects::Imported { .. } => {
+                (0, LayerStorage::Atlas)
+            }
+        };
         SurfaceInfo {
             size: self.size,
             id: self.id(),
             context_id: self.context_id,
-            framebuffer_object: match self.objects {
-                EGLSurfaceObjects::TextureImage { framebuffer_object, .. } => framebuffer_object,
-                EGLSurfaceObjects::Window { .. } => 0,
-            },
+            framebuffer_object,
+            layers: self.layers,
+            layer_storage,
+        }
+    }
+
+    // Copies each layer of an array-backed surface into the texture that other contexts sample
+    // it through. The surface's context must be current.
+    pub(crate) fn copy_layers(&self, gl: &Gl) {
+        let (framebuffer_objects, layer_framebuffer_objects) = match self.objects {
+            EGLSurfaceObjects::TextureArray {
+                ref framebuffer_objects,
+                ref layer_framebuffer_objects,
+                ..
+            } => (framebuffer_objects, layer_framebuffer_objects),
+            EGLSurfaceObjects::TextureImage { .. } |
+            EGLSurfaceObjects::Window { .. } |
+            EGLSurfaceObjects::Imported { .. } => return,
+        };
+
+        unsafe {
+            let (mut old_draw_framebuffer, mut old_read_framebuffer) = (0, 0);
+            gl.GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut old_draw_framebuffer);
+            gl.GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut old_read_framebuffer);
+            let scissor_test_enabled = gl.IsEnabled(gl::SCISSOR_TEST) != gl::FALSE;
+            gl.Disable(gl::SCISSOR_TEST);
+
+            for (&framebuffer_object, &layer_framebuffer_object) in
+                    framebuffer_objects.iter().zip(layer_framebuffer_objects.iter()) {
+                gl.BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer_object);
+                gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, layer_framebuffer_object);
+                gl.BlitFramebuffer(0,
+                                   0,
+                                   self.size.width,
+                                   self.size.height,
+                                   0,
+                                   0,
+                                   self.size.width,
+                                   self.size.height,
+                                   gl::COLOR_BUFFER_BIT,
+                                   gl::NEAREST);
+            }
+
+            if scissor_test_enabled {
+                gl.Enable(gl::SCISSOR_TEST);
+            }
+            gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, old_draw_framebuffer as GLuint);
+            gl.BindFramebuffer(gl::READ_FRAMEBUFFER, old_read_framebuffer as GLuint);
+            gl.Flush();
         }
     }
 
     pub(crate) fn id(&self) -> SurfaceID {
         match self.objects {
-            EGLSurfaceObjects::TextureImage { egl_image, .. } => SurfaceID(egl_image as usize),
+            EGLSurfaceObjects::TextureImage { egl_image, .. } |
+            EGLSurfaceObjects::Imported { egl_image, .. } => SurfaceID(egl_image as usize),
+            EGLSurfaceObjects::TextureArray { ref layer_egl_images, .. } => {
+                SurfaceID(layer_egl_images.first().cloned().unwrap_or(EGL_NO_IMAGE_KHR) as usize)
+            }
             EGLSurfaceObjects::Window { egl_surface, .. } => SurfaceID(egl_surface as usize),
         }
     }
@@ -276,7 +946,20 @@ impl EGLBackedSurface {
                     EGLSurfaceObjects::TextureImage { framebuffer_object, .. } => {
                         gl_utils::unbind_framebuffer_if_necessary(gl, framebuffer_object);
                     }
-                    EGLSurfaceObjects::Window { .. } => {}
+                    EGLSurfaceObjects::TextureArray {
+                        ref framebuffer_objects,
+                        multiview_framebuffer_object,
+                        ..
+                    } => {
+                        for &framebuffer_object in framebuffer_objects.iter() {
+                            gl_utils::unbind_framebuffer_if_necessary(gl, framebuffer_object);
+                        }
+                        if multiview_framebuffer_object != 0 {
+                            gl_utils::unbind_framebuffer_if_necessary(gl,
+                                                                      multiview_framebuffer_object);
+                        }
+                    }
+                    EGLSurfaceObjects::Window { .. } | EGLSurfaceObjects::Imported { .. } => {}
                 }
             })
         }
@@ -287,16 +970,148 @@ impl EGLBackedSurface {
             EGLSurfaceObjects::Window { egl_surface, .. } => {
                 ExternalEGLSurfaces { draw: egl_surface, read: egl_surface }
             }
-            EGLSurfaceObjects::TextureImage { .. } => ExternalEGLSurfaces::default(),
+            EGLSurfaceObjects::TextureImage { .. } |
+            EGLSurfaceObjects::TextureArray { .. } |
+            EGLSurfaceObjects::Imported { .. } => ExternalEGLSurfaces::default(),
+        }
+    }
+
+    /// Exports this surface so that another process can sample from it.
+    ///
+    /// The dma-buf backing the surface is exported if `EGL_MESA_image_dma_buf_export` is
+    /// available. Otherwise, the contents are read back into shared memory. The context that
+    /// this surface belongs to must be current.
+    #[cfg(unix)]
+    pub(crate) fn export(&self, gl: &Gl, egl_display: EGLDisplay)
+                         -> Result<SurfaceHandle, Error> {
+        let (egl_image, framebuffer_object, format) = match self.objects {
+            EGLSurfaceObjects::TextureImage { egl_image, framebuffer_object, format, .. } => {
+                (egl_image, framebuffer_object, format)
+            }
+            // Export the copy of the first layer, which is up to date while the surface is
+            // unbound.
+            EGLSurfaceObjects::TextureArray {
+                ref layer_egl_images,
+                ref layer_framebuffer_objects,
+                ..
+            } => (layer_egl_images[0], layer_framebuffer_objects[0], SurfaceFormat::RGBA8),
+            EGLSurfaceObjects::Window { .. } => return Err(Error::WidgetAttached),
+            EGLSurfaceObjects::Imported { .. } => return Err(Error::IncompatibleSurface),
+        };
+
+        unsafe {
+            export_surface_image(gl, egl_display, egl_image, framebuffer_object, &self.size, format)
         }
     }
 }
 
 impl EGLSurfaceTexture {
+    pub(crate) fn layer_object(&self, layer: u32) -> Option<GLuint> {
+        if self.layer_texture_objects.is_empty() {
+            self.layer_rect(layer).map(|_| self.texture_object)
+        } else {
+            self.layer_texture_objects.get(layer as usize).cloned()
+        }
+    }
+
+    pub(crate) fn layer_rect(&self, layer: u32) -> Option<Rect<i32>> {
+        self.surface.info().layer_rect(layer)
+    }
+
+    /// Wraps a surface exported from another process in a texture local to the current context.
+    #[cfg(unix)]
+    pub(crate) fn from_handle(gl: &Gl,
+                              egl_display: EGLDisplay,
+                              egl_context: EGLContext,
+                              context_id: ContextID,
+                              handle: &SurfaceHandle,
+                              target: SurfaceTextureTarget,
+                              origin: Origin)
+                              -> Result<EGLSurfaceTexture, Error> {
+        unsafe {
+            let gl_texture_target = imported_texture_target(egl_display, handle, target)?;
+            let (egl_image, texture_object) = import_surface_image(gl,
+                                                                   egl_display,
+                                                                   egl_context,
+                                                                   handle)?;
+            Ok(EGLSurfaceTexture::from_imported_image(gl,
+                                                      context_id,
+                                                      &handle.size(),
+                                                      egl_image,
+                                                      texture_object,
+                                                      gl_texture_target,
+                                                      origin))
+        }
+    }
+
+    /// Wraps a decoded video frame in a texture local to the current context.
+    #[cfg(unix)]
+    pub(crate) fn from_planes(gl: &Gl,
+                              egl_display: EGLDisplay,
+                              egl_context: EGLContext,
+                              context_id: ContextID,
+                              image: &PlanarImage,
+                              target: SurfaceTextureTarget,
+                              origin: Origin)
+                              -> Result<EGLSurfaceTexture, Error> {
+        unsafe {
+            let (egl_image, texture_object, gl_texture_target) =
+                import_planar_image(gl, egl_display, egl_context, image, target)?;
+            Ok(EGLSurfaceTexture::from_imported_image(gl,
+                                                      context_id,
+                                                      &image.size,
+                                                      egl_image,
+                                                      texture_object,
+                                                      gl_texture_target,
+                                                      origin))
+        }
+    }
+
+    #[cfg(unix)]
+    pub(crate) unsafe fn from_imported_image(gl: &Gl,
+                                             context_id: ContextID,
+                                             size: &Size2D<i32>,
+                                             egl_image: EGLImageKHR,
+                                             texture_object: GLuint,
+                                             target: GLenum,
+                                             origin: Origin)
+                                             -> EGLSurfaceTexture {
+        let surface = EGLBackedSurface {
+            context_id,
+            size: *size,
+            layers: 1,
+            objects: EGLSurfaceObjects::Imported {
+                egl_image,
+                texture_object,
+                texture_target: target,
+            },
+            destroyed: false,
+            origin,
+        };
+        EGLSurfaceTexture {
+            surface,
+            texture_object: bind_egl_image_to_gl_texture(gl, egl_image, target),
+            target,
+            depth_texture_object: 0,
+            layer_texture_objects: vec![],
+            phantom: PhantomData,
+        }
+    }
+
     pub(crate) fn destroy(mut self, gl: &Gl) -> EGLBackedSurface {
         unsafe {
-            gl.DeleteTextures(1, &self.texture_object);
+            if self.layer_texture_objects.is_empty() {
+                gl.DeleteTextures(1, &self.texture_object);
+            } else {
+                gl.DeleteTextures(self.layer_texture_objects.len() as GLsizei,
+                                  self.layer_texture_objects.as_ptr());
+                self.layer_texture_objects.clear();
+            }
             self.texture_object = 0;
+            if self.depth_texture_object != 0 {
+                gl.DeleteTextures(1, &self.depth_texture_object);
+                self.depth_texture_object = 0;
+            }
             self.surface
         }
     }
@@ -309,11 +1124,114 @@ impl Default for ExternalEGLSurfaces {
     }
 }
 
+/// Returns the value of `EGL_GL_COLORSPACE_KHR` that selects the given color space for a window
+/// surface, or `None` if the platform default should be used.
+/// 
+/// Returns an `UnsupportedColorSpace` error if the display lacks the extension the color space
+/// needs.
+pub(crate) unsafe fn egl_color_space(egl_display: EGLDisplay, color_space: Option<ColorSpace>)
+                                     -> Result<Option<EGLenum>, Error> {
+    let (value, extension) = match color_space {
+        None => return Ok(None),
+        Some(ColorSpace::SRGB) => (EGL_GL_COLORSPACE_SRGB_KHR, "EGL_KHR_gl_colorspace"),
+        Some(ColorSpace::Linear) => (EGL_GL_COLORSPACE_LINEAR_KHR, "EGL_KHR_gl_colorspace"),
+        Some(ColorSpace::DisplayP3) => {
+            (EGL_GL_COLORSPACE_DISPLAY_P3_EXT, "EGL_EXT_gl_colorspace_display_p3")
+        }
+        Some(ColorSpace::SCRGBLinear) => {
+            (EGL_GL_COLORSPACE_SCRGB_LINEAR_EXT, "EGL_EXT_gl_colorspace_scrgb_linear")
+        }
+        Some(ColorSpace::BT2020PQ) => {
+            (EGL_GL_COLORSPACE_BT2020_PQ_EXT, "EGL_EXT_gl_colorspace_bt2020_pq")
+        }
+    };
+
+    // All of the `EXT` color spaces are set through the attribute that `EGL_KHR_gl_colorspace`
+    // introduces.
+    if !display_supports_extension(egl_display, "EGL_KHR_gl_colorspace") ||
+            !display_supports_extension(egl_display, extension) {
+        return Err(Error::UnsupportedColorSpace);
+    }
+    Ok(Some(value))
+}
+
+// Destroys the surfaces belonging to a context that were dropped under `LeakPolicy::Defer`. The
+// context must be current. Returns the native windows that window surfaces were rendering to, for
+// backends that own them.
+pub(crate) fn destroy_deferred_surfaces(gl: &Gl,
+                                        egl_display: EGLDisplay,
+                                        leak_tracker: &LeakTracker,
+                                        context_id: ContextID)
+                                        -> Vec<*const c_void> {
+    let surfaces: Vec<EGLBackedSurface> = leak_tracker.take_deferred_surfaces(context_id);
+    surfaces.into_iter().filter_map(|mut surface| {
+        surface.destroy(gl, egl_display, context_id).ok().and_then(|native_window| native_window)
+    }).collect()
+}
+
+/// Returns the formats that `EGLBackedSurface::new_generic()` can allocate with a context of the
+/// given version and extensions.
+/// 
+/// The sized internal formats that `gl_texture_formats()` uses need OpenGL ES 3.0, or OpenGL 3.0
+/// or the equivalent extensions.
+///
+/// `BGRA8` textures are allocated from `GL_BGRA` data, which drivers such as Mesa store in that
+/// order, so they export as `DRM_FORMAT_ARGB8888` and can be handed to X servers and compositors
+/// that only accept that layout. OpenGL ES has no sized BGRA format, so it's only offered on
+/// OpenGL.
+pub(crate) fn supported_surface_formats(gl_info: &GLInfo) -> Vec<SurfaceFormat> {
+    let gl_3 = gl_info.version >= GLVersion::new(3, 0);
+    let extension = |name| gl_info.supports_extension(name);
+    let mut formats = vec![SurfaceFormat::RGBA8];
+    match gl_info.api {
+        GLApi::GL => {
+            formats.push(SurfaceFormat::BGRA8);
+            if gl_3 || extension("GL_EXT_texture_sRGB") {
+                formats.push(SurfaceFormat::SRGB8A8);
+            }
+            formats.push(SurfaceFormat::RGB10A2);
+            if gl_3 || extension("GL_ARB_texture_float") {
+                formats.push(SurfaceFormat::RGBA16F);
+            }
+            if gl_3 || extension("GL_ARB_texture_rg") {
+                formats.extend_from_slice(&[SurfaceFormat::R8, SurfaceFormat::RG8]);
+            }
+        }
+        GLApi::GLES if gl_3 => {
+            formats.extend_from_slice(&[SurfaceFormat::SRGB8A8, SurfaceFormat::RGB10A2]);
+            if gl_info.version >= GLVersion::new(3, 2) ||
+                    extension("GL_EXT_color_buffer_float") ||
+                    extension("GL_EXT_color_buffer_half_float") {
+                formats.push(SurfaceFormat::RGBA16F);
+            }
+            formats.extend_from_slice(&[SurfaceFormat::R8, SurfaceFormat::RG8]);
+        }
+        GLApi::GLES => {}
+    }
+    formats
+}
+
+// The internal format, format, and type to pass to `glTexImage2D()` to allocate storage of the
+// given format.
+//
+// `RGBA8` uses an unsized internal format so that it works on OpenGL ES 2.0 too.
+fn gl_texture_formats(format: SurfaceFormat) -> (GLenum, GLenum, GLenum) {
+    match format {
+        SurfaceFormat::RGBA8 => (gl::RGBA, gl::RGBA, gl::UNSIGNED_BYTE),
+        SurfaceFormat::BGRA8 => (gl::RGBA8, GL_BGRA, gl::UNSIGNED_BYTE),
+        SurfaceFormat::SRGB8A8 => (gl::SRGB8_ALPHA8, gl::RGBA, gl::UNSIGNED_BYTE),
+        SurfaceFormat::RGB10A2 => (gl::RGB10_A2, gl::RGBA, gl::UNSIGNED_INT_2_10_10_10_REV),
+        SurfaceFormat::RGBA16F => (gl::RGBA16F, gl::RGBA, gl::HALF_FLOAT),
+        SurfaceFormat::R8 => (gl::R8, gl::RED, gl::UNSIGNED_BYTE),
+        SurfaceFormat::RG8 => (gl::RG8, gl::RG, gl::UNSIGNED_BYTE),
+    }
+}
+
 #[allow(dead_code)]
 pub(crate) unsafe fn create_pbuffer_surface(egl_display: EGLDisplay,
                                             egl_config: EGLConfig,
                                             size: &Size2D<i32>)
-                                            -> EGLSurface {
+                                            -> Result<EGLSurface, Error> {
     let attributes = [
         egl::WIDTH as EGLint,           size.width as EGLint,
         egl::HEIGHT as EGLint,          size.height as EGLint,
@@ -325,26 +1243,738 @@ pub(crate) unsafe fn create_pbuffer_surface(egl_display: EGLDisplay,
 
     EGL_FUNCTIONS.with(|egl| {
         let egl_surface = egl.CreatePbufferSurface(egl_display, egl_config, attributes.as_ptr());
-        assert_ne!(egl_surface, egl::NO_SURFACE);
-        egl_surface
+        if egl_surface == egl::NO_SURFACE {
+            let failure = egl.GetError().to_windowing_api_failure("eglCreatePbufferSurface");
+            return Err(Error::SurfaceCreationFailed(Box::new(failure)));
+        }
+        Ok(egl_surface)
     })
 }
 
+/// Creates an EGL image that shares the storage of a 2D texture belonging to the given context.
+/// 
+/// Returns `EGL_NO_IMAGE_KHR` if the driver can't share textures of that format.
 #[allow(dead_code)]
-pub(crate) unsafe fn bind_egl_image_to_gl_texture(gl: &Gl, egl_image: EGLImageKHR) -> GLuint {
+pub(crate) unsafe fn create_egl_image_from_gl_texture(egl_display: EGLDisplay,
+                                                      egl_context: EGLContext,
+                                                      texture_object: GLuint)
+                                                      -> EGLImageKHR {
+    let egl_image_attribs = [
+        EGL_IMAGE_PRESERVED_KHR as EGLint,  egl::FALSE as EGLint,
+        egl::NONE as EGLint,                0,
+    ];
+
+    let egl_client_buffer = texture_object as usize as EGLClientBuffer;
+    (EGL_EXTENSION_FUNCTIONS.CreateImageKHR)(egl_display,
+                                             egl_context,
+                                             EGL_GL_TEXTURE_2D_KHR,
+                                             egl_client_buffer,
+                                             egl_image_attribs.as_ptr())
+}
+
+// Depth textures can't be filtered linearly in OpenGL ES, so they get nearest filtering.
+#[allow(dead_code)]
+pub(crate) unsafe fn bind_depth_egl_image_to_gl_texture(gl: &Gl, egl_image: EGLImageKHR)
+                                                        -> GLuint {
+    let texture = bind_egl_image_to_gl_texture(gl, egl_image, gl::TEXTURE_2D);
+    gl.BindTexture(gl::TEXTURE_2D, texture);
+    gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+    gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+    gl.BindTexture(gl::TEXTURE_2D, 0);
+    texture
+}
+
+/// Creates a texture bound to the given target that samples from the EGL image.
+/// 
+/// Images we allocated ourselves are always bound to `GL_TEXTURE_2D`. Use
+/// `imported_texture_target()` to find the target for images that came from elsewhere.
+#[allow(dead_code)]
+pub(crate) unsafe fn bind_egl_image_to_gl_texture(gl: &Gl, egl_image: EGLImageKHR, target: GLenum)
+                                                  -> GLuint {
     let mut texture = 0;
     gl.GenTextures(1, &mut texture);
     debug_assert_ne!(texture, 0);
 
-    // FIXME(pcwalton): Should this be `GL_TEXTURE_EXTERNAL_OES`?
-    gl.BindTexture(gl::TEXTURE_2D, texture);
-    (EGL_EXTENSION_FUNCTIONS.ImageTargetTexture2DOES)(gl::TEXTURE_2D, egl_image);
-    gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
-    gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
-    gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
-    gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
-    gl.BindTexture(gl::TEXTURE_2D, 0);
+    // External textures only allow these filtering and wrapping modes, so they're used for every
+    // target alike.
+    gl.BindTexture(target, texture);
+    (EGL_EXTENSION_FUNCTIONS.ImageTargetTexture2DOES)(target, egl_image);
+    gl.TexParameteri(target, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+    gl.TexParameteri(target, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+    gl.TexParameteri(target, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+    gl.TexParameteri(target, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+    gl.BindTexture(target, 0);
 
     debug_assert_eq!(gl.GetError(), gl::NO_ERROR);
     texture
 }
+
+/// Exports the image backing a generic surface, falling back to a copy in shared memory.
+///
+/// The framebuffer object is used to read back the contents, in the surface's own format, if the
+/// dma-buf can't be exported.
+#[cfg(unix)]
+pub(crate) unsafe fn export_surface_image(gl: &Gl,
+                                          egl_display: EGLDisplay,
+                                          egl_image: EGLImageKHR,
+                                          framebuffer_object: GLuint,
+                                          size: &Size2D<i32>,
+                                          format: SurfaceFormat)
+                                          -> Result<SurfaceHandle, Error> {
+    // There's no cross-process synchronization, so make sure rendering has landed before the
+    // other side starts sampling.
+    gl.Finish();
+
+    if let Some(image) = export_dma_buf_image(egl_display, egl_image, size) {
+        return Ok(SurfaceHandle::DmaBuf(image));
+    }
+
+    let stride = size.width as usize * format.bytes_per_pixel();
+    let (fd, mapping) = SharedMemoryMapping::create(stride * size.height as usize)?;
+
+    // Clear stale errors so that a rejected format or type is reported rather than missed.
+    while gl.GetError() != gl::NO_ERROR {}
+
+    let (_, gl_format, gl_type) = gl_texture_formats(format);
+    let mut old_read_framebuffer = 0;
+    gl.GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut old_read_framebuffer);
+    gl.BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer_object);
+    gl.PixelStorei(gl::PACK_ALIGNMENT, 1);
+    gl.ReadPixels(0,
+                  0,
+                  size.width,
+                  size.height,
+                  gl_format,
+                  gl_type,
+                  mapping.ptr as *mut c_void);
+    gl.PixelStorei(gl::PACK_ALIGNMENT, 4);
+    gl.BindFramebuffer(gl::READ_FRAMEBUFFER, old_read_framebuffer as GLuint);
+
+    // OpenGL ES only guarantees that `GL_RGBA`/`GL_UNSIGNED_BYTE` can be read back.
+    if gl.GetError() != gl::NO_ERROR {
+        libc::close(fd);
+        return Err(Error::UnsupportedSurfaceFormat);
+    }
+
+    let stride = stride as u32;
+    Ok(SurfaceHandle::SharedMemory(SharedMemoryImage { fd, size: *size, stride, format }))
+}
+
+/// Returns the texture target that an image imported from the given handle should be bound to.
+/// 
+/// `GL_TEXTURE_EXTERNAL_OES` is only chosen when the driver reports that it can't sample the
+/// image any other way, in which case a request for a 2D-compatible target fails.
+#[cfg(unix)]
+pub(crate) unsafe fn imported_texture_target(egl_display: EGLDisplay,
+                                             handle: &SurfaceHandle,
+                                             target: SurfaceTextureTarget)
+                                             -> Result<GLenum, Error> {
+    let external_only = match *handle {
+        SurfaceHandle::DmaBuf(ref image) => {
+            dma_buf_is_external_only(egl_display, image.fourcc, image.modifier)
+        }
+        SurfaceHandle::SharedMemory(_) |
+        SurfaceHandle::IOSurface { .. } |
+        SurfaceHandle::D3DShareHandle { .. } => false,
+    };
+    match (external_only, target) {
+        (false, _) => Ok(gl::TEXTURE_2D),
+        (true, SurfaceTextureTarget::Any) => Ok(GL_TEXTURE_EXTERNAL_OES),
+        (true, SurfaceTextureTarget::Compatible2D) => Err(Error::UnsupportedTextureTarget),
+    }
+}
+
+/// Creates an EGL image from a surface handle exported by another process.
+///
+/// Returns the image along with the texture that holds its pixels, if they had to be uploaded
+/// from shared memory. Otherwise, the texture is zero.
+#[cfg(unix)]
+pub(crate) unsafe fn import_surface_image(gl: &Gl,
+                                          egl_display: EGLDisplay,
+                                          egl_context: EGLContext,
+                                          handle: &SurfaceHandle)
+                                          -> Result<(EGLImageKHR, GLuint), Error> {
+    match *handle {
+        SurfaceHandle::DmaBuf(ref image) => Ok((import_dma_buf_image(egl_display, image)?, 0)),
+        SurfaceHandle::SharedMemory(ref image) => {
+            upload_shared_memory_image(gl, egl_display, egl_context, image)
+        }
+        SurfaceHandle::IOSurface { .. } | SurfaceHandle::D3DShareHandle { .. } => {
+            Err(Error::UnsupportedOnThisPlatform)
+        }
+    }
+}
+
+/// Creates an EGL image from the planes of a decoded video frame.
+///
+/// Returns the image, the texture that holds its pixels if they had to be converted on the CPU
+/// (zero otherwise), and the target that textures wrapping the image must be bound to. The
+/// conversion happens when the frame can't be imported directly, or when it could only be
+/// sampled as an external texture and a 2D-compatible target was requested.
+#[cfg(unix)]
+pub(crate) unsafe fn import_planar_image(gl: &Gl,
+                                         egl_display: EGLDisplay,
+                                         egl_context: EGLContext,
+                                         image: &PlanarImage,
+                                         target: SurfaceTextureTarget)
+                                         -> Result<(EGLImageKHR, GLuint, GLenum), Error> {
+    image.validate()?;
+
+    let fourcc = image.format.drm_fourcc();
+    let external_only = dma_buf_is_external_only(egl_display, fourcc, image.modifier);
+    if !external_only || target == SurfaceTextureTarget::Any {
+        let color_space = match image.color_space {
+            YUVColorSpace::BT601 => EGL_ITU_REC601_EXT,
+            YUVColorSpace::BT709 => EGL_ITU_REC709_EXT,
+            YUVColorSpace::BT2020 => EGL_ITU_REC2020_EXT,
+        };
+        let range = match image.range {
+            YUVRange::Limited => EGL_YUV_NARROW_RANGE_EXT,
+            YUVRange::Full => EGL_YUV_FULL_RANGE_EXT,
+        };
+        let hints = [
+            EGL_YUV_COLOR_SPACE_HINT_EXT as EGLint, color_space as EGLint,
+            EGL_SAMPLE_RANGE_HINT_EXT as EGLint,    range as EGLint,
+        ];
+        if let Ok(egl_image) = import_dma_buf_planes(egl_display,
+                                                     &image.size,
+                                                     fourcc,
+                                                     image.modifier,
+                                                     &image.planes,
+                                                     &hints) {
+            let gl_texture_target = if external_only {
+                GL_TEXTURE_EXTERNAL_OES
+            } else {
+                gl::TEXTURE_2D
+            };
+            return Ok((egl_image, 0, gl_texture_target));
+        }
+    }
+
+    let pixels = image.to_rgba()?;
+    let (egl_image, texture_object) = upload_image(gl,
+                                                   egl_display,
+                                                   egl_context,
+                                                   &image.size,
+                                                   SurfaceFormat::RGBA8,
+                                                   image.size.width as usize * 4,
+                                                   pixels.as_ptr())?;
+    Ok((egl_image, texture_object, gl::TEXTURE_2D))
+}
+
+/// Exports the dma-buf backing an EGL image, if the driver allows it.
+///
+/// The returned planes own their file descriptors.
+#[cfg(unix)]
+pub(crate) unsafe fn export_dma_buf_image(egl_display: EGLDisplay,
+                                          egl_image: EGLImageKHR,
+                                          size: &Size2D<i32>)
+                                          -> Option<DmaBufImage> {
+    if !display_supports_extension(egl_display, "EGL_MESA_image_dma_buf_export") {
+        return None;
+    }
+
+    let (query, export) = match (EGL_EXTENSION_FUNCTIONS.ExportDMABUFImageQueryMESA,
+                                 EGL_EXTENSION_FUNCTIONS.ExportDMABUFImageMESA) {
+        (Some(query), Some(export)) => (query, export),
+        _ => return None,
+    };
+
+    let (mut fourcc, mut plane_count) = (0, 0);
+    let mut modifiers = [DRM_FORMAT_MOD_INVALID; MAX_DMA_BUF_PLANES];
+    if query(egl_display, egl_image, &mut fourcc, &mut plane_count, ptr::null_mut()) ==
+            egl::FALSE || plane_count < 1 || plane_count as usize > MAX_DMA_BUF_PLANES {
+        return None;
+    }
+    if query(egl_display, egl_image, &mut fourcc, &mut plane_count, modifiers.as_mut_ptr()) ==
+            egl::FALSE {
+        return None;
+    }
+
+    let mut fds = [-1; MAX_DMA_BUF_PLANES];
+    let (mut strides, mut offsets) = ([0; MAX_DMA_BUF_PLANES], [0; MAX_DMA_BUF_PLANES]);
+    if export(egl_display,
+              egl_image,
+              fds.as_mut_ptr(),
+              strides.as_mut_ptr(),
+              offsets.as_mut_ptr()) == egl::FALSE {
+        return None;
+    }
+
+    // Planes that share a buffer with the first plane may be reported without a file
+    // descriptor. Give every plane its own so that the handle can own them uniformly.
+    let plane_count = plane_count as usize;
+    let mut planes: Vec<DmaBufPlane> = Vec::with_capacity(plane_count);
+    for plane_index in 0..plane_count {
+        let fd = if fds[plane_index] >= 0 { fds[plane_index] } else { libc::dup(fds[0]) };
+        if fd < 0 {
+            // Close the descriptors exported so far, along with any duplicates.
+            let exported_fds = fds[..plane_count].iter().cloned().filter(|&fd| fd >= 0);
+            let mut owned_fds: Vec<_> =
+                exported_fds.chain(planes.iter().map(|plane| plane.fd)).collect();
+            owned_fds.sort();
+            owned_fds.dedup();
+            for owned_fd in owned_fds {
+                libc::close(owned_fd);
+            }
+            return None;
+        }
+        planes.push(DmaBufPlane {
+            fd,
+            offset: offsets[plane_index] as u32,
+            stride: strides[plane_index] as u32,
+        });
+    }
+
+    Some(DmaBufImage { size: *size, fourcc: fourcc as u32, modifier: modifiers[0], planes })
+}
+
+// Drivers list the format and modifier pairs they can only sample as external textures. Without
+// an explicit modifier there's nothing to look up, so YUV images, which drivers almost never
+// sample as 2D textures, are assumed to be external-only and everything else isn't.
+#[cfg(unix)]
+unsafe fn dma_buf_is_external_only(egl_display: EGLDisplay, fourcc: u32, modifier: u64) -> bool {
+    let yuv = PlanarFormat::is_yuv_drm_fourcc(fourcc);
+    if modifier == DRM_FORMAT_MOD_INVALID ||
+            !display_supports_extension(egl_display, "EGL_EXT_image_dma_buf_import_modifiers") {
+        return yuv;
+    }
+    let query = match EGL_EXTENSION_FUNCTIONS.QueryDmaBufModifiersEXT {
+        Some(query) => query,
+        None => return yuv,
+    };
+
+    let fourcc = fourcc as EGLint;
+    let mut modifier_count = 0;
+    if query(egl_display, fourcc, 0, ptr::null_mut(), ptr::null_mut(), &mut modifier_count) ==
+            egl::FALSE || modifier_count <= 0 {
+        return yuv;
+    }
+    let mut modifiers = vec![DRM_FORMAT_MOD_INVALID; modifier_count as usize];
+    let mut external_only = vec![egl::FALSE; modifier_count as usize];
+    if query(egl_display,
+             fourcc,
+             modifier_count,
+             modifiers.as_mut_ptr(),
+             external_only.as_mut_ptr(),
+             &mut modifier_count) == egl::FALSE {
+        return yuv;
+    }
+
+    modifiers.iter()
+             .zip(external_only.iter())
+             .take(modifier_count as usize)
+             .find(|&(&supported_modifier, _)| supported_modifier == modifier)
+             .map_or(yuv, |(_, &external_only)| external_only != egl::FALSE)
+}
+
+#[cfg(unix)]
+unsafe fn import_dma_buf_image(egl_display: EGLDisplay, image: &DmaBufImage)
+                               -> Result<EGLImageKHR, Error> {
+    import_dma_buf_planes(egl_display,
+                          &image.size,
+                          image.fourcc,
+                          image.modifier,
+                          &image.planes,
+                          &[])
+}
+
+// `extra_attributes` is a list of attribute-value pairs to pass along, such as YUV hints.
+#[cfg(unix)]
+unsafe fn import_dma_buf_planes(egl_display: EGLDisplay,
+                                size: &Size2D<i32>,
+                                fourcc: u32,
+                                modifier: u64,
+                                planes: &[DmaBufPlane],
+                                extra_attributes: &[EGLint])
+                                -> Result<EGLImageKHR, Error> {
+    // These come from another process, so don't trust them.
+    if planes.is_empty() || planes.len() > MAX_DMA_BUF_PLANES || size.width <= 0 ||
+            size.height <= 0 || planes.iter().any(|plane| {
+                plane.offset > EGLint::MAX as u32 ||
+                    plane.stride > EGLint::MAX as u32
+            }) {
+        return Err(Error::SurfaceImportFailed(WindowingApiError::BadParameter.into()));
+    }
+    if !display_supports_extension(egl_display, "EGL_EXT_image_dma_buf_import") {
+        return Err(Error::RequiredExtensionUnavailable);
+    }
+
+    let mut attributes = vec![
+        egl::WIDTH as EGLint,                   size.width,
+        egl::HEIGHT as EGLint,                  size.height,
+        EGL_LINUX_DRM_FOURCC_EXT as EGLint,     fourcc as EGLint,
+    ];
+    for (plane, plane_attributes) in planes.iter().zip(EGL_DMA_BUF_PLANE_ATTRIBUTES.iter()) {
+        attributes.extend_from_slice(&[
+            plane_attributes[0] as EGLint,  plane.fd,
+            plane_attributes[1] as EGLint,  plane.offset as EGLint,
+            plane_attributes[2] as EGLint,  plane.stride as EGLint,
+        ]);
+        if modifier != DRM_FORMAT_MOD_INVALID {
+            attributes.extend_from_slice(&[
+                plane_attributes[3] as EGLint,  modifier as u32 as EGLint,
+                plane_attributes[4] as EGLint,  (modifier >> 32) as u32 as EGLint,
+            ]);
+        }
+    }
+    attributes.extend_from_slice(extra_attributes);
+    attributes.extend_from_slice(&[egl::NONE as EGLint, 0]);
+
+    let egl_image = (EGL_EXTENSION_FUNCTIONS.CreateImageKHR)(egl_display,
+                                                             egl::NO_CONTEXT,
+                                                             EGL_LINUX_DMA_BUF_EXT,
+                                                             ptr::null_mut(),
+                                                             attributes.as_ptr());
+    if egl_image == EGL_NO_IMAGE_KHR {
+        let failure = EGL_FUNCTIONS.with(|egl| {
+            egl.GetError().to_windowing_api_failure("eglCreateImageKHR")
+        });
+        return Err(Error::SurfaceImportFailed(Box::new(failure)));
+    }
+    Ok(egl_image)
+}
+
+#[cfg(unix)]
+unsafe fn upload_shared_memory_image(gl: &Gl,
+                                     egl_display: EGLDisplay,
+                                     egl_context: EGLContext,
+                                     image: &SharedMemoryImage)
+                                     -> Result<(EGLImageKHR, GLuint), Error> {
+    // The size and stride come from another process, so check them before mapping anything.
+    let len = match shared_memory_image_len(image) {
+        Some(len) => len,
+        None => return Err(Error::SurfaceImportFailed(WindowingApiError::BadParameter.into())),
+    };
+    let mapping = SharedMemoryMapping::open(image.fd, len)?;
+    upload_image(gl,
+                 egl_display,
+                 egl_context,
+                 &image.size,
+                 image.format,
+                 image.stride as usize,
+                 mapping.ptr as *const u8)
+}
+
+// Returns the number of bytes that a shared memory image spans, or `None` if its size or stride
+// is invalid.
+#[cfg(unix)]
+fn shared_memory_image_len(image: &SharedMemoryImage) -> Option<usize> {
+    let bytes_per_pixel = image.format.bytes_per_pixel();
+    if image.size.width <= 0 || image.size.height <= 0 ||
+            image.stride as usize % bytes_per_pixel != 0 || image.stride > GLint::MAX as u32 {
+        return None;
+    }
+    let row_size = image.size.width.checked_mul(bytes_per_pixel as i32)?;
+    if (image.stride as i32) < row_size {
+        return None;
+    }
+    (image.stride as usize).checked_mul(image.size.height as usize)
+}
+
+// Copies pixels of the given format, with rows `stride` bytes apart, into a new texture and wraps
+// it in an EGL image.
+//
+// The context's version isn't known here, so padded rows are copied one at a time rather than
+// through `GL_UNPACK_ROW_LENGTH`.
+#[cfg(unix)]
+pub(crate) unsafe fn upload_image(gl: &Gl,
+                                  egl_display: EGLDisplay,
+                                  egl_context: EGLContext,
+                                  size: &Size2D<i32>,
+                                  format: SurfaceFormat,
+                                  stride: usize,
+                                  pixels: *const u8)
+                                  -> Result<(EGLImageKHR, GLuint), Error> {
+    // Clear stale errors so that a rejected format is reported rather than missed.
+    while gl.GetError() != gl::NO_ERROR {}
+
+    let (internal_format, gl_format, gl_type) = gl_texture_formats(format);
+    let mut texture_object = 0;
+    gl.GenTextures(1, &mut texture_object);
+    gl.BindTexture(gl::TEXTURE_2D, texture_object);
+    gl.TexImage2D(gl::TEXTURE_2D,
+                  0,
+                  internal_format as GLint,
+                  size.width,
+                  size.height,
+                  0,
+                  gl_format,
+                  gl_type,
+                  ptr::null());
+    tex_sub_image_rows(gl, &Rect::new(Point2D::zero(), *size), pixels, stride, format, false);
+    gl.BindTexture(gl::TEXTURE_2D, 0);
+
+    if gl.GetError() != gl::NO_ERROR {
+        gl.DeleteTextures(1, &texture_object);
+        return Err(Error::UnsupportedSurfaceFormat);
+    }
+
+    let egl_image_attribs = [
+        EGL_IMAGE_PRESERVED_KHR as EGLint,  egl::TRUE as EGLint,
+        egl::NONE as EGLint,                0,
+    ];
+    let egl_image = (EGL_EXTENSION_FUNCTIONS.CreateImageKHR)(egl_display,
+                                                             egl_context,
+                                                             EGL_GL_TEXTURE_2D_KHR,
+                                                             texture_object as usize as
+                                                                EGLClientBuffer,
+                                                             egl_image_attribs.as_ptr());
+    if egl_image == EGL_NO_IMAGE_KHR {
+        let failure = EGL_FUNCTIONS.with(|egl| {
+            egl.GetError().to_windowing_api_failure("eglCreateImageKHR")
+        });
+        gl.DeleteTextures(1, &texture_object);
+        return Err(Error::SurfaceImportFailed(Box::new(failure)));
+    }
+
+    Ok((egl_image, texture_object))
+}
+
+// Copies the rows of `rect` from `data`, `stride` bytes apart, into a 2D texture and flushes so
+// that other contexts sampling the texture see them.
+//
+// If `pixel_unpack_buffer` is supplied, the rows are streamed through that buffer, creating it if
+// necessary. The driver can then perform the transfer asynchronously, and the copy into the
+// buffer writes sequentially, which suits write-combined memory. Contexts that can't map pixel
+// unpack buffers copy directly instead.
+pub(crate) unsafe fn upload_texture_data(gl: &Gl,
+                                         gl_info: &GLInfo,
+                                         texture_object: GLuint,
+                                         pixel_unpack_buffer: Option<&mut GLuint>,
+                                         rect: &Rect<i32>,
+                                         data: &[u8],
+                                         stride: usize,
+                                         format: SurfaceFormat)
+                                         -> Result<(), Error> {
+    if rect.size.width == 0 || rect.size.height == 0 {
+        return Ok(());
+    }
+
+    let pixel_unpack_buffer = match pixel_unpack_buffer {
+        Some(pixel_unpack_buffer) if supports_pixel_unpack_buffers(gl_info) => {
+            Some(pixel_unpack_buffer)
+        }
+        _ => None,
+    };
+    match pixel_unpack_buffer {
+        None => {
+            gl.BindTexture(gl::TEXTURE_2D, texture_object);
+            tex_sub_image_rows(gl,
+                               rect,
+                               data.as_ptr(),
+                               stride,
+                               format,
+                               supports_unpack_row_length(gl_info));
+            gl.BindTexture(gl::TEXTURE_2D, 0);
+        }
+        Some(pixel_unpack_buffer) => {
+            let row_size = rect.size.width as usize * format.bytes_per_pixel();
+            let ptr = map_pixel_unpack_buffer(gl,
+                                              pixel_unpack_buffer,
+                                              row_size * rect.size.height as usize)?;
+            for row in 0..(rect.size.height as usize) {
+                ptr::copy_nonoverlapping(data.as_ptr().add(row * stride),
+                                         ptr.add(row * row_size),
+                                         row_size);
+            }
+            gl.UnmapBuffer(gl::PIXEL_UNPACK_BUFFER);
+            tex_sub_image_from_pixel_unpack_buffer(gl, texture_object, rect, format);
+        }
+    }
+
+    gl.Flush();
+    Ok(())
+}
+
+// Copies the rows of `rect` from `data`, `stride` bytes apart, into the bound 2D texture.
+//
+// Padded rows are copied in one call through `GL_UNPACK_ROW_LENGTH` if `unpack_row_length` is
+// true, and one row at a time otherwise.
+unsafe fn tex_sub_image_rows(gl: &Gl,
+                             rect: &Rect<i32>,
+                             data: *const u8,
+                             stride: usize,
+                             format: SurfaceFormat,
+                             unpack_row_length: bool) {
+    let (_, gl_format, gl_type) = gl_texture_formats(format);
+    let bytes_per_pixel = format.bytes_per_pixel();
+    let row_size = rect.size.width as usize * bytes_per_pixel;
+    gl.PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+    if stride == row_size || unpack_row_length {
+        if stride != row_size {
+            gl.PixelStorei(gl::UNPACK_ROW_LENGTH, (stride / bytes_per_pixel) as GLint);
+        }
+        gl.TexSubImage2D(gl::TEXTURE_2D,
+                         0,
+                         rect.origin.x,
+                         rect.origin.y,
+                         rect.size.width,
+                         rect.size.height,
+                         gl_format,
+                         gl_type,
+                         data as *const c_void);
+        if stride != row_size {
+            gl.PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
+        }
+    } else {
+        for row in 0..rect.size.height {
+            gl.TexSubImage2D(gl::TEXTURE_2D,
+                             0,
+                             rect.origin.x,
+                             rect.origin.y + row,
+                             rect.size.width,
+                             1,
+                             gl_format,
+                             gl_type,
+                             data.add(row as usize * stride) as *const c_void);
+        }
+    }
+    gl.PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+}
+
+// Whether `GL_UNPACK_ROW_LENGTH` can be set, which OpenGL ES 2.0 lacks without
+// `GL_EXT_unpack_subimage`.
+pub(crate) fn supports_unpack_row_length(gl_info: &GLInfo) -> bool {
+    match gl_info.api {
+        GLApi::GL => true,
+        GLApi::GLES => {
+            gl_info.version >= GLVersion::new(3, 0) ||
+                gl_info.supports_extension("GL_EXT_unpack_subimage")
+        }
+    }
+}
+
+// Whether pixel unpack buffers can be mapped with `glMapBufferRange()`, which needs OpenGL 3.0,
+// OpenGL ES 3.0, or `GL_ARB_map_buffer_range` on older OpenGL.
+pub(crate) fn supports_pixel_unpack_buffers(gl_info: &GLInfo) -> bool {
+    match gl_info.api {
+        GLApi::GL => {
+            gl_info.version >= GLVersion::new(3, 0) ||
+                gl_info.supports_extension("GL_ARB_map_buffer_range")
+        }
+        GLApi::GLES => gl_info.version >= GLVersion::new(3, 0),
+    }
+}
+
+// Whether layered surfaces can be backed by a `GL_TEXTURE_2D_ARRAY`, rendered to one layer at a
+// time with `glFramebufferTextureLayer()` and copied out with `glBlitFramebuffer()`. Both need
+// OpenGL 3.0 or OpenGL ES 3.0.
+pub(crate) fn supports_array_layers(gl_info: &GLInfo) -> bool {
+    gl_info.version >= GLVersion::new(3, 0)
+}
+
+// Allocates a `GL_TEXTURE_2D_ARRAY` with `layers` layers of the given size.
+unsafe fn create_array_texture(gl: &Gl,
+                               size: &Size2D<i32>,
+                               layers: u32,
+                               internal_format: GLenum,
+                               format: GLenum,
+                               gl_type: GLenum)
+                               -> GLuint {
+    let mut texture_object = 0;
+    gl.GenTextures(1, &mut texture_object);
+    gl.BindTexture(gl::TEXTURE_2D_ARRAY, texture_object);
+    gl.TexImage3D(gl::TEXTURE_2D_ARRAY,
+                  0,
+                  internal_format as GLint,
+                  size.width,
+                  size.height,
+                  layers as GLsizei,
+                  0,
+                  format,
+                  gl_type,
+                  ptr::null());
+    gl.BindTexture(gl::TEXTURE_2D_ARRAY, 0);
+    texture_object
+}
+
+// Creates a framebuffer with every layer of the array textures attached as a `GL_OVR_multiview`
+// view. Returns zero if the extension is missing, can't render that many views, or won't accept
+// the attachments.
+unsafe fn create_multiview_framebuffer(gl: &Gl,
+                                       gl_info: &GLInfo,
+                                       texture_object: GLuint,
+                                       depth_stencil_texture_object: GLuint,
+                                       layers: u32)
+                                       -> GLuint {
+    if !gl_info.supports_extension("GL_OVR_multiview") ||
+            !gl.FramebufferTextureMultiviewOVR.is_loaded() {
+        return 0;
+    }
+    let mut max_views = 0;
+    gl.GetIntegerv(gl::MAX_VIEWS_OVR, &mut max_views);
+    if layers > max_views as u32 {
+        return 0;
+    }
+
+    let mut framebuffer_object = 0;
+    gl.GenFramebuffers(1, &mut framebuffer_object);
+    gl.BindFramebuffer(gl::FRAMEBUFFER, framebuffer_object);
+    gl.FramebufferTextureMultiviewOVR(gl::FRAMEBUFFER,
+                                      gl::COLOR_ATTACHMENT0,
+                                      texture_object,
+                                      0,
+                                      0,
+                                      layers as GLsizei);
+    if depth_stencil_texture_object != 0 {
+        gl.FramebufferTextureMultiviewOVR(gl::FRAMEBUFFER,
+                                          gl::DEPTH_STENCIL_ATTACHMENT,
+                                          depth_stencil_texture_object,
+                                          0,
+                                          0,
+                                          layers as GLsizei);
+    }
+    if gl.CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+        gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl.DeleteFramebuffers(1, &framebuffer_object);
+        return 0;
+    }
+    framebuffer_object
+}
+
+// Binds the given pixel unpack buffer, creating it if necessary, and maps `len` bytes of fresh
+// storage in it for writing. The buffer stays bound.
+pub(crate) unsafe fn map_pixel_unpack_buffer(gl: &Gl, pixel_unpack_buffer: &mut GLuint, len: usize)
+                                             -> Result<*mut u8, Error> {
+    if *pixel_unpack_buffer == 0 {
+        gl.GenBuffers(1, pixel_unpack_buffer);
+    }
+    gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, *pixel_unpack_buffer);
+
+    // Respecifying the storage orphans the old contents, so we never wait on the GPU to finish
+    // reading a previous upload.
+    gl.BufferData(gl::PIXEL_UNPACK_BUFFER, len as GLsizeiptr, ptr::null(), gl::STREAM_DRAW);
+    let ptr = gl.MapBufferRange(gl::PIXEL_UNPACK_BUFFER,
+                                0,
+                                len as GLsizeiptr,
+                                gl::MAP_WRITE_BIT | gl::MAP_INVALIDATE_BUFFER_BIT);
+    if ptr.is_null() {
+        gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+        return Err(Error::SurfaceLockFailed);
+    }
+    Ok(ptr as *mut u8)
+}
+
+// Copies tightly packed rows from the bound, unmapped pixel unpack buffer into `rect` of a 2D
+// texture, then unbinds the buffer.
+pub(crate) unsafe fn tex_sub_image_from_pixel_unpack_buffer(gl: &Gl,
+                                                            texture_object: GLuint,
+                                                            rect: &Rect<i32>,
+                                                            format: SurfaceFormat) {
+    let (_, gl_format, gl_type) = gl_texture_formats(format);
+    gl.BindTexture(gl::TEXTURE_2D, texture_object);
+    gl.PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+    gl.TexSubImage2D(gl::TEXTURE_2D,
+                     0,
+                     rect.origin.x,
+                     rect.origin.y,
+                     rect.size.width,
+                     rect.size.height,
+                     gl_format,
+                     gl_type,
+                     ptr::null());
+    gl.PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+    gl.BindTexture(gl::TEXTURE_2D, 0);
+    gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+}
@@ -0,0 +1,290 @@
+// surfman/surfman/src/platform/generic/egl/wayland.rs
+//
+//! Sampling from Wayland client buffers, for compositors built on surfman.
+//!
+//! This is shared by all of the EGL backends on Linux, since a compositor may itself be running
+//! on X11, on another Wayland compositor, or directly on the hardware. Each backend's `Connection`
+//! and `Device` forward to the functions here.
+
+use crate::egl::types::{EGLContext, EGLDisplay, EGLenum, EGLint};
+use crate::egl;
+use crate::gl::types::{GLint, GLuint};
+use crate::gl;
+use crate::leak::Origin;
+use crate::platform::unix::wayland::ffi::{WL_SHM_FORMAT_ARGB8888, WL_SHM_FORMAT_XRGB8888};
+use crate::{ContextID, Error, GL_TEXTURE_EXTERNAL_OES, Gl, SurfaceFormat};
+use crate::{SurfaceTextureTarget, WindowingApiError, WindowingApiFailure};
+use super::device::{EGL_FUNCTIONS, display_supports_extension};
+use super::error::ToWindowingApiError;
+use super::ffi::{EGL_EXTENSION_FUNCTIONS, EGL_IMAGE_PRESERVED_KHR};
+use super::ffi::{EGL_NO_IMAGE_KHR, EGL_TEXTURE_EXTERNAL_WL};
+use super::ffi::{EGL_TEXTURE_Y_UV_WL, EGL_TEXTURE_Y_U_V_WL};
+use super::ffi::{EGL_TEXTURE_Y_XUXV_WL, EGL_WAYLAND_BUFFER_WL};
+use super::ffi::{EGL_WAYLAND_PLANE_WL, EGL_WAYLAND_Y_INVERTED_WL};
+use super::ffi::{EGLClientBuffer, EGLImageKHR};
+use super::surface::{self, EGLSurfaceTexture};
+
+use euclid::default::Size2D;
+use std::os::raw::c_void;
+use std::slice;
+use wayland_sys::server::{WAYLAND_SERVER_OPTION, wl_display, wl_resource};
+
+/// How the pixels of a Wayland client buffer are laid out.
+///
+/// Buffers that clients allocated through the EGL implementation are sampled without a copy,
+/// provided that the compositor's display was bound with `Connection::bind_wayland_display()`.
+/// Buffers in the YUV layouts are imported one plane at a time, and converting them to RGB is up
+/// to the compositor's shaders. `wl_shm` buffers are copied, so their textures don't reflect later
+/// commits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WaylandBufferFormat {
+    /// One RGB plane.
+    RGB,
+    /// One RGBA plane.
+    RGBA,
+    /// One plane that can only be sampled as `GL_TEXTURE_EXTERNAL_OES`, which converts it to RGB.
+    External,
+    /// Three planes: luma, then each chroma channel. This is `EGL_TEXTURE_Y_U_V_WL`.
+    YUVPlanes,
+    /// Two planes: luma, then both chroma channels interleaved. This is `EGL_TEXTURE_Y_UV_WL`.
+    YAndUVPlanes,
+    /// Two planes: luma, then packed `XUXV` chroma. This is `EGL_TEXTURE_Y_XUXV_WL`.
+    YAndXUXVPlanes,
+    /// A `wl_shm` buffer, whose pixels are copied into one RGBA plane.
+    SharedMemory,
+}
+
+/// The size, layout, and orientation of a Wayland client buffer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WaylandBufferInfo {
+    /// The size of the buffer in pixels.
+    pub size: Size2D<i32>,
+    /// How the buffer's pixels are laid out.
+    pub format: WaylandBufferFormat,
+    /// True if row 0 of the buffer's textures is the top row of the image, as it is for nearly
+    /// all clients. False if it's the bottom row, as OpenGL would have it.
+    pub top_row_first: bool,
+}
+
+impl WaylandBufferFormat {
+    /// Returns the number of planes in a buffer of this format. Each plane is wrapped in its own
+    /// surface texture.
+    pub fn plane_count(self) -> u32 {
+        match self {
+            WaylandBufferFormat::RGB |
+            WaylandBufferFormat::RGBA |
+            WaylandBufferFormat::External |
+            WaylandBufferFormat::SharedMemory => 1,
+            WaylandBufferFormat::YAndUVPlanes | WaylandBufferFormat::YAndXUXVPlanes => 2,
+            WaylandBufferFormat::YUVPlanes => 3,
+        }
+    }
+}
+
+pub(crate) unsafe fn bind_wayland_display(egl_display: EGLDisplay,
+                                          wayland_display: *mut wl_display)
+                                          -> Result<(), Error> {
+    let bind = match EGL_EXTENSION_FUNCTIONS.BindWaylandDisplayWL {
+        Some(bind) if display_supports_extension(egl_display, "EGL_WL_bind_wayland_display") => {
+            bind
+        }
+        _ => return Err(Error::RequiredExtensionUnavailable),
+    };
+    if bind(egl_display, wayland_display as *mut c_void) == egl::FALSE {
+        return Err(Error::Failed);
+    }
+    Ok(())
+}
+
+pub(crate) unsafe fn unbind_wayland_display(egl_display: EGLDisplay,
+                                            wayland_display: *mut wl_display)
+                                            -> Result<(), Error> {
+    let unbind = match EGL_EXTENSION_FUNCTIONS.UnbindWaylandDisplayWL {
+        Some(unbind) => unbind,
+        None => return Err(Error::RequiredExtensionUnavailable),
+    };
+    if unbind(egl_display, wayland_display as *mut c_void) == egl::FALSE {
+        return Err(Error::Failed);
+    }
+    Ok(())
+}
+
+pub(crate) unsafe fn query_wl_buffer(egl_display: EGLDisplay, buffer: *mut wl_resource)
+                                     -> Result<WaylandBufferInfo, Error> {
+    if buffer.is_null() {
+        return Err(Error::SurfaceImportFailed(WindowingApiError::BadParameter.into()));
+    }
+
+    // The query fails for buffers that the EGL implementation didn't allocate, and always fails
+    // if the compositor's display isn't bound.
+    if let Some(query) = EGL_EXTENSION_FUNCTIONS.QueryWaylandBufferWL {
+        let query_attribute = |attribute: EGLenum, value: &mut EGLint| {
+            query(egl_display, buffer as *mut c_void, attribute as EGLint, value) != egl::FALSE
+        };
+        let mut texture_format = 0;
+        if query_attribute(egl::TEXTURE_FORMAT, &mut texture_format) {
+            let format = match texture_format as EGLenum {
+                egl::TEXTURE_RGB => WaylandBufferFormat::RGB,
+                egl::TEXTURE_RGBA => WaylandBufferFormat::RGBA,
+                EGL_TEXTURE_EXTERNAL_WL => WaylandBufferFormat::External,
+                EGL_TEXTURE_Y_U_V_WL => WaylandBufferFormat::YUVPlanes,
+                EGL_TEXTURE_Y_UV_WL => WaylandBufferFormat::YAndUVPlanes,
+                EGL_TEXTURE_Y_XUXV_WL => WaylandBufferFormat::YAndXUXVPlanes,
+                _ => return Err(Error::UnsupportedSurfaceFormat),
+            };
+            let (mut width, mut height) = (0, 0);
+            query_attribute(egl::WIDTH, &mut width);
+            query_attribute(egl::HEIGHT, &mut height);
+
+            // Implementations that predate the attribute don't flip buffers.
+            let mut y_inverted = egl::TRUE as EGLint;
+            query_attribute(EGL_WAYLAND_Y_INVERTED_WL, &mut y_inverted);
+
+            return Ok(WaylandBufferInfo {
+                size: Size2D::new(width, height),
+                format,
+                top_row_first: y_inverted != egl::FALSE as EGLint,
+            });
+        }
+    }
+
+    let wayland_server = match *WAYLAND_SERVER_OPTION {
+        Some(ref wayland_server) => wayland_server,
+        None => return Err(Error::RequiredExtensionUnavailable),
+    };
+    let shm_buffer = (wayland_server.wl_shm_buffer_get)(buffer);
+    if shm_buffer.is_null() {
+        let failure = WindowingApiFailure::new("wl_shm_buffer_get",
+                                               WindowingApiError::BadParameter,
+                                               None);
+        return Err(Error::SurfaceImportFailed(Box::new(failure)));
+    }
+    Ok(WaylandBufferInfo {
+        size: Size2D::new((wayland_server.wl_shm_buffer_get_width)(shm_buffer),
+                          (wayland_server.wl_shm_buffer_get_height)(shm_buffer)),
+        format: WaylandBufferFormat::SharedMemory,
+        top_row_first: true,
+    })
+}
+
+/// Wraps one plane of a Wayland client buffer in a texture local to the current context.
+pub(crate) unsafe fn create_surface_texture_from_wl_buffer(gl: &Gl,
+                                                           egl_display: EGLDisplay,
+                                                           egl_context: EGLContext,
+                                                           context_id: ContextID,
+                                                           buffer: *mut wl_resource,
+                                                           plane: u32,
+                                                           target: SurfaceTextureTarget,
+                                                           origin: Origin)
+                                                           -> Result<EGLSurfaceTexture, Error> {
+    let info = query_wl_buffer(egl_display, buffer)?;
+    if plane >= info.format.plane_count() {
+        return Err(Error::SurfaceImportFailed(WindowingApiError::BadParameter.into()));
+    }
+
+    if info.format == WaylandBufferFormat::SharedMemory {
+        let (egl_image, texture_object) = upload_shm_buffer(gl, egl_display, egl_context, buffer)?;
+        return Ok(EGLSurfaceTexture::from_imported_image(gl,
+                                                         context_id,
+                                                         &info.size,
+                                                         egl_image,
+                                                         texture_object,
+                                                         gl::TEXTURE_2D,
+                                                         origin));
+    }
+
+    let gl_texture_target = match (info.format, target) {
+        (WaylandBufferFormat::External, SurfaceTextureTarget::Any) => GL_TEXTURE_EXTERNAL_OES,
+        (WaylandBufferFormat::External, SurfaceTextureTarget::Compatible2D) => {
+            return Err(Error::UnsupportedTextureTarget)
+        }
+        _ => gl::TEXTURE_2D,
+    };
+
+    let egl_image_attribs = [
+        EGL_WAYLAND_PLANE_WL as EGLint,     plane as EGLint,
+        EGL_IMAGE_PRESERVED_KHR as EGLint,  egl::TRUE as EGLint,
+        egl::NONE as EGLint,                0,
+    ];
+    let egl_image = (EGL_EXTENSION_FUNCTIONS.CreateImageKHR)(egl_display,
+                                                             egl::NO_CONTEXT,
+                                                             EGL_WAYLAND_BUFFER_WL,
+                                                             buffer as EGLClientBuffer,
+                                                             egl_image_attribs.as_ptr());
+    if egl_image == EGL_NO_IMAGE_KHR {
+        let failure = EGL_FUNCTIONS.with(|egl| {
+            egl.GetError().to_windowing_api_failure("eglCreateImageKHR")
+        });
+        return Err(Error::SurfaceImportFailed(Box::new(failure)));
+    }
+
+    let mut surface_texture = EGLSurfaceTexture::from_imported_image(gl,
+                                                                     context_id,
+                                                                     &info.size,
+                                                                     egl_image,
+                                                                     0,
+                                                                     gl_texture_target,
+                                                                     origin);
+
+    // Chroma planes are usually subsampled, by a factor that depends on the client's format.
+    if plane > 0 && gl_texture_target == gl::TEXTURE_2D {
+        surface_texture.surface.size = texture_size(gl, surface_texture.texture_object);
+    }
+    Ok(surface_texture)
+}
+
+// Copies a `wl_shm` buffer into a new RGBA texture. Only the two formats that every compositor
+// must support are accepted.
+unsafe fn upload_shm_buffer(gl: &Gl,
+                            egl_display: EGLDisplay,
+                            egl_context: EGLContext,
+                            buffer: *mut wl_resource)
+                            -> Result<(EGLImageKHR, GLuint), Error> {
+    let wayland_server = WAYLAND_SERVER_OPTION.as_ref().unwrap();
+    let shm_buffer = (wayland_server.wl_shm_buffer_get)(buffer);
+    let has_alpha = match (wayland_server.wl_shm_buffer_get_format)(shm_buffer) {
+        WL_SHM_FORMAT_ARGB8888 => true,
+        WL_SHM_FORMAT_XRGB8888 => false,
+        _ => return Err(Error::UnsupportedSurfaceFormat),
+    };
+    let width = (wayland_server.wl_shm_buffer_get_width)(shm_buffer);
+    let height = (wayland_server.wl_shm_buffer_get_height)(shm_buffer);
+    let stride = (wayland_server.wl_shm_buffer_get_stride)(shm_buffer);
+    let min_stride = match width.checked_mul(4) {
+        Some(min_stride) if width > 0 && height > 0 && stride >= min_stride => min_stride,
+        _ => return Err(Error::SurfaceImportFailed(WindowingApiError::BadParameter.into())),
+    };
+
+    // The pixels are little-endian ARGB, so BGRA in memory.
+    let row_len = min_stride as usize;
+    let mut pixels = vec![0; row_len * height as usize];
+    (wayland_server.wl_shm_buffer_begin_access)(shm_buffer);
+    let data = (wayland_server.wl_shm_buffer_get_data)(shm_buffer) as *const u8;
+    for (y, dest_row) in pixels.chunks_mut(row_len).enumerate() {
+        let src_row = slice::from_raw_parts(data.add(y * stride as usize), row_len);
+        for (src_pixel, dest_pixel) in src_row.chunks(4).zip(dest_row.chunks_mut(4)) {
+            let alpha = if has_alpha { src_pixel[3] } else { 0xff };
+            dest_pixel.copy_from_slice(&[src_pixel[2], src_pixel[1], src_pixel[0], alpha]);
+        }
+    }
+    (wayland_server.wl_shm_buffer_end_access)(shm_buffer);
+
+    surface::upload_image(gl,
+                          egl_display,
+                          egl_context,
+                          &Size2D::new(width, height),
+                          SurfaceFormat::RGBA8,
+                          row_len,
+                          pixels.as_ptr())
+}
+
+unsafe fn texture_size(gl: &Gl, texture_object: GLuint) -> Size2D<i32> {
+    let mut old_texture = 0;
+    gl.GetIntegerv(gl::TEXTURE_BINDING_2D, &mut old_texture);
+    gl.BindTexture(gl::TEXTURE_2D, texture_object);
+    let (mut width, mut height): (GLint, GLint) = (0, 0);
+    gl.GetTexLevelParameteriv(gl::TEXTURE_2D, 0, gl::TEXTURE_WIDTH, &mut width);
+    gl.GetTexLevelParameteriv(gl::TEXTURE_2D, 0, gl::TEXTURE_HEIGHT, &mut height);
+    gl.BindTexture(gl::TEXTURE_2D, old_texture as GLuint);
+    Size2D::new(width, height)
+}
@@ -2,12 +2,14 @@
 //
 //! A context abstraction that allows the choice of backends dynamically.
 
-use crate::{ContextAttributes, ContextID, Error, SurfaceInfo};
+use crate::{ContextAttributes, ContextDescriptorDiagnostics, ContextID, Error, GLApi};
+use crate::{GLInfo, PixelFormatAttributes, SurfaceInfo};
 use crate::device::Device as DeviceInterface;
 use super::device::Device;
 use super::surface::Surface;
 
 use std::os::raw::c_void;
+use std::rc::Rc;
 
 /// Represents an OpenGL rendering context.
 /// 
@@ -24,7 +26,8 @@ use std::os::raw::c_void;
 /// OpenGL objects may not be shared across contexts directly, but surface textures effectively
 /// allow for sharing of texture data. Contexts are local to a single thread and device.
 /// 
-/// A context must be explicitly destroyed with `destroy_context()`, or a panic will occur.
+/// A context should be explicitly destroyed with `destroy_context()`. A context that is dropped
+/// without being destroyed is handled according to the device's `LeakPolicy`.
 pub enum Context<Def, Alt> where Def: DeviceInterface, Alt: DeviceInterface {
     /// The default rendering context type.
     Default(Def::Context),
@@ -67,6 +70,64 @@ impl<Def, Alt> Device<Def, Alt> where Def: DeviceInterface, Alt: DeviceInterface
         }
     }
 
+    /// Returns a context descriptor for every pixel format that this device can create contexts
+    /// with, along with the attributes of each pixel format.
+    ///
+    /// The version and profile of the descriptors come from `attributes`. The `ALPHA`, `DEPTH`,
+    /// and `STENCIL` flags are ignored; use the pixel format attributes to tell the descriptors
+    /// apart.
+    pub fn enumerate_context_descriptors(&self, attributes: &ContextAttributes)
+                                         -> Result<Vec<(ContextDescriptor<Def, Alt>,
+                                                        PixelFormatAttributes)>, Error> {
+        match *self {
+            Device::Default(ref device) => {
+                Ok(device.enumerate_context_descriptors(attributes)?.into_iter().map(|candidate| {
+                    (ContextDescriptor::Default(candidate.0), candidate.1)
+                }).collect())
+            }
+            Device::Alternate(ref device) => {
+                Ok(device.enumerate_context_descriptors(attributes)?.into_iter().map(|candidate| {
+                    (ContextDescriptor::Alternate(candidate.0), candidate.1)
+                }).collect())
+            }
+        }
+    }
+
+    /// Creates a context descriptor from one of the pixel formats that
+    /// `enumerate_context_descriptors()` returns.
+    ///
+    /// `choose` receives the attributes of every candidate pixel format and returns the index of
+    /// the one to use. If it returns `None` or an index out of range, a `NoPixelFormatFound` error
+    /// is returned.
+    pub fn create_context_descriptor_with<F>(&self, attributes: &ContextAttributes, choose: F)
+                                             -> Result<ContextDescriptor<Def, Alt>, Error>
+                                             where F: FnOnce(&[PixelFormatAttributes]) ->
+                                                   Option<usize> {
+        match *self {
+            Device::Default(ref device) => {
+                device.create_context_descriptor_with(attributes, choose)
+                      .map(ContextDescriptor::Default)
+            }
+            Device::Alternate(ref device) => {
+                device.create_context_descriptor_with(attributes, choose)
+                      .map(ContextDescriptor::Alternate)
+            }
+        }
+    }
+
+    /// Explains which pixel formats `create_context_descriptor()` passes over for the given
+    /// attributes, and why.
+    ///
+    /// This is meant for finding out why `create_context_descriptor()` returned
+    /// `NoPixelFormatFound` or `UnsupportedGLProfile`.
+    pub fn diagnose_context_descriptor(&self, attributes: &ContextAttributes)
+                                       -> ContextDescriptorDiagnostics {
+        match *self {
+            Device::Default(ref device) => device.diagnose_context_descriptor(attributes),
+            Device::Alternate(ref device) => device.diagnose_context_descriptor(attributes),
+        }
+    }
+
     /// Creates a new OpenGL context.
     /// 
     /// The context initially has no surface attached. Until a surface is bound to it, rendering
@@ -259,6 +320,36 @@ impl<Def, Alt> Device<Def, Alt> where Def: DeviceInterface, Alt: DeviceInterface
         }
     }
 
+    /// Returns the OpenGL API flavor (OpenGL or OpenGL ES) that the context was created for.
+    pub fn context_gl_api(&self, context: &Context<Def, Alt>) -> GLApi {
+        match (self, context) {
+            (&Device::Default(ref device), &Context::Default(ref context)) => {
+                device.context_gl_api(context)
+            }
+            (&Device::Alternate(ref device), &Context::Alternate(ref context)) => {
+                device.context_gl_api(context)
+            }
+            _ => panic!("Incompatible context!"),
+        }
+    }
+
+    /// Returns what the OpenGL implementation reports about the context: its version and
+    /// profile, the vendor and renderer, and the extensions it supports.
+    ///
+    /// The context is queried the first time this is called, and the same information is
+    /// returned after that.
+    pub fn gl_info(&self, context: &Context<Def, Alt>) -> Result<Rc<GLInfo>, Error> {
+        match (self, context) {
+            (&Device::Default(ref device), &Context::Default(ref context)) => {
+                device.gl_info(context)
+            }
+            (&Device::Alternate(ref device), &Context::Alternate(ref context)) => {
+                device.gl_info(context)
+            }
+            _ => Err(Error::IncompatibleContext),
+        }
+    }
+
     /// Fetches the address of an OpenGL function associated with this context.
     /// 
     /// OpenGL functions are local to a context. You should not use OpenGL functions on one context
@@ -2,16 +2,22 @@
 //
 //! A device abstraction that allows the choice of backends dynamically.
 
-use crate::{ContextID, Error, GLApi, SurfaceAccess, SurfaceInfo, SurfaceType};
+use crate::{ContextID, Error, GLApi, SurfaceAccess, SurfaceFormat, SurfaceInfo, SurfaceType};
+use crate::{DeviceCapabilities, GLInfo, LeakPolicy, PixelFormatAttributes, SurfaceTextureTarget};
 use crate::connection::Connection as ConnectionInterface;
-use crate::context::ContextAttributes;
+use crate::context::{ContextAttributes, ContextDescriptorDiagnostics};
 use crate::device::Device as DeviceInterface;
 use crate::gl::types::{GLenum, GLuint};
+use crate::ipc::SurfaceHandle;
+#[cfg(unix)]
+use crate::video::PlanarImage;
 use super::connection::Connection;
 use super::context::{Context, ContextDescriptor, NativeContext};
-use super::surface::{NativeWidget, Surface, SurfaceTexture};
+use super::surface::{NativeImage, NativeWidget, Surface, SurfaceTexture};
 
+use euclid::default::Rect;
 use std::os::raw::c_void;
+use std::rc::Rc;
 
 /// Represents a hardware display adapter that can be used for rendering (including the CPU).
 ///
@@ -83,13 +89,39 @@ impl<Def, Alt> Device<Def, Alt> where Def: DeviceInterface,
         }
     }
 
-    /// Returns the OpenGL API flavor that this device supports (OpenGL or OpenGL ES).
+    /// Returns the OpenGL API flavor that contexts on this device use by default (OpenGL or
+    /// OpenGL ES).
     pub fn gl_api(&self) -> GLApi {
         match *self {
             Device::Default(ref device) => device.gl_api(),
             Device::Alternate(ref device) => device.gl_api(),
         }
     }
+
+    /// Returns the optional features that this device supports.
+    pub fn capabilities(&self) -> DeviceCapabilities {
+        match *self {
+            Device::Default(ref device) => device.capabilities(),
+            Device::Alternate(ref device) => device.capabilities(),
+        }
+    }
+
+    /// Sets what happens to contexts and surfaces created with this device that are dropped
+    /// without being destroyed, overriding the global leak policy.
+    pub fn set_leak_policy(&self, policy: LeakPolicy) {
+        match *self {
+            Device::Default(ref device) => device.set_leak_policy(policy),
+            Device::Alternate(ref device) => device.set_leak_policy(policy),
+        }
+    }
+
+    /// Returns the leak policy that this device follows.
+    pub fn leak_policy(&self) -> LeakPolicy {
+        match *self {
+            Device::Default(ref device) => device.leak_policy(),
+            Device::Alternate(ref device) => device.leak_policy(),
+        }
+    }
 }
 
 impl<Def, Alt> DeviceInterface for Device<Def, Alt>
@@ -101,6 +133,7 @@ impl<Def, Alt> DeviceInterface for Device<Def, Alt>
     type Context = Context<Def, Alt>;
     type ContextDescriptor = ContextDescriptor<Def, Alt>;
     type NativeContext = NativeContext<Def, Alt>;
+    type NativeImage = NativeImage<Def, Alt>;
     type Surface = Surface<Def, Alt>;
     type SurfaceTexture = SurfaceTexture<Def, Alt>;
 
@@ -126,6 +159,21 @@ impl<Def, Alt> DeviceInterface for Device<Def, Alt>
         Device::gl_api(self)
     }
 
+    #[inline]
+    fn capabilities(&self) -> DeviceCapabilities {
+        Device::capabilities(self)
+    }
+
+    #[inline]
+    fn set_leak_policy(&self, policy: LeakPolicy) {
+        Device::set_leak_policy(self, policy)
+    }
+
+    #[inline]
+    fn leak_policy(&self) -> LeakPolicy {
+        Device::leak_policy(self)
+    }
+
     // context.rs
 
     #[inline]
@@ -134,6 +182,27 @@ impl<Def, Alt> DeviceInterface for Device<Def, Alt>
         Device::create_context_descriptor(self, attributes)
     }
 
+    #[inline]
+    fn enumerate_context_descriptors(&self, attributes: &ContextAttributes)
+                                     -> Result<Vec<(Self::ContextDescriptor,
+                                                    PixelFormatAttributes)>, Error> {
+        Device::enumerate_context_descriptors(self, attributes)
+    }
+
+    #[inline]
+    fn create_context_descriptor_with<F>(&self, attributes: &ContextAttributes, choose: F)
+                                         -> Result<Self::ContextDescriptor, Error>
+                                         where F: FnOnce(&[PixelFormatAttributes]) ->
+                                               Option<usize> {
+        Device::create_context_descriptor_with(self, attributes, choose)
+    }
+
+    #[inline]
+    fn diagnose_context_descriptor(&self, attributes: &ContextAttributes)
+                                   -> ContextDescriptorDiagnostics {
+        Device::diagnose_context_descriptor(self, attributes)
+    }
+
     #[inline]
     fn create_context(&mut self, descriptor: &ContextDescriptor<Def, Alt>)
                       -> Result<Context<Def, Alt>, Error> {
@@ -177,6 +246,16 @@ impl<Def, Alt> DeviceInterface for Device<Def, Alt>
         Device::context_descriptor_attributes(self, context_descriptor)
     }
 
+    #[inline]
+    fn context_gl_api(&self, context: &Context<Def, Alt>) -> GLApi {
+        Device::context_gl_api(self, context)
+    }
+
+    #[inline]
+    fn gl_info(&self, context: &Context<Def, Alt>) -> Result<Rc<GLInfo>, Error> {
+        Device::gl_info(self, context)
+    }
+
     #[inline]
     fn get_proc_address(&self, context: &Context<Def, Alt>, symbol_name: &str) -> *const c_void {
         Device::get_proc_address(self, context, symbol_name)
@@ -216,6 +295,14 @@ impl<Def, Alt> DeviceInterface for Device<Def, Alt>
         Device::create_surface(self, context, surface_access, surface_type)
     }
 
+    #[inline]
+    unsafe fn create_surface_from_native_image(&self,
+                                               context: &Context<Def, Alt>,
+                                               native_image: NativeImage<Def, Alt>)
+                                               -> Result<Surface<Def, Alt>, Error> {
+        Device::create_surface_from_native_image(self, context, native_image)
+    }
+
     #[inline]
     fn create_surface_texture(&self, context: &mut Context<Def, Alt>, surface: Surface<Def, Alt>)
                               -> Result<SurfaceTexture<Def, Alt>, (Error, Surface<Def, Alt>)> {
@@ -241,12 +328,29 @@ impl<Def, Alt> DeviceInterface for Device<Def, Alt>
         Device::surface_gl_texture_target(self)
     }
 
+    #[inline]
+    fn supported_surface_formats(&self, context: &Context<Def, Alt>) -> Vec<SurfaceFormat> {
+        Device::supported_surface_formats(self, context)
+    }
+
     #[inline]
     fn present_surface(&self, context: &Context<Def, Alt>, surface: &mut Surface<Def, Alt>)
                        -> Result<(), Error> {
         Device::present_surface(self, context, surface)
     }
 
+    #[inline]
+    fn upload_surface_data(&self,
+                           context: &Context<Def, Alt>,
+                           surface: &mut Surface<Def, Alt>,
+                           rect: &Rect<i32>,
+                           data: &[u8],
+                           stride: usize,
+                           format: SurfaceFormat)
+                           -> Result<(), Error> {
+        Device::upload_surface_data(self, context, surface, rect, data, stride, format)
+    }
+
     #[inline]
     fn surface_info(&self, surface: &Surface<Def, Alt>) -> SurfaceInfo {
         Device::surface_info(self, surface)
@@ -256,4 +360,52 @@ impl<Def, Alt> DeviceInterface for Device<Def, Alt>
     fn surface_texture_object(&self, surface_texture: &SurfaceTexture<Def, Alt>) -> GLuint {
         Device::surface_texture_object(self, surface_texture)
     }
+
+    #[inline]
+    fn surface_texture_depth_object(&self, surface_texture: &SurfaceTexture<Def, Alt>)
+                                    -> Option<GLuint> {
+        Device::surface_texture_depth_object(self, surface_texture)
+    }
+
+    #[inline]
+    fn surface_texture_layer_object(&self, surface_texture: &SurfaceTexture<Def, Alt>, layer: u32)
+                                    -> Option<GLuint> {
+        Device::surface_texture_layer_object(self, surface_texture, layer)
+    }
+
+    #[inline]
+    fn surface_texture_layer_rect(&self, surface_texture: &SurfaceTexture<Def, Alt>, layer: u32)
+                                  -> Option<Rect<i32>> {
+        Device::surface_texture_layer_rect(self, surface_texture, layer)
+    }
+
+    #[inline]
+    fn surface_texture_target(&self, surface_texture: &SurfaceTexture<Def, Alt>) -> GLenum {
+        Device::surface_texture_target(self, surface_texture)
+    }
+
+    #[inline]
+    fn export_surface(&self, context: &Context<Def, Alt>, surface: &Surface<Def, Alt>)
+                      -> Result<SurfaceHandle, Error> {
+        Device::export_surface(self, context, surface)
+    }
+
+    #[inline]
+    fn create_surface_texture_from_handle(&self,
+                                          context: &mut Context<Def, Alt>,
+                                          handle: SurfaceHandle,
+                                          target: SurfaceTextureTarget)
+                                          -> Result<SurfaceTexture<Def, Alt>, Error> {
+        Device::create_surface_texture_from_handle(self, context, handle, target)
+    }
+
+    #[cfg(unix)]
+    #[inline]
+    fn create_surface_texture_from_planes(&self,
+                                          context: &mut Context<Def, Alt>,
+                                          image: &PlanarImage,
+                                          target: SurfaceTextureTarget)
+                                          -> Result<SurfaceTexture<Def, Alt>, Error> {
+        Device::create_surface_texture_from_planes(self, context, image, target)
+    }
 }
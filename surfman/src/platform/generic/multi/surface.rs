@@ -5,10 +5,14 @@
 use crate::connection::Connection as ConnectionInterface;
 use crate::device::Device as DeviceInterface;
 use crate::gl::types::{GLenum, GLuint};
-use crate::{Error, SurfaceAccess, SurfaceInfo, SurfaceType};
+use crate::ipc::SurfaceHandle;
+#[cfg(unix)]
+use crate::video::PlanarImage;
+use crate::{Error, SurfaceAccess, SurfaceFormat, SurfaceInfo, SurfaceTextureTarget, SurfaceType};
 use super::context::Context;
 use super::device::Device;
 
+use euclid::default::Rect;
 use std::fmt::{self, Debug, Formatter};
 
 /// Represents a hardware buffer of pixels that can be rendered to via the CPU or GPU and either
@@ -26,7 +30,8 @@ use std::fmt::{self, Debug, Formatter};
 /// 
 /// Depending on the platform, each surface may be internally double-buffered.
 /// 
-/// Surfaces must be destroyed with the `destroy_surface()` method, or a panic will occur.
+/// Surfaces should be destroyed with the `destroy_surface()` method. A surface that is dropped
+/// without being destroyed is handled according to the device's `LeakPolicy`.
 pub enum Surface<Def, Alt> where Def: DeviceInterface, Alt: DeviceInterface {
     /// The default surface type.
     Default(Def::Surface),
@@ -41,8 +46,9 @@ pub enum Surface<Def, Alt> where Def: DeviceInterface, Alt: DeviceInterface {
 /// framebuffer).
 /// 
 /// Surface textures are local to a context, but that context does not have to be the same context
-/// as that associated with the underlying surface. The texture must be destroyed with the
-/// `destroy_surface_texture()` method, or a panic will occur.
+/// as that associated with the underlying surface. The texture should be destroyed with the
+/// `destroy_surface_texture()` method. Dropping it instead leaks the texture object and drops its
+/// surface, which is handled according to the device's `LeakPolicy`.
 pub enum SurfaceTexture<Def, Alt> where Def: DeviceInterface, Alt: DeviceInterface {
     /// The default surface texture type.
     Default(Def::SurfaceTexture),
@@ -58,6 +64,14 @@ pub enum NativeWidget<Def, Alt> where Def: DeviceInterface, Alt: DeviceInterface
     Alternate(<Alt::Connection as ConnectionInterface>::NativeWidget),
 }
 
+/// A native image type that can dynamically switch between backends.
+pub enum NativeImage<Def, Alt> where Def: DeviceInterface, Alt: DeviceInterface {
+    /// The default native image type.
+    Default(Def::NativeImage),
+    /// The alternate native image type.
+    Alternate(Alt::NativeImage),
+}
+
 impl<Def, Alt> Debug for Surface<Def, Alt> where Def: DeviceInterface, Alt: DeviceInterface {
     fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
         write!(f, "Surface")
@@ -84,11 +98,15 @@ impl<Def, Alt> Device<Def, Alt> where Def: DeviceInterface, Alt: DeviceInterface
         match (&mut *self, context) {
             (&mut Device::Default(ref mut device), &Context::Default(ref context)) => {
                 let surface_type = match surface_type {
-                    SurfaceType::Generic { size } => SurfaceType::Generic { size },
+                    SurfaceType::Generic { size, format, depth_stencil } => {
+                        SurfaceType::Generic { size, format, depth_stencil }
+                    }
+                    SurfaceType::Layered { size, layers } => SurfaceType::Layered { size, layers },
                     SurfaceType::Widget {
                         native_widget: NativeWidget::Default(native_widget),
-                    } => SurfaceType::Widget { native_widget },
-                    SurfaceType::Widget { native_widget: _ } => {
+                        color_space,
+                    } => SurfaceType::Widget { native_widget, color_space },
+                    SurfaceType::Widget { .. } => {
                         return Err(Error::IncompatibleNativeWidget)
                     }
                 };
@@ -97,11 +115,15 @@ impl<Def, Alt> Device<Def, Alt> where Def: DeviceInterface, Alt: DeviceInterface
             }
             (&mut Device::Alternate(ref mut device), &Context::Alternate(ref context)) => {
                 let surface_type = match surface_type {
-                    SurfaceType::Generic { size } => SurfaceType::Generic { size },
+                    SurfaceType::Generic { size, format, depth_stencil } => {
+                        SurfaceType::Generic { size, format, depth_stencil }
+                    }
+                    SurfaceType::Layered { size, layers } => SurfaceType::Layered { size, layers },
                     SurfaceType::Widget {
                         native_widget: NativeWidget::Alternate(native_widget),
-                    } => SurfaceType::Widget { native_widget },
-                    SurfaceType::Widget { native_widget: _ } => {
+                        color_space,
+                    } => SurfaceType::Widget { native_widget, color_space },
+                    SurfaceType::Widget { .. } => {
                         return Err(Error::IncompatibleNativeWidget)
                     }
                 };
@@ -112,6 +134,38 @@ impl<Def, Alt> Device<Def, Alt> where Def: DeviceInterface, Alt: DeviceInterface
         }
     }
 
+    /// Adopts an image that another library created as a generic surface, without taking
+    /// ownership of it.
+    /// 
+    /// The resulting surface can be bound to the given context and wrapped in a `SurfaceTexture`
+    /// like any other generic surface. Destroying the surface leaves the native image alone.
+    pub unsafe fn create_surface_from_native_image(&self,
+                                                   context: &Context<Def, Alt>,
+                                                   native_image: NativeImage<Def, Alt>)
+                                                   -> Result<Surface<Def, Alt>, Error> {
+        match (self, context) {
+            (&Device::Default(ref device), &Context::Default(ref context)) => {
+                match native_image {
+                    NativeImage::Default(native_image) => {
+                        device.create_surface_from_native_image(context, native_image)
+                              .map(Surface::Default)
+                    }
+                    _ => Err(Error::IncompatibleNativeImage),
+                }
+            }
+            (&Device::Alternate(ref device), &Context::Alternate(ref context)) => {
+                match native_image {
+                    NativeImage::Alternate(native_image) => {
+                        device.create_surface_from_native_image(context, native_image)
+                              .map(Surface::Alternate)
+                    }
+                    _ => Err(Error::IncompatibleNativeImage),
+                }
+            }
+            _ => Err(Error::IncompatibleContext),
+        }
+    }
+
     /// Creates a surface texture from an existing generic surface for use with the given context.
     /// 
     /// The surface texture is local to the supplied context and takes ownership of the surface.
@@ -158,8 +212,8 @@ impl<Def, Alt> Device<Def, Alt> where Def: DeviceInterface, Alt: DeviceInterface
     /// The supplied context must be the context the surface is associated with, or this returns
     /// an `IncompatibleSurface` error.
     /// 
-    /// You must explicitly call this method to dispose of a surface. Otherwise, a panic occurs in
-    /// the `drop` method.
+    /// You should explicitly call this method to dispose of a surface. Otherwise, the surface is
+    /// handled according to the device's `LeakPolicy` when it is dropped.
     pub fn destroy_surface(&self, context: &mut Context<Def, Alt>, surface: &mut Surface<Def, Alt>)
                            -> Result<(), Error> {
         match (self, &mut *context) {
@@ -184,8 +238,9 @@ impl<Def, Alt> Device<Def, Alt> where Def: DeviceInterface, Alt: DeviceInterface
     /// The supplied context must be the same context the surface texture was created with, or an
     /// `IncompatibleSurfaceTexture` error is returned.
     /// 
-    /// All surface textures must be explicitly destroyed with this function, or a panic will
-    /// occur.
+    /// All surface textures should be explicitly destroyed with this function. Otherwise, the
+    /// texture object is leaked and the surface is handled according to the device's `LeakPolicy`
+    /// when the surface texture is dropped.
     pub fn destroy_surface_texture(&self,
                                    context: &mut Context<Def, Alt>,
                                    surface_texture: SurfaceTexture<Def, Alt>)
@@ -250,6 +305,39 @@ impl<Def, Alt> Device<Def, Alt> where Def: DeviceInterface, Alt: DeviceInterface
         }
     }
 
+    /// Copies pixels from the CPU into a rectangle of a generic surface.
+    /// 
+    /// `data` holds the rows of `rect` in order, `stride` bytes apart, in the given format, which
+    /// must be the surface's format.
+    pub fn upload_surface_data(&self,
+                               context: &Context<Def, Alt>,
+                               surface: &mut Surface<Def, Alt>,
+                               rect: &Rect<i32>,
+                               data: &[u8],
+                               stride: usize,
+                               format: SurfaceFormat)
+                               -> Result<(), Error> {
+        match (self, context) {
+            (&Device::Default(ref device), &Context::Default(ref context)) => {
+                match *surface {
+                    Surface::Default(ref mut surface) => {
+                        device.upload_surface_data(context, surface, rect, data, stride, format)
+                    }
+                    _ => Err(Error::IncompatibleSurface),
+                }
+            }
+            (&Device::Alternate(ref device), &Context::Alternate(ref context)) => {
+                match *surface {
+                    Surface::Alternate(ref mut surface) => {
+                        device.upload_surface_data(context, surface, rect, data, stride, format)
+                    }
+                    _ => Err(Error::IncompatibleSurface),
+                }
+            }
+            _ => Err(Error::IncompatibleContext),
+        }
+    }
+
     /// Returns the OpenGL texture target needed to read from this surface texture.
     /// 
     /// This will be `GL_TEXTURE_2D` or `GL_TEXTURE_RECTANGLE`, depending on platform.
@@ -261,6 +349,22 @@ impl<Def, Alt> Device<Def, Alt> where Def: DeviceInterface, Alt: DeviceInterface
         }
     }
 
+    /// Returns the formats that generic surfaces created on this device with the given context may
+    /// have.
+    /// 
+    /// If the context doesn't belong to this device, no formats are supported.
+    pub fn supported_surface_formats(&self, context: &Context<Def, Alt>) -> Vec<SurfaceFormat> {
+        match (self, context) {
+            (&Device::Default(ref device), &Context::Default(ref context)) => {
+                device.supported_surface_formats(context)
+            }
+            (&Device::Alternate(ref device), &Context::Alternate(ref context)) => {
+                device.supported_surface_formats(context)
+            }
+            _ => vec![],
+        }
+    }
+
     /// Returns various information about the surface, including the framebuffer object needed to
     /// render to this surface.
     /// 
@@ -293,4 +397,151 @@ impl<Def, Alt> Device<Def, Alt> where Def: DeviceInterface, Alt: DeviceInterface
             _ => panic!("Incompatible context!"),
         }
     }
+
+    /// Returns the OpenGL texture object containing the depth buffer of the surface, or `None` if
+    /// the surface wasn't created with a sampleable depth buffer.
+    /// 
+    /// It is only legal to read from, not write to, this texture object.
+    pub fn surface_texture_depth_object(&self, surface_texture: &SurfaceTexture<Def, Alt>)
+                                        -> Option<GLuint> {
+        match (self, surface_texture) {
+            (&Device::Default(ref device), SurfaceTexture::Default(ref surface_texture)) => {
+                device.surface_texture_depth_object(surface_texture)
+            }
+            (&Device::Alternate(ref device), SurfaceTexture::Alternate(ref surface_texture)) => {
+                device.surface_texture_depth_object(surface_texture)
+            }
+            _ => panic!("Incompatible context!"),
+        }
+    }
+
+    /// Returns the OpenGL texture object containing the given layer of the surface, or `None` if
+    /// the surface has no such layer.
+    /// 
+    /// This is `surface_texture_object()` unless the surface's layers are stored in an array
+    /// texture, in which case each layer has a texture of its own.
+    pub fn surface_texture_layer_object(&self,
+                                        surface_texture: &SurfaceTexture<Def, Alt>,
+                                        layer: u32)
+                                        -> Option<GLuint> {
+        match (self, surface_texture) {
+            (&Device::Default(ref device), SurfaceTexture::Default(ref surface_texture)) => {
+                device.surface_texture_layer_object(surface_texture, layer)
+            }
+            (&Device::Alternate(ref device), SurfaceTexture::Alternate(ref surface_texture)) => {
+                device.surface_texture_layer_object(surface_texture, layer)
+            }
+            _ => panic!("Incompatible context!"),
+        }
+    }
+
+    /// Returns the region of `surface_texture_layer_object()` that holds the given layer of the
+    /// surface, in texels, or `None` if the surface has no such layer.
+    /// 
+    /// Surfaces that aren't layered have a single layer that covers the whole texture.
+    pub fn surface_texture_layer_rect(&self, surface_texture: &SurfaceTexture<Def, Alt>, layer: u32)
+                                      -> Option<Rect<i32>> {
+        match (self, surface_texture) {
+            (&Device::Default(ref device), SurfaceTexture::Default(ref surface_texture)) => {
+                device.surface_texture_layer_rect(surface_texture, layer)
+            }
+            (&Device::Alternate(ref device), SurfaceTexture::Alternate(ref surface_texture)) => {
+                device.surface_texture_layer_rect(surface_texture, layer)
+            }
+            _ => panic!("Incompatible context!"),
+        }
+    }
+
+    /// Returns the OpenGL texture target that the surface texture's texture object must be bound
+    /// to.
+    /// 
+    /// This is `GL_TEXTURE_2D`, `GL_TEXTURE_RECTANGLE`, or `GL_TEXTURE_EXTERNAL_OES`. The last
+    /// is only possible for textures created from handles with `SurfaceTextureTarget::Any`.
+    pub fn surface_texture_target(&self, surface_texture: &SurfaceTexture<Def, Alt>) -> GLenum {
+        match (self, surface_texture) {
+            (&Device::Default(ref device), SurfaceTexture::Default(ref surface_texture)) => {
+                device.surface_texture_target(surface_texture)
+            }
+            (&Device::Alternate(ref device), SurfaceTexture::Alternate(ref surface_texture)) => {
+                device.surface_texture_target(surface_texture)
+            }
+            _ => panic!("Incompatible context!"),
+        }
+    }
+
+    /// Exports a generic surface so that it can be sampled from in another process.
+    /// 
+    /// The supplied context must be the context the surface is associated with. The returned
+    /// handle shares memory with the surface when the platform supports it; otherwise, it
+    /// contains a snapshot of the surface contents in shared memory.
+    /// 
+    /// Calling this method on a widget surface returns a `WidgetAttached` error.
+    pub fn export_surface(&self, context: &Context<Def, Alt>, surface: &Surface<Def, Alt>)
+                          -> Result<SurfaceHandle, Error> {
+        match (self, context) {
+            (&Device::Default(ref device), &Context::Default(ref context)) => {
+                match *surface {
+                    Surface::Default(ref surface) => device.export_surface(context, surface),
+                    _ => Err(Error::IncompatibleSurface),
+                }
+            }
+            (&Device::Alternate(ref device), &Context::Alternate(ref context)) => {
+                match *surface {
+                    Surface::Alternate(ref surface) => device.export_surface(context, surface),
+                    _ => Err(Error::IncompatibleSurface),
+                }
+            }
+            _ => Err(Error::IncompatibleContext),
+        }
+    }
+
+    /// Wraps a surface exported from another process in a surface texture local to the given
+    /// context.
+    /// 
+    /// The surface that the returned texture wraps can only be read from. Destroy it with
+    /// `destroy_surface_texture()` followed by `destroy_surface()` as usual.
+    /// 
+    /// Some images, such as YUV dma-bufs, can only be sampled as external textures. Pass
+    /// `SurfaceTextureTarget::Compatible2D` if your shaders can't use `samplerExternalOES`; an
+    /// `UnsupportedTextureTarget` error is returned for such images.
+    pub fn create_surface_texture_from_handle(&self,
+                                              context: &mut Context<Def, Alt>,
+                                              handle: SurfaceHandle,
+                                              target: SurfaceTextureTarget)
+                                              -> Result<SurfaceTexture<Def, Alt>, Error> {
+        match (self, &mut *context) {
+            (&Device::Default(ref device), &mut Context::Default(ref mut context)) => {
+                device.create_surface_texture_from_handle(context, handle, target)
+                      .map(SurfaceTexture::Default)
+            }
+            (&Device::Alternate(ref device), &mut Context::Alternate(ref mut context)) => {
+                device.create_surface_texture_from_handle(context, handle, target)
+                      .map(SurfaceTexture::Alternate)
+            }
+            _ => Err(Error::IncompatibleContext),
+        }
+    }
+
+    /// Wraps a decoded video frame in a surface texture local to the given context.
+    /// 
+    /// The frame is imported without a copy where possible; otherwise, its planes are converted
+    /// to RGBA on the CPU. The first row of the frame is row 0 of the texture.
+    #[cfg(unix)]
+    pub fn create_surface_texture_from_planes(&self,
+                                              context: &mut Context<Def, Alt>,
+                                              image: &PlanarImage,
+                                              target: SurfaceTextureTarget)
+                                              -> Result<SurfaceTexture<Def, Alt>, Error> {
+        match (self, &mut *context) {
+            (&Device::Default(ref device), &mut Context::Default(ref mut context)) => {
+                device.create_surface_texture_from_planes(context, image, target)
+                      .map(SurfaceTexture::Default)
+            }
+            (&Device::Alternate(ref device), &mut Context::Alternate(ref mut context)) => {
+                device.create_surface_texture_from_planes(context, image, target)
+                      .map(SurfaceTexture::Alternate)
+            }
+            _ => Err(Error::IncompatibleContext),
+        }
+    }
 }
@@ -2,10 +2,14 @@
 //
 //! Wrapper for Core OpenGL contexts.
 
-use crate::context::{CREATE_CONTEXT_MUTEX, ContextID};
+use crate::context::{self, CREATE_CONTEXT_MUTEX, ContextID, PixelFormatSurfaceTypes};
+use crate::context::{ContextDescriptorDiagnostics, choose_context_descriptor};
 use crate::gl_utils;
+use crate::info::GLInfoCache;
+use crate::leak::{LeakedObject, Origin};
 use crate::surface::Framebuffer;
-use crate::{ContextAttributeFlags, ContextAttributes, Error, GLVersion, Gl, SurfaceInfo};
+use crate::{ContextAttributeFlags, ContextAttributes, Error, GLApi, GLVersion, Gl};
+use crate::{GLInfo, PixelFormatAttributes, SurfaceInfo};
 use super::device::Device;
 use super::error::ToWindowingApiError;
 use super::ffi::{CGLReleaseContext, CGLRetainContext};
@@ -14,7 +18,8 @@ use super::surface::Surface;
 use cgl::{CGLChoosePixelFormat, CGLContextObj, CGLCreateContext, CGLDescribePixelFormat, CGLError};
 use cgl::{CGLGetCurrentContext, CGLGetPixelFormat, CGLPixelFormatAttribute, CGLPixelFormatObj};
 use cgl::{CGLReleasePixelFormat, CGLRetainPixelFormat, CGLSetCurrentContext};
-use cgl::{kCGLPFAAllowOfflineRenderers, kCGLPFAAlphaSize, kCGLPFADepthSize};
+use cgl::{kCGLPFAAllowOfflineRenderers, kCGLPFAAlphaSize, kCGLPFAColorFloat, kCGLPFAColorSize};
+use cgl::{kCGLPFADepthSize, kCGLPFAMultisample, kCGLPFASampleBuffers, kCGLPFASamples};
 use cgl::{kCGLPFAStencilSize, kCGLPFAOpenGLProfile};
 use core_foundation::base::TCFType;
 use core_foundation::bundle::CFBundleGetBundleWithIdentifier;
@@ -24,8 +29,8 @@ use core_foundation::string::CFString;
 use std::mem;
 use std::os::raw::c_void;
 use std::ptr;
+use std::rc::Rc;
 use std::str::FromStr;
-use std::thread;
 
 // No CGL error occurred.
 #[allow(non_upper_case_globals)]
@@ -76,21 +81,23 @@ thread_local! {
 /// OpenGL objects may not be shared across contexts directly, but surface textures effectively
 /// allow for sharing of texture data. Contexts are local to a single thread and device.
 /// 
-/// A context must be explicitly destroyed with `destroy_context()`, or a panic will occur.
+/// A context should be explicitly destroyed with `destroy_context()`. A context that is dropped
+/// without being destroyed is handled according to the device's `LeakPolicy`.
 pub struct Context {
     pub(crate) cgl_context: CGLContextObj,
     pub(crate) id: ContextID,
+    gl_info: GLInfoCache,
     framebuffer: Framebuffer<Surface, ()>,
+    origin: Origin,
 }
 
 /// Wraps a native CGL context object.
 pub struct NativeContext(pub CGLContextObj);
 
 impl Drop for Context {
-    #[inline]
     fn drop(&mut self) {
-        if !self.cgl_context.is_null() && !thread::panicking() {
-            panic!("Contexts must be destroyed explicitly with `destroy_context`!")
+        if !self.cgl_context.is_null() {
+            self.origin.report_leak(LeakedObject::Context, false);
         }
     }
 }
@@ -125,36 +132,181 @@ impl Clone for ContextDescriptor {
 
 unsafe impl Send for ContextDescriptor {}
 
+impl ContextDescriptor {
+    unsafe fn get_attribute(&self, attribute: CGLPixelFormatAttribute) -> i32 {
+        let mut value = 0;
+        let err = CGLDescribePixelFormat(self.cgl_pixel_format, 0, attribute, &mut value);
+        debug_assert_eq!(err, kCGLNoError);
+        value
+    }
+
+    fn pixel_format_attributes(&self) -> PixelFormatAttributes {
+        unsafe {
+            // CGL reports the size of a whole pixel, padding included. Its formats have four
+            // equal channels, which are 8 bits each unless the format is floating point.
+            let color_size = self.get_attribute(kCGLPFAColorSize) as u32;
+            let channel_size = if color_size <= 32 { 8 } else { color_size / 4 };
+            let samples = if self.get_attribute(kCGLPFASampleBuffers) == 0 {
+                0
+            } else {
+                self.get_attribute(kCGLPFASamples) as u32
+            };
+
+            // Surfaces on macOS are always framebuffer objects, never native GL surfaces.
+            PixelFormatAttributes {
+                red_size: channel_size,
+                green_size: channel_size,
+                blue_size: channel_size,
+                alpha_size: self.get_attribute(kCGLPFAAlphaSize) as u32,
+                depth_size: self.get_attribute(kCGLPFADepthSize) as u32,
+                stencil_size: self.get_attribute(kCGLPFAStencilSize) as u32,
+                samples,
+                surface_types: PixelFormatSurfaceTypes::empty(),
+                native_visual_id: None,
+            }
+        }
+    }
+}
+
 impl Device {
     /// Creates a context descriptor with the given attributes.
     /// 
     /// Context descriptors are local to this device.
     pub fn create_context_descriptor(&self, attributes: &ContextAttributes)
                                      -> Result<ContextDescriptor, Error> {
-        if attributes.flags.contains(ContextAttributeFlags::COMPATIBILITY_PROFILE) &&
-                attributes.version.major > 2 {
-            return Err(Error::UnsupportedGLProfile);
-        };
-
-        let profile = if attributes.version.major >= 4 {
-            kCGLOGLPVersion_GL4_Core
-        } else if attributes.version.major == 3 {
-            kCGLOGLPVersion_3_2_Core
-        } else {
-            kCGLOGLPVersion_Legacy
-        };
-
         let flags = attributes.flags;
         let alpha_size   = if flags.contains(ContextAttributeFlags::ALPHA)   { 8  } else { 0 };
         let depth_size   = if flags.contains(ContextAttributeFlags::DEPTH)   { 24 } else { 0 };
         let stencil_size = if flags.contains(ContextAttributeFlags::STENCIL) { 8  } else { 0 };
 
-        let mut cgl_pixel_format_attributes = vec![
-            kCGLPFAOpenGLProfile, profile,
-            kCGLPFAAlphaSize,     alpha_size,
-            kCGLPFADepthSize,     depth_size,
-            kCGLPFAStencilSize,   stencil_size,
-        ];
+        // The profile of the pixel format determines the version of the context, so work down
+        // through the acceptable profiles until one has a matching pixel format.
+        for profile in candidate_profiles(attributes)? {
+            let mut cgl_pixel_format_attributes = self.base_pixel_format_attributes(profile);
+            cgl_pixel_format_attributes.extend_from_slice(&[
+                kCGLPFAAlphaSize,     alpha_size,
+                kCGLPFADepthSize,     depth_size,
+                kCGLPFAStencilSize,   stencil_size,
+                0,                    0,
+            ]);
+
+            unsafe {
+                if let Some(cgl_pixel_format) = choose_pixel_format(&cgl_pixel_format_attributes)? {
+                    return Ok(ContextDescriptor { cgl_pixel_format });
+                }
+            }
+        }
+
+        debug!("surfman: No CGL pixel format found; {}",
+               self.diagnose_context_descriptor(attributes));
+        Err(Error::NoPixelFormatFound)
+    }
+
+    /// Returns a context descriptor for every pixel format that this device can create contexts
+    /// with, along with the attributes of each pixel format.
+    ///
+    /// The version and profile of the descriptors come from `attributes`; they use the highest
+    /// profile in the requested range that has any pixel formats. The `ALPHA`, `DEPTH`, and
+    /// `STENCIL` flags are ignored; use the pixel format attributes to tell the descriptors apart.
+    ///
+    /// CGL can't list pixel formats, so this asks it for each combination of 8-bit or
+    /// floating-point color, alpha, depth, stencil, and multisampling, and returns the distinct
+    /// formats it picks.
+    pub fn enumerate_context_descriptors(&self, attributes: &ContextAttributes)
+                                         -> Result<Vec<(ContextDescriptor, PixelFormatAttributes)>,
+                                                   Error> {
+        for profile in candidate_profiles(attributes)? {
+            let candidates = self.enumerate_context_descriptors_for_profile(profile)?;
+            if !candidates.is_empty() {
+                return Ok(candidates);
+            }
+        }
+        Ok(vec![])
+    }
+
+    fn enumerate_context_descriptors_for_profile(&self, profile: CGLPixelFormatAttribute)
+                                                 -> Result<Vec<(ContextDescriptor,
+                                                                PixelFormatAttributes)>,
+                                                           Error> {
+        let base_attributes = self.base_pixel_format_attributes(profile);
+
+        let mut candidates: Vec<(ContextDescriptor, PixelFormatAttributes)> = vec![];
+        for &(color_size, color_float) in &[(24, false), (64, true)] {
+            for &alpha_size in &[0, 8] {
+                for &depth_size in &[0, 24] {
+                    for &stencil_size in &[0, 8] {
+                        for &samples in &[0, 4] {
+                            let mut cgl_pixel_format_attributes = base_attributes.clone();
+                            cgl_pixel_format_attributes.extend_from_slice(&[
+                                kCGLPFAColorSize,     color_size,
+                                kCGLPFAAlphaSize,     alpha_size,
+                                kCGLPFADepthSize,     depth_size,
+                                kCGLPFAStencilSize,   stencil_size,
+                            ]);
+                            if color_float {
+                                cgl_pixel_format_attributes.push(kCGLPFAColorFloat);
+                            }
+                            if samples > 0 {
+                                cgl_pixel_format_attributes.extend_from_slice(&[
+                                    kCGLPFAMultisample,
+                                    kCGLPFASampleBuffers, 1,
+                                    kCGLPFASamples,       samples,
+                                ]);
+                            }
+                            cgl_pixel_format_attributes.extend_from_slice(&[0, 0]);
+
+                            let descriptor = unsafe {
+                                match choose_pixel_format(&cgl_pixel_format_attributes)? {
+                                    None => continue,
+                                    Some(cgl_pixel_format) => {
+                                        ContextDescriptor { cgl_pixel_format }
+                                    }
+                                }
+                            };
+                            let pixel_format_attributes = descriptor.pixel_format_attributes();
+                            if candidates.iter().all(|&(_, ref other_attributes)| {
+                                *other_attributes != pixel_format_attributes
+                            }) {
+                                candidates.push((descriptor, pixel_format_attributes));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(candidates)
+    }
+
+    /// Creates a context descriptor from one of the pixel formats that
+    /// `enumerate_context_descriptors()` returns.
+    ///
+    /// `choose` receives the attributes of every candidate pixel format and returns the index of
+    /// the one to use. If it returns `None` or an index out of range, a `NoPixelFormatFound` error
+    /// is returned.
+    pub fn create_context_descriptor_with<F>(&self, attributes: &ContextAttributes, choose: F)
+                                             -> Result<ContextDescriptor, Error>
+                                             where F: FnOnce(&[PixelFormatAttributes]) ->
+                                                   Option<usize> {
+        let candidates = self.enumerate_context_descriptors(attributes)?;
+        choose_context_descriptor(candidates, choose)
+    }
+
+    /// Explains which pixel formats `create_context_descriptor()` passes over for the given
+    /// attributes, and why.
+    ///
+    /// This is meant for finding out why `create_context_descriptor()` returned
+    /// `NoPixelFormatFound` or `UnsupportedGLProfile`.
+    pub fn diagnose_context_descriptor(&self, attributes: &ContextAttributes)
+                                       -> ContextDescriptorDiagnostics {
+        let candidates = self.enumerate_context_descriptors(attributes);
+        ContextDescriptorDiagnostics::new(attributes, candidates, None)
+    }
+
+    // Returns the pixel format attributes that select the renderer and profile, without a
+    // terminator.
+    fn base_pixel_format_attributes(&self, profile: CGLPixelFormatAttribute)
+                                    -> Vec<CGLPixelFormatAttribute> {
+        let mut cgl_pixel_format_attributes = vec![kCGLPFAOpenGLProfile, profile];
 
         // This means "opt into the integrated GPU".
         //
@@ -163,22 +315,7 @@ impl Device {
             cgl_pixel_format_attributes.push(kCGLPFAAllowOfflineRenderers);
         }
 
-        cgl_pixel_format_attributes.extend_from_slice(&[0, 0]);
-
-        unsafe {
-            let (mut cgl_pixel_format, mut cgl_pixel_format_count) = (ptr::null_mut(), 0);
-            let err = CGLChoosePixelFormat(cgl_pixel_format_attributes.as_ptr(),
-                                           &mut cgl_pixel_format,
-                                           &mut cgl_pixel_format_count);
-            if err != kCGLNoError {
-                return Err(Error::PixelFormatSelectionFailed(err.to_windowing_api_error()));
-            }
-            if cgl_pixel_format_count == 0 {
-                return Err(Error::NoPixelFormatFound);
-            }
-
-            Ok(ContextDescriptor { cgl_pixel_format })
-        }
+        cgl_pixel_format_attributes
     }
 
     /// Creates a new OpenGL context.
@@ -198,7 +335,8 @@ impl Device {
                                        ptr::null_mut(),
                                        &mut cgl_context);
             if err != kCGLNoError {
-                return Err(Error::ContextCreationFailed(err.to_windowing_api_error()));
+                let failure = err.to_windowing_api_failure("CGLCreateContext");
+                return Err(Error::ContextCreationFailed(Box::new(failure)));
             }
             debug_assert_ne!(cgl_context, ptr::null_mut());
 
@@ -206,7 +344,9 @@ impl Device {
             let context = Context {
                 cgl_context,
                 id: *next_context_id,
+                gl_info: GLInfoCache::default(),
                 framebuffer: Framebuffer::None,
+                origin: Origin::new(&self.0.leak_tracker),
             };
             next_context_id.0 += 1;
             Ok(context)
@@ -223,7 +363,9 @@ impl Device {
         let context = Context {
             cgl_context: native_context.0,
             id: *next_context_id,
+            gl_info: GLInfoCache::default(),
             framebuffer: Framebuffer::None,
+            origin: Origin::new(&self.0.leak_tracker),
         };
         next_context_id.0 += 1;
         mem::forget(native_context);
@@ -269,7 +411,8 @@ impl Device {
         unsafe {
             let err = CGLSetCurrentContext(context.cgl_context);
             if err != kCGLNoError {
-                return Err(Error::MakeCurrentFailed(err.to_windowing_api_error()));
+                let failure = err.to_windowing_api_failure("CGLSetCurrentContext");
+                return Err(Error::MakeCurrentFailed(Box::new(failure)));
             }
             Ok(())
         }
@@ -283,7 +426,8 @@ impl Device {
         unsafe {
             let err = CGLSetCurrentContext(ptr::null_mut());
             if err != kCGLNoError {
-                return Err(Error::MakeCurrentFailed(err.to_windowing_api_error()));
+                let failure = err.to_windowing_api_failure("CGLSetCurrentContext");
+                return Err(Error::MakeCurrentFailed(Box::new(failure)));
             }
             Ok(())
         }
@@ -314,7 +458,7 @@ impl Device {
             Framebuffer::None => {}
         }
 
-        if new_surface.context_id != context.id {
+        if new_surface.context_id != context.id || new_surface.framebuffer_object == 0 {
             return Err((Error::IncompatibleSurface, new_surface));
         }
 
@@ -358,10 +502,10 @@ impl Device {
     pub fn context_descriptor_attributes(&self, context_descriptor: &ContextDescriptor)
                                          -> ContextAttributes {
         unsafe {
-            let alpha_size = get_pixel_format_attribute(context_descriptor, kCGLPFAAlphaSize);
-            let depth_size = get_pixel_format_attribute(context_descriptor, kCGLPFADepthSize);
-            let stencil_size = get_pixel_format_attribute(context_descriptor, kCGLPFAStencilSize);
-            let gl_profile = get_pixel_format_attribute(context_descriptor, kCGLPFAOpenGLProfile);
+            let alpha_size = context_descriptor.get_attribute(kCGLPFAAlphaSize);
+            let depth_size = context_descriptor.get_attribute(kCGLPFADepthSize);
+            let stencil_size = context_descriptor.get_attribute(kCGLPFAStencilSize);
+            let gl_profile = context_descriptor.get_attribute(kCGLPFAOpenGLProfile);
 
             let mut attribute_flags = ContextAttributeFlags::empty();
             attribute_flags.set(ContextAttributeFlags::ALPHA, alpha_size != 0);
@@ -376,20 +520,31 @@ impl Device {
                 attribute_flags.insert(ContextAttributeFlags::COMPATIBILITY_PROFILE);
             }
 
-            return ContextAttributes { flags: attribute_flags, version };
+            return ContextAttributes {
+                api: Some(GLApi::GL),
+                version,
+                minimum_version: None,
+                flags: attribute_flags,
+            };
         }
+    }
 
-        unsafe fn get_pixel_format_attribute(context_descriptor: &ContextDescriptor,
-                                             attribute: CGLPixelFormatAttribute)
-                                             -> i32 {
-            let mut value = 0;
-            let err = CGLDescribePixelFormat(context_descriptor.cgl_pixel_format,
-                                             0,
-                                             attribute,
-                                             &mut value);
-            debug_assert_eq!(err, kCGLNoError);
-            value
-        }
+    /// Returns the OpenGL API flavor (OpenGL or OpenGL ES) that the context was created for.
+    #[inline]
+    pub fn context_gl_api(&self, _: &Context) -> GLApi {
+        GLApi::GL
+    }
+
+    /// Returns what the OpenGL implementation reports about the context: its version and
+    /// profile, the vendor and renderer, and the extensions it supports.
+    ///
+    /// The context is queried the first time this is called, and the same information is
+    /// returned after that.
+    pub fn gl_info(&self, context: &Context) -> Result<Rc<GLInfo>, Error> {
+        context.gl_info.get_or_query(|| {
+            let _guard = self.temporarily_make_context_current(context)?;
+            GL_FUNCTIONS.with(GLInfo::current)
+        })
     }
 
     /// Fetches the address of an OpenGL function associated with this context.
@@ -436,6 +591,64 @@ impl Device {
     }
 }
 
+// Returns `None` if no pixel format matches the given zero-terminated attribute list.
+unsafe fn choose_pixel_format(cgl_pixel_format_attributes: &[CGLPixelFormatAttribute])
+                              -> Result<Option<CGLPixelFormatObj>, Error> {
+    let (mut cgl_pixel_format, mut cgl_pixel_format_count) = (ptr::null_mut(), 0);
+    let err = CGLChoosePixelFormat(cgl_pixel_format_attributes.as_ptr(),
+                                   &mut cgl_pixel_format,
+                                   &mut cgl_pixel_format_count);
+    if err != kCGLNoError {
+        let failure = err.to_windowing_api_failure("CGLChoosePixelFormat");
+        return Err(Error::PixelFormatSelectionFailed(Box::new(failure)));
+    }
+    if cgl_pixel_format_count == 0 {
+        return Ok(None);
+    }
+    Ok(Some(cgl_pixel_format))
+}
+
+// Returns the CGL profiles that can satisfy the requested version range, best first.
+//
+// CGL only offers the legacy 2.1 compatibility profile and the 3.2 and 4.1 core profiles, and the
+// core profiles stand in for any version with the same major number.
+fn candidate_profiles(attributes: &ContextAttributes)
+                      -> Result<Vec<CGLPixelFormatAttribute>, Error> {
+    // CGL only creates desktop OpenGL contexts.
+    if attributes.api == Some(GLApi::GLES) {
+        return Err(Error::UnsupportedGLType);
+    }
+
+    let flags = attributes.flags;
+    let candidates =
+        context::version_candidates(GLApi::GL,
+                                    attributes.version,
+                                    attributes.minimum_version.unwrap_or(attributes.version),
+                                    flags.contains(ContextAttributeFlags::COMPATIBILITY_PROFILE),
+                                    flags.contains(ContextAttributeFlags::ALLOW_CORE_PROFILE));
+
+    let mut profiles = vec![];
+    for (version, compatibility_profile) in candidates {
+        let profile = if version.major <= 2 {
+            kCGLOGLPVersion_Legacy
+        } else if compatibility_profile {
+            continue
+        } else if version.major == 3 {
+            kCGLOGLPVersion_3_2_Core
+        } else {
+            kCGLOGLPVersion_GL4_Core
+        };
+        if !profiles.contains(&profile) {
+            profiles.push(profile);
+        }
+    }
+
+    if profiles.is_empty() {
+        return Err(Error::UnsupportedGLProfile);
+    }
+    Ok(profiles)
+}
+
 fn get_proc_address(symbol_name: &str) -> *const c_void {
     OPENGL_FRAMEWORK.with(|framework| {
         unsafe {
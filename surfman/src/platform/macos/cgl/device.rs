@@ -2,10 +2,12 @@
 //
 //! A handle to the device. (This is a no-op, because handles are implicit in Apple's Core OpenGL.)
 
-use crate::GLApi;
+use crate::{DeviceCapabilities, GLApi, LeakPolicy};
 use crate::platform::macos::system::device::{Adapter as SystemAdapter, Device as SystemDevice};
 use super::connection::Connection;
 
+use std::collections::HashSet;
+
 pub use crate::platform::macos::system::device::NativeDevice;
 
 /// Represents a hardware display adapter that can be used for rendering (including the CPU).
@@ -39,9 +41,38 @@ impl Device {
         Adapter(self.0.adapter())
     }
 
-    /// Returns the OpenGL API flavor that this device supports (OpenGL or OpenGL ES).
+    /// Returns the OpenGL API flavor that contexts on this device use by default (OpenGL or
+    /// OpenGL ES).
     #[inline]
     pub fn gl_api(&self) -> GLApi {
         GLApi::GL
     }
+
+    /// Returns the optional features that this device supports.
+    ///
+    /// CGL has no extensions of its own, so `platform_extensions` is empty.
+    pub fn capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities {
+            widget_surfaces: true,
+            cpu_surface_reads: false,
+            cpu_surface_uploads: true,
+            cross_device_surface_textures: true,
+            damage_present: false,
+            surface_texture_target: self.surface_gl_texture_target(),
+            platform_extensions: HashSet::new(),
+        }
+    }
+
+    /// Sets what happens to contexts and surfaces created with this device that are dropped
+    /// without being destroyed, overriding the global leak policy.
+    #[inline]
+    pub fn set_leak_policy(&self, policy: LeakPolicy) {
+        self.0.set_leak_policy(policy)
+    }
+
+    /// Returns the leak policy that this device follows.
+    #[inline]
+    pub fn leak_policy(&self) -> LeakPolicy {
+        self.0.leak_policy()
+    }
 }
@@ -2,11 +2,14 @@
 //
 //! Translation of errors from the CGL API to `surfman` errors.
 
-use crate::WindowingApiError;
+use crate::{WindowingApiError, WindowingApiFailure};
 use cgl::CGLError;
 
 pub(crate) trait ToWindowingApiError {
     fn to_windowing_api_error(self) -> WindowingApiError;
+
+    // Records that the named CGL function failed with this error code.
+    fn to_windowing_api_failure(self, call: &'static str) -> WindowingApiFailure;
 }
 
 impl ToWindowingApiError for CGLError {
@@ -30,7 +33,12 @@ impl ToWindowingApiError for CGLError {
             10015 => WindowingApiError::BadCodeModule,
             10016 => WindowingApiError::BadAlloc,
             10017 => WindowingApiError::BadConnection,
-            _     => WindowingApiError::Failed,
+            0     => WindowingApiError::Failed,
+            _     => WindowingApiError::Other(self),
         }
     }
+
+    fn to_windowing_api_failure(self, call: &'static str) -> WindowingApiFailure {
+        WindowingApiFailure::new(call, self.to_windowing_api_error(), Some(self))
+    }
 }
@@ -5,20 +5,31 @@
 use crate::context::ContextID;
 use crate::gl::types::{GLenum, GLint, GLuint};
 use crate::gl_utils;
+use crate::ipc::SurfaceHandle;
 use crate::platform::macos::system::surface::Surface as SystemSurface;
 use crate::renderbuffers::Renderbuffers;
-use crate::{Error, SurfaceAccess, SurfaceID, SurfaceInfo, SurfaceType, gl};
+use crate::{DepthStencilAttributes, Error, SurfaceAccess, SurfaceFormat, SurfaceID, SurfaceInfo};
+use crate::{LayerStorage, SurfaceTextureTarget, SurfaceType, gl};
+use crate::surface::layer_rect;
+use crate::video::PlanarImage;
+use crate::{WindowingApiError, WindowingApiFailure};
 use super::context::{Context, GL_FUNCTIONS};
 use super::device::Device;
+use super::error::ToWindowingApiError;
+use super::ffi::CGLTexImageIOSurface2D;
 
+use cgl::{CGLGetCurrentContext, kCGLNoError};
 use core_foundation::base::TCFType;
-use euclid::default::Size2D;
-use io_surface::{self, IOSurface};
+use euclid::default::Rect;
 use std::fmt::{self, Debug, Formatter};
 use std::marker::PhantomData;
 
 pub use crate::platform::macos::system::surface::{NativeSurface, NativeWidget};
 
+/// An `IOSurface` created outside `surfman`, to be adopted as a surface with
+/// `Device::create_surface_from_native_image()`.
+pub type NativeImage = NativeSurface;
+
 const SURFACE_GL_TEXTURE_TARGET: GLenum = gl::TEXTURE_RECTANGLE;
 
 /// Represents a hardware buffer of pixels that can be rendered to via the CPU or GPU and either
@@ -36,13 +47,16 @@ const SURFACE_GL_TEXTURE_TARGET: GLenum = gl::TEXTURE_RECTANGLE;
 /// 
 /// Depending on the platform, each surface may be internally double-buffered.
 /// 
-/// Surfaces must be destroyed with the `destroy_surface()` method, or a panic will occur.
+/// Surfaces should be destroyed with the `destroy_surface()` method. A surface that is dropped
+/// without being destroyed is handled according to the device's `LeakPolicy`.
 pub struct Surface {
     pub(crate) system_surface: SystemSurface,
     pub(crate) context_id: ContextID,
     pub(crate) framebuffer_object: GLuint,
     pub(crate) texture_object: GLuint,
     pub(crate) renderbuffers: Renderbuffers,
+    // The number of layers laid out side by side in the surface.
+    pub(crate) layers: u32,
 }
 
 /// Represents an OpenGL texture that wraps a surface.
@@ -52,8 +66,9 @@ pub struct Surface {
 /// framebuffer).
 /// 
 /// Surface textures are local to a context, but that context does not have to be the same context
-/// as that associated with the underlying surface. The texture must be destroyed with the
-/// `destroy_surface_texture()` method, or a panic will occur.
+/// as that associated with the underlying surface. The texture should be destroyed with the
+/// `destroy_surface_texture()` method. Dropping it instead leaks the texture object and drops its
+/// surface, which is handled according to the device's `LeakPolicy`.
 pub struct SurfaceTexture {
     pub(crate) surface: Surface,
     pub(crate) texture_object: GLuint,
@@ -84,13 +99,51 @@ impl Device {
                           access: SurfaceAccess,
                           surface_type: SurfaceType<NativeWidget>)
                           -> Result<Surface, Error> {
+        // `IOSurface`s can't hold depth, and our contexts don't share textures with one another,
+        // so depth buffers can't be sampled from other contexts.
+        let (depth_stencil, layers) = match surface_type {
+            SurfaceType::Generic { depth_stencil, .. } => (depth_stencil, 1),
+            SurfaceType::Layered { layers, .. } => (None, layers),
+            SurfaceType::Widget { .. } => (None, 1),
+        };
+        if let Some(DepthStencilAttributes { sampleable: true, .. }) = depth_stencil {
+            return Err(Error::UnsupportedDepthStencilFormat);
+        }
+
         let system_surface = self.0.create_surface(access, surface_type)?;
+        self.create_surface_from_system_surface(context, system_surface, depth_stencil, layers)
+    }
+
+    /// Adopts an `IOSurface` that another library created as a generic surface.
+    /// 
+    /// The surface holds its own reference to the `IOSurface`, which it gives up when it's
+    /// destroyed. It can be bound to the context and wrapped in a surface texture like any other
+    /// generic surface. The `IOSurface` must have one of the pixel formats that
+    /// `supported_surface_formats()` lists.
+    pub unsafe fn create_surface_from_native_image(&self,
+                                                   context: &Context,
+                                                   native_image: NativeImage)
+                                                   -> Result<Surface, Error> {
+        let system_surface = self.0.create_surface_from_native_surface(&native_image)?;
+        self.create_surface_from_system_surface(context, system_surface, None, 1)
+    }
 
+    fn create_surface_from_system_surface(&self,
+                                          context: &Context,
+                                          mut system_surface: SystemSurface,
+                                          depth_stencil: Option<DepthStencilAttributes>,
+                                          layers: u32)
+                                          -> Result<Surface, Error> {
         let _guard = self.temporarily_make_context_current(context);
         GL_FUNCTIONS.with(|gl| {
             unsafe {
-                let texture_object = self.bind_to_gl_texture(&system_surface.io_surface,
-                                                             &system_surface.size);
+                let texture_object = match self.bind_to_gl_texture(&system_surface) {
+                    Ok(texture_object) => texture_object,
+                    Err(failure) => {
+                        self.0.destroy_surface(&mut system_surface)?;
+                        return Err(Error::SurfaceCreationFailed(Box::new(failure)));
+                    }
+                };
 
                 let mut framebuffer_object = 0;
                 gl.GenFramebuffers(1, &mut framebuffer_object);
@@ -105,13 +158,25 @@ impl Device {
                 let context_descriptor = self.context_descriptor(context);
                 let context_attributes = self.context_descriptor_attributes(&context_descriptor);
 
-                let renderbuffers = Renderbuffers::new(gl,
-                                                       &system_surface.size,
-                                                       &context_attributes);
+                let mut renderbuffers = Renderbuffers::new(gl,
+                                                           &system_surface.size,
+                                                           &context_attributes,
+                                                           depth_stencil);
                 renderbuffers.bind_to_current_framebuffer(gl);
 
-                debug_assert_eq!(gl.CheckFramebufferStatus(gl::FRAMEBUFFER),
-                                 gl::FRAMEBUFFER_COMPLETE);
+                let framebuffer_status = gl.CheckFramebufferStatus(gl::FRAMEBUFFER);
+                if framebuffer_status != gl::FRAMEBUFFER_COMPLETE {
+                    drop(_guard);
+                    gl_utils::destroy_framebuffer(gl, framebuffer_object);
+                    renderbuffers.destroy(gl);
+                    gl.DeleteTextures(1, &texture_object);
+                    self.0.destroy_surface(&mut system_surface)?;
+
+                    let failure = WindowingApiFailure::new("glCheckFramebufferStatus",
+                                                           WindowingApiError::BadAlloc,
+                                                           Some(framebuffer_status as i32));
+                    return Err(Error::SurfaceCreationFailed(Box::new(failure)));
+                }
 
                 Ok(Surface {
                     system_surface,
@@ -119,6 +184,7 @@ impl Device {
                     framebuffer_object,
                     texture_object,
                     renderbuffers,
+                    layers,
                 })
             }
         })
@@ -140,10 +206,17 @@ impl Device {
             return Err((Error::WidgetAttached, surface));
         }
 
-        let _guard = self.temporarily_make_context_current(context).unwrap();
+        let _guard = match self.temporarily_make_context_current(context) {
+            Ok(guard) => guard,
+            Err(err) => return Err((err, surface)),
+        };
 
-        let texture_object = self.bind_to_gl_texture(&surface.system_surface.io_surface,    
-                                                     &surface.system_surface.size);
+        let texture_object = match self.bind_to_gl_texture(&surface.system_surface) {
+            Ok(texture_object) => texture_object,
+            Err(failure) => {
+                return Err((Error::SurfaceTextureCreationFailed(Box::new(failure)), surface));
+            }
+        };
         Ok(SurfaceTexture {
             surface,
             texture_object,
@@ -151,7 +224,8 @@ impl Device {
         })
     }
 
-    fn bind_to_gl_texture(&self, io_surface: &IOSurface, size: &Size2D<i32>) -> GLuint {
+    fn bind_to_gl_texture(&self, system_surface: &SystemSurface)
+                          -> Result<GLuint, WindowingApiFailure> {
         GL_FUNCTIONS.with(|gl| {
             unsafe {
                 let mut texture = 0;
@@ -159,7 +233,11 @@ impl Device {
                 debug_assert_ne!(texture, 0);
 
                 gl.BindTexture(gl::TEXTURE_RECTANGLE, texture);
-                io_surface.bind_to_gl_texture(size.width, size.height, true);
+                if let Err(failure) = tex_image_io_surface(system_surface) {
+                    gl.BindTexture(gl::TEXTURE_RECTANGLE, 0);
+                    gl.DeleteTextures(1, &texture);
+                    return Err(failure);
+                }
 
                 gl.TexParameteri(gl::TEXTURE_RECTANGLE,
                                  gl::TEXTURE_MAG_FILTER,
@@ -178,7 +256,7 @@ impl Device {
 
                 debug_assert_eq!(gl.GetError(), gl::NO_ERROR);
 
-                texture
+                Ok(texture)
             }
         })
     }
@@ -188,8 +266,8 @@ impl Device {
     /// The supplied context must be the context the surface is associated with, or this returns
     /// an `IncompatibleSurface` error.
     /// 
-    /// You must explicitly call this method to dispose of a surface. Otherwise, a panic occurs in
-    /// the `drop` method.
+    /// You should explicitly call this method to dispose of a surface. Otherwise, the surface is
+    /// handled according to the device's `LeakPolicy` when it is dropped.
     pub fn destroy_surface(&self, context: &mut Context, surface: &mut Surface)
                            -> Result<(), Error> {
         GL_FUNCTIONS.with(|gl| {
@@ -215,8 +293,9 @@ impl Device {
     /// The supplied context must be the same context the surface texture was created with, or an
     /// `IncompatibleSurfaceTexture` error is returned.
     /// 
-    /// All surface textures must be explicitly destroyed with this function, or a panic will
-    /// occur.
+    /// All surface textures should be explicitly destroyed with this function. Otherwise, the
+    /// texture object is leaked and the surface is handled according to the device's `LeakPolicy`
+    /// when the surface texture is dropped.
     pub fn destroy_surface_texture(&self, _: &mut Context, mut surface_texture: SurfaceTexture)
                                    -> Result<Surface, (Error, SurfaceTexture)> {
         GL_FUNCTIONS.with(|gl| {
@@ -237,6 +316,45 @@ impl Device {
         surface_texture.texture_object
     }
 
+    /// Returns the OpenGL texture object containing the depth buffer of this surface texture's
+    /// surface.
+    /// 
+    /// Depth buffers can't be shared between contexts on macOS, so this always returns `None`.
+    #[inline]
+    pub fn surface_texture_depth_object(&self, _: &SurfaceTexture) -> Option<GLuint> {
+        None
+    }
+
+    /// Returns the OpenGL texture object containing the given layer of the surface, or `None` if
+    /// the surface has no such layer.
+    /// 
+    /// Layers are laid out side by side in one texture on this platform, so this is
+    /// `surface_texture_object()` for every layer.
+    pub fn surface_texture_layer_object(&self, surface_texture: &SurfaceTexture, layer: u32)
+                                        -> Option<GLuint> {
+        self.surface_texture_layer_rect(surface_texture, layer)
+            .map(|_| self.surface_texture_object(surface_texture))
+    }
+
+    /// Returns the region of `surface_texture_layer_object()` that holds the given layer of the
+    /// surface, in texels, or `None` if the surface has no such layer.
+    /// 
+    /// Surfaces that aren't layered have a single layer that covers the whole texture.
+    pub fn surface_texture_layer_rect(&self, surface_texture: &SurfaceTexture, layer: u32)
+                                      -> Option<Rect<i32>> {
+        let surface = &surface_texture.surface;
+        layer_rect(&surface.system_surface.size, surface.layers, layer)
+    }
+
+    /// Returns the OpenGL texture target that the surface texture's texture object must be bound
+    /// to.
+    /// 
+    /// `IOSurface`s are always bound as rectangle textures, so this is `GL_TEXTURE_RECTANGLE`.
+    #[inline]
+    pub fn surface_texture_target(&self, _: &SurfaceTexture) -> GLenum {
+        SURFACE_GL_TEXTURE_TARGET
+    }
+
     /// Returns the OpenGL texture target needed to read from this surface texture.
     /// 
     /// This will be `GL_TEXTURE_2D` or `GL_TEXTURE_RECTANGLE`, depending on platform.
@@ -245,6 +363,30 @@ impl Device {
         SURFACE_GL_TEXTURE_TARGET
     }
 
+    /// Returns the formats that generic surfaces created on this device may have.
+    #[inline]
+    pub fn supported_surface_formats(&self, _: &Context) -> Vec<SurfaceFormat> {
+        self.0.supported_surface_formats()
+    }
+
+    /// Copies pixels from the CPU into a rectangle of a generic surface.
+    /// 
+    /// The rows are written directly into the `IOSurface` backing the surface, so the surface
+    /// must allow CPU access.
+    pub fn upload_surface_data(&self,
+                               context: &Context,
+                               surface: &mut Surface,
+                               rect: &Rect<i32>,
+                               data: &[u8],
+                               stride: usize,
+                               format: SurfaceFormat)
+                               -> Result<(), Error> {
+        if context.id != surface.context_id {
+            return Err(Error::IncompatibleSurface);
+        }
+        self.0.upload_surface_data(&mut surface.system_surface, rect, data, stride, format)
+    }
+
     /// Displays the contents of a widget surface on screen.
     /// 
     /// Widget surfaces are internally double-buffered, so changes to them don't show up in their
@@ -257,15 +399,11 @@ impl Device {
 
         GL_FUNCTIONS.with(|gl| {
             unsafe {
-                let size = surface.system_surface.size;
                 gl.BindTexture(gl::TEXTURE_RECTANGLE, surface.texture_object);
-                surface.system_surface
-                       .io_surface
-                       .bind_to_gl_texture(size.width, size.height, true);
+                let result = tex_image_io_surface(&surface.system_surface);
                 gl.BindTexture(gl::TEXTURE_RECTANGLE, 0);
+                result.map_err(|failure| Error::PresentFailed(Box::new(failure)))
             }
-
-            Ok(())
         })
     }
 
@@ -298,6 +436,8 @@ impl Device {
             id: system_surface_info.id,
             context_id: surface.context_id,
             framebuffer_object: surface.framebuffer_object,
+            layers: surface.layers,
+            layer_storage: LayerStorage::Atlas,
         }
     }
 
@@ -308,6 +448,97 @@ impl Device {
     pub fn native_surface(&self, surface: &Surface) -> NativeSurface {
         self.0.native_surface(&surface.system_surface)
     }
+
+    /// Exports a generic surface so that it can be sampled from in another process.
+    /// 
+    /// The handle contains the global ID of the `IOSurface` backing the surface, so it shares
+    /// memory with the surface.
+    /// 
+    /// The supplied context must be the context the surface is associated with, or an
+    /// `IncompatibleSurface` error is returned.
+    pub fn export_surface(&self, context: &Context, surface: &Surface)
+                          -> Result<SurfaceHandle, Error> {
+        if context.id != surface.context_id {
+            return Err(Error::IncompatibleSurface);
+        }
+
+        let _guard = self.temporarily_make_context_current(context)?;
+        GL_FUNCTIONS.with(|gl| unsafe { gl.Flush() });
+        self.0.export_surface(&surface.system_surface)
+    }
+
+    /// Wraps a surface exported from another process in a surface texture local to the given
+    /// context.
+    /// 
+    /// Both `IOSurface` and shared memory handles are accepted. The surface that the returned
+    /// texture wraps can only be read from. Rectangle textures can always be sampled without
+    /// `samplerExternalOES`, so the requested target makes no difference here.
+    pub fn create_surface_texture_from_handle(&self,
+                                              context: &mut Context,
+                                              handle: SurfaceHandle,
+                                              _: SurfaceTextureTarget)
+                                              -> Result<SurfaceTexture, Error> {
+        let system_surface = self.0.create_surface_from_handle(&handle)?;
+
+        // Imported surfaces have no framebuffer, which prevents them from being bound to a
+        // context.
+        let surface = Surface {
+            system_surface,
+            context_id: context.id,
+            framebuffer_object: 0,
+            texture_object: 0,
+            renderbuffers: Renderbuffers::IndividualDepthStencil { depth: 0, stencil: 0 },
+            layers: 1,
+        };
+        match self.create_surface_texture(context, surface) {
+            Ok(surface_texture) => Ok(surface_texture),
+            Err((err, mut surface)) => {
+                drop(self.destroy_surface(context, &mut surface));
+                Err(err)
+            }
+        }
+    }
+
+    /// Wraps a decoded video frame in a surface texture local to the given context.
+    /// 
+    /// Video frames on macOS arrive as `IOSurface`s rather than dma-bufs, so this always returns
+    /// an `UnsupportedOnThisPlatform` error.
+    pub fn create_surface_texture_from_planes(&self,
+                                              _: &mut Context,
+                                              _: &PlanarImage,
+                                              _: SurfaceTextureTarget)
+                                              -> Result<SurfaceTexture, Error> {
+        Err(Error::UnsupportedOnThisPlatform)
+    }
+}
+
+// Attaches the `IOSurface` to the currently-bound rectangle texture, using the OpenGL formats that
+// match the surface's pixel format.
+unsafe fn tex_image_io_surface(system_surface: &SystemSurface)
+                               -> Result<(), WindowingApiFailure> {
+    let (internal_format, format, type_) = match system_surface.format {
+        SurfaceFormat::RGBA8 => (gl::RGBA, gl::RGBA, gl::UNSIGNED_BYTE),
+        SurfaceFormat::BGRA8 => (gl::RGBA, gl::BGRA, gl::UNSIGNED_INT_8_8_8_8_REV),
+        SurfaceFormat::SRGB8A8 => (gl::SRGB8_ALPHA8, gl::BGRA, gl::UNSIGNED_INT_8_8_8_8_REV),
+        SurfaceFormat::RGB10A2 => (gl::RGB10_A2, gl::BGRA, gl::UNSIGNED_INT_2_10_10_10_REV),
+        SurfaceFormat::RGBA16F => (gl::RGBA16F, gl::RGBA, gl::HALF_FLOAT),
+        SurfaceFormat::R8 => (gl::R8, gl::RED, gl::UNSIGNED_BYTE),
+        SurfaceFormat::RG8 => (gl::RG8, gl::RG, gl::UNSIGNED_BYTE),
+    };
+    let size = system_surface.size;
+    let result = CGLTexImageIOSurface2D(CGLGetCurrentContext(),
+                                        gl::TEXTURE_RECTANGLE,
+                                        internal_format,
+                                        size.width,
+                                        size.height,
+                                        format,
+                                        type_,
+                                        system_surface.io_surface.as_concrete_TypeRef(),
+                                        0);
+    if result != kCGLNoError {
+        return Err(result.to_windowing_api_failure("CGLTexImageIOSurface2D"));
+    }
+    Ok(())
 }
 
 impl Surface {
@@ -2,10 +2,12 @@
 //
 //! A handle to the device. (This is a no-op, because handles are implicit in `IOSurface`.)
 
-use crate::Error;
+use crate::leak::LeakTracker;
+use crate::{Error, LeakPolicy};
 use super::connection::Connection;
 
 use std::marker::PhantomData;
+use std::sync::Arc;
 
 /// Represents a hardware display adapter that can be used for rendering (including the CPU).
 ///
@@ -21,6 +23,7 @@ pub struct Adapter {
 #[derive(Clone)]
 pub struct Device {
     adapter: Adapter,
+    pub(crate) leak_tracker: Arc<LeakTracker>,
     phantom: PhantomData<*mut ()>,
 }
 
@@ -33,7 +36,7 @@ pub struct NativeDevice;
 impl Device {
     #[inline]
     pub(crate) fn new(adapter: Adapter) -> Result<Device, Error> {
-        Ok(Device { adapter, phantom: PhantomData })
+        Ok(Device { adapter, leak_tracker: LeakTracker::new(), phantom: PhantomData })
     }
 
     /// Returns the native device corresponding to this device.
@@ -53,4 +56,17 @@ impl Device {
     pub fn adapter(&self) -> Adapter {
         self.adapter.clone()
     }
+
+    /// Sets what happens to surfaces created with this device that are dropped without being
+    /// destroyed, overriding the global leak policy.
+    #[inline]
+    pub fn set_leak_policy(&self, policy: LeakPolicy) {
+        self.leak_tracker.set_policy(policy)
+    }
+
+    /// Returns the leak policy that this device follows.
+    #[inline]
+    pub fn leak_policy(&self) -> LeakPolicy {
+        self.leak_tracker.policy()
+    }
 }
@@ -4,11 +4,17 @@
 
 #![allow(non_upper_case_globals)]
 
+use core_foundation::string::CFStringRef;
 use io_surface::IOSurfaceRef;
 use mach::kern_return::kern_return_t;
 use std::os::raw::c_void;
 
-pub(crate) const kCVPixelFormatType_32BGRA: i32 = 0x42475241;   // 'BGRA'
+pub(crate) const kCVPixelFormatType_32BGRA: i32 = 0x42475241;                 // 'BGRA'
+pub(crate) const kCVPixelFormatType_32RGBA: i32 = 0x52474241;                 // 'RGBA'
+pub(crate) const kCVPixelFormatType_ARGB2101010LEPacked: i32 = 0x6c313072;    // 'l10r'
+pub(crate) const kCVPixelFormatType_64RGBAHalf: i32 = 0x52476841;             // 'RGhA'
+pub(crate) const kCVPixelFormatType_OneComponent8: i32 = 0x4c303038;          // 'L008'
+pub(crate) const kCVPixelFormatType_TwoComponent8: i32 = 0x32433038;          // '2C08'
 
 pub(crate) const kCVReturnSuccess: i32 = 0;
 
@@ -25,12 +31,19 @@ extern {
     pub(crate) fn IOSurfaceGetAllocSize(buffer: IOSurfaceRef) -> usize;
     pub(crate) fn IOSurfaceGetBaseAddress(buffer: IOSurfaceRef) -> *mut c_void;
     pub(crate) fn IOSurfaceGetBytesPerRow(buffer: IOSurfaceRef) -> usize;
+    pub(crate) fn IOSurfaceGetHeight(buffer: IOSurfaceRef) -> usize;
+    pub(crate) fn IOSurfaceGetID(buffer: IOSurfaceRef) -> u32;
+    pub(crate) fn IOSurfaceGetPixelFormat(buffer: IOSurfaceRef) -> u32;
+    pub(crate) fn IOSurfaceGetWidth(buffer: IOSurfaceRef) -> usize;
     pub(crate) fn IOSurfaceLock(buffer: IOSurfaceRef,
                                 options: IOSurfaceLockOptions,
                                 seed: *mut u32)
                                 -> kern_return_t;
+    pub(crate) fn IOSurfaceLookup(csid: u32) -> IOSurfaceRef;
     pub(crate) fn IOSurfaceUnlock(buffer: IOSurfaceRef,
                                   options: IOSurfaceLockOptions,
                                   seed: *mut u32)
                                   -> kern_return_t;
+
+    pub(crate) static kIOSurfaceIsGlobal: CFStringRef;
 }
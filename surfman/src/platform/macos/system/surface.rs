@@ -2,23 +2,33 @@
 //
 //! Surface management for macOS.
 
-use crate::{Error, SurfaceAccess, SurfaceID, SurfaceType, SystemSurfaceInfo};
+use crate::ipc::{SharedMemoryMapping, SurfaceHandle};
+use crate::leak::{LeakedObject, Origin};
+use crate::{Error, SurfaceAccess, SurfaceFormat, SurfaceID, SurfaceType, SystemSurfaceInfo};
+use crate::{WindowingApiError, WindowingApiFailure};
+use crate::surface::{check_upload_region, layered_surface_size};
 use super::device::Device;
 use super::ffi::{IOSurfaceGetAllocSize, IOSurfaceGetBaseAddress, IOSurfaceGetBytesPerRow};
-use super::ffi::{IOSurfaceLock, IOSurfaceUnlock, kCVPixelFormatType_32BGRA, kIOMapDefaultCache};
-use super::ffi::{kIOMapWriteCombineCache, kCVReturnSuccess};
+use super::ffi::{IOSurfaceGetHeight, IOSurfaceGetID, IOSurfaceGetPixelFormat, IOSurfaceGetWidth};
+use super::ffi::{IOSurfaceLock, IOSurfaceLookup};
+use super::ffi::{IOSurfaceUnlock, kCVPixelFormatType_32BGRA, kCVPixelFormatType_32RGBA};
+use super::ffi::{kCVPixelFormatType_64RGBAHalf, kCVPixelFormatType_ARGB2101010LEPacked};
+use super::ffi::{kCVPixelFormatType_OneComponent8, kCVPixelFormatType_TwoComponent8};
+use super::ffi::{kCVReturnSuccess, kIOMapDefaultCache, kIOMapWriteCombineCache};
+use super::ffi::kIOSurfaceIsGlobal;
 
 use cocoa::appkit::{NSScreen, NSView as NSViewMethods, NSWindow};
 use cocoa::base::{YES, id};
 use cocoa::foundation::{NSPoint, NSRect, NSSize};
 use cocoa::quartzcore::{CALayer, CATransform3D, transaction};
 use core_foundation::base::TCFType;
+use core_foundation::boolean::CFBoolean;
 use core_foundation::dictionary::CFDictionary;
 use core_foundation::number::CFNumber;
 use core_foundation::string::CFString;
 use core_graphics::geometry::{CGRect, CGSize, CG_ZERO_POINT};
 use display_link::macos::cvdisplaylink::{CVDisplayLink, CVTimeStamp, DisplayLink};
-use euclid::default::Size2D;
+use euclid::default::{Rect, Size2D};
 use io_surface::{self, IOSurface, IOSurfaceRef, kIOSurfaceBytesPerElement, kIOSurfaceBytesPerRow};
 use io_surface::{kIOSurfaceCacheMode, kIOSurfaceHeight, kIOSurfacePixelFormat, kIOSurfaceWidth};
 use mach::kern_return::KERN_SUCCESS;
@@ -27,9 +37,6 @@ use std::mem;
 use std::os::raw::c_void;
 use std::slice;
 use std::sync::{Arc, Condvar, Mutex};
-use std::thread;
-
-const BYTES_PER_PIXEL: i32 = 4;
 
 /// Represents a hardware buffer of pixels that can be rendered to via the CPU or GPU and either
 /// displayed in a native widget or bound to a texture for reading.
@@ -41,13 +48,16 @@ const BYTES_PER_PIXEL: i32 = 4;
 /// 
 /// Depending on the platform, each surface may be internally double-buffered.
 /// 
-/// Surfaces must be destroyed with the `destroy_surface()` method, or a panic will occur.
+/// Surfaces should be destroyed with the `destroy_surface()` method. A surface that is dropped
+/// without being destroyed is handled according to the device's `LeakPolicy`.
 pub struct Surface {
     pub(crate) io_surface: IOSurface,
     pub(crate) size: Size2D<i32>,
+    pub(crate) format: SurfaceFormat,
     access: SurfaceAccess,
     pub(crate) destroyed: bool,
     pub(crate) view_info: Option<ViewInfo>,
+    origin: Origin,
 }
 
 /// A wrapper around an `IOSurface`.
@@ -64,8 +74,8 @@ impl Debug for Surface {
 
 impl Drop for Surface {
     fn drop(&mut self) {
-        if !self.destroyed && !thread::panicking() {
-            panic!("Should have destroyed the surface first with `destroy_surface()`!")
+        if !self.destroyed {
+            self.origin.report_leak(LeakedObject::Surface, false);
         }
     }
 }
@@ -106,25 +116,44 @@ impl Device {
                           surface_type: SurfaceType<NativeWidget>)
                           -> Result<Surface, Error> {
         unsafe {
-            let size = match surface_type {
-                SurfaceType::Generic { size } => size,
-                SurfaceType::Widget { ref native_widget } => {
+            // Widget surfaces are always BGRA, since that's what Core Animation prefers. Their
+            // layers don't have a configurable color space yet. Depth and stencil buffers are up
+            // to the OpenGL backend layered on top of this one.
+            let (size, format) = match surface_type {
+                SurfaceType::Generic { size, format, .. } => (size, format),
+                SurfaceType::Layered { size, layers } => {
+                    (layered_surface_size(&size, layers)?, SurfaceFormat::RGBA8)
+                }
+                SurfaceType::Widget { color_space: Some(_), .. } => {
+                    return Err(Error::UnsupportedColorSpace)
+                }
+                SurfaceType::Widget { ref native_widget, color_space: None } => {
                     let window: id = msg_send![native_widget.view.0, window];
                     let bounds = window.convertRectToBacking(native_widget.view.0.bounds());
-                    Size2D::new(bounds.size.width.round(), bounds.size.height.round()).to_i32()
+                    let size = Size2D::new(bounds.size.width.round(),
+                                           bounds.size.height.round()).to_i32();
+                    (size, SurfaceFormat::BGRA8)
                 }
             };
 
-            let io_surface = self.create_io_surface(&size, access);
+            let io_surface = self.create_io_surface(&size, access, format);
 
             let view_info = match surface_type {
-                SurfaceType::Generic { .. } => None,
+                SurfaceType::Generic { .. } | SurfaceType::Layered { .. } => None,
                 SurfaceType::Widget { ref native_widget, .. } => {
-                    Some(self.create_view_info(&size, access, native_widget))
+                    Some(self.create_view_info(&size, access, native_widget)?)
                 }
             };
 
-            Ok(Surface { io_surface, size, access, destroyed: false, view_info })
+            Ok(Surface {
+                io_surface,
+                size,
+                format,
+                access,
+                destroyed: false,
+                view_info,
+                origin: Origin::new(&self.leak_tracker),
+            })
         }
     }
 
@@ -132,15 +161,28 @@ impl Device {
                                size: &Size2D<i32>,
                                surface_access: SurfaceAccess,
                                native_widget: &NativeWidget)
-                               -> ViewInfo {
-        let front_surface = self.create_io_surface(&size, surface_access);
-
+                               -> Result<ViewInfo, Error> {
+        // Find the display that the view is on, so that presentation can wait for its vblank.
         let window: id = msg_send![native_widget.view.0, window];
         let device_description: CFDictionary<CFString, CFNumber> =
             CFDictionary::wrap_under_get_rule(window.screen().deviceDescription() as *const _);
         let description_key: CFString = CFString::from("NSScreenNumber");
-        let display_id = device_description.get(description_key).to_i64().unwrap() as u32;
-        let mut display_link = DisplayLink::on_display(display_id).unwrap();
+        let display_id = match device_description.find(description_key)
+                                                 .and_then(|number| number.to_i64()) {
+            None => return Err(Error::InvalidNativeWidget),
+            Some(display_id) => display_id as u32,
+        };
+        let mut display_link = match DisplayLink::on_display(display_id) {
+            Some(display_link) => display_link,
+            None => {
+                let failure = WindowingApiFailure::new("CVDisplayLinkCreateWithCGDisplay",
+                                                       WindowingApiError::Failed,
+                                                       None);
+                return Err(Error::SurfaceCreationFailed(Box::new(failure)));
+            }
+        };
+
+        let front_surface = self.create_io_surface(&size, surface_access, SurfaceFormat::BGRA8);
         let next_vblank = Arc::new(VblankCond { mutex: Mutex::new(()), cond: Condvar::new() });
         display_link.set_output_callback(display_link_output_callback,
                                          mem::transmute(next_vblank.clone()));
@@ -176,13 +218,13 @@ impl Device {
 
         transaction::commit();
 
-        ViewInfo { layer, front_surface, display_link, next_vblank }
+        Ok(ViewInfo { layer, front_surface, display_link, next_vblank })
     }
 
     /// Destroys a surface.
     /// 
-    /// You must explicitly call this method to dispose of a surface. Otherwise, a panic occurs in
-    /// the `drop` method.
+    /// You should explicitly call this method to dispose of a surface. Otherwise, the surface is
+    /// handled according to the device's `LeakPolicy` when it is dropped.
     pub fn destroy_surface(&self, surface: &mut Surface) -> Result<(), Error> {
         surface.destroyed = true;
         Ok(())
@@ -203,26 +245,76 @@ impl Device {
         surface.lock_data()
     }
 
-    fn create_io_surface(&self, size: &Size2D<i32>, access: SurfaceAccess) -> IOSurface {
+    /// Copies pixels from the CPU into a rectangle of a surface.
+    /// 
+    /// `data` holds the rows of `rect` in order, `stride` bytes apart, in the given format, which
+    /// must be the surface's format. The rows are copied straight into the `IOSurface`.
+    pub fn upload_surface_data(&self,
+                               surface: &mut Surface,
+                               rect: &Rect<i32>,
+                               data: &[u8],
+                               stride: usize,
+                               format: SurfaceFormat)
+                               -> Result<(), Error> {
+        if format != surface.format {
+            return Err(Error::UnsupportedSurfaceFormat);
+        }
+        check_upload_region(&surface.size, rect, data.len(), stride, format)?;
+
+        let mut guard = surface.lock_data()?;
+        let surface_stride = guard.stride();
+        let surface_data = guard.data();
+        let row_size = rect.size.width as usize * format.bytes_per_pixel();
+        let start = rect.origin.y as usize * surface_stride +
+            rect.origin.x as usize * format.bytes_per_pixel();
+        for row in 0..(rect.size.height as usize) {
+            let dest_start = start + row * surface_stride;
+            surface_data[dest_start..(dest_start + row_size)]
+                .copy_from_slice(&data[(row * stride)..(row * stride + row_size)]);
+        }
+        Ok(())
+    }
+
+    /// Returns the formats that generic surfaces created on this device may have.
+    #[inline]
+    pub fn supported_surface_formats(&self) -> Vec<SurfaceFormat> {
+        vec![
+            SurfaceFormat::RGBA8,
+            SurfaceFormat::BGRA8,
+            SurfaceFormat::SRGB8A8,
+            SurfaceFormat::RGB10A2,
+            SurfaceFormat::RGBA16F,
+            SurfaceFormat::R8,
+            SurfaceFormat::RG8,
+        ]
+    }
+
+    fn create_io_surface(&self, size: &Size2D<i32>, access: SurfaceAccess, format: SurfaceFormat)
+                         -> IOSurface {
         let cache_mode = match access {
             SurfaceAccess::GPUCPUWriteCombined => kIOMapWriteCombineCache,
             SurfaceAccess::GPUOnly | SurfaceAccess::GPUCPU => kIOMapDefaultCache,
         };
+        let bytes_per_pixel = format.bytes_per_pixel() as i32;
 
         unsafe {
+            // Surfaces are global so that `IOSurfaceLookup()` can find them from other processes
+            // after `export_surface()`.
             let properties = CFDictionary::from_CFType_pairs(&[
                 (CFString::wrap_under_get_rule(kIOSurfaceWidth),
                  CFNumber::from(size.width).as_CFType()),
                 (CFString::wrap_under_get_rule(kIOSurfaceHeight),
                  CFNumber::from(size.height).as_CFType()),
                 (CFString::wrap_under_get_rule(kIOSurfaceBytesPerElement),
-                 CFNumber::from(BYTES_PER_PIXEL).as_CFType()),
+                 CFNumber::from(bytes_per_pixel).as_CFType()),
                 (CFString::wrap_under_get_rule(kIOSurfaceBytesPerRow),
-                 CFNumber::from(size.width * BYTES_PER_PIXEL).as_CFType()),
+                 CFNumber::from(size.width * bytes_per_pixel).as_CFType()),
                 (CFString::wrap_under_get_rule(kIOSurfacePixelFormat),
-                 CFNumber::from(kCVPixelFormatType_32BGRA).as_CFType()),
+                 CFNumber::from(io_surface_pixel_format(format)).as_CFType()),
                 (CFString::wrap_under_get_rule(kIOSurfaceCacheMode),
                  CFNumber::from(cache_mode).as_CFType()),
+                (CFString::wrap_under_get_rule(kIOSurfaceIsGlobal),
+                 CFBoolean::true_value().as_CFType()),
             ]);
 
             io_surface::new(&properties)
@@ -248,6 +340,133 @@ impl Device {
         mem::forget(io_surface);
         NativeSurface(io_surface_ref)
     }
+
+    /// Returns a handle that another process can use to look up this surface.
+    /// 
+    /// Calling this method on a widget surface returns a `WidgetAttached` error.
+    pub fn export_surface(&self, surface: &Surface) -> Result<SurfaceHandle, Error> {
+        if surface.view_info.is_some() {
+            return Err(Error::WidgetAttached);
+        }
+
+        unsafe {
+            let id = IOSurfaceGetID(surface.io_surface.as_concrete_TypeRef());
+            Ok(SurfaceHandle::IOSurface { id, size: surface.size })
+        }
+    }
+
+    /// Opens a surface exported from another process.
+    /// 
+    /// `IOSurface` handles are looked up by ID and share memory with the exporting surface.
+    /// Shared memory handles are copied into a new surface.
+    pub fn create_surface_from_handle(&self, handle: &SurfaceHandle)
+                                      -> Result<Surface, Error> {
+        match *handle {
+            SurfaceHandle::IOSurface { id, size } => {
+                unsafe {
+                    let io_surface_ref = IOSurfaceLookup(id);
+                    if io_surface_ref.is_null() {
+                        let failure = WindowingApiFailure::new("IOSurfaceLookup",
+                                                               WindowingApiError::BadParameter,
+                                                               None);
+                        return Err(Error::SurfaceImportFailed(Box::new(failure)));
+                    }
+                    let io_surface = IOSurface::wrap_under_create_rule(io_surface_ref);
+                    let pixel_format = IOSurfaceGetPixelFormat(io_surface_ref) as i32;
+                    let format = match surface_format_from_io_surface_pixel_format(pixel_format) {
+                        Some(format) => format,
+                        None => {
+                            let failure = WindowingApiError::BadParameter.into();
+                            return Err(Error::SurfaceImportFailed(Box::new(failure)));
+                        }
+                    };
+                    Ok(Surface {
+                        io_surface,
+                        size,
+                        format,
+                        access: SurfaceAccess::GPUOnly,
+                        destroyed: false,
+                        view_info: None,
+                        origin: Origin::new(&self.leak_tracker),
+                    })
+                }
+            }
+            SurfaceHandle::SharedMemory(ref image) => {
+                let src_stride = image.stride as usize;
+                let mapping =
+                    SharedMemoryMapping::open(image.fd, src_stride * image.size.height as usize)?;
+                // RGBA images are swizzled, since IOSurfaces store 8-bit color as BGRA. Every
+                // other format is copied as is.
+                let format = match image.format {
+                    SurfaceFormat::RGBA8 => SurfaceFormat::BGRA8,
+                    format => format,
+                };
+                let mut surface = Surface {
+                    io_surface: self.create_io_surface(&image.size, SurfaceAccess::GPUCPU, format),
+                    size: image.size,
+                    format,
+                    access: SurfaceAccess::GPUCPU,
+                    destroyed: false,
+                    view_info: None,
+                    origin: Origin::new(&self.leak_tracker),
+                };
+                let result = surface.lock_data().map(|mut guard| {
+                    let dest_stride = guard.stride();
+                    let dest = guard.data();
+                    let src = unsafe { slice::from_raw_parts(mapping.ptr, mapping.len) };
+                    let row_len = image.size.width as usize * format.bytes_per_pixel();
+                    for y in 0..(image.size.height as usize) {
+                        let src_row = &src[(y * src_stride)..][..row_len];
+                        let dest_row = &mut dest[(y * dest_stride)..][..row_len];
+                        if image.format != SurfaceFormat::RGBA8 {
+                            dest_row.copy_from_slice(src_row);
+                            continue;
+                        }
+                        for x in 0..(image.size.width as usize) {
+                            // RGBA to BGRA.
+                            let offset = x * 4;
+                            dest_row[offset + 0] = src_row[offset + 2];
+                            dest_row[offset + 1] = src_row[offset + 1];
+                            dest_row[offset + 2] = src_row[offset + 0];
+                            dest_row[offset + 3] = src_row[offset + 3];
+                        }
+                    }
+                });
+                match result {
+                    Ok(()) => Ok(surface),
+                    Err(err) => {
+                        surface.destroyed = true;
+                        Err(err)
+                    }
+                }
+            }
+            SurfaceHandle::DmaBuf(_) | SurfaceHandle::D3DShareHandle { .. } => {
+                Err(Error::UnsupportedOnThisPlatform)
+            }
+        }
+    }
+
+    // Retains the `IOSurface`, so the caller keeps its own reference.
+    pub(crate) unsafe fn create_surface_from_native_surface(&self, native_surface: &NativeSurface)
+                                                            -> Result<Surface, Error> {
+        let io_surface_ref = native_surface.0;
+        let pixel_format = IOSurfaceGetPixelFormat(io_surface_ref) as i32;
+        let format = match surface_format_from_io_surface_pixel_format(pixel_format) {
+            Some(format) => format,
+            None => return Err(Error::UnsupportedSurfaceFormat),
+        };
+        let size = Size2D::new(IOSurfaceGetWidth(io_surface_ref) as i32,
+                               IOSurfaceGetHeight(io_surface_ref) as i32);
+        Ok(Surface {
+            io_surface: IOSurface::wrap_under_get_rule(io_surface_ref),
+            size,
+            format,
+            access: SurfaceAccess::GPUOnly,
+            destroyed: false,
+            view_info: None,
+            origin: Origin::new(&self.leak_tracker),
+        })
+    }
 }
 
 impl Surface {
@@ -305,7 +524,9 @@ impl<'a> SurfaceDataGuard<'a> {
     #[inline]
     pub fn stride(&self) -> usize { self.stride }
 
-    /// Returns a mutable slice of the pixel data in this surface, in BGRA format.
+    /// Returns a mutable slice of the pixel data in this surface, in the surface's format.
+    /// 
+    /// Widget surfaces are always in BGRA format.
     #[inline]
     pub fn data(&mut self) -> &mut [u8] {
         unsafe {
@@ -324,6 +545,31 @@ impl<'a> Drop for SurfaceDataGuard<'a> {
     }
 }
 
+// Sampling from an `SRGB8A8` surface decodes the stored values, but the storage itself is plain
+// BGRA, so both formats share a pixel format.
+fn io_surface_pixel_format(format: SurfaceFormat) -> i32 {
+    match format {
+        SurfaceFormat::RGBA8 => kCVPixelFormatType_32RGBA,
+        SurfaceFormat::BGRA8 | SurfaceFormat::SRGB8A8 => kCVPixelFormatType_32BGRA,
+        SurfaceFormat::RGB10A2 => kCVPixelFormatType_ARGB2101010LEPacked,
+        SurfaceFormat::RGBA16F => kCVPixelFormatType_64RGBAHalf,
+        SurfaceFormat::R8 => kCVPixelFormatType_OneComponent8,
+        SurfaceFormat::RG8 => kCVPixelFormatType_TwoComponent8,
+    }
+}
+
+fn surface_format_from_io_surface_pixel_format(pixel_format: i32) -> Option<SurfaceFormat> {
+    match pixel_format {
+        kCVPixelFormatType_32RGBA => Some(SurfaceFormat::RGBA8),
+        kCVPixelFormatType_32BGRA => Some(SurfaceFormat::BGRA8),
+        kCVPixelFormatType_ARGB2101010LEPacked => Some(SurfaceFormat::RGB10A2),
+        kCVPixelFormatType_64RGBAHalf => Some(SurfaceFormat::RGBA16F),
+        kCVPixelFormatType_OneComponent8 => Some(SurfaceFormat::R8),
+        kCVPixelFormatType_TwoComponent8 => Some(SurfaceFormat::RG8),
+        _ => None,
+    }
+}
+
 impl Drop for NativeWidget {
     #[inline]
     fn drop(&mut self) {
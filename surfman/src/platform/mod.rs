@@ -20,6 +20,8 @@ pub use macos::system;
 pub mod unix;
 #[cfg(all(unix, not(target_os = "macos"), not(target_os = "android")))]
 pub use unix::default;
+#[cfg(all(unix, not(target_os = "macos"), not(target_os = "android")))]
+pub use unix::system;
 
 #[cfg(target_os = "windows")]
 pub mod windows;
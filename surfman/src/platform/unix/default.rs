@@ -35,7 +35,8 @@ pub mod context {
     /// OpenGL objects may not be shared across contexts directly, but surface textures effectively
     /// allow for sharing of texture data. Contexts are local to a single thread and device.
     /// 
-    /// A context must be explicitly destroyed with `destroy_context()`, or a panic will occur.
+    /// A context should be explicitly destroyed with `destroy_context()`. A context that is dropped
+    /// without being destroyed is handled according to the device's `LeakPolicy`.
     pub type Context = MultiContext<WaylandDevice, X11Device>;
 
     /// Information needed to create a context. Some APIs call this a "config" or a "pixel format".
@@ -46,10 +47,14 @@ pub mod context {
 
 /// Thread-local handles to devices.
 pub mod device {
+    use crate::platform::generic::multi::context::ContextDescriptor as MultiContextDescriptor;
     use crate::platform::generic::multi::device::Adapter as MultiAdapter;
     use crate::platform::generic::multi::device::Device as MultiDevice;
     use crate::platform::unix::wayland::device::Device as WaylandDevice;
     use crate::platform::unix::x11::device::Device as X11Device;
+    use super::context::ContextDescriptor;
+
+    use x11::xlib::XVisualInfo;
 
     /// Represents a hardware display adapter that can be used for rendering (including the CPU).
     ///
@@ -61,10 +66,28 @@ pub mod device {
     ///
     /// Devices contain most of the relevant surface management methods.
     pub type Device = MultiDevice<WaylandDevice, X11Device>;
+
+    impl Device {
+        /// Returns the X visual that windows must be created with in order to display widget
+        /// surfaces belonging to contexts created from the given descriptor.
+        ///
+        /// Returns `None` on Wayland, which has no visuals, and wherever the X11 device's method
+        /// of the same name does.
+        pub fn context_descriptor_visual_info(&self, context_descriptor: &ContextDescriptor)
+                                              -> Option<XVisualInfo> {
+            match (self, context_descriptor) {
+                (MultiDevice::Alternate(device), MultiContextDescriptor::Alternate(descriptor)) => {
+                    device.context_descriptor_visual_info(descriptor)
+                }
+                _ => None,
+            }
+        }
+    }
 }
 
 /// Hardware buffers of pixels.
 pub mod surface {
+    use crate::platform::generic::multi::surface::NativeImage as MultiNativeImage;
     use crate::platform::generic::multi::surface::NativeWidget as MultiNativeWidget;
     use crate::platform::generic::multi::surface::Surface as MultiSurface;
     use crate::platform::generic::multi::surface::SurfaceTexture as MultiSurfaceTexture;
@@ -74,6 +97,10 @@ pub mod surface {
     /// A wrapper for a Wayland surface or an X11 `Window`, as appropriate.
     pub type NativeWidget = MultiNativeWidget<WaylandDevice, X11Device>;
 
+    /// A texture or EGL image created outside `surfman`, for the Wayland or X11 backend as
+    /// appropriate.
+    pub type NativeImage = MultiNativeImage<WaylandDevice, X11Device>;
+
     /// Represents a hardware buffer of pixels that can be rendered to via the CPU or GPU and
     /// either displayed in a native widget or bound to a texture for reading.
     /// 
@@ -89,7 +116,8 @@ pub mod surface {
     /// 
     /// Depending on the platform, each surface may be internally double-buffered.
     /// 
-    /// Surfaces must be destroyed with the `destroy_surface()` method, or a panic will occur.
+    /// Surfaces should be destroyed with the `destroy_surface()` method. A surface that is dropped
+    /// without being destroyed is handled according to the device's `LeakPolicy`.
     pub type Surface = MultiSurface<WaylandDevice, X11Device>;
 
     /// Represents an OpenGL texture that wraps a surface.
@@ -99,8 +127,9 @@ pub mod surface {
     /// framebuffer).
     /// 
     /// Surface textures are local to a context, but that context does not have to be the same
-    /// context as that associated with the underlying surface. The texture must be destroyed with
-    /// the `destroy_surface_texture()` method, or a panic will occur.
+    /// context as that associated with the underlying surface. The texture should be destroyed with
+    /// the `destroy_surface_texture()` method. Dropping it instead leaks the texture object and
+    /// drops its surface, which is handled according to the device's `LeakPolicy`.
     pub type SurfaceTexture = MultiSurfaceTexture<WaylandDevice, X11Device>;
 
     // FIXME(pcwalton): Revamp how this works.
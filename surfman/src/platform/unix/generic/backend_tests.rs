@@ -0,0 +1,233 @@
+// surfman/surfman/src/platform/unix/generic/backend_tests.rs
+//
+//! Unit tests for features only the Linux backends have, run against the surfaceless backend
+//! since it needs no display server.
+
+use crate::gl::types::{GLenum, GLuint};
+use crate::gl;
+use crate::ipc::SharedMemoryMapping;
+use crate::platform::unix::wayland::buffer::WaylandBufferFormat;
+use crate::platform::unix::wayland::ffi::{WL_BUFFER_DESTROY, WL_BUFFER_INTERFACE};
+use crate::platform::unix::wayland::ffi::{WL_SHM_CREATE_POOL, WL_SHM_FORMAT_ARGB8888};
+use crate::platform::unix::wayland::ffi::{WL_SHM_POOL_CREATE_BUFFER, WL_SHM_POOL_DESTROY};
+use crate::platform::unix::wayland::ffi::WL_SHM_POOL_INTERFACE;
+use crate::platform::unix::wayland::globals::WaylandGlobals;
+use crate::{ContextAttributeFlags, ContextAttributes, Error, GLVersion, Gl, SurfaceAccess};
+use crate::{SurfaceFormat, SurfaceTextureTarget, SurfaceType, WindowingApiError};
+use super::connection::Connection;
+use super::context::Context;
+use super::device::Device;
+
+use euclid::default::Size2D;
+use std::os::raw::c_void;
+use std::ptr;
+use std::slice;
+use std::sync::mpsc;
+use std::thread;
+use wayland_sys::client::WAYLAND_CLIENT_HANDLE;
+use wayland_sys::server::WAYLAND_SERVER_OPTION;
+
+struct Environment {
+    device: Device,
+    context: Context,
+    gl: Gl,
+}
+
+impl Environment {
+    // Returns `None` if the hardware can't run these tests.
+    fn new() -> Option<Environment> {
+        let connection = Connection::new().unwrap();
+        let adapter = connection.create_low_power_adapter().unwrap();
+        let mut device = match connection.create_device(&adapter) {
+            Ok(device) => device,
+            Err(Error::RequiredExtensionUnavailable) => return None,
+            Err(err) => panic!("Failed to create device: {:?}", err),
+        };
+
+        let attributes = ContextAttributes::new(GLVersion::new(3, 0),
+                                                ContextAttributeFlags::empty());
+        let context_descriptor = device.create_context_descriptor(&attributes).unwrap();
+        let mut context = device.create_context(&context_descriptor).unwrap();
+        let surface_type = SurfaceType::Generic {
+            size: Size2D::new(16, 16),
+            format: SurfaceFormat::RGBA8,
+            depth_stencil: None,
+        };
+        let surface = device.create_surface(&context, SurfaceAccess::GPUOnly, surface_type)
+                            .unwrap();
+        device.bind_surface_to_context(&mut context, surface).unwrap();
+        device.make_context_current(&context).unwrap();
+        let gl = Gl::load_with(|symbol| device.get_proc_address(&context, symbol));
+        Some(Environment { device, context, gl })
+    }
+}
+
+// Serve a `wl_shm` buffer from an in-process Wayland client and make sure that it's copied into a
+// texture whose row 0 is the top row of the buffer, skipping the padding at the end of each row.
+#[test]
+fn test_wl_shm_buffer_import() {
+    let mut env = match Environment::new() {
+        None => return,
+        Some(env) => env,
+    };
+    let wayland_server = match *WAYLAND_SERVER_OPTION {
+        Some(ref wayland_server) => wayland_server,
+        None => {
+            env.device.destroy_context(&mut env.context).unwrap();
+            return;
+        }
+    };
+
+    unsafe {
+        let display = (wayland_server.wl_display_create)();
+        assert_eq!((wayland_server.wl_display_init_shm)(display), 0);
+        let mut fds = [0; 2];
+        assert_eq!(libc::socketpair(libc::AF_UNIX,
+                                    libc::SOCK_STREAM | libc::SOCK_CLOEXEC,
+                                    0,
+                                    fds.as_mut_ptr()),
+                   0);
+        let client = (wayland_server.wl_client_create)(display, fds[0]);
+        assert!(!client.is_null());
+
+        let (buffer_id_sender, buffer_id_receiver) = mpsc::channel();
+        let (done_sender, done_receiver) = mpsc::channel();
+        let client_fd = fds[1];
+        let client_thread = thread::spawn(move || {
+            run_wl_shm_client(client_fd, buffer_id_sender, done_receiver)
+        });
+
+        // Serve the client until it has created its buffer.
+        let event_loop = (wayland_server.wl_display_get_event_loop)(display);
+        let buffer_id = loop {
+            (wayland_server.wl_display_flush_clients)(display);
+            (wayland_server.wl_event_loop_dispatch)(event_loop, 10);
+            if let Ok(buffer_id) = buffer_id_receiver.try_recv() {
+                break buffer_id;
+            }
+        };
+        let buffer = (wayland_server.wl_client_get_object)(client, buffer_id);
+        assert!(!buffer.is_null());
+
+        let info = env.device.wl_buffer_info(buffer).unwrap();
+        assert_eq!(info.size, Size2D::new(2, 2));
+        assert_eq!(info.format, WaylandBufferFormat::SharedMemory);
+        assert!(info.top_row_first);
+
+        let surface_texture = env.device
+                                 .create_surface_texture_from_wl_buffer(&mut env.context,
+                                                                        buffer,
+                                                                        0,
+                                                                        SurfaceTextureTarget::Any)
+                                 .unwrap();
+        let target = env.device.surface_texture_target(&surface_texture);
+        assert_eq!(target, gl::TEXTURE_2D);
+        let framebuffer_object =
+            make_fbo(&env.gl, target, env.device.surface_texture_object(&surface_texture));
+        assert_eq!(read_pixel(&env.gl, 0, 0), [255, 0, 0, 255]);
+        assert_eq!(read_pixel(&env.gl, 0, 1), [0, 255, 0, 128]);
+        env.gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+        env.gl.DeleteFramebuffers(1, &framebuffer_object);
+        assert_eq!(env.gl.GetError(), gl::NO_ERROR);
+
+        let mut surface = env.device
+                             .destroy_surface_texture(&mut env.context, surface_texture)
+                             .unwrap();
+        env.device.destroy_surface(&mut env.context, &mut surface).unwrap();
+
+        // `wl_shm` buffers only have one plane.
+        match env.device.create_surface_texture_from_wl_buffer(&mut env.context,
+                                                               buffer,
+                                                               1,
+                                                               SurfaceTextureTarget::Any) {
+            Err(Error::SurfaceImportFailed(ref failure))
+                    if failure.error == WindowingApiError::BadParameter => {}
+            Err(err) => panic!("Expected `BadParameter` but got {:?}!", err),
+            Ok(_) => panic!("Shouldn't be able to import a plane that doesn't exist!"),
+        }
+
+        done_sender.send(()).unwrap();
+        client_thread.join().unwrap();
+        (wayland_server.wl_client_destroy)(client);
+        (wayland_server.wl_display_destroy)(display);
+    }
+
+    env.device.destroy_context(&mut env.context).unwrap();
+}
+
+// Connects to an in-process compositor over the given socket and creates a 2x2 ARGB `wl_shm`
+// buffer whose top row is opaque red and whose bottom row is half-transparent green. Sends the
+// buffer's ID once the compositor has seen it, and destroys the buffer when told to.
+fn run_wl_shm_client(fd: i32, buffer_id_sender: mpsc::Sender<u32>, done: mpsc::Receiver<()>) {
+    const STRIDE: usize = 16;
+    const LEN: usize = STRIDE * 2;
+
+    unsafe {
+        let wayland_client = &*WAYLAND_CLIENT_HANDLE;
+        let display = (wayland_client.wl_display_connect_to_fd)(fd);
+        assert!(!display.is_null());
+        let globals = WaylandGlobals::new(display).unwrap();
+        assert!(!globals.shm().is_null());
+
+        let (shm_fd, mapping) = SharedMemoryMapping::create(LEN).unwrap();
+        let data = slice::from_raw_parts_mut(mapping.ptr, LEN);
+        for byte in data.iter_mut() {
+            *byte = 0xee;
+        }
+        for x in 0..2 {
+            // Little-endian ARGB.
+            data[x * 4..x * 4 + 4].copy_from_slice(&[0x00, 0x00, 0xff, 0xff]);
+            data[STRIDE + x * 4..STRIDE + x * 4 + 4].copy_from_slice(&[0x00, 0xff, 0x00, 0x80]);
+        }
+        drop(mapping);
+
+        let pool = (wayland_client.wl_proxy_marshal_constructor)(globals.shm(),
+                                                                 WL_SHM_CREATE_POOL,
+                                                                 &WL_SHM_POOL_INTERFACE.0,
+                                                                 ptr::null_mut::<c_void>(),
+                                                                 shm_fd,
+                                                                 LEN as i32);
+        libc::close(shm_fd);
+        let buffer = (wayland_client.wl_proxy_marshal_constructor)(pool,
+                                                                   WL_SHM_POOL_CREATE_BUFFER,
+                                                                   &WL_BUFFER_INTERFACE.0,
+                                                                   ptr::null_mut::<c_void>(),
+                                                                   0,
+                                                                   2,
+                                                                   2,
+                                                                   STRIDE as i32,
+                                                                   WL_SHM_FORMAT_ARGB8888);
+        (wayland_client.wl_proxy_marshal)(pool, WL_SHM_POOL_DESTROY);
+        (wayland_client.wl_proxy_destroy)(pool);
+        assert!((wayland_client.wl_display_roundtrip)(display) >= 0);
+
+        buffer_id_sender.send((wayland_client.wl_proxy_get_id)(buffer)).unwrap();
+        done.recv().unwrap();
+
+        (wayland_client.wl_proxy_marshal)(buffer, WL_BUFFER_DESTROY);
+        (wayland_client.wl_proxy_destroy)(buffer);
+        drop(globals);
+        (wayland_client.wl_display_disconnect)(display);
+    }
+}
+
+fn make_fbo(gl: &Gl, texture_target: GLenum, texture: GLuint) -> GLuint {
+    unsafe {
+        let mut framebuffer_object = 0;
+        gl.GenFramebuffers(1, &mut framebuffer_object);
+        gl.BindFramebuffer(gl::FRAMEBUFFER, framebuffer_object);
+        gl.FramebufferTexture2D(gl::FRAMEBUFFER,
+                                gl::COLOR_ATTACHMENT0,
+                                texture_target,
+                                texture,
+                                0);
+        assert_eq!(gl.CheckFramebufferStatus(gl::FRAMEBUFFER), gl::FRAMEBUFFER_COMPLETE);
+        framebuffer_object
+    }
+}
+
+unsafe fn read_pixel(gl: &Gl, x: i32, y: i32) -> [u8; 4] {
+    let mut pixel = [0; 4];
+    gl.ReadPixels(x, y, 1, 1, gl::RGBA, gl::UNSIGNED_BYTE, pixel.as_mut_ptr() as *mut c_void);
+    pixel
+}
@@ -7,11 +7,13 @@ use crate::egl::types::{EGLAttrib, EGLDisplay};
 use crate::egl;
 use crate::platform::generic::egl::device::EGL_FUNCTIONS;
 use crate::platform::generic::egl::ffi::EGL_PLATFORM_SURFACELESS_MESA;
+use crate::platform::generic::egl::wayland;
 use super::device::{Adapter, Device, NativeDevice};
 use super::surface::NativeWidget;
 
 use std::os::raw::c_void;
 use std::sync::Arc;
+use wayland_sys::server;
 
 #[cfg(feature = "sm-winit")]
 use winit::Window;
@@ -123,6 +125,23 @@ impl Connection {
         Device::new(self, &self.create_adapter()?) 
     }
 
+    /// Lets clients of the given compositor display allocate buffers that
+    /// `Device::create_surface_texture_from_wl_buffer()` samples without a copy.
+    ///
+    /// The display must be unbound with `unbind_wayland_display()` before it's destroyed.
+    #[inline]
+    pub unsafe fn bind_wayland_display(&self, wayland_display: *mut server::wl_display)
+                                       -> Result<(), Error> {
+        wayland::bind_wayland_display(self.native_connection.egl_display, wayland_display)
+    }
+
+    /// Undoes `bind_wayland_display()`.
+    #[inline]
+    pub unsafe fn unbind_wayland_display(&self, wayland_display: *mut server::wl_display)
+                                         -> Result<(), Error> {
+        wayland::unbind_wayland_display(self.native_connection.egl_display, wayland_display)
+    }
+
     /// Opens the display connection corresponding to the given `winit` window.
     #[inline]
     #[cfg(feature = "sm-winit")]
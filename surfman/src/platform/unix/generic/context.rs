@@ -2,15 +2,17 @@
 //
 //! OpenGL rendering contexts on surfaceless Mesa.
 
-use crate::context::ContextID;
+use crate::context::{ContextDescriptorDiagnostics, ContextID, choose_context_descriptor};
 use crate::egl::types::EGLint;
 use crate::egl;
+use crate::leak::Origin;
 use crate::platform::generic::egl::context::{self, CurrentContextGuard, EGLBackedContext};
-use crate::{ContextAttributes, Error, Gl, SurfaceInfo};
+use crate::{ContextAttributes, Error, GLApi, GLInfo, Gl, PixelFormatAttributes, SurfaceInfo};
 use super::device::Device;
 use super::surface::Surface;
 
 use std::os::raw::c_void;
+use std::rc::Rc;
 
 pub use crate::platform::generic::egl::context::{ContextDescriptor, NativeContext};
 
@@ -19,6 +21,13 @@ thread_local! {
     pub static GL_FUNCTIONS: Gl = Gl::load_with(context::get_proc_address);
 }
 
+// The config attributes that every context on this device needs: surfaces are pbuffers, since
+// there is no window system.
+const CONFIG_ATTRIBUTES: [EGLint; 4] = [
+    egl::SURFACE_TYPE as EGLint,        egl::PBUFFER_BIT as EGLint,
+    egl::COLOR_BUFFER_TYPE as EGLint,   egl::RGB_BUFFER as EGLint,
+];
+
 /// Represents an OpenGL rendering context.
 /// 
 /// A context allows you to issue rendering commands to a surface. When initially created, a
@@ -34,7 +43,8 @@ thread_local! {
 /// OpenGL objects may not be shared across contexts directly, but surface textures effectively
 /// allow for sharing of texture data. Contexts are local to a single thread and device.
 /// 
-/// A context must be explicitly destroyed with `destroy_context()`, or a panic will occur.
+/// A context should be explicitly destroyed with `destroy_context()`. A context that is dropped
+/// without being destroyed is handled according to the device's `LeakPolicy`.
 pub struct Context(pub(crate) EGLBackedContext);
 
 impl Device {
@@ -48,11 +58,61 @@ impl Device {
         self.adapter.set_environment_variables();
 
         unsafe {
-            ContextDescriptor::new(self.native_connection.egl_display, attributes, &[
-                egl::SURFACE_TYPE as EGLint,        egl::PBUFFER_BIT as EGLint,
-                egl::RENDERABLE_TYPE as EGLint,     egl::OPENGL_BIT as EGLint,
-                egl::COLOR_BUFFER_TYPE as EGLint,   egl::RGB_BUFFER as EGLint,
-            ])
+            ContextDescriptor::new(self.native_connection.egl_display,
+                                   attributes,
+                                   self.gl_api(),
+                                   &CONFIG_ATTRIBUTES)
+        }
+    }
+
+    /// Returns a context descriptor for every pixel format that this device can create contexts
+    /// with, along with the attributes of each pixel format.
+    ///
+    /// The version and profile of the descriptors come from `attributes`. The `ALPHA`, `DEPTH`,
+    /// and `STENCIL` flags are ignored; use the pixel format attributes to tell the descriptors
+    /// apart.
+    pub fn enumerate_context_descriptors(&self, attributes: &ContextAttributes)
+                                         -> Result<Vec<(ContextDescriptor, PixelFormatAttributes)>,
+                                                   Error> {
+        // Set environment variables as appropriate.
+        self.adapter.set_environment_variables();
+
+        let egl_display = self.native_connection.egl_display;
+        unsafe {
+            ContextDescriptor::enumerate(egl_display,
+                                         attributes,
+                                         self.gl_api(),
+                                         &CONFIG_ATTRIBUTES)
+        }
+    }
+
+    /// Creates a context descriptor from one of the pixel formats that
+    /// `enumerate_context_descriptors()` returns.
+    ///
+    /// `choose` receives the attributes of every candidate pixel format and returns the index of
+    /// the one to use. If it returns `None` or an index out of range, a `NoPixelFormatFound` error
+    /// is returned.
+    pub fn create_context_descriptor_with<F>(&self, attributes: &ContextAttributes, choose: F)
+                                             -> Result<ContextDescriptor, Error>
+                                             where F: FnOnce(&[PixelFormatAttributes]) ->
+                                                   Option<usize> {
+        let candidates = self.enumerate_context_descriptors(attributes)?;
+        choose_context_descriptor(candidates, choose)
+    }
+
+    /// Explains which pixel formats `create_context_descriptor()` passes over for the given
+    /// attributes, and why.
+    ///
+    /// This is meant for finding out why `create_context_descriptor()` returned
+    /// `NoPixelFormatFound` or `UnsupportedGLProfile`.
+    pub fn diagnose_context_descriptor(&self, attributes: &ContextAttributes)
+                                       -> ContextDescriptorDiagnostics {
+        // Set environment variables as appropriate.
+        self.adapter.set_environment_variables();
+
+        let egl_display = self.native_connection.egl_display;
+        unsafe {
+            ContextDescriptor::diagnose(egl_display, attributes, self.gl_api(), &CONFIG_ATTRIBUTES)
         }
     }
 
@@ -63,9 +123,9 @@ impl Device {
     #[inline]
     pub fn create_context(&mut self, descriptor: &ContextDescriptor) -> Result<Context, Error> {
         unsafe {
-            EGLBackedContext::new(self.native_connection.egl_display,
-                                  descriptor,
-                                  self.gl_api()).map(Context)
+            let origin = Origin::new(&self.leak_tracker);
+            EGLBackedContext::new(self.native_connection.egl_display, descriptor, origin)
+                .map(Context)
         }
     }
 
@@ -77,13 +137,16 @@ impl Device {
     #[inline]
     pub unsafe fn create_context_from_native_context(&self, native_context: NativeContext)
                                                      -> Result<Context, Error> {
-        Ok(Context(EGLBackedContext::from_native_context(native_context)))
+        let origin = Origin::new(&self.leak_tracker);
+        Ok(Context(EGLBackedContext::from_native_context(native_context, origin)))
     }
 
     /// Destroys a context.
     /// 
     /// The context must have been created on this device.
     pub fn destroy_context(&self, context: &mut Context) -> Result<(), Error> {
+        self.destroy_deferred_objects(context);
+
         if let Ok(Some(mut surface)) = self.unbind_surface_from_context(context) {
             self.destroy_surface(context, &mut surface)?;
         }
@@ -117,6 +180,8 @@ impl Device {
     /// After calling this function, it is valid to use OpenGL rendering commands.
     #[inline]
     pub fn make_context_current(&self, context: &Context) -> Result<(), Error> {
+        self.destroy_deferred_objects(context);
+
         unsafe {
             context.0.make_current(self.native_connection.egl_display)
         }
@@ -141,6 +206,28 @@ impl Device {
         Ok(guard)
     }
 
+    // Destroys the contexts that this thread dropped under `LeakPolicy::Defer`.
+    pub(crate) fn destroy_deferred_contexts(&self) {
+        GL_FUNCTIONS.with(|gl| {
+            unsafe {
+                let egl_display = self.native_connection.egl_display;
+                context::destroy_deferred_contexts(gl, egl_display, &self.leak_tracker);
+            }
+        })
+    }
+
+    // Destroys the contexts that this thread dropped under `LeakPolicy::Defer`, and the surfaces
+    // of the given context that were dropped under it.
+    fn destroy_deferred_objects(&self, context: &Context) {
+        GL_FUNCTIONS.with(|gl| {
+            unsafe {
+                let egl_display = self.native_connection.egl_display;
+                context::destroy_deferred_contexts(gl, egl_display, &self.leak_tracker);
+                context.0.destroy_deferred_surfaces(gl, egl_display, &self.leak_tracker);
+            }
+        })
+    }
+
     /// Returns the attributes that the context descriptor was created with.
     #[inline]
     pub fn context_descriptor_attributes(&self, context_descriptor: &ContextDescriptor)
@@ -150,6 +237,26 @@ impl Device {
         }
     }
 
+    /// Returns the OpenGL API flavor (OpenGL or OpenGL ES) that the context was created for.
+    #[inline]
+    pub fn context_gl_api(&self, context: &Context) -> GLApi {
+        unsafe {
+            context::context_gl_api(self.native_connection.egl_display, context.0.egl_context)
+        }
+    }
+
+    /// Returns what the OpenGL implementation reports about the context: its version and
+    /// profile, the vendor and renderer, and the extensions it supports.
+    ///
+    /// The context is queried the first time this is called, and the same information is
+    /// returned after that.
+    pub fn gl_info(&self, context: &Context) -> Result<Rc<GLInfo>, Error> {
+        context.0.gl_info.get_or_query(|| {
+            let _guard = self.temporarily_make_context_current(context)?;
+            GL_FUNCTIONS.with(GLInfo::current)
+        })
+    }
+
     /// Fetches the address of an OpenGL function associated with this context.
     /// 
     /// OpenGL functions are local to a context. You should not use OpenGL functions on one context
@@ -14,3 +14,5 @@ mod implementation;
 #[cfg(test)]
 #[path = "../../../tests.rs"]
 mod tests;
+#[cfg(test)]
+mod backend_tests;
@@ -7,6 +7,8 @@ pub mod default;
 #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
 pub mod generic;
 #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+pub mod system;
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
 pub mod wayland;
 #[cfg(all(any(feature = "sm-x11",
               all(unix, not(any(target_os = "macos", target_os = "android"))))))]
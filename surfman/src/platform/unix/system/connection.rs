@@ -0,0 +1,201 @@
+// surfman/surfman/src/platform/unix/system/connection.rs
+//
+//! A connection to either a Wayland or an X11 display server.
+
+use crate::Error;
+use super::device::{Adapter, Device, NativeDevice};
+use super::surface::NativeWidget;
+
+use std::ptr;
+use std::sync::Arc;
+use wayland_sys::client::{WAYLAND_CLIENT_HANDLE, WAYLAND_CLIENT_OPTION, wl_display};
+use x11::xlib::{Display, XCloseDisplay, XInitThreads, XOpenDisplay};
+
+#[cfg(feature = "sm-winit")]
+use euclid::default::Size2D;
+#[cfg(feature = "sm-winit")]
+use wayland_sys::client::wl_proxy;
+#[cfg(feature = "sm-winit")]
+use winit::Window;
+#[cfg(feature = "sm-winit")]
+use winit::os::unix::WindowExt;
+
+lazy_static! {
+    static ref X_THREADS_INIT: () = {
+        unsafe {
+            XInitThreads();
+        }
+    };
+}
+
+/// A connection to the Wayland or X11 display server.
+#[derive(Clone)]
+pub struct Connection {
+    pub(crate) native_connection: Arc<NativeConnectionWrapper>,
+}
+
+unsafe impl Send for Connection {}
+
+pub(crate) struct NativeConnectionWrapper {
+    pub(crate) native_connection: NativeConnection,
+    is_owned: bool,
+}
+
+/// The native display server connection.
+#[derive(Clone, Copy)]
+pub enum NativeConnection {
+    /// A Wayland display.
+    Wayland(*mut wl_display),
+    /// An Xlib display.
+    X11(*mut Display),
+}
+
+impl Drop for NativeConnectionWrapper {
+    fn drop(&mut self) {
+        if !self.is_owned {
+            return;
+        }
+        unsafe {
+            match self.native_connection {
+                NativeConnection::Wayland(wayland_display) => {
+                    (WAYLAND_CLIENT_HANDLE.wl_display_disconnect)(wayland_display);
+                }
+                NativeConnection::X11(x11_display) => {
+                    XCloseDisplay(x11_display);
+                }
+            }
+        }
+    }
+}
+
+impl Connection {
+    /// Connects to the default display server.
+    ///
+    /// The Wayland compositor is preferred if one is running; otherwise, this connects to the X11
+    /// server named by `DISPLAY`.
+    pub fn new() -> Result<Connection, Error> {
+        unsafe {
+            if let Some(ref wayland_client) = *WAYLAND_CLIENT_OPTION {
+                let wayland_display = (wayland_client.wl_display_connect)(ptr::null());
+                if !wayland_display.is_null() {
+                    return Ok(Connection::from_native(NativeConnection::Wayland(wayland_display),
+                                                      true));
+                }
+            }
+
+            *X_THREADS_INIT;
+            let x11_display = XOpenDisplay(ptr::null());
+            if x11_display.is_null() {
+                return Err(Error::ConnectionFailed);
+            }
+            Ok(Connection::from_native(NativeConnection::X11(x11_display), true))
+        }
+    }
+
+    /// Wraps an existing Wayland or X11 display in a `Connection`.
+    ///
+    /// The display is not retained. Therefore, it is the caller's responsibility to ensure that
+    /// the display remains open as long as the connection is alive. Xlib displays must have been
+    /// opened after calling `XInitThreads()`.
+    #[inline]
+    pub unsafe fn from_native_connection(native_connection: NativeConnection)
+                                         -> Result<Connection, Error> {
+        Ok(Connection::from_native(native_connection, false))
+    }
+
+    fn from_native(native_connection: NativeConnection, is_owned: bool) -> Connection {
+        Connection {
+            native_connection: Arc::new(NativeConnectionWrapper { native_connection, is_owned }),
+        }
+    }
+
+    /// Returns the underlying native connection.
+    #[inline]
+    pub fn native_connection(&self) -> NativeConnection {
+        self.native_connection.native_connection
+    }
+
+    /// Returns the "best" adapter on this system.
+    ///
+    /// All adapters are the CPU on this backend, so this is equivalent to every other
+    /// `create_*_adapter()` method.
+    #[inline]
+    pub fn create_adapter(&self) -> Result<Adapter, Error> {
+        Ok(Adapter)
+    }
+
+    /// Returns the "best" adapter on this system, preferring high-performance hardware adapters.
+    #[inline]
+    pub fn create_hardware_adapter(&self) -> Result<Adapter, Error> {
+        self.create_adapter()
+    }
+
+    /// Returns the "best" adapter on this system, preferring low-power hardware adapters.
+    #[inline]
+    pub fn create_low_power_adapter(&self) -> Result<Adapter, Error> {
+        self.create_adapter()
+    }
+
+    /// Returns the "best" adapter on this system, preferring software adapters.
+    #[inline]
+    pub fn create_software_adapter(&self) -> Result<Adapter, Error> {
+        self.create_adapter()
+    }
+
+    /// Opens the device corresponding to the given adapter.
+    ///
+    /// Device handles are local to a single thread.
+    #[inline]
+    pub fn create_device(&self, adapter: &Adapter) -> Result<Device, Error> {
+        Device::new(self, adapter)
+    }
+
+    /// An alias for `connection.create_device()` with the default adapter.
+    #[inline]
+    pub unsafe fn create_device_from_native_device(&self, _: NativeDevice)
+                                                   -> Result<Device, Error> {
+        self.create_device(&self.create_adapter()?)
+    }
+
+    /// Opens the display connection corresponding to the given `winit` window.
+    #[cfg(feature = "sm-winit")]
+    pub fn from_winit_window(window: &Window) -> Result<Connection, Error> {
+        if let Some(wayland_display) = window.get_wayland_display() {
+            let native_connection = NativeConnection::Wayland(wayland_display as *mut wl_display);
+            return Ok(Connection::from_native(native_connection, false));
+        }
+        match window.get_xlib_display() {
+            Some(x11_display) => {
+                let native_connection = NativeConnection::X11(x11_display as *mut Display);
+                Ok(Connection::from_native(native_connection, false))
+            }
+            None => Err(Error::IncompatibleWinitWindow),
+        }
+    }
+
+    /// Creates a native widget type from the given `winit` window.
+    ///
+    /// This type can be later used to create surfaces that display in the window.
+    #[cfg(feature = "sm-winit")]
+    pub fn create_native_widget_from_winit_window(&self, window: &Window)
+                                                  -> Result<NativeWidget, Error> {
+        match self.native_connection.native_connection {
+            NativeConnection::Wayland(_) => {
+                let wayland_surface = match window.get_wayland_surface() {
+                    Some(wayland_surface) => wayland_surface as *mut wl_proxy,
+                    None => return Err(Error::IncompatibleNativeWidget),
+                };
+                let hidpi_factor = window.get_primary_monitor().get_hidpi_factor();
+                let window_size = window.get_inner_size().unwrap().to_physical(hidpi_factor);
+                let size = Size2D::new(window_size.width as i32, window_size.height as i32);
+                Ok(NativeWidget::Wayland { wayland_surface, size })
+            }
+            NativeConnection::X11(_) => {
+                match window.get_xlib_window() {
+                    Some(window) => Ok(NativeWidget::X11 { window }),
+                    None => Err(Error::IncompatibleNativeWidget),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+// surfman/surfman/src/platform/unix/system/device.rs
+//
+//! A handle to the display server, holding the globals needed to share memory with it.
+
+use crate::leak::LeakTracker;
+use crate::{Error, LeakPolicy};
+use super::connection::{Connection, NativeConnection};
+use crate::platform::unix::wayland::globals::WaylandGlobals;
+
+use std::marker::PhantomData;
+use std::sync::Arc;
+use x11::xshm::XShmQueryExtension;
+
+/// Represents a display adapter.
+///
+/// Surfaces on this backend are always drawn by the CPU, so there is only one adapter.
+///
+/// Adapters can be sent between threads. To create surfaces with an adapter, open a thread-local
+/// `Device`.
+#[derive(Clone, Debug)]
+pub struct Adapter;
+
+/// A thread-local handle to a device.
+///
+/// Devices contain most of the relevant surface management methods.
+pub struct Device {
+    pub(crate) display_objects: DisplayObjects,
+    connection: Connection,
+    pub(crate) leak_tracker: Arc<LeakTracker>,
+    phantom: PhantomData<*mut ()>,
+}
+
+/// An empty type representing the native device.
+///
+/// Surfaces on this backend are plain memory shared with the display server, so this type
+/// doesn't contain anything.
+#[derive(Clone)]
+pub struct NativeDevice;
+
+pub(crate) enum DisplayObjects {
+    Wayland(WaylandGlobals),
+    X11 {
+        display: *mut x11::xlib::Display,
+        // False if the server can't attach to our shared memory, for example because it's
+        // running on another machine. Images are then sent over the wire.
+        mit_shm_available: bool,
+    },
+}
+
+impl Device {
+    pub(crate) fn new(connection: &Connection, _: &Adapter) -> Result<Device, Error> {
+        unsafe {
+            let display_objects = match connection.native_connection() {
+                NativeConnection::Wayland(display) => {
+                    let wayland_globals = WaylandGlobals::new(display)?;
+                    if wayland_globals.shm().is_null() {
+                        return Err(Error::DeviceOpenFailed);
+                    }
+                    DisplayObjects::Wayland(wayland_globals)
+                }
+                NativeConnection::X11(display) => {
+                    let mit_shm_available = XShmQueryExtension(display) != 0;
+                    DisplayObjects::X11 { display, mit_shm_available }
+                }
+            };
+            Ok(Device {
+                display_objects,
+                connection: connection.clone(),
+                leak_tracker: LeakTracker::new(),
+                phantom: PhantomData,
+            })
+        }
+    }
+
+    /// Returns the native device corresponding to this device.
+    #[inline]
+    pub fn native_device(&self) -> NativeDevice {
+        NativeDevice
+    }
+
+    /// Returns the display server connection that this device was created with.
+    #[inline]
+    pub fn connection(&self) -> Connection {
+        self.connection.clone()
+    }
+
+    /// Returns the adapter that this device was created with.
+    #[inline]
+    pub fn adapter(&self) -> Adapter {
+        Adapter
+    }
+
+    /// Sets what happens to surfaces created with this device that are dropped without being
+    /// destroyed, overriding the global leak policy.
+    #[inline]
+    pub fn set_leak_policy(&self, policy: LeakPolicy) {
+        self.leak_tracker.set_policy(policy)
+    }
+
+    /// Returns the leak policy that this device follows.
+    #[inline]
+    pub fn leak_policy(&self) -> LeakPolicy {
+        self.leak_tracker.policy()
+    }
+}
@@ -0,0 +1,53 @@
+// surfman/surfman/src/platform/unix/system/ffi.rs
+//
+//! FFI definitions for the Linux system backend.
+
+use crate::platform::unix::x11::ffi::{open_library, symbol, xcb_connection_t};
+use crate::platform::unix::x11::ffi::{xcb_generic_error_t, xcb_void_cookie_t};
+
+use std::mem;
+use x11::xlib::Display;
+
+// The MIT-SHM functions in the `x11` crate live in `libXext`, which the crate only links for
+// features we don't otherwise need.
+#[link(name = "Xext")]
+extern "C" {}
+
+// Attaching through XCB lets us check the result of that one request, instead of catching the
+// error in a process-wide Xlib error handler.
+#[allow(non_snake_case)]
+pub(crate) struct XCBShmFunctions {
+    pub(crate) XGetXCBConnection: unsafe extern "C" fn(*mut Display) -> *mut xcb_connection_t,
+    pub(crate) xcb_generate_id: unsafe extern "C" fn(*mut xcb_connection_t) -> u32,
+    pub(crate) xcb_request_check: unsafe extern "C" fn(*mut xcb_connection_t, xcb_void_cookie_t)
+                                                       -> *mut xcb_generic_error_t,
+    pub(crate) xcb_shm_attach_checked: unsafe extern "C" fn(*mut xcb_connection_t,
+                                                            u32,
+                                                            u32,
+                                                            u8)
+                                                            -> xcb_void_cookie_t,
+}
+
+unsafe impl Send for XCBShmFunctions {}
+unsafe impl Sync for XCBShmFunctions {}
+
+lazy_static! {
+    // `None` if `libxcb-shm` isn't installed, in which case images are sent over the wire.
+    pub(crate) static ref XCB_SHM_FUNCTIONS: Option<XCBShmFunctions> = unsafe {
+        XCBShmFunctions::load()
+    };
+}
+
+impl XCBShmFunctions {
+    unsafe fn load() -> Option<XCBShmFunctions> {
+        let x11_xcb = open_library(b"libX11-xcb.so.1\0")?;
+        let xcb = open_library(b"libxcb.so.1\0")?;
+        let shm = open_library(b"libxcb-shm.so.0\0")?;
+        Some(XCBShmFunctions {
+            XGetXCBConnection: mem::transmute(symbol(x11_xcb, b"XGetXCBConnection\0")?),
+            xcb_generate_id: mem::transmute(symbol(xcb, b"xcb_generate_id\0")?),
+            xcb_request_check: mem::transmute(symbol(xcb, b"xcb_request_check\0")?),
+            xcb_shm_attach_checked: mem::transmute(symbol(shm, b"xcb_shm_attach_checked\0")?),
+        })
+    }
+}
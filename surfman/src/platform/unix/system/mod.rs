@@ -0,0 +1,12 @@
+// surfman/surfman/src/platform/unix/system/mod.rs
+//
+//! CPU-only surfaces presented with MIT-SHM on X11 and `wl_shm` on Wayland.
+
+pub mod connection;
+pub mod device;
+pub mod surface;
+
+mod ffi;
+
+#[cfg(test)]
+mod tests;
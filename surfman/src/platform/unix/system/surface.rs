@@ -0,0 +1,611 @@
+// surfman/surfman/src/platform/unix/system/surface.rs
+//
+//! Surfaces drawn by the CPU and shown with MIT-SHM on X11 or `wl_shm` on Wayland.
+
+use crate::ipc::SharedMemoryMapping;
+use crate::leak::{LeakedObject, Origin};
+use crate::{Error, SurfaceAccess, SurfaceFormat, SurfaceID, SurfaceType, SystemSurfaceInfo};
+use crate::{WindowingApiError, WindowingApiFailure};
+use crate::surface::{check_upload_region, layered_surface_size};
+use super::device::{DisplayObjects, Device};
+use super::ffi::XCB_SHM_FUNCTIONS;
+use crate::platform::unix::wayland::ffi::{WL_BUFFER_DESTROY, WL_BUFFER_INTERFACE};
+use crate::platform::unix::wayland::ffi::{WL_SHM_CREATE_POOL, WL_SHM_FORMAT_XRGB8888};
+use crate::platform::unix::wayland::ffi::{WL_SHM_POOL_CREATE_BUFFER, WL_SHM_POOL_DESTROY};
+use crate::platform::unix::wayland::ffi::{WL_SHM_POOL_INTERFACE, WL_SURFACE_ATTACH};
+use crate::platform::unix::wayland::ffi::{WL_SURFACE_COMMIT, WL_SURFACE_DAMAGE};
+
+use euclid::default::{Rect, Size2D};
+use std::cell::Cell;
+use std::fmt::{self, Debug, Formatter};
+use std::marker::PhantomData;
+use std::mem;
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+use std::slice;
+use wayland_sys::client::{WAYLAND_CLIENT_HANDLE, wl_display, wl_event_queue, wl_proxy};
+use x11::xlib::{self, Display, GC, Window, XCreateGC, XCreateImage, XDestroyImage};
+use x11::xlib::{XFlush, XFreeGC, XGetWindowAttributes, XImage, XPutImage, XSync};
+use x11::xlib::{XWindowAttributes, ZPixmap};
+use x11::xshm::{XShmCreateImage, XShmDetach, XShmPutImage, XShmSegmentInfo};
+
+/// Represents a buffer of pixels drawn by the CPU that can either be displayed in a native widget
+/// or read back.
+///
+/// Surfaces come in two varieties: generic and widget surfaces. Generic surfaces are plain memory.
+/// Widget surfaces share their memory with the display server and show up in their widget when
+/// presented.
+///
+/// Surfaces should be destroyed with the `destroy_surface()` method. A surface that is dropped
+/// without being destroyed is handled according to the device's `LeakPolicy`.
+pub struct Surface {
+    pub(crate) size: Size2D<i32>,
+    pub(crate) format: SurfaceFormat,
+    access: SurfaceAccess,
+    pub(crate) destroyed: bool,
+    objects: SurfaceObjects,
+    origin: Origin,
+}
+
+enum SurfaceObjects {
+    Memory {
+        pixels: Vec<u8>,
+    },
+    X11 {
+        display: *mut Display,
+        window: Window,
+        gc: GC,
+        image: *mut XImage,
+        // `None` if the server can't attach to our memory. Xlib keeps a pointer to this, so it's
+        // boxed.
+        shm_segment_info: Option<Box<XShmSegmentInfo>>,
+        // Backs the image when it isn't in shared memory. Only Xlib reads it.
+        _pixels: Vec<u8>,
+    },
+    Wayland {
+        display: *mut wl_display,
+        event_queue: *mut wl_event_queue,
+        wayland_surface: *mut wl_proxy,
+        mapping: SharedMemoryMapping,
+        buffers: [Box<WaylandBuffer>; 2],
+        back_buffer: usize,
+    },
+}
+
+// Boxed, because the release listener holds a pointer to it.
+struct WaylandBuffer {
+    buffer: *mut wl_proxy,
+    busy: Cell<bool>,
+}
+
+#[repr(C)]
+struct BufferListener {
+    release: unsafe extern "C" fn(*mut c_void, *mut wl_proxy),
+}
+
+static BUFFER_LISTENER: BufferListener = BufferListener { release: handle_buffer_release };
+
+/// A native widget to display surfaces in: either an X11 window or a Wayland surface.
+#[derive(Clone)]
+pub enum NativeWidget {
+    /// An X11 window.
+    ///
+    /// The window's visual must be 24- or 32-bit TrueColor with the usual BGRA channel layout.
+    X11 {
+        /// The window ID.
+        window: Window,
+    },
+    /// A Wayland surface.
+    Wayland {
+        /// The `wl_surface` proxy.
+        wayland_surface: *mut wl_proxy,
+        /// The size of the surface, in device pixels.
+        size: Size2D<i32>,
+    },
+}
+
+/// Represents the CPU view of the pixel data of this surface.
+pub struct SurfaceDataGuard<'a> {
+    stride: usize,
+    ptr: *mut u8,
+    len: usize,
+    phantom: PhantomData<&'a mut Surface>,
+}
+
+unsafe impl Send for Surface {}
+
+impl Debug for Surface {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        write!(formatter, "Surface({:x})", self.id().0)
+    }
+}
+
+impl Drop for Surface {
+    fn drop(&mut self) {
+        if !self.destroyed {
+            self.origin.report_leak(LeakedObject::Surface, false);
+        }
+    }
+}
+
+impl Device {
+    /// Creates either a generic or a widget surface, depending on the supplied surface type.
+    ///
+    /// Widget surfaces are always BGRA. Generic surfaces may have any format, but their depth and
+    /// stencil attributes are ignored, since there's no GPU to render to them.
+    pub fn create_surface(&mut self,
+                          access: SurfaceAccess,
+                          surface_type: SurfaceType<NativeWidget>)
+                          -> Result<Surface, Error> {
+        let (size, format, objects) = match surface_type {
+            SurfaceType::Generic { size, format, .. } => {
+                let pixels = vec![0; memory_len(&size, format)?];
+                (size, format, SurfaceObjects::Memory { pixels })
+            }
+            SurfaceType::Layered { size, layers } => {
+                let size = layered_surface_size(&size, layers)?;
+                let format = SurfaceFormat::RGBA8;
+                let pixels = vec![0; memory_len(&size, format)?];
+                (size, format, SurfaceObjects::Memory { pixels })
+            }
+            SurfaceType::Widget { color_space: Some(_), .. } => {
+                return Err(Error::UnsupportedColorSpace)
+            }
+            SurfaceType::Widget { native_widget, color_space: None } => {
+                let (size, objects) = match (&self.display_objects, native_widget) {
+                    (&DisplayObjects::X11 { display, mit_shm_available },
+                     NativeWidget::X11 { window }) => unsafe {
+                        create_x11_objects(display, window, mit_shm_available)?
+                    },
+                    (&DisplayObjects::Wayland(ref globals),
+                     NativeWidget::Wayland { wayland_surface, size }) => unsafe {
+                        let objects = create_wayland_objects(globals.display,
+                                                             globals.event_queue,
+                                                             globals.shm(),
+                                                             wayland_surface,
+                                                             &size)?;
+                        (size, objects)
+                    },
+                    _ => return Err(Error::IncompatibleNativeWidget),
+                };
+                (size, SurfaceFormat::BGRA8, objects)
+            }
+        };
+
+        let origin = Origin::new(&self.leak_tracker);
+        Ok(Surface { size, format, access, destroyed: false, objects, origin })
+    }
+
+    /// Destroys a surface.
+    ///
+    /// You should explicitly call this method to dispose of a surface. Otherwise, the surface is
+    /// handled according to the device's `LeakPolicy` when it is dropped.
+    pub fn destroy_surface(&self, surface: &mut Surface) -> Result<(), Error> {
+        unsafe {
+            match surface.objects {
+                SurfaceObjects::Memory { .. } => {}
+                SurfaceObjects::X11 {
+                    display,
+                    gc,
+                    ref mut image,
+                    ref mut shm_segment_info,
+                    ..
+                } => {
+                    // The pixels belong to us or to the shared memory segment, not to Xlib.
+                    (**image).data = ptr::null_mut();
+                    XDestroyImage(*image);
+                    *image = ptr::null_mut();
+                    if let Some(mut shm_segment_info) = shm_segment_info.take() {
+                        XShmDetach(display, &mut *shm_segment_info);
+                        XSync(display, xlib::False);
+                        libc::shmdt(shm_segment_info.shmaddr as *const c_void);
+                    }
+                    XFreeGC(display, gc);
+                }
+                SurfaceObjects::Wayland { ref buffers, .. } => {
+                    for buffer in buffers {
+                        (WAYLAND_CLIENT_HANDLE.wl_proxy_marshal)(buffer.buffer, WL_BUFFER_DESTROY);
+                        (WAYLAND_CLIENT_HANDLE.wl_proxy_destroy)(buffer.buffer);
+                    }
+                }
+            }
+        }
+
+        surface.destroyed = true;
+        Ok(())
+    }
+
+    /// Displays the contents of a widget surface on screen.
+    ///
+    /// On X11, the pixels are copied into the window before this returns. On Wayland, widget
+    /// surfaces are double-buffered: after presenting, the surface's data is that of the frame
+    /// presented before this one, so redraw everything that changed since then.
+    pub fn present_surface(&self, surface: &mut Surface) -> Result<(), Error> {
+        unsafe {
+            match surface.objects {
+                SurfaceObjects::Memory { .. } => Err(Error::NoWidgetAttached),
+                SurfaceObjects::X11 { display, window, gc, image, ref shm_segment_info, .. } => {
+                    let (width, height) = (surface.size.width as u32, surface.size.height as u32);
+                    if shm_segment_info.is_some() {
+                        XShmPutImage(display, window, gc, image, 0, 0, 0, 0, width, height,
+                                     xlib::False);
+                        // Wait for the server to finish reading before we draw again.
+                        XSync(display, xlib::False);
+                    } else {
+                        XPutImage(display, window, gc, image, 0, 0, 0, 0, width, height);
+                        XFlush(display);
+                    }
+                    Ok(())
+                }
+                SurfaceObjects::Wayland {
+                    display,
+                    wayland_surface,
+                    ref buffers,
+                    ref mut back_buffer,
+                    ..
+                } => {
+                    let wayland_client = &*WAYLAND_CLIENT_HANDLE;
+                    let buffer = &buffers[*back_buffer];
+                    (wayland_client.wl_proxy_marshal)(wayland_surface,
+                                                      WL_SURFACE_ATTACH,
+                                                      buffer.buffer,
+                                                      0i32,
+                                                      0i32);
+                    (wayland_client.wl_proxy_marshal)(wayland_surface,
+                                                      WL_SURFACE_DAMAGE,
+                                                      0i32,
+                                                      0i32,
+                                                      i32::MAX,
+                                                      i32::MAX);
+                    (wayland_client.wl_proxy_marshal)(wayland_surface, WL_SURFACE_COMMIT);
+                    buffer.busy.set(true);
+                    *back_buffer = 1 - *back_buffer;
+                    if (wayland_client.wl_display_flush)(display) < 0 {
+                        let failure = WindowingApiFailure::new("wl_display_flush",
+                                                               WindowingApiError::Failed,
+                                                               None);
+                        return Err(Error::PresentFailed(Box::new(failure)));
+                    }
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// Returns a pointer to the underlying surface data for reading or writing by the CPU.
+    ///
+    /// On Wayland, this waits for the compositor to release the buffer being drawn to.
+    pub fn lock_surface_data<'s>(&self, surface: &'s mut Surface)
+                                 -> Result<SurfaceDataGuard<'s>, Error> {
+        if !surface.access.cpu_access_allowed() {
+            return Err(Error::SurfaceDataInaccessible);
+        }
+
+        let stride = surface.stride();
+        let (ptr, len) = unsafe {
+            match surface.objects {
+                SurfaceObjects::Memory { ref mut pixels } => (pixels.as_mut_ptr(), pixels.len()),
+                SurfaceObjects::X11 { image, .. } => {
+                    ((*image).data as *mut u8, stride * surface.size.height as usize)
+                }
+                SurfaceObjects::Wayland {
+                    display,
+                    event_queue,
+                    ref mapping,
+                    ref buffers,
+                    back_buffer,
+                    ..
+                } => {
+                    while buffers[back_buffer].busy.get() {
+                        let wayland_client = &*WAYLAND_CLIENT_HANDLE;
+                        if (wayland_client.wl_display_dispatch_queue)(display, event_queue) < 0 {
+                            return Err(Error::SurfaceLockFailed);
+                        }
+                    }
+                    let len = stride * surface.size.height as usize;
+                    (mapping.ptr.add(back_buffer * len), len)
+                }
+            }
+        };
+
+        Ok(SurfaceDataGuard { stride, ptr, len, phantom: PhantomData })
+    }
+
+    /// Copies pixels from the CPU into a rectangle of a surface.
+    ///
+    /// `data` holds the rows of `rect` in order, `stride` bytes apart, in the given format, which
+    /// must be the surface's format.
+    pub fn upload_surface_data(&self,
+                               surface: &mut Surface,
+                               rect: &Rect<i32>,
+                               data: &[u8],
+                               stride: usize,
+                               format: SurfaceFormat)
+                               -> Result<(), Error> {
+        if format != surface.format {
+            return Err(Error::UnsupportedSurfaceFormat);
+        }
+        check_upload_region(&surface.size, rect, data.len(), stride, format)?;
+
+        let mut guard = self.lock_surface_data(surface)?;
+        let surface_stride = guard.stride();
+        let surface_data = guard.data();
+        let row_size = rect.size.width as usize * format.bytes_per_pixel();
+        let start = rect.origin.y as usize * surface_stride +
+            rect.origin.x as usize * format.bytes_per_pixel();
+        for row in 0..(rect.size.height as usize) {
+            let dest_start = start + row * surface_stride;
+            surface_data[dest_start..(dest_start + row_size)]
+                .copy_from_slice(&data[(row * stride)..(row * stride + row_size)]);
+        }
+        Ok(())
+    }
+
+    /// Returns the formats that generic surfaces created on this device may have.
+    #[inline]
+    pub fn supported_surface_formats(&self) -> Vec<SurfaceFormat> {
+        vec![
+            SurfaceFormat::RGBA8,
+            SurfaceFormat::BGRA8,
+            SurfaceFormat::SRGB8A8,
+            SurfaceFormat::RGB10A2,
+            SurfaceFormat::RGBA16F,
+            SurfaceFormat::R8,
+            SurfaceFormat::RG8,
+        ]
+    }
+
+    /// Returns various information about the surface.
+    #[inline]
+    pub fn surface_info(&self, surface: &Surface) -> SystemSurfaceInfo {
+        SystemSurfaceInfo {
+            size: surface.size,
+            id: surface.id(),
+        }
+    }
+}
+
+impl Surface {
+    fn id(&self) -> SurfaceID {
+        match self.objects {
+            SurfaceObjects::Memory { ref pixels } => SurfaceID(pixels.as_ptr() as usize),
+            SurfaceObjects::X11 { image, .. } => SurfaceID(image as usize),
+            SurfaceObjects::Wayland { ref mapping, .. } => SurfaceID(mapping.ptr as usize),
+        }
+    }
+
+    fn stride(&self) -> usize {
+        match self.objects {
+            SurfaceObjects::X11 { image, .. } => unsafe { (*image).bytes_per_line as usize },
+            SurfaceObjects::Memory { .. } | SurfaceObjects::Wayland { .. } => {
+                self.size.width as usize * self.format.bytes_per_pixel()
+            }
+        }
+    }
+}
+
+impl<'a> SurfaceDataGuard<'a> {
+    /// Returns the number of bytes per row of the surface.
+    #[inline]
+    pub fn stride(&self) -> usize { self.stride }
+
+    /// Returns a mutable slice of the pixel data in this surface, in the surface's format.
+    ///
+    /// Widget surfaces are always in BGRA format.
+    #[inline]
+    pub fn data(&mut self) -> &mut [u8] {
+        unsafe {
+            slice::from_raw_parts_mut(self.ptr, self.len)
+        }
+    }
+}
+
+fn memory_len(size: &Size2D<i32>, format: SurfaceFormat) -> Result<usize, Error> {
+    if size.width <= 0 || size.height <= 0 {
+        return Err(Error::SurfaceCreationFailed(WindowingApiError::BadParameter.into()));
+    }
+    (size.width as usize * format.bytes_per_pixel()).checked_mul(size.height as usize)
+          .ok_or(Error::SurfaceCreationFailed(WindowingApiError::Failed.into()))
+}
+
+unsafe fn create_x11_objects(display: *mut Display, window: Window, mit_shm_available: bool)
+                             -> Result<(Size2D<i32>, SurfaceObjects), Error> {
+    let mut attributes: XWindowAttributes = mem::zeroed();
+    if XGetWindowAttributes(display, window, &mut attributes) == 0 {
+        return Err(Error::InvalidNativeWidget);
+    }
+
+    // We only know how to draw BGRA.
+    let visual = attributes.visual;
+    if (attributes.depth != 24 && attributes.depth != 32) || (*visual).red_mask != 0xff0000 ||
+            (*visual).green_mask != 0xff00 || (*visual).blue_mask != 0xff {
+        return Err(Error::IncompatibleNativeWidget);
+    }
+    let size = Size2D::new(attributes.width, attributes.height);
+    let depth = attributes.depth as u32;
+
+    let mut shm_segment_info = None;
+    let mut pixels = vec![];
+    let mut image = ptr::null_mut();
+    if mit_shm_available {
+        if let Some((shm_image, info)) = create_x11_shm_image(display, visual, depth, &size) {
+            image = shm_image;
+            shm_segment_info = Some(info);
+        }
+    }
+
+    if image.is_null() {
+        let stride = size.width as usize * 4;
+        pixels = vec![0; memory_len(&size, SurfaceFormat::BGRA8)?];
+        image = XCreateImage(display,
+                             visual,
+                             depth,
+                             ZPixmap,
+                             0,
+                             pixels.as_mut_ptr() as *mut c_char,
+                             size.width as u32,
+                             size.height as u32,
+                             32,
+                             stride as c_int);
+        if image.is_null() {
+            let failure = WindowingApiFailure::new("XCreateImage", WindowingApiError::Failed, None);
+            return Err(Error::SurfaceCreationFailed(Box::new(failure)));
+        }
+    }
+
+    let gc = XCreateGC(display, window, 0, ptr::null_mut());
+    Ok((size, SurfaceObjects::X11 {
+        display,
+        window,
+        gc,
+        image,
+        shm_segment_info,
+        _pixels: pixels,
+    }))
+}
+
+// Returns `None` if anything goes wrong, so that the caller can fall back to sending images over
+// the wire.
+unsafe fn create_x11_shm_image(display: *mut Display,
+                               visual: *mut xlib::Visual,
+                               depth: u32,
+                               size: &Size2D<i32>)
+                               -> Option<(*mut XImage, Box<XShmSegmentInfo>)> {
+    let mut shm_segment_info: Box<XShmSegmentInfo> = Box::new(mem::zeroed());
+    let image = XShmCreateImage(display,
+                                visual,
+                                depth,
+                                ZPixmap,
+                                ptr::null_mut(),
+                                &mut *shm_segment_info,
+                                size.width as u32,
+                                size.height as u32);
+    if image.is_null() {
+        return None;
+    }
+
+    let len = (*image).bytes_per_line as usize * size.height as usize;
+    let shmid = libc::shmget(libc::IPC_PRIVATE, len, libc::IPC_CREAT | 0o600);
+    if shmid < 0 {
+        XDestroyImage(image);
+        return None;
+    }
+    let shmaddr = libc::shmat(shmid, ptr::null(), 0);
+    if shmaddr as isize == -1 {
+        libc::shmctl(shmid, libc::IPC_RMID, ptr::null_mut());
+        XDestroyImage(image);
+        return None;
+    }
+    shm_segment_info.shmid = shmid;
+    shm_segment_info.shmaddr = shmaddr as *mut c_char;
+    shm_segment_info.readOnly = xlib::False;
+    (*image).data = shmaddr as *mut c_char;
+
+    // A remote server can't reach our memory, and only says so in an error, so check the result
+    // of this request on this display.
+    let attached = attach_shm_segment(display, &mut shm_segment_info);
+
+    // The segment goes away once both we and the server have detached.
+    libc::shmctl(shmid, libc::IPC_RMID, ptr::null_mut());
+
+    if !attached {
+        (*image).data = ptr::null_mut();
+        XDestroyImage(image);
+        libc::shmdt(shmaddr);
+        return None;
+    }
+    Some((image, shm_segment_info))
+}
+
+unsafe fn attach_shm_segment(display: *mut Display, shm_segment_info: &mut XShmSegmentInfo)
+                             -> bool {
+    let xcb = match *XCB_SHM_FUNCTIONS {
+        Some(ref xcb) => xcb,
+        None => return false,
+    };
+
+    // Xlib allocates its IDs through XCB, so the segment ID is valid for `XShmPutImage()` and
+    // `XShmDetach()` too.
+    let connection = (xcb.XGetXCBConnection)(display);
+    let shmseg = (xcb.xcb_generate_id)(connection);
+    let cookie = (xcb.xcb_shm_attach_checked)(connection,
+                                              shmseg,
+                                              shm_segment_info.shmid as u32,
+                                              shm_segment_info.readOnly as u8);
+    let error = (xcb.xcb_request_check)(connection, cookie);
+    if !error.is_null() {
+        libc::free(error as *mut c_void);
+        return false;
+    }
+    shm_segment_info.shmseg = shmseg as xlib::XID;
+    true
+}
+
+unsafe fn create_wayland_objects(display: *mut wl_display,
+                                 event_queue: *mut wl_event_queue,
+                                 shm: *mut wl_proxy,
+                                 wayland_surface: *mut wl_proxy,
+                                 size: &Size2D<i32>)
+                                 -> Result<SurfaceObjects, Error> {
+    let wayland_client = &*WAYLAND_CLIENT_HANDLE;
+
+    let stride = size.width as usize * 4;
+    let buffer_len = memory_len(size, SurfaceFormat::BGRA8)?;
+    let pool_len = match buffer_len.checked_mul(2) {
+        Some(pool_len) if pool_len <= i32::MAX as usize => pool_len,
+        _ => return Err(Error::SurfaceCreationFailed(WindowingApiError::Failed.into())),
+    };
+    let (fd, mapping) = match SharedMemoryMapping::create(pool_len) {
+        Ok(shared_memory) => shared_memory,
+        Err(Error::SurfaceExportFailed(failure)) => {
+            return Err(Error::SurfaceCreationFailed(failure))
+        }
+        Err(err) => return Err(err),
+    };
+
+    // The pool inherits our private queue from the `wl_shm` proxy, and the buffers inherit it
+    // from the pool.
+    let pool = (wayland_client.wl_proxy_marshal_constructor)(shm,
+                                                             WL_SHM_CREATE_POOL,
+                                                             &WL_SHM_POOL_INTERFACE.0,
+                                                             ptr::null_mut::<c_void>(),
+                                                             fd,
+                                                             pool_len as i32);
+    libc::close(fd);
+
+    let create_buffer = |index: usize| {
+        let buffer = (wayland_client.wl_proxy_marshal_constructor)(pool,
+                                                                   WL_SHM_POOL_CREATE_BUFFER,
+                                                                   &WL_BUFFER_INTERFACE.0,
+                                                                   ptr::null_mut::<c_void>(),
+                                                                   (index * buffer_len) as i32,
+                                                                   size.width,
+                                                                   size.height,
+                                                                   stride as i32,
+                                                                   WL_SHM_FORMAT_XRGB8888);
+        let mut buffer = Box::new(WaylandBuffer { buffer, busy: Cell::new(false) });
+        (wayland_client.wl_proxy_add_listener)(
+            buffer.buffer,
+            &BUFFER_LISTENER as *const BufferListener as *mut extern "C" fn(),
+            &mut *buffer as *mut WaylandBuffer as *mut c_void);
+        buffer
+    };
+    let buffers = [create_buffer(0), create_buffer(1)];
+
+    // Buffers keep the memory alive on their own.
+    (wayland_client.wl_proxy_marshal)(pool, WL_SHM_POOL_DESTROY);
+    (wayland_client.wl_proxy_destroy)(pool);
+
+    Ok(SurfaceObjects::Wayland {
+        display,
+        event_queue,
+        wayland_surface,
+        mapping,
+        buffers,
+        back_buffer: 0,
+    })
+}
+
+unsafe extern "C" fn handle_buffer_release(data: *mut c_void, _: *mut wl_proxy) {
+    (*(data as *const WaylandBuffer)).busy.set(false);
+}
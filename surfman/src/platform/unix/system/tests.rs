@@ -0,0 +1,118 @@
+// surfman/surfman/src/platform/unix/system/tests.rs
+//
+//! Unit tests for the CPU-only backend.
+
+use crate::{Error, SurfaceAccess, SurfaceFormat, SurfaceType};
+use super::connection::{Connection, NativeConnection};
+use super::device::Device;
+use super::surface::NativeWidget;
+
+use euclid::default::{Point2D, Rect, Size2D};
+use x11::xlib;
+
+// Returns `None` if there's no display server to talk to.
+fn create_device() -> Option<Device> {
+    let connection = match Connection::new() {
+        Ok(connection) => connection,
+        Err(Error::ConnectionFailed) => return None,
+        Err(err) => panic!("Failed to connect: {:?}", err),
+    };
+    let adapter = connection.create_adapter().unwrap();
+    Some(connection.create_device(&adapter).unwrap())
+}
+
+#[test]
+fn test_generic_surface() {
+    let mut device = match create_device() {
+        Some(device) => device,
+        None => return,
+    };
+
+    let surface_type = SurfaceType::Generic {
+        size: Size2D::new(4, 4),
+        format: SurfaceFormat::RGBA8,
+        depth_stencil: None,
+    };
+    let mut surface = device.create_surface(SurfaceAccess::GPUCPU, surface_type).unwrap();
+    assert_eq!(device.surface_info(&surface).size, Size2D::new(4, 4));
+
+    let rect = Rect::new(Point2D::new(1, 2), Size2D::new(2, 1));
+    let pixels = [1, 2, 3, 4, 5, 6, 7, 8];
+    device.upload_surface_data(&mut surface, &rect, &pixels, 8, SurfaceFormat::RGBA8).unwrap();
+    {
+        let mut guard = device.lock_surface_data(&mut surface).unwrap();
+        let stride = guard.stride();
+        let data = guard.data();
+        assert_eq!(&data[(2 * stride + 4)..(2 * stride + 12)], &pixels);
+        assert!(data[..(2 * stride)].iter().all(|&byte| byte == 0));
+    }
+
+    // Generic surfaces have nowhere to go.
+    match device.present_surface(&mut surface) {
+        Err(Error::NoWidgetAttached) => {}
+        result => panic!("Presenting a generic surface should fail: {:?}", result),
+    }
+    device.destroy_surface(&mut surface).unwrap();
+}
+
+#[test]
+fn test_x11_widget_surface() {
+    let mut device = match create_device() {
+        Some(device) => device,
+        None => return,
+    };
+    let display = match device.connection().native_connection() {
+        NativeConnection::X11(display) => display,
+        NativeConnection::Wayland(_) => return,
+    };
+
+    unsafe {
+        let root = xlib::XDefaultRootWindow(display);
+        let window = xlib::XCreateSimpleWindow(display, root, 0, 0, 16, 8, 0, 0, 0);
+        let surface_type = SurfaceType::Widget {
+            native_widget: NativeWidget::X11 { window },
+            color_space: None,
+        };
+        let mut surface = match device.create_surface(SurfaceAccess::GPUCPU, surface_type) {
+            Ok(surface) => surface,
+            Err(Error::IncompatibleNativeWidget) => {
+                // The default visual isn't one we can draw.
+                xlib::XDestroyWindow(display, window);
+                return;
+            }
+            Err(err) => panic!("Failed to create widget surface: {:?}", err),
+        };
+        assert_eq!(device.surface_info(&surface).size, Size2D::new(16, 8));
+
+        let rect = Rect::new(Point2D::zero(), Size2D::new(16, 8));
+        let pixels = vec![0x80; 16 * 8 * 4];
+        device.upload_surface_data(&mut surface, &rect, &pixels, 16 * 4, SurfaceFormat::BGRA8)
+              .unwrap();
+        device.present_surface(&mut surface).unwrap();
+
+        // X11 widget surfaces are single-buffered, so the pixels are still there.
+        {
+            let mut guard = device.lock_surface_data(&mut surface).unwrap();
+            let stride = guard.stride();
+            let data = guard.data();
+            for row in 0..8 {
+                assert!(data[(row * stride)..(row * stride + 16 * 4)].iter()
+                                                                       .all(|&byte| byte == 0x80));
+            }
+        }
+
+        // Only BGRA data can be uploaded to widget surfaces.
+        match device.upload_surface_data(&mut surface,
+                                         &rect,
+                                         &pixels,
+                                         16 * 4,
+                                         SurfaceFormat::RGBA8) {
+            Err(Error::UnsupportedSurfaceFormat) => {}
+            result => panic!("Uploading RGBA to a widget surface should fail: {:?}", result),
+        }
+
+        device.destroy_surface(&mut surface).unwrap();
+        xlib::XDestroyWindow(display, window);
+        xlib::XSync(display, xlib::False);
+    }
+}
@@ -0,0 +1,175 @@
+// surfman/surfman/src/platform/unix/wayland/backend_tests.rs
+//
+//! Unit tests for features only the Wayland backend has.
+
+use crate::gl;
+use crate::{ContextAttributeFlags, ContextAttributes, Error, GLVersion, Gl, LeakPolicy};
+use crate::{SurfaceAccess, SurfaceFormat, SurfaceType};
+use super::connection::Connection;
+use super::ffi::{WL_COMPOSITOR_CREATE_SURFACE, WL_COMPOSITOR_INTERFACE};
+use super::ffi::{WL_DISPLAY_GET_REGISTRY, WL_REGISTRY_BIND, WL_REGISTRY_INTERFACE};
+use super::ffi::{WL_SURFACE_DESTROY, WL_SURFACE_INTERFACE};
+use super::present::PresentEvent;
+
+use euclid::default::Size2D;
+use std::env;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+use wayland_sys::client::{WAYLAND_CLIENT_HANDLE, wl_display, wl_proxy};
+
+#[repr(C)]
+struct RegistryListener {
+    global: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32, *const c_char, u32),
+    global_remove: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32),
+}
+
+static REGISTRY_LISTENER: RegistryListener = RegistryListener {
+    global: handle_registry_global,
+    global_remove: handle_registry_global_remove,
+};
+
+#[test]
+fn test_present_and_poll_round_trip() {
+    // Without a runtime directory there's no compositor socket to look for.
+    if env::var_os("XDG_RUNTIME_DIR").is_none() {
+        return;
+    }
+    let connection = match Connection::new() {
+        Ok(connection) => connection,
+        Err(Error::ConnectionFailed) => return,
+        Err(err) => panic!("Failed to connect: {:?}", err),
+    };
+    let adapter = connection.create_adapter().unwrap();
+    let mut device = match connection.create_device(&adapter) {
+        Ok(device) => device,
+        Err(Error::RequiredExtensionUnavailable) => return,
+        Err(err) => panic!("Failed to create device: {:?}", err),
+    };
+    let display = connection.native_connection.wayland_display.unwrap();
+
+    let attributes = ContextAttributes::new(GLVersion::new(3, 0), ContextAttributeFlags::ALPHA);
+    let context_descriptor = device.create_context_descriptor(&attributes).unwrap();
+    let mut context = device.create_context(&context_descriptor).unwrap();
+    let surface_type = SurfaceType::Generic {
+        size: Size2D::new(16, 16),
+        format: SurfaceFormat::RGBA8,
+        depth_stencil: None,
+    };
+    let surface = device.create_surface(&context, SurfaceAccess::GPUOnly, surface_type).unwrap();
+    device.bind_surface_to_context(&mut context, surface).unwrap();
+    device.make_context_current(&context).unwrap();
+    let gl = Gl::load_with(|symbol| device.get_proc_address(&context, symbol));
+
+    unsafe {
+        let compositor = bind_compositor(display);
+        if compositor.is_null() {
+            device.destroy_context(&mut context).unwrap();
+            return;
+        }
+
+        let wayland_client = &*WAYLAND_CLIENT_HANDLE;
+        let wayland_surface = (wayland_client.wl_proxy_marshal_constructor)(
+            compositor,
+            WL_COMPOSITOR_CREATE_SURFACE,
+            &WL_SURFACE_INTERFACE.0,
+            ptr::null_mut::<c_void>());
+        let mut presenter = match device.create_presenter(wayland_surface) {
+            Ok(presenter) => presenter,
+            Err(Error::RequiredExtensionUnavailable) => {
+                // No `wl_shm` to fall back to.
+                (wayland_client.wl_proxy_marshal)(wayland_surface, WL_SURFACE_DESTROY);
+                destroy_proxy(wayland_surface);
+                destroy_proxy(compositor);
+                device.destroy_context(&mut context).unwrap();
+                return;
+            }
+            Err(err) => panic!("Failed to create presenter: {:?}", err),
+        };
+
+        let framebuffer_object = device.context_surface_info(&context)
+                                       .unwrap()
+                                       .unwrap()
+                                       .framebuffer_object;
+        gl.BindFramebuffer(gl::FRAMEBUFFER, framebuffer_object);
+        gl.ClearColor(0.0, 1.0, 0.0, 1.0);
+        gl.Clear(gl::COLOR_BUFFER_BIT);
+
+        let surface = device.unbind_surface_from_context(&mut context).unwrap().unwrap();
+        let first_serial = device.present_surface_to_window(&context, &mut presenter, &surface)
+                                 .unwrap();
+        let second_serial = device.present_surface_to_window(&context, &mut presenter, &surface)
+                                  .unwrap();
+        assert_eq!(second_serial, first_serial.wrapping_add(1));
+
+        // The surface has no role, so the compositor may never show it. Whatever it does report
+        // must be about one of our presentations.
+        for _ in 0..2 {
+            for event in device.poll_present_events(&mut presenter) {
+                let serial = match event {
+                    PresentEvent::Frame { serial, .. } | PresentEvent::Idle { serial } => serial,
+                };
+                assert!(serial == first_serial || serial == second_serial);
+            }
+            (wayland_client.wl_display_roundtrip)(display);
+        }
+
+        device.destroy_presenter(&mut presenter).unwrap();
+
+        // Under `LeakPolicy::Leak`, a dropped presenter is leaked instead of panicking.
+        device.set_leak_policy(LeakPolicy::Leak);
+        drop(device.create_presenter(wayland_surface).unwrap());
+
+        device.bind_surface_to_context(&mut context, surface).unwrap();
+        device.destroy_context(&mut context).unwrap();
+
+        (wayland_client.wl_proxy_marshal)(wayland_surface, WL_SURFACE_DESTROY);
+        destroy_proxy(wayland_surface);
+        destroy_proxy(compositor);
+        (wayland_client.wl_display_roundtrip)(display);
+    }
+}
+
+// Returns the `wl_compositor` global, or null if there isn't one.
+unsafe fn bind_compositor(display: *mut wl_display) -> *mut wl_proxy {
+    let wayland_client = &*WAYLAND_CLIENT_HANDLE;
+    let registry = (wayland_client.wl_proxy_marshal_constructor)(display as *mut wl_proxy,
+                                                                 WL_DISPLAY_GET_REGISTRY,
+                                                                 &WL_REGISTRY_INTERFACE.0,
+                                                                 ptr::null_mut::<c_void>());
+    let mut compositor: Box<*mut wl_proxy> = Box::new(ptr::null_mut());
+    (wayland_client.wl_proxy_add_listener)(
+        registry,
+        &REGISTRY_LISTENER as *const RegistryListener as *mut extern "C" fn(),
+        &mut *compositor as *mut *mut wl_proxy as *mut c_void);
+    (wayland_client.wl_display_roundtrip)(display);
+
+    // Bound globals outlive the registry, whose listener points at `compositor`.
+    destroy_proxy(registry);
+    *compositor
+}
+
+unsafe fn destroy_proxy(proxy: *mut wl_proxy) {
+    (WAYLAND_CLIENT_HANDLE.wl_proxy_destroy)(proxy);
+}
+
+unsafe extern "C" fn handle_registry_global(data: *mut c_void,
+                                            registry: *mut wl_proxy,
+                                            name: u32,
+                                            interface: *const c_char,
+                                            _: u32) {
+    let compositor = data as *mut *mut wl_proxy;
+    if CStr::from_ptr(interface).to_bytes() == b"wl_compositor" && (*compositor).is_null() {
+        *compositor = (WAYLAND_CLIENT_HANDLE.wl_proxy_marshal_constructor_versioned)(
+            registry,
+            WL_REGISTRY_BIND,
+            &WL_COMPOSITOR_INTERFACE.0,
+            1,
+            name,
+            WL_COMPOSITOR_INTERFACE.0.name,
+            1u32,
+            ptr::null_mut::<c_void>());
+    }
+}
+
+unsafe extern "C" fn handle_registry_global_remove(_: *mut c_void, _: *mut wl_proxy, _: u32) {}
@@ -0,0 +1,7 @@
+// surfman/surfman/src/platform/unix/wayland/buffer.rs
+//
+//! Sampling from Wayland client buffers, for compositors built on surfman.
+//!
+//! Every EGL backend on Linux can do this; the types live here for compatibility.
+
+pub use crate::platform::generic::egl::wayland::{WaylandBufferFormat, WaylandBufferInfo};
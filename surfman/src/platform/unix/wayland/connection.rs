@@ -7,6 +7,7 @@ use crate::egl::types::{EGLAttrib, EGLDisplay};
 use crate::egl;
 use crate::platform::generic::egl::device::EGL_FUNCTIONS;
 use crate::platform::generic::egl::ffi::EGL_PLATFORM_WAYLAND_KHR;
+use crate::platform::generic::egl::wayland;
 use super::device::{Adapter, Device, NativeDevice};
 use super::surface::NativeWidget;
 
@@ -15,6 +16,7 @@ use std::os::raw::c_void;
 use std::ptr;
 use std::sync::Arc;
 use wayland_sys::client::{WAYLAND_CLIENT_HANDLE, wl_display, wl_proxy};
+use wayland_sys::server;
 
 #[cfg(feature = "sm-winit")]
 use winit::Window;
@@ -29,7 +31,9 @@ pub struct Connection {
 
 pub(crate) struct NativeConnectionWrapper {
     pub(crate) egl_display: EGLDisplay,
-    wayland_display: Option<*mut wl_display>,
+    // Absent if the connection was created from a bare EGL display.
+    pub(crate) wayland_display: Option<*mut wl_display>,
+    is_owned: bool,
 }
 
 /// An EGL display wrapping a Wayland display.
@@ -54,7 +58,7 @@ impl Connection {
     /// connection is.
     pub unsafe fn from_native_connection(native_connection: NativeConnection)
                                          -> Result<Connection, Error> {
-        Connection::from_egl_display(native_connection.0, None)
+        Connection::from_egl_display(native_connection.0, None, false)
     }
 
     /// Returns the underlying native connection.
@@ -107,6 +111,23 @@ impl Connection {
         Device::new(self, &native_device.adapter)
     }
 
+    /// Lets clients of the given compositor display allocate buffers that
+    /// `Device::create_surface_texture_from_wl_buffer()` samples without a copy.
+    ///
+    /// The display must be unbound with `unbind_wayland_display()` before it's destroyed.
+    #[inline]
+    pub unsafe fn bind_wayland_display(&self, wayland_display: *mut server::wl_display)
+                                       -> Result<(), Error> {
+        wayland::bind_wayland_display(self.native_connection.egl_display, wayland_display)
+    }
+
+    /// Undoes `bind_wayland_display()`.
+    #[inline]
+    pub unsafe fn unbind_wayland_display(&self, wayland_display: *mut server::wl_display)
+                                         -> Result<(), Error> {
+        wayland::unbind_wayland_display(self.native_connection.egl_display, wayland_display)
+    }
+
     unsafe fn from_wayland_display(wayland_display: *mut wl_display, is_owned: bool)
                                    -> Result<Connection, Error> {
         if wayland_display.is_null() {
@@ -124,17 +145,24 @@ impl Connection {
 
             let (mut egl_major_version, mut egl_minor_version) = (0, 0);
             let ok = egl.Initialize(egl_display, &mut egl_major_version, &mut egl_minor_version);
-            assert_ne!(ok, egl::FALSE);
+            if ok == egl::FALSE {
+                return Err(Error::DeviceOpenFailed);
+            }
 
-            let owned_display = if is_owned { Some(wayland_display) } else { None };
-            Connection::from_egl_display(egl_display, owned_display)
+            Connection::from_egl_display(egl_display, Some(wayland_display), is_owned)
         })
     }
 
-    fn from_egl_display(egl_display: EGLDisplay, wayland_display: Option<*mut wl_display>)
+    fn from_egl_display(egl_display: EGLDisplay,
+                        wayland_display: Option<*mut wl_display>,
+                        is_owned: bool)
                         -> Result<Connection, Error> {
         Ok(Connection {
-            native_connection: Arc::new(NativeConnectionWrapper { egl_display, wayland_display })
+            native_connection: Arc::new(NativeConnectionWrapper {
+                egl_display,
+                wayland_display,
+                is_owned,
+            })
         })
     }
 
@@ -177,8 +205,11 @@ impl Connection {
 impl Drop for NativeConnectionWrapper {
     fn drop(&mut self) {
         unsafe {
-            if let Some(wayland_display) = self.wayland_display {
-                (WAYLAND_CLIENT_HANDLE.wl_display_disconnect)(wayland_display);
+            match self.wayland_display {
+                Some(wayland_display) if self.is_owned => {
+                    (WAYLAND_CLIENT_HANDLE.wl_display_disconnect)(wayland_display);
+                }
+                _ => {}
             }
         }
     }
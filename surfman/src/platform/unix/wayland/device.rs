@@ -2,7 +2,9 @@
 //
 //! A wrapper around Wayland `EGLDisplay`s.
 
-use crate::{Error, GLApi};
+use crate::platform::generic::egl::device::{display_extensions, extensions_support_damage_present};
+use crate::leak::LeakTracker;
+use crate::{DeviceCapabilities, Error, GLApi, LeakPolicy};
 use super::connection::{Connection, NativeConnectionWrapper};
 
 use std::sync::Arc;
@@ -15,6 +17,7 @@ pub use crate::platform::unix::generic::device::Adapter;
 pub struct Device {
     pub(crate) native_connection: Arc<NativeConnectionWrapper>,
     pub(crate) adapter: Adapter,
+    pub(crate) leak_tracker: Arc<LeakTracker>,
 }
 
 /// Wraps an adapter.
@@ -26,12 +29,20 @@ pub struct NativeDevice {
     pub adapter: Adapter,
 }
 
+impl Drop for Device {
+    fn drop(&mut self) {
+        self.destroy_deferred_contexts();
+        self.leak_tracker.close();
+    }
+}
+
 impl Device {
     #[inline]
     pub(crate) fn new(connection: &Connection, adapter: &Adapter) -> Result<Device, Error> {
         Ok(Device {
             native_connection: connection.native_connection.clone(),
             adapter: (*adapter).clone(),
+            leak_tracker: LeakTracker::new(),
         })
     }
 
@@ -56,9 +67,37 @@ impl Device {
         self.adapter.clone()
     }
 
-    /// Returns the OpenGL API flavor that this device supports (OpenGL or OpenGL ES).
+    /// Returns the OpenGL API flavor that contexts on this device use by default (OpenGL or
+    /// OpenGL ES).
     #[inline]
     pub fn gl_api(&self) -> GLApi {
         GLApi::GL
     }
+
+    /// Returns the optional features that this device supports.
+    pub fn capabilities(&self) -> DeviceCapabilities {
+        let platform_extensions = unsafe { display_extensions(self.native_connection.egl_display) };
+        DeviceCapabilities {
+            widget_surfaces: true,
+            cpu_surface_reads: false,
+            cpu_surface_uploads: true,
+            cross_device_surface_textures: true,
+            damage_present: extensions_support_damage_present(&platform_extensions),
+            surface_texture_target: self.surface_gl_texture_target(),
+            platform_extensions,
+        }
+    }
+
+    /// Sets what happens to contexts and surfaces created with this device that are dropped
+    /// without being destroyed, overriding the global leak policy.
+    #[inline]
+    pub fn set_leak_policy(&self, policy: LeakPolicy) {
+        self.leak_tracker.set_policy(policy)
+    }
+
+    /// Returns the leak policy that this device follows.
+    #[inline]
+    pub fn leak_policy(&self) -> LeakPolicy {
+        self.leak_tracker.policy()
+    }
 }
@@ -0,0 +1,382 @@
+// surfman/surfman/src/platform/unix/wayland/ffi.rs
+//
+//! Protocol definitions for the Wayland objects that surfman creates itself.
+//!
+//! `wayland-sys` loads `libwayland-client` dynamically, so the interface tables of the core
+//! protocol objects aren't available to link against, and those of `zwp_linux_dmabuf_v1` aren't
+//! shipped in any library. These are transcribed from `wayland.xml` and
+//! `linux-dmabuf-unstable-v1.xml`, limited to the requests and events of the versions we bind.
+
+#![allow(dead_code, non_upper_case_globals)]
+
+use std::os::raw::c_char;
+use std::ptr;
+use wayland_sys::common::{wl_interface, wl_message};
+
+pub(crate) const WL_DISPLAY_GET_REGISTRY: u32 = 1;
+
+pub(crate) const WL_REGISTRY_BIND: u32 = 0;
+
+pub(crate) const WL_SHM_CREATE_POOL: u32 = 0;
+
+pub(crate) const WL_SHM_POOL_CREATE_BUFFER: u32 = 0;
+pub(crate) const WL_SHM_POOL_DESTROY: u32 = 1;
+
+pub(crate) const WL_BUFFER_DESTROY: u32 = 0;
+
+pub(crate) const WL_COMPOSITOR_CREATE_SURFACE: u32 = 0;
+
+pub(crate) const WL_SURFACE_DESTROY: u32 = 0;
+pub(crate) const WL_SURFACE_ATTACH: u32 = 1;
+pub(crate) const WL_SURFACE_DAMAGE: u32 = 2;
+pub(crate) const WL_SURFACE_FRAME: u32 = 3;
+pub(crate) const WL_SURFACE_COMMIT: u32 = 6;
+
+pub(crate) const WL_SHM_FORMAT_ARGB8888: u32 = 0;
+pub(crate) const WL_SHM_FORMAT_XRGB8888: u32 = 1;
+
+pub(crate) const ZWP_LINUX_DMABUF_V1_DESTROY: u32 = 0;
+pub(crate) const ZWP_LINUX_DMABUF_V1_CREATE_PARAMS: u32 = 1;
+
+pub(crate) const ZWP_LINUX_BUFFER_PARAMS_V1_DESTROY: u32 = 0;
+pub(crate) const ZWP_LINUX_BUFFER_PARAMS_V1_ADD: u32 = 1;
+pub(crate) const ZWP_LINUX_BUFFER_PARAMS_V1_CREATE: u32 = 2;
+pub(crate) const ZWP_LINUX_BUFFER_PARAMS_V1_CREATE_IMMED: u32 = 3;
+
+pub(crate) const ZWP_LINUX_BUFFER_PARAMS_V1_FLAGS_Y_INVERT: u32 = 1;
+
+// `create_immed` first appeared in version 2, and the `modifier` event in version 3.
+pub(crate) const ZWP_LINUX_DMABUF_V1_MAX_VERSION: u32 = 3;
+
+// Statics holding raw pointers must be wrapped to be shared between threads. The tables are
+// never written to.
+pub(crate) struct SyncWrapper<T>(pub(crate) T);
+
+unsafe impl<T> Sync for SyncWrapper<T> {}
+
+static NULL_TYPES: SyncWrapper<[*const wl_interface; 6]> = SyncWrapper([ptr::null(); 6]);
+
+pub(crate) static WL_REGISTRY_INTERFACE: SyncWrapper<wl_interface> = SyncWrapper(wl_interface {
+    name: b"wl_registry\0" as *const u8 as *const c_char,
+    version: 1,
+    request_count: 1,
+    requests: &WL_REGISTRY_REQUESTS.0 as *const wl_message,
+    event_count: 2,
+    events: &WL_REGISTRY_EVENTS.0 as *const wl_message,
+});
+
+static WL_REGISTRY_REQUESTS: SyncWrapper<[wl_message; 1]> = SyncWrapper([
+    wl_message {
+        name: b"bind\0" as *const u8 as *const c_char,
+        signature: b"usun\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+]);
+
+static WL_REGISTRY_EVENTS: SyncWrapper<[wl_message; 2]> = SyncWrapper([
+    wl_message {
+        name: b"global\0" as *const u8 as *const c_char,
+        signature: b"usu\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+    wl_message {
+        name: b"global_remove\0" as *const u8 as *const c_char,
+        signature: b"u\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+]);
+
+pub(crate) static WL_SHM_INTERFACE: SyncWrapper<wl_interface> = SyncWrapper(wl_interface {
+    name: b"wl_shm\0" as *const u8 as *const c_char,
+    version: 1,
+    request_count: 1,
+    requests: &WL_SHM_REQUESTS.0 as *const wl_message,
+    event_count: 1,
+    events: &WL_SHM_EVENTS.0 as *const wl_message,
+});
+
+static WL_SHM_CREATE_POOL_TYPES: SyncWrapper<[*const wl_interface; 3]> = SyncWrapper([
+    &WL_SHM_POOL_INTERFACE.0 as *const wl_interface,
+    ptr::null(),
+    ptr::null(),
+]);
+
+static WL_SHM_REQUESTS: SyncWrapper<[wl_message; 1]> = SyncWrapper([
+    wl_message {
+        name: b"create_pool\0" as *const u8 as *const c_char,
+        signature: b"nhi\0" as *const u8 as *const c_char,
+        types: &WL_SHM_CREATE_POOL_TYPES.0 as *const *const wl_interface,
+    },
+]);
+
+static WL_SHM_EVENTS: SyncWrapper<[wl_message; 1]> = SyncWrapper([
+    wl_message {
+        name: b"format\0" as *const u8 as *const c_char,
+        signature: b"u\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+]);
+
+pub(crate) static WL_SHM_POOL_INTERFACE: SyncWrapper<wl_interface> = SyncWrapper(wl_interface {
+    name: b"wl_shm_pool\0" as *const u8 as *const c_char,
+    version: 1,
+    request_count: 3,
+    requests: &WL_SHM_POOL_REQUESTS.0 as *const wl_message,
+    event_count: 0,
+    events: ptr::null(),
+});
+
+static WL_SHM_POOL_CREATE_BUFFER_TYPES: SyncWrapper<[*const wl_interface; 6]> = SyncWrapper([
+    &WL_BUFFER_INTERFACE.0 as *const wl_interface,
+    ptr::null(),
+    ptr::null(),
+    ptr::null(),
+    ptr::null(),
+    ptr::null(),
+]);
+
+static WL_SHM_POOL_REQUESTS: SyncWrapper<[wl_message; 3]> = SyncWrapper([
+    wl_message {
+        name: b"create_buffer\0" as *const u8 as *const c_char,
+        signature: b"niiiiu\0" as *const u8 as *const c_char,
+        types: &WL_SHM_POOL_CREATE_BUFFER_TYPES.0 as *const *const wl_interface,
+    },
+    wl_message {
+        name: b"destroy\0" as *const u8 as *const c_char,
+        signature: b"\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+    wl_message {
+        name: b"resize\0" as *const u8 as *const c_char,
+        signature: b"i\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+]);
+
+pub(crate) static WL_BUFFER_INTERFACE: SyncWrapper<wl_interface> = SyncWrapper(wl_interface {
+    name: b"wl_buffer\0" as *const u8 as *const c_char,
+    version: 1,
+    request_count: 1,
+    requests: &WL_BUFFER_REQUESTS.0 as *const wl_message,
+    event_count: 1,
+    events: &WL_BUFFER_EVENTS.0 as *const wl_message,
+});
+
+static WL_BUFFER_REQUESTS: SyncWrapper<[wl_message; 1]> = SyncWrapper([
+    wl_message {
+        name: b"destroy\0" as *const u8 as *const c_char,
+        signature: b"\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+]);
+
+static WL_BUFFER_EVENTS: SyncWrapper<[wl_message; 1]> = SyncWrapper([
+    wl_message {
+        name: b"release\0" as *const u8 as *const c_char,
+        signature: b"\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+]);
+
+pub(crate) static WL_CALLBACK_INTERFACE: SyncWrapper<wl_interface> = SyncWrapper(wl_interface {
+    name: b"wl_callback\0" as *const u8 as *const c_char,
+    version: 1,
+    request_count: 0,
+    requests: ptr::null(),
+    event_count: 1,
+    events: &WL_CALLBACK_EVENTS.0 as *const wl_message,
+});
+
+static WL_CALLBACK_EVENTS: SyncWrapper<[wl_message; 1]> = SyncWrapper([
+    wl_message {
+        name: b"done\0" as *const u8 as *const c_char,
+        signature: b"u\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+]);
+
+pub(crate) static WL_COMPOSITOR_INTERFACE: SyncWrapper<wl_interface> =
+        SyncWrapper(wl_interface {
+    name: b"wl_compositor\0" as *const u8 as *const c_char,
+    version: 1,
+    request_count: 1,
+    requests: &WL_COMPOSITOR_REQUESTS.0 as *const wl_message,
+    event_count: 0,
+    events: ptr::null(),
+});
+
+static WL_COMPOSITOR_CREATE_SURFACE_TYPES: SyncWrapper<[*const wl_interface; 1]> =
+    SyncWrapper([&WL_SURFACE_INTERFACE.0 as *const wl_interface]);
+
+// Only `create_surface`; surfman never creates regions.
+static WL_COMPOSITOR_REQUESTS: SyncWrapper<[wl_message; 1]> = SyncWrapper([
+    wl_message {
+        name: b"create_surface\0" as *const u8 as *const c_char,
+        signature: b"n\0" as *const u8 as *const c_char,
+        types: &WL_COMPOSITOR_CREATE_SURFACE_TYPES.0 as *const *const wl_interface,
+    },
+]);
+
+pub(crate) static WL_SURFACE_INTERFACE: SyncWrapper<wl_interface> = SyncWrapper(wl_interface {
+    name: b"wl_surface\0" as *const u8 as *const c_char,
+    version: 1,
+    request_count: 7,
+    requests: &WL_SURFACE_REQUESTS.0 as *const wl_message,
+    event_count: 2,
+    events: &WL_SURFACE_EVENTS.0 as *const wl_message,
+});
+
+static WL_SURFACE_ATTACH_TYPES: SyncWrapper<[*const wl_interface; 3]> = SyncWrapper([
+    &WL_BUFFER_INTERFACE.0 as *const wl_interface,
+    ptr::null(),
+    ptr::null(),
+]);
+
+static WL_SURFACE_FRAME_TYPES: SyncWrapper<[*const wl_interface; 1]> =
+    SyncWrapper([&WL_CALLBACK_INTERFACE.0 as *const wl_interface]);
+
+static WL_SURFACE_REQUESTS: SyncWrapper<[wl_message; 7]> = SyncWrapper([
+    wl_message {
+        name: b"destroy\0" as *const u8 as *const c_char,
+        signature: b"\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+    wl_message {
+        name: b"attach\0" as *const u8 as *const c_char,
+        signature: b"?oii\0" as *const u8 as *const c_char,
+        types: &WL_SURFACE_ATTACH_TYPES.0 as *const *const wl_interface,
+    },
+    wl_message {
+        name: b"damage\0" as *const u8 as *const c_char,
+        signature: b"iiii\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+    wl_message {
+        name: b"frame\0" as *const u8 as *const c_char,
+        signature: b"n\0" as *const u8 as *const c_char,
+        types: &WL_SURFACE_FRAME_TYPES.0 as *const *const wl_interface,
+    },
+    wl_message {
+        name: b"set_opaque_region\0" as *const u8 as *const c_char,
+        signature: b"?o\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+    wl_message {
+        name: b"set_input_region\0" as *const u8 as *const c_char,
+        signature: b"?o\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+    wl_message {
+        name: b"commit\0" as *const u8 as *const c_char,
+        signature: b"\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+]);
+
+// The `wl_output` argument is left untyped, since we don't define outputs.
+static WL_SURFACE_EVENTS: SyncWrapper<[wl_message; 2]> = SyncWrapper([
+    wl_message {
+        name: b"enter\0" as *const u8 as *const c_char,
+        signature: b"o\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+    wl_message {
+        name: b"leave\0" as *const u8 as *const c_char,
+        signature: b"o\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+]);
+
+pub(crate) static ZWP_LINUX_DMABUF_V1_INTERFACE: SyncWrapper<wl_interface> =
+        SyncWrapper(wl_interface {
+    name: b"zwp_linux_dmabuf_v1\0" as *const u8 as *const c_char,
+    version: ZWP_LINUX_DMABUF_V1_MAX_VERSION as i32,
+    request_count: 2,
+    requests: &ZWP_LINUX_DMABUF_V1_REQUESTS.0 as *const wl_message,
+    event_count: 2,
+    events: &ZWP_LINUX_DMABUF_V1_EVENTS.0 as *const wl_message,
+});
+
+static ZWP_LINUX_DMABUF_V1_CREATE_PARAMS_TYPES: SyncWrapper<[*const wl_interface; 1]> =
+    SyncWrapper([&ZWP_LINUX_BUFFER_PARAMS_V1_INTERFACE.0 as *const wl_interface]);
+
+static ZWP_LINUX_DMABUF_V1_REQUESTS: SyncWrapper<[wl_message; 2]> = SyncWrapper([
+    wl_message {
+        name: b"destroy\0" as *const u8 as *const c_char,
+        signature: b"\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+    wl_message {
+        name: b"create_params\0" as *const u8 as *const c_char,
+        signature: b"n\0" as *const u8 as *const c_char,
+        types: &ZWP_LINUX_DMABUF_V1_CREATE_PARAMS_TYPES.0 as *const *const wl_interface,
+    },
+]);
+
+static ZWP_LINUX_DMABUF_V1_EVENTS: SyncWrapper<[wl_message; 2]> = SyncWrapper([
+    wl_message {
+        name: b"format\0" as *const u8 as *const c_char,
+        signature: b"u\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+    wl_message {
+        name: b"modifier\0" as *const u8 as *const c_char,
+        signature: b"3uuu\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+]);
+
+pub(crate) static ZWP_LINUX_BUFFER_PARAMS_V1_INTERFACE: SyncWrapper<wl_interface> =
+        SyncWrapper(wl_interface {
+    name: b"zwp_linux_buffer_params_v1\0" as *const u8 as *const c_char,
+    version: ZWP_LINUX_DMABUF_V1_MAX_VERSION as i32,
+    request_count: 4,
+    requests: &ZWP_LINUX_BUFFER_PARAMS_V1_REQUESTS.0 as *const wl_message,
+    event_count: 2,
+    events: &ZWP_LINUX_BUFFER_PARAMS_V1_EVENTS.0 as *const wl_message,
+});
+
+static ZWP_LINUX_BUFFER_PARAMS_V1_NEW_BUFFER_TYPES: SyncWrapper<[*const wl_interface; 5]> =
+        SyncWrapper([
+    &WL_BUFFER_INTERFACE.0 as *const wl_interface,
+    ptr::null(),
+    ptr::null(),
+    ptr::null(),
+    ptr::null(),
+]);
+
+static ZWP_LINUX_BUFFER_PARAMS_V1_REQUESTS: SyncWrapper<[wl_message; 4]> = SyncWrapper([
+    wl_message {
+        name: b"destroy\0" as *const u8 as *const c_char,
+        signature: b"\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+    wl_message {
+        name: b"add\0" as *const u8 as *const c_char,
+        signature: b"huuuuu\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+    wl_message {
+        name: b"create\0" as *const u8 as *const c_char,
+        signature: b"iiuu\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+    wl_message {
+        name: b"create_immed\0" as *const u8 as *const c_char,
+        signature: b"2niiuu\0" as *const u8 as *const c_char,
+        types: &ZWP_LINUX_BUFFER_PARAMS_V1_NEW_BUFFER_TYPES.0 as *const *const wl_interface,
+    },
+]);
+
+static ZWP_LINUX_BUFFER_PARAMS_V1_EVENTS: SyncWrapper<[wl_message; 2]> = SyncWrapper([
+    wl_message {
+        name: b"created\0" as *const u8 as *const c_char,
+        signature: b"n\0" as *const u8 as *const c_char,
+        types: &ZWP_LINUX_BUFFER_PARAMS_V1_NEW_BUFFER_TYPES.0 as *const *const wl_interface,
+    },
+    wl_message {
+        name: b"failed\0" as *const u8 as *const c_char,
+        signature: b"\0" as *const u8 as *const c_char,
+        types: &NULL_TYPES.0 as *const *const wl_interface,
+    },
+]);
@@ -0,0 +1,156 @@
+// surfman/surfman/src/platform/unix/wayland/globals.rs
+//
+//! Compositor globals that surfman binds for itself.
+
+use crate::Error;
+use super::ffi::{WL_DISPLAY_GET_REGISTRY, WL_REGISTRY_BIND, WL_REGISTRY_INTERFACE};
+use super::ffi::{WL_SHM_INTERFACE, ZWP_LINUX_DMABUF_V1_DESTROY, ZWP_LINUX_DMABUF_V1_INTERFACE};
+use super::ffi::ZWP_LINUX_DMABUF_V1_MAX_VERSION;
+
+use std::cmp;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+use wayland_sys::client::{WAYLAND_CLIENT_HANDLE, wl_display, wl_event_queue, wl_proxy};
+
+// Objects bound on a private event queue, so that waiting for our events never dispatches the
+// application's.
+pub(crate) struct WaylandGlobals {
+    pub(crate) display: *mut wl_display,
+    pub(crate) event_queue: *mut wl_event_queue,
+    registry: *mut wl_proxy,
+    // Boxed, because the registry listener holds a pointer to it.
+    globals: Box<RegistryGlobals>,
+}
+
+struct RegistryGlobals {
+    shm: *mut wl_proxy,
+    linux_dmabuf: *mut wl_proxy,
+    linux_dmabuf_version: u32,
+}
+
+#[repr(C)]
+struct RegistryListener {
+    global: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32, *const c_char, u32),
+    global_remove: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32),
+}
+
+static REGISTRY_LISTENER: RegistryListener = RegistryListener {
+    global: handle_registry_global,
+    global_remove: handle_registry_global_remove,
+};
+
+impl WaylandGlobals {
+    /// Binds whichever of the globals we use the compositor offers. Callers check for the ones
+    /// they need.
+    pub(crate) unsafe fn new(display: *mut wl_display) -> Result<WaylandGlobals, Error> {
+        let wayland_client = &*WAYLAND_CLIENT_HANDLE;
+        let event_queue = (wayland_client.wl_display_create_queue)(display);
+
+        // Objects inherit the queue of the proxy that created them, so create the registry from a
+        // display wrapper assigned to our queue.
+        let display_wrapper = (wayland_client.wl_proxy_create_wrapper)(display as *mut wl_proxy);
+        (wayland_client.wl_proxy_set_queue)(display_wrapper, event_queue);
+        let registry = (wayland_client.wl_proxy_marshal_constructor)(
+            display_wrapper,
+            WL_DISPLAY_GET_REGISTRY,
+            &WL_REGISTRY_INTERFACE.0,
+            ptr::null_mut::<c_void>());
+        (wayland_client.wl_proxy_wrapper_destroy)(display_wrapper);
+
+        let mut wayland_globals = WaylandGlobals {
+            display,
+            event_queue,
+            registry,
+            globals: Box::new(RegistryGlobals {
+                shm: ptr::null_mut(),
+                linux_dmabuf: ptr::null_mut(),
+                linux_dmabuf_version: 0,
+            }),
+        };
+        (wayland_client.wl_proxy_add_listener)(
+            registry,
+            &REGISTRY_LISTENER as *const RegistryListener as *mut extern "C" fn(),
+            &mut *wayland_globals.globals as *mut RegistryGlobals as *mut c_void);
+
+        if (wayland_client.wl_display_roundtrip_queue)(display, event_queue) < 0 {
+            return Err(Error::DeviceOpenFailed);
+        }
+        Ok(wayland_globals)
+    }
+
+    /// Returns the `wl_shm` global, or null if the compositor doesn't offer one.
+    #[inline]
+    pub(crate) fn shm(&self) -> *mut wl_proxy {
+        self.globals.shm
+    }
+
+    /// Returns the `zwp_linux_dmabuf_v1` global, or null if the compositor doesn't offer one.
+    #[inline]
+    pub(crate) fn linux_dmabuf(&self) -> *mut wl_proxy {
+        self.globals.linux_dmabuf
+    }
+
+    #[inline]
+    pub(crate) fn linux_dmabuf_version(&self) -> u32 {
+        self.globals.linux_dmabuf_version
+    }
+}
+
+impl Drop for WaylandGlobals {
+    fn drop(&mut self) {
+        unsafe {
+            let wayland_client = &*WAYLAND_CLIENT_HANDLE;
+            if !self.globals.shm.is_null() {
+                (wayland_client.wl_proxy_destroy)(self.globals.shm);
+            }
+            if !self.globals.linux_dmabuf.is_null() {
+                (wayland_client.wl_proxy_marshal)(self.globals.linux_dmabuf,
+                                                  ZWP_LINUX_DMABUF_V1_DESTROY);
+                (wayland_client.wl_proxy_destroy)(self.globals.linux_dmabuf);
+            }
+            (wayland_client.wl_proxy_destroy)(self.registry);
+            (wayland_client.wl_event_queue_destroy)(self.event_queue);
+        }
+    }
+}
+
+unsafe extern "C" fn handle_registry_global(data: *mut c_void,
+                                            registry: *mut wl_proxy,
+                                            name: u32,
+                                            interface: *const c_char,
+                                            version: u32) {
+    let globals = data as *mut RegistryGlobals;
+    match CStr::from_ptr(interface).to_bytes() {
+        b"wl_shm" if (*globals).shm.is_null() => {
+            // Version 1 has everything we need.
+            (*globals).shm = bind(registry, name, &WL_SHM_INTERFACE.0, 1);
+        }
+        b"zwp_linux_dmabuf_v1" if (*globals).linux_dmabuf.is_null() => {
+            let version = cmp::min(version, ZWP_LINUX_DMABUF_V1_MAX_VERSION);
+            (*globals).linux_dmabuf = bind(registry,
+                                           name,
+                                           &ZWP_LINUX_DMABUF_V1_INTERFACE.0,
+                                           version);
+            (*globals).linux_dmabuf_version = version;
+        }
+        _ => {}
+    }
+}
+
+unsafe extern "C" fn handle_registry_global_remove(_: *mut c_void, _: *mut wl_proxy, _: u32) {}
+
+unsafe fn bind(registry: *mut wl_proxy,
+               name: u32,
+               interface: &'static wayland_sys::common::wl_interface,
+               version: u32)
+               -> *mut wl_proxy {
+    (WAYLAND_CLIENT_HANDLE.wl_proxy_marshal_constructor_versioned)(registry,
+                                                                   WL_REGISTRY_BIND,
+                                                                   interface,
+                                                                   version,
+                                                                   name,
+                                                                   interface.name,
+                                                                   version,
+                                                                   ptr::null_mut::<c_void>())
+}
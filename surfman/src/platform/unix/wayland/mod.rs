@@ -2,15 +2,22 @@
 //
 //! Bindings to Wayland via the Linux GBM interface.
 
+pub mod buffer;
 pub mod connection;
 pub mod context;
 pub mod device;
+pub mod present;
 pub mod surface;
 
+pub(crate) mod ffi;
+pub(crate) mod globals;
+
 #[path = "../../../implementation/mod.rs"]
 mod implementation;
 
 #[cfg(test)]
 #[path = "../../../tests.rs"]
 mod tests;
+#[cfg(test)]
+mod backend_tests;
 
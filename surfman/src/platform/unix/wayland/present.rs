@@ -0,0 +1,627 @@
+// surfman/surfman/src/platform/unix/wayland/present.rs
+//
+//! Showing generic surfaces on Wayland surfaces via `zwp_linux_dmabuf_v1` or `wl_shm`.
+
+use crate::egl::types::EGLDisplay;
+use crate::gl::types::GLuint;
+use crate::gl;
+use crate::ipc::{DmaBufImage, SharedMemoryMapping};
+use crate::leak::{LeakedObject, Origin};
+use crate::platform::generic::egl::surface::{self, EGLSurfaceObjects};
+use crate::platform::generic::egl::ffi::EGLImageKHR;
+use crate::{Error, Gl, WindowingApiError, WindowingApiFailure};
+use super::context::{Context, GL_FUNCTIONS};
+use super::device::Device;
+use super::ffi::{WL_BUFFER_DESTROY, WL_BUFFER_INTERFACE, WL_CALLBACK_INTERFACE};
+use super::ffi::{WL_SHM_CREATE_POOL, WL_SHM_FORMAT_ARGB8888, WL_SHM_POOL_CREATE_BUFFER};
+use super::ffi::{WL_SHM_POOL_DESTROY, WL_SHM_POOL_INTERFACE, WL_SURFACE_ATTACH};
+use super::ffi::{WL_SURFACE_COMMIT, WL_SURFACE_DAMAGE, WL_SURFACE_FRAME};
+use super::ffi::{ZWP_LINUX_BUFFER_PARAMS_V1_ADD, ZWP_LINUX_BUFFER_PARAMS_V1_CREATE};
+use super::ffi::{ZWP_LINUX_BUFFER_PARAMS_V1_CREATE_IMMED, ZWP_LINUX_BUFFER_PARAMS_V1_DESTROY};
+use super::ffi::{ZWP_LINUX_BUFFER_PARAMS_V1_FLAGS_Y_INVERT, ZWP_LINUX_BUFFER_PARAMS_V1_INTERFACE};
+use super::ffi::ZWP_LINUX_DMABUF_V1_CREATE_PARAMS;
+use super::globals::WaylandGlobals;
+use super::surface::Surface;
+
+use euclid::default::Size2D;
+use std::collections::VecDeque;
+use std::mem::ManuallyDrop;
+use std::os::raw::c_void;
+use std::ptr;
+use std::slice;
+use wayland_sys::client::{WAYLAND_CLIENT_HANDLE, wl_proxy};
+
+/// Shows generic surfaces on a Wayland surface without making them widget surfaces.
+///
+/// The Wayland surface can be any `wl_surface` the application owns, typically a subsurface of a
+/// toplevel window. The presenter only attaches buffers to it and commits; giving it a role and
+/// placing it is up to the application.
+///
+/// Where the compositor supports `zwp_linux_dmabuf_v1` and can import the surface's buffer, the
+/// compositor displays the surface's own memory. Otherwise, each frame is read back and copied
+/// into a `wl_shm` buffer.
+///
+/// The compositor may read from a presented surface until it reports it idle with a
+/// `PresentEvent::Idle` carrying the serial returned by `present_surface_to_window()`. Rendering
+/// to the surface before then may show up on screen mid-frame.
+///
+/// Presenters should be destroyed with the `destroy_presenter()` method. A presenter that is
+/// dropped without being destroyed is handled according to the device's `LeakPolicy`.
+pub struct Presenter {
+    // Owns the private event queue that all of the objects below are assigned to. Leaked along
+    // with them if the presenter is dropped without being destroyed.
+    globals: ManuallyDrop<WaylandGlobals>,
+    // A wrapper for the application's surface, so that frame callbacks arrive on our queue.
+    surface_wrapper: *mut wl_proxy,
+    // Boxed, because the listeners hold a pointer to it.
+    state: Box<PresenterState>,
+    buffers: Vec<PresentedBuffer>,
+    frame_callbacks: Vec<FrameCallback>,
+    // False if the compositor lacks `zwp_linux_dmabuf_v1` or couldn't import one of our buffers.
+    dmabuf_available: bool,
+    // False until the compositor has imported a buffer once. Buffers are created with a
+    // roundtrip until then, so that failure can be detected.
+    dmabuf_verified: bool,
+    next_serial: u32,
+    destroyed: bool,
+    origin: Origin,
+}
+
+// Filled in by the listeners while our queue is dispatched.
+struct PresenterState {
+    events: VecDeque<ProtocolEvent>,
+    // `Some(null)` if the compositor refused a dma-buf.
+    created_buffer: Option<*mut wl_proxy>,
+}
+
+enum ProtocolEvent {
+    Release { buffer: *mut wl_proxy },
+    FrameDone { callback: *mut wl_proxy, time: u32 },
+}
+
+struct PresentedBuffer {
+    buffer: *mut wl_proxy,
+    serial: u32,
+    idle: bool,
+    size: Size2D<i32>,
+    // `None` if the buffer aliases a surface. Such buffers are destroyed as soon as they're idle.
+    shm_mapping: Option<SharedMemoryMapping>,
+}
+
+struct FrameCallback {
+    callback: *mut wl_proxy,
+    serial: u32,
+}
+
+/// A notification from the compositor about a surface presented with a `Presenter`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PresentEvent {
+    /// The compositor has shown the presentation with the given serial, and this is a good time
+    /// to present the next one.
+    Frame {
+        /// The serial returned by `present_surface_to_window()`.
+        serial: u32,
+        /// A timestamp in milliseconds, with an undefined base.
+        time: u32,
+    },
+    /// The compositor no longer reads from the surface presented with the given serial, so it
+    /// may be rendered to again.
+    Idle {
+        /// The serial returned by `present_surface_to_window()`.
+        serial: u32,
+    },
+}
+
+#[repr(C)]
+struct BufferListener {
+    release: unsafe extern "C" fn(*mut c_void, *mut wl_proxy),
+}
+
+#[repr(C)]
+struct CallbackListener {
+    done: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32),
+}
+
+#[repr(C)]
+struct BufferParamsListener {
+    created: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, *mut wl_proxy),
+    failed: unsafe extern "C" fn(*mut c_void, *mut wl_proxy),
+}
+
+static BUFFER_LISTENER: BufferListener = BufferListener { release: handle_buffer_release };
+
+static CALLBACK_LISTENER: CallbackListener = CallbackListener { done: handle_callback_done };
+
+static BUFFER_PARAMS_LISTENER: BufferParamsListener = BufferParamsListener {
+    created: handle_buffer_params_created,
+    failed: handle_buffer_params_failed,
+};
+
+unsafe impl Send for Presenter {}
+
+impl Drop for Presenter {
+    fn drop(&mut self) {
+        if self.destroyed {
+            unsafe {
+                ManuallyDrop::drop(&mut self.globals);
+            }
+            return;
+        }
+
+        // The compositor may still be reading from our buffers, so they're leaked along with the
+        // event queue.
+        self.origin.report_leak(LeakedObject::Presenter, false);
+    }
+}
+
+impl Device {
+    /// Creates a presenter that shows generic surfaces on the given Wayland surface.
+    ///
+    /// Returns `ConnectionRequired` if the device's connection was created from a bare EGL
+    /// display, since there's no Wayland display to talk to then.
+    ///
+    /// The `wl_surface` must belong to this device's Wayland display and must outlive the
+    /// presenter.
+    pub unsafe fn create_presenter(&self, wayland_surface: *mut wl_proxy)
+                                   -> Result<Presenter, Error> {
+        let display = match self.native_connection.wayland_display {
+            Some(display) => display,
+            None => return Err(Error::ConnectionRequired),
+        };
+        if wayland_surface.is_null() {
+            return Err(Error::InvalidNativeWidget);
+        }
+
+        let globals = WaylandGlobals::new(display)?;
+        if globals.shm().is_null() {
+            return Err(Error::RequiredExtensionUnavailable);
+        }
+
+        let wayland_client = &*WAYLAND_CLIENT_HANDLE;
+        let surface_wrapper = (wayland_client.wl_proxy_create_wrapper)(wayland_surface);
+        (wayland_client.wl_proxy_set_queue)(surface_wrapper, globals.event_queue);
+
+        let dmabuf_available = !globals.linux_dmabuf().is_null();
+        Ok(Presenter {
+            globals: ManuallyDrop::new(globals),
+            surface_wrapper,
+            state: Box::new(PresenterState { events: VecDeque::new(), created_buffer: None }),
+            buffers: vec![],
+            frame_callbacks: vec![],
+            dmabuf_available,
+            dmabuf_verified: false,
+            next_serial: 0,
+            destroyed: false,
+            origin: Origin::new(&self.leak_tracker),
+        })
+    }
+
+    /// Destroys a presenter, detaching its buffers from the Wayland surface.
+    ///
+    /// You should explicitly call this method to dispose of a presenter. Otherwise, the presenter
+    /// is handled according to the device's `LeakPolicy` when it is dropped.
+    pub fn destroy_presenter(&self, presenter: &mut Presenter) -> Result<(), Error> {
+        unsafe {
+            let wayland_client = &*WAYLAND_CLIENT_HANDLE;
+            (wayland_client.wl_proxy_marshal)(presenter.surface_wrapper,
+                                              WL_SURFACE_ATTACH,
+                                              ptr::null_mut::<wl_proxy>(),
+                                              0,
+                                              0);
+            (wayland_client.wl_proxy_marshal)(presenter.surface_wrapper, WL_SURFACE_COMMIT);
+
+            for presented_buffer in presenter.buffers.drain(..) {
+                destroy_buffer(presented_buffer.buffer);
+            }
+            for frame_callback in presenter.frame_callbacks.drain(..) {
+                (wayland_client.wl_proxy_destroy)(frame_callback.callback);
+            }
+            (wayland_client.wl_proxy_wrapper_destroy)(presenter.surface_wrapper);
+            (wayland_client.wl_display_flush)(presenter.globals.display);
+        }
+
+        presenter.destroyed = true;
+        Ok(())
+    }
+
+    /// Shows a generic surface on the presenter's Wayland surface, and commits it.
+    ///
+    /// Returns the serial that the compositor's notifications about this presentation carry.
+    ///
+    /// Unlike X11, the image appears upright: the compositor is told to flip imported buffers,
+    /// and copies are flipped on the way out. The Wayland surface takes on the size of the
+    /// surface.
+    ///
+    /// The supplied context must be the context the surface is associated with, or an
+    /// `IncompatibleSurface` error is returned. Widget surfaces return `WidgetAttached`.
+    pub fn present_surface_to_window(&self,
+                                     context: &Context,
+                                     presenter: &mut Presenter,
+                                     surface: &Surface)
+                                     -> Result<u32, Error> {
+        if context.0.id != surface.0.context_id {
+            return Err(Error::IncompatibleSurface);
+        }
+        let (egl_image, framebuffer_object) = match surface.0.objects {
+            EGLSurfaceObjects::TextureImage { egl_image, framebuffer_object, .. } => {
+                (egl_image, framebuffer_object)
+            }
+            // Layered surfaces present their first layer.
+            EGLSurfaceObjects::TextureArray {
+                ref layer_egl_images,
+                ref layer_framebuffer_objects,
+                ..
+            } => (layer_egl_images[0], layer_framebuffer_objects[0]),
+            EGLSurfaceObjects::Window { .. } => return Err(Error::WidgetAttached),
+            EGLSurfaceObjects::Imported { .. } => return Err(Error::IncompatibleSurface),
+        };
+
+        let egl_display = self.native_connection.egl_display;
+        let size = surface.0.size;
+        let _guard = self.temporarily_make_context_current(context)?;
+        unsafe {
+            GL_FUNCTIONS.with(|gl| -> Result<u32, Error> {
+                // The compositor waits on the buffer's implicit fence, so a flush is enough to
+                // order its reads after our rendering.
+                gl.Flush();
+
+                let index = match presenter.import_surface(egl_display, egl_image, &size) {
+                    Some(index) => index,
+                    None => {
+                        let index = presenter.shm_buffer_for_size(&size)?;
+                        let mapping = presenter.buffers[index].shm_mapping.as_ref().unwrap();
+                        copy_surface_to_shm(gl, framebuffer_object, &size, mapping);
+                        index
+                    }
+                };
+
+                let serial = presenter.next_serial;
+                presenter.next_serial = presenter.next_serial.wrapping_add(1);
+                let presented_buffer = &mut presenter.buffers[index];
+                presented_buffer.serial = serial;
+                presented_buffer.idle = false;
+
+                let wayland_client = &*WAYLAND_CLIENT_HANDLE;
+                let surface_wrapper = presenter.surface_wrapper;
+                (wayland_client.wl_proxy_marshal)(surface_wrapper,
+                                                  WL_SURFACE_ATTACH,
+                                                  presented_buffer.buffer,
+                                                  0,
+                                                  0);
+                (wayland_client.wl_proxy_marshal)(surface_wrapper,
+                                                  WL_SURFACE_DAMAGE,
+                                                  0,
+                                                  0,
+                                                  i32::MAX,
+                                                  i32::MAX);
+                let callback = (wayland_client.wl_proxy_marshal_constructor)(
+                    surface_wrapper,
+                    WL_SURFACE_FRAME,
+                    &WL_CALLBACK_INTERFACE.0,
+                    ptr::null_mut::<c_void>());
+                (wayland_client.wl_proxy_add_listener)(
+                    callback,
+                    &CALLBACK_LISTENER as *const CallbackListener as *mut extern "C" fn(),
+                    &mut *presenter.state as *mut PresenterState as *mut c_void);
+                presenter.frame_callbacks.push(FrameCallback { callback, serial });
+                (wayland_client.wl_proxy_marshal)(surface_wrapper, WL_SURFACE_COMMIT);
+
+                if (wayland_client.wl_display_flush)(presenter.globals.display) < 0 {
+                    let failure = WindowingApiFailure::new("wl_display_flush",
+                                                           WindowingApiError::Failed,
+                                                           None);
+                    return Err(Error::PresentFailed(Box::new(failure)));
+                }
+                Ok(serial)
+            })
+        }
+    }
+
+    /// Returns the notifications the compositor has sent about this presenter's surfaces,
+    /// without blocking.
+    pub fn poll_present_events(&self, presenter: &mut Presenter) -> Vec<PresentEvent> {
+        unsafe {
+            let wayland_client = &*WAYLAND_CLIENT_HANDLE;
+            let (display, event_queue) = (presenter.globals.display, presenter.globals.event_queue);
+
+            // Reading is only allowed once everything already queued has been dispatched.
+            while (wayland_client.wl_display_prepare_read_queue)(display, event_queue) != 0 {
+                (wayland_client.wl_display_dispatch_queue_pending)(display, event_queue);
+            }
+            (wayland_client.wl_display_flush)(display);
+
+            let mut poll_fd = libc::pollfd {
+                fd: (wayland_client.wl_display_get_fd)(display),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if libc::poll(&mut poll_fd, 1, 0) > 0 {
+                (wayland_client.wl_display_read_events)(display);
+            } else {
+                (wayland_client.wl_display_cancel_read)(display);
+            }
+            (wayland_client.wl_display_dispatch_queue_pending)(display, event_queue);
+
+            let mut events = vec![];
+            while let Some(event) = presenter.next_event() {
+                events.push(event);
+            }
+            events
+        }
+    }
+
+    /// Blocks until the compositor sends a notification about one of this presenter's surfaces.
+    pub fn wait_for_present_event(&self, presenter: &mut Presenter)
+                                  -> Result<PresentEvent, Error> {
+        unsafe {
+            let wayland_client = &*WAYLAND_CLIENT_HANDLE;
+            loop {
+                if let Some(event) = presenter.next_event() {
+                    return Ok(event);
+                }
+                if (wayland_client.wl_display_dispatch_queue)(presenter.globals.display,
+                                                              presenter.globals.event_queue) < 0 {
+                    let failure = WindowingApiFailure::new("wl_display_dispatch_queue",
+                                                           WindowingApiError::Failed,
+                                                           None);
+                    return Err(Error::PresentFailed(Box::new(failure)));
+                }
+            }
+        }
+    }
+}
+
+impl Presenter {
+    // Wraps the surface's buffer in a `wl_buffer`. Returns `None` if the compositor can't display
+    // it directly.
+    unsafe fn import_surface(&mut self,
+                             egl_display: EGLDisplay,
+                             egl_image: EGLImageKHR,
+                             size: &Size2D<i32>)
+                             -> Option<usize> {
+        if !self.dmabuf_available {
+            return None;
+        }
+        let image = surface::export_dma_buf_image(egl_display, egl_image, size)?;
+
+        let wayland_client = &*WAYLAND_CLIENT_HANDLE;
+        let params = (wayland_client.wl_proxy_marshal_constructor)(
+            self.globals.linux_dmabuf(),
+            ZWP_LINUX_DMABUF_V1_CREATE_PARAMS,
+            &ZWP_LINUX_BUFFER_PARAMS_V1_INTERFACE.0,
+            ptr::null_mut::<c_void>());
+
+        // libwayland duplicates the file descriptors as it sends them.
+        for (plane_index, plane) in image.planes.iter().enumerate() {
+            (wayland_client.wl_proxy_marshal)(params,
+                                              ZWP_LINUX_BUFFER_PARAMS_V1_ADD,
+                                              plane.fd,
+                                              plane_index as u32,
+                                              plane.offset,
+                                              plane.stride,
+                                              (image.modifier >> 32) as u32,
+                                              image.modifier as u32);
+        }
+        close_planes(&image);
+
+        // Our rows start at the bottom, as OpenGL's do.
+        let flags = ZWP_LINUX_BUFFER_PARAMS_V1_FLAGS_Y_INVERT;
+        let buffer = if self.dmabuf_verified && self.globals.linux_dmabuf_version() >= 2 {
+            (wayland_client.wl_proxy_marshal_constructor)(params,
+                                                          ZWP_LINUX_BUFFER_PARAMS_V1_CREATE_IMMED,
+                                                          &WL_BUFFER_INTERFACE.0,
+                                                          ptr::null_mut::<c_void>(),
+                                                          size.width,
+                                                          size.height,
+                                                          image.fourcc,
+                                                          flags)
+        } else {
+            self.create_buffer_with_roundtrip(params, &image, flags)
+        };
+        (wayland_client.wl_proxy_marshal)(params, ZWP_LINUX_BUFFER_PARAMS_V1_DESTROY);
+        (wayland_client.wl_proxy_destroy)(params);
+
+        if buffer.is_null() {
+            // Most likely the buffer lives on a GPU the compositor can't use. Don't try again.
+            self.dmabuf_available = false;
+            return None;
+        }
+        self.dmabuf_verified = true;
+
+        self.add_buffer_listener(buffer);
+        self.buffers.push(PresentedBuffer {
+            buffer,
+            serial: 0,
+            idle: false,
+            size: *size,
+            shm_mapping: None,
+        });
+        Some(self.buffers.len() - 1)
+    }
+
+    unsafe fn create_buffer_with_roundtrip(&mut self,
+                                           params: *mut wl_proxy,
+                                           image: &DmaBufImage,
+                                           flags: u32)
+                                           -> *mut wl_proxy {
+        let wayland_client = &*WAYLAND_CLIENT_HANDLE;
+        (wayland_client.wl_proxy_add_listener)(
+            params,
+            &BUFFER_PARAMS_LISTENER as *const BufferParamsListener as *mut extern "C" fn(),
+            &mut *self.state as *mut PresenterState as *mut c_void);
+        (wayland_client.wl_proxy_marshal)(params,
+                                          ZWP_LINUX_BUFFER_PARAMS_V1_CREATE,
+                                          image.size.width,
+                                          image.size.height,
+                                          image.fourcc,
+                                          flags);
+
+        self.state.created_buffer = None;
+        while self.state.created_buffer.is_none() {
+            if (wayland_client.wl_display_roundtrip_queue)(self.globals.display,
+                                                           self.globals.event_queue) < 0 {
+                return ptr::null_mut();
+            }
+        }
+        self.state.created_buffer.take().unwrap()
+    }
+
+    // Returns the index of an idle `wl_shm` buffer of the given size to copy into, creating one
+    // if necessary.
+    unsafe fn shm_buffer_for_size(&mut self, size: &Size2D<i32>) -> Result<usize, Error> {
+        if let Some(index) = self.buffers.iter().position(|presented_buffer| {
+            presented_buffer.idle && presented_buffer.shm_mapping.is_some() &&
+                presented_buffer.size == *size
+        }) {
+            return Ok(index);
+        }
+
+        let stride = size.width as usize * 4;
+        let len = match stride.checked_mul(size.height as usize) {
+            Some(len) if len > 0 && len <= i32::MAX as usize => len,
+            _ => return Err(Error::PresentFailed(WindowingApiError::BadParameter.into())),
+        };
+        let (fd, mapping) = match SharedMemoryMapping::create(len) {
+            Ok(shared_memory) => shared_memory,
+            Err(Error::SurfaceExportFailed(failure)) => return Err(Error::PresentFailed(failure)),
+            Err(err) => return Err(err),
+        };
+
+        let wayland_client = &*WAYLAND_CLIENT_HANDLE;
+        let pool = (wayland_client.wl_proxy_marshal_constructor)(self.globals.shm(),
+                                                                 WL_SHM_CREATE_POOL,
+                                                                 &WL_SHM_POOL_INTERFACE.0,
+                                                                 ptr::null_mut::<c_void>(),
+                                                                 fd,
+                                                                 len as i32);
+        libc::close(fd);
+        let buffer = (wayland_client.wl_proxy_marshal_constructor)(pool,
+                                                                   WL_SHM_POOL_CREATE_BUFFER,
+                                                                   &WL_BUFFER_INTERFACE.0,
+                                                                   ptr::null_mut::<c_void>(),
+                                                                   0,
+                                                                   size.width,
+                                                                   size.height,
+                                                                   stride as i32,
+                                                                   WL_SHM_FORMAT_ARGB8888);
+        (wayland_client.wl_proxy_marshal)(pool, WL_SHM_POOL_DESTROY);
+        (wayland_client.wl_proxy_destroy)(pool);
+
+        self.add_buffer_listener(buffer);
+        self.buffers.push(PresentedBuffer {
+            buffer,
+            serial: 0,
+            idle: true,
+            size: *size,
+            shm_mapping: Some(mapping),
+        });
+        Ok(self.buffers.len() - 1)
+    }
+
+    unsafe fn add_buffer_listener(&mut self, buffer: *mut wl_proxy) {
+        (WAYLAND_CLIENT_HANDLE.wl_proxy_add_listener)(
+            buffer,
+            &BUFFER_LISTENER as *const BufferListener as *mut extern "C" fn(),
+            &mut *self.state as *mut PresenterState as *mut c_void);
+    }
+
+    // Turns the next protocol event received on our queue into a notification, if it concerns a
+    // presentation.
+    unsafe fn next_event(&mut self) -> Option<PresentEvent> {
+        while let Some(event) = self.state.events.pop_front() {
+            match event {
+                ProtocolEvent::Release { buffer } => {
+                    if let Some(serial) = self.mark_idle(buffer) {
+                        return Some(PresentEvent::Idle { serial });
+                    }
+                }
+                ProtocolEvent::FrameDone { callback, time } => {
+                    let index = match self.frame_callbacks.iter().position(|frame_callback| {
+                        frame_callback.callback == callback
+                    }) {
+                        Some(index) => index,
+                        None => continue,
+                    };
+                    let frame_callback = self.frame_callbacks.remove(index);
+                    (WAYLAND_CLIENT_HANDLE.wl_proxy_destroy)(frame_callback.callback);
+                    return Some(PresentEvent::Frame { serial: frame_callback.serial, time });
+                }
+            }
+        }
+        None
+    }
+
+    unsafe fn mark_idle(&mut self, buffer: *mut wl_proxy) -> Option<u32> {
+        let index = self.buffers.iter().position(|presented_buffer| {
+            presented_buffer.buffer == buffer
+        })?;
+        let serial = self.buffers[index].serial;
+        if self.buffers[index].shm_mapping.is_some() {
+            self.buffers[index].idle = true;
+        } else {
+            let presented_buffer = self.buffers.swap_remove(index);
+            destroy_buffer(presented_buffer.buffer);
+        }
+        Some(serial)
+    }
+}
+
+// Reads the surface back into a `wl_shm` buffer. The compositor expects the top row first, in
+// little-endian ARGB, so rows are flipped and red and blue are swapped on the way.
+unsafe fn copy_surface_to_shm(gl: &Gl,
+                              framebuffer_object: GLuint,
+                              size: &Size2D<i32>,
+                              mapping: &SharedMemoryMapping) {
+    let stride = size.width as usize * 4;
+    let mut pixels = vec![0u8; stride * size.height as usize];
+
+    let mut old_read_framebuffer = 0;
+    gl.GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut old_read_framebuffer);
+    gl.BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer_object);
+    gl.ReadPixels(0,
+                  0,
+                  size.width,
+                  size.height,
+                  gl::RGBA,
+                  gl::UNSIGNED_BYTE,
+                  pixels.as_mut_ptr() as *mut c_void);
+    gl.BindFramebuffer(gl::READ_FRAMEBUFFER, old_read_framebuffer as GLuint);
+
+    let dest = slice::from_raw_parts_mut(mapping.ptr, mapping.len);
+    for (src_row, dest_row) in pixels.chunks(stride).rev().zip(dest.chunks_mut(stride)) {
+        for (src_pixel, dest_pixel) in src_row.chunks(4).zip(dest_row.chunks_mut(4)) {
+            dest_pixel.copy_from_slice(&[src_pixel[2], src_pixel[1], src_pixel[0], src_pixel[3]]);
+        }
+    }
+}
+
+unsafe fn destroy_buffer(buffer: *mut wl_proxy) {
+    (WAYLAND_CLIENT_HANDLE.wl_proxy_marshal)(buffer, WL_BUFFER_DESTROY);
+    (WAYLAND_CLIENT_HANDLE.wl_proxy_destroy)(buffer);
+}
+
+unsafe fn close_planes(image: &DmaBufImage) {
+    for plane in &image.planes {
+        libc::close(plane.fd);
+    }
+}
+
+unsafe extern "C" fn handle_buffer_release(data: *mut c_void, buffer: *mut wl_proxy) {
+    let state = data as *mut PresenterState;
+    (*state).events.push_back(ProtocolEvent::Release { buffer });
+}
+
+unsafe extern "C" fn handle_callback_done(data: *mut c_void, callback: *mut wl_proxy, time: u32) {
+    let state = data as *mut PresenterState;
+    (*state).events.push_back(ProtocolEvent::FrameDone { callback, time });
+}
+
+unsafe extern "C" fn handle_buffer_params_created(data: *mut c_void,
+                                                  _: *mut wl_proxy,
+                                                  buffer: *mut wl_proxy) {
+    (*(data as *mut PresenterState)).created_buffer = Some(buffer);
+}
+
+unsafe extern "C" fn handle_buffer_params_failed(data: *mut c_void, _: *mut wl_proxy) {
+    (*(data as *mut PresenterState)).created_buffer = Some(ptr::null_mut());
+}
@@ -0,0 +1,345 @@
+// surfman/surfman/src/platform/unix/x11/backend_tests.rs
+//
+//! Unit tests for features only the X11 backend has.
+
+use crate::gl;
+use crate::ipc::SurfaceHandle;
+use crate::{ContextAttributeFlags, ContextAttributes, Error, GLVersion, Gl, LeakPolicy};
+use crate::{SurfaceAccess, SurfaceFormat, SurfaceType};
+use super::connection::Connection;
+use super::context::Context;
+use super::device::Device;
+use super::present::{PresentEvent, Presenter};
+use super::surface::{NativeWidget, Surface};
+
+use euclid::default::{Point2D, Rect, Size2D};
+use std::mem;
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+use x11::xlib::{self, Display, Window};
+
+const SIZE: i32 = 16;
+
+const DRM_FORMAT_ARGB8888: u32 = 0x3432_5241;
+
+struct Environment {
+    device: Device,
+    context: Context,
+    gl: Gl,
+    display: *mut Display,
+}
+
+impl Environment {
+    // Returns `None` if there's no X server or it can't run these tests.
+    fn new() -> Option<Environment> {
+        let connection = match Connection::new() {
+            Ok(connection) => connection,
+            Err(Error::ConnectionFailed) => return None,
+            Err(err) => panic!("Failed to connect: {:?}", err),
+        };
+        let adapter = connection.create_adapter().unwrap();
+        let mut device = match connection.create_device(&adapter) {
+            Ok(device) => device,
+            Err(Error::RequiredExtensionUnavailable) => return None,
+            Err(err) => panic!("Failed to create device: {:?}", err),
+        };
+
+        let attributes = ContextAttributes::new(GLVersion::new(3, 0),
+                                                ContextAttributeFlags::empty());
+        let context_descriptor = device.create_context_descriptor(&attributes).unwrap();
+        let mut context = device.create_context(&context_descriptor).unwrap();
+        let surface_type = SurfaceType::Generic {
+            size: Size2D::new(SIZE, SIZE),
+            format: SurfaceFormat::RGBA8,
+            depth_stencil: None,
+        };
+        let surface = device.create_surface(&context, SurfaceAccess::GPUOnly, surface_type)
+                            .unwrap();
+        device.bind_surface_to_context(&mut context, surface).unwrap();
+        device.make_context_current(&context).unwrap();
+        let gl = Gl::load_with(|symbol| device.get_proc_address(&context, symbol));
+
+        let display = connection.native_connection().x11_display;
+        Some(Environment { device, context, gl, display })
+    }
+
+    // Maps a window and waits until the server has shown it, so that it has contents to read.
+    unsafe fn create_window(&self) -> Window {
+        let root = xlib::XDefaultRootWindow(self.display);
+        let window = xlib::XCreateSimpleWindow(self.display,
+                                               root,
+                                               0,
+                                               0,
+                                               SIZE as u32,
+                                               SIZE as u32,
+                                               0,
+                                               0,
+                                               0);
+        xlib::XSelectInput(self.display, window, xlib::StructureNotifyMask);
+        xlib::XMapWindow(self.display, window);
+        loop {
+            let mut event: xlib::XEvent = mem::zeroed();
+            xlib::XWindowEvent(self.display, window, xlib::StructureNotifyMask, &mut event);
+            if event.get_type() == xlib::MapNotify {
+                break;
+            }
+        }
+        window
+    }
+
+    // Clears the surface to red, with a green bottom row in OpenGL's coordinate system.
+    unsafe fn draw(&self) {
+        let framebuffer_object = self.device
+                                     .context_surface_info(&self.context)
+                                     .unwrap()
+                                     .unwrap()
+                                     .framebuffer_object;
+        self.gl.BindFramebuffer(gl::FRAMEBUFFER, framebuffer_object);
+        self.gl.Viewport(0, 0, SIZE, SIZE);
+        self.gl.ClearColor(1.0, 0.0, 0.0, 1.0);
+        self.gl.Clear(gl::COLOR_BUFFER_BIT);
+        self.gl.Scissor(0, 0, SIZE, 1);
+        self.gl.Enable(gl::SCISSOR_TEST);
+        self.gl.ClearColor(0.0, 1.0, 0.0, 1.0);
+        self.gl.Clear(gl::COLOR_BUFFER_BIT);
+        self.gl.Disable(gl::SCISSOR_TEST);
+    }
+
+    // Binds a new surface of the given format to the context, returning the old one.
+    fn replace_surface(&mut self, format: SurfaceFormat) -> Option<Surface> {
+        let old_surface = self.device.unbind_surface_from_context(&mut self.context).unwrap();
+        let surface_type = SurfaceType::Generic {
+            size: Size2D::new(SIZE, SIZE),
+            format,
+            depth_stencil: None,
+        };
+        let surface = self.device
+                          .create_surface(&self.context, SurfaceAccess::GPUOnly, surface_type)
+                          .unwrap();
+        self.device.bind_surface_to_context(&mut self.context, surface).unwrap();
+        old_surface
+    }
+
+    unsafe fn dri3_present(&self) -> bool {
+        let (mut opcode, mut event, mut error) = (0, 0, 0);
+        let name = b"DRI3\0";
+        xlib::XQueryExtension(self.display,
+                              name.as_ptr() as *const c_char,
+                              &mut opcode,
+                              &mut event,
+                              &mut error) != 0
+    }
+
+    // Returns true if the context's surface exports as a single-plane ARGB8888 dma-buf.
+    fn exports_argb8888(&mut self) -> bool {
+        let surface = self.device.unbind_surface_from_context(&mut self.context).unwrap().unwrap();
+        let argb8888 = match self.device.export_surface(&self.context, &surface) {
+            Ok(SurfaceHandle::DmaBuf(ref image)) => {
+                image.fourcc == DRM_FORMAT_ARGB8888 && image.planes.len() == 1
+            }
+            _ => false,
+        };
+        self.device.bind_surface_to_context(&mut self.context, surface).unwrap();
+        argb8888
+    }
+
+    // Presents the context's surface and returns the window's top and bottom rows once the server
+    // has shown it.
+    unsafe fn present(&mut self, presenter: &mut Presenter, window: Window) -> (u32, u32) {
+        let surface = self.device.unbind_surface_from_context(&mut self.context).unwrap().unwrap();
+        let serial = self.device
+                         .present_surface_to_window(&self.context, presenter, &surface)
+                         .unwrap();
+        loop {
+            match self.device.wait_for_present_event(presenter).unwrap() {
+                PresentEvent::Complete { serial: complete_serial, .. } if
+                        complete_serial == serial => break,
+                _ => {}
+            }
+        }
+        self.device.bind_surface_to_context(&mut self.context, surface).unwrap();
+
+        let image = xlib::XGetImage(self.display,
+                                    window,
+                                    0,
+                                    0,
+                                    SIZE as u32,
+                                    SIZE as u32,
+                                    xlib::XAllPlanes(),
+                                    xlib::ZPixmap);
+        assert!(!image.is_null());
+        let top = xlib::XGetPixel(image, 0, 0) as u32 & 0xffffff;
+        let bottom = xlib::XGetPixel(image, 0, SIZE - 1) as u32 & 0xffffff;
+        xlib::XDestroyImage(image);
+        (top, bottom)
+    }
+}
+
+#[test]
+fn test_present_surface_to_window() {
+    let mut env = match Environment::new() {
+        Some(env) => env,
+        None => return,
+    };
+
+    unsafe {
+        let window = env.create_window();
+        let mut presenter = match env.device.create_presenter(&NativeWidget { window }) {
+            Ok(presenter) => presenter,
+            Err(Error::RequiredExtensionUnavailable) => {
+                // No Present extension, or no XCB libraries to use it with.
+                xlib::XDestroyWindow(env.display, window);
+                env.device.destroy_context(&mut env.context).unwrap();
+                return;
+            }
+            Err(err) => panic!("Failed to create presenter: {:?}", err),
+        };
+
+        // By default, the window shows the surface upright, which takes a copy.
+        assert!(!presenter.top_down());
+        env.draw();
+        assert_eq!(env.present(&mut presenter, window), (0xff0000, 0x00ff00));
+        assert!(presenter.last_frame_copied());
+
+        // Top-down surfaces are shown as they are, whether or not DRI3 can import them.
+        presenter.set_top_down(true);
+        env.draw();
+        assert_eq!(env.present(&mut presenter, window), (0x00ff00, 0xff0000));
+
+        // A `BGRA8` surface that exports in a layout DRI3 accepts is shown from its own memory.
+        let old_surface = env.replace_surface(SurfaceFormat::BGRA8);
+        env.draw();
+        let dri3_importable = env.dri3_present() && env.exports_argb8888();
+        assert_eq!(env.present(&mut presenter, window), (0x00ff00, 0xff0000));
+        if dri3_importable {
+            assert!(!presenter.last_frame_copied());
+        }
+        let mut surface = env.device.unbind_surface_from_context(&mut env.context)
+                                    .unwrap()
+                                    .unwrap();
+        env.device.destroy_surface(&mut env.context, &mut surface).unwrap();
+        env.device.bind_surface_to_context(&mut env.context, old_surface.unwrap()).unwrap();
+
+        env.device.destroy_presenter(&env.context, &mut presenter).unwrap();
+
+        // Under `LeakPolicy::Leak`, a dropped presenter is leaked instead of panicking.
+        env.device.set_leak_policy(LeakPolicy::Leak);
+        drop(env.device.create_presenter(&NativeWidget { window }).unwrap());
+
+        xlib::XDestroyWindow(env.display, window);
+        env.device.destroy_context(&mut env.context).unwrap();
+    }
+}
+
+#[test]
+fn test_pixmap_surface_texture() {
+    let mut env = match Environment::new() {
+        Some(env) => env,
+        None => return,
+    };
+
+    unsafe {
+        let root = xlib::XDefaultRootWindow(env.display);
+        let depth = xlib::XDefaultDepth(env.display, xlib::XDefaultScreen(env.display)) as u32;
+        let pixmap = xlib::XCreatePixmap(env.display, root, SIZE as u32, SIZE as u32, depth);
+        let gc = xlib::XCreateGC(env.display, pixmap, 0, ptr::null_mut());
+        xlib::XSetForeground(env.display, gc, 0xff0000);
+        xlib::XFillRectangle(env.display, pixmap, gc, 0, 0, SIZE as u32, SIZE as u32);
+
+        let surface_texture = match env.device.create_surface_texture_from_pixmap(&mut env.context,
+                                                                                  pixmap) {
+            Ok(surface_texture) => surface_texture,
+            Err(Error::RequiredExtensionUnavailable) => {
+                // No `EGL_KHR_image_pixmap`.
+                xlib::XFreeGC(env.display, gc);
+                xlib::XFreePixmap(env.display, pixmap);
+                env.device.destroy_context(&mut env.context).unwrap();
+                return;
+            }
+            Err(err) => panic!("Failed to wrap the pixmap: {:?}", err),
+        };
+        // Catch up with whatever was drawn before the texture existed.
+        env.device.refresh_surface_texture(&env.context, &surface_texture).unwrap();
+
+        // Draw a green rectangle with X and pick it up.
+        xlib::XSetForeground(env.display, gc, 0x00ff00);
+        xlib::XFillRectangle(env.display, pixmap, gc, 2, 3, 4, 5);
+        let damaged_rects = env.device
+                               .refresh_surface_texture(&env.context, &surface_texture)
+                               .unwrap();
+        let green_rect = Rect::new(Point2D::new(2, 3), Size2D::new(4, 5));
+        let whole_rect = Rect::new(Point2D::zero(), Size2D::new(SIZE, SIZE));
+        assert!(damaged_rects == [green_rect] || damaged_rects == [whole_rect],
+                "Unexpected damage: {:?}",
+                damaged_rects);
+
+        // Row 0 of the texture is the top row of the pixmap.
+        env.device.make_context_current(&env.context).unwrap();
+        let texture_object = env.device.surface_texture_object(&surface_texture);
+        let mut framebuffer_object = 0;
+        env.gl.GenFramebuffers(1, &mut framebuffer_object);
+        env.gl.BindFramebuffer(gl::FRAMEBUFFER, framebuffer_object);
+        env.gl.FramebufferTexture2D(gl::FRAMEBUFFER,
+                                    gl::COLOR_ATTACHMENT0,
+                                    gl::TEXTURE_2D,
+                                    texture_object,
+                                    0);
+        assert_eq!(read_pixel(&env.gl, 3, 4), [0, 255, 0]);
+        assert_eq!(read_pixel(&env.gl, 0, 0), [255, 0, 0]);
+        env.gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+        env.gl.DeleteFramebuffers(1, &framebuffer_object);
+
+        let mut surface = env.device
+                             .destroy_surface_texture(&mut env.context, surface_texture)
+                             .unwrap();
+        env.device.destroy_surface(&mut env.context, &mut surface).unwrap();
+
+        // Under `LeakPolicy::Defer`, a dropped texture's damage object is destroyed later along
+        // with its surface.
+        env.device.set_leak_policy(LeakPolicy::Defer);
+        let surface_texture = env.device
+                                 .create_surface_texture_from_pixmap(&mut env.context, pixmap)
+                                 .unwrap();
+        drop(surface_texture);
+        env.device.make_context_current(&env.context).unwrap();
+
+        xlib::XFreeGC(env.display, gc);
+        xlib::XFreePixmap(env.display, pixmap);
+        env.device.destroy_context(&mut env.context).unwrap();
+    }
+}
+
+#[test]
+fn test_alpha_context_descriptor_visual() {
+    let mut env = match Environment::new() {
+        Some(env) => env,
+        None => return,
+    };
+
+    // Only configs that can be chosen for `ALPHA` count.
+    let attributes = ContextAttributes::new(GLVersion::new(3, 0), ContextAttributeFlags::ALPHA);
+    let has_32_bit_visual = env.device
+                               .enumerate_context_descriptors(&attributes)
+                               .unwrap()
+                               .iter()
+                               .any(|(descriptor, pixel_format)| {
+        pixel_format.alpha_size >= 8 &&
+            env.device
+               .context_descriptor_visual_info(descriptor)
+               .map_or(false, |visual_info| visual_info.depth == 32)
+    });
+
+    let descriptor = env.device.create_context_descriptor(&attributes).unwrap();
+    let visual_info = env.device.context_descriptor_visual_info(&descriptor);
+    if has_32_bit_visual {
+        assert_eq!(visual_info.unwrap().depth, 32);
+    }
+
+    env.device.destroy_context(&mut env.context).unwrap();
+}
+
+unsafe fn read_pixel(gl: &Gl, x: i32, y: i32) -> [u8; 3] {
+    let mut pixel = [0u8; 4];
+    gl.ReadPixels(x, y, 1, 1, gl::RGBA, gl::UNSIGNED_BYTE, pixel.as_mut_ptr() as *mut c_void);
+    [pixel[0], pixel[1], pixel[2]]
+}
@@ -8,6 +8,7 @@ use crate::error::Error;
 use crate::platform::generic::egl::device::EGL_FUNCTIONS;
 use crate::platform::generic::egl::ffi::EGL_PLATFORM_X11_KHR;
 use crate::platform::unix::generic::device::Adapter;
+use crate::platform::generic::egl::wayland;
 use super::device::{Device, NativeDevice};
 use super::surface::NativeWidget;
 
@@ -15,6 +16,7 @@ use std::marker::PhantomData;
 use std::os::raw::c_void;
 use std::ptr;
 use std::sync::Arc;
+use wayland_sys::server;
 use x11::xlib::{Display, XCloseDisplay, XInitThreads, XLockDisplay, XOpenDisplay, XUnlockDisplay};
 
 #[cfg(feature = "sm-winit")]
@@ -81,7 +83,13 @@ impl Connection {
                 return Err(Error::ConnectionFailed);
             }
 
-            let egl_display = create_egl_display(x11_display);
+            let egl_display = match create_egl_display(x11_display) {
+                Ok(egl_display) => egl_display,
+                Err(err) => {
+                    XCloseDisplay(x11_display);
+                    return Err(err);
+                }
+            };
 
             Ok(Connection {
                 native_connection: Arc::new(NativeConnectionWrapper {
@@ -116,7 +124,7 @@ impl Connection {
 
     fn from_x11_display(x11_display: *mut Display, is_owned: bool) -> Result<Connection, Error> {
         unsafe {
-            let egl_display = create_egl_display(x11_display);
+            let egl_display = create_egl_display(x11_display)?;
             Ok(Connection {
                 native_connection: Arc::new(NativeConnectionWrapper {
                     egl_display,
@@ -180,6 +188,23 @@ impl Connection {
         Device::new(self, &native_device.adapter)
     }
 
+    /// Lets clients of the given compositor display allocate buffers that
+    /// `Device::create_surface_texture_from_wl_buffer()` samples without a copy.
+    ///
+    /// The display must be unbound with `unbind_wayland_display()` before it's destroyed.
+    #[inline]
+    pub unsafe fn bind_wayland_display(&self, wayland_display: *mut server::wl_display)
+                                       -> Result<(), Error> {
+        wayland::bind_wayland_display(self.native_connection.egl_display, wayland_display)
+    }
+
+    /// Undoes `bind_wayland_display()`.
+    #[inline]
+    pub unsafe fn unbind_wayland_display(&self, wayland_display: *mut server::wl_display)
+                                         -> Result<(), Error> {
+        wayland::unbind_wayland_display(self.native_connection.egl_display, wayland_display)
+    }
+
     /// Opens the display connection corresponding to the given `winit` window.
     #[cfg(feature = "sm-winit")]
     pub fn from_winit_window(window: &Window) -> Result<Connection, Error> {
@@ -234,18 +259,23 @@ impl<'a> DisplayGuard<'a> {
     }
 }
 
-unsafe fn create_egl_display(display: *mut Display) -> EGLDisplay {
+unsafe fn create_egl_display(display: *mut Display) -> Result<EGLDisplay, Error> {
     EGL_FUNCTIONS.with(|egl| {
         let display_attributes = [egl::NONE as EGLAttrib];
         let egl_display = egl.GetPlatformDisplay(EGL_PLATFORM_X11_KHR,
                                                  display as *mut c_void,
                                                  display_attributes.as_ptr());
+        if egl_display == egl::NO_DISPLAY {
+            return Err(Error::DeviceOpenFailed);
+        }
 
         let (mut egl_major_version, mut egl_minor_version) = (0, 0);
         let ok = egl.Initialize(egl_display, &mut egl_major_version, &mut egl_minor_version);
-        assert_ne!(ok, egl::FALSE);
+        if ok == egl::FALSE {
+            return Err(Error::DeviceOpenFailed);
+        }
 
-        egl_display
+        Ok(egl_display)
     })
 }
 
@@ -0,0 +1,338 @@
+// surfman/surfman/src/platform/unix/x11/ffi.rs
+//
+//! FFI definitions for the DRI3, Present, and DAMAGE extensions.
+//!
+//! DRI3 and Present are only reachable through XCB. The libraries are loaded at runtime, since
+//! their development symlinks are rarely installed and the extensions are optional anyway.
+
+#![allow(dead_code, non_camel_case_types)]
+
+use libc::{RTLD_LAZY, dlopen, dlsym};
+use std::mem;
+use std::os::raw::{c_char, c_int, c_void};
+use x11::xlib::{Bool, Display, Drawable, XID, XRectangle};
+
+pub(crate) const XCB_PRESENT_EVENT_CONFIGURE_NOTIFY: u16 = 0;
+pub(crate) const XCB_PRESENT_EVENT_COMPLETE_NOTIFY: u16 = 1;
+pub(crate) const XCB_PRESENT_EVENT_IDLE_NOTIFY: u16 = 2;
+
+pub(crate) const XCB_PRESENT_EVENT_MASK_NO_EVENT: u32 = 0;
+pub(crate) const XCB_PRESENT_EVENT_MASK_COMPLETE_NOTIFY: u32 = 2;
+pub(crate) const XCB_PRESENT_EVENT_MASK_IDLE_NOTIFY: u32 = 4;
+
+pub(crate) const XCB_PRESENT_OPTION_NONE: u32 = 0;
+
+pub(crate) const XCB_PRESENT_COMPLETE_KIND_PIXMAP: u8 = 0;
+
+pub(crate) const XCB_PRESENT_COMPLETE_MODE_COPY: u8 = 0;
+pub(crate) const XCB_PRESENT_COMPLETE_MODE_FLIP: u8 = 1;
+pub(crate) const XCB_PRESENT_COMPLETE_MODE_SKIP: u8 = 2;
+pub(crate) const XCB_PRESENT_COMPLETE_MODE_SUBOPTIMAL_COPY: u8 = 3;
+
+pub(crate) const XCB_NONE: u32 = 0;
+
+pub(crate) const XDAMAGE_REPORT_NON_EMPTY: c_int = 3;
+
+pub(crate) type Damage = XID;
+pub(crate) type XserverRegion = XID;
+
+pub(crate) enum xcb_connection_t {}
+pub(crate) enum xcb_extension_t {}
+pub(crate) enum xcb_special_event_t {}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(crate) struct xcb_void_cookie_t {
+    pub(crate) sequence: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(crate) struct xcb_dri3_query_version_cookie_t {
+    pub(crate) sequence: u32,
+}
+
+#[repr(C)]
+pub(crate) struct xcb_generic_error_t {
+    pub(crate) response_type: u8,
+    pub(crate) error_code: u8,
+    pub(crate) sequence: u16,
+    pub(crate) resource_id: u32,
+    pub(crate) minor_code: u16,
+    pub(crate) major_code: u8,
+    pub(crate) pad0: u8,
+    pub(crate) pad: [u32; 5],
+    pub(crate) full_sequence: u32,
+}
+
+#[repr(C)]
+pub(crate) struct xcb_query_extension_reply_t {
+    pub(crate) response_type: u8,
+    pub(crate) pad0: u8,
+    pub(crate) sequence: u16,
+    pub(crate) length: u32,
+    pub(crate) present: u8,
+    pub(crate) major_opcode: u8,
+    pub(crate) first_event: u8,
+    pub(crate) first_error: u8,
+}
+
+#[repr(C)]
+pub(crate) struct xcb_dri3_query_version_reply_t {
+    pub(crate) response_type: u8,
+    pub(crate) pad0: u8,
+    pub(crate) sequence: u16,
+    pub(crate) length: u32,
+    pub(crate) major_version: u32,
+    pub(crate) minor_version: u32,
+}
+
+#[repr(C)]
+pub(crate) struct xcb_present_generic_event_t {
+    pub(crate) response_type: u8,
+    pub(crate) extension: u8,
+    pub(crate) sequence: u16,
+    pub(crate) length: u32,
+    pub(crate) evtype: u16,
+    pub(crate) pad0: [u8; 2],
+    pub(crate) event: u32,
+}
+
+#[repr(C, packed)]
+pub(crate) struct xcb_present_complete_notify_event_t {
+    pub(crate) response_type: u8,
+    pub(crate) extension: u8,
+    pub(crate) sequence: u16,
+    pub(crate) length: u32,
+    pub(crate) event_type: u16,
+    pub(crate) kind: u8,
+    pub(crate) mode: u8,
+    pub(crate) event: u32,
+    pub(crate) window: u32,
+    pub(crate) serial: u32,
+    pub(crate) ust: u64,
+    pub(crate) full_sequence: u32,
+    pub(crate) msc: u64,
+}
+
+#[repr(C)]
+pub(crate) struct xcb_present_idle_notify_event_t {
+    pub(crate) response_type: u8,
+    pub(crate) extension: u8,
+    pub(crate) sequence: u16,
+    pub(crate) length: u32,
+    pub(crate) event_type: u16,
+    pub(crate) pad0: [u8; 2],
+    pub(crate) event: u32,
+    pub(crate) window: u32,
+    pub(crate) serial: u32,
+    pub(crate) pixmap: u32,
+    pub(crate) idle_fence: u32,
+    pub(crate) full_sequence: u32,
+}
+
+#[allow(non_snake_case)]
+pub(crate) struct XCBFunctions {
+    pub(crate) XGetXCBConnection: unsafe extern "C" fn(*mut Display) -> *mut xcb_connection_t,
+    pub(crate) xcb_generate_id: unsafe extern "C" fn(*mut xcb_connection_t) -> u32,
+    pub(crate) xcb_flush: unsafe extern "C" fn(*mut xcb_connection_t) -> c_int,
+    pub(crate) xcb_discard_reply: unsafe extern "C" fn(*mut xcb_connection_t, u32),
+    pub(crate) xcb_request_check: unsafe extern "C" fn(*mut xcb_connection_t, xcb_void_cookie_t)
+                                                       -> *mut xcb_generic_error_t,
+    pub(crate) xcb_get_extension_data:
+        unsafe extern "C" fn(*mut xcb_connection_t, *mut xcb_extension_t)
+                             -> *const xcb_query_extension_reply_t,
+    pub(crate) xcb_register_for_special_xge: unsafe extern "C" fn(*mut xcb_connection_t,
+                                                                  *mut xcb_extension_t,
+                                                                  u32,
+                                                                  *mut u32)
+                                                                  -> *mut xcb_special_event_t,
+    pub(crate) xcb_unregister_for_special_event: unsafe extern "C" fn(*mut xcb_connection_t,
+                                                                      *mut xcb_special_event_t),
+    pub(crate) xcb_poll_for_special_event:
+        unsafe extern "C" fn(*mut xcb_connection_t, *mut xcb_special_event_t)
+                             -> *mut xcb_present_generic_event_t,
+    pub(crate) xcb_wait_for_special_event:
+        unsafe extern "C" fn(*mut xcb_connection_t, *mut xcb_special_event_t)
+                             -> *mut xcb_present_generic_event_t,
+    pub(crate) xcb_free_pixmap: unsafe extern "C" fn(*mut xcb_connection_t, u32)
+                                                     -> xcb_void_cookie_t,
+
+    pub(crate) xcb_present_id: *mut xcb_extension_t,
+    pub(crate) xcb_present_select_input_checked: unsafe extern "C" fn(*mut xcb_connection_t,
+                                                                      u32,
+                                                                      u32,
+                                                                      u32)
+                                                                      -> xcb_void_cookie_t,
+    pub(crate) xcb_present_pixmap_checked: unsafe extern "C" fn(*mut xcb_connection_t,
+                                                                u32,
+                                                                u32,
+                                                                u32,
+                                                                u32,
+                                                                u32,
+                                                                i16,
+                                                                i16,
+                                                                u32,
+                                                                u32,
+                                                                u32,
+                                                                u32,
+                                                                u64,
+                                                                u64,
+                                                                u64,
+                                                                u32,
+                                                                *const c_void)
+                                                                -> xcb_void_cookie_t,
+
+    // `None` if `libxcb-dri3` isn't installed, in which case surfaces are always copied.
+    pub(crate) dri3: Option<DRI3Functions>,
+}
+
+pub(crate) struct DRI3Functions {
+    pub(crate) xcb_dri3_id: *mut xcb_extension_t,
+    pub(crate) xcb_dri3_query_version: unsafe extern "C" fn(*mut xcb_connection_t, u32, u32)
+                                                            -> xcb_dri3_query_version_cookie_t,
+    pub(crate) xcb_dri3_query_version_reply:
+        unsafe extern "C" fn(*mut xcb_connection_t,
+                             xcb_dri3_query_version_cookie_t,
+                             *mut *mut xcb_generic_error_t)
+                             -> *mut xcb_dri3_query_version_reply_t,
+    pub(crate) xcb_dri3_pixmap_from_buffer_checked: unsafe extern "C" fn(*mut xcb_connection_t,
+                                                                         u32,
+                                                                         u32,
+                                                                         u32,
+                                                                         u16,
+                                                                         u16,
+                                                                         u16,
+                                                                         u8,
+                                                                         u8,
+                                                                         i32)
+                                                                         -> xcb_void_cookie_t,
+    // Only present in DRI3 1.2 and up.
+    pub(crate) xcb_dri3_pixmap_from_buffers_checked: Option<PixmapFromBuffersFn>,
+}
+
+pub(crate) type PixmapFromBuffersFn = unsafe extern "C" fn(*mut xcb_connection_t,
+                                                           u32,
+                                                           u32,
+                                                           u8,
+                                                           u16,
+                                                           u16,
+                                                           u32,
+                                                           u32,
+                                                           u32,
+                                                           u32,
+                                                           u32,
+                                                           u32,
+                                                           u32,
+                                                           u32,
+                                                           u8,
+                                                           u8,
+                                                           u64,
+                                                           *const i32)
+                                                           -> xcb_void_cookie_t;
+
+unsafe impl Send for XCBFunctions {}
+unsafe impl Sync for XCBFunctions {}
+
+lazy_static! {
+    pub(crate) static ref XCB_FUNCTIONS: Option<XCBFunctions> = unsafe { XCBFunctions::load() };
+}
+
+#[allow(non_snake_case)]
+pub(crate) struct XDamageFunctions {
+    pub(crate) XDamageQueryExtension: unsafe extern "C" fn(*mut Display, *mut c_int, *mut c_int)
+                                                           -> Bool,
+    pub(crate) XDamageCreate: unsafe extern "C" fn(*mut Display, Drawable, c_int) -> Damage,
+    pub(crate) XDamageDestroy: unsafe extern "C" fn(*mut Display, Damage),
+    pub(crate) XDamageSubtract: unsafe extern "C" fn(*mut Display,
+                                                     Damage,
+                                                     XserverRegion,
+                                                     XserverRegion),
+    // Damage is reported as XFixes regions.
+    pub(crate) XFixesCreateRegion: unsafe extern "C" fn(*mut Display, *mut XRectangle, c_int)
+                                                        -> XserverRegion,
+    pub(crate) XFixesDestroyRegion: unsafe extern "C" fn(*mut Display, XserverRegion),
+    pub(crate) XFixesFetchRegion: unsafe extern "C" fn(*mut Display, XserverRegion, *mut c_int)
+                                                       -> *mut XRectangle,
+}
+
+lazy_static! {
+    pub(crate) static ref XDAMAGE_FUNCTIONS: Option<XDamageFunctions> = unsafe {
+        XDamageFunctions::load()
+    };
+}
+
+impl XCBFunctions {
+    unsafe fn load() -> Option<XCBFunctions> {
+        let x11_xcb = open_library(b"libX11-xcb.so.1\0")?;
+        let xcb = open_library(b"libxcb.so.1\0")?;
+        let present = open_library(b"libxcb-present.so.0\0")?;
+        Some(XCBFunctions {
+            XGetXCBConnection: mem::transmute(symbol(x11_xcb, b"XGetXCBConnection\0")?),
+            xcb_generate_id: mem::transmute(symbol(xcb, b"xcb_generate_id\0")?),
+            xcb_flush: mem::transmute(symbol(xcb, b"xcb_flush\0")?),
+            xcb_discard_reply: mem::transmute(symbol(xcb, b"xcb_discard_reply\0")?),
+            xcb_request_check: mem::transmute(symbol(xcb, b"xcb_request_check\0")?),
+            xcb_get_extension_data: mem::transmute(symbol(xcb, b"xcb_get_extension_data\0")?),
+            xcb_register_for_special_xge:
+                mem::transmute(symbol(xcb, b"xcb_register_for_special_xge\0")?),
+            xcb_unregister_for_special_event:
+                mem::transmute(symbol(xcb, b"xcb_unregister_for_special_event\0")?),
+            xcb_poll_for_special_event:
+                mem::transmute(symbol(xcb, b"xcb_poll_for_special_event\0")?),
+            xcb_wait_for_special_event:
+                mem::transmute(symbol(xcb, b"xcb_wait_for_special_event\0")?),
+            xcb_free_pixmap: mem::transmute(symbol(xcb, b"xcb_free_pixmap\0")?),
+            xcb_present_id: symbol(present, b"xcb_present_id\0")? as *mut xcb_extension_t,
+            xcb_present_select_input_checked:
+                mem::transmute(symbol(present, b"xcb_present_select_input_checked\0")?),
+            xcb_present_pixmap_checked:
+                mem::transmute(symbol(present, b"xcb_present_pixmap_checked\0")?),
+            dri3: DRI3Functions::load(),
+        })
+    }
+}
+
+impl DRI3Functions {
+    unsafe fn load() -> Option<DRI3Functions> {
+        let dri3 = open_library(b"libxcb-dri3.so.0\0")?;
+        Some(DRI3Functions {
+            xcb_dri3_id: symbol(dri3, b"xcb_dri3_id\0")? as *mut xcb_extension_t,
+            xcb_dri3_query_version: mem::transmute(symbol(dri3, b"xcb_dri3_query_version\0")?),
+            xcb_dri3_query_version_reply:
+                mem::transmute(symbol(dri3, b"xcb_dri3_query_version_reply\0")?),
+            xcb_dri3_pixmap_from_buffer_checked:
+                mem::transmute(symbol(dri3, b"xcb_dri3_pixmap_from_buffer_checked\0")?),
+            xcb_dri3_pixmap_from_buffers_checked:
+                symbol(dri3, b"xcb_dri3_pixmap_from_buffers_checked\0").map(|function| {
+                    mem::transmute(function)
+                }),
+        })
+    }
+}
+
+impl XDamageFunctions {
+    unsafe fn load() -> Option<XDamageFunctions> {
+        let xdamage = open_library(b"libXdamage.so.1\0")?;
+        let xfixes = open_library(b"libXfixes.so.3\0")?;
+        Some(XDamageFunctions {
+            XDamageQueryExtension: mem::transmute(symbol(xdamage, b"XDamageQueryExtension\0")?),
+            XDamageCreate: mem::transmute(symbol(xdamage, b"XDamageCreate\0")?),
+            XDamageDestroy: mem::transmute(symbol(xdamage, b"XDamageDestroy\0")?),
+            XDamageSubtract: mem::transmute(symbol(xdamage, b"XDamageSubtract\0")?),
+            XFixesCreateRegion: mem::transmute(symbol(xfixes, b"XFixesCreateRegion\0")?),
+            XFixesDestroyRegion: mem::transmute(symbol(xfixes, b"XFixesDestroyRegion\0")?),
+            XFixesFetchRegion: mem::transmute(symbol(xfixes, b"XFixesFetchRegion\0")?),
+        })
+    }
+}
+
+pub(crate) unsafe fn open_library(name: &[u8]) -> Option<*mut c_void> {
+    let library = dlopen(name.as_ptr() as *const c_char, RTLD_LAZY);
+    if library.is_null() { None } else { Some(library) }
+}
+
+pub(crate) unsafe fn symbol(library: *mut c_void, name: &[u8]) -> Option<*mut c_void> {
+    let symbol = dlsym(library, name.as_ptr() as *const c_char);
+    if symbol.is_null() { None } else { Some(symbol) }
+}
@@ -5,12 +5,18 @@
 pub mod connection;
 pub mod context;
 pub mod device;
+pub mod pixmap;
+pub mod present;
 pub mod surface;
 
+pub(crate) mod ffi;
+
 #[path = "../../../implementation/mod.rs"]
 mod implementation;
 
 #[cfg(test)]
 #[path = "../../../tests.rs"]
 mod tests;
+#[cfg(test)]
+mod backend_tests;
 
@@ -0,0 +1,282 @@
+// surfman/surfman/src/platform/unix/x11/pixmap.rs
+//
+//! Sampling from X11 pixmaps with `EGL_KHR_image_pixmap`, tracking updates with XDAMAGE.
+
+use crate::egl::types::EGLint;
+use crate::egl;
+use crate::gl::types::GLuint;
+use crate::gl;
+use crate::leak::{LeakPolicy, LeakedObject, Origin};
+use crate::platform::generic::egl::device::{EGL_FUNCTIONS, display_supports_extension};
+use crate::platform::generic::egl::ffi::{EGL_EXTENSION_FUNCTIONS, EGL_IMAGE_PRESERVED_KHR};
+use crate::platform::generic::egl::ffi::{EGL_NATIVE_PIXMAP_KHR, EGL_NO_IMAGE_KHR};
+use crate::platform::generic::egl::ffi::EGLClientBuffer;
+use crate::platform::generic::egl::surface::{EGLSurfaceObjects, EGLSurfaceTexture};
+use crate::{ContextID, Error, WindowingApiError, WindowingApiFailure};
+use super::context::{Context, GL_FUNCTIONS};
+use super::device::Device;
+use super::ffi::{Damage, XDAMAGE_FUNCTIONS, XDAMAGE_REPORT_NON_EMPTY, XserverRegion};
+use super::surface::SurfaceTexture;
+
+use euclid::default::{Point2D, Rect, Size2D};
+use std::fmt::{self, Debug, Formatter};
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+use std::slice;
+use x11::xlib::{Display, False, Pixmap, XFree, XGetGeometry, XSync};
+
+/// The damage tracking state of a surface texture that wraps an X11 pixmap.
+///
+/// If the surface texture is dropped without being destroyed, its surface reports the leak, and
+/// this follows the same policy: under `LeakPolicy::Defer`, the device destroys the damage
+/// object later, and otherwise it's leaked.
+pub(crate) struct PixmapTracking {
+    // Zero if the DAMAGE extension is unavailable, in which case every refresh reports the whole
+    // pixmap.
+    damage: Damage,
+    // Receives the damaged area on each refresh.
+    parts: XserverRegion,
+    context_id: ContextID,
+    destroyed: bool,
+    origin: Origin,
+}
+
+impl Device {
+    /// Wraps an X11 pixmap in a surface texture local to the given context.
+    ///
+    /// The texture samples from the pixmap's own storage. Call `refresh_surface_texture()` after
+    /// the pixmap has been drawn to and before sampling, so that the texture reflects the new
+    /// contents.
+    ///
+    /// Row 0 of the texture is the top row of the pixmap, so the image is upside down in OpenGL's
+    /// coordinate system.
+    ///
+    /// The pixmap must outlive both the surface texture and the surface that destroying the
+    /// texture returns. Returns `RequiredExtensionUnavailable` if the EGL implementation doesn't
+    /// support `EGL_KHR_image_pixmap`.
+    pub fn create_surface_texture_from_pixmap(&self, context: &mut Context, pixmap: Pixmap)
+                                              -> Result<SurfaceTexture, Error> {
+        let egl_display = self.native_connection.egl_display;
+        unsafe {
+            if !display_supports_extension(egl_display, "EGL_KHR_image_pixmap") {
+                return Err(Error::RequiredExtensionUnavailable);
+            }
+
+            let (size, pixmap_tracking) = {
+                let display_guard = self.native_connection.lock_display();
+                let display = display_guard.display();
+                let (mut root_window, mut x, mut y, mut width, mut height) = (0, 0, 0, 0, 0);
+                let (mut border_width, mut depth) = (0, 0);
+                if XGetGeometry(display,
+                                pixmap,
+                                &mut root_window,
+                                &mut x,
+                                &mut y,
+                                &mut width,
+                                &mut height,
+                                &mut border_width,
+                                &mut depth) == 0 {
+                    let failure = WindowingApiFailure::new("XGetGeometry",
+                                                           WindowingApiError::BadNativePixmap,
+                                                           None);
+                    return Err(Error::SurfaceTextureCreationFailed(Box::new(failure)));
+                }
+                let pixmap_tracking = PixmapTracking::new(display,
+                                                          pixmap,
+                                                          context.0.id,
+                                                          Origin::new(&self.leak_tracker));
+                (Size2D::new(width as i32, height as i32), pixmap_tracking)
+            };
+
+            let _guard = match self.temporarily_make_context_current(context) {
+                Ok(guard) => guard,
+                Err(err) => {
+                    pixmap_tracking.destroy(self.native_connection.lock_display().display());
+                    return Err(err);
+                }
+            };
+
+            let egl_image_attribs = [
+                EGL_IMAGE_PRESERVED_KHR as EGLint,  egl::TRUE as EGLint,
+                egl::NONE as EGLint,                0,
+            ];
+            let egl_image = (EGL_EXTENSION_FUNCTIONS.CreateImageKHR)(egl_display,
+                                                                     egl::NO_CONTEXT,
+                                                                     EGL_NATIVE_PIXMAP_KHR,
+                                                                     pixmap as EGLClientBuffer,
+                                                                     egl_image_attribs.as_ptr());
+            if egl_image == EGL_NO_IMAGE_KHR {
+                let native_code = EGL_FUNCTIONS.with(|egl| egl.GetError());
+                pixmap_tracking.destroy(self.native_connection.lock_display().display());
+                let failure = WindowingApiFailure::new("eglCreateImageKHR",
+                                                       WindowingApiError::BadNativePixmap,
+                                                       Some(native_code));
+                return Err(Error::SurfaceTextureCreationFailed(Box::new(failure)));
+            }
+
+            let surface_texture = GL_FUNCTIONS.with(|gl| {
+                EGLSurfaceTexture::from_imported_image(gl,
+                                                       context.0.id,
+                                                       &size,
+                                                       egl_image,
+                                                       0,
+                                                       gl::TEXTURE_2D,
+                                                       Origin::new(&self.leak_tracker))
+            });
+            Ok(SurfaceTexture(surface_texture, Some(pixmap_tracking)))
+        }
+    }
+
+    /// Brings a surface texture wrapping a pixmap up to date with X rendering to the pixmap.
+    ///
+    /// Returns the areas of the pixmap that changed since the texture was created or last
+    /// refreshed, with the origin at the top left of the pixmap. An empty list means there's
+    /// nothing new to sample. Without the DAMAGE extension, the whole pixmap is always reported.
+    ///
+    /// While the texture exists, the X server sends `DamageNotify` events to the application's
+    /// event queue. They can be ignored.
+    ///
+    /// The supplied context must be the one the surface texture was created with, and the
+    /// surface texture must have come from `create_surface_texture_from_pixmap()`. Otherwise, an
+    /// `IncompatibleSurfaceTexture` error is returned.
+    pub fn refresh_surface_texture(&self, context: &Context, surface_texture: &SurfaceTexture)
+                                   -> Result<Vec<Rect<i32>>, Error> {
+        let pixmap_tracking = match surface_texture.1 {
+            Some(ref pixmap_tracking) => pixmap_tracking,
+            None => return Err(Error::IncompatibleSurfaceTexture),
+        };
+        if context.0.id != surface_texture.0.surface.context_id {
+            return Err(Error::IncompatibleSurfaceTexture);
+        }
+        let egl_image = match surface_texture.0.surface.objects {
+            EGLSurfaceObjects::Imported { egl_image, .. } => egl_image,
+            EGLSurfaceObjects::TextureImage { .. } |
+            EGLSurfaceObjects::TextureArray { .. } |
+            EGLSurfaceObjects::Window { .. } => return Err(Error::IncompatibleSurfaceTexture),
+        };
+
+        unsafe {
+            let damaged_rects = {
+                let display_guard = self.native_connection.lock_display();
+                pixmap_tracking.take_damage(display_guard.display(),
+                                            &surface_texture.0.surface.size)
+            };
+            if damaged_rects.is_empty() {
+                return Ok(damaged_rects);
+            }
+
+            // Respecifying the texture makes drivers that copy pixmaps instead of aliasing them
+            // pick up the new contents.
+            let _guard = self.temporarily_make_context_current(context)?;
+            GL_FUNCTIONS.with(|gl| {
+                let mut old_texture = 0;
+                gl.GetIntegerv(gl::TEXTURE_BINDING_2D, &mut old_texture);
+                gl.BindTexture(gl::TEXTURE_2D, surface_texture.0.texture_object);
+                (EGL_EXTENSION_FUNCTIONS.ImageTargetTexture2DOES)(gl::TEXTURE_2D, egl_image);
+                gl.BindTexture(gl::TEXTURE_2D, old_texture as GLuint);
+            });
+            Ok(damaged_rects)
+        }
+    }
+
+    // Destroys the damage tracking of pixmap surface textures that were dropped under
+    // `LeakPolicy::Defer`. This needs no context, so it happens whenever deferred objects are
+    // destroyed.
+    pub(crate) fn destroy_deferred_pixmap_tracking(&self) {
+        let pixmap_trackings: Vec<PixmapTracking> = self.leak_tracker.take_deferred_objects();
+        if pixmap_trackings.is_empty() {
+            return;
+        }
+        let display_guard = self.native_connection.lock_display();
+        for pixmap_tracking in pixmap_trackings {
+            unsafe {
+                pixmap_tracking.destroy(display_guard.display());
+            }
+        }
+    }
+}
+
+impl Debug for PixmapTracking {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        write!(formatter, "PixmapTracking({:x})", self.damage)
+    }
+}
+
+impl Drop for PixmapTracking {
+    fn drop(&mut self) {
+        if self.destroyed || self.damage == 0 || self.origin.policy() != LeakPolicy::Defer {
+            return;
+        }
+
+        let pixmap_tracking = PixmapTracking {
+            damage: self.damage,
+            parts: self.parts,
+            context_id: self.context_id,
+            destroyed: false,
+            origin: self.origin.take(),
+        };
+        if let Err(pixmap_tracking) = self.origin.defer(LeakedObject::SurfaceTexture,
+                                                        self.context_id,
+                                                        pixmap_tracking) {
+            mem::forget(pixmap_tracking);
+        }
+    }
+}
+
+impl PixmapTracking {
+    unsafe fn new(display: *mut Display, pixmap: Pixmap, context_id: ContextID, origin: Origin)
+                  -> PixmapTracking {
+        let mut pixmap_tracking = PixmapTracking {
+            damage: 0,
+            parts: 0,
+            context_id,
+            destroyed: false,
+            origin,
+        };
+        let xdamage = match *XDAMAGE_FUNCTIONS {
+            Some(ref xdamage) => xdamage,
+            None => return pixmap_tracking,
+        };
+        let (mut event_base, mut error_base) = (0, 0);
+        if (xdamage.XDamageQueryExtension)(display, &mut event_base, &mut error_base) == 0 {
+            return pixmap_tracking;
+        }
+
+        pixmap_tracking.damage = (xdamage.XDamageCreate)(display, pixmap, XDAMAGE_REPORT_NON_EMPTY);
+        pixmap_tracking.parts = (xdamage.XFixesCreateRegion)(display, ptr::null_mut(), 0);
+        pixmap_tracking
+    }
+
+    // Either way, this waits for a reply, so the X server has carried out every request that drew
+    // to the pixmap before it returns.
+    unsafe fn take_damage(&self, display: *mut Display, size: &Size2D<i32>) -> Vec<Rect<i32>> {
+        if self.damage == 0 {
+            XSync(display, False);
+            return vec![Rect::new(Point2D::zero(), *size)];
+        }
+
+        let xdamage = XDAMAGE_FUNCTIONS.as_ref().unwrap();
+        (xdamage.XDamageSubtract)(display, self.damage, 0, self.parts);
+        let mut rect_count = 0;
+        let rects = (xdamage.XFixesFetchRegion)(display, self.parts, &mut rect_count);
+        if rects.is_null() {
+            return vec![];
+        }
+        let damaged_rects = slice::from_raw_parts(rects, rect_count as usize).iter().map(|rect| {
+            Rect::new(Point2D::new(rect.x as i32, rect.y as i32),
+                      Size2D::new(rect.width as i32, rect.height as i32))
+        }).collect();
+        XFree(rects as *mut c_void);
+        damaged_rects
+    }
+
+    pub(crate) unsafe fn destroy(mut self, display: *mut Display) {
+        self.destroyed = true;
+        if self.damage != 0 {
+            let xdamage = XDAMAGE_FUNCTIONS.as_ref().unwrap();
+            (xdamage.XDamageDestroy)(display, self.damage);
+            (xdamage.XFixesDestroyRegion)(display, self.parts);
+        }
+    }
+}
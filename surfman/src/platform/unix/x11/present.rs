@@ -0,0 +1,714 @@
+// surfman/surfman/src/platform/unix/x11/present.rs
+//
+//! Showing generic surfaces in X11 windows with the Present extension.
+
+use crate::egl::types::{EGLDisplay, EGLint};
+use crate::egl;
+use crate::gl::types::GLuint;
+use crate::gl;
+use crate::gl_utils;
+use crate::ipc::DmaBufImage;
+use crate::leak::{LeakedObject, Origin};
+use crate::platform::generic::egl::device::EGL_FUNCTIONS;
+use crate::platform::generic::egl::ffi::{EGL_EXTENSION_FUNCTIONS, EGL_IMAGE_PRESERVED_KHR};
+use crate::platform::generic::egl::ffi::{EGL_NATIVE_PIXMAP_KHR, EGL_NO_IMAGE_KHR};
+use crate::platform::generic::egl::ffi::{DRM_FORMAT_MOD_INVALID, EGLClientBuffer, EGLImageKHR};
+use crate::platform::generic::egl::surface::{self, EGLSurfaceObjects};
+use crate::{Error, Gl, WindowingApiError, WindowingApiFailure};
+use super::context::{Context, GL_FUNCTIONS};
+use super::device::Device;
+use super::ffi::{XCB_FUNCTIONS, XCB_NONE, XCB_PRESENT_COMPLETE_KIND_PIXMAP};
+use super::ffi::{XCB_PRESENT_COMPLETE_MODE_FLIP, XCB_PRESENT_COMPLETE_MODE_SKIP};
+use super::ffi::{XCB_PRESENT_EVENT_COMPLETE_NOTIFY, XCB_PRESENT_EVENT_IDLE_NOTIFY};
+use super::ffi::{XCB_PRESENT_EVENT_MASK_COMPLETE_NOTIFY, XCB_PRESENT_EVENT_MASK_IDLE_NOTIFY};
+use super::ffi::{XCB_PRESENT_EVENT_MASK_NO_EVENT, XCB_PRESENT_OPTION_NONE, XCBFunctions};
+use super::ffi::{xcb_connection_t, xcb_extension_t, xcb_present_complete_notify_event_t};
+use super::ffi::{xcb_present_generic_event_t, xcb_present_idle_notify_event_t};
+use super::ffi::{xcb_special_event_t, xcb_void_cookie_t};
+use super::surface::{NativeWidget, Surface};
+
+use euclid::default::Size2D;
+use std::mem::{self, ManuallyDrop};
+use std::os::raw::c_void;
+use std::ptr;
+use x11::xlib::{Display, Window, XCreatePixmap, XFreePixmap, XGetWindowAttributes};
+use x11::xlib::XWindowAttributes;
+
+const DRM_FORMAT_ARGB8888: u32 = 0x3432_5241;
+const DRM_FORMAT_XRGB8888: u32 = 0x3432_5258;
+
+/// Shows generic surfaces in an X11 window without making them widget surfaces.
+///
+/// By default, each frame is flipped and copied on the GPU into an X pixmap, so the window shows
+/// the surface upright, as the Wayland presenter does. The Present extension can't flip pixmaps
+/// itself, so callers that render upside down can call `set_top_down()` instead: then, where the
+/// X server can import the surface's buffer through DRI3, the window displays the surface's own
+/// memory, and the same surface can be presented and sampled from a surface texture without any
+/// copies.
+///
+/// DRI3 only accepts buffers laid out as `DRM_FORMAT_ARGB8888` or `DRM_FORMAT_XRGB8888`, which
+/// drivers use for `SurfaceFormat::BGRA8` surfaces but not for `RGBA8` ones. Surfaces of other
+/// formats are always copied. `last_frame_copied()` reports which path the last frame took.
+///
+/// The X server reads from a presented surface until it reports it idle with a
+/// `PresentEvent::Idle` carrying the serial returned by `present_surface_to_window()`. Rendering
+/// to the surface before then may show up on screen mid-frame.
+///
+/// Presenters should be destroyed with the `destroy_presenter()` method. A presenter that is
+/// dropped without being destroyed is handled according to the device's `LeakPolicy`.
+pub struct Presenter {
+    window: Window,
+    depth: u8,
+    event_id: u32,
+    special_event: *mut xcb_special_event_t,
+    // Bumped by XCB whenever an event arrives. Boxed, because XCB holds a pointer to it, and
+    // leaked along with the presenter if it's dropped without being destroyed.
+    event_stamp: ManuallyDrop<Box<u32>>,
+    // `(major, minor)`, or `None` if DRI3 is unavailable or the server couldn't import one of our
+    // buffers.
+    dri3_version: Option<(u32, u32)>,
+    // False until the server has imported a buffer once. Pixmap creation is checked
+    // synchronously until then.
+    dri3_verified: bool,
+    pixmaps: Vec<PresentedPixmap>,
+    next_serial: u32,
+    // True if surfaces are stored top row first, so they can be shown without flipping.
+    top_down: bool,
+    // True if the last surface presented was copied into a pixmap rather than imported.
+    last_frame_copied: bool,
+    destroyed: bool,
+    origin: Origin,
+}
+
+struct PresentedPixmap {
+    pixmap: u32,
+    serial: u32,
+    idle: bool,
+    size: Size2D<i32>,
+    // `None` if the pixmap aliases a surface. Such pixmaps are freed as soon as they're idle.
+    copy_target: Option<CopyTarget>,
+}
+
+// An X pixmap wrapped in a framebuffer, for presenting surfaces that can't be imported.
+struct CopyTarget {
+    egl_image: EGLImageKHR,
+    texture_object: GLuint,
+    framebuffer_object: GLuint,
+}
+
+/// A notification from the X server about a surface presented with a `Presenter`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PresentEvent {
+    /// The presentation with the given serial has reached the screen, or has been skipped in
+    /// favor of a later one.
+    Complete {
+        /// The serial returned by `present_surface_to_window()`.
+        serial: u32,
+        /// How the server displayed the surface.
+        mode: PresentMode,
+        /// The media stream counter (vblank count) at which the surface was displayed.
+        msc: u64,
+        /// The time at which the surface was displayed, in microseconds.
+        ust: u64,
+    },
+    /// The X server no longer reads from the surface presented with the given serial, so it may
+    /// be rendered to again.
+    Idle {
+        /// The serial returned by `present_surface_to_window()`.
+        serial: u32,
+    },
+}
+
+/// How the X server displayed a presented surface.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PresentMode {
+    /// The contents were copied into the window.
+    Copy,
+    /// The surface was scanned out directly.
+    Flip,
+    /// The presentation was superseded by a later one before it could be displayed.
+    Skip,
+}
+
+unsafe impl Send for Presenter {}
+
+impl Drop for Presenter {
+    fn drop(&mut self) {
+        if self.destroyed {
+            unsafe {
+                ManuallyDrop::drop(&mut self.event_stamp);
+            }
+            return;
+        }
+
+        // The server may still be reading from our pixmaps, so they're leaked along with the
+        // event queue.
+        self.origin.report_leak(LeakedObject::Presenter, false);
+    }
+}
+
+impl Device {
+    /// Creates a presenter that shows generic surfaces in the given window.
+    ///
+    /// Returns `RequiredExtensionUnavailable` if the X server doesn't support the Present
+    /// extension or the XCB libraries needed to use it aren't installed.
+    pub fn create_presenter(&self, native_widget: &NativeWidget) -> Result<Presenter, Error> {
+        let xcb = match *XCB_FUNCTIONS {
+            Some(ref xcb) => xcb,
+            None => return Err(Error::RequiredExtensionUnavailable),
+        };
+
+        unsafe {
+            let display_guard = self.native_connection.lock_display();
+            let display = display_guard.display();
+            let connection = (xcb.XGetXCBConnection)(display);
+            if !extension_present(xcb, connection, xcb.xcb_present_id) {
+                return Err(Error::RequiredExtensionUnavailable);
+            }
+
+            let mut attributes: XWindowAttributes = mem::zeroed();
+            if XGetWindowAttributes(display, native_widget.window, &mut attributes) == 0 {
+                return Err(Error::InvalidNativeWidget);
+            }
+
+            // Present events are delivered to their own queue, so they never pass through Xlib.
+            let event_id = (xcb.xcb_generate_id)(connection);
+            let mut event_stamp = Box::new(0);
+            let special_event = (xcb.xcb_register_for_special_xge)(connection,
+                                                                   xcb.xcb_present_id,
+                                                                   event_id,
+                                                                   &mut *event_stamp);
+            let event_mask = XCB_PRESENT_EVENT_MASK_COMPLETE_NOTIFY |
+                XCB_PRESENT_EVENT_MASK_IDLE_NOTIFY;
+            let cookie = (xcb.xcb_present_select_input_checked)(connection,
+                                                                event_id,
+                                                                native_widget.window as u32,
+                                                                event_mask);
+            if !request_succeeded(xcb, connection, cookie) {
+                (xcb.xcb_unregister_for_special_event)(connection, special_event);
+                return Err(Error::InvalidNativeWidget);
+            }
+
+            Ok(Presenter {
+                window: native_widget.window,
+                depth: attributes.depth as u8,
+                event_id,
+                special_event,
+                event_stamp: ManuallyDrop::new(event_stamp),
+                dri3_version: query_dri3_version(xcb, connection),
+                dri3_verified: false,
+                pixmaps: vec![],
+                next_serial: 0,
+                top_down: false,
+                last_frame_copied: false,
+                destroyed: false,
+                origin: Origin::new(&self.leak_tracker),
+            })
+        }
+    }
+
+    /// Destroys a presenter.
+    ///
+    /// The context must be the one that surfaces were presented from.
+    ///
+    /// You should explicitly call this method to dispose of a presenter. Otherwise, the presenter
+    /// is handled according to the device's `LeakPolicy` when it is dropped.
+    pub fn destroy_presenter(&self, context: &Context, presenter: &mut Presenter)
+                             -> Result<(), Error> {
+        let xcb = XCB_FUNCTIONS.as_ref().unwrap();
+        let _guard = self.temporarily_make_context_current(context)?;
+        unsafe {
+            let display_guard = self.native_connection.lock_display();
+            let connection = (xcb.XGetXCBConnection)(display_guard.display());
+            let egl_display = self.native_connection.egl_display;
+            GL_FUNCTIONS.with(|gl| {
+                for presented_pixmap in presenter.pixmaps.drain(..) {
+                    presented_pixmap.destroy(gl, xcb, connection, egl_display);
+                }
+            });
+
+            let cookie = (xcb.xcb_present_select_input_checked)(connection,
+                                                                presenter.event_id,
+                                                                presenter.window as u32,
+                                                                XCB_PRESENT_EVENT_MASK_NO_EVENT);
+            (xcb.xcb_discard_reply)(connection, cookie.sequence);
+            (xcb.xcb_unregister_for_special_event)(connection, presenter.special_event);
+            (xcb.xcb_flush)(connection);
+        }
+
+        presenter.destroyed = true;
+        Ok(())
+    }
+
+    /// Shows a generic surface in the presenter's window at the next vertical blank.
+    ///
+    /// Returns the serial that the X server's notifications about this presentation carry.
+    ///
+    /// The image appears upright, unless the presenter was told with `set_top_down()` that
+    /// surfaces are already stored top row first. Surfaces are placed at the window's origin; the
+    /// window's size doesn't have to match.
+    ///
+    /// The supplied context must be the context the surface is associated with, or an
+    /// `IncompatibleSurface` error is returned. Widget surfaces return `WidgetAttached`.
+    pub fn present_surface_to_window(&self,
+                                     context: &Context,
+                                     presenter: &mut Presenter,
+                                     surface: &Surface)
+                                     -> Result<u32, Error> {
+        if context.0.id != surface.0.context_id {
+            return Err(Error::IncompatibleSurface);
+        }
+        let (egl_image, framebuffer_object) = match surface.0.objects {
+            EGLSurfaceObjects::TextureImage { egl_image, framebuffer_object, .. } => {
+                (egl_image, framebuffer_object)
+            }
+            // Layered surfaces present their first layer.
+            EGLSurfaceObjects::TextureArray {
+                ref layer_egl_images,
+                ref layer_framebuffer_objects,
+                ..
+            } => (layer_egl_images[0], layer_framebuffer_objects[0]),
+            EGLSurfaceObjects::Window { .. } => return Err(Error::WidgetAttached),
+            EGLSurfaceObjects::Imported { .. } => return Err(Error::IncompatibleSurface),
+        };
+
+        let xcb = XCB_FUNCTIONS.as_ref().unwrap();
+        let egl_display = self.native_connection.egl_display;
+        let size = surface.0.size;
+        let _guard = self.temporarily_make_context_current(context)?;
+        unsafe {
+            let display_guard = self.native_connection.lock_display();
+            let connection = (xcb.XGetXCBConnection)(display_guard.display());
+
+            GL_FUNCTIONS.with(|gl| -> Result<u32, Error> {
+                // The X server waits on the buffer's implicit fence, so a flush is enough to order
+                // its reads after our rendering.
+                gl.Flush();
+
+                let index = if presenter.top_down {
+                    presenter.import_surface(xcb, connection, egl_display, egl_image, &size)
+                } else {
+                    None
+                };
+                presenter.last_frame_copied = index.is_none();
+                let index = match index {
+                    Some(index) => index,
+                    None => {
+                        let index = presenter.copy_target_for_size(gl,
+                                                                   display_guard.display(),
+                                                                   egl_display,
+                                                                   &size)?;
+                        let copy_target = presenter.pixmaps[index].copy_target.as_ref().unwrap();
+                        copy_target.copy_from(gl, framebuffer_object, &size, !presenter.top_down);
+                        index
+                    }
+                };
+
+                let serial = presenter.next_serial;
+                presenter.next_serial = presenter.next_serial.wrapping_add(1);
+                let presented_pixmap = &mut presenter.pixmaps[index];
+                presented_pixmap.serial = serial;
+                presented_pixmap.idle = false;
+
+                let cookie = (xcb.xcb_present_pixmap_checked)(connection,
+                                                              presenter.window as u32,
+                                                              presented_pixmap.pixmap,
+                                                              serial,
+                                                              XCB_NONE,
+                                                              XCB_NONE,
+                                                              0,
+                                                              0,
+                                                              XCB_NONE,
+                                                              XCB_NONE,
+                                                              XCB_NONE,
+                                                              XCB_PRESENT_OPTION_NONE,
+                                                              0,
+                                                              0,
+                                                              0,
+                                                              0,
+                                                              ptr::null());
+                if !request_succeeded(xcb, connection, cookie) {
+                    // Pixmaps that alias a surface are never reused, so free this one now.
+                    if presented_pixmap.copy_target.is_some() {
+                        presented_pixmap.idle = true;
+                    } else {
+                        let presented_pixmap = presenter.pixmaps.swap_remove(index);
+                        (xcb.xcb_free_pixmap)(connection, presented_pixmap.pixmap);
+                        (xcb.xcb_flush)(connection);
+                    }
+                    let failure = WindowingApiFailure::new("xcb_present_pixmap",
+                                                           WindowingApiError::BadNativePixmap,
+                                                           None);
+                    return Err(Error::PresentFailed(Box::new(failure)));
+                }
+                Ok(serial)
+            })
+        }
+    }
+
+    /// Returns the notifications the X server has sent about this presenter's surfaces, without
+    /// blocking.
+    pub fn poll_present_events(&self, presenter: &mut Presenter) -> Vec<PresentEvent> {
+        let xcb = XCB_FUNCTIONS.as_ref().unwrap();
+        let mut events = vec![];
+        unsafe {
+            let display_guard = self.native_connection.lock_display();
+            let connection = (xcb.XGetXCBConnection)(display_guard.display());
+            loop {
+                let event = (xcb.xcb_poll_for_special_event)(connection, presenter.special_event);
+                if event.is_null() {
+                    break;
+                }
+                if let Some(event) = presenter.handle_event(xcb, connection, event) {
+                    events.push(event);
+                }
+            }
+        }
+        events
+    }
+
+    /// Blocks until the X server sends a notification about one of this presenter's surfaces.
+    pub fn wait_for_present_event(&self, presenter: &mut Presenter)
+                                  -> Result<PresentEvent, Error> {
+        let xcb = XCB_FUNCTIONS.as_ref().unwrap();
+        unsafe {
+            // Don't hold the display lock while blocking; XCB is thread-safe on its own.
+            let connection = {
+                let display_guard = self.native_connection.lock_display();
+                (xcb.XGetXCBConnection)(display_guard.display())
+            };
+            loop {
+                let event = (xcb.xcb_wait_for_special_event)(connection, presenter.special_event);
+                if event.is_null() {
+                    let failure = WindowingApiFailure::new("xcb_wait_for_special_event",
+                                                           WindowingApiError::Failed,
+                                                           None);
+                    return Err(Error::PresentFailed(Box::new(failure)));
+                }
+                if let Some(event) = presenter.handle_event(xcb, connection, event) {
+                    return Ok(event);
+                }
+            }
+        }
+    }
+}
+
+impl Presenter {
+    /// Tells the presenter whether surfaces are stored top row first, which is upside down in
+    /// OpenGL's coordinate system.
+    ///
+    /// Top-down surfaces are shown as they are, which lets the X server display their memory
+    /// directly through DRI3 where it can. Other surfaces are flipped, which takes a copy. This is
+    /// false by default.
+    #[inline]
+    pub fn set_top_down(&mut self, top_down: bool) {
+        self.top_down = top_down
+    }
+
+    /// Returns true if the presenter shows surfaces as they are, without flipping them.
+    #[inline]
+    pub fn top_down(&self) -> bool {
+        self.top_down
+    }
+
+    /// Returns true if the surface most recently passed to `present_surface_to_window()` was
+    /// copied into a pixmap, and false if the X server displays its memory directly.
+    ///
+    /// Surfaces are copied unless the presenter is top-down, the X server supports DRI3, and the
+    /// surface's buffer is in a layout that DRI3 accepts, which in practice means a `BGRA8`
+    /// surface.
+    #[inline]
+    pub fn last_frame_copied(&self) -> bool {
+        self.last_frame_copied
+    }
+
+    // Wraps the surface's buffer in a pixmap. Returns `None` if the X server can't display it
+    // directly.
+    unsafe fn import_surface(&mut self,
+                             xcb: &XCBFunctions,
+                             connection: *mut xcb_connection_t,
+                             egl_display: EGLDisplay,
+                             egl_image: EGLImageKHR,
+                             size: &Size2D<i32>)
+                             -> Option<usize> {
+        let (dri3, (major_version, minor_version)) = match (&xcb.dri3, self.dri3_version) {
+            (&Some(ref dri3), Some(version)) => (dri3, version),
+            _ => return None,
+        };
+        let image = surface::export_dma_buf_image(egl_display, egl_image, size)?;
+
+        // The server reads pixmaps in its own byte order, which is BGRA for 24- and 32-bit
+        // windows. Alpha is only meaningful if the window has an alpha channel.
+        let compatible = match image.fourcc {
+            DRM_FORMAT_ARGB8888 => self.depth == 24 || self.depth == 32,
+            DRM_FORMAT_XRGB8888 => self.depth == 24,
+            _ => false,
+        };
+        let explicit_modifier = image.modifier != DRM_FORMAT_MOD_INVALID;
+        let multi_buffer = (major_version, minor_version) >= (1, 2) &&
+            dri3.xcb_dri3_pixmap_from_buffers_checked.is_some();
+        if !compatible || (!multi_buffer && (image.planes.len() > 1 || explicit_modifier)) {
+            close_planes(&image);
+            return None;
+        }
+
+        // XCB takes ownership of the file descriptors.
+        let pixmap = (xcb.xcb_generate_id)(connection);
+        let cookie = if multi_buffer {
+            let plane = |index: usize| {
+                match image.planes.get(index) {
+                    Some(plane) => (plane.stride, plane.offset),
+                    None => (0, 0),
+                }
+            };
+            let fds: Vec<i32> = image.planes.iter().map(|plane| plane.fd).collect();
+            (dri3.xcb_dri3_pixmap_from_buffers_checked.unwrap())(connection,
+                                                                 pixmap,
+                                                                 self.window as u32,
+                                                                 fds.len() as u8,
+                                                                 size.width as u16,
+                                                                 size.height as u16,
+                                                                 plane(0).0,
+                                                                 plane(0).1,
+                                                                 plane(1).0,
+                                                                 plane(1).1,
+                                                                 plane(2).0,
+                                                                 plane(2).1,
+                                                                 plane(3).0,
+                                                                 plane(3).1,
+                                                                 self.depth,
+                                                                 32,
+                                                                 image.modifier,
+                                                                 fds.as_ptr())
+        } else {
+            let plane = &image.planes[0];
+            (dri3.xcb_dri3_pixmap_from_buffer_checked)(connection,
+                                                       pixmap,
+                                                       self.window as u32,
+                                                       plane.stride * size.height as u32,
+                                                       size.width as u16,
+                                                       size.height as u16,
+                                                       plane.stride as u16,
+                                                       self.depth,
+                                                       32,
+                                                       plane.fd)
+        };
+
+        if self.dri3_verified {
+            (xcb.xcb_discard_reply)(connection, cookie.sequence);
+        } else if request_succeeded(xcb, connection, cookie) {
+            self.dri3_verified = true;
+        } else {
+            // Most likely the buffer lives on a different GPU than the server's. Don't try again.
+            self.dri3_version = None;
+            return None;
+        }
+
+        self.pixmaps.push(PresentedPixmap {
+            pixmap,
+            serial: 0,
+            idle: false,
+            size: *size,
+            copy_target: None,
+        });
+        Some(self.pixmaps.len() - 1)
+    }
+
+    // Returns the index of an idle pixmap of the given size to copy into, creating one if
+    // necessary.
+    unsafe fn copy_target_for_size(&mut self,
+                                   gl: &Gl,
+                                   display: *mut Display,
+                                   egl_display: EGLDisplay,
+                                   size: &Size2D<i32>)
+                                   -> Result<usize, Error> {
+        if let Some(index) = self.pixmaps.iter().position(|presented_pixmap| {
+            presented_pixmap.idle && presented_pixmap.copy_target.is_some() &&
+                presented_pixmap.size == *size
+        }) {
+            return Ok(index);
+        }
+
+        let pixmap = XCreatePixmap(display,
+                                   self.window,
+                                   size.width as u32,
+                                   size.height as u32,
+                                   self.depth as u32);
+        let egl_image_attribs = [
+            EGL_IMAGE_PRESERVED_KHR as EGLint,  egl::TRUE as EGLint,
+            egl::NONE as EGLint,                0,
+        ];
+        let egl_image = (EGL_EXTENSION_FUNCTIONS.CreateImageKHR)(egl_display,
+                                                                 egl::NO_CONTEXT,
+                                                                 EGL_NATIVE_PIXMAP_KHR,
+                                                                 pixmap as EGLClientBuffer,
+                                                                 egl_image_attribs.as_ptr());
+        if egl_image == EGL_NO_IMAGE_KHR {
+            let native_code = EGL_FUNCTIONS.with(|egl| egl.GetError());
+            XFreePixmap(display, pixmap);
+            let failure = WindowingApiFailure::new("eglCreateImageKHR",
+                                                   WindowingApiError::BadNativePixmap,
+                                                   Some(native_code));
+            return Err(Error::PresentFailed(Box::new(failure)));
+        }
+
+        let texture_object = surface::bind_egl_image_to_gl_texture(gl, egl_image, gl::TEXTURE_2D);
+        let mut old_framebuffer = 0;
+        gl.GetIntegerv(gl::FRAMEBUFFER_BINDING, &mut old_framebuffer);
+        let framebuffer_object =
+            gl_utils::create_and_bind_framebuffer(gl, gl::TEXTURE_2D, texture_object);
+        gl.BindFramebuffer(gl::FRAMEBUFFER, old_framebuffer as GLuint);
+
+        self.pixmaps.push(PresentedPixmap {
+            pixmap: pixmap as u32,
+            serial: 0,
+            idle: true,
+            size: *size,
+            copy_target: Some(CopyTarget { egl_image, texture_object, framebuffer_object }),
+        });
+        Ok(self.pixmaps.len() - 1)
+    }
+
+    unsafe fn handle_event(&mut self,
+                           xcb: &XCBFunctions,
+                           connection: *mut xcb_connection_t,
+                           event: *mut xcb_present_generic_event_t)
+                           -> Option<PresentEvent> {
+        let result = match (*event).evtype {
+            XCB_PRESENT_EVENT_COMPLETE_NOTIFY => {
+                let event = &*(event as *const xcb_present_complete_notify_event_t);
+                if event.kind == XCB_PRESENT_COMPLETE_KIND_PIXMAP {
+                    let mode = match event.mode {
+                        XCB_PRESENT_COMPLETE_MODE_FLIP => PresentMode::Flip,
+                        XCB_PRESENT_COMPLETE_MODE_SKIP => PresentMode::Skip,
+                        _ => PresentMode::Copy,
+                    };
+                    Some(PresentEvent::Complete {
+                        serial: event.serial,
+                        mode,
+                        msc: event.msc,
+                        ust: event.ust,
+                    })
+                } else {
+                    None
+                }
+            }
+            XCB_PRESENT_EVENT_IDLE_NOTIFY => {
+                let event = &*(event as *const xcb_present_idle_notify_event_t);
+                self.mark_idle(xcb, connection, event.pixmap, event.serial);
+                Some(PresentEvent::Idle { serial: event.serial })
+            }
+            _ => None,
+        };
+        libc::free(event as *mut c_void);
+        result
+    }
+
+    unsafe fn mark_idle(&mut self,
+                        xcb: &XCBFunctions,
+                        connection: *mut xcb_connection_t,
+                        pixmap: u32,
+                        serial: u32) {
+        let index = match self.pixmaps.iter().position(|presented_pixmap| {
+            presented_pixmap.pixmap == pixmap && presented_pixmap.serial == serial
+        }) {
+            Some(index) => index,
+            None => return,
+        };
+        if self.pixmaps[index].copy_target.is_some() {
+            self.pixmaps[index].idle = true;
+        } else {
+            let presented_pixmap = self.pixmaps.swap_remove(index);
+            (xcb.xcb_free_pixmap)(connection, presented_pixmap.pixmap);
+            (xcb.xcb_flush)(connection);
+        }
+    }
+}
+
+impl PresentedPixmap {
+    unsafe fn destroy(self,
+                      gl: &Gl,
+                      xcb: &XCBFunctions,
+                      connection: *mut xcb_connection_t,
+                      egl_display: EGLDisplay) {
+        if let Some(copy_target) = self.copy_target {
+            gl_utils::destroy_framebuffer(gl, copy_target.framebuffer_object);
+            gl.DeleteTextures(1, &copy_target.texture_object);
+            (EGL_EXTENSION_FUNCTIONS.DestroyImageKHR)(egl_display, copy_target.egl_image);
+        }
+        (xcb.xcb_free_pixmap)(connection, self.pixmap);
+    }
+}
+
+impl CopyTarget {
+    // X pixmaps are stored top row first, so `flip` turns an OpenGL image upright.
+    unsafe fn copy_from(&self,
+                        gl: &Gl,
+                        framebuffer_object: GLuint,
+                        size: &Size2D<i32>,
+                        flip: bool) {
+        let (dest_y0, dest_y1) = if flip { (size.height, 0) } else { (0, size.height) };
+        let (mut old_read_framebuffer, mut old_draw_framebuffer) = (0, 0);
+        gl.GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut old_read_framebuffer);
+        gl.GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut old_draw_framebuffer);
+        gl.BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer_object);
+        gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.framebuffer_object);
+        gl.BlitFramebuffer(0,
+                           0,
+                           size.width,
+                           size.height,
+                           0,
+                           dest_y0,
+                           size.width,
+                           dest_y1,
+                           gl::COLOR_BUFFER_BIT,
+                           gl::NEAREST);
+        gl.BindFramebuffer(gl::READ_FRAMEBUFFER, old_read_framebuffer as GLuint);
+        gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, old_draw_framebuffer as GLuint);
+        gl.Flush();
+    }
+}
+
+unsafe fn extension_present(xcb: &XCBFunctions,
+                            connection: *mut xcb_connection_t,
+                            extension: *mut xcb_extension_t)
+                            -> bool {
+    let reply = (xcb.xcb_get_extension_data)(connection, extension);
+    !reply.is_null() && (*reply).present != 0
+}
+
+unsafe fn query_dri3_version(xcb: &XCBFunctions, connection: *mut xcb_connection_t)
+                             -> Option<(u32, u32)> {
+    let dri3 = xcb.dri3.as_ref()?;
+    if !extension_present(xcb, connection, dri3.xcb_dri3_id) {
+        return None;
+    }
+
+    // Ask for the version that introduced modifiers; the server replies with what it has.
+    let cookie = (dri3.xcb_dri3_query_version)(connection, 1, 2);
+    let reply = (dri3.xcb_dri3_query_version_reply)(connection, cookie, ptr::null_mut());
+    if reply.is_null() {
+        return None;
+    }
+    let version = ((*reply).major_version, (*reply).minor_version);
+    libc::free(reply as *mut c_void);
+    Some(version)
+}
+
+unsafe fn request_succeeded(xcb: &XCBFunctions,
+                            connection: *mut xcb_connection_t,
+                            cookie: xcb_void_cookie_t)
+                            -> bool {
+    let error = (xcb.xcb_request_check)(connection, cookie);
+    if error.is_null() {
+        return true;
+    }
+    libc::free(error as *mut c_void);
+    false
+}
+
+unsafe fn close_planes(image: &DmaBufImage) {
+    for plane in &image.planes {
+        libc::close(plane.fd);
+    }
+}
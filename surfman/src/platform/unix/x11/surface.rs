@@ -4,27 +4,38 @@
 
 use crate::gl::types::{GLenum, GLuint};
 use crate::gl;
+use crate::ipc::SurfaceHandle;
+use crate::leak::Origin;
 use crate::platform::generic::egl::context;
-use crate::platform::generic::egl::surface::{EGLBackedSurface, EGLSurfaceTexture};
-use crate::{Error, SurfaceAccess, SurfaceInfo, SurfaceType};
+use crate::platform::generic::egl::surface::{self, EGLBackedSurface, EGLSurfaceTexture};
+use crate::{ColorSpace, DepthStencilAttributes, Error, SurfaceAccess, SurfaceFormat, SurfaceInfo};
+use crate::{SurfaceTextureTarget, SurfaceType};
+use crate::surface::layered_surface_size;
+use crate::video::PlanarImage;
+use crate::platform::generic::egl::wayland::{self, WaylandBufferInfo};
 use super::context::{Context, GL_FUNCTIONS};
 use super::device::Device;
+use super::pixmap::PixmapTracking;
 
-use euclid::default::Size2D;
-use std::marker::PhantomData;
+use euclid::default::{Rect, Size2D};
+use std::slice;
 use std::os::raw::c_void;
+use wayland_sys::server::wl_resource;
 use x11::xlib::{Window, XGetGeometry};
 
-// FIXME(pcwalton): Is this right, or should it be `TEXTURE_EXTERNAL_OES`?
+pub use crate::platform::generic::egl::surface::NativeImage;
+
+// Textures wrapping our own surfaces are plain 2D textures. Only imported images may need
+// `GL_TEXTURE_EXTERNAL_OES`.
 const SURFACE_GL_TEXTURE_TARGET: GLenum = gl::TEXTURE_2D;
 
 /// Represents a hardware buffer of pixels that can be rendered to via the CPU or GPU and either
 /// displayed in a native widget or bound to a texture for reading.
 /// 
 /// Surfaces come in two varieties: generic and widget surfaces. Generic surfaces can be bound to a
-/// texture but cannot be displayed in a widget (without using other APIs such as Core Animation,
-/// DirectComposition, or XPRESENT). Widget surfaces are the opposite: they can be displayed in a
-/// widget but not bound to a texture.
+/// texture but cannot be displayed in a widget, except through a `Presenter`, which shows them in
+/// a window with the X11 Present extension. Widget surfaces are the opposite: they can be
+/// displayed in a widget but not bound to a texture.
 /// 
 /// Surfaces are specific to a given context and cannot be rendered to from any context other than
 /// the one they were created with. However, they can be *read* from any context on any thread (as
@@ -33,7 +44,8 @@ const SURFACE_GL_TEXTURE_TARGET: GLenum = gl::TEXTURE_2D;
 /// 
 /// Depending on the platform, each surface may be internally double-buffered.
 /// 
-/// Surfaces must be destroyed with the `destroy_surface()` method, or a panic will occur.
+/// Surfaces should be destroyed with the `destroy_surface()` method. A surface that is dropped
+/// without being destroyed is handled according to the device's `LeakPolicy`.
 #[derive(Debug)]
 pub struct Surface(pub(crate) EGLBackedSurface);
 
@@ -44,10 +56,13 @@ pub struct Surface(pub(crate) EGLBackedSurface);
 /// framebuffer).
 /// 
 /// Surface textures are local to a context, but that context does not have to be the same context
-/// as that associated with the underlying surface. The texture must be destroyed with the
-/// `destroy_surface_texture()` method, or a panic will occur.
+/// as that associated with the underlying surface. The texture should be destroyed with the
+/// `destroy_surface_texture()` method. Dropping it instead leaks the texture object and drops its
+/// surface, which is handled according to the device's `LeakPolicy`.
 #[derive(Debug)]
-pub struct SurfaceTexture(pub(crate) EGLSurfaceTexture);
+pub struct SurfaceTexture(pub(crate) EGLSurfaceTexture,
+                          // Present if the texture wraps an X11 pixmap.
+                          pub(crate) Option<PixmapTracking>);
 
 /// A wrapper for a Wayland surface, with associated size.
 #[derive(Clone)]
@@ -64,39 +79,63 @@ impl Device {
     /// up in a `SurfaceTexture` for reading by other contexts.
     pub fn create_surface(&mut self,
                           context: &Context,
-                          _: SurfaceAccess,
+                          access: SurfaceAccess,
                           surface_type: SurfaceType<NativeWidget>)
                           -> Result<Surface, Error> {
         match surface_type {
-            SurfaceType::Generic { size } => self.create_generic_surface(context, &size),
-            SurfaceType::Widget { native_widget } => {
+            SurfaceType::Generic { size, format, depth_stencil } => {
+                self.create_generic_surface(context, &size, access, format, depth_stencil, 1)
+            }
+            SurfaceType::Layered { size, layers } => {
+                self.create_layered_surface(context, &size, access, layers)
+            }
+            SurfaceType::Widget { native_widget, color_space } => {
                 unsafe {
-                    self.create_window_surface(context, native_widget.window)
+                    self.create_window_surface(context, native_widget.window, color_space)
                 }
             }
         }
     }
 
-    fn create_generic_surface(&mut self, context: &Context, size: &Size2D<i32>)
+    fn create_generic_surface(&mut self,
+                              context: &Context,
+                              size: &Size2D<i32>,
+                              access: SurfaceAccess,
+                              format: SurfaceFormat,
+                              depth_stencil: Option<DepthStencilAttributes>,
+                              layers: u32)
                               -> Result<Surface, Error> {
+        if !self.supported_surface_formats(context).contains(&format) {
+            return Err(Error::UnsupportedSurfaceFormat);
+        }
+
         let _guard = self.temporarily_make_context_current(context)?;
         let context_descriptor = self.context_descriptor(context);
         let context_attributes = self.context_descriptor_attributes(&context_descriptor);
         GL_FUNCTIONS.with(|gl| {
-            Ok(Surface(EGLBackedSurface::new_generic(gl,
-                                                     self.native_connection.egl_display,
-                                                     context.0.egl_context,
-                                                     context.0.id,
-                                                     &context_attributes,
-                                                     size)))
+            EGLBackedSurface::new_generic(gl,
+                                          self.native_connection.egl_display,
+                                          context.0.egl_context,
+                                          context.0.id,
+                                          &context_attributes,
+                                          size,
+                                          access,
+                                          format,
+                                          depth_stencil,
+                                          layers,
+                                          Origin::new(&self.leak_tracker)).map(Surface)
         })
     }
 
-    unsafe fn create_window_surface(&mut self, context: &Context, mut x11_window: Window)
+    unsafe fn create_window_surface(&mut self,
+                                    context: &Context,
+                                    mut x11_window: Window,
+                                    color_space: Option<ColorSpace>)
                                     -> Result<Surface, Error> {
         let context_descriptor = self.context_descriptor(context);
         let egl_config = context::egl_config_from_id(self.native_connection.egl_display,
-                                                     context_descriptor.egl_config_id);
+                                                     context_descriptor.egl_config_id)
+            .map_err(|failure| Error::SurfaceCreationFailed(Box::new(failure)))?;
 
         let display_guard = self.native_connection.lock_display();
         let (mut root_window, mut x, mut y, mut width, mut height) = (0, 0, 0, 0, 0);
@@ -112,11 +151,69 @@ impl Device {
                      &mut depth);
         let size = Size2D::new(width as i32, height as i32);
 
-        Ok(Surface(EGLBackedSurface::new_window(self.native_connection.egl_display,
-                                                egl_config,
-                                                &mut x11_window as *mut Window as *mut c_void,
+        EGLBackedSurface::new_window(self.native_connection.egl_display,
+                                     egl_config,
+                                     &mut x11_window as *mut Window as *mut c_void,
+                                     context.0.id,
+                                     &size,
+                                     color_space,
+                                     Origin::new(&self.leak_tracker)).map(Surface)
+    }
+
+    // Backs the layers with an array texture where the context supports them and the CPU doesn't
+    // need access, and lays them out side by side in a generic surface otherwise.
+    fn create_layered_surface(&mut self,
+                              context: &Context,
+                              size: &Size2D<i32>,
+                              access: SurfaceAccess,
+                              layers: u32)
+                              -> Result<Surface, Error> {
+        let gl_info = self.gl_info(context)?;
+        if access.cpu_access_allowed() || !surface::supports_array_layers(&gl_info) {
+            let size = layered_surface_size(size, layers)?;
+            let format = SurfaceFormat::RGBA8;
+            return self.create_generic_surface(context, &size, access, format, None, layers);
+        }
+
+        let _guard = self.temporarily_make_context_current(context)?;
+        let context_descriptor = self.context_descriptor(context);
+        let context_attributes = self.context_descriptor_attributes(&context_descriptor);
+        GL_FUNCTIONS.with(|gl| {
+            EGLBackedSurface::new_layered(gl,
+                                          &gl_info,
+                                          self.native_connection.egl_display,
+                                          context.0.egl_context,
+                                          context.0.id,
+                                          &context_attributes,
+                                          size,
+                                          layers,
+                                          Origin::new(&self.leak_tracker)).map(Surface)
+        })
+    }
+
+    /// Adopts a texture or EGL image that another library created as a generic surface.
+    /// 
+    /// The surface renders to the native image directly, and can be bound to the context and
+    /// wrapped in a surface texture like any other generic surface. Destroying the surface leaves
+    /// the native image alone. A texture must belong to the supplied context.
+    /// 
+    /// Returns an `UnsupportedSurfaceFormat` error if the image can't be rendered to.
+    pub unsafe fn create_surface_from_native_image(&self,
+                                                   context: &Context,
+                                                   native_image: NativeImage)
+                                                   -> Result<Surface, Error> {
+        let _guard = self.temporarily_make_context_current(context)?;
+        let context_descriptor = self.context_descriptor(context);
+        let context_attributes = self.context_descriptor_attributes(&context_descriptor);
+        GL_FUNCTIONS.with(|gl| {
+            EGLBackedSurface::from_native_image(gl,
+                                                self.native_connection.egl_display,
+                                                context.0.egl_context,
                                                 context.0.id,
-                                                &size)))
+                                                &context_attributes,
+                                                native_image,
+                                                Origin::new(&self.leak_tracker)).map(Surface)
+        })
     }
 
     /// Creates a surface texture from an existing generic surface for use with the given context.
@@ -138,7 +235,7 @@ impl Device {
 
         GL_FUNCTIONS.with(|gl| {
             match surface.0.to_surface_texture(gl) {
-                Ok(surface_texture) => Ok(SurfaceTexture(surface_texture)),
+                Ok(surface_texture) => Ok(SurfaceTexture(surface_texture, None)),
                 Err((err, surface)) => Err((err, Surface(surface))),
             }
         })
@@ -149,8 +246,8 @@ impl Device {
     /// The supplied context must be the context the surface is associated with, or this returns
     /// an `IncompatibleSurface` error.
     /// 
-    /// You must explicitly call this method to dispose of a surface. Otherwise, a panic occurs in
-    /// the `drop` method.
+    /// You should explicitly call this method to dispose of a surface. Otherwise, the surface is
+    /// handled according to the device's `LeakPolicy` when it is dropped.
     pub fn destroy_surface(&self, context: &mut Context, surface: &mut Surface)
                            -> Result<(), Error> {
         GL_FUNCTIONS.with(|gl| {
@@ -165,14 +262,23 @@ impl Device {
     /// The supplied context must be the same context the surface texture was created with, or an
     /// `IncompatibleSurfaceTexture` error is returned.
     /// 
-    /// All surface textures must be explicitly destroyed with this function, or a panic will
-    /// occur.
+    /// All surface textures should be explicitly destroyed with this function. Otherwise, the
+    /// texture object is leaked and the surface is handled according to the device's `LeakPolicy`
+    /// when the surface texture is dropped.
     pub fn destroy_surface_texture(&self, context: &mut Context, surface_texture: SurfaceTexture)
                                    -> Result<Surface, (Error, SurfaceTexture)> {
-        match self.temporarily_make_context_current(context) {
-            Ok(_guard) => GL_FUNCTIONS.with(|gl| Ok(Surface(surface_texture.0.destroy(gl)))),
-            Err(err) => Err((err, surface_texture)),
+        let _guard = match self.temporarily_make_context_current(context) {
+            Ok(guard) => guard,
+            Err(err) => return Err((err, surface_texture)),
+        };
+
+        let SurfaceTexture(surface_texture, pixmap_tracking) = surface_texture;
+        if let Some(pixmap_tracking) = pixmap_tracking {
+            unsafe {
+                pixmap_tracking.destroy(self.native_connection.lock_display().display());
+            }
         }
+        GL_FUNCTIONS.with(|gl| Ok(Surface(surface_texture.destroy(gl))))
     }
 
     /// Displays the contents of a widget surface on screen.
@@ -193,6 +299,48 @@ impl Device {
         Err(Error::Unimplemented)
     }
 
+    /// Maps the pixel data of a generic surface for writing by the CPU.
+    /// 
+    /// The guard exposes fresh storage covering the whole surface in the surface's format, rows
+    /// in order from row 0. Its previous contents are undefined, so every byte should be written.
+    /// The pixels are streamed to the surface through a pixel buffer object when the guard is
+    /// dropped. Contexts that can't map pixel buffer objects, such as OpenGL ES 2.0 contexts,
+    /// return an `UnsupportedOnThisPlatform` error; use `upload_surface_data()` with them instead.
+    /// 
+    /// The surface must allow CPU access, or a `SurfaceDataInaccessible` error is returned. The
+    /// supplied context must match the context the surface was created with, or an
+    /// `IncompatibleSurface` error is returned.
+    pub fn lock_surface_data_for_writing<'s>(&'s self,
+                                             context: &'s Context,
+                                             surface: &'s mut Surface)
+                                             -> Result<SurfaceDataGuard<'s>, Error> {
+        let gl_info = self.gl_info(context)?;
+        let _guard = self.temporarily_make_context_current(context)?;
+        let (ptr, stride, len) = GL_FUNCTIONS.with(|gl| {
+            surface.0.map_data(gl, &gl_info, context.0.id)
+        })?;
+        Ok(SurfaceDataGuard { device: self, context, surface, stride, ptr, len })
+    }
+
+    /// Copies pixels from the CPU into a rectangle of a generic surface.
+    /// 
+    /// Uploads to surfaces created with `SurfaceAccess::GPUCPUWriteCombined` are streamed through
+    /// a pixel buffer object; other surfaces are updated synchronously.
+    pub fn upload_surface_data(&self,
+                               context: &Context,
+                               surface: &mut Surface,
+                               rect: &Rect<i32>,
+                               data: &[u8],
+                               stride: usize,
+                               format: SurfaceFormat)
+                               -> Result<(), Error> {
+        let gl_info = self.gl_info(context)?;
+        let _guard = self.temporarily_make_context_current(context)?;
+        GL_FUNCTIONS.with(|gl| {
+            surface.0.upload_data(gl, &gl_info, context.0.id, rect, data, stride, format)
+        })
+    }
+
     /// Returns the OpenGL texture target needed to read from this surface texture.
     /// 
     /// This will be `GL_TEXTURE_2D` or `GL_TEXTURE_RECTANGLE`, depending on platform.
@@ -201,6 +349,17 @@ impl Device {
         SURFACE_GL_TEXTURE_TARGET
     }
 
+    /// Returns the formats that generic surfaces created on this device with the given context may
+    /// have.
+    /// 
+    /// Formats beyond `RGBA8` depend on the version and extensions of the context.
+    pub fn supported_surface_formats(&self, context: &Context) -> Vec<SurfaceFormat> {
+        match self.gl_info(context) {
+            Ok(gl_info) => surface::supported_surface_formats(&gl_info),
+            Err(_) => vec![SurfaceFormat::RGBA8],
+        }
+    }
+
     /// Returns various information about the surface, including the framebuffer object needed to
     /// render to this surface.
     /// 
@@ -218,9 +377,180 @@ impl Device {
     pub fn surface_texture_object(&self, surface_texture: &SurfaceTexture) -> GLuint {
         surface_texture.0.texture_object
     }
+
+    /// Returns the OpenGL texture object containing the depth buffer of this surface texture's
+    /// surface, or `None` if the surface wasn't created with a sampleable depth buffer.
+    /// 
+    /// As with `surface_texture_object()`, it is only legal to read from this texture object.
+    #[inline]
+    pub fn surface_texture_depth_object(&self, surface_texture: &SurfaceTexture)
+                                        -> Option<GLuint> {
+        match surface_texture.0.depth_texture_object {
+            0 => None,
+            depth_texture_object => Some(depth_texture_object),
+        }
+    }
+
+    /// Returns the OpenGL texture object containing the given layer of the surface, or `None` if
+    /// the surface has no such layer.
+    /// 
+    /// Each layer of a surface backed by an array texture has a texture of its own, holding a
+    /// copy of the layer made when the surface was last unbound from its context. Otherwise, this
+    /// is `surface_texture_object()`.
+    #[inline]
+    pub fn surface_texture_layer_object(&self, surface_texture: &SurfaceTexture, layer: u32)
+                                        -> Option<GLuint> {
+        surface_texture.0.layer_object(layer)
+    }
+
+    /// Returns the region of `surface_texture_layer_object()` that holds the given layer of the
+    /// surface, in texels, or `None` if the surface has no such layer.
+    /// 
+    /// Surfaces that aren't layered have a single layer that covers the whole texture.
+    #[inline]
+    pub fn surface_texture_layer_rect(&self, surface_texture: &SurfaceTexture, layer: u32)
+                                      -> Option<Rect<i32>> {
+        surface_texture.0.layer_rect(layer)
+    }
+
+    /// Returns the OpenGL texture target that the surface texture's texture object must be bound
+    /// to.
+    /// 
+    /// This is `GL_TEXTURE_2D` unless the texture was created from a handle to an image that the
+    /// driver can only sample as `GL_TEXTURE_EXTERNAL_OES`.
+    #[inline]
+    pub fn surface_texture_target(&self, surface_texture: &SurfaceTexture) -> GLenum {
+        surface_texture.0.target
+    }
+
+    /// Exports a generic surface so that it can be sampled from in another process.
+    /// 
+    /// The dma-buf backing the surface is exported if the EGL implementation supports
+    /// `EGL_MESA_image_dma_buf_export`. Otherwise, the surface contents are copied into shared
+    /// memory, and the handle won't reflect subsequent rendering.
+    /// 
+    /// The supplied context must be the context the surface is associated with, or an
+    /// `IncompatibleSurface` error is returned.
+    pub fn export_surface(&self, context: &Context, surface: &Surface)
+                          -> Result<SurfaceHandle, Error> {
+        if context.0.id != surface.0.context_id {
+            return Err(Error::IncompatibleSurface);
+        }
+
+        let _guard = self.temporarily_make_context_current(context)?;
+        GL_FUNCTIONS.with(|gl| surface.0.export(gl, self.native_connection.egl_display))
+    }
+
+    /// Wraps a surface exported from another process in a surface texture local to the given
+    /// context.
+    /// 
+    /// Both dma-buf and shared memory handles are accepted. The surface that the returned texture
+    /// wraps can only be read from.
+    pub fn create_surface_texture_from_handle(&self,
+                                              context: &mut Context,
+                                              handle: SurfaceHandle,
+                                              target: SurfaceTextureTarget)
+                                              -> Result<SurfaceTexture, Error> {
+        let _guard = self.temporarily_make_context_current(context)?;
+        GL_FUNCTIONS.with(|gl| {
+            EGLSurfaceTexture::from_handle(gl,
+                                           self.native_connection.egl_display,
+                                           context.0.egl_context,
+                                           context.0.id,
+                                           &handle,
+                                           target,
+                                           Origin::new(&self.leak_tracker)).map(|surface_texture| {
+                SurfaceTexture(surface_texture, None)
+            })
+        })
+    }
+
+    /// Wraps a decoded video frame in a surface texture local to the given context.
+    /// 
+    /// The frame is imported as a dma-buf without a copy where the driver allows it; otherwise,
+    /// its planes are converted to RGBA on the CPU. The first row of the frame is row 0 of the
+    /// texture.
+    pub fn create_surface_texture_from_planes(&self,
+                                              context: &mut Context,
+                                              image: &PlanarImage,
+                                              target: SurfaceTextureTarget)
+                                              -> Result<SurfaceTexture, Error> {
+        let _guard = self.temporarily_make_context_current(context)?;
+        GL_FUNCTIONS.with(|gl| {
+            EGLSurfaceTexture::from_planes(gl,
+                                           self.native_connection.egl_display,
+                                           context.0.egl_context,
+                                           context.0.id,
+                                           image,
+                                           target,
+                                           Origin::new(&self.leak_tracker)).map(|surface_texture| {
+                SurfaceTexture(surface_texture, None)
+            })
+        })
+    }
+
+    /// Wraps one plane of a Wayland client buffer in a surface texture local to the given
+    /// context. See `WaylandBufferFormat` for which buffers can be sampled without a copy.
+    ///
+    /// The buffer must outlive the surface texture and the surface that destroying it returns.
+    pub unsafe fn create_surface_texture_from_wl_buffer(&self,
+                                                        context: &mut Context,
+                                                        buffer: *mut wl_resource,
+                                                        plane: u32,
+                                                        target: SurfaceTextureTarget)
+                                                        -> Result<SurfaceTexture, Error> {
+        let _guard = self.temporarily_make_context_current(context)?;
+        GL_FUNCTIONS.with(|gl| {
+            wayland::create_surface_texture_from_wl_buffer(gl,
+                                                           self.native_connection.egl_display,
+                                                           context.0.egl_context,
+                                                           context.0.id,
+                                                           buffer,
+                                                           plane,
+                                                           target,
+                                                           Origin::new(&self.leak_tracker))
+                .map(|surface_texture| SurfaceTexture(surface_texture, None))
+        })
+    }
+
+    /// Returns the size, layout, and orientation of a Wayland client buffer.
+    #[inline]
+    pub unsafe fn wl_buffer_info(&self, buffer: *mut wl_resource)
+                                 -> Result<WaylandBufferInfo, Error> {
+        wayland::query_wl_buffer(self.native_connection.egl_display, buffer)
+    }
 }
 
 /// Represents the CPU view of the pixel data of this surface.
 pub struct SurfaceDataGuard<'a> {
-    phantom: PhantomData<&'a ()>,
+    device: &'a Device,
+    context: &'a Context,
+    surface: &'a mut Surface,
+    stride: usize,
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl<'a> SurfaceDataGuard<'a> {
+    /// Returns the number of bytes per row of the surface.
+    #[inline]
+    pub fn stride(&self) -> usize { self.stride }
+
+    /// Returns a mutable slice of the pixel data in this surface, in the surface's format.
+    #[inline]
+    pub fn data(&mut self) -> &mut [u8] {
+        unsafe {
+            slice::from_raw_parts_mut(self.ptr, self.len)
+        }
+    }
+}
+
+impl<'a> Drop for SurfaceDataGuard<'a> {
+    fn drop(&mut self) {
+        // If the context can't be made current, the buffer stays mapped until the surface is
+        // destroyed.
+        if let Ok(_guard) = self.device.temporarily_make_context_current(self.context) {
+            GL_FUNCTIONS.with(|gl| self.surface.0.unmap_data(gl));
+        }
+    }
 }
@@ -2,21 +2,25 @@
 //
 //! Wrapper for EGL contexts managed by ANGLE using Direct3D 11 as a backend on Windows.
 
-use crate::context::{CREATE_CONTEXT_MUTEX, ContextID};
+use crate::context::{CREATE_CONTEXT_MUTEX, ContextDescriptorDiagnostics, ContextID};
+use crate::context::choose_context_descriptor;
 use crate::egl::types::{EGLConfig, EGLContext, EGLint};
 use crate::egl;
+use crate::info::GLInfoCache;
+use crate::leak::{LeakedObject, Origin};
 use crate::platform::generic::egl::context::{self, CurrentContextGuard};
 use crate::platform::generic::egl::device::EGL_FUNCTIONS;
 use crate::platform::generic::egl::error::ToWindowingApiError;
 use crate::platform::generic::egl::surface::ExternalEGLSurfaces;
 use crate::surface::Framebuffer;
-use crate::{ContextAttributes, Error, Gl, SurfaceInfo};
+use crate::{ContextAttributes, Error, GLApi, GLInfo, Gl, PixelFormatAttributes, SurfaceInfo};
+use crate::WindowingApiFailure;
 use super::device::Device;
 use super::surface::{Surface, Win32Objects};
 
 use std::mem;
 use std::os::raw::c_void;
-use std::thread;
+use std::rc::Rc;
 use winapi::shared::winerror::S_OK;
 use winapi::um::winbase::INFINITE;
 
@@ -27,6 +31,13 @@ thread_local! {
     pub static GL_FUNCTIONS: Gl = Gl::load_with(context::get_proc_address);
 }
 
+// The config attributes that every context on this device needs. Surfaces are pbuffers bound
+// to textures, so configs must support that.
+const CONFIG_ATTRIBUTES: [EGLint; 4] = [
+    egl::BIND_TO_TEXTURE_RGBA as EGLint,    1 as EGLint,
+    egl::SURFACE_TYPE as EGLint,            egl::PBUFFER_BIT as EGLint,
+];
+
 /// Represents an OpenGL rendering context.
 /// 
 /// A context allows you to issue rendering commands to a surface. When initially created, a
@@ -42,19 +53,21 @@ thread_local! {
 /// OpenGL objects may not be shared across contexts directly, but surface textures effectively
 /// allow for sharing of texture data. Contexts are local to a single thread and device.
 /// 
-/// A context must be explicitly destroyed with `destroy_context()`, or a panic will occur.
+/// A context should be explicitly destroyed with `destroy_context()`. A context that is dropped
+/// without being destroyed is handled according to the device's `LeakPolicy`.
 pub struct Context {
     pub(crate) egl_context: EGLContext,
     pub(crate) id: ContextID,
+    gl_info: GLInfoCache,
     framebuffer: Framebuffer<Surface, ExternalEGLSurfaces>,
     context_is_owned: bool,
+    origin: Origin,
 }
 
 impl Drop for Context {
-    #[inline]
     fn drop(&mut self) {
-        if self.egl_context != egl::NO_CONTEXT && !thread::panicking() {
-            panic!("Contexts must be destroyed explicitly with `destroy_context`!")
+        if self.egl_context != egl::NO_CONTEXT {
+            self.origin.report_leak(LeakedObject::Context, false);
         }
     }
 }
@@ -67,11 +80,53 @@ impl Device {
     pub fn create_context_descriptor(&self, attributes: &ContextAttributes)
                                      -> Result<ContextDescriptor, Error> {
         unsafe {
-            ContextDescriptor::new(self.egl_display, attributes, &[
-                egl::BIND_TO_TEXTURE_RGBA as EGLint,    1 as EGLint,
-                egl::SURFACE_TYPE as EGLint,            egl::PBUFFER_BIT as EGLint,
-                egl::RENDERABLE_TYPE as EGLint,         egl::OPENGL_ES2_BIT as EGLint,
-            ])
+            ContextDescriptor::new(self.egl_display, attributes, self.gl_api(), &CONFIG_ATTRIBUTES)
+        }
+    }
+
+    /// Returns a context descriptor for every pixel format that this device can create contexts
+    /// with, along with the attributes of each pixel format.
+    ///
+    /// The version and profile of the descriptors come from `attributes`. The `ALPHA`, `DEPTH`,
+    /// and `STENCIL` flags are ignored; use the pixel format attributes to tell the descriptors
+    /// apart.
+    pub fn enumerate_context_descriptors(&self, attributes: &ContextAttributes)
+                                         -> Result<Vec<(ContextDescriptor, PixelFormatAttributes)>,
+                                                   Error> {
+        unsafe {
+            ContextDescriptor::enumerate(self.egl_display,
+                                         attributes,
+                                         self.gl_api(),
+                                         &CONFIG_ATTRIBUTES)
+        }
+    }
+
+    /// Creates a context descriptor from one of the pixel formats that
+    /// `enumerate_context_descriptors()` returns.
+    ///
+    /// `choose` receives the attributes of every candidate pixel format and returns the index of
+    /// the one to use. If it returns `None` or an index out of range, a `NoPixelFormatFound` error
+    /// is returned.
+    pub fn create_context_descriptor_with<F>(&self, attributes: &ContextAttributes, choose: F)
+                                             -> Result<ContextDescriptor, Error>
+                                             where F: FnOnce(&[PixelFormatAttributes]) ->
+                                                   Option<usize> {
+        let candidates = self.enumerate_context_descriptors(attributes)?;
+        choose_context_descriptor(candidates, choose)
+    }
+
+    /// Explains which pixel formats `create_context_descriptor()` passes over for the given
+    /// attributes, and why.
+    ///
+    /// This is meant for finding out why `create_context_descriptor()` returned
+    /// `NoPixelFormatFound` or `UnsupportedGLProfile`.
+    pub fn diagnose_context_descriptor(&self, attributes: &ContextAttributes)
+                                       -> ContextDescriptorDiagnostics {
+        unsafe {
+            ContextDescriptor::diagnose(self.egl_display,
+                                        attributes,
+                                        self.gl_api(),
+                                        &CONFIG_ATTRIBUTES)
         }
     }
 
@@ -82,15 +137,15 @@ impl Device {
     pub fn create_context(&mut self, descriptor: &ContextDescriptor) -> Result<Context, Error> {
         let mut next_context_id = CREATE_CONTEXT_MUTEX.lock().unwrap();
         unsafe {
-            let egl_context = context::create_context(self.egl_display,
-                                                      descriptor,
-                                                      self.gl_api())?;
+            let egl_context = context::create_context(self.egl_display, descriptor)?;
 
             let context = Context {
                 egl_context,
                 id: *next_context_id,
+                gl_info: GLInfoCache::default(),
                 framebuffer: Framebuffer::None,
                 context_is_owned: true,
+                origin: Origin::new(&self.leak_tracker),
             };
             next_context_id.0 += 1;
             Ok(context)
@@ -110,11 +165,13 @@ impl Device {
         let context = Context {
             egl_context: native_context.egl_context,
             id: *next_context_id,
+            gl_info: GLInfoCache::default(),
             framebuffer: Framebuffer::External(ExternalEGLSurfaces {
                 draw: native_context.egl_draw_surface,
                 read: native_context.egl_read_surface,
             }),
             context_is_owned: false,
+            origin: Origin::new(&self.leak_tracker),
         };
         next_context_id.0 += 1;
 
@@ -179,8 +236,8 @@ impl Device {
                                              egl_read_surface,
                                              context.egl_context);
                 if result == egl::FALSE {
-                    let err = egl.GetError().to_windowing_api_error();
-                    return Err(Error::MakeCurrentFailed(err));
+                    let err = egl.GetError().to_windowing_api_failure("eglMakeCurrent");
+                    return Err(Error::MakeCurrentFailed(Box::new(err)));
                 }
                 Ok(())
             })
@@ -221,6 +278,26 @@ impl Device {
         }
     }
 
+    /// Returns the OpenGL API flavor (OpenGL or OpenGL ES) that the context was created for.
+    #[inline]
+    pub fn context_gl_api(&self, context: &Context) -> GLApi {
+        unsafe {
+            context::context_gl_api(self.egl_display, context.egl_context)
+        }
+    }
+
+    /// Returns what the OpenGL implementation reports about the context: its version and
+    /// profile, the vendor and renderer, and the extensions it supports.
+    ///
+    /// The context is queried the first time this is called, and the same information is
+    /// returned after that.
+    pub fn gl_info(&self, context: &Context) -> Result<Rc<GLInfo>, Error> {
+        context.gl_info.get_or_query(|| {
+            let _guard = self.temporarily_make_context_current(context)?;
+            GL_FUNCTIONS.with(GLInfo::current)
+        })
+    }
+
     /// Fetches the address of an OpenGL function associated with this context.
     /// 
     /// OpenGL functions are local to a context. You should not use OpenGL functions on one context
@@ -235,7 +312,7 @@ impl Device {
 
     #[inline]
     pub(crate) fn context_descriptor_to_egl_config(&self, context_descriptor: &ContextDescriptor)
-                                                   -> EGLConfig {
+                                                   -> Result<EGLConfig, WindowingApiFailure> {
         unsafe {
             context::egl_config_from_id(self.egl_display,
                                         context_descriptor.egl_config_id)
@@ -254,7 +331,8 @@ impl Device {
     /// If an error is returned, the surface is returned alongside it.
     pub fn bind_surface_to_context(&self, context: &mut Context, surface: Surface)
                                    -> Result<(), (Error, Surface)> {
-        if context.id != surface.context_id {
+        // Imported surfaces have no EGL surface of their own and can't be rendered to.
+        if context.id != surface.context_id || surface.egl_surface == egl::NO_SURFACE {
             return Err((Error::IncompatibleSurface, surface));
         }
 
@@ -4,16 +4,19 @@
 
 use crate::egl::types::{EGLAttrib, EGLDisplay, EGLint};
 use crate::egl;
-use crate::platform::generic::egl::device::EGL_FUNCTIONS;
+use crate::platform::generic::egl::device::{EGL_FUNCTIONS, display_extensions};
+use crate::platform::generic::egl::device::extensions_support_damage_present;
 use crate::platform::generic::egl::ffi::{EGL_D3D11_DEVICE_ANGLE, EGL_EXTENSION_FUNCTIONS};
 use crate::platform::generic::egl::ffi::{EGL_NO_DEVICE_EXT, EGL_PLATFORM_DEVICE_EXT};
-use crate::{Error, GLApi};
+use crate::leak::LeakTracker;
+use crate::{DeviceCapabilities, Error, GLApi, LeakPolicy};
 use super::connection::Connection;
 
 use std::cell::{RefCell, RefMut};
 use std::mem;
 use std::os::raw::c_void;
 use std::ptr;
+use std::sync::Arc;
 use winapi::Interface;
 use winapi::shared::dxgi::{self, IDXGIAdapter, IDXGIDevice, IDXGIFactory1};
 use winapi::shared::minwindef::UINT;
@@ -22,6 +25,9 @@ use winapi::um::d3d11::{D3D11CreateDevice, D3D11_SDK_VERSION, ID3D11Device};
 use winapi::um::d3dcommon::{D3D_DRIVER_TYPE, D3D_FEATURE_LEVEL_9_3};
 use wio::com::ComPtr;
 
+// UWP apps have no HWNDs to create window surfaces for.
+const WIDGET_SURFACES: bool = cfg!(not(target_vendor = "uwp"));
+
 thread_local! {
     static DXGI_FACTORY: RefCell<Option<ComPtr<IDXGIFactory1>>> = RefCell::new(None);
 }
@@ -45,6 +51,7 @@ pub struct Device {
     pub(crate) d3d11_device: ComPtr<ID3D11Device>,
     pub(crate) d3d_driver_type: D3D_DRIVER_TYPE,
     pub(crate) display_is_owned: bool,
+    pub(crate) leak_tracker: Arc<LeakTracker>,
 }
 
 pub(crate) enum VendorPreference {
@@ -98,7 +105,10 @@ impl Adapter {
 
                 let mut adapter_desc = mem::zeroed();
                 let result = (*dxgi_adapter_1).GetDesc1(&mut adapter_desc);
-                assert_eq!(result, S_OK);
+                if result != S_OK {
+                    adapter_index += 1;
+                    continue;
+                }
 
                 let choose_this = match vendor_preference {
                     VendorPreference::Prefer(vendor_id) => vendor_id == adapter_desc.VendorId,
@@ -110,7 +120,9 @@ impl Adapter {
                     let result = (*dxgi_adapter_1).QueryInterface(
                         &IDXGIAdapter::uuidof(),
                         &mut dxgi_adapter as *mut *mut IDXGIAdapter as *mut *mut c_void);
-                    assert_eq!(result, S_OK);
+                    if result != S_OK {
+                        return Err(Error::NoAdapterFound);
+                    }
                     let dxgi_adapter = ComPtr::from_raw(dxgi_adapter);
 
                     return Ok(Adapter { dxgi_adapter, d3d_driver_type });
@@ -132,7 +144,9 @@ impl Adapter {
             let result = (*dxgi_adapter_1).QueryInterface(
                 &IDXGIAdapter::uuidof(),
                 &mut dxgi_adapter as *mut *mut IDXGIAdapter as *mut *mut c_void);
-            assert!(winerror::SUCCEEDED(result));
+            if !winerror::SUCCEEDED(result) {
+                return Err(Error::NoAdapterFound);
+            }
             let dxgi_adapter = ComPtr::from_raw(dxgi_adapter);
 
             Ok(Adapter { dxgi_adapter, d3d_driver_type })
@@ -164,32 +178,38 @@ impl Device {
             debug_assert!(d3d11_feature_level >= D3D_FEATURE_LEVEL_9_3);
             let d3d11_device = ComPtr::from_raw(d3d11_device);
 
-            let eglCreateDeviceANGLE =
-                EGL_EXTENSION_FUNCTIONS.CreateDeviceANGLE
-                                       .expect("Where's the `EGL_ANGLE_device_creation` \
-                                                extension?");
+            let eglCreateDeviceANGLE = match EGL_EXTENSION_FUNCTIONS.CreateDeviceANGLE {
+                None => return Err(Error::RequiredExtensionUnavailable),
+                Some(eglCreateDeviceANGLE) => eglCreateDeviceANGLE,
+            };
             let egl_device = eglCreateDeviceANGLE(EGL_D3D11_DEVICE_ANGLE as EGLint,
                                                   d3d11_device.as_raw() as *mut c_void,
                                                   ptr::null_mut());
-            assert_ne!(egl_device, EGL_NO_DEVICE_EXT);
+            if egl_device == EGL_NO_DEVICE_EXT {
+                return Err(Error::DeviceOpenFailed);
+            }
 
             EGL_FUNCTIONS.with(|egl| {
                 let attribs = [egl::NONE as EGLAttrib, egl::NONE as EGLAttrib, 0, 0];
                 let egl_display = egl.GetPlatformDisplay(EGL_PLATFORM_DEVICE_EXT,
                                                          egl_device as *mut c_void,
                                                          &attribs[0]);
-                assert_ne!(egl_display, egl::NO_DISPLAY);
+                if egl_display == egl::NO_DISPLAY {
+                    return Err(Error::DeviceOpenFailed);
+                }
 
-                // I don't think this should ever fail.
                 let (mut major_version, mut minor_version) = (0, 0);
                 let result = egl.Initialize(egl_display, &mut major_version, &mut minor_version);
-                assert_ne!(result, egl::FALSE);
+                if result == egl::FALSE {
+                    return Err(Error::DeviceOpenFailed);
+                }
 
                 Ok(Device {
                     egl_display,
                     d3d11_device,
                     d3d_driver_type,
                     display_is_owned: true,
+                    leak_tracker: LeakTracker::new(),
                 })
             })
         }
@@ -203,6 +223,7 @@ impl Device {
                 d3d11_device: ComPtr::from_raw(native_device.d3d11_device),
                 d3d_driver_type: native_device.d3d_driver_type,
                 display_is_owned: false,
+                leak_tracker: LeakTracker::new(),
             })
         }
     }
@@ -244,11 +265,40 @@ impl Device {
         }
     }
 
-    /// Returns the OpenGL API flavor that this device supports (OpenGL or OpenGL ES).
+    /// Returns the OpenGL API flavor that contexts on this device use by default (OpenGL or
+    /// OpenGL ES).
     #[inline]
     pub fn gl_api(&self) -> GLApi {
         GLApi::GLES
     }
+
+    /// Returns the optional features that this device supports.
+    pub fn capabilities(&self) -> DeviceCapabilities {
+        let platform_extensions = unsafe { display_extensions(self.egl_display) };
+        DeviceCapabilities {
+            widget_surfaces: WIDGET_SURFACES,
+            cpu_surface_reads: false,
+            cpu_surface_uploads: false,
+            cross_device_surface_textures: true,
+            damage_present: WIDGET_SURFACES &&
+                extensions_support_damage_present(&platform_extensions),
+            surface_texture_target: self.surface_gl_texture_target(),
+            platform_extensions,
+        }
+    }
+
+    /// Sets what happens to contexts and surfaces created with this device that are dropped
+    /// without being destroyed, overriding the global leak policy.
+    #[inline]
+    pub fn set_leak_policy(&self, policy: LeakPolicy) {
+        self.leak_tracker.set_policy(policy)
+    }
+
+    /// Returns the leak policy that this device follows.
+    #[inline]
+    pub fn leak_policy(&self) -> LeakPolicy {
+        self.leak_tracker.policy()
+    }
 }
 
 impl Drop for Device {
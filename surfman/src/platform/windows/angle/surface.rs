@@ -7,21 +7,27 @@ use crate::egl::types::EGLSurface;
 use crate::egl::{self, EGLint};
 use crate::gl::types::{GLenum, GLint, GLuint};
 use crate::gl;
+use crate::ipc::SurfaceHandle;
+use crate::leak::{LeakedObject, Origin};
 use crate::platform::generic::egl::device::EGL_FUNCTIONS;
 use crate::platform::generic::egl::error::ToWindowingApiError;
 use crate::platform::generic::egl::ffi::EGL_D3D_TEXTURE_2D_SHARE_HANDLE_ANGLE;
 use crate::platform::generic::egl::ffi::EGL_DXGI_KEYED_MUTEX_ANGLE;
 use crate::platform::generic::egl::ffi::EGL_EXTENSION_FUNCTIONS;
-use crate::{Error, SurfaceAccess, SurfaceID, SurfaceInfo, SurfaceType};
+use crate::platform::generic::egl::ffi::EGL_GL_COLORSPACE_KHR;
+use crate::platform::generic::egl::surface::egl_color_space;
+use crate::{ColorSpace, Error, SurfaceAccess, SurfaceFormat, SurfaceID, SurfaceInfo};
+use crate::{LayerStorage, SurfaceTextureTarget, SurfaceType};
+use crate::surface::{layer_rect, layered_surface_size};
+use crate::{WindowingApiError, WindowingApiFailure};
 use super::context::{Context, ContextDescriptor, GL_FUNCTIONS};
 use super::device::Device;
 
-use euclid::default::Size2D;
+use euclid::default::{Rect, Size2D};
 use std::fmt::{self, Debug, Formatter};
 use std::marker::PhantomData;
 use std::os::raw::c_void;
 use std::ptr;
-use std::thread;
 use winapi::shared::dxgi::IDXGIKeyedMutex;
 use winapi::shared::windef::{HWND, RECT};
 use winapi::shared::winerror::S_OK;
@@ -48,13 +54,17 @@ const SURFACE_GL_TEXTURE_TARGET: GLenum = gl::TEXTURE_2D;
 /// 
 /// Depending on the platform, each surface may be internally double-buffered.
 /// 
-/// Surfaces must be destroyed with the `destroy_surface()` method, or a panic will occur.
+/// Surfaces should be destroyed with the `destroy_surface()` method. A surface that is dropped
+/// without being destroyed is handled according to the device's `LeakPolicy`.
 pub struct Surface {
     pub(crate) egl_surface: EGLSurface,
     pub(crate) size: Size2D<i32>,
+    // The number of layers laid out side by side in the surface.
+    pub(crate) layers: u32,
     pub(crate) context_id: ContextID,
     pub(crate) context_descriptor: ContextDescriptor,
     pub(crate) win32_objects: Win32Objects,
+    origin: Origin,
 }
 
 /// Represents an OpenGL texture that wraps a surface.
@@ -64,8 +74,9 @@ pub struct Surface {
 /// framebuffer).
 /// 
 /// Surface textures are local to a context, but that context does not have to be the same context
-/// as that associated with the underlying surface. The texture must be destroyed with the
-/// `destroy_surface_texture()` method, or a panic will occur.
+/// as that associated with the underlying surface. The texture should be destroyed with the
+/// `destroy_surface_texture()` method. Dropping it instead leaks the texture object and drops its
+/// surface, which is handled according to the device's `LeakPolicy`.
 pub struct SurfaceTexture {
     pub(crate) surface: Surface,
     pub(crate) local_egl_surface: EGLSurface,
@@ -84,8 +95,8 @@ impl Debug for Surface {
 
 impl Drop for Surface {
     fn drop(&mut self) {
-        if self.egl_surface != egl::NO_SURFACE && !thread::panicking() {
-            panic!("Should have destroyed the surface first with `destroy_surface()`!")
+        if self.egl_surface != egl::NO_SURFACE {
+            self.origin.report_leak(LeakedObject::Surface, false);
         }
     }
 }
@@ -117,6 +128,14 @@ pub struct NativeWidget {
 #[cfg(target_vendor = "uwp")]
 pub struct NativeWidget;
 
+/// A Direct3D 11 texture created outside `surfman`, identified by its DXGI share handle.
+pub struct NativeImage {
+    /// The share handle of the texture.
+    pub share_handle: HANDLE,
+    /// The size of the texture.
+    pub size: Size2D<i32>,
+}
+
 impl Device {
     /// Creates either a generic or a widget surface, depending on the supplied surface type.
     /// 
@@ -128,10 +147,23 @@ impl Device {
                           surface_type: SurfaceType<NativeWidget>)
                           -> Result<Surface, Error> {
         match surface_type {
-            SurfaceType::Generic { ref size } => self.create_pbuffer_surface(context, size, None),
+            SurfaceType::Generic { size, format: SurfaceFormat::RGBA8, depth_stencil: None } => {
+                self.create_pbuffer_surface(context, &size, None)
+            }
+            // Pbuffers get their depth and stencil buffers from the context's config.
+            SurfaceType::Generic { format: SurfaceFormat::RGBA8, .. } => {
+                Err(Error::UnsupportedDepthStencilFormat)
+            }
+            SurfaceType::Generic { .. } => Err(Error::UnsupportedSurfaceFormat),
+            SurfaceType::Layered { size, layers } => {
+                let size = layered_surface_size(&size, layers)?;
+                let mut surface = self.create_pbuffer_surface(context, &size, None)?;
+                surface.layers = layers;
+                Ok(surface)
+            }
             #[cfg(not(target_vendor = "uwp"))]
-            SurfaceType::Widget { ref native_widget } => {
-                self.create_window_surface(context, native_widget)
+            SurfaceType::Widget { ref native_widget, color_space } => {
+                self.create_window_surface(context, native_widget, color_space)
             }
             #[cfg(target_vendor = "uwp")]
             SurfaceType::Widget { .. } => Err(Error::UnsupportedOnThisPlatform)
@@ -145,7 +177,8 @@ impl Device {
                               share_handle: Option<HANDLE>)
                               -> Result<Surface, Error> {
         let context_descriptor = self.context_descriptor(context);
-        let egl_config = self.context_descriptor_to_egl_config(&context_descriptor);
+        let egl_config = self.context_descriptor_to_egl_config(&context_descriptor)
+                             .map_err(|failure| Error::SurfaceCreationFailed(Box::new(failure)))?;
 
         unsafe {
             let attributes = [
@@ -165,7 +198,11 @@ impl Device {
                                                           texture as *const _,
                                                           egl_config,
                                                           attributes.as_ptr());
-                    assert_ne!(surface, egl::NO_SURFACE);
+                    if surface == egl::NO_SURFACE {
+                        let failure = egl.GetError().to_windowing_api_failure(
+                            "eglCreatePbufferFromClientBuffer");
+                        return Err(Error::SurfaceCreationFailed(Box::new(failure)));
+                    }
                     surface
                 } else if share_handle.is_some() {
                     egl::NO_SURFACE
@@ -173,14 +210,22 @@ impl Device {
                     let surface = egl.CreatePbufferSurface(self.egl_display,
                                                            egl_config,
                                                            attributes.as_ptr());
-                    assert_ne!(surface, egl::NO_SURFACE);
+                    if surface == egl::NO_SURFACE {
+                        let failure =
+                            egl.GetError().to_windowing_api_failure("eglCreatePbufferSurface");
+                        return Err(Error::SurfaceCreationFailed(Box::new(failure)));
+                    }
                     surface
                 };
 
                 let eglQuerySurfacePointerANGLE =
-                    EGL_EXTENSION_FUNCTIONS.QuerySurfacePointerANGLE
-                                        .expect("Where's the `EGL_ANGLE_query_surface_pointer` \
-                                                 extension?");
+                    match EGL_EXTENSION_FUNCTIONS.QuerySurfacePointerANGLE {
+                        Some(eglQuerySurfacePointerANGLE) => eglQuerySurfacePointerANGLE,
+                        None => {
+                            egl.DestroySurface(self.egl_display, egl_surface);
+                            return Err(Error::RequiredExtensionUnavailable);
+                        }
+                    };
 
                 let share_handle = if let Some(share_handle) = share_handle {
                     share_handle
@@ -191,8 +236,12 @@ impl Device {
                                                     egl_surface,
                                                     EGL_D3D_TEXTURE_2D_SHARE_HANDLE_ANGLE as EGLint,
                                                     &mut share_handle);
-                    assert_ne!(result, egl::FALSE);
-                    assert_ne!(share_handle, INVALID_HANDLE_VALUE);
+                    if result == egl::FALSE || share_handle == INVALID_HANDLE_VALUE {
+                        let failure = egl.GetError().to_windowing_api_failure(
+                            "eglQuerySurfacePointerANGLE");
+                        egl.DestroySurface(self.egl_display, egl_surface);
+                        return Err(Error::SurfaceCreationFailed(Box::new(failure)));
+                    }
                     share_handle
                 };
 
@@ -215,8 +264,10 @@ impl Device {
                 Ok(Surface {
                     egl_surface,
                     size: *size,
+                    layers: 1,
                     context_id: context.id,
                     context_descriptor,
+                    origin: Origin::new(&self.leak_tracker),
                     win32_objects: Win32Objects::Pbuffer {
                         share_handle,
                         keyed_mutex
@@ -227,29 +278,47 @@ impl Device {
     }
 
     #[cfg(not(target_vendor = "uwp"))]
-    fn create_window_surface(&mut self, context: &Context, native_widget: &NativeWidget)
-                              -> Result<Surface, Error> {
+    fn create_window_surface(&mut self,
+                             context: &Context,
+                             native_widget: &NativeWidget,
+                             color_space: Option<ColorSpace>)
+                             -> Result<Surface, Error> {
         let context_descriptor = self.context_descriptor(context);
-        let egl_config = self.context_descriptor_to_egl_config(&context_descriptor);
+        let egl_config = self.context_descriptor_to_egl_config(&context_descriptor)
+                             .map_err(|failure| Error::SurfaceCreationFailed(Box::new(failure)))?;
 
         unsafe {
             let mut rect = RECT { left: 0, top: 0, right: 0, bottom: 0 };
             let ok = winuser::GetWindowRect(native_widget.window_handle, &mut rect);
-            assert_ne!(ok, 0);
+            if ok == 0 {
+                return Err(Error::InvalidNativeWidget);
+            }
+
+            let mut attributes = vec![];
+            if let Some(color_space) = egl_color_space(self.egl_display, color_space)? {
+                attributes.push(EGL_GL_COLORSPACE_KHR as EGLint);
+                attributes.push(color_space as EGLint);
+            }
+            attributes.push(egl::NONE as EGLint);
 
             EGL_FUNCTIONS.with(|egl| {
-                let attributes = [egl::NONE as EGLint];
                 let egl_surface = egl.CreateWindowSurface(self.egl_display,
                                                           egl_config,
                                                           native_widget.window_handle as _,
                                                           attributes.as_ptr());
-                assert_ne!(egl_surface, egl::NO_SURFACE);
+                if egl_surface == egl::NO_SURFACE {
+                    let failure =
+                        egl.GetError().to_windowing_api_failure("eglCreateWindowSurface");
+                    return Err(Error::SurfaceCreationFailed(Box::new(failure)));
+                }
 
                 Ok(Surface {
                     egl_surface,
                     size: Size2D::new(rect.right - rect.left, rect.bottom - rect.top),
+                    layers: 1,
                     context_id: context.id,
                     context_descriptor,
+                    origin: Origin::new(&self.leak_tracker),
                     win32_objects: Win32Objects::Window,
                 })
             })
@@ -257,11 +326,22 @@ impl Device {
     }
 
     #[cfg(target_vendor = "uwp")]
-    fn create_window_surface(&mut self, context: &Context, native_widget: &NativeWidget)
-                              -> Result<Surface, Error> {
+    fn create_window_surface(&mut self,
+                             context: &Context,
+                             native_widget: &NativeWidget,
+                             color_space: Option<ColorSpace>)
+                             -> Result<Surface, Error> {
         Err(Error::UnsupportedOnThisPlatform)
     }
 
+    /// Adopts a Direct3D texture that another library created as a generic surface.
+    /// 
+    /// This isn't implemented on Windows yet, so it always returns an `Unimplemented` error.
+    pub unsafe fn create_surface_from_native_image(&self, _: &Context, _: NativeImage)
+                                                   -> Result<Surface, Error> {
+        Err(Error::Unimplemented)
+    }
+
     /// Creates a surface texture from an existing generic surface for use with the given context.
     /// 
     /// The surface texture is local to the supplied context and takes ownership of the surface.
@@ -280,7 +360,13 @@ impl Device {
             Win32Objects::Pbuffer { share_handle, .. } => share_handle,
         };
 
-        let local_egl_config = self.context_descriptor_to_egl_config(&surface.context_descriptor);
+        let local_egl_config =
+            match self.context_descriptor_to_egl_config(&surface.context_descriptor) {
+                Ok(local_egl_config) => local_egl_config,
+                Err(failure) => {
+                    return Err((Error::SurfaceImportFailed(Box::new(failure)), surface));
+                }
+            };
         EGL_FUNCTIONS.with(|egl| {
             unsafe {
                 // First, create an EGL surface local to this thread.
@@ -300,24 +386,35 @@ impl Device {
                                                       local_egl_config,
                                                       pbuffer_attributes.as_ptr());
                 if local_egl_surface == egl::NO_SURFACE {
-                    let windowing_api_error = egl.GetError().to_windowing_api_error();
-                    return Err((Error::SurfaceImportFailed(windowing_api_error), surface));
+                    let failure = egl.GetError()
+                                     .to_windowing_api_failure("eglCreatePbufferFromClientBuffer");
+                    return Err((Error::SurfaceImportFailed(Box::new(failure)), surface));
                 }
 
                 let mut local_keyed_mutex: *mut IDXGIKeyedMutex = ptr::null_mut();
-                let eglQuerySurfacePointerANGLE = EGL_EXTENSION_FUNCTIONS.QuerySurfacePointerANGLE
-                                                                         .unwrap();
-                let result = eglQuerySurfacePointerANGLE(
-                    self.egl_display,
-                    local_egl_surface,
-                    EGL_DXGI_KEYED_MUTEX_ANGLE as EGLint,
-                    &mut local_keyed_mutex as *mut *mut IDXGIKeyedMutex as *mut *mut c_void);
+                let result = match EGL_EXTENSION_FUNCTIONS.QuerySurfacePointerANGLE {
+                    None => egl::FALSE,
+                    Some(eglQuerySurfacePointerANGLE) => {
+                        eglQuerySurfacePointerANGLE(
+                            self.egl_display,
+                            local_egl_surface,
+                            EGL_DXGI_KEYED_MUTEX_ANGLE as EGLint,
+                            &mut local_keyed_mutex as *mut *mut IDXGIKeyedMutex as *mut *mut c_void)
+                    }
+                };
                 let local_keyed_mutex = if result != egl::FALSE && !local_keyed_mutex.is_null() {
                     let local_keyed_mutex = ComPtr::from_raw(local_keyed_mutex);
                     local_keyed_mutex.AddRef();
 
                     let result = local_keyed_mutex.AcquireSync(0, INFINITE);
-                    assert_eq!(result, S_OK);
+                    if result != S_OK {
+                        egl.DestroySurface(self.egl_display, local_egl_surface);
+                        let failure = WindowingApiFailure::new("IDXGIKeyedMutex::AcquireSync",
+                                                               WindowingApiError::Failed,
+                                                               Some(result));
+                        let error = Error::SurfaceTextureCreationFailed(Box::new(failure));
+                        return Err((error, surface));
+                    }
 
                     Some(local_keyed_mutex)
                 } else {
@@ -336,9 +433,15 @@ impl Device {
                     if egl.BindTexImage(self.egl_display,
                                         local_egl_surface,
                                         egl::BACK_BUFFER as GLint) == egl::FALSE {
-                        let windowing_api_error = egl.GetError().to_windowing_api_error();
-                        return Err((Error::SurfaceTextureCreationFailed(windowing_api_error),
-                                    surface));
+                        let failure = egl.GetError().to_windowing_api_failure("eglBindTexImage");
+                        gl.BindTexture(gl::TEXTURE_2D, 0);
+                        gl.DeleteTextures(1, &texture);
+                        if let Some(ref local_keyed_mutex) = local_keyed_mutex {
+                            local_keyed_mutex.ReleaseSync(0);
+                        }
+                        egl.DestroySurface(self.egl_display, local_egl_surface);
+                        let error = Error::SurfaceTextureCreationFailed(Box::new(failure));
+                        return Err((error, surface));
                     }
 
                     // Initialize the texture, for convenience.
@@ -371,8 +474,8 @@ impl Device {
     /// The supplied context must be the context the surface is associated with, or this returns
     /// an `IncompatibleSurface` error.
     /// 
-    /// You must explicitly call this method to dispose of a surface. Otherwise, a panic occurs in
-    /// the `drop` method.
+    /// You should explicitly call this method to dispose of a surface. Otherwise, the surface is
+    /// handled according to the device's `LeakPolicy` when it is dropped.
     pub fn destroy_surface(&self, context: &mut Context, surface: &mut Surface)
                            -> Result<(), Error> {
         if context.id != surface.context_id {
@@ -399,8 +502,9 @@ impl Device {
     /// The supplied context must be the same context the surface texture was created with, or an
     /// `IncompatibleSurfaceTexture` error is returned.
     /// 
-    /// All surface textures must be explicitly destroyed with this function, or a panic will
-    /// occur.
+    /// All surface textures should be explicitly destroyed with this function. Otherwise, the
+    /// texture object is leaked and the surface is handled according to the device's `LeakPolicy`
+    /// when the surface texture is dropped.
     pub fn destroy_surface_texture(&self, _: &mut Context, mut surface_texture: SurfaceTexture)
                                    -> Result<Surface, (Error, SurfaceTexture)> {
         unsafe {
@@ -429,6 +533,15 @@ impl Device {
         SURFACE_GL_TEXTURE_TARGET
     }
 
+    /// Returns the formats that generic surfaces created on this device may have.
+    /// 
+    /// ANGLE pbuffers take their format from the context's EGL config, so only `RGBA8` is
+    /// available.
+    #[inline]
+    pub fn supported_surface_formats(&self, _: &Context) -> Vec<SurfaceFormat> {
+        vec![SurfaceFormat::RGBA8]
+    }
+
     /// Returns a pointer to the underlying surface data for reading or writing by the CPU.
     #[inline]
     pub fn lock_surface_data<'s>(&self, _surface: &'s mut Surface)
@@ -436,6 +549,19 @@ impl Device {
         Err(Error::Unimplemented)
     }
 
+    /// Copies pixels from the CPU into a rectangle of a generic surface.
+    #[inline]
+    pub fn upload_surface_data(&self,
+                               _: &Context,
+                               _: &mut Surface,
+                               _: &Rect<i32>,
+                               _: &[u8],
+                               _: usize,
+                               _: SurfaceFormat)
+                               -> Result<(), Error> {
+        Err(Error::Unimplemented)
+    }
+
     /// Displays the contents of a widget surface on screen.
     /// 
     /// Widget surfaces are internally double-buffered, so changes to them don't show up in their
@@ -471,6 +597,8 @@ impl Device {
             id: surface.id(),
             context_id: surface.context_id,
             framebuffer_object: 0,
+            layers: surface.layers,
+            layer_storage: LayerStorage::Atlas,
         }
     }
 
@@ -481,6 +609,99 @@ impl Device {
     pub fn surface_texture_object(&self, surface_texture: &SurfaceTexture) -> GLuint {
         surface_texture.gl_texture
     }
+
+    /// Returns the OpenGL texture object containing the depth buffer of this surface texture's
+    /// surface.
+    /// 
+    /// ANGLE surfaces can't have sampleable depth buffers, so this always returns `None`.
+    #[inline]
+    pub fn surface_texture_depth_object(&self, _: &SurfaceTexture) -> Option<GLuint> {
+        None
+    }
+
+    /// Returns the OpenGL texture object containing the given layer of the surface, or `None` if
+    /// the surface has no such layer.
+    /// 
+    /// Layers are laid out side by side in one texture on this platform, so this is
+    /// `surface_texture_object()` for every layer.
+    pub fn surface_texture_layer_object(&self, surface_texture: &SurfaceTexture, layer: u32)
+                                        -> Option<GLuint> {
+        self.surface_texture_layer_rect(surface_texture, layer)
+            .map(|_| self.surface_texture_object(surface_texture))
+    }
+
+    /// Returns the region of `surface_texture_layer_object()` that holds the given layer of the
+    /// surface, in texels, or `None` if the surface has no such layer.
+    /// 
+    /// Surfaces that aren't layered have a single layer that covers the whole texture.
+    pub fn surface_texture_layer_rect(&self, surface_texture: &SurfaceTexture, layer: u32)
+                                      -> Option<Rect<i32>> {
+        let surface = &surface_texture.surface;
+        layer_rect(&surface.size, surface.layers, layer)
+    }
+
+    /// Returns the OpenGL texture target that the surface texture's texture object must be bound
+    /// to.
+    /// 
+    /// Pbuffers are bound to textures with `eglBindTexImage()`, which only supports
+    /// `GL_TEXTURE_2D`.
+    #[inline]
+    pub fn surface_texture_target(&self, _: &SurfaceTexture) -> GLenum {
+        SURFACE_GL_TEXTURE_TARGET
+    }
+
+    /// Exports a generic surface so that it can be sampled from in another process.
+    /// 
+    /// The handle contains the Direct3D share handle of the pbuffer backing the surface, so it
+    /// shares memory with the surface.
+    /// 
+    /// The supplied context must be the context the surface is associated with, or an
+    /// `IncompatibleSurface` error is returned.
+    pub fn export_surface(&self, context: &Context, surface: &Surface)
+                          -> Result<SurfaceHandle, Error> {
+        if context.id != surface.context_id {
+            return Err(Error::IncompatibleSurface);
+        }
+
+        match surface.win32_objects {
+            Win32Objects::Pbuffer { share_handle, .. } => {
+                Ok(SurfaceHandle::D3DShareHandle {
+                    handle: share_handle as usize,
+                    size: surface.size,
+                })
+            }
+            Win32Objects::Window => Err(Error::WidgetAttached),
+        }
+    }
+
+    /// Wraps a surface exported from another process in a surface texture local to the given
+    /// context.
+    /// 
+    /// Only Direct3D share handles are accepted. The surface that the returned texture wraps can
+    /// only be read from, and it's always a 2D texture.
+    pub fn create_surface_texture_from_handle(&self,
+                                              context: &mut Context,
+                                              handle: SurfaceHandle,
+                                              _: SurfaceTextureTarget)
+                                              -> Result<SurfaceTexture, Error> {
+        let (share_handle, size) = match handle {
+            SurfaceHandle::D3DShareHandle { handle, size } => (handle as HANDLE, size),
+            SurfaceHandle::IOSurface { .. } => return Err(Error::UnsupportedOnThisPlatform),
+        };
+
+        // The imported surface has no EGL surface of its own; one is created from the share
+        // handle when the surface texture is.
+        let surface = Surface {
+            egl_surface: egl::NO_SURFACE,
+            size,
+            layers: 1,
+            context_id: context.id,
+            context_descriptor: self.context_descriptor(context),
+            origin: Origin::new(&self.leak_tracker),
+            win32_objects: Win32Objects::Pbuffer { share_handle, keyed_mutex: None },
+        };
+        self.create_surface_texture(context, surface).map_err(|(err, _)| err)
+    }
 }
 
 impl Surface {
@@ -2,10 +2,13 @@
 //
 //! Wrapper for WGL contexts on Windows.
 
-use crate::context::{self, CREATE_CONTEXT_MUTEX};
+use crate::context::{self, CREATE_CONTEXT_MUTEX, ContextDescriptorDiagnostics};
+use crate::context::PixelFormatSurfaceTypes;
+use crate::info::GLInfoCache;
 use crate::surface::Framebuffer;
-use crate::{ContextAttributeFlags, ContextAttributes, ContextID, Error, GLVersion};
-use crate::{SurfaceInfo, WindowingApiError};
+use crate::{ContextAttributeFlags, ContextAttributes, ContextID, Error, GLApi, GLVersion};
+use crate::{GLInfo, PixelFormatAttributes, SurfaceInfo, WindowingApiError};
+use crate::WindowingApiFailure;
 use super::device::{DCGuard, Device, HiddenWindow};
 use super::surface::{Surface, Win32Objects};
 
@@ -13,10 +16,13 @@ use crate::Gl;
 use crate::gl::types::{GLenum, GLint, GLuint};
 use crate::gl;
 use std::borrow::Cow;
+use std::collections::HashSet;
 use std::ffi::{CStr, CString};
+use std::io;
 use std::mem;
 use std::os::raw::{c_char, c_int, c_void};
 use std::ptr;
+use std::rc::Rc;
 use std::thread;
 use winapi::shared::minwindef::{BOOL, FALSE, FLOAT, HMODULE, LPARAM, LPVOID, LRESULT, UINT};
 use winapi::shared::minwindef::{WORD, WPARAM};
@@ -30,17 +36,24 @@ use winapi::um::wingdi::{wglGetCurrentDC, wglGetProcAddress, wglMakeCurrent};
 use winapi::um::winuser::{self, COLOR_BACKGROUND, CREATESTRUCTA, CS_OWNDC, WM_CREATE, WNDCLASSA};
 use winapi::um::winuser::{WS_OVERLAPPEDWINDOW, WS_VISIBLE};
 
+const WGL_NUMBER_PIXEL_FORMATS_ARB:  GLenum = 0x2000;
 const WGL_DRAW_TO_WINDOW_ARB:        GLenum = 0x2001;
+const WGL_DRAW_TO_BITMAP_ARB:        GLenum = 0x2002;
 const WGL_ACCELERATION_ARB:          GLenum = 0x2003;
 const WGL_SUPPORT_OPENGL_ARB:        GLenum = 0x2010;
 const WGL_DOUBLE_BUFFER_ARB:         GLenum = 0x2011;
 const WGL_PIXEL_TYPE_ARB:            GLenum = 0x2013;
 const WGL_COLOR_BITS_ARB:            GLenum = 0x2014;
+const WGL_RED_BITS_ARB:              GLenum = 0x2015;
+const WGL_GREEN_BITS_ARB:            GLenum = 0x2017;
+const WGL_BLUE_BITS_ARB:             GLenum = 0x2019;
 const WGL_ALPHA_BITS_ARB:            GLenum = 0x201b;
 const WGL_DEPTH_BITS_ARB:            GLenum = 0x2022;
 const WGL_STENCIL_BITS_ARB:          GLenum = 0x2023;
 const WGL_FULL_ACCELERATION_ARB:     GLenum = 0x2027;
 const WGL_TYPE_RGBA_ARB:             GLenum = 0x202b;
+const WGL_DRAW_TO_PBUFFER_ARB:       GLenum = 0x202d;
+const WGL_SAMPLES_ARB:               GLenum = 0x2042;
 const WGL_CONTEXT_MAJOR_VERSION_ARB: GLenum = 0x2091;
 const WGL_CONTEXT_MINOR_VERSION_ARB: GLenum = 0x2092;
 const WGL_CONTEXT_PROFILE_MASK_ARB:  GLenum = 0x9126;
@@ -48,6 +61,15 @@ const WGL_CONTEXT_PROFILE_MASK_ARB:  GLenum = 0x9126;
 const WGL_CONTEXT_CORE_PROFILE_BIT_ARB:          GLenum = 0x00000001;
 const WGL_CONTEXT_COMPATIBILITY_PROFILE_BIT_ARB: GLenum = 0x00000002;
 
+// The pixel format attributes that every context on this device needs.
+const REQUIRED_PIXEL_FORMAT_ATTRIBUTES: [c_int; 10] = [
+    WGL_DRAW_TO_WINDOW_ARB as c_int, gl::TRUE as c_int,
+    WGL_SUPPORT_OPENGL_ARB as c_int, gl::TRUE as c_int,
+    WGL_DOUBLE_BUFFER_ARB as c_int,  gl::TRUE as c_int,
+    WGL_PIXEL_TYPE_ARB as c_int,     WGL_TYPE_RGBA_ARB as c_int,
+    WGL_ACCELERATION_ARB as c_int,   WGL_FULL_ACCELERATION_ARB as c_int,
+];
+
 #[allow(non_snake_case)]
 #[derive(Default)]
 pub(crate) struct WGLExtensionFunctions {
@@ -58,6 +80,7 @@ pub(crate) struct WGLExtensionFunctions {
     GetExtensionsStringARB: Option<unsafe extern "C" fn(hdc: HDC) -> *const c_char>,
     pub(crate) pixel_format_functions: Option<WGLPixelFormatExtensionFunctions>,
     pub(crate) dx_interop_functions: Option<WGLDXInteropExtensionFunctions>,
+    pub(crate) extensions: HashSet<String>,
 }
 
 #[allow(non_snake_case)]
@@ -110,7 +133,22 @@ pub(crate) struct WGLDXInteropExtensionFunctions {
 pub struct ContextDescriptor {
     pixel_format: c_int,
     gl_version: GLVersion,
+    minimum_gl_version: GLVersion,
     compatibility_profile: bool,
+    allow_core_profile: bool,
+}
+
+impl ContextDescriptor {
+    fn new(pixel_format: c_int, attributes: &ContextAttributes) -> ContextDescriptor {
+        let flags = attributes.flags;
+        ContextDescriptor {
+            pixel_format,
+            gl_version: attributes.version,
+            minimum_gl_version: attributes.minimum_version.unwrap_or(attributes.version),
+            compatibility_profile: flags.contains(ContextAttributeFlags::COMPATIBILITY_PROFILE),
+            allow_core_profile: flags.contains(ContextAttributeFlags::ALLOW_CORE_PROFILE),
+        }
+    }
 }
 
 /// Represents an OpenGL rendering context.
@@ -128,12 +166,14 @@ pub struct ContextDescriptor {
 /// OpenGL objects may not be shared across contexts directly, but surface textures effectively
 /// allow for sharing of texture data. Contexts are local to a single thread and device.
 /// 
-/// A context must be explicitly destroyed with `destroy_context()`, or a panic will occur.
+/// A context should be explicitly destroyed with `destroy_context()`. A context that is dropped
+/// without being destroyed is handled according to the device's `LeakPolicy`.
 pub struct Context {
     pub(crate) glrc: HGLRC,
     pub(crate) id: ContextID,
     pub(crate) gl: Gl,
     hidden_window: Option<HiddenWindow>,
+    gl_info: GLInfoCache,
     pub(crate) framebuffer: Framebuffer<Surface, ()>,
     status: ContextStatus,
 }
@@ -170,24 +210,24 @@ impl Device {
     #[allow(non_snake_case)]
     pub fn create_context_descriptor(&self, attributes: &ContextAttributes)
                                      -> Result<ContextDescriptor, Error> {
+        // WGL only creates desktop OpenGL contexts.
+        if attributes.api == Some(GLApi::GLES) {
+            return Err(Error::UnsupportedGLType);
+        }
+
         let flags = attributes.flags;
         let alpha_bits   = if flags.contains(ContextAttributeFlags::ALPHA)   { 8  } else { 0 };
         let depth_bits   = if flags.contains(ContextAttributeFlags::DEPTH)   { 24 } else { 0 };
         let stencil_bits = if flags.contains(ContextAttributeFlags::STENCIL) { 8  } else { 0 };
-        let compatibility_profile = flags.contains(ContextAttributeFlags::COMPATIBILITY_PROFILE);
-
-        let attrib_i_list = [
-            WGL_DRAW_TO_WINDOW_ARB as c_int, gl::TRUE as c_int,
-            WGL_SUPPORT_OPENGL_ARB as c_int, gl::TRUE as c_int,
-            WGL_DOUBLE_BUFFER_ARB as c_int,  gl::TRUE as c_int,
-            WGL_PIXEL_TYPE_ARB as c_int,     WGL_TYPE_RGBA_ARB as c_int,
-            WGL_ACCELERATION_ARB as c_int,   WGL_FULL_ACCELERATION_ARB as c_int,
+
+        let mut attrib_i_list = REQUIRED_PIXEL_FORMAT_ATTRIBUTES.to_vec();
+        attrib_i_list.extend_from_slice(&[
             WGL_COLOR_BITS_ARB as c_int,     32,
             WGL_ALPHA_BITS_ARB as c_int,     alpha_bits,
             WGL_DEPTH_BITS_ARB as c_int,     depth_bits,
             WGL_STENCIL_BITS_ARB as c_int,   stencil_bits,
             0,
-        ];
+        ]);
 
         let wglChoosePixelFormatARB = match WGL_EXTENSION_FUNCTIONS.pixel_format_functions {
             None => return Err(Error::RequiredExtensionUnavailable),
@@ -204,20 +244,110 @@ impl Device {
                                              &mut pixel_format,
                                              &mut pixel_format_count);
             if ok == FALSE {
-                return Err(Error::PixelFormatSelectionFailed(WindowingApiError::Failed));
+                let failure = wgl_failure("wglChoosePixelFormatARB");
+                return Err(Error::PixelFormatSelectionFailed(Box::new(failure)));
             }
             if pixel_format_count == 0 {
+                debug!("surfman: No WGL pixel format found; {}",
+                       self.diagnose_context_descriptor(attributes));
                 return Err(Error::NoPixelFormatFound);
             }
 
-            Ok(ContextDescriptor {
-                pixel_format,
-                gl_version: attributes.version,
-                compatibility_profile,
-            })
+            Ok(ContextDescriptor::new(pixel_format, attributes))
+        }
+    }
+
+    /// Returns a context descriptor for every pixel format that this device can create contexts
+    /// with, along with the attributes of each pixel format.
+    ///
+    /// The version and profile of the descriptors come from `attributes`. The `ALPHA`, `DEPTH`,
+    /// and `STENCIL` flags are ignored; use the pixel format attributes to tell the descriptors
+    /// apart.
+    #[allow(non_snake_case)]
+    pub fn enumerate_context_descriptors(&self, attributes: &ContextAttributes)
+                                         -> Result<Vec<(ContextDescriptor, PixelFormatAttributes)>,
+                                                   Error> {
+        if attributes.api == Some(GLApi::GLES) {
+            return Err(Error::UnsupportedGLType);
+        }
+
+        let (wglChoosePixelFormatARB, wglGetPixelFormatAttribivARB) =
+            match WGL_EXTENSION_FUNCTIONS.pixel_format_functions {
+                None => return Err(Error::RequiredExtensionUnavailable),
+                Some(ref pixel_format_functions) => {
+                    (pixel_format_functions.ChoosePixelFormatARB,
+                     pixel_format_functions.GetPixelFormatAttribivARB)
+                }
+            };
+
+        let mut attrib_i_list = REQUIRED_PIXEL_FORMAT_ATTRIBUTES.to_vec();
+        attrib_i_list.push(0);
+
+        let hidden_window_dc = self.hidden_window.get_dc();
+        unsafe {
+            // The pixel format is ignored when querying the number of pixel formats.
+            let mut pixel_format_count = 0;
+            let ok = wglGetPixelFormatAttribivARB(hidden_window_dc.dc,
+                                                  0,
+                                                  0,
+                                                  1,
+                                                  &(WGL_NUMBER_PIXEL_FORMATS_ARB as c_int),
+                                                  &mut pixel_format_count);
+            if ok == FALSE {
+                let failure = wgl_failure("wglGetPixelFormatAttribivARB");
+                return Err(Error::PixelFormatSelectionFailed(Box::new(failure)));
+            }
+
+            let mut pixel_formats = vec![0; pixel_format_count as usize];
+            let mut real_pixel_format_count = 0;
+            let ok = wglChoosePixelFormatARB(hidden_window_dc.dc,
+                                             attrib_i_list.as_ptr(),
+                                             ptr::null(),
+                                             pixel_formats.len() as UINT,
+                                             pixel_formats.as_mut_ptr(),
+                                             &mut real_pixel_format_count);
+            if ok == FALSE {
+                let failure = wgl_failure("wglChoosePixelFormatARB");
+                return Err(Error::PixelFormatSelectionFailed(Box::new(failure)));
+            }
+            pixel_formats.truncate(real_pixel_format_count as usize);
+
+            // Skip the pixel formats whose attributes the driver won't report.
+            Ok(pixel_formats.into_iter().filter_map(|pixel_format| {
+                let pixel_format_attributes =
+                    get_pixel_format_attributes(wglGetPixelFormatAttribivARB,
+                                                hidden_window_dc.dc,
+                                                pixel_format)?;
+                Some((ContextDescriptor::new(pixel_format, attributes), pixel_format_attributes))
+            }).collect())
         }
     }
 
+    /// Creates a context descriptor from one of the pixel formats that
+    /// `enumerate_context_descriptors()` returns.
+    ///
+    /// `choose` receives the attributes of every candidate pixel format and returns the index of
+    /// the one to use. If it returns `None` or an index out of range, a `NoPixelFormatFound` error
+    /// is returned.
+    pub fn create_context_descriptor_with<F>(&self, attributes: &ContextAttributes, choose: F)
+                                             -> Result<ContextDescriptor, Error>
+                                             where F: FnOnce(&[PixelFormatAttributes]) ->
+                                                   Option<usize> {
+        let candidates = self.enumerate_context_descriptors(attributes)?;
+        context::choose_context_descriptor(candidates, choose)
+    }
+
+    /// Explains which pixel formats `create_context_descriptor()` passes over for the given
+    /// attributes, and why.
+    ///
+    /// This is meant for finding out why `create_context_descriptor()` returned
+    /// `NoPixelFormatFound` or `UnsupportedGLProfile`.
+    pub fn diagnose_context_descriptor(&self, attributes: &ContextAttributes)
+                                       -> ContextDescriptorDiagnostics {
+        let candidates = self.enumerate_context_descriptors(attributes);
+        ContextDescriptorDiagnostics::new(attributes, candidates, None)
+    }
+
     /// Creates a new OpenGL context.
     /// 
     /// The context initially has no surface attached. Until a surface is bound to it, rendering
@@ -240,29 +370,45 @@ impl Device {
                 // Set the pixel format on the hidden window DC.
                 let hidden_window_dc = hidden_window.get_dc();
                 let dc = hidden_window_dc.dc;
-                set_dc_pixel_format(dc, descriptor.pixel_format);
-
-                // Make the context.
-                let profile_mask = if descriptor.compatibility_profile {
-                    WGL_CONTEXT_COMPATIBILITY_PROFILE_BIT_ARB
-                } else {
-                    WGL_CONTEXT_CORE_PROFILE_BIT_ARB
+                set_dc_pixel_format(dc, descriptor.pixel_format)?;
+
+                // Make the context, working down from the preferred version and profile until
+                // the driver accepts one.
+                let candidates = context::version_candidates(GLApi::GL,
+                                                             descriptor.gl_version,
+                                                             descriptor.minimum_gl_version,
+                                                             descriptor.compatibility_profile,
+                                                             descriptor.allow_core_profile);
+                let glrc_candidate = candidates.into_iter().map(|(gl_version, compatibility)| {
+                    let profile_mask = if compatibility {
+                        WGL_CONTEXT_COMPATIBILITY_PROFILE_BIT_ARB
+                    } else {
+                        WGL_CONTEXT_CORE_PROFILE_BIT_ARB
+                    };
+                    let wgl_attributes = [
+                        WGL_CONTEXT_MAJOR_VERSION_ARB as c_int, gl_version.major as c_int,
+                        WGL_CONTEXT_MINOR_VERSION_ARB as c_int, gl_version.minor as c_int,
+                        WGL_CONTEXT_PROFILE_MASK_ARB as c_int,  profile_mask as c_int,
+                        0,
+                    ];
+                    wglCreateContextAttribsARB(dc, ptr::null_mut(), wgl_attributes.as_ptr())
+                }).find(|glrc| !glrc.is_null());
+                glrc = match glrc_candidate {
+                    None => {
+                        let failure = wgl_failure("wglCreateContextAttribsARB");
+                        return Err(Error::ContextCreationFailed(Box::new(failure)));
+                    }
+                    Some(glrc) => glrc,
                 };
-                let wgl_attributes = [
-                    WGL_CONTEXT_MAJOR_VERSION_ARB as c_int, descriptor.gl_version.major as c_int,
-                    WGL_CONTEXT_MINOR_VERSION_ARB as c_int, descriptor.gl_version.minor as c_int,
-                    WGL_CONTEXT_PROFILE_MASK_ARB as c_int,  profile_mask as c_int,
-                    0,
-                ];
-                glrc = wglCreateContextAttribsARB(dc, ptr::null_mut(), wgl_attributes.as_ptr());
-                if glrc.is_null() {
-                    return Err(Error::ContextCreationFailed(WindowingApiError::Failed));
-                }
 
                 // Temporarily make the context current.
                 let _guard = CurrentContextGuard::new();
                 let ok = wglMakeCurrent(dc, glrc);
-                assert_ne!(ok, FALSE);
+                if ok == FALSE {
+                    let failure = wgl_failure("wglMakeCurrent");
+                    wglDeleteContext(glrc);
+                    return Err(Error::ContextCreationFailed(Box::new(failure)));
+                }
 
                 // Load the GL functions.
                 gl = Gl::load_with(get_proc_address);
@@ -274,6 +420,7 @@ impl Device {
                 id: *next_context_id,
                 gl,
                 hidden_window: Some(hidden_window),
+                gl_info: GLInfoCache::default(),
                 framebuffer: Framebuffer::None,
                 status: ContextStatus::Owned,
             };
@@ -298,7 +445,9 @@ impl Device {
             let dc = hidden_window_dc.dc;
             let _guard = CurrentContextGuard::new();
             let ok = wglMakeCurrent(dc, native_context.0);
-            assert_ne!(ok, FALSE);
+            if ok == FALSE {
+                return Err(Error::MakeCurrentFailed(Box::new(wgl_failure("wglMakeCurrent"))));
+            }
             Gl::load_with(get_proc_address)
         };
 
@@ -307,6 +456,7 @@ impl Device {
             id: *next_context_id,
             gl,
             hidden_window: Some(hidden_window),
+            gl_info: GLInfoCache::default(),
             framebuffer: Framebuffer::External(()),
             status: ContextStatus::Referenced,
         };
@@ -347,13 +497,22 @@ impl Device {
             let dc_guard = self.get_context_dc(context);
             let pixel_format = wingdi::GetPixelFormat(dc_guard.dc);
 
-            let _guard = self.temporarily_make_context_current(context);
-
-            let gl_version = GLVersion::current(&context.gl);
-            let compatibility_profile =
-                context::current_context_uses_compatibility_profile(&context.gl);
+            // Every WGL context supports at least OpenGL 1.1, so fall back to that.
+            let (gl_version, compatibility_profile) = match self.gl_info(context) {
+                Ok(gl_info) => (gl_info.version, gl_info.compatibility_profile),
+                Err(err) => {
+                    warn!("surfman: Couldn't query the GL version of a context: {:?}", err);
+                    (GLVersion::new(1, 1), false)
+                }
+            };
 
-            ContextDescriptor { pixel_format, gl_version, compatibility_profile }
+            ContextDescriptor {
+                pixel_format,
+                gl_version,
+                minimum_gl_version: gl_version,
+                compatibility_profile,
+                allow_core_profile: false,
+            }
         }
     }
 
@@ -387,10 +546,22 @@ impl Device {
             let (alpha_bits, depth_bits, stencil_bits) =
                 (attrib_value_i_list[0], attrib_value_i_list[1], attrib_value_i_list[2]);
 
+            let minimum_version =
+                if context_descriptor.minimum_gl_version < context_descriptor.gl_version {
+                    Some(context_descriptor.minimum_gl_version)
+                } else {
+                    None
+                };
             let mut attributes = ContextAttributes {
+                api: Some(GLApi::GL),
                 version: context_descriptor.gl_version,
+                minimum_version,
                 flags: ContextAttributeFlags::empty(),
             };
+            attributes.flags.set(ContextAttributeFlags::COMPATIBILITY_PROFILE,
+                                 context_descriptor.compatibility_profile);
+            attributes.flags.set(ContextAttributeFlags::ALLOW_CORE_PROFILE,
+                                 context_descriptor.allow_core_profile);
             if alpha_bits > 0 {
                 attributes.flags.insert(ContextAttributeFlags::ALPHA);
             }
@@ -433,7 +604,7 @@ impl Device {
             if ok != FALSE {
                 Ok(())
             } else {
-                Err(Error::MakeCurrentFailed(WindowingApiError::Failed))
+                Err(Error::MakeCurrentFailed(Box::new(wgl_failure("wglMakeCurrent"))))
             }
         }
     }
@@ -449,11 +620,29 @@ impl Device {
             if ok != FALSE {
                 Ok(())
             } else {
-                Err(Error::MakeCurrentFailed(WindowingApiError::Failed))
+                Err(Error::MakeCurrentFailed(Box::new(wgl_failure("wglMakeCurrent"))))
             }
         }
     }
 
+    /// Returns the OpenGL API flavor (OpenGL or OpenGL ES) that the context was created for.
+    #[inline]
+    pub fn context_gl_api(&self, _: &Context) -> GLApi {
+        GLApi::GL
+    }
+
+    /// Returns what the OpenGL implementation reports about the context: its version and
+    /// profile, the vendor and renderer, and the extensions it supports.
+    ///
+    /// The context is queried the first time this is called, and the same information is
+    /// returned after that.
+    pub fn gl_info(&self, context: &Context) -> Result<Rc<GLInfo>, Error> {
+        context.gl_info.get_or_query(|| {
+            let _guard = self.temporarily_make_context_current(context)?;
+            GLInfo::current(&context.gl)
+        })
+    }
+
     /// Fetches the address of an OpenGL function associated with this context.
     /// 
     /// OpenGL functions are local to a context. You should not use OpenGL functions on one context
@@ -488,6 +677,9 @@ impl Device {
         if context.id != surface.context_id {
             return Err((Error::IncompatibleSurface, surface));
         }
+        if let Win32Objects::Imported { .. } = surface.win32_objects {
+            return Err((Error::IncompatibleSurface, surface));
+        }
 
         match context.framebuffer {
             Framebuffer::None => {}
@@ -534,6 +726,9 @@ impl Device {
                 Framebuffer::Surface (Surface {
                     win32_objects: Win32Objects::Texture { .. },
                     ..
+                }) | Framebuffer::Surface (Surface {
+                    win32_objects: Win32Objects::Imported { .. },
+                    ..
                 }) | Framebuffer::External(()) | Framebuffer::None => {
                     context.hidden_window.as_ref().unwrap().get_dc()
                 }
@@ -585,6 +780,12 @@ impl NativeContext {
     }
 }
 
+// Records that the named WGL function just failed, along with `GetLastError()`.
+pub(crate) fn wgl_failure(call: &'static str) -> WindowingApiFailure {
+    let code = io::Error::last_os_error().raw_os_error();
+    WindowingApiFailure::new(call, WindowingApiError::Failed, code)
+}
+
 fn extension_loader_thread() -> WGLExtensionFunctions {
     unsafe {
         let instance = libloaderapi::GetModuleHandleA(ptr::null_mut());
@@ -687,6 +888,9 @@ extern "system" fn extension_loader_window_proc(hwnd: HWND,
                     None => Cow::Borrowed(""),
                 };
 
+                (*wgl_extension_functions).extensions =
+                    extensions.split_whitespace().map(|name| name.to_owned()).collect();
+
                 // Load function pointers.
                 for extension in extensions.split(' ') {
                     if extension == "WGL_ARB_pixel_format" {
@@ -811,7 +1015,75 @@ fn get_proc_address(symbol_name: &str) -> *const c_void {
     }
 }
 
-pub(crate) fn set_dc_pixel_format(dc: HDC, pixel_format: c_int) {
+// Returns `None` if the driver fails to report the attributes of the pixel format.
+#[allow(non_snake_case)]
+unsafe fn get_pixel_format_attributes(wglGetPixelFormatAttribivARB:
+                                        unsafe extern "C" fn(HDC,
+                                                             c_int,
+                                                             c_int,
+                                                             UINT,
+                                                             *const c_int,
+                                                             *mut c_int)
+                                                             -> BOOL,
+                                      dc: HDC,
+                                      pixel_format: c_int)
+                                      -> Option<PixelFormatAttributes> {
+    let attrib_name_i_list = [
+        WGL_RED_BITS_ARB as c_int,
+        WGL_GREEN_BITS_ARB as c_int,
+        WGL_BLUE_BITS_ARB as c_int,
+        WGL_ALPHA_BITS_ARB as c_int,
+        WGL_DEPTH_BITS_ARB as c_int,
+        WGL_STENCIL_BITS_ARB as c_int,
+        WGL_DRAW_TO_WINDOW_ARB as c_int,
+        WGL_DRAW_TO_PBUFFER_ARB as c_int,
+        WGL_DRAW_TO_BITMAP_ARB as c_int,
+    ];
+    let mut values = [0; 9];
+    let ok = wglGetPixelFormatAttribivARB(dc,
+                                          pixel_format,
+                                          0,
+                                          attrib_name_i_list.len() as UINT,
+                                          attrib_name_i_list.as_ptr(),
+                                          values.as_mut_ptr());
+    if ok == FALSE {
+        warn!("surfman: Couldn't query the attributes of pixel format {}: {:?}",
+              pixel_format,
+              wgl_failure("wglGetPixelFormatAttribivARB"));
+        return None;
+    }
+
+    // Drivers without `WGL_ARB_multisample` reject the samples query, which means no format is
+    // multisampled.
+    let mut samples = 0;
+    if wglGetPixelFormatAttribivARB(dc,
+                                    pixel_format,
+                                    0,
+                                    1,
+                                    &(WGL_SAMPLES_ARB as c_int),
+                                    &mut samples) == FALSE {
+        samples = 0;
+    }
+
+    let mut surface_types = PixelFormatSurfaceTypes::empty();
+    surface_types.set(PixelFormatSurfaceTypes::WINDOW, values[6] != 0);
+    surface_types.set(PixelFormatSurfaceTypes::PBUFFER, values[7] != 0);
+    surface_types.set(PixelFormatSurfaceTypes::PIXMAP, values[8] != 0);
+
+    Some(PixelFormatAttributes {
+        red_size: values[0] as u32,
+        green_size: values[1] as u32,
+        blue_size: values[2] as u32,
+        alpha_size: values[3] as u32,
+        depth_size: values[4] as u32,
+        stencil_size: values[5] as u32,
+        samples: samples as u32,
+        surface_types,
+        native_visual_id: Some(pixel_format as u32),
+    })
+}
+
+pub(crate) fn set_dc_pixel_format(dc: HDC, pixel_format: c_int) -> Result<(), Error> {
     unsafe {
         let mut pixel_format_descriptor = mem::zeroed();
         let pixel_format_count =
@@ -819,9 +1091,15 @@ pub(crate) fn set_dc_pixel_format(dc: HDC, pixel_format: c_int) {
                                         pixel_format,
                                         mem::size_of::<PIXELFORMATDESCRIPTOR>() as UINT,
                                         &mut pixel_format_descriptor);
-        assert_ne!(pixel_format_count, 0);
+        if pixel_format_count == 0 {
+            let failure = wgl_failure("DescribePixelFormat");
+            return Err(Error::PixelFormatSelectionFailed(Box::new(failure)));
+        }
         let ok = wingdi::SetPixelFormat(dc, pixel_format, &mut pixel_format_descriptor);
-        assert_ne!(ok, FALSE);
+        if ok == FALSE {
+            return Err(Error::PixelFormatSelectionFailed(Box::new(wgl_failure("SetPixelFormat"))));
+        }
+        Ok(())
     }
 }
 
@@ -2,7 +2,8 @@
 //
 //! An implementation of the GPU device for Windows using the WGL API.
 
-use crate::{Error, GLApi};
+use crate::leak::LeakTracker;
+use crate::{DeviceCapabilities, Error, GLApi, LeakPolicy};
 use super::connection::Connection;
 use super::context::WGL_EXTENSION_FUNCTIONS;
 
@@ -10,6 +11,7 @@ use std::marker::PhantomData;
 use std::mem;
 use std::os::raw::{c_int, c_void};
 use std::ptr;
+use std::sync::Arc;
 use std::sync::mpsc::{self, Sender};
 use std::thread::{self, JoinHandle};
 use winapi::shared::dxgi::{IDXGIAdapter, IDXGIDevice};
@@ -56,6 +58,7 @@ pub struct Device {
     pub(crate) d3d11_device_context: ComPtr<ID3D11DeviceContext>,
     pub(crate) gl_dx_interop_device: HANDLE,
     pub(crate) hidden_window: HiddenWindow,
+    pub(crate) leak_tracker: Arc<LeakTracker>,
 }
 
 /// Wraps a Direct3D 11 device and its associated GL/DX interop device.
@@ -145,7 +148,9 @@ impl Device {
 
             let gl_dx_interop_device =
                 (dx_interop_functions.DXOpenDeviceNV)(d3d11_device.as_raw() as *mut c_void);
-            assert!(!gl_dx_interop_device.is_null());
+            if gl_dx_interop_device.is_null() {
+                return Err(Error::DeviceOpenFailed);
+            }
 
             let hidden_window = HiddenWindow::new();
 
@@ -155,6 +160,7 @@ impl Device {
                 d3d11_device_context,
                 gl_dx_interop_device,
                 hidden_window,
+                leak_tracker: LeakTracker::new(),
             })
         }
     }
@@ -163,13 +169,17 @@ impl Device {
         unsafe {
             (*native_device.d3d11_device).AddRef();
             let d3d11_device = ComPtr::from_raw(native_device.d3d11_device);
-            let dxgi_device: ComPtr<IDXGIDevice> = d3d11_device.cast().unwrap();
+            let dxgi_device: ComPtr<IDXGIDevice> = match d3d11_device.cast() {
+                Ok(dxgi_device) => dxgi_device,
+                Err(_) => return Err(Error::IncompatibleNativeDevice),
+            };
 
             // Fetch the DXGI adapter.
             let mut dxgi_adapter = ptr::null_mut();
             let result = dxgi_device.GetAdapter(&mut dxgi_adapter);
-            assert_eq!(result, S_OK);
-            assert!(!dxgi_adapter.is_null());
+            if result != S_OK || dxgi_adapter.is_null() {
+                return Err(Error::IncompatibleNativeDevice);
+            }
             let dxgi_adapter = ComPtr::from_raw(dxgi_adapter);
 
             // Turn that DXGI adapter into a `surfman` adapter.
@@ -190,6 +200,7 @@ impl Device {
                 d3d11_device_context,
                 gl_dx_interop_device,
                 hidden_window,
+                leak_tracker: LeakTracker::new(),
             })
         }
     }
@@ -218,11 +229,38 @@ impl Device {
         self.adapter.clone()
     }
 
-    /// Returns the OpenGL API flavor that this device supports (OpenGL or OpenGL ES).
+    /// Returns the OpenGL API flavor that contexts on this device use by default (OpenGL or
+    /// OpenGL ES).
     #[inline]
     pub fn gl_api(&self) -> GLApi {
         GLApi::GL
     }
+
+    /// Returns the optional features that this device supports.
+    pub fn capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities {
+            widget_surfaces: true,
+            cpu_surface_reads: false,
+            cpu_surface_uploads: false,
+            cross_device_surface_textures: true,
+            damage_present: false,
+            surface_texture_target: self.surface_gl_texture_target(),
+            platform_extensions: WGL_EXTENSION_FUNCTIONS.extensions.clone(),
+        }
+    }
+
+    /// Sets what happens to contexts and surfaces created with this device that are dropped
+    /// without being destroyed, overriding the global leak policy.
+    #[inline]
+    pub fn set_leak_policy(&self, policy: LeakPolicy) {
+        self.leak_tracker.set_policy(policy)
+    }
+
+    /// Returns the leak policy that this device follows.
+    #[inline]
+    pub fn leak_policy(&self) -> LeakPolicy {
+        self.leak_tracker.policy()
+    }
 }
 
 impl Adapter {
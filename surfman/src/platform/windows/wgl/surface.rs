@@ -2,25 +2,31 @@
 //
 //! An implementation of the GPU device for Windows using WGL/Direct3D interoperability.
 
-use crate::error::WindowingApiError;
+use crate::error::{WindowingApiError, WindowingApiFailure};
+use crate::ipc::SurfaceHandle;
+use crate::leak::{LeakedObject, Origin};
 use crate::renderbuffers::Renderbuffers;
-use crate::{ContextID, Error, SurfaceAccess, SurfaceID, SurfaceInfo, SurfaceType};
+use crate::{ContextID, DepthStencilAttributes, Error, SurfaceAccess, SurfaceFormat, SurfaceID};
+use crate::{LayerStorage, SurfaceInfo, SurfaceTextureTarget, SurfaceType};
+use crate::surface::{layer_rect, layered_surface_size};
 use super::context::{self, Context, WGL_EXTENSION_FUNCTIONS};
 use super::device::Device;
 
 use crate::gl::types::{GLenum, GLint, GLuint};
 use crate::gl;
 use crate::gl_utils;
-use euclid::default::Size2D;
+use euclid::default::{Rect, Size2D};
 use std::fmt::{self, Debug, Formatter};
 use std::marker::PhantomData;
 use std::mem;
 use std::os::raw::c_void;
 use std::ptr;
-use std::thread;
 use winapi::Interface;
 use winapi::shared::dxgi::IDXGIResource;
-use winapi::shared::dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM;
+use winapi::shared::dxgiformat::{DXGI_FORMAT, DXGI_FORMAT_B8G8R8A8_UNORM};
+use winapi::shared::dxgiformat::{DXGI_FORMAT_R10G10B10A2_UNORM, DXGI_FORMAT_R16G16B16A16_FLOAT};
+use winapi::shared::dxgiformat::{DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_FORMAT_R8G8B8A8_UNORM_SRGB};
+use winapi::shared::dxgiformat::{DXGI_FORMAT_R8G8_UNORM, DXGI_FORMAT_R8_UNORM};
 use winapi::shared::dxgitype::DXGI_SAMPLE_DESC;
 use winapi::shared::minwindef::{FALSE, UINT};
 use winapi::shared::ntdef::HANDLE;
@@ -54,12 +60,16 @@ const WGL_ACCESS_READ_WRITE_NV: GLenum = 0x0001;
 /// 
 /// Depending on the platform, each surface may be internally double-buffered.
 /// 
-/// Surfaces must be destroyed with the `destroy_surface()` method, or a panic will occur.
+/// Surfaces should be destroyed with the `destroy_surface()` method. A surface that is dropped
+/// without being destroyed is handled according to the device's `LeakPolicy`.
 pub struct Surface {
     pub(crate) size: Size2D<i32>,
+    // The number of layers laid out side by side in the surface.
+    pub(crate) layers: u32,
     pub(crate) context_id: ContextID,
     pub(crate) win32_objects: Win32Objects,
     pub(crate) destroyed: bool,
+    origin: Origin,
 }
 
 pub(crate) enum Win32Objects {
@@ -74,6 +84,10 @@ pub(crate) enum Win32Objects {
     Widget {
         window_handle: HWND,
     },
+    // A texture shared from another process, which can only be sampled from.
+    Imported {
+        dxgi_share_handle: HANDLE,
+    },
 }
 
 /// Represents an OpenGL texture that wraps a surface.
@@ -83,8 +97,9 @@ pub(crate) enum Win32Objects {
 /// framebuffer).
 /// 
 /// Surface textures are local to a context, but that context does not have to be the same context
-/// as that associated with the underlying surface. The texture must be destroyed with the
-/// `destroy_surface_texture()` method, or a panic will occur.
+/// as that associated with the underlying surface. The texture should be destroyed with the
+/// `destroy_surface_texture()` method. Dropping it instead leaks the texture object and drops its
+/// surface, which is handled according to the device's `LeakPolicy`.
 pub struct SurfaceTexture {
     pub(crate) surface: Surface,
     #[allow(dead_code)]
@@ -104,8 +119,8 @@ impl Debug for Surface {
 
 impl Drop for Surface {
     fn drop(&mut self) {
-        if !self.destroyed && !thread::panicking() {
-            panic!("Should have destroyed the surface first with `destroy_surface()`!")
+        if !self.destroyed {
+            self.origin.report_leak(LeakedObject::Surface, false);
         }
     }
 }
@@ -124,6 +139,14 @@ pub struct NativeWidget {
     pub window_handle: HWND,
 }
 
+/// A Direct3D 11 texture created outside `surfman`, identified by its DXGI share handle.
+pub struct NativeImage {
+    /// The share handle of the texture.
+    pub share_handle: HANDLE,
+    /// The size of the texture.
+    pub size: Size2D<i32>,
+}
+
 impl Device {
     /// Creates either a generic or a widget surface, depending on the supplied surface type.
     /// 
@@ -135,14 +158,35 @@ impl Device {
                           surface_type: SurfaceType<NativeWidget>)
                           -> Result<Surface, Error> {
         match surface_type {
-            SurfaceType::Generic { size } => self.create_generic_surface(context, &size),
-            SurfaceType::Widget { native_widget } => {
+            // Only the color texture is shared through Direct3D, so a depth texture couldn't be
+            // read from other contexts.
+            SurfaceType::Generic {
+                depth_stencil: Some(DepthStencilAttributes { sampleable: true, .. }),
+                ..
+            } => Err(Error::UnsupportedDepthStencilFormat),
+            SurfaceType::Generic { size, format, depth_stencil } => {
+                self.create_generic_surface(context, &size, format, depth_stencil, 1)
+            }
+            SurfaceType::Layered { size, layers } => {
+                let size = layered_surface_size(&size, layers)?;
+                self.create_generic_surface(context, &size, SurfaceFormat::RGBA8, None, layers)
+            }
+            // The window's color space is fixed by the pixel format it was created with.
+            SurfaceType::Widget { color_space: Some(_), .. } => {
+                Err(Error::UnsupportedColorSpace)
+            }
+            SurfaceType::Widget { native_widget, color_space: None } => {
                 self.create_widget_surface(context, native_widget)
             }
         }
     }
 
-    fn create_generic_surface(&mut self, context: &Context, size: &Size2D<i32>)
+    fn create_generic_surface(&mut self,
+                              context: &Context,
+                              size: &Size2D<i32>,
+                              format: SurfaceFormat,
+                              depth_stencil: Option<DepthStencilAttributes>,
+                              layers: u32)
                               -> Result<Surface, Error> {
         let dx_interop_functions = match WGL_EXTENSION_FUNCTIONS.dx_interop_functions {
             None => return Err(Error::RequiredExtensionUnavailable),
@@ -158,7 +202,7 @@ impl Device {
                 Height: size.height as UINT,
                 MipLevels: 1,
                 ArraySize: 1,
-                Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                Format: dxgi_format(format),
                 SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
                 Usage: D3D11_USAGE_DEFAULT,
                 BindFlags: D3D11_BIND_SHADER_RESOURCE | D3D11_BIND_RENDER_TARGET,
@@ -170,9 +214,11 @@ impl Device {
                                                                ptr::null(),
                                                                &mut d3d11_texture);
             if !winerror::SUCCEEDED(result) {
-                return Err(Error::SurfaceCreationFailed(WindowingApiError::Failed));
+                let failure = WindowingApiFailure::new("ID3D11Device::CreateTexture2D",
+                                                       WindowingApiError::Failed,
+                                                       Some(result));
+                return Err(Error::SurfaceCreationFailed(Box::new(failure)));
             }
-            assert!(!d3d11_texture.is_null());
             let d3d11_texture = ComPtr::from_raw(d3d11_texture);
 
             // Upcast it to a DXGI resource.
@@ -180,22 +226,33 @@ impl Device {
             result = d3d11_texture.QueryInterface(
                 &IDXGIResource::uuidof(),
                 &mut dxgi_resource as *mut *mut IDXGIResource as *mut *mut c_void);
-            assert!(winerror::SUCCEEDED(result));
-            assert!(!dxgi_resource.is_null());
+            if !winerror::SUCCEEDED(result) {
+                let failure = WindowingApiFailure::new("IUnknown::QueryInterface",
+                                                       WindowingApiError::Failed,
+                                                       Some(result));
+                return Err(Error::SurfaceCreationFailed(Box::new(failure)));
+            }
             let dxgi_resource = ComPtr::from_raw(dxgi_resource);
 
             // Get the share handle. We'll need it both to bind to GL and to share the texture
             // across contexts.
             let mut dxgi_share_handle = INVALID_HANDLE_VALUE;
             result = dxgi_resource.GetSharedHandle(&mut dxgi_share_handle);
-            assert!(winerror::SUCCEEDED(result));
-            assert_ne!(dxgi_share_handle, INVALID_HANDLE_VALUE);
+            if !winerror::SUCCEEDED(result) || dxgi_share_handle == INVALID_HANDLE_VALUE {
+                let failure = WindowingApiFailure::new("IDXGIResource::GetSharedHandle",
+                                                       WindowingApiError::Failed,
+                                                       Some(result));
+                return Err(Error::SurfaceCreationFailed(Box::new(failure)));
+            }
 
             // Tell GL about the share handle.
             let ok = (dx_interop_functions.DXSetResourceShareHandleNV)(
                 d3d11_texture.as_raw() as *mut c_void,
                 dxgi_share_handle);
-            assert_ne!(ok, FALSE);
+            if ok == FALSE {
+                let failure = context::wgl_failure("wglDXSetResourceShareHandleNV");
+                return Err(Error::SurfaceCreationFailed(Box::new(failure)));
+            }
 
             // Make our texture object on the GL side.
             let mut gl_texture = 0;
@@ -208,7 +265,11 @@ impl Device {
                                                           gl_texture,
                                                           gl::TEXTURE_2D,
                                                           WGL_ACCESS_READ_WRITE_NV);
-            assert_ne!(gl_dx_interop_object, INVALID_HANDLE_VALUE);
+            if gl_dx_interop_object == INVALID_HANDLE_VALUE {
+                let failure = context::wgl_failure("wglDXRegisterObjectNV");
+                context.gl.DeleteTextures(1, &gl_texture);
+                return Err(Error::SurfaceCreationFailed(Box::new(failure)));
+            }
 
             // Build our FBO.
             let mut gl_framebuffer = 0;
@@ -225,7 +286,10 @@ impl Device {
             // Create renderbuffers as appropriate, and attach them.
             let context_descriptor = self.context_descriptor(context);
             let context_attributes = self.context_descriptor_attributes(&context_descriptor);
-            let renderbuffers = Renderbuffers::new(&context.gl, &size, &context_attributes);
+            let renderbuffers = Renderbuffers::new(&context.gl,
+                                                   &size,
+                                                   &context_attributes,
+                                                   depth_stencil);
             renderbuffers.bind_to_current_framebuffer(&context.gl);
 
             // FIXME(pcwalton): Do we need to acquire the keyed mutex, or does the GL driver do
@@ -233,6 +297,7 @@ impl Device {
 
             Ok(Surface {
                 size: *size,
+                layers,
                 context_id: context.id,
                 win32_objects: Win32Objects::Texture {
                     d3d11_texture,
@@ -243,6 +308,7 @@ impl Device {
                     renderbuffers,
                 },
                 destroyed: false,
+                origin: Origin::new(&self.leak_tracker),
             })
         }
     }
@@ -262,17 +328,19 @@ impl Device {
                 let context_dc_guard = self.get_context_dc(context);
                 let pixel_format = wingdi::GetPixelFormat(context_dc_guard.dc);
                 let window_dc = winuser::GetDC(native_widget.window_handle);
-                context::set_dc_pixel_format(window_dc, pixel_format);
+                context::set_dc_pixel_format(window_dc, pixel_format)?;
             }
 
             Ok(Surface {
                 size: Size2D::new(widget_rect.right - widget_rect.left,
                                   widget_rect.bottom - widget_rect.top),
+                layers: 1,
                 context_id: context.id,
                 win32_objects: Win32Objects::Widget {
                     window_handle: native_widget.window_handle,
                 },
                 destroyed: false,
+                origin: Origin::new(&self.leak_tracker),
             })
         }
     }
@@ -282,8 +350,8 @@ impl Device {
     /// The supplied context must be the context the surface is associated with, or this returns
     /// an `IncompatibleSurface` error.
     /// 
-    /// You must explicitly call this method to dispose of a surface. Otherwise, a panic occurs in
-    /// the `drop` method.
+    /// You should explicitly call this method to dispose of a surface. Otherwise, the surface is
+    /// handled according to the device's `LeakPolicy` when it is dropped.
     pub fn destroy_surface(&self, context: &mut Context, surface: &mut Surface)
                            -> Result<(), Error> {
         let dx_interop_functions =
@@ -320,7 +388,7 @@ impl Device {
                     assert_ne!(ok, FALSE);
                     *gl_dx_interop_object = INVALID_HANDLE_VALUE;
                 }
-                Win32Objects::Widget { window_handle: _ } => {}
+                Win32Objects::Widget { window_handle: _ } | Win32Objects::Imported { .. } => {}
             }
 
             surface.destroyed = true;
@@ -329,6 +397,14 @@ impl Device {
         Ok(())
     }
 
+    /// Adopts a Direct3D texture that another library created as a generic surface.
+    /// 
+    /// This isn't implemented on Windows yet, so it always returns an `Unimplemented` error.
+    pub unsafe fn create_surface_from_native_image(&self, _: &Context, _: NativeImage)
+                                                   -> Result<Surface, Error> {
+        Err(Error::Unimplemented)
+    }
+
     /// Creates a surface texture from an existing generic surface for use with the given context.
     /// 
     /// The surface texture is local to the supplied context and takes ownership of the surface.
@@ -343,7 +419,8 @@ impl Device {
                                   -> Result<SurfaceTexture, (Error, Surface)> {
         let dxgi_share_handle = match surface.win32_objects {
             Win32Objects::Widget { .. } => return Err((Error::WidgetAttached, surface)),
-            Win32Objects::Texture { dxgi_share_handle, .. } => dxgi_share_handle,
+            Win32Objects::Texture { dxgi_share_handle, .. } |
+            Win32Objects::Imported { dxgi_share_handle } => dxgi_share_handle,
         };
 
         let dx_interop_functions =
@@ -363,7 +440,10 @@ impl Device {
                                                               &ID3D11Texture2D::uuidof(),
                                                               &mut local_d3d11_texture);
             if !winerror::SUCCEEDED(result) || local_d3d11_texture.is_null() {
-                return Err((Error::SurfaceImportFailed(WindowingApiError::Failed), surface));
+                let failure = WindowingApiFailure::new("ID3D11Device::OpenSharedResource",
+                                                       WindowingApiError::Failed,
+                                                       Some(result));
+                return Err((Error::SurfaceImportFailed(Box::new(failure)), surface));
             }
             let local_d3d11_texture =
                 ComPtr::from_raw(local_d3d11_texture as *mut ID3D11Texture2D);
@@ -420,8 +500,9 @@ impl Device {
     /// The supplied context must be the same context the surface texture was created with, or an
     /// `IncompatibleSurfaceTexture` error is returned.
     /// 
-    /// All surface textures must be explicitly destroyed with this function, or a panic will
-    /// occur.
+    /// All surface textures should be explicitly destroyed with this function. Otherwise, the
+    /// texture object is leaked and the surface is handled according to the device's `LeakPolicy`
+    /// when the surface texture is dropped.
     pub fn destroy_surface_texture(&self,
                                    context: &mut Context,
                                    mut surface_texture: SurfaceTexture)
@@ -461,7 +542,7 @@ impl Device {
 
     pub(crate) fn lock_surface(&self, surface: &Surface) {
         let mut gl_dx_interop_object = match surface.win32_objects {
-            Win32Objects::Widget { .. } => return,
+            Win32Objects::Widget { .. } | Win32Objects::Imported { .. } => return,
             Win32Objects::Texture { gl_dx_interop_object, .. } => gl_dx_interop_object,
         };
 
@@ -480,7 +561,7 @@ impl Device {
 
     pub(crate) fn unlock_surface(&self, surface: &Surface) {
         let mut gl_dx_interop_object = match surface.win32_objects {
-            Win32Objects::Widget { .. } => return,
+            Win32Objects::Widget { .. } | Win32Objects::Imported { .. } => return,
             Win32Objects::Texture { gl_dx_interop_object, .. } => gl_dx_interop_object,
         };
 
@@ -504,6 +585,19 @@ impl Device {
         Err(Error::Unimplemented)
     }
 
+    /// Copies pixels from the CPU into a rectangle of a generic surface.
+    #[inline]
+    pub fn upload_surface_data(&self,
+                               _: &Context,
+                               _: &mut Surface,
+                               _: &Rect<i32>,
+                               _: &[u8],
+                               _: usize,
+                               _: SurfaceFormat)
+                               -> Result<(), Error> {
+        Err(Error::Unimplemented)
+    }
+
     /// Returns the OpenGL texture target needed to read from this surface texture.
     /// 
     /// This will be `GL_TEXTURE_2D` or `GL_TEXTURE_RECTANGLE`, depending on platform.
@@ -512,6 +606,23 @@ impl Device {
         gl::TEXTURE_2D
     }
 
+    /// Returns the formats that generic surfaces created on this device may have.
+    /// 
+    /// Every format maps to a Direct3D 11 texture format that the `WGL_NV_DX_interop` extension
+    /// can share with OpenGL.
+    #[inline]
+    pub fn supported_surface_formats(&self, _: &Context) -> Vec<SurfaceFormat> {
+        vec![
+            SurfaceFormat::RGBA8,
+            SurfaceFormat::BGRA8,
+            SurfaceFormat::SRGB8A8,
+            SurfaceFormat::RGB10A2,
+            SurfaceFormat::RGBA16F,
+            SurfaceFormat::R8,
+            SurfaceFormat::RG8,
+        ]
+    }
+
     /// Displays the contents of a widget surface on screen.
     /// 
     /// Widget surfaces are internally double-buffered, so changes to them don't show up in their
@@ -542,14 +653,17 @@ impl Device {
     /// 0, the default framebuffer, depending on platform.
     #[inline]
     pub fn surface_info(&self, surface: &Surface) -> SurfaceInfo {
+        let framebuffer_object = match surface.win32_objects {
+            Win32Objects::Texture { gl_framebuffer, .. } => gl_framebuffer,
+            Win32Objects::Widget { .. } | Win32Objects::Imported { .. } => 0,
+        };
         SurfaceInfo {
             size: surface.size,
             id: surface.id(),
             context_id: surface.context_id,
-            framebuffer_object: match surface.win32_objects {
-                Win32Objects::Texture { gl_framebuffer, .. } => gl_framebuffer,
-                Win32Objects::Widget { .. } => 0,
-            },
+            framebuffer_object,
+            layers: surface.layers,
+            layer_storage: LayerStorage::Atlas,
         }
     }
 
@@ -560,6 +674,116 @@ impl Device {
     pub fn surface_texture_object(&self, surface_texture: &SurfaceTexture) -> GLuint {
         surface_texture.gl_texture
     }
+
+    /// Returns the OpenGL texture object containing the depth buffer of this surface texture's
+    /// surface.
+    /// 
+    /// Depth buffers can't be sampled from other contexts with WGL, so this always returns
+    /// `None`.
+    #[inline]
+    pub fn surface_texture_depth_object(&self, _: &SurfaceTexture) -> Option<GLuint> {
+        None
+    }
+
+    /// Returns the OpenGL texture object containing the given layer of the surface, or `None` if
+    /// the surface has no such layer.
+    /// 
+    /// Layers are laid out side by side in one texture on this platform, so this is
+    /// `surface_texture_object()` for every layer.
+    pub fn surface_texture_layer_object(&self, surface_texture: &SurfaceTexture, layer: u32)
+                                        -> Option<GLuint> {
+        self.surface_texture_layer_rect(surface_texture, layer)
+            .map(|_| self.surface_texture_object(surface_texture))
+    }
+
+    /// Returns the region of `surface_texture_layer_object()` that holds the given layer of the
+    /// surface, in texels, or `None` if the surface has no such layer.
+    /// 
+    /// Surfaces that aren't layered have a single layer that covers the whole texture.
+    pub fn surface_texture_layer_rect(&self, surface_texture: &SurfaceTexture, layer: u32)
+                                      -> Option<Rect<i32>> {
+        let surface = &surface_texture.surface;
+        layer_rect(&surface.size, surface.layers, layer)
+    }
+
+    /// Returns the OpenGL texture target that the surface texture's texture object must be bound
+    /// to.
+    /// 
+    /// Direct3D textures are always shared with OpenGL as 2D textures, so this is
+    /// `GL_TEXTURE_2D`.
+    #[inline]
+    pub fn surface_texture_target(&self, _: &SurfaceTexture) -> GLenum {
+        SURFACE_GL_TEXTURE_TARGET
+    }
+
+    /// Exports a generic surface so that it can be sampled from in another process.
+    /// 
+    /// The handle contains the DXGI share handle of the Direct3D texture backing the surface, so
+    /// it shares memory with the surface.
+    /// 
+    /// The supplied context must be the context the surface is associated with, or an
+    /// `IncompatibleSurface` error is returned.
+    pub fn export_surface(&self, context: &Context, surface: &Surface)
+                          -> Result<SurfaceHandle, Error> {
+        if context.id != surface.context_id {
+            return Err(Error::IncompatibleSurface);
+        }
+
+        match surface.win32_objects {
+            Win32Objects::Texture { dxgi_share_handle, .. } => {
+                Ok(SurfaceHandle::D3DShareHandle {
+                    handle: dxgi_share_handle as usize,
+                    size: surface.size,
+                })
+            }
+            Win32Objects::Widget { .. } => Err(Error::WidgetAttached),
+            Win32Objects::Imported { .. } => Err(Error::IncompatibleSurface),
+        }
+    }
+
+    /// Wraps a surface exported from another process in a surface texture local to the given
+    /// context.
+    /// 
+    /// Only Direct3D share handles are accepted. The surface that the returned texture wraps can
+    /// only be read from. It's always a 2D texture, whichever target is requested.
+    pub fn create_surface_texture_from_handle(&self,
+                                              context: &mut Context,
+                                              handle: SurfaceHandle,
+                                              _: SurfaceTextureTarget)
+                                              -> Result<SurfaceTexture, Error> {
+        let (dxgi_share_handle, size) = match handle {
+            SurfaceHandle::D3DShareHandle { handle, size } => (handle as HANDLE, size),
+            SurfaceHandle::IOSurface { .. } => return Err(Error::UnsupportedOnThisPlatform),
+        };
+
+        let surface = Surface {
+            size,
+            layers: 1,
+            context_id: context.id,
+            win32_objects: Win32Objects::Imported { dxgi_share_handle },
+            destroyed: false,
+            origin: Origin::new(&self.leak_tracker),
+        };
+        match self.create_surface_texture(context, surface) {
+            Ok(surface_texture) => Ok(surface_texture),
+            Err((err, mut surface)) => {
+                surface.destroyed = true;
+                Err(err)
+            }
+        }
+    }
+}
+
+fn dxgi_format(format: SurfaceFormat) -> DXGI_FORMAT {
+    match format {
+        SurfaceFormat::RGBA8 => DXGI_FORMAT_R8G8B8A8_UNORM,
+        SurfaceFormat::BGRA8 => DXGI_FORMAT_B8G8R8A8_UNORM,
+        SurfaceFormat::SRGB8A8 => DXGI_FORMAT_R8G8B8A8_UNORM_SRGB,
+        SurfaceFormat::RGB10A2 => DXGI_FORMAT_R10G10B10A2_UNORM,
+        SurfaceFormat::RGBA16F => DXGI_FORMAT_R16G16B16A16_FLOAT,
+        SurfaceFormat::R8 => DXGI_FORMAT_R8_UNORM,
+        SurfaceFormat::RG8 => DXGI_FORMAT_R8G8_UNORM,
+    }
 }
 
 impl Surface {
@@ -569,6 +793,7 @@ impl Surface {
                 SurfaceID((*d3d11_texture).as_raw() as usize)
             }
             Win32Objects::Widget { window_handle } => SurfaceID(window_handle as usize),
+            Win32Objects::Imported { dxgi_share_handle } => SurfaceID(dxgi_share_handle as usize),
         }
     }
 }
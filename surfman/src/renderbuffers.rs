@@ -4,32 +4,38 @@
 
 use crate::Gl;
 use crate::context::{ContextAttributeFlags, ContextAttributes};
-use crate::gl::types::GLuint;
+use crate::gl::types::{GLenum, GLint, GLuint};
 use crate::gl;
+use crate::surface::{DepthStencilAttributes, DepthStencilFormat};
 
 use euclid::default::Size2D;
+use std::ptr;
 
+// Dropping these without calling `destroy()` leaks them. That only happens when the surface that
+// owns them is leaked, which the surface reports according to the leak policy.
 pub(crate) enum Renderbuffers {
     IndividualDepthStencil {
         depth: GLuint,
         stencil: GLuint,
     },
     CombinedDepthStencil(GLuint),
-}
-
-impl Drop for Renderbuffers {
-    fn drop(&mut self) {
-        match *self {
-            Renderbuffers::IndividualDepthStencil { depth: 0, stencil: 0 } |
-            Renderbuffers::CombinedDepthStencil(0) => {}
-            _ => panic!("Should have destroyed the FBO renderbuffers with `destroy()`!"),
-        }
-    }
+    // A sampleable depth buffer. The stencil buffer, if any, lives in the same texture.
+    DepthTexture {
+        texture: GLuint,
+        attachment: GLenum,
+    },
 }
 
 impl Renderbuffers {
-    pub(crate) fn new(gl: &Gl, size: &Size2D<i32>, attributes: &ContextAttributes)
+    pub(crate) fn new(gl: &Gl,
+                      size: &Size2D<i32>,
+                      attributes: &ContextAttributes,
+                      depth_stencil: Option<DepthStencilAttributes>)
                       -> Renderbuffers {
+        if let Some(depth_stencil) = depth_stencil {
+            return Renderbuffers::with_format(gl, size, depth_stencil);
+        }
+
         unsafe {
             if attributes.flags.contains(ContextAttributeFlags::DEPTH |
                                          ContextAttributeFlags::STENCIL) {
@@ -70,6 +76,63 @@ impl Renderbuffers {
         }
     }
 
+    fn with_format(gl: &Gl, size: &Size2D<i32>, depth_stencil: DepthStencilAttributes)
+                   -> Renderbuffers {
+        let format = depth_stencil.format;
+        let (internal_format, gl_format, gl_type) = format.gl_texture_formats();
+        unsafe {
+            if depth_stencil.sampleable {
+                let mut texture = 0;
+                gl.GenTextures(1, &mut texture);
+                gl.BindTexture(gl::TEXTURE_2D, texture);
+                gl.TexImage2D(gl::TEXTURE_2D,
+                              0,
+                              internal_format as GLint,
+                              size.width,
+                              size.height,
+                              0,
+                              gl_format,
+                              gl_type,
+                              ptr::null());
+                gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+                gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+                gl.BindTexture(gl::TEXTURE_2D, 0);
+
+                let attachment = if format.has_stencil() {
+                    gl::DEPTH_STENCIL_ATTACHMENT
+                } else {
+                    gl::DEPTH_ATTACHMENT
+                };
+                return Renderbuffers::DepthTexture { texture, attachment };
+            }
+
+            let mut renderbuffer = 0;
+            gl.GenRenderbuffers(1, &mut renderbuffer);
+            gl.BindRenderbuffer(gl::RENDERBUFFER, renderbuffer);
+            gl.RenderbufferStorage(gl::RENDERBUFFER, internal_format, size.width, size.height);
+            gl.BindRenderbuffer(gl::RENDERBUFFER, 0);
+
+            match format {
+                DepthStencilFormat::Depth24Stencil8 | DepthStencilFormat::Depth32FStencil8 => {
+                    Renderbuffers::CombinedDepthStencil(renderbuffer)
+                }
+                DepthStencilFormat::Depth16 |
+                DepthStencilFormat::Depth24 |
+                DepthStencilFormat::Depth32F => {
+                    Renderbuffers::IndividualDepthStencil { depth: renderbuffer, stencil: 0 }
+                }
+            }
+        }
+    }
+
+    // Returns the texture backing the depth buffer, if it's sampleable.
+    pub(crate) fn depth_texture(&self) -> Option<GLuint> {
+        match *self {
+            Renderbuffers::DepthTexture { texture, .. } if texture != 0 => Some(texture),
+            _ => None,
+        }
+    }
+
     pub(crate) fn bind_to_current_framebuffer(&self, gl: &Gl) {
         unsafe {
             match *self {
@@ -98,6 +161,13 @@ impl Renderbuffers {
                                                    stencil_renderbuffer);
                     }
                 }
+                Renderbuffers::DepthTexture { texture, attachment } => {
+                    gl.FramebufferTexture2D(gl::FRAMEBUFFER,
+                                            attachment,
+                                            gl::TEXTURE_2D,
+                                            texture,
+                                            0);
+                }
             }
         }
     }
@@ -126,6 +196,12 @@ impl Renderbuffers {
                         *depth_renderbuffer = 0;
                     }
                 }
+                Renderbuffers::DepthTexture { ref mut texture, .. } => {
+                    if *texture != 0 {
+                        gl.DeleteTextures(1, texture);
+                        *texture = 0;
+                    }
+                }
             }
         }
     }
@@ -2,11 +2,15 @@
 //
 //! Information related to hardware surfaces.
 
+use crate::Error;
 use crate::context::ContextID;
 
-use crate::gl::types::GLuint;
-use euclid::default::Size2D;
+use crate::gl::types::{GLenum, GLuint};
+use crate::gl;
+use euclid::default::{Point2D, Rect, Size2D};
+use std::convert::TryFrom;
 use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
 
 /// Various data about the surface.
 pub struct SystemSurfaceInfo {
@@ -19,6 +23,8 @@ pub struct SystemSurfaceInfo {
 /// Various data about the surface.
 pub struct SurfaceInfo {
     /// The surface's size, in device pixels.
+    /// 
+    /// If the layers are stored in an array texture, this is the size of each layer.
     pub size: Size2D<i32>,
     /// The ID of the surface. This should be globally unique for each currently-allocated surface.
     pub id: SurfaceID,
@@ -28,6 +34,67 @@ pub struct SurfaceInfo {
     /// 
     /// This is only valid when the surface is actually attached to a context.
     pub framebuffer_object: GLuint,
+    /// The number of layers in the surface.
+    /// 
+    /// Every surface has at least one layer; only layered surfaces have more than one. Use
+    /// `layer_framebuffer_object()` and `layer_rect()` to render to each one.
+    pub layers: u32,
+    /// How the layers are stored.
+    pub layer_storage: LayerStorage,
+}
+
+impl SurfaceInfo {
+    /// Returns the framebuffer object that renders to the given layer, or `None` if the surface
+    /// has no such layer.
+    /// 
+    /// This is `framebuffer_object` unless the layers are stored in an array texture.
+    pub fn layer_framebuffer_object(&self, layer: u32) -> Option<GLuint> {
+        if layer >= self.layers {
+            return None;
+        }
+        match self.layer_storage {
+            LayerStorage::Atlas => Some(self.framebuffer_object),
+            LayerStorage::Array { ref framebuffer_objects, .. } => {
+                framebuffer_objects.get(layer as usize).cloned()
+            }
+        }
+    }
+
+    /// Returns the region of the layer's framebuffer that the given layer occupies, in device
+    /// pixels, or `None` if the surface has no such layer.
+    /// 
+    /// To draw to a layer, bind `layer_framebuffer_object()` and set the viewport and scissor
+    /// rectangle to this region.
+    pub fn layer_rect(&self, layer: u32) -> Option<Rect<i32>> {
+        match self.layer_storage {
+            LayerStorage::Atlas => layer_rect(&self.size, self.layers, layer),
+            LayerStorage::Array { .. } if layer < self.layers => {
+                Some(Rect::new(Point2D::zero(), self.size))
+            }
+            LayerStorage::Array { .. } => None,
+        }
+    }
+}
+
+/// How the layers of a surface are stored.
+/// 
+/// See `SurfaceType::Layered` for when each is used.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LayerStorage {
+    /// The layers are laid out side by side, left to right, in a single 2D image, and all of them
+    /// render through the surface's framebuffer object.
+    /// 
+    /// Surfaces that aren't layered have a single layer stored this way.
+    Atlas,
+    /// Each layer is a layer of a `GL_TEXTURE_2D_ARRAY` the size of the surface.
+    Array {
+        /// One framebuffer object per layer, in order, with that layer attached via
+        /// `glFramebufferTextureLayer()`. The first is the surface's framebuffer object.
+        framebuffer_objects: Arc<[GLuint]>,
+        /// A framebuffer object with every layer attached as a `GL_OVR_multiview` view, for
+        /// rendering all of them in one pass, or 0 if the context lacks `GL_OVR_multiview`.
+        multiview_framebuffer_object: GLuint,
+    },
 }
 
 // The default framebuffer for a context.
@@ -66,8 +133,8 @@ impl Display for SurfaceID {
 pub enum SurfaceAccess {
     /// The surface data is accessible by the GPU only.
     /// 
-    /// The `lock_surface_data()` method will return the `SurfaceDataInaccessible` error when
-    /// called on this surface.
+    /// The `lock_surface_data()` and `upload_surface_data()` methods will return the
+    /// `SurfaceDataInaccessible` error when called on this surface.
     /// 
     /// This is typically the flag you will want to use.
     GPUOnly,
@@ -88,10 +155,113 @@ pub enum SurfaceAccess {
     /// This flag has no effect on correctness (at least on x86), but not following the rules
     /// above may result in severe performance consequences.
     /// 
-    /// The driver is free to treat this as identical to `GPUCPU`.
+    /// The driver is free to treat this as identical to `GPUCPU`. On EGL, uploads to such surfaces
+    /// are streamed through a pixel buffer object instead of being copied synchronously.
     GPUCPUWriteCombined,
 }
 
+/// The pixel format of a generic surface.
+/// 
+/// Not every backend supports every format. Use `Device::supported_surface_formats()` to find out
+/// which ones are available; creating a surface with any other format returns an
+/// `UnsupportedSurfaceFormat` error.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum SurfaceFormat {
+    /// 8-bit normalized red, green, blue, and alpha channels, in that order in memory.
+    RGBA8,
+    /// 8-bit normalized blue, green, red, and alpha channels, in that order in memory.
+    BGRA8,
+    /// 8-bit red, green, and blue channels in the sRGB color space, plus 8-bit linear alpha.
+    /// 
+    /// Rendering to a surface of this format encodes linear values to sRGB when
+    /// `GL_FRAMEBUFFER_SRGB` is enabled, and sampling from it decodes them again.
+    SRGB8A8,
+    /// 10-bit normalized red, green, and blue channels with a 2-bit alpha channel.
+    RGB10A2,
+    /// 16-bit floating point red, green, blue, and alpha channels.
+    RGBA16F,
+    /// A single 8-bit normalized red channel.
+    R8,
+    /// 8-bit normalized red and green channels.
+    RG8,
+}
+
+/// The texture targets that a caller is prepared to sample a surface texture through.
+/// 
+/// Textures wrapping surfaces that were created locally always use the target that
+/// `Device::surface_gl_texture_target()` returns. Textures wrapping images that came from
+/// elsewhere may have to be bound to `GL_TEXTURE_EXTERNAL_OES` instead, which shaders can only
+/// sample with a `samplerExternalOES`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SurfaceTextureTarget {
+    /// Any target that the image supports, including `GL_TEXTURE_EXTERNAL_OES`.
+    /// 
+    /// `GL_TEXTURE_2D` or `GL_TEXTURE_RECTANGLE` is still chosen whenever the driver allows it.
+    Any,
+    /// A target that can be sampled with an ordinary `sampler2D`, or `sampler2DRect` on macOS.
+    /// 
+    /// Creating the surface texture fails with an `UnsupportedTextureTarget` error if the driver
+    /// can only sample the image as an external texture.
+    Compatible2D,
+}
+
+/// The value of `GL_TEXTURE_EXTERNAL_OES`, which the OpenGL bindings don't include.
+/// 
+/// `Device::surface_texture_target()` returns this for textures that must be sampled with a
+/// `samplerExternalOES`.
+pub const GL_TEXTURE_EXTERNAL_OES: GLenum = 0x8d65;
+
+/// The color space that the contents of a widget surface are interpreted in when they're shown on
+/// screen.
+/// 
+/// Support varies by platform and display. Creating a widget surface with a color space that
+/// isn't available returns an `UnsupportedColorSpace` error.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ColorSpace {
+    /// The sRGB color space. Values written to the surface are encoded with the sRGB transfer
+    /// function when `GL_FRAMEBUFFER_SRGB` is enabled.
+    SRGB,
+    /// The sRGB primaries with a linear transfer function.
+    Linear,
+    /// The Display P3 color space, with the sRGB transfer function.
+    DisplayP3,
+    /// The extended-range scRGB color space with a linear transfer function.
+    /// 
+    /// This is typically paired with a floating-point framebuffer for HDR output.
+    SCRGBLinear,
+    /// The BT.2020 primaries with the SMPTE ST 2084 (PQ) transfer function, for HDR10 output.
+    BT2020PQ,
+}
+
+/// The format of a generic surface's depth buffer, and of its stencil buffer if it has one.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum DepthStencilFormat {
+    /// A 16-bit normalized depth buffer, with no stencil buffer.
+    Depth16,
+    /// A 24-bit normalized depth buffer, with no stencil buffer.
+    Depth24,
+    /// A 32-bit floating point depth buffer, with no stencil buffer.
+    Depth32F,
+    /// A 24-bit normalized depth buffer packed together with an 8-bit stencil buffer.
+    Depth24Stencil8,
+    /// A 32-bit floating point depth buffer packed together with an 8-bit stencil buffer.
+    Depth32FStencil8,
+}
+
+/// Describes the depth and stencil buffers of a generic surface.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct DepthStencilAttributes {
+    /// The format of the depth and stencil buffers.
+    pub format: DepthStencilFormat,
+    /// Whether the depth buffer is backed by a texture instead of a renderbuffer.
+    /// 
+    /// A sampleable depth buffer travels along with the surface when it's wrapped in a
+    /// `SurfaceTexture`; use `Device::surface_texture_depth_object()` to read it. Backends that
+    /// can't share depth textures between contexts return an `UnsupportedDepthStencilFormat`
+    /// error.
+    pub sampleable: bool,
+}
+
 /// Information specific to the type of surface: generic or widget.
 pub enum SurfaceType<NativeWidget> {
     /// An off-screen surface that has a pixel size. Generic surfaces can sometimes be shown on
@@ -101,7 +271,12 @@ pub enum SurfaceType<NativeWidget> {
         /// The size of the surface.
         /// 
         /// For HiDPI screens, this is a physical size, not a logical size.
-        size: Size2D<i32>
+        size: Size2D<i32>,
+        /// The pixel format of the surface.
+        format: SurfaceFormat,
+        /// How the depth and stencil buffers are allocated, or `None` to allocate them as the
+        /// context's `DEPTH` and `STENCIL` attribute flags request.
+        depth_stencil: Option<DepthStencilAttributes>,
     },
     /// A surface displayed inside a native widget (window or view). The size of a widget surface
     /// is automatically determined based on the size of the widget. (For example, if the widget is
@@ -112,7 +287,132 @@ pub enum SurfaceType<NativeWidget> {
         /// 
         /// For example, on Windows this wraps an `HWND`.
         native_widget: NativeWidget,
+        /// The color space of the surface, or `None` for the platform default.
+        color_space: Option<ColorSpace>,
     },
+    /// An off-screen `RGBA8` surface with several layers of the same size, such as one per eye
+    /// for stereo rendering.
+    /// 
+    /// On the Linux EGL backends, `SurfaceAccess::GPUOnly` surfaces whose context supports array
+    /// textures (OpenGL 3.0 or OpenGL ES 3.0) store their layers in a `GL_TEXTURE_2D_ARRAY`. Each
+    /// layer has its own framebuffer object, and all of them can be rendered at once through
+    /// `GL_OVR_multiview` where that's available; see `LayerStorage::Array`. Surface textures wrap
+    /// each layer in a texture of its own, returned by `Device::surface_texture_layer_object()`.
+    /// EGL can't share array textures between contexts, so each layer is copied into its texture
+    /// when the surface is unbound from its context.
+    /// 
+    /// Otherwise, the layers are laid out side by side, left to right, in a single 2D image that
+    /// renders through one framebuffer object; see `LayerStorage::Atlas`. Every layer's surface
+    /// texture object is then the same texture, and the layer occupies
+    /// `Device::surface_texture_layer_rect()` within it.
+    /// 
+    /// Either way, render to a layer by binding `SurfaceInfo::layer_framebuffer_object()` and
+    /// setting the viewport and scissor rectangle to `SurfaceInfo::layer_rect()`.
+    /// 
+    /// Handles exported from layered surfaces don't carry the layer count, so surface textures
+    /// created from them have a single layer. They hold the whole image of an atlas, or the
+    /// first layer of an array.
+    Layered {
+        /// The size of each layer.
+        /// 
+        /// For HiDPI screens, this is a physical size, not a logical size.
+        size: Size2D<i32>,
+        /// The number of layers.
+        layers: u32,
+    },
+}
+
+impl DepthStencilFormat {
+    /// Returns true if this format has a stencil buffer.
+    pub fn has_stencil(self) -> bool {
+        match self {
+            DepthStencilFormat::Depth16 |
+            DepthStencilFormat::Depth24 |
+            DepthStencilFormat::Depth32F => false,
+            DepthStencilFormat::Depth24Stencil8 | DepthStencilFormat::Depth32FStencil8 => true,
+        }
+    }
+
+    // The internal format, format, and type to pass to `glTexImage2D()` to allocate a depth
+    // texture of this format. The internal format is also valid for `glRenderbufferStorage()`.
+    pub(crate) fn gl_texture_formats(self) -> (GLenum, GLenum, GLenum) {
+        match self {
+            DepthStencilFormat::Depth16 => {
+                (gl::DEPTH_COMPONENT16, gl::DEPTH_COMPONENT, gl::UNSIGNED_SHORT)
+            }
+            DepthStencilFormat::Depth24 => {
+                (gl::DEPTH_COMPONENT24, gl::DEPTH_COMPONENT, gl::UNSIGNED_INT)
+            }
+            DepthStencilFormat::Depth32F => {
+                (gl::DEPTH_COMPONENT32F, gl::DEPTH_COMPONENT, gl::FLOAT)
+            }
+            DepthStencilFormat::Depth24Stencil8 => {
+                (gl::DEPTH24_STENCIL8, gl::DEPTH_STENCIL, gl::UNSIGNED_INT_24_8)
+            }
+            DepthStencilFormat::Depth32FStencil8 => {
+                (gl::DEPTH32F_STENCIL8, gl::DEPTH_STENCIL, gl::FLOAT_32_UNSIGNED_INT_24_8_REV)
+            }
+        }
+    }
+}
+
+// Returns the size of the image that holds `layers` layers of the given size side by side.
+#[allow(dead_code)]
+pub(crate) fn layered_surface_size(layer_size: &Size2D<i32>, layers: u32)
+                                   -> Result<Size2D<i32>, Error> {
+    let width = match i32::try_from(layers) {
+        Ok(layers) if layers > 0 => layer_size.width.checked_mul(layers),
+        _ => None,
+    };
+    match width {
+        Some(width) => Ok(Size2D::new(width, layer_size.height)),
+        None => Err(Error::UnsupportedLayerCount),
+    }
+}
+
+// Returns the region that the given layer occupies in a surface of the given total size with
+// `layers` layers side by side.
+pub(crate) fn layer_rect(size: &Size2D<i32>, layers: u32, layer: u32) -> Option<Rect<i32>> {
+    if layer >= layers {
+        return None;
+    }
+    let layer_size = Size2D::new(size.width / layers as i32, size.height);
+    Some(Rect::new(Point2D::new(layer as i32 * layer_size.width, 0), layer_size))
+}
+
+// Checks that `rect` lies within a surface of the given size and that `data_len` bytes spaced
+// `stride` bytes per row cover it in the given format.
+#[allow(dead_code)]
+pub(crate) fn check_upload_region(size: &Size2D<i32>,
+                                  rect: &Rect<i32>,
+                                  data_len: usize,
+                                  stride: usize,
+                                  format: SurfaceFormat)
+                                  -> Result<(), Error> {
+    let fits = |origin: i32, length: i32, limit: i32| {
+        match origin.checked_add(length) {
+            Some(end) => origin >= 0 && length >= 0 && end <= limit,
+            None => false,
+        }
+    };
+    if !fits(rect.origin.x, rect.size.width, size.width) ||
+            !fits(rect.origin.y, rect.size.height, size.height) {
+        return Err(Error::InvalidSurfaceData);
+    }
+
+    let bytes_per_pixel = format.bytes_per_pixel();
+    let row_size = rect.size.width as usize * bytes_per_pixel;
+    if stride % bytes_per_pixel != 0 || stride < row_size {
+        return Err(Error::InvalidSurfaceData);
+    }
+    let required_len = match rect.size.height as usize {
+        0 => 0,
+        rows => stride * (rows - 1) + row_size,
+    };
+    if data_len < required_len {
+        return Err(Error::InvalidSurfaceData);
+    }
+    Ok(())
 }
 
 impl SurfaceAccess {
@@ -125,3 +425,37 @@ impl SurfaceAccess {
         }
     }
 }
+
+impl Default for SurfaceFormat {
+    #[inline]
+    fn default() -> SurfaceFormat {
+        SurfaceFormat::RGBA8
+    }
+}
+
+impl SurfaceFormat {
+    /// Returns the number of bytes that a single pixel of this format occupies.
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            SurfaceFormat::R8 => 1,
+            SurfaceFormat::RG8 => 2,
+            SurfaceFormat::RGBA8 |
+            SurfaceFormat::BGRA8 |
+            SurfaceFormat::SRGB8A8 |
+            SurfaceFormat::RGB10A2 => 4,
+            SurfaceFormat::RGBA16F => 8,
+        }
+    }
+
+    /// Returns true if this format has an alpha channel.
+    pub fn has_alpha(self) -> bool {
+        match self {
+            SurfaceFormat::R8 | SurfaceFormat::RG8 => false,
+            SurfaceFormat::RGBA8 |
+            SurfaceFormat::BGRA8 |
+            SurfaceFormat::SRGB8A8 |
+            SurfaceFormat::RGB10A2 |
+            SurfaceFormat::RGBA16F => true,
+        }
+    }
+}
@@ -9,16 +9,36 @@
 #![allow(missing_docs)]
 
 use crate::gl::types::{GLenum, GLuint};
+#[cfg(target_os = "linux")]
+use crate::gl::types::GLint;
 use crate::gl;
+#[cfg(unix)]
+use crate::ipc;
+use crate::managed;
 use crate::{ContextAttributeFlags, ContextAttributes, Error, GLApi, GLVersion, Gl, SurfaceAccess};
-use crate::{SurfaceType, WindowingApiError};
+use crate::{DepthStencilAttributes, DepthStencilFormat, LayerStorage, LeakPolicy, SurfaceFormat};
+use crate::SurfaceTextureTarget;
+use crate::SurfaceType;
+use crate::{WindowingApiError, WindowingApiFailure};
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use crate::ipc::DmaBufPlane;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use crate::video::{PlanarFormat, PlanarImage, YUVColorSpace, YUVRange};
 use super::connection::{Connection, NativeConnection};
 use super::context::{Context, ContextDescriptor, NativeContext};
 use super::device::{Adapter, Device};
 use super::surface::Surface;
+#[cfg(target_os = "linux")]
+use super::surface::NativeImage;
 
-use euclid::default::Size2D;
+use euclid::default::{Point2D, Rect, Size2D};
+use std::error::Error as StdError;
 use std::os::raw::c_void;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(target_os = "linux")]
+use std::ptr;
+use std::rc::Rc;
 use std::sync::mpsc;
 use std::thread;
 
@@ -77,6 +97,74 @@ pub fn test_device_accessors() {
     drop(device.gl_api());
 }
 
+// Tests that the capability report agrees with what the device does.
+#[cfg_attr(not(feature = "sm-test"), test)]
+pub fn test_device_capabilities() {
+    let mut env = match BasicEnvironment::new() {
+        None => return,
+        Some(env) => env,
+    };
+
+    let capabilities = env.device.capabilities();
+    assert_eq!(capabilities.surface_texture_target, env.device.surface_gl_texture_target());
+    for extension in &capabilities.platform_extensions {
+        assert!(capabilities.supports_platform_extension(extension));
+    }
+    assert!(!capabilities.supports_platform_extension("EGL_SURFMAN_nonexistent"));
+    if !capabilities.widget_surfaces {
+        assert!(!capabilities.damage_present);
+    }
+
+    let size = Size2D::new(16, 16);
+    let mut surface = env.device.create_surface(&env.context,
+                                                SurfaceAccess::GPUCPU,
+                                                SurfaceType::Generic {
+                                                    size,
+                                                    format: SurfaceFormat::RGBA8,
+                                                    depth_stencil: None,
+                                                }).unwrap();
+    let pixels = vec![0; 16 * 16 * 4];
+    let result = env.device.upload_surface_data(&env.context,
+                                                &mut surface,
+                                                &Rect::new(Point2D::zero(), size),
+                                                &pixels,
+                                                16 * 4,
+                                                SurfaceFormat::RGBA8);
+    assert_eq!(result.is_ok(), capabilities.cpu_surface_uploads);
+    env.device.destroy_surface(&mut env.context, &mut surface).unwrap();
+
+    env.device.destroy_context(&mut env.context).unwrap();
+}
+
+// Tests that errors carry the native details of failures, report them as their source, and
+// convert into boxed errors.
+#[cfg_attr(not(feature = "sm-test"), test)]
+pub fn test_error_reporting() {
+    let failure = WindowingApiFailure::new("eglCreateImageKHR",
+                                           WindowingApiError::BadMatch,
+                                           Some(0x3009));
+    let error = Error::SurfaceImportFailed(Box::new(failure));
+    assert_eq!(error.windowing_api_failure(), Some(&failure));
+    assert_eq!(error.native_code(), Some(0x3009));
+    assert_eq!(error.failed_call(), Some("eglCreateImageKHR"));
+    assert_eq!(error.to_string(),
+               "failed to import the surface: eglCreateImageKHR failed: BadMatch (0x3009)");
+    let source = error.source().and_then(|source| source.downcast_ref::<WindowingApiFailure>());
+    assert_eq!(source, Some(&failure));
+
+    let error = Error::ContextCreationFailed(WindowingApiError::Other(0x1234).into());
+    assert_eq!(error.native_code(), Some(0x1234));
+    assert_eq!(error.failed_call(), None);
+    assert_eq!(error.to_string(), "failed to create the context: error 0x1234");
+
+    assert_eq!(Error::NoAdapterFound.windowing_api_failure(), None);
+    assert_eq!(Error::NoAdapterFound.native_code(), None);
+    assert!(Error::NoAdapterFound.source().is_none());
+
+    let boxed: Box<dyn StdError + Send + Sync> = Error::Unimplemented.into();
+    assert_eq!(boxed.to_string(), "not implemented on this platform yet");
+}
+
 // Tests that all combinations of flags result in the creation of valid context descriptors and
 // contexts.
 #[cfg_attr(not(feature = "sm-test"), test)]
@@ -101,7 +189,7 @@ pub fn test_context_creation() {
     for &version in versions {
         for flag_bits in 0..(ContextAttributeFlags::all().bits() + 1) {
             let flags = ContextAttributeFlags::from_bits_truncate(flag_bits);
-            let attributes = ContextAttributes { version, flags };
+            let attributes = ContextAttributes::new(version, flags);
             let descriptor = match device.create_context_descriptor(&attributes) {
                 Ok(descriptor) => descriptor,
                 Err(Error::UnsupportedGLProfile) | Err(Error::UnsupportedGLVersion) => {
@@ -117,11 +205,18 @@ pub fn test_context_creation() {
                     let actual_descriptor = device.context_descriptor(&context);
                     let actual_attributes =
                         device.context_descriptor_attributes(&actual_descriptor);
-                    if !actual_attributes.flags.contains(attributes.flags) {
+                    // Contexts report the profile they got, which may be core if that was
+                    // allowed.
+                    let mut expected_flags = attributes.flags;
+                    if expected_flags.contains(ContextAttributeFlags::ALLOW_CORE_PROFILE) {
+                        expected_flags.remove(ContextAttributeFlags::ALLOW_CORE_PROFILE |
+                                              ContextAttributeFlags::COMPATIBILITY_PROFILE);
+                    }
+                    if !actual_attributes.flags.contains(expected_flags) {
                         device.destroy_context(&mut context).unwrap();
                         panic!("Expected at least attribute flags {:?} for {:?} {:?} but got \
                                 {:?}",
-                               attributes.flags,
+                               expected_flags,
                                gl_api,
                                version,
                                actual_attributes.flags);
@@ -137,9 +232,11 @@ pub fn test_context_creation() {
 
                     device.destroy_context(&mut context).unwrap();
                 }
-                Err(Error::ContextCreationFailed(WindowingApiError::BadPixelFormat)) |
-                Err(Error::ContextCreationFailed(WindowingApiError::BadConfig)) |
-                Err(Error::ContextCreationFailed(WindowingApiError::BadMatch)) => {
+                Err(Error::ContextCreationFailed(ref failure))
+                        if matches!(failure.error,
+                                    WindowingApiError::BadPixelFormat |
+                                    WindowingApiError::BadConfig |
+                                    WindowingApiError::BadMatch) => {
                     // This is OK, as it just means the GL implementation didn't support the
                     // requested GL version.
                 }
@@ -151,6 +248,216 @@ pub fn test_context_creation() {
     }
 }
 
+// Tests that enumerated context descriptors agree with their pixel formats and that the caller's
+// choice of pixel format is honored.
+#[cfg_attr(not(feature = "sm-test"), test)]
+pub fn test_context_descriptor_enumeration() {
+    let connection = Connection::new().unwrap();
+    let adapter = connection.create_low_power_adapter().expect("Failed to create adapter!");
+    let mut device = match connection.create_device(&adapter) {
+        Ok(device) => device,
+        Err(Error::RequiredExtensionUnavailable) => {
+            // Can't run these tests on this hardware.
+            return;
+        }
+        Err(err) => panic!("Failed to create device: {:?}", err),
+    };
+
+    let version = match device.gl_api() {
+        GLApi::GL => GL_VERSIONS[0],
+        GLApi::GLES => GL_ES_VERSIONS[0],
+    };
+    let attributes = ContextAttributes::new(version, ContextAttributeFlags::empty());
+
+    let candidates = device.enumerate_context_descriptors(&attributes).unwrap();
+    assert!(!candidates.is_empty());
+    for (descriptor, pixel_format) in &candidates {
+        let flags = device.context_descriptor_attributes(descriptor).flags;
+        assert_eq!(flags.contains(ContextAttributeFlags::ALPHA), pixel_format.alpha_size != 0);
+        assert_eq!(flags.contains(ContextAttributeFlags::DEPTH), pixel_format.depth_size != 0);
+        assert_eq!(flags.contains(ContextAttributeFlags::STENCIL),
+                   pixel_format.stencil_size != 0);
+    }
+
+    // Ask for a pixel format with stencil, if there is one, and check that we get it.
+    let wants_stencil = candidates.iter().any(|&(_, ref pixel_format)| {
+        pixel_format.stencil_size != 0
+    });
+    let descriptor = device.create_context_descriptor_with(&attributes, |pixel_formats| {
+        pixel_formats.iter().position(|pixel_format| {
+            (pixel_format.stencil_size != 0) == wants_stencil
+        })
+    }).unwrap();
+    let mut context = device.create_context(&descriptor).unwrap();
+    let actual_descriptor = device.context_descriptor(&context);
+    let actual_flags = device.context_descriptor_attributes(&actual_descriptor).flags;
+    assert_eq!(actual_flags.contains(ContextAttributeFlags::STENCIL), wants_stencil);
+    device.destroy_context(&mut context).unwrap();
+
+    // Declining every pixel format is an error.
+    match device.create_context_descriptor_with(&attributes, |_| None) {
+        Err(Error::NoPixelFormatFound) => {}
+        Err(err) => panic!("Expected `NoPixelFormatFound` but got {:?}", err),
+        Ok(_) => panic!("Expected `NoPixelFormatFound` but got a descriptor"),
+    }
+}
+
+// Tests that context descriptor diagnostics agree with whether descriptor creation succeeds.
+#[cfg_attr(not(feature = "sm-test"), test)]
+pub fn test_context_descriptor_diagnostics() {
+    let connection = Connection::new().unwrap();
+    let adapter = connection.create_low_power_adapter().expect("Failed to create adapter!");
+    let device = match connection.create_device(&adapter) {
+        Ok(device) => device,
+        Err(Error::RequiredExtensionUnavailable) => {
+            // Can't run these tests on this hardware.
+            return;
+        }
+        Err(err) => panic!("Failed to create device: {:?}", err),
+    };
+
+    let version = match device.gl_api() {
+        GLApi::GL => GL_VERSIONS[0],
+        GLApi::GLES => GL_ES_VERSIONS[0],
+    };
+    for flag_bits in 0..(ContextAttributeFlags::all().bits() + 1) {
+        let flags = ContextAttributeFlags::from_bits_truncate(flag_bits);
+        let attributes = ContextAttributes::new(version, flags);
+        let diagnostics = device.diagnose_context_descriptor(&attributes);
+        assert_eq!(diagnostics.requested, attributes);
+        assert!(diagnostics.rejected.iter().all(|rejected| !rejected.mismatches.is_empty()));
+        assert!(diagnostics.to_string().starts_with("requested GL"));
+
+        match device.create_context_descriptor(&attributes) {
+            Ok(_) => {
+                assert!(diagnostics.error.is_none());
+                assert!(!diagnostics.accepted.is_empty());
+            }
+            Err(Error::NoPixelFormatFound) => {
+                assert!(diagnostics.accepted.is_empty());
+                assert!(diagnostics.nearest().is_some() || diagnostics.rejected.is_empty());
+            }
+            Err(Error::UnsupportedGLProfile) => {
+                match diagnostics.error {
+                    Some(Error::UnsupportedGLProfile) => {}
+                    ref error => panic!("Expected `UnsupportedGLProfile` but got {:?}", error),
+                }
+            }
+            Err(err) => panic!("Context descriptor creation failed: {:?}", err),
+        }
+    }
+}
+
+// Tests that asking for a range of GL versions yields a context somewhere in that range, and that
+// the context reports the version it actually got.
+#[cfg_attr(not(feature = "sm-test"), test)]
+pub fn test_context_version_negotiation() {
+    let connection = Connection::new().unwrap();
+    let adapter = connection.create_low_power_adapter().expect("Failed to create adapter!");
+    let mut device = match connection.create_device(&adapter) {
+        Ok(device) => device,
+        Err(Error::RequiredExtensionUnavailable) => {
+            // Can't run these tests on this hardware.
+            return;
+        }
+        Err(err) => panic!("Failed to create device: {:?}", err),
+    };
+
+    let versions = match device.gl_api() {
+        GLApi::GL => &GL_VERSIONS[..],
+        GLApi::GLES => &GL_ES_VERSIONS[..],
+    };
+    let (minimum_version, preferred_version) = (versions[0], versions[versions.len() - 1]);
+    for &flags in &[
+        ContextAttributeFlags::empty(),
+        ContextAttributeFlags::COMPATIBILITY_PROFILE | ContextAttributeFlags::ALLOW_CORE_PROFILE,
+    ] {
+        let attributes = ContextAttributes {
+            minimum_version: Some(minimum_version),
+            ..ContextAttributes::new(preferred_version, flags)
+        };
+        let descriptor = device.create_context_descriptor(&attributes).unwrap();
+        let mut context = device.create_context(&descriptor).unwrap();
+
+        let actual_descriptor = device.context_descriptor(&context);
+        let actual_version = device.context_descriptor_attributes(&actual_descriptor).version;
+        device.destroy_context(&mut context).unwrap();
+        assert!(actual_version >= minimum_version,
+                "Expected at least GL version {:?} but got {:?}",
+                minimum_version,
+                actual_version);
+    }
+
+    // An empty range behaves like a plain version request.
+    let attributes = ContextAttributes {
+        minimum_version: Some(preferred_version),
+        ..ContextAttributes::new(minimum_version, ContextAttributeFlags::empty())
+    };
+    let descriptor = device.create_context_descriptor(&attributes).unwrap();
+    let mut context = device.create_context(&descriptor).unwrap();
+    device.destroy_context(&mut context).unwrap();
+}
+
+// Tests that contexts of either OpenGL API flavor can be requested, and that they report the flavor
+// they got.
+#[cfg_attr(not(feature = "sm-test"), test)]
+pub fn test_context_api_selection() {
+    let connection = Connection::new().unwrap();
+    let adapter = connection.create_low_power_adapter().expect("Failed to create adapter!");
+    let mut device = match connection.create_device(&adapter) {
+        Ok(device) => device,
+        Err(Error::RequiredExtensionUnavailable) => {
+            // Can't run these tests on this hardware.
+            return;
+        }
+        Err(err) => panic!("Failed to create device: {:?}", err),
+    };
+
+    for &gl_api in &[GLApi::GL, GLApi::GLES] {
+        let versions = match gl_api {
+            GLApi::GL => &GL_VERSIONS[..],
+            GLApi::GLES => &GL_ES_VERSIONS[..],
+        };
+        let attributes = ContextAttributes {
+            api: Some(gl_api),
+            minimum_version: Some(versions[0]),
+            ..ContextAttributes::new(versions[versions.len() - 1], ContextAttributeFlags::empty())
+        };
+        let descriptor = match device.create_context_descriptor(&attributes) {
+            Ok(descriptor) => descriptor,
+            Err(Error::UnsupportedGLType) if gl_api != device.gl_api() => {
+                // This device only supports its default flavor.
+                continue
+            }
+            Err(err) => panic!("Context descriptor creation failed: {:?}", err),
+        };
+        assert_eq!(device.context_descriptor_attributes(&descriptor).api, Some(gl_api));
+
+        let mut context = device.create_context(&descriptor).unwrap();
+        assert_eq!(device.context_gl_api(&context), gl_api);
+        let actual_descriptor = device.context_descriptor(&context);
+        assert_eq!(device.context_descriptor_attributes(&actual_descriptor).api, Some(gl_api));
+
+        // Make sure the context can render.
+        let gl = Gl::load_with(|symbol| device.get_proc_address(&context, symbol));
+        let surface = make_surface(&mut device, &context);
+        device.bind_surface_to_context(&mut context, surface).unwrap();
+        device.make_context_current(&context).unwrap();
+        bind_context_fbo(&gl, &device, &context);
+        clear(&gl, &[0, 255, 0, 255]);
+        assert_eq!(get_pixel_from_bottom_row(&gl), [0, 255, 0, 255]);
+
+        device.destroy_context(&mut context).unwrap();
+    }
+
+    // Without a request, contexts use the device's default flavor.
+    let attributes = ContextAttributes::new(GLVersion::new(2, 0), ContextAttributeFlags::empty());
+    let descriptor = device.create_context_descriptor(&attributes).unwrap();
+    let mut context = device.create_context(&descriptor).unwrap();
+    assert_eq!(device.context_gl_api(&context), device.gl_api());
+    device.destroy_context(&mut context).unwrap();
+}
+
 // Tests that newly-created contexts are not immediately made current (issue #7).
 #[cfg_attr(not(feature = "sm-test"), test)]
 pub fn test_newly_created_contexts_are_not_current() {
@@ -165,10 +472,8 @@ pub fn test_newly_created_contexts_are_not_current() {
         Err(err) => panic!("Failed to create device: {:?}", err),
     };
 
-    let context_descriptor = device.create_context_descriptor(&ContextAttributes {
-        version: GLVersion::new(3, 0),
-        flags: ContextAttributeFlags::empty(),
-    }).unwrap();
+    let attributes = ContextAttributes::new(GLVersion::new(3, 0), ContextAttributeFlags::empty());
+    let context_descriptor = device.create_context_descriptor(&attributes).unwrap();
 
     // Make no context current.
     device.make_no_context_current().unwrap();
@@ -223,10 +528,8 @@ pub fn test_generic_surface_creation() {
         Err(err) => panic!("Failed to create device: {:?}", err),
     };
 
-    let descriptor = device.create_context_descriptor(&ContextAttributes {
-        version: GLVersion::new(3, 0),
-        flags: ContextAttributeFlags::empty(),
-    }).unwrap();
+    let attributes = ContextAttributes::new(GLVersion::new(3, 0), ContextAttributeFlags::empty());
+    let descriptor = device.create_context_descriptor(&attributes).unwrap();
 
     let mut context = device.create_context(&descriptor).unwrap();
     let context_id = device.context_id(&context);
@@ -238,6 +541,8 @@ pub fn test_generic_surface_creation() {
     ].iter().map(|&access| {
         let surface = device.create_surface(&context, access, SurfaceType::Generic {
             size: Size2D::new(640, 480),
+            format: SurfaceFormat::RGBA8,
+            depth_stencil: None,
         }).unwrap();
         let info = device.surface_info(&surface);
         assert_eq!(info.size, Size2D::new(640, 480));
@@ -261,6 +566,129 @@ pub fn test_generic_surface_creation() {
     device.destroy_context(&mut context).unwrap();
 }
 
+// Tests that surfaces can be created and rendered to in every format the device claims to support,
+// and that other formats are rejected.
+#[cfg_attr(not(feature = "sm-test"), test)]
+pub fn test_surface_formats() {
+    let mut env = match BasicEnvironment::new() {
+        None => return,
+        Some(env) => env,
+    };
+
+    let supported_formats = env.device.supported_surface_formats(&env.context);
+    assert!(supported_formats.contains(&SurfaceFormat::RGBA8));
+
+    // OpenGL ES 2.0 has no sized internal formats, so only `RGBA8` can be offered.
+    if env.device.gl_api() == GLApi::GLES {
+        let es2_attributes = ContextAttributes::new(GLVersion::new(2, 0),
+                                                    ContextAttributeFlags::empty());
+        let es2_descriptor = env.device.create_context_descriptor(&es2_attributes).unwrap();
+        let mut es2_context = env.device.create_context(&es2_descriptor).unwrap();
+        let es2_formats = env.device.supported_surface_formats(&es2_context);
+        if env.device.gl_info(&es2_context).unwrap().version < GLVersion::new(3, 0) {
+            assert_eq!(es2_formats, vec![SurfaceFormat::RGBA8]);
+        }
+        env.device.destroy_context(&mut es2_context).unwrap();
+    }
+
+    let old_surface = env.device.unbind_surface_from_context(&mut env.context).unwrap().unwrap();
+
+    for &format in &[
+        SurfaceFormat::RGBA8,
+        SurfaceFormat::BGRA8,
+        SurfaceFormat::SRGB8A8,
+        SurfaceFormat::RGB10A2,
+        SurfaceFormat::RGBA16F,
+        SurfaceFormat::R8,
+        SurfaceFormat::RG8,
+    ] {
+        let surface_type = SurfaceType::Generic {
+            size: Size2D::new(640, 480),
+            format,
+            depth_stencil: None,
+        };
+        let surface = match env.device.create_surface(&env.context,
+                                                      SurfaceAccess::GPUOnly,
+                                                      surface_type) {
+            Ok(surface) => surface,
+            Err(Error::UnsupportedSurfaceFormat) => {
+                assert!(!supported_formats.contains(&format));
+                continue;
+            }
+            Err(err) => panic!("Failed to create {:?} surface: {:?}", format, err),
+        };
+        assert!(supported_formats.contains(&format));
+
+        env.device.bind_surface_to_context(&mut env.context, surface).unwrap();
+        env.device.make_context_current(&env.context).unwrap();
+        bind_context_fbo(&env.gl, &env.device, &env.context);
+
+        unsafe {
+            assert_eq!(env.gl.CheckFramebufferStatus(gl::FRAMEBUFFER),
+                       gl::FRAMEBUFFER_COMPLETE);
+            clear(&env.gl, &[255, 255, 255, 255]);
+            // OpenGL ES can't read floating point framebuffers as bytes.
+            if format != SurfaceFormat::RGBA16F {
+                assert_eq!(get_pixel_from_bottom_row(&env.gl)[0], 255);
+            }
+        }
+
+        let mut surface = env.device.unbind_surface_from_context(&mut env.context)
+                                    .unwrap()
+                                    .unwrap();
+        env.device.destroy_surface(&mut env.context, &mut surface).unwrap();
+    }
+
+    env.device.bind_surface_to_context(&mut env.context, old_surface).unwrap();
+    env.device.destroy_context(&mut env.context).unwrap();
+}
+
+// Tests that version strings parse and that context info matches what the context was created
+// with.
+#[cfg_attr(not(feature = "sm-test"), test)]
+pub fn test_gl_info() {
+    let version_strings = [
+        ("4.6.0 NVIDIA 440.82", Some(GLVersion::new(4, 6))),
+        ("OpenGL ES 3.2 Mesa 20.0.8", Some(GLVersion::new(3, 2))),
+        ("OpenGL ES-CM 1.1", Some(GLVersion::new(1, 1))),
+        ("3.3 (Core Profile) Mesa 19.2.8", Some(GLVersion::new(3, 3))),
+        ("4.5.14008 Compatibility Profile Context 21.30.15.03", Some(GLVersion::new(4, 5))),
+        ("2.1 ATI-3.10.18", Some(GLVersion::new(2, 1))),
+        ("", None),
+        ("unknown", None),
+    ];
+    for &(string, expected) in &version_strings {
+        assert_eq!(GLVersion::parse(string), expected, "parsing {:?}", string);
+    }
+
+    let mut env = match BasicEnvironment::new() {
+        None => return,
+        Some(env) => env,
+    };
+
+    let gl_info = env.device.gl_info(&env.context).unwrap();
+    assert_eq!(gl_info.api, env.device.context_gl_api(&env.context));
+
+    // Some backends create a newer context than the one requested, so only check the lower bound.
+    let context_descriptor = env.device.context_descriptor(&env.context);
+    let attributes = env.device.context_descriptor_attributes(&context_descriptor);
+    assert!(gl_info.version >= attributes.version);
+
+    assert!(!gl_info.vendor.is_empty());
+    assert!(!gl_info.renderer.is_empty());
+    assert!(!gl_info.extensions.is_empty());
+    for extension in &gl_info.extensions {
+        assert!(gl_info.supports_extension(extension));
+    }
+    assert!(!gl_info.supports_extension("GL_SURFMAN_nonexistent"));
+
+    // The second query is answered from the cache.
+    let cached_gl_info = env.device.gl_info(&env.context).unwrap();
+    assert!(Rc::ptr_eq(&cached_gl_info, &gl_info));
+
+    env.device.destroy_context(&mut env.context).unwrap();
+}
+
 // Tests that basic GL commands work.
 #[cfg_attr(not(feature = "sm-test"), test)]
 pub fn test_gl() {
@@ -356,6 +784,10 @@ pub fn test_surface_texture_blit_framebuffer() {
                                        .create_surface_texture(&mut env.context, green_surface)
                                        .unwrap();
 
+        // Textures wrapping local surfaces always use the device's target.
+        assert_eq!(env.device.surface_texture_target(&green_surface_texture),
+                   env.device.surface_gl_texture_target());
+
         let main_surface = make_surface(&mut env.device, &env.context);
         env.device.bind_surface_to_context(&mut env.context, main_surface).unwrap();
 
@@ -593,10 +1025,9 @@ pub fn test_depth_and_stencil() {
 
     // Check depth.
 
-    let depth_context_descriptor = device.create_context_descriptor(&ContextAttributes {
-        version: GLVersion::new(3, 0),
-        flags: ContextAttributeFlags::DEPTH,
-    }).unwrap();
+    let depth_attributes = ContextAttributes::new(GLVersion::new(3, 0),
+                                                  ContextAttributeFlags::DEPTH);
+    let depth_context_descriptor = device.create_context_descriptor(&depth_attributes).unwrap();
 
     let mut depth_context = device.create_context(&depth_context_descriptor).unwrap();
     let depth_surface = make_surface(&mut device, &depth_context);
@@ -631,10 +1062,9 @@ pub fn test_depth_and_stencil() {
 
     // Check stencil.
 
-    let stencil_context_descriptor = device.create_context_descriptor(&ContextAttributes {
-        version: GLVersion::new(3, 0),
-        flags: ContextAttributeFlags::STENCIL,
-    }).unwrap();
+    let stencil_attributes = ContextAttributes::new(GLVersion::new(3, 0),
+                                                    ContextAttributeFlags::STENCIL);
+    let stencil_context_descriptor = device.create_context_descriptor(&stencil_attributes).unwrap();
 
     let mut stencil_context = device.create_context(&stencil_context_descriptor).unwrap();
     let stencil_surface = make_surface(&mut device, &stencil_context);
@@ -668,6 +1098,229 @@ pub fn test_depth_and_stencil() {
     device.destroy_context(&mut stencil_context).unwrap();
 }
 
+// Make sure that each layer of a layered surface can be rendered to separately and then found
+// again in the surface texture, whether the layers are stored in an array texture or side by side.
+#[cfg_attr(not(feature = "sm-test"), test)]
+pub fn test_layered_surface() {
+    let mut env = match BasicEnvironment::new() {
+        None => return,
+        Some(env) => env,
+    };
+
+    let layer_size = Size2D::new(320, 240);
+    let mut context = env.device.create_context(&env.context_descriptor).unwrap();
+    match env.device.create_surface(&context,
+                                    SurfaceAccess::GPUOnly,
+                                    SurfaceType::Layered { size: layer_size, layers: 0 }) {
+        Err(Error::UnsupportedLayerCount) => {}
+        Err(err) => panic!("Expected `UnsupportedLayerCount` but got {:?}!", err),
+        Ok(_) => panic!("Shouldn't be able to create a surface with no layers!"),
+    }
+
+    // Array textures can't be written to from the CPU, so surfaces that allow it always lay their
+    // layers out side by side.
+    for &access in &[SurfaceAccess::GPUOnly, SurfaceAccess::GPUCPU] {
+        let surface = env.device
+                         .create_surface(&context,
+                                         access,
+                                         SurfaceType::Layered { size: layer_size, layers: 2 })
+                         .unwrap();
+        let info = env.device.surface_info(&surface);
+        assert_eq!(info.layers, 2);
+        match info.layer_storage {
+            LayerStorage::Atlas => {
+                assert_eq!(info.size, Size2D::new(640, 240));
+                assert_eq!(info.layer_rect(0), Some(Rect::new(Point2D::new(0, 0), layer_size)));
+                assert_eq!(info.layer_rect(1),
+                           Some(Rect::new(Point2D::new(320, 0), layer_size)));
+                assert_eq!(info.layer_framebuffer_object(1), Some(info.framebuffer_object));
+            }
+            LayerStorage::Array { ref framebuffer_objects, .. } => {
+                assert_eq!(access, SurfaceAccess::GPUOnly);
+                assert_eq!(info.size, layer_size);
+                assert_eq!(framebuffer_objects.len(), 2);
+                assert_eq!(framebuffer_objects[0], info.framebuffer_object);
+                assert_ne!(framebuffer_objects[0], framebuffer_objects[1]);
+                assert_eq!(info.layer_rect(1), Some(Rect::new(Point2D::zero(), layer_size)));
+                assert_eq!(info.layer_framebuffer_object(1), Some(framebuffer_objects[1]));
+            }
+        }
+        assert_eq!(info.layer_rect(2), None);
+        assert_eq!(info.layer_framebuffer_object(2), None);
+
+        env.device.bind_surface_to_context(&mut context, surface).unwrap();
+        env.device.make_context_current(&context).unwrap();
+
+        // Clear the first layer to red and the second layer to green. Where the layers can be
+        // rendered in one pass, make sure that clearing through the multiview framebuffer clears
+        // them all first.
+        let info = env.device.context_surface_info(&context).unwrap().unwrap();
+        unsafe {
+            if let LayerStorage::Array { multiview_framebuffer_object, .. } = info.layer_storage {
+                if multiview_framebuffer_object != 0 {
+                    env.gl.BindFramebuffer(gl::FRAMEBUFFER, multiview_framebuffer_object);
+                    clear(&env.gl, &[0, 0, 255, 255]);
+                    env.gl.BindFramebuffer(gl::FRAMEBUFFER,
+                                           info.layer_framebuffer_object(1).unwrap());
+                    assert_eq!(get_pixel_from_bottom_row(&env.gl), [0, 0, 255, 255]);
+                }
+            }
+            env.gl.Enable(gl::SCISSOR_TEST);
+            for &(layer, color) in &[(0, [255, 0, 0, 255]), (1, [0, 255, 0, 255])] {
+                let rect = info.layer_rect(layer).unwrap();
+                env.gl.BindFramebuffer(gl::FRAMEBUFFER,
+                                       info.layer_framebuffer_object(layer).unwrap());
+                env.gl.Viewport(rect.origin.x, rect.origin.y, rect.size.width, rect.size.height);
+                env.gl.Scissor(rect.origin.x, rect.origin.y, rect.size.width, rect.size.height);
+                clear(&env.gl, &color);
+            }
+            env.gl.Disable(gl::SCISSOR_TEST);
+            check_gl(&env.gl);
+        }
+
+        let surface = env.device.unbind_surface_from_context(&mut context).unwrap().unwrap();
+        let surface_texture = env.device
+                                 .create_surface_texture(&mut env.context, surface)
+                                 .unwrap();
+        assert_eq!(env.device.surface_texture_layer_rect(&surface_texture, 1),
+                   info.layer_rect(1));
+        assert_eq!(env.device.surface_texture_layer_rect(&surface_texture, 2), None);
+        assert_eq!(env.device.surface_texture_layer_object(&surface_texture, 0),
+                   Some(env.device.surface_texture_object(&surface_texture)));
+        assert_eq!(env.device.surface_texture_layer_object(&surface_texture, 2), None);
+
+        env.device.make_context_current(&env.context).unwrap();
+        unsafe {
+            for &(layer, color) in &[(0, [255, 0, 0, 255]), (1, [0, 255, 0, 255])] {
+                let texture_object =
+                    env.device.surface_texture_layer_object(&surface_texture, layer).unwrap();
+                let framebuffer_object =
+                    make_fbo(&env.gl, env.device.surface_gl_texture_target(), texture_object);
+                let rect = env.device.surface_texture_layer_rect(&surface_texture, layer).unwrap();
+                let mut pixel: [u8; 4] = [0; 4];
+                env.gl.ReadPixels(rect.max_x() - 1,
+                                  rect.origin.y,
+                                  1,
+                                  1,
+                                  gl::RGBA,
+                                  gl::UNSIGNED_BYTE,
+                                  pixel.as_mut_ptr() as *mut c_void);
+                assert_eq!(pixel, color);
+                env.gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+                env.gl.DeleteFramebuffers(1, &framebuffer_object);
+            }
+            check_gl(&env.gl);
+        }
+
+        let mut surface = env.device
+                             .destroy_surface_texture(&mut env.context, surface_texture)
+                             .unwrap();
+        env.device.destroy_surface(&mut context, &mut surface).unwrap();
+    }
+
+    env.device.destroy_context(&mut context).unwrap();
+    env.device.destroy_context(&mut env.context).unwrap();
+}
+
+// Make sure that each depth/stencil format can be allocated and cleared, and that sampleable depth
+// buffers can be read from another context through the surface texture.
+#[cfg(not(target_os = "android"))]
+#[cfg_attr(not(feature = "sm-test"), test)]
+pub fn test_depth_stencil_formats() {
+    let mut env = match BasicEnvironment::new() {
+        None => return,
+        Some(env) => env,
+    };
+
+    // Can't readback from the depth or stencil buffer on OpenGL ES, so just disable this test.
+    if env.device.gl_api() == GLApi::GLES {
+        env.device.destroy_context(&mut env.context).unwrap();
+        return;
+    }
+
+    for &format in &[
+        DepthStencilFormat::Depth16,
+        DepthStencilFormat::Depth24,
+        DepthStencilFormat::Depth32F,
+        DepthStencilFormat::Depth24Stencil8,
+        DepthStencilFormat::Depth32FStencil8,
+    ] {
+        for &sampleable in &[false, true] {
+            let mut context = env.device.create_context(&env.context_descriptor).unwrap();
+            let surface_type = SurfaceType::Generic {
+                size: Size2D::new(640, 480),
+                format: SurfaceFormat::RGBA8,
+                depth_stencil: Some(DepthStencilAttributes { format, sampleable }),
+            };
+            let surface = match env.device.create_surface(&context,
+                                                          SurfaceAccess::GPUOnly,
+                                                          surface_type) {
+                Ok(surface) => surface,
+                Err(Error::UnsupportedDepthStencilFormat) if sampleable => {
+                    env.device.destroy_context(&mut context).unwrap();
+                    continue;
+                }
+                Err(err) => panic!("Failed to create surface: {:?}", err),
+            };
+            env.device.bind_surface_to_context(&mut context, surface).unwrap();
+            env.device.make_context_current(&context).unwrap();
+
+            unsafe {
+                bind_context_fbo(&env.gl, &env.device, &context);
+                assert_eq!(env.gl.CheckFramebufferStatus(gl::FRAMEBUFFER),
+                           gl::FRAMEBUFFER_COMPLETE);
+
+                env.gl.ClearDepth(0.5);
+                env.gl.ClearStencil(99);
+                env.gl.Clear(gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
+                assert!(approx_eq(get_depth_from_bottom_row(&env.gl), 0.5));
+                if format.has_stencil() {
+                    assert_eq!(get_stencil_from_bottom_row(&env.gl), 99);
+                }
+                check_gl(&env.gl);
+            }
+
+            let surface = env.device.unbind_surface_from_context(&mut context).unwrap().unwrap();
+            let surface_texture = env.device
+                                     .create_surface_texture(&mut env.context, surface)
+                                     .unwrap();
+            let depth_texture = env.device.surface_texture_depth_object(&surface_texture);
+            assert_eq!(depth_texture.is_some(), sampleable);
+
+            if let Some(depth_texture) = depth_texture {
+                env.device.make_context_current(&env.context).unwrap();
+                unsafe {
+                    let attachment = if format.has_stencil() {
+                        gl::DEPTH_STENCIL_ATTACHMENT
+                    } else {
+                        gl::DEPTH_ATTACHMENT
+                    };
+                    let mut framebuffer_object = 0;
+                    env.gl.GenFramebuffers(1, &mut framebuffer_object);
+                    env.gl.BindFramebuffer(gl::FRAMEBUFFER, framebuffer_object);
+                    env.gl.FramebufferTexture2D(gl::FRAMEBUFFER,
+                                                attachment,
+                                                gl::TEXTURE_2D,
+                                                depth_texture,
+                                                0);
+                    assert!(approx_eq(get_depth_from_bottom_row(&env.gl), 0.5));
+                    env.gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+                    env.gl.DeleteFramebuffers(1, &framebuffer_object);
+                    check_gl(&env.gl);
+                }
+            }
+
+            let mut surface = env.device
+                                 .destroy_surface_texture(&mut env.context, surface_texture)
+                                 .unwrap();
+            env.device.destroy_surface(&mut context, &mut surface).unwrap();
+            env.device.destroy_context(&mut context).unwrap();
+        }
+    }
+
+    env.device.destroy_context(&mut env.context).unwrap();
+}
+
 // Make sure that the current native context can be fetched and that they can be correctly wrapped
 // in `surfman` contexts.
 #[cfg_attr(not(feature = "sm-test"), test)]
@@ -695,6 +1348,562 @@ pub fn test_get_native_context() {
     env.device.destroy_context(&mut env.context).unwrap();
 }
 
+// Exports a surface, sends its handle over a socket, and samples it on another device.
+#[cfg(unix)]
+#[cfg_attr(not(feature = "sm-test"), test)]
+pub fn test_surface_handle_round_trip() {
+    let mut env = match BasicEnvironment::new() {
+        None => return,
+        Some(env) => env,
+    };
+
+    let mut other_device = env.connection.create_device(&env.adapter).unwrap();
+
+    unsafe {
+        clear(&env.gl, &[255, 0, 0, 255]);
+        assert_eq!(get_pixel_from_bottom_row(&env.gl), [255, 0, 0, 255]);
+
+        let mut other_context = other_device.create_context(&env.context_descriptor).unwrap();
+        let other_surface = make_surface(&mut other_device, &other_context);
+        other_device.bind_surface_to_context(&mut other_context, other_surface).unwrap();
+        other_device.make_context_current(&other_context).unwrap();
+        bind_context_fbo(&env.gl, &other_device, &other_context);
+
+        clear(&env.gl, &[0, 255, 0, 255]);
+        assert_eq!(get_pixel_from_bottom_row(&env.gl), [0, 255, 0, 255]);
+
+        let mut green_surface = other_device.unbind_surface_from_context(&mut other_context)
+                                            .unwrap()
+                                            .unwrap();
+        let handle = other_device.export_surface(&other_context, &green_surface).unwrap();
+        assert_eq!(handle.size(), Size2D::new(640, 480));
+
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        ipc::send_surface_handle(&sender, &handle).unwrap();
+        drop(handle);
+        let handle = ipc::recv_surface_handle(&receiver).unwrap();
+
+        env.device.make_context_current(&env.context).unwrap();
+        let green_surface_texture =
+            env.device
+               .create_surface_texture_from_handle(&mut env.context,
+                                                   handle,
+                                                   SurfaceTextureTarget::Compatible2D)
+               .unwrap();
+        assert_eq!(get_pixel_from_bottom_row(&env.gl), [255, 0, 0, 255]);
+
+        // An RGBA image exported by surfman can always be sampled without `samplerExternalOES`.
+        let target = env.device.surface_texture_target(&green_surface_texture);
+        assert_eq!(target, env.device.surface_gl_texture_target());
+
+        let mut green_framebuffer_object =
+            make_fbo(&env.gl, target, env.device.surface_texture_object(&green_surface_texture));
+
+        // Blit to main framebuffer.
+        blit_fbo(&env.gl, context_fbo(&env.device, &env.context), green_framebuffer_object);
+        bind_context_fbo(&env.gl, &env.device, &env.context);
+        assert_eq!(get_pixel_from_bottom_row(&env.gl), [0, 255, 0, 255]);
+
+        // Imported surfaces can only be sampled, never rendered to.
+        let mut imported_surface = env.device
+                                      .destroy_surface_texture(&mut env.context,
+                                                               green_surface_texture)
+                                      .unwrap();
+        env.gl.BindFramebuffer(gl::FRAMEBUFFER, 0); check_gl(&env.gl);
+        env.gl.DeleteFramebuffers(1, &mut green_framebuffer_object);
+        let old_surface = env.device.unbind_surface_from_context(&mut env.context).unwrap();
+        match env.device.bind_surface_to_context(&mut env.context, imported_surface) {
+            Err((Error::IncompatibleSurface, surface)) => imported_surface = surface,
+            Err((err, _)) => panic!("Unexpected error binding imported surface: {:?}", err),
+            Ok(()) => panic!("Imported surfaces shouldn't be bindable!"),
+        }
+        env.device.bind_surface_to_context(&mut env.context, old_surface.unwrap()).unwrap();
+
+        // Handles come from other processes, so sizes that don't make sense must be rejected.
+        let bad_handles = vec![
+            ipc::SurfaceHandle::SharedMemory(ipc::SharedMemoryImage {
+                fd: open_dev_null(),
+                size: Size2D::new(640, -1),
+                stride: 640 * 4,
+                format: SurfaceFormat::RGBA8,
+            }),
+            ipc::SurfaceHandle::SharedMemory(ipc::SharedMemoryImage {
+                fd: open_dev_null(),
+                size: Size2D::new(i32::MAX, 1),
+                stride: 640 * 4,
+                format: SurfaceFormat::RGBA8,
+            }),
+            ipc::SurfaceHandle::SharedMemory(ipc::SharedMemoryImage {
+                fd: open_dev_null(),
+                size: Size2D::new(640, 480),
+                stride: 640 * 4,
+                format: SurfaceFormat::RGBA16F,
+            }),
+            ipc::SurfaceHandle::DmaBuf(ipc::DmaBufImage {
+                size: Size2D::new(0, 480),
+                fourcc: 0x34324241,     // DRM_FORMAT_ABGR8888
+                modifier: 0,
+                planes: vec![ipc::DmaBufPlane { fd: open_dev_null(), offset: 0, stride: 640 * 4 }],
+            }),
+        ];
+        for handle in bad_handles {
+            match env.device.create_surface_texture_from_handle(&mut env.context,
+                                                                handle,
+                                                                SurfaceTextureTarget::Any) {
+                Err(Error::SurfaceImportFailed(ref failure))
+                        if failure.error == WindowingApiError::BadParameter => {}
+                Err(err) => panic!("Expected `BadParameter` but got {:?}!", err),
+                Ok(_) => panic!("Shouldn't be able to import a handle with a bad size!"),
+            }
+        }
+
+        // Clean up.
+        env.device.destroy_surface(&mut env.context, &mut imported_surface).unwrap();
+        other_device.destroy_surface(&mut other_context, &mut green_surface).unwrap();
+        other_device.destroy_context(&mut other_context).unwrap();
+        env.device.destroy_context(&mut env.context).unwrap();
+    }
+}
+
+// Make sure that each YUV format is converted with the right matrix and range, and that the first
+// row of the frame ends up as row 0 of the texture. Requesting a 2D-compatible texture forces the
+// CPU conversion path for YUV frames, so this works with software renderers too.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[cfg_attr(not(feature = "sm-test"), test)]
+pub fn test_planar_image_import() {
+    let mut env = match BasicEnvironment::new() {
+        None => return,
+        Some(env) => env,
+    };
+
+    // Each case lists the 8-bit YUV values of the top and bottom halves of the frame, followed by
+    // the RGBA colors they should convert to.
+    let cases = [
+        (PlanarFormat::NV12, YUVColorSpace::BT601, YUVRange::Limited,
+         [[81, 90, 240], [235, 128, 128]], [[254, 0, 0, 255], [255, 255, 255, 255]]),
+        (PlanarFormat::I420, YUVColorSpace::BT709, YUVRange::Full,
+         [[255, 128, 128], [0, 128, 128]], [[255, 255, 255, 255], [0, 0, 0, 255]]),
+        (PlanarFormat::P010, YUVColorSpace::BT2020, YUVRange::Limited,
+         [[16, 128, 128], [235, 128, 128]], [[0, 0, 0, 255], [255, 255, 255, 255]]),
+    ];
+
+    unsafe {
+        for &(format, color_space, range, ref yuv, ref rgba) in &cases {
+            let mut image = make_planar_image(format, color_space, range, yuv);
+            let surface_texture =
+                env.device
+                   .create_surface_texture_from_planes(&mut env.context,
+                                                       &image,
+                                                       SurfaceTextureTarget::Compatible2D)
+                   .unwrap();
+            // The caller keeps ownership of the planes and can close them right away.
+            libc::close(image.planes[0].fd);
+
+            let target = env.device.surface_texture_target(&surface_texture);
+            assert_eq!(target, gl::TEXTURE_2D);
+            let framebuffer_object =
+                make_fbo(&env.gl, target, env.device.surface_texture_object(&surface_texture));
+            assert_eq!(get_pixel_from_bottom_row(&env.gl), rgba[0]);
+            let mut pixel: [u8; 4] = [0; 4];
+            env.gl.ReadPixels(0,
+                              15,
+                              1,
+                              1,
+                              gl::RGBA,
+                              gl::UNSIGNED_BYTE,
+                              pixel.as_mut_ptr() as *mut c_void);
+            assert_eq!(pixel, rgba[1]);
+            env.gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+            env.gl.DeleteFramebuffers(1, &framebuffer_object);
+            check_gl(&env.gl);
+
+            let mut surface = env.device
+                                 .destroy_surface_texture(&mut env.context, surface_texture)
+                                 .unwrap();
+            env.device.destroy_surface(&mut env.context, &mut surface).unwrap();
+
+            // A frame that's missing planes must be rejected.
+            image.planes.pop();
+            match env.device.create_surface_texture_from_planes(&mut env.context,
+                                                                &image,
+                                                                SurfaceTextureTarget::Any) {
+                Err(Error::SurfaceImportFailed(ref failure))
+                        if failure.error == WindowingApiError::BadParameter => {}
+                Err(err) => panic!("Expected `BadParameter` but got {:?}!", err),
+                Ok(_) => panic!("Shouldn't be able to import a frame that's missing planes!"),
+            }
+        }
+    }
+
+    env.device.destroy_context(&mut env.context).unwrap();
+}
+
+#[cfg(unix)]
+#[cfg_attr(not(feature = "sm-test"), test)]
+pub fn test_upload_surface_data() {
+    let mut env = match BasicEnvironment::new() {
+        None => return,
+        Some(env) => env,
+    };
+
+    if !env.device.capabilities().cpu_surface_uploads {
+        env.device.destroy_context(&mut env.context).unwrap();
+        return;
+    }
+
+    let size = Size2D::new(640, 480);
+    let blue: Vec<u8> = [0, 0, 255, 255].iter().cloned().cycle().take(640 * 480 * 4).collect();
+
+    // Surfaces without CPU access can't be uploaded to.
+    let mut surface = make_surface(&mut env.device, &env.context);
+    match env.device.upload_surface_data(&env.context,
+                                         &mut surface,
+                                         &Rect::new(Point2D::zero(), size),
+                                         &blue,
+                                         640 * 4,
+                                         SurfaceFormat::RGBA8) {
+        Err(Error::SurfaceDataInaccessible) => {}
+        Err(err) => panic!("Expected `SurfaceDataInaccessible` but got {:?}!", err),
+        Ok(()) => panic!("Shouldn't be able to upload to a GPU-only surface!"),
+    }
+    env.device.destroy_surface(&mut env.context, &mut surface).unwrap();
+
+    for &access in &[SurfaceAccess::GPUCPU, SurfaceAccess::GPUCPUWriteCombined] {
+        let mut surface = env.device.create_surface(&env.context,
+                                                    access,
+                                                    SurfaceType::Generic {
+                                                        size,
+                                                        format: SurfaceFormat::RGBA8,
+                                                        depth_stencil: None,
+                                                    }).unwrap();
+        env.device.upload_surface_data(&env.context,
+                                       &mut surface,
+                                       &Rect::new(Point2D::zero(), size),
+                                       &blue,
+                                       640 * 4,
+                                       SurfaceFormat::RGBA8).unwrap();
+
+        // Upload a 2x2 red square at (1, 0), with a padding pixel at the end of each row.
+        let red = [
+            255, 0, 0, 255,     255, 0, 0, 255,     0, 0, 0, 0,
+            255, 0, 0, 255,     255, 0, 0, 255,     0, 0, 0, 0,
+        ];
+        let rect = Rect::new(Point2D::new(1, 0), Size2D::new(2, 2));
+        env.device.upload_surface_data(&env.context,
+                                       &mut surface,
+                                       &rect,
+                                       &red,
+                                       12,
+                                       SurfaceFormat::RGBA8).unwrap();
+
+        // Rectangles, strides, and lengths that don't fit are rejected.
+        for &(rect, len, stride) in &[
+            (Rect::new(Point2D::new(639, 0), Size2D::new(2, 2)), 24, 12),
+            (rect, 24, 4),
+            (rect, 16, 12),
+        ] {
+            match env.device.upload_surface_data(&env.context,
+                                                 &mut surface,
+                                                 &rect,
+                                                 &red[0..len],
+                                                 stride,
+                                                 SurfaceFormat::RGBA8) {
+                Err(Error::InvalidSurfaceData) => {}
+                Err(err) => panic!("Expected `InvalidSurfaceData` but got {:?}!", err),
+                Ok(()) => panic!("Shouldn't be able to upload data that doesn't fit!"),
+            }
+        }
+        match env.device.upload_surface_data(&env.context,
+                                             &mut surface,
+                                             &rect,
+                                             &red,
+                                             12,
+                                             SurfaceFormat::BGRA8) {
+            Err(Error::UnsupportedSurfaceFormat) => {}
+            Err(err) => panic!("Expected `UnsupportedSurfaceFormat` but got {:?}!", err),
+            Ok(()) => panic!("Shouldn't be able to upload data in another format!"),
+        }
+
+        let surface_texture = env.device.create_surface_texture(&mut env.context, surface)
+                                        .unwrap();
+        unsafe {
+            let mut framebuffer_object =
+                make_fbo(&env.gl,
+                         env.device.surface_texture_target(&surface_texture),
+                         env.device.surface_texture_object(&surface_texture));
+            let mut pixels = [0u8; 4 * 3 * 4];
+            env.gl.ReadPixels(0,
+                              0,
+                              4,
+                              3,
+                              gl::RGBA,
+                              gl::UNSIGNED_BYTE,
+                              pixels.as_mut_ptr() as *mut c_void);
+            check_gl(&env.gl);
+            for (index, pixel) in pixels.chunks(4).enumerate() {
+                let (x, y) = (index % 4, index / 4);
+                let expected = if (x == 1 || x == 2) && y < 2 {
+                    [255, 0, 0, 255]
+                } else {
+                    [0, 0, 255, 255]
+                };
+                assert_eq!(pixel, expected, "at ({}, {}) with {:?}", x, y, access);
+            }
+
+            env.gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+            env.gl.DeleteFramebuffers(1, &mut framebuffer_object);
+        }
+
+        let mut surface = env.device.destroy_surface_texture(&mut env.context, surface_texture)
+                                    .unwrap();
+        env.device.destroy_surface(&mut env.context, &mut surface).unwrap();
+    }
+
+    // Write-mapped guards upload the whole surface when they're dropped.
+    #[cfg(target_os = "linux")]
+    {
+        let mut surface = env.device.create_surface(&env.context,
+                                                    SurfaceAccess::GPUCPUWriteCombined,
+                                                    SurfaceType::Generic {
+                                                        size,
+                                                        format: SurfaceFormat::RGBA8,
+                                                        depth_stencil: None,
+                                                    }).unwrap();
+        {
+            let mut guard = env.device.lock_surface_data_for_writing(&env.context, &mut surface)
+                                      .unwrap();
+            assert_eq!(guard.stride(), 640 * 4);
+            for pixel in guard.data().chunks_mut(4) {
+                pixel.copy_from_slice(&[0, 255, 0, 255]);
+            }
+        }
+
+        let surface_texture = env.device.create_surface_texture(&mut env.context, surface)
+                                        .unwrap();
+        unsafe {
+            let mut framebuffer_object =
+                make_fbo(&env.gl,
+                         env.device.surface_texture_target(&surface_texture),
+                         env.device.surface_texture_object(&surface_texture));
+            assert_eq!(get_pixel_from_bottom_row(&env.gl), [0, 255, 0, 255]);
+            env.gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+            env.gl.DeleteFramebuffers(1, &mut framebuffer_object);
+        }
+
+        let mut surface = env.device.destroy_surface_texture(&mut env.context, surface_texture)
+                                    .unwrap();
+        env.device.destroy_surface(&mut env.context, &mut surface).unwrap();
+    }
+
+    env.device.destroy_context(&mut env.context).unwrap();
+}
+
+// Adopt a texture that the test creates itself, render to it through surfman, and make sure that
+// destroying the surface leaves the texture alone.
+#[cfg(target_os = "linux")]
+#[cfg_attr(not(feature = "sm-test"), test)]
+pub fn test_native_image_adoption() {
+    let mut env = match BasicEnvironment::new() {
+        None => return,
+        Some(env) => env,
+    };
+
+    unsafe {
+        let size = Size2D::new(640, 480);
+        let mut texture_object = 0;
+        env.gl.GenTextures(1, &mut texture_object);
+        env.gl.BindTexture(gl::TEXTURE_2D, texture_object);
+        env.gl.TexImage2D(gl::TEXTURE_2D,
+                          0,
+                          gl::RGBA as GLint,
+                          size.width,
+                          size.height,
+                          0,
+                          gl::RGBA,
+                          gl::UNSIGNED_BYTE,
+                          ptr::null());
+        env.gl.BindTexture(gl::TEXTURE_2D, 0); check_gl(&env.gl);
+
+        let native_image = NativeImage::Texture { texture_object, size };
+        let adopted_surface = env.device.create_surface_from_native_image(&env.context,
+                                                                          native_image)
+                                        .unwrap();
+        let mut main_surface = env.device
+                                  .unbind_surface_from_context(&mut env.context)
+                                  .unwrap()
+                                  .unwrap();
+        env.device.bind_surface_to_context(&mut env.context, adopted_surface).unwrap();
+        bind_context_fbo(&env.gl, &env.device, &env.context);
+        clear(&env.gl, &[0, 255, 0, 255]);
+        assert_eq!(get_pixel_from_bottom_row(&env.gl), [0, 255, 0, 255]);
+
+        // The CPU can't write to an image of unknown format.
+        let mut adopted_surface = env.device
+                                     .unbind_surface_from_context(&mut env.context)
+                                     .unwrap()
+                                     .unwrap();
+        let pixels = vec![0; 640 * 4];
+        match env.device.upload_surface_data(&env.context,
+                                             &mut adopted_surface,
+                                             &Rect::new(Point2D::zero(), Size2D::new(640, 1)),
+                                             &pixels,
+                                             640 * 4,
+                                             SurfaceFormat::RGBA8) {
+            Err(Error::SurfaceDataInaccessible) => {}
+            result => panic!("Unexpected upload result: {:?}", result),
+        }
+
+        // The adopted surface can be sampled like any other.
+        let surface_texture = env.device
+                                 .create_surface_texture(&mut env.context, adopted_surface)
+                                 .unwrap();
+        let mut framebuffer_object =
+            make_fbo(&env.gl,
+                     env.device.surface_texture_target(&surface_texture),
+                     env.device.surface_texture_object(&surface_texture));
+        assert_eq!(get_pixel_from_bottom_row(&env.gl), [0, 255, 0, 255]);
+        env.gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+        env.gl.DeleteFramebuffers(1, &mut framebuffer_object);
+
+        let mut adopted_surface = env.device
+                                     .destroy_surface_texture(&mut env.context, surface_texture)
+                                     .unwrap();
+        env.device.destroy_surface(&mut env.context, &mut adopted_surface).unwrap();
+
+        // The texture survives its surface, contents and all.
+        assert_eq!(env.gl.IsTexture(texture_object), gl::TRUE);
+        let mut framebuffer_object = make_fbo(&env.gl, gl::TEXTURE_2D, texture_object);
+        assert_eq!(get_pixel_from_bottom_row(&env.gl), [0, 255, 0, 255]);
+        env.gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+        env.gl.DeleteFramebuffers(1, &mut framebuffer_object);
+        env.gl.DeleteTextures(1, &texture_object);
+
+        env.device.destroy_surface(&mut env.context, &mut main_surface).unwrap();
+        env.device.destroy_context(&mut env.context).unwrap();
+    }
+}
+
+// Tests that contexts and surfaces dropped without being destroyed follow the device's leak policy.
+#[cfg_attr(not(feature = "sm-test"), test)]
+pub fn test_leak_policy() {
+    let mut env = match BasicEnvironment::new() {
+        None => return,
+        Some(env) => env,
+    };
+
+    assert_eq!(env.device.leak_policy(), crate::leak_policy());
+
+    // Leaked objects are logged rather than panicking.
+    env.device.set_leak_policy(LeakPolicy::Leak);
+    assert_eq!(env.device.leak_policy(), LeakPolicy::Leak);
+    let mut context = env.device.create_context(&env.context_descriptor).unwrap();
+    let surface = make_surface(&mut env.device, &context);
+    env.device.bind_surface_to_context(&mut context, surface).unwrap();
+    drop(make_surface(&mut env.device, &context));
+    drop(context);
+
+    // Deferred objects are destroyed the next time one of the device's contexts is made current.
+    env.device.set_leak_policy(LeakPolicy::Defer);
+    assert_eq!(env.device.leak_policy(), LeakPolicy::Defer);
+    let mut context = env.device.create_context(&env.context_descriptor).unwrap();
+    let surface = make_surface(&mut env.device, &context);
+    env.device.bind_surface_to_context(&mut context, surface).unwrap();
+    drop(context);
+
+    let surface = make_surface(&mut env.device, &env.context);
+    let framebuffer_object = env.device.surface_info(&surface).framebuffer_object;
+    drop(surface);
+    env.device.make_context_current(&env.context).unwrap();
+
+    // Only the EGL backends on Linux can defer destruction; elsewhere, the surface is leaked.
+    if cfg!(target_os = "linux") {
+        unsafe {
+            assert_eq!(env.gl.IsFramebuffer(framebuffer_object), gl::FALSE);
+        }
+    }
+
+    env.device.destroy_context(&mut env.context).unwrap();
+}
+
+// Tests that managed surfaces and surface textures are destroyed after they're dropped, even on
+// other threads.
+#[cfg_attr(not(feature = "sm-test"), test)]
+pub fn test_managed_objects() {
+    let mut env = match BasicEnvironment::new() {
+        None => return,
+        Some(env) => env,
+    };
+    env.device.destroy_context(&mut env.context).unwrap();
+
+    let device: managed::Device<Device> = managed::Device::new(env.device);
+    let mut context = device.create_context(&env.context_descriptor).unwrap();
+    let surface = make_managed_surface(&device, &context);
+    if let Err((err, _)) = device.bind_surface_to_context(&mut context, surface) {
+        panic!("Failed to bind surface: {:?}", err);
+    }
+    device.make_context_current(&mut context).unwrap();
+
+    // A surface dropped on another thread is destroyed on this one.
+    let surface = make_managed_surface(&device, &context);
+    let framebuffer_object = device.borrow().surface_info(&surface).framebuffer_object;
+    thread::spawn(move || drop(surface)).join().unwrap();
+    device.make_context_current(&mut context).unwrap();
+    unsafe {
+        assert_eq!(env.gl.IsFramebuffer(framebuffer_object), gl::FALSE);
+    }
+
+    // A dropped surface texture is destroyed along with its surface.
+    let surface = make_managed_surface(&device, &context);
+    let framebuffer_object = device.borrow().surface_info(&surface).framebuffer_object;
+    let surface_texture = match device.create_surface_texture(&mut context, surface) {
+        Ok(surface_texture) => surface_texture,
+        Err((err, _)) => panic!("Failed to create surface texture: {:?}", err),
+    };
+    let texture_object = device.borrow().surface_texture_object(&surface_texture);
+    drop(surface_texture);
+    device.destroy_dropped_objects(&mut context);
+    unsafe {
+        assert_eq!(env.gl.IsTexture(texture_object), gl::FALSE);
+        assert_eq!(env.gl.IsFramebuffer(framebuffer_object), gl::FALSE);
+    }
+
+    // Dropping the context destroys it, along with its bound surface.
+    drop(context);
+}
+
+// Surfaces and surface textures that outlive their managed context are leaked, instead of being
+// queued for a context that will never destroy them and reported when the device is dropped.
+#[cfg_attr(not(feature = "sm-test"), test)]
+pub fn test_managed_objects_outliving_their_context() {
+    let mut env = match BasicEnvironment::new() {
+        None => return,
+        Some(env) => env,
+    };
+    env.device.destroy_context(&mut env.context).unwrap();
+    env.device.set_leak_policy(LeakPolicy::Panic);
+
+    let device: managed::Device<Device> = managed::Device::new(env.device);
+    let mut context = device.create_context(&env.context_descriptor).unwrap();
+    let mut other_context = device.create_context(&env.context_descriptor).unwrap();
+    let surface = make_managed_surface(&device, &context);
+    let other_surface = make_managed_surface(&device, &other_context);
+    let surface_texture = match device.create_surface_texture(&mut context, other_surface) {
+        Ok(surface_texture) => surface_texture,
+        Err((err, _)) => panic!("Failed to create surface texture: {:?}", err),
+    };
+    let other_thread_surface = make_managed_surface(&device, &context);
+
+    drop(context);
+    drop(surface);
+    thread::spawn(move || drop(other_thread_surface)).join().unwrap();
+    drop(surface_texture);
+
+    // The surface texture's own surface still belongs to a live context.
+    device.destroy_dropped_objects(&mut other_context);
+    drop(other_context);
+    drop(device);
+}
+
 fn bind_context_fbo(gl: &Gl, device: &Device, context: &Context) {
     unsafe {
         gl.BindFramebuffer(gl::FRAMEBUFFER, context_fbo(device, context)); check_gl(&gl);
@@ -708,7 +1917,23 @@ fn context_fbo(device: &Device, context: &Context) -> GLuint {
 fn make_surface(device: &mut Device, context: &Context) -> Surface {
     device.create_surface(&context,
                           SurfaceAccess::GPUOnly,
-                          SurfaceType::Generic { size: Size2D::new(640, 480) })
+                          SurfaceType::Generic {
+                              size: Size2D::new(640, 480),
+                              format: SurfaceFormat::RGBA8,
+                              depth_stencil: None,
+                          })
+          .unwrap()
+}
+
+fn make_managed_surface(device: &managed::Device<Device>, context: &managed::Context<Device>)
+                        -> managed::Surface<Device> {
+    device.create_surface(context,
+                          SurfaceAccess::GPUOnly,
+                          SurfaceType::Generic {
+                              size: Size2D::new(640, 480),
+                              format: SurfaceFormat::RGBA8,
+                              depth_stencil: None,
+                          })
           .unwrap()
 }
 
@@ -766,10 +1991,9 @@ impl BasicEnvironment {
             Err(err) => panic!("Failed to create device: {:?}", err),
         };
 
-        let context_descriptor = device.create_context_descriptor(&ContextAttributes {
-            version: GLVersion::new(3, 0),
-            flags: ContextAttributeFlags::empty(),
-        }).unwrap();
+        let attributes = ContextAttributes::new(GLVersion::new(3, 0),
+                                                ContextAttributeFlags::empty());
+        let context_descriptor = device.create_context_descriptor(&attributes).unwrap();
 
         let mut context = device.create_context(&context_descriptor).unwrap();
         let surface = make_surface(&mut device, &context);
@@ -838,6 +2062,101 @@ fn get_pixel_from_second_from_bottom_row(gl: &Gl) -> [u8; 4] {
     }
 }
 
+fn get_depth_from_bottom_row(gl: &Gl) -> f32 {
+    unsafe {
+        let mut depth_value: f32 = -1.0;
+        gl.ReadPixels(0,
+                      0,
+                      1,
+                      1,
+                      gl::DEPTH_COMPONENT,
+                      gl::FLOAT,
+                      (&mut depth_value) as *mut f32 as *mut c_void);
+        depth_value
+    }
+}
+
+fn get_stencil_from_bottom_row(gl: &Gl) -> u8 {
+    unsafe {
+        let mut stencil_value: u8 = 0;
+        gl.ReadPixels(0,
+                      0,
+                      1,
+                      1,
+                      gl::STENCIL_INDEX,
+                      gl::UNSIGNED_BYTE,
+                      (&mut stencil_value) as *mut u8 as *mut c_void);
+        stencil_value
+    }
+}
+
+// Creates a 16x16 frame whose top and bottom halves have the given 8-bit YUV values, scaled up
+// for 10-bit formats. All planes share one shared memory buffer.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn make_planar_image(format: PlanarFormat,
+                     color_space: YUVColorSpace,
+                     range: YUVRange,
+                     yuv: &[[u16; 3]; 2])
+                     -> PlanarImage {
+    // Each plane is described by the components it holds, in order, and its subsampling.
+    let (plane_components, sample_size): (&[(&[usize], usize)], usize) = match format {
+        PlanarFormat::NV12 => (&[(&[0], 1), (&[1, 2], 2)], 1),
+        PlanarFormat::I420 => (&[(&[0], 1), (&[1], 2), (&[2], 2)], 1),
+        PlanarFormat::P010 => (&[(&[0], 1), (&[1, 2], 2)], 2),
+    };
+
+    let mut data = vec![];
+    let mut planes = vec![];
+    for &(components, subsampling) in plane_components {
+        let size = 16 / subsampling;
+        planes.push(DmaBufPlane {
+            fd: -1,
+            offset: data.len() as u32,
+            stride: (size * components.len() * sample_size) as u32,
+        });
+        for row in 0..size {
+            let half = if row < size / 2 { 0 } else { 1 };
+            for _ in 0..size {
+                for &component in components {
+                    let value = yuv[half][component];
+                    if sample_size == 1 {
+                        data.push(value as u8);
+                    } else {
+                        let value = (value << 2) << 6;
+                        data.extend_from_slice(&[value as u8, (value >> 8) as u8]);
+                    }
+                }
+            }
+        }
+    }
+
+    unsafe {
+        let fd = libc::memfd_create(b"surfman-test-frame\0".as_ptr() as *const libc::c_char, 0);
+        assert!(fd >= 0);
+        assert_eq!(libc::write(fd, data.as_ptr() as *const c_void, data.len()),
+                   data.len() as isize);
+        for plane in &mut planes {
+            plane.fd = fd;
+        }
+        PlanarImage {
+            size: Size2D::new(16, 16),
+            format,
+            modifier: 0x00ff_ffff_ffff_ffff,
+            planes,
+            color_space,
+            range,
+        }
+    }
+}
+
+// Returns a file descriptor that can stand in for a buffer in a surface handle. The handle takes
+// ownership of it.
+#[cfg(unix)]
+fn open_dev_null() -> i32 {
+    use std::os::unix::io::IntoRawFd;
+    std::fs::File::open("/dev/null").unwrap().into_raw_fd()
+}
+
 fn check_gl(gl: &Gl) {
     unsafe {
         assert_eq!(gl.GetError(), gl::NO_ERROR);
@@ -0,0 +1,314 @@
+// surfman/surfman/src/video.rs
+//
+//! Decoded video frames that can be sampled from as surface textures.
+//!
+//! Hardware video decoders on Linux hand out frames as multi-planar dma-bufs in YUV formats.
+//! `Device::create_surface_texture_from_planes()` imports them without a copy when the EGL
+//! implementation supports `EGL_EXT_image_dma_buf_import`. When it doesn't, the planes are mapped
+//! and converted to RGBA on the CPU instead, which is slow but works with any driver, including
+//! software renderers.
+
+use crate::ipc::{DmaBufPlane, SharedMemoryMapping};
+use crate::{Error, WindowingApiError};
+
+use euclid::default::Size2D;
+use std::os::unix::io::RawFd;
+use std::slice;
+
+/// The layout of the planes of a YUV video frame.
+///
+/// In every format, the chroma planes are subsampled by two horizontally and vertically.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PlanarFormat {
+    /// An 8-bit luma plane followed by a plane of interleaved 8-bit Cb and Cr samples.
+    NV12,
+    /// An 8-bit luma plane followed by separate 8-bit Cb and Cr planes.
+    I420,
+    /// Like `NV12`, but with 16-bit little-endian samples whose upper 10 bits are significant.
+    P010,
+}
+
+/// The matrix that converts the YUV samples of a video frame to RGB.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum YUVColorSpace {
+    /// ITU-R BT.601, used by standard-definition video.
+    BT601,
+    /// ITU-R BT.709, used by high-definition video.
+    BT709,
+    /// ITU-R BT.2020, used by ultra-high-definition and HDR video.
+    BT2020,
+}
+
+/// The range of values that the samples of a video frame span.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum YUVRange {
+    /// Luma spans 16 to 235 and chroma spans 16 to 240, scaled up for deeper formats.
+    ///
+    /// This is what most video uses.
+    Limited,
+    /// Luma and chroma span every representable value.
+    Full,
+}
+
+/// A decoded video frame made up of dma-buf planes.
+///
+/// The file descriptors belong to the caller, which may close them as soon as the frame has been
+/// wrapped in a surface texture.
+#[derive(Debug)]
+pub struct PlanarImage {
+    /// The size of the frame in pixels.
+    pub size: Size2D<i32>,
+    /// The layout of the planes.
+    pub format: PlanarFormat,
+    /// The DRM format modifier describing the memory layout, or `DRM_FORMAT_MOD_INVALID`
+    /// (`0x00ff_ffff_ffff_ffff`) if the driver should infer it.
+    pub modifier: u64,
+    /// The planes of the frame, luma first. There must be exactly
+    /// `format.plane_count()` of these.
+    pub planes: Vec<DmaBufPlane>,
+    /// The matrix used to convert the frame to RGB.
+    pub color_space: YUVColorSpace,
+    /// The range of the frame's samples.
+    pub range: YUVRange,
+}
+
+// How one plane of a format is laid out: the subsampling factor in each direction and the number
+// of bytes per texel.
+#[derive(Clone, Copy)]
+struct PlaneLayout {
+    subsampling: i32,
+    bytes_per_texel: usize,
+}
+
+impl PlaneLayout {
+    fn size(&self, frame_size: &Size2D<i32>) -> Size2D<usize> {
+        let subsampling = self.subsampling;
+        Size2D::new(((frame_size.width + subsampling - 1) / subsampling) as usize,
+                    ((frame_size.height + subsampling - 1) / subsampling) as usize)
+    }
+}
+
+const LUMA_8: PlaneLayout = PlaneLayout { subsampling: 1, bytes_per_texel: 1 };
+const LUMA_16: PlaneLayout = PlaneLayout { subsampling: 1, bytes_per_texel: 2 };
+const CHROMA_8: PlaneLayout = PlaneLayout { subsampling: 2, bytes_per_texel: 1 };
+const INTERLEAVED_CHROMA_8: PlaneLayout = PlaneLayout { subsampling: 2, bytes_per_texel: 2 };
+const INTERLEAVED_CHROMA_16: PlaneLayout = PlaneLayout { subsampling: 2, bytes_per_texel: 4 };
+
+// `DRM_FORMAT_NV12`, `DRM_FORMAT_YUV420`, and `DRM_FORMAT_P010` from `drm_fourcc.h`.
+const DRM_FORMAT_NV12: u32 = 0x3231_564e;
+const DRM_FORMAT_YUV420: u32 = 0x3231_5559;
+const DRM_FORMAT_P010: u32 = 0x3031_3050;
+
+impl PlanarFormat {
+    /// Returns the number of planes that frames in this format consist of.
+    #[inline]
+    pub fn plane_count(self) -> usize {
+        self.plane_layouts().len()
+    }
+
+    #[inline]
+    pub(crate) fn drm_fourcc(self) -> u32 {
+        match self {
+            PlanarFormat::NV12 => DRM_FORMAT_NV12,
+            PlanarFormat::I420 => DRM_FORMAT_YUV420,
+            PlanarFormat::P010 => DRM_FORMAT_P010,
+        }
+    }
+
+    /// Returns true if the DRM fourcc code is one of the YUV formats above.
+    pub(crate) fn is_yuv_drm_fourcc(fourcc: u32) -> bool {
+        [DRM_FORMAT_NV12, DRM_FORMAT_YUV420, DRM_FORMAT_P010].contains(&fourcc)
+    }
+
+    fn plane_layouts(self) -> &'static [PlaneLayout] {
+        match self {
+            PlanarFormat::NV12 => &[LUMA_8, INTERLEAVED_CHROMA_8],
+            PlanarFormat::I420 => &[LUMA_8, CHROMA_8, CHROMA_8],
+            PlanarFormat::P010 => &[LUMA_16, INTERLEAVED_CHROMA_16],
+        }
+    }
+
+    fn bit_depth(self) -> u32 {
+        match self {
+            PlanarFormat::NV12 | PlanarFormat::I420 => 8,
+            PlanarFormat::P010 => 10,
+        }
+    }
+}
+
+impl YUVColorSpace {
+    // The contributions of red and blue to luma.
+    fn luma_coefficients(self) -> (f32, f32) {
+        match self {
+            YUVColorSpace::BT601 => (0.299, 0.114),
+            YUVColorSpace::BT709 => (0.2126, 0.0722),
+            YUVColorSpace::BT2020 => (0.2627, 0.0593),
+        }
+    }
+}
+
+impl PlanarImage {
+    /// Checks that the planes match the format and are large enough to hold the frame.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        let layouts = self.format.plane_layouts();
+        if self.size.width <= 0 || self.size.height <= 0 || self.planes.len() != layouts.len() {
+            return Err(Error::SurfaceImportFailed(WindowingApiError::BadParameter.into()));
+        }
+        for (plane, layout) in self.planes.iter().zip(layouts.iter()) {
+            if (plane.stride as usize) < layout.size(&self.size).width * layout.bytes_per_texel {
+                return Err(Error::SurfaceImportFailed(WindowingApiError::BadParameter.into()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps the planes and converts the frame to tightly-packed RGBA rows, in the same order as
+    /// the rows of the frame.
+    pub(crate) fn to_rgba(&self) -> Result<Vec<u8>, Error> {
+        self.validate()?;
+
+        let layouts = self.format.plane_layouts();
+        let mut mappings = Vec::with_capacity(self.planes.len());
+        for (plane, layout) in self.planes.iter().zip(layouts.iter()) {
+            let len = plane.offset as usize + plane.stride as usize * layout.size(&self.size).height;
+            mappings.push(PlaneMapping::new(plane.fd, len)?);
+        }
+        let planes: Vec<&[u8]> = mappings.iter().zip(self.planes.iter()).map(|(mapping, plane)| {
+            &mapping.bytes()[(plane.offset as usize)..]
+        }).collect();
+
+        let (width, height) = (self.size.width as usize, self.size.height as usize);
+        let (kr, kb) = self.color_space.luma_coefficients();
+        let normalize = Normalization::new(self.format.bit_depth(), self.range);
+
+        let mut pixels = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                let (luma, cb, cr) = self.fetch(&planes, x, y);
+                let (luma, cb, cr) = (normalize.luma(luma),
+                                      normalize.chroma(cb),
+                                      normalize.chroma(cr));
+                let r = luma + 2.0 * (1.0 - kr) * cr;
+                let b = luma + 2.0 * (1.0 - kb) * cb;
+                let g = (luma - kr * r - kb * b) / (1.0 - kr - kb);
+                pixels.extend_from_slice(&[to_unorm8(r), to_unorm8(g), to_unorm8(b), 255]);
+            }
+        }
+        Ok(pixels)
+    }
+
+    // Returns the raw luma, Cb, and Cr samples of the pixel at the given position.
+    fn fetch(&self, planes: &[&[u8]], x: usize, y: usize) -> (u32, u32, u32) {
+        let (luma_stride, chroma_stride) = (self.planes[0].stride as usize,
+                                            self.planes[1].stride as usize);
+        let (chroma_x, chroma_y) = (x / 2, y / 2);
+        match self.format {
+            PlanarFormat::NV12 => {
+                let chroma = chroma_y * chroma_stride + chroma_x * 2;
+                (planes[0][y * luma_stride + x] as u32,
+                 planes[1][chroma] as u32,
+                 planes[1][chroma + 1] as u32)
+            }
+            PlanarFormat::I420 => {
+                let chroma = chroma_y * chroma_stride + chroma_x;
+                let cr_chroma = chroma_y * self.planes[2].stride as usize + chroma_x;
+                (planes[0][y * luma_stride + x] as u32,
+                 planes[1][chroma] as u32,
+                 planes[2][cr_chroma] as u32)
+            }
+            PlanarFormat::P010 => {
+                let chroma = chroma_y * chroma_stride + chroma_x * 4;
+                (read_p010_sample(planes[0], y * luma_stride + x * 2),
+                 read_p010_sample(planes[1], chroma),
+                 read_p010_sample(planes[1], chroma + 2))
+            }
+        }
+    }
+}
+
+// Maps raw samples of a given bit depth and range to luma in [0, 1] and chroma in [-0.5, 0.5].
+struct Normalization {
+    luma_offset: f32,
+    luma_scale: f32,
+    chroma_offset: f32,
+    chroma_scale: f32,
+}
+
+impl Normalization {
+    fn new(bit_depth: u32, range: YUVRange) -> Normalization {
+        let unit = (1 << (bit_depth - 8)) as f32;
+        let chroma_offset = 128.0 * unit;
+        match range {
+            YUVRange::Limited => Normalization {
+                luma_offset: 16.0 * unit,
+                luma_scale: 219.0 * unit,
+                chroma_offset,
+                chroma_scale: 224.0 * unit,
+            },
+            YUVRange::Full => {
+                let max = ((1 << bit_depth) - 1) as f32;
+                Normalization { luma_offset: 0.0, luma_scale: max, chroma_offset, chroma_scale: max }
+            }
+        }
+    }
+
+    fn luma(&self, sample: u32) -> f32 {
+        (sample as f32 - self.luma_offset) / self.luma_scale
+    }
+
+    fn chroma(&self, sample: u32) -> f32 {
+        (sample as f32 - self.chroma_offset) / self.chroma_scale
+    }
+}
+
+fn read_p010_sample(plane: &[u8], offset: usize) -> u32 {
+    (plane[offset] as u32 | (plane[offset + 1] as u32) << 8) >> 6
+}
+
+fn to_unorm8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+// A read-only mapping of one plane, bracketed by dma-buf CPU access synchronization.
+struct PlaneMapping {
+    fd: RawFd,
+    mapping: SharedMemoryMapping,
+}
+
+impl PlaneMapping {
+    fn new(fd: RawFd, len: usize) -> Result<PlaneMapping, Error> {
+        let mapping = SharedMemoryMapping::open_read_only(fd, len)?;
+        sync_dma_buf(fd, DMA_BUF_SYNC_START);
+        Ok(PlaneMapping { fd, mapping })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.mapping.ptr, self.mapping.len) }
+    }
+}
+
+impl Drop for PlaneMapping {
+    fn drop(&mut self) {
+        sync_dma_buf(self.fd, DMA_BUF_SYNC_END);
+    }
+}
+
+const DMA_BUF_SYNC_START: u64 = 0;
+const DMA_BUF_SYNC_END: u64 = 4;
+
+// Tells the exporter that the CPU is about to read the buffer, or has finished doing so. Buffers
+// that aren't dma-bufs, such as shared memory, reject the ioctl, which is harmless.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn sync_dma_buf(fd: RawFd, flags: u64) {
+    // `DMA_BUF_IOCTL_SYNC` and `DMA_BUF_SYNC_READ` from `linux/dma-buf.h`.
+    const DMA_BUF_IOCTL_SYNC: u32 = 0x4008_6200;
+    const DMA_BUF_SYNC_READ: u64 = 1;
+
+    let sync = flags | DMA_BUF_SYNC_READ;
+    unsafe {
+        libc::ioctl(fd, DMA_BUF_IOCTL_SYNC as _, &sync);
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn sync_dma_buf(_: RawFd, _: u64) {}